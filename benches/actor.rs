@@ -0,0 +1,79 @@
+//! Compares the lock based `ConcurrentEngine` with the `ActorEngine` when many threads submit
+//! transactions of their own clients. Run with `cargo bench --bench actor`.
+
+use rust_coding_test::{
+    ActorEngine, Amount, ClientId, ConcurrentEngine, EngineConfig, Transaction, TransactionId,
+    TransactionType,
+};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+const PRODUCERS: u32 = 8;
+const TRANSACTIONS_PER_PRODUCER: u32 = 200_000;
+const SHARDS: usize = 8;
+
+/// Deposits of the producer's own clients, so that producers never wait on each other's accounts
+fn deposit(producer: u32, i: u32) -> Transaction {
+    Transaction {
+        transaction_type: TransactionType::Deposit,
+        client_id: (producer * 1000 + i % 1000) as ClientId,
+        transaction_id: TransactionId(producer * TRANSACTIONS_PER_PRODUCER + i),
+        amount: Some(Amount::new(1.0).unwrap()),
+        to_client_id: None,
+        currency: None,
+        timestamp: None,
+        metadata: Default::default(),
+    }
+}
+
+/// Runs `submit` for every transaction of every producer on its own thread
+fn produce<E: Send + Sync + 'static>(engine: &Arc<E>, submit: fn(&E, Transaction)) {
+    let producers: Vec<_> = (0..PRODUCERS)
+        .map(|producer| {
+            let engine = Arc::clone(engine);
+            thread::spawn(move || {
+                for i in 0..TRANSACTIONS_PER_PRODUCER {
+                    submit(&engine, deposit(producer, i));
+                }
+            })
+        })
+        .collect();
+    for producer in producers {
+        producer.join().unwrap();
+    }
+}
+
+fn main() {
+    let start = Instant::now();
+    let engine = Arc::new(ConcurrentEngine::new(SHARDS, EngineConfig::default()));
+    produce(&engine, |engine, transaction| {
+        let _ = engine.submit(transaction);
+    });
+    report("locks", start);
+
+    let start = Instant::now();
+    let engine = Arc::new(ActorEngine::new(SHARDS, EngineConfig::default()));
+    produce(&engine, |engine, transaction| {
+        let _ = engine.submit(transaction);
+    });
+    drop(Arc::into_inner(engine).unwrap().into_engine());
+    report("actors, submit", start);
+
+    let start = Instant::now();
+    let engine = Arc::new(ActorEngine::new(SHARDS, EngineConfig::default()));
+    produce(&engine, ActorEngine::post);
+    drop(Arc::into_inner(engine).unwrap().into_engine());
+    report("actors, post", start);
+}
+
+fn report(name: &str, start: Instant) {
+    let elapsed = start.elapsed();
+    let transactions = f64::from(PRODUCERS * TRANSACTIONS_PER_PRODUCER);
+    println!(
+        "{:>16}: {:>8.1?} ({:.0} transactions/s)",
+        name,
+        elapsed,
+        transactions / elapsed.as_secs_f64()
+    );
+}
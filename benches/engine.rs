@@ -0,0 +1,76 @@
+//! Baseline timings of the single threaded engine on synthetic workloads: dispatching single
+//! transactions, dispute heavy workloads and ingesting a whole csv file.
+//! Run with `cargo bench --bench engine`; larger files can be made with `gen-data`.
+
+use rust_coding_test::{
+    open_source, InputFormat, Transaction, TransactionEngine, TransactionSource, Workload,
+};
+use std::fs::File;
+use std::io::BufWriter;
+use std::time::{Duration, Instant};
+
+const TRANSACTIONS: u64 = 1_000_000;
+/// Runs of every benchmark, the fastest one is reported
+const RUNS: usize = 3;
+
+fn workload(dispute_rate: f64, skew: f64) -> Workload {
+    Workload {
+        rows: TRANSACTIONS,
+        clients: 10_000,
+        skew,
+        dispute_rate,
+        seed: 42,
+    }
+}
+
+fn execute_all(transactions: &[Transaction]) -> Duration {
+    let mut engine = TransactionEngine::new();
+    let start = Instant::now();
+    for transaction in transactions.iter().cloned() {
+        let _ = engine.execute(transaction);
+    }
+    start.elapsed()
+}
+
+fn best_of<F: FnMut() -> Duration>(mut run: F) -> Duration {
+    (0..RUNS).map(|_| run()).min().unwrap_or_default()
+}
+
+fn main() {
+    for (name, workload) in [
+        ("dispatch", workload(0.0, 0.0)),
+        ("skewed dispatch", workload(0.0, 3.0)),
+        ("dispute heavy", workload(0.5, 0.0)),
+    ] {
+        let transactions: Vec<Transaction> = workload.transactions().collect();
+        report(name, best_of(|| execute_all(&transactions)));
+    }
+
+    let path = std::env::temp_dir().join("rust-coding-test-bench.csv");
+    workload(0.05, 0.0)
+        .write_csv(BufWriter::new(
+            File::create(&path).expect("Failed to create bench file"),
+        ))
+        .expect("Failed to write bench file");
+    let elapsed = best_of(|| {
+        let start = Instant::now();
+        let mut source = open_source(&path, InputFormat::Csv).expect("Failed to open bench file");
+        let mut engine = TransactionEngine::new();
+        while let Some(transaction) = source.next_transaction() {
+            let _ = engine.execute(transaction.expect("Generated rows are valid"));
+        }
+        start.elapsed()
+    });
+    report("file ingestion", elapsed);
+    let _ = std::fs::remove_file(&path);
+}
+
+fn report(name: &str, elapsed: Duration) {
+    println!(
+        "{:>16}: {:>8.1?} ({:.0} ns/transaction, {:.0} transactions/s)",
+        name,
+        elapsed,
+        elapsed.as_nanos() as f64 / TRANSACTIONS as f64,
+        TRANSACTIONS as f64 / elapsed.as_secs_f64()
+    );
+}
@@ -0,0 +1,61 @@
+//! Compares reading csv input through serde with the `FastCsvSource` enabled by `--fast-parse`,
+//! on its own and feeding the engine. Run with `cargo bench --bench parse`.
+
+use rust_coding_test::{CsvSource, FastCsvSource, TransactionEngine, TransactionSource, Workload};
+use std::time::{Duration, Instant};
+
+const TRANSACTIONS: u64 = 1_000_000;
+/// Runs of every benchmark, the fastest one is reported
+const RUNS: usize = 3;
+
+fn best_of<F: FnMut() -> Duration>(mut run: F) -> Duration {
+    (0..RUNS).map(|_| run()).min().unwrap_or_default()
+}
+
+/// Reads every row of the source, handing the transactions to the engine if given
+fn read_all(mut source: impl TransactionSource, mut engine: Option<TransactionEngine>) -> Duration {
+    let start = Instant::now();
+    while let Some(transaction) = source.next_transaction() {
+        let transaction = transaction.expect("Generated rows are valid");
+        if let Some(engine) = engine.as_mut() {
+            let _ = engine.execute(transaction);
+        }
+    }
+    start.elapsed()
+}
+
+fn main() {
+    let mut csv = Vec::new();
+    Workload {
+        rows: TRANSACTIONS,
+        clients: 10_000,
+        skew: 0.0,
+        dispute_rate: 0.05,
+        seed: 42,
+    }
+    .write_csv(&mut csv)
+    .expect("Failed to write bench input");
+
+    for (name, engine) in [("parse", false), ("parse and apply", true)] {
+        let engine = || engine.then(TransactionEngine::new);
+        let serde = best_of(|| read_all(CsvSource::new(&csv[..]), engine()));
+        let fast = best_of(|| read_all(FastCsvSource::new(&csv[..]), engine()));
+        report(&format!("{}, serde", name), serde);
+        report(&format!("{}, fast", name), fast);
+        println!(
+            "{:>24}: {:.1}x",
+            "speedup",
+            serde.as_secs_f64() / fast.as_secs_f64()
+        );
+    }
+}
+
+fn report(name: &str, elapsed: Duration) {
+    println!(
+        "{:>24}: {:>8.1?} ({:.0} ns/transaction, {:.0} transactions/s)",
+        name,
+        elapsed,
+        elapsed.as_nanos() as f64 / TRANSACTIONS as f64,
+        TRANSACTIONS as f64 / elapsed.as_secs_f64()
+    );
+}
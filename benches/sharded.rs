@@ -0,0 +1,66 @@
+//! Compares the single threaded engine with the sharded engine on a synthetic workload.
+//! Run with `cargo bench --bench sharded`.
+
+use rust_coding_test::{
+    Amount, ClientId, EngineConfig, ShardedEngine, Transaction, TransactionEngine, TransactionId,
+    TransactionType,
+};
+use std::time::Instant;
+
+const TRANSACTIONS: u32 = 2_000_000;
+const CLIENTS: u32 = 10_000;
+
+fn workload() -> Vec<Transaction> {
+    (0..TRANSACTIONS)
+        .map(|i| {
+            let (transaction_type, transaction_id, amount) = match i % 10 {
+                0..=5 => (TransactionType::Deposit, i, Amount::new(10.0).ok()),
+                6 | 7 => (TransactionType::Withdrawal, i, Amount::new(5.0).ok()),
+                8 => (TransactionType::Dispute, i - 8, None),
+                _ => (TransactionType::Resolve, i - 9, None),
+            };
+            Transaction {
+                transaction_type,
+                // Keep referencing rows on the client of the referenced deposit
+                client_id: ((transaction_id / 10) % CLIENTS) as ClientId,
+                transaction_id: TransactionId(transaction_id),
+                amount,
+                to_client_id: None,
+                currency: None,
+                timestamp: None,
+                metadata: Default::default(),
+            }
+        })
+        .collect()
+}
+
+fn main() {
+    let transactions = workload();
+
+    let start = Instant::now();
+    let mut engine = TransactionEngine::new();
+    for transaction in transactions.iter().cloned() {
+        let _ = engine.execute(transaction);
+    }
+    report("single threaded", start);
+
+    for shards in [2, 4, 8] {
+        let start = Instant::now();
+        let mut engine = ShardedEngine::new(shards, EngineConfig::default());
+        for transaction in transactions.iter().cloned() {
+            engine.submit(transaction);
+        }
+        engine.finish();
+        report(&format!("{} shards", shards), start);
+    }
+}
+
+fn report(name: &str, start: Instant) {
+    let elapsed = start.elapsed();
+    println!(
+        "{:>16}: {:>8.1?} ({:.0} transactions/s)",
+        name,
+        elapsed,
+        f64::from(TRANSACTIONS) / elapsed.as_secs_f64()
+    );
+}
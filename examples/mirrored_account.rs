@@ -0,0 +1,266 @@
+//! Plugs a custom account implementation into the engine. Every change of the balance of a
+//! `MirroredAccount` is pushed to an external system, here a shared list standing in for a
+//! database or message queue, while the bookkeeping itself is delegated to a `BasicAccount`.
+//! Run with `cargo run --example mirrored_account`.
+
+use rust_coding_test::{
+    AccountFactory, AccountPolicies, AccountState, Amount, Balance, BasicAccount, ClientAccount,
+    ClientId, Currency, DisputeState, EngineConfig, Transaction, TransactionEngine, TransactionId,
+    TransactionStore, TransactionType, UpdateError,
+};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// Balances reported by the accounts, in order
+type Mirror = Arc<Mutex<Vec<(ClientId, Option<Currency>, Balance)>>>;
+
+struct MirroredAccount {
+    inner: BasicAccount,
+    mirror: Mirror,
+}
+
+impl MirroredAccount {
+    /// Reports the balance in the currency if the operation went through
+    fn mirrored(
+        &self,
+        currency: Option<Currency>,
+        result: Result<(), UpdateError>,
+    ) -> Result<(), UpdateError> {
+        if result.is_ok() {
+            let balance = self.inner.balance(currency);
+            self.mirror.lock().expect("Mirror is never poisoned").push((
+                self.inner.get_client_id(),
+                currency,
+                balance,
+            ));
+        }
+        result
+    }
+
+    /// Currency of a logged transaction, authorization or chargeback, looked up before it is
+    /// disputed or settled, `None` if the log cannot be read
+    fn currency_of(&self, transaction_id: TransactionId) -> Option<Currency> {
+        let state = self.inner.state().ok()?;
+        state
+            .transaction_log
+            .iter()
+            .chain(&state.active_disputes)
+            .chain(&state.authorizations)
+            .chain(&state.chargebacks)
+            .find(|(id, _, _)| *id == transaction_id)
+            .and_then(|(_, currency, _)| *currency)
+    }
+}
+
+impl ClientAccount for MirroredAccount {
+    fn deposit(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), UpdateError> {
+        let result = self.inner.deposit(transaction_id, amount, currency);
+        self.mirrored(currency, result)
+    }
+
+    fn withdraw(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), UpdateError> {
+        let result = self.inner.withdraw(transaction_id, amount, currency);
+        self.mirrored(currency, result)
+    }
+
+    fn adjust(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), UpdateError> {
+        let result = self.inner.adjust(transaction_id, amount, currency);
+        self.mirrored(currency, result)
+    }
+
+    fn post_adjustment(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), UpdateError> {
+        let result = self.inner.post_adjustment(transaction_id, amount, currency);
+        self.mirrored(currency, result)
+    }
+
+    fn dispute(&mut self, transaction_id: TransactionId) -> Result<(), UpdateError> {
+        let currency = self.currency_of(transaction_id);
+        let result = self.inner.dispute(transaction_id);
+        self.mirrored(currency, result)
+    }
+
+    fn dispute_partial(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+    ) -> Result<(), UpdateError> {
+        let currency = self.currency_of(transaction_id);
+        let result = self.inner.dispute_partial(transaction_id, amount);
+        self.mirrored(currency, result)
+    }
+
+    fn resolve(&mut self, transaction_id: TransactionId) -> Result<(), UpdateError> {
+        let currency = self.currency_of(transaction_id);
+        let result = self.inner.resolve(transaction_id);
+        self.mirrored(currency, result)
+    }
+
+    fn chargeback(&mut self, transaction_id: TransactionId) -> Result<(), UpdateError> {
+        let currency = self.currency_of(transaction_id);
+        let result = self.inner.chargeback(transaction_id);
+        self.mirrored(currency, result)
+    }
+
+    fn represent(&mut self, transaction_id: TransactionId) -> Result<(), UpdateError> {
+        let currency = self.currency_of(transaction_id);
+        let result = self.inner.represent(transaction_id);
+        self.mirrored(currency, result)
+    }
+
+    fn authorize(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), UpdateError> {
+        let result = self.inner.authorize(transaction_id, amount, currency);
+        self.mirrored(currency, result)
+    }
+
+    fn capture(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: Option<f64>,
+    ) -> Result<(), UpdateError> {
+        let currency = self.currency_of(transaction_id);
+        let result = self.inner.capture(transaction_id, amount);
+        self.mirrored(currency, result)
+    }
+
+    fn void(&mut self, transaction_id: TransactionId) -> Result<(), UpdateError> {
+        let currency = self.currency_of(transaction_id);
+        let result = self.inner.void(transaction_id);
+        self.mirrored(currency, result)
+    }
+
+    fn dispute_state(&self, transaction_id: TransactionId) -> (DisputeState, u32) {
+        self.inner.dispute_state(transaction_id)
+    }
+
+    fn forget(&mut self, transaction_id: TransactionId) -> bool {
+        self.inner.forget(transaction_id)
+    }
+
+    fn get_client_id(&self) -> ClientId {
+        self.inner.get_client_id()
+    }
+
+    fn balance(&self, currency: Option<Currency>) -> Balance {
+        self.inner.balance(currency)
+    }
+
+    fn currencies(&self) -> Vec<Option<Currency>> {
+        self.inner.currencies()
+    }
+
+    fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    fn set_locked(&mut self, locked: bool) {
+        self.inner.set_locked(locked)
+    }
+
+    fn state(&self) -> io::Result<AccountState> {
+        self.inner.state()
+    }
+}
+
+#[derive(Debug)]
+struct MirroredFactory {
+    mirror: Mirror,
+}
+
+impl AccountFactory for MirroredFactory {
+    fn create(
+        &self,
+        client_id: ClientId,
+        policies: AccountPolicies,
+        store: Option<Box<dyn TransactionStore>>,
+    ) -> Box<dyn ClientAccount> {
+        let inner = match store {
+            Some(store) => BasicAccount::with_store(client_id, policies, store),
+            None => BasicAccount::with_policies(client_id, policies),
+        };
+        Box::new(MirroredAccount {
+            inner,
+            mirror: self.mirror.clone(),
+        })
+    }
+
+    fn restore(
+        &self,
+        state: AccountState,
+        policies: AccountPolicies,
+        store: Option<Box<dyn TransactionStore>>,
+    ) -> io::Result<Box<dyn ClientAccount>> {
+        let inner = match store {
+            Some(store) => BasicAccount::from_state_with_store(state, policies, store)?,
+            None => BasicAccount::from_state(state, policies),
+        };
+        Ok(Box::new(MirroredAccount {
+            inner,
+            mirror: self.mirror.clone(),
+        }))
+    }
+}
+
+fn main() {
+    let mirror = Mirror::default();
+    let mut engine = TransactionEngine::with_config(EngineConfig {
+        account_factory: Some(Arc::new(MirroredFactory {
+            mirror: mirror.clone(),
+        })),
+        ..EngineConfig::default()
+    });
+
+    for (transaction_type, transaction_id, amount) in [
+        (TransactionType::Deposit, 1, Some(10.0)),
+        (TransactionType::Withdrawal, 2, Some(4.0)),
+        (TransactionType::Withdrawal, 3, Some(100.0)),
+        (TransactionType::Dispute, 1, None),
+        (TransactionType::Resolve, 1, None),
+    ] {
+        let result = engine.execute(Transaction {
+            transaction_type,
+            client_id: 1,
+            transaction_id: TransactionId(transaction_id),
+            amount: amount.map(|amount| Amount::new(amount).unwrap()),
+            to_client_id: None,
+            currency: None,
+            timestamp: None,
+            metadata: Default::default(),
+        });
+        if let Err(err) = result {
+            println!("rejected: {}", err);
+        }
+    }
+
+    // The refused withdrawal was never mirrored
+    for (client_id, _, balance) in mirror.lock().expect("Mirror is never poisoned").iter() {
+        println!(
+            "client {}: available {:.4}, held {:.4}",
+            client_id, balance.available, balance.held
+        );
+    }
+}
@@ -0,0 +1,100 @@
+/*
+ * C interface of the transaction engine, implemented in src/ffi.rs. Link against the cdylib or
+ * staticlib built by `cargo build --release`.
+ *
+ * Kept in sync with src/ffi.rs by hand, regenerate with cbindgen where it is available.
+ */
+#ifndef RUST_CODING_TEST_H
+#define RUST_CODING_TEST_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The transaction was applied */
+#define ENGINE_OK 0
+/* The engine rejected the transaction, see engine_last_error */
+#define ENGINE_REJECTED 1
+/* The client has no account */
+#define ENGINE_NOT_FOUND 2
+/* A pointer was null or the transaction type unknown, see engine_last_error */
+#define ENGINE_INVALID_ARGUMENT -1
+/* The file could not be read, see engine_last_error */
+#define ENGINE_IO_ERROR -2
+
+/* Client ids, widened to match builds with the client-id-u32 or client-id-u64 feature */
+#if defined(ENGINE_CLIENT_ID_U64)
+typedef uint64_t EngineClientId;
+typedef int64_t EngineToClient;
+#elif defined(ENGINE_CLIENT_ID_U32)
+typedef uint32_t EngineClientId;
+typedef int64_t EngineToClient;
+#else
+typedef uint16_t EngineClientId;
+typedef int32_t EngineToClient;
+#endif
+
+/* Opaque engine handle */
+typedef struct Engine Engine;
+
+typedef struct EngineTransaction {
+  /* Name of the type as in csv input, e.g. "deposit" */
+  const char *transaction_type;
+  EngineClientId client;
+  uint32_t tx;
+  /* NAN for transactions without amount such as disputes */
+  double amount;
+  /* Credited client of transfers, negative for other transactions */
+  EngineToClient to_client;
+} EngineTransaction;
+
+typedef struct EngineFileReport {
+  /* Rows of the file, including malformed ones */
+  uint64_t rows_read;
+  /* Rows that could not be read as a transaction and were skipped */
+  uint64_t malformed;
+  /* Transactions the engine rejected */
+  uint64_t rejected;
+  /* Accounts the file locked */
+  uint64_t locked;
+} EngineFileReport;
+
+typedef struct EngineAccount {
+  double available;
+  double held;
+  double total;
+  bool locked;
+} EngineAccount;
+
+/* Creates an engine with the default configuration, released with engine_free */
+Engine *engine_new(void);
+
+/* Releases an engine. NULL is ignored. */
+void engine_free(Engine *engine);
+
+/* Applies a transaction in the default currency */
+int32_t engine_submit_transaction(Engine *engine, const EngineTransaction *transaction);
+
+/* Applies every transaction of a csv or ndjson file, the format guessed from its extension,
+ * filling report with what was found in it */
+int32_t engine_process_file(Engine *engine, const char *path, EngineFileReport *report);
+
+/* Accounts as csv ordered by client. Owned by the engine and valid until the next call of
+ * engine_accounts_csv or engine_free. */
+const char *engine_accounts_csv(Engine *engine);
+
+/* Fills account with the funds of the client in the default currency */
+int32_t engine_get_account(const Engine *engine, EngineClientId client, EngineAccount *account);
+
+/* Message of the last failed call, NULL if the last transaction or file was applied. Owned by the engine
+ * and valid until its next call. */
+const char *engine_last_error(const Engine *engine);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
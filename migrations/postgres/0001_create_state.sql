@@ -0,0 +1,48 @@
+-- Schema of the PostgreSQL backend of the `StateStore` trait.
+--
+-- Mirrors the engine state kept in snapshots: one row per account and currency, the disputable
+-- transactions of every account and the disputes opened on them. A backend applies each
+-- transaction inside a single database transaction that appends its ledger entry and updates the
+-- rows it changes, so that the tables never show half of a transaction.
+
+CREATE TABLE accounts (
+    client      INTEGER          NOT NULL CHECK (client BETWEEN 0 AND 65535),
+    -- Three letter code, empty for funds without currency
+    currency    CHAR(3)          NOT NULL DEFAULT '',
+    available   DOUBLE PRECISION NOT NULL DEFAULT 0,
+    held        DOUBLE PRECISION NOT NULL DEFAULT 0,
+    locked      BOOLEAN          NOT NULL DEFAULT FALSE,
+    PRIMARY KEY (client, currency)
+);
+
+-- Applied transactions in log sequence order
+CREATE TABLE ledger_entries (
+    lsn         BIGINT           PRIMARY KEY,
+    type        TEXT             NOT NULL CHECK (type IN (
+                    'deposit', 'withdrawal', 'dispute', 'resolve', 'chargeback', 'transfer', 'lock',
+                    'unlock', 'fee', 'interest', 'reversal')),
+    client      INTEGER          NOT NULL,
+    tx          BIGINT           NOT NULL,
+    amount      DOUBLE PRECISION,
+    to_client   INTEGER,
+    currency    CHAR(3),
+    -- Seconds since the Unix epoch
+    timestamp   BIGINT
+);
+
+CREATE INDEX ledger_entries_client ON ledger_entries (client, lsn);
+
+-- Disputable transactions with the change they made to the available funds, and the state of
+-- their disputes
+CREATE TABLE disputes (
+    client      INTEGER          NOT NULL,
+    tx          BIGINT           NOT NULL,
+    currency    CHAR(3)          NOT NULL DEFAULT '',
+    amount      DOUBLE PRECISION NOT NULL,
+    state       TEXT             NOT NULL DEFAULT 'undisputed' CHECK (state IN (
+                    'undisputed', 'disputed', 'resolved', 'chargedback')),
+    -- Amount held while disputed
+    held        DOUBLE PRECISION NOT NULL DEFAULT 0,
+    times       INTEGER          NOT NULL DEFAULT 0,
+    PRIMARY KEY (client, tx)
+);
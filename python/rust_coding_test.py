@@ -0,0 +1,115 @@
+"""Python bindings of the transaction engine over the C interface of src/ffi.rs.
+
+Build the shared library with `cargo build --release`, then:
+
+    from rust_coding_test import Engine
+
+    with Engine() as engine:
+        report = engine.process_file("transactions.csv")
+        accounts = engine.accounts_csv()
+
+`accounts_csv()` returns the accounts as the batch command writes them, ready for
+`pandas.read_csv(io.StringIO(accounts))`. The library is looked up in target/release next to
+this directory unless RUST_CODING_TEST_LIB names it.
+"""
+
+import ctypes
+import os
+import sys
+
+ENGINE_OK = 0
+ENGINE_INVALID_ARGUMENT = -1
+ENGINE_IO_ERROR = -2
+
+
+class FileReport(ctypes.Structure):
+    """What `Engine.process_file` found in a file"""
+
+    _fields_ = [
+        ("rows_read", ctypes.c_uint64),
+        ("malformed", ctypes.c_uint64),
+        ("rejected", ctypes.c_uint64),
+        ("locked", ctypes.c_uint64),
+    ]
+
+
+def _library_path():
+    if "RUST_CODING_TEST_LIB" in os.environ:
+        return os.environ["RUST_CODING_TEST_LIB"]
+    name = {"darwin": "librust_coding_test.dylib", "win32": "rust_coding_test.dll"}.get(
+        sys.platform, "librust_coding_test.so"
+    )
+    root = os.path.dirname(os.path.dirname(os.path.abspath(__file__)))
+    return os.path.join(root, "target", "release", name)
+
+
+def _load(path):
+    library = ctypes.CDLL(path)
+    library.engine_new.restype = ctypes.c_void_p
+    library.engine_free.argtypes = [ctypes.c_void_p]
+    library.engine_process_file.argtypes = [
+        ctypes.c_void_p,
+        ctypes.c_char_p,
+        ctypes.POINTER(FileReport),
+    ]
+    library.engine_process_file.restype = ctypes.c_int32
+    library.engine_accounts_csv.argtypes = [ctypes.c_void_p]
+    library.engine_accounts_csv.restype = ctypes.c_char_p
+    library.engine_last_error.argtypes = [ctypes.c_void_p]
+    library.engine_last_error.restype = ctypes.c_char_p
+    return library
+
+
+class Engine:
+    """Engine with the default configuration, released by `close` or at the end of a `with`"""
+
+    def __init__(self, library=None):
+        self._library = _load(library or _library_path())
+        self._engine = self._library.engine_new()
+
+    def process_file(self, path):
+        """Applies every transaction of a csv or ndjson file, returning a `FileReport`. Raises
+        `OSError` if the file cannot be read."""
+        report = FileReport()
+        code = self._library.engine_process_file(
+            self._engine, os.fsencode(path), ctypes.byref(report)
+        )
+        if code == ENGINE_IO_ERROR:
+            raise OSError(self._last_error())
+        if code != ENGINE_OK:
+            raise ValueError(self._last_error())
+        return report
+
+    def accounts_csv(self):
+        """Accounts as csv ordered by client"""
+        return self._library.engine_accounts_csv(self._engine).decode()
+
+    def close(self):
+        if getattr(self, "_engine", None) is not None:
+            self._library.engine_free(self._engine)
+            self._engine = None
+
+    def _last_error(self):
+        message = self._library.engine_last_error(self._engine)
+        return message.decode() if message else "unknown error"
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, *exc_info):
+        self.close()
+
+    def __del__(self):
+        self.close()
+
+
+if __name__ == "__main__":
+    with Engine() as engine:
+        for path in sys.argv[1:]:
+            report = engine.process_file(path)
+            print(
+                f"{path}: {report.rows_read} rows, {report.malformed} malformed, "
+                f"{report.rejected} rejected",
+                file=sys.stderr,
+            )
+        print(engine.accounts_csv(), end="")
@@ -1,136 +1,1069 @@
-use crate::transaction::TransactionId;
-use std::collections::HashMap;
+use crate::currency::Currency;
+use crate::error::{ConfigError, UpdateError};
+use crate::overdraft::OverdraftAccount;
+use crate::policy::{
+    AccountKind, AccountPolicies, LockPolicy, NegativeBalancePolicy, OverdraftPolicy, SavingsPolicy,
+};
+use crate::savings::SavingsAccount;
+use crate::storage::TransactionStore;
+use crate::tier::Tiers;
+use crate::toml;
+use crate::transaction::{TransactionId, TransactionType};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
 
+/// Id of a client, 16 bits wide unless the `client-id-u32` or `client-id-u64` feature widens it
+#[cfg(not(any(feature = "client-id-u32", feature = "client-id-u64")))]
 pub type ClientId = u16;
+#[cfg(all(feature = "client-id-u32", not(feature = "client-id-u64")))]
+pub type ClientId = u32;
+#[cfg(feature = "client-id-u64")]
+pub type ClientId = u64;
 
-/// Trait defining available operations on client account
-/// TODO: make operation methods return Result<(), UpdateError> in case something goes wrong
-pub trait ClientAccount {
-    fn deposit(&mut self, transaction_id: TransactionId, amount: f64);
+/// Half of the smallest amount of four decimal input. Disputable remainders below it are
+/// rounding errors of earlier partial disputes.
+pub(crate) const DUST: f64 = 0.00005;
 
-    /// Does nothing if there are not enough available funds
-    fn withdraw(&mut self, transaction_id: TransactionId, amount: f64);
+/// Funds of an account in a single currency
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Balance {
+    pub available: f64,
+    pub held: f64,
+}
+
+impl Balance {
+    pub fn total(&self) -> f64 {
+        self.available + self.held
+    }
+}
+
+/// Where a transaction is in its dispute cycles. A dispute moves it from `Undisputed` or
+/// `Resolved` to `Disputed`, which ends with a resolve or a final chargeback. A chargeback the
+/// merchant won the representment of ends `Represented`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DisputeState {
+    #[default]
+    Undisputed,
+    Disputed,
+    Resolved,
+    ChargedBack,
+    Represented,
+}
+
+impl DisputeState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DisputeState::Undisputed => "undisputed",
+            DisputeState::Disputed => "disputed",
+            DisputeState::Resolved => "resolved",
+            DisputeState::ChargedBack => "chargedback",
+            DisputeState::Represented => "represented",
+        }
+    }
+}
+
+impl fmt::Display for DisputeState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for DisputeState {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "undisputed" => Ok(DisputeState::Undisputed),
+            "disputed" => Ok(DisputeState::Disputed),
+            "resolved" => Ok(DisputeState::Resolved),
+            "chargedback" => Ok(DisputeState::ChargedBack),
+            "represented" => Ok(DisputeState::Represented),
+            _ => Err(format!("unknown dispute state '{}'", value)),
+        }
+    }
+}
 
-    fn dispute(&mut self, transaction_id: TransactionId);
+/// Trait defining available operations on client account.
+/// Operations that are refused leave the account unchanged and return the reason.
+/// Funds are kept per currency, `None` being transactions without a currency, and are never
+/// netted across currencies. Disputes, resolves and chargebacks apply to the currency of the
+/// referenced transaction.
+/// Accounts are `Send` so that they can be processed on worker threads.
+pub trait ClientAccount: Send {
+    fn deposit(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), UpdateError>;
 
-    fn resolve(&mut self, transaction_id: TransactionId);
+    /// Fails if there are not enough available funds in the currency
+    fn withdraw(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), UpdateError>;
 
-    fn chargeback(&mut self, transaction_id: TransactionId);
+    /// Adds `amount`, negative for a charge, to the available funds in the currency without
+    /// making it disputable, e.g. for fees and interest. Fails if a charge is not covered by the
+    /// available funds. Ignores the lock, which the caller is expected to check.
+    fn adjust(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), UpdateError>;
+
+    /// Adds `amount`, negative for a debit, to the available funds in the currency as a manual
+    /// ledger adjustment, even if that leaves them negative. The adjustment is disputable like a
+    /// deposit or a withdrawal of the same sign. Ignores the lock, which the caller is expected
+    /// to check.
+    fn post_adjustment(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), UpdateError>;
+
+    fn dispute(&mut self, transaction_id: TransactionId) -> Result<(), UpdateError>;
+
+    /// Disputes only `amount` of the transaction, at most what is still disputable of it. The rest
+    /// stays disputable, and a resolve or chargeback settles every disputed portion at once.
+    fn dispute_partial(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+    ) -> Result<(), UpdateError>;
+
+    fn resolve(&mut self, transaction_id: TransactionId) -> Result<(), UpdateError>;
+
+    /// Chargebacks are final, what remains of the transaction can no longer be disputed
+    fn chargeback(&mut self, transaction_id: TransactionId) -> Result<(), UpdateError>;
+
+    /// Credits back the amount taken by the chargeback of the transaction, whose merchant won the
+    /// representment, and lifts the lock if the policy says so and no other chargeback is left.
+    /// Accepted on locked accounts.
+    fn represent(&mut self, transaction_id: TransactionId) -> Result<(), UpdateError>;
+
+    /// Moves `amount` from the available to the held funds in the currency until the
+    /// authorization is captured or voided. Fails if there are not enough available funds.
+    fn authorize(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), UpdateError>;
+
+    /// Withdraws `amount` of the authorization, all of it if `None`, and releases the rest of the
+    /// hold. The captured amount is disputable like a withdrawal.
+    fn capture(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: Option<f64>,
+    ) -> Result<(), UpdateError>;
+
+    /// Releases the hold of the authorization
+    fn void(&mut self, transaction_id: TransactionId) -> Result<(), UpdateError>;
+
+    /// Dispute state of the transaction and the number of disputes it went through
+    fn dispute_state(&self, transaction_id: TransactionId) -> (DisputeState, u32);
+
+    /// Stops keeping the transaction so that it can no longer be disputed, to bound memory.
+    /// Returns whether it is under dispute or an open authorization, in which case it can still be
+    /// resolved or chargebacked, captured or voided.
+    fn forget(&mut self, transaction_id: TransactionId) -> bool;
+
+    /// Lets `ticks` ticks pass, settling what was waiting for them. Accounts without delayed
+    /// operations have nothing to do.
+    fn advance(&mut self, _ticks: u32) {}
 
     fn get_client_id(&self) -> ClientId;
 
-    /// Total funds are available + held funds held by the client
-    fn get_total_funds(&self) -> f64;
+    /// Funds in the currency, zero if the account never held any
+    fn balance(&self, currency: Option<Currency>) -> Balance;
 
-    fn get_available_funds(&self) -> f64;
+    /// Currencies the account has funds in, in order. Accounts without any funds report `None`.
+    fn currencies(&self) -> Vec<Option<Currency>>;
 
-    fn get_held_funds(&self) -> f64;
+    /// Total funds are available + held funds held by the client, without currency
+    fn get_total_funds(&self) -> f64 {
+        self.balance(None).total()
+    }
+
+    fn get_available_funds(&self) -> f64 {
+        self.balance(None).available
+    }
+
+    fn get_held_funds(&self) -> f64 {
+        self.balance(None).held
+    }
 
     fn is_locked(&self) -> bool;
+
+    /// Locks or unlocks the account regardless of the lock policy, on behalf of an administrator
+    fn set_locked(&mut self, locked: bool);
+
+    /// Full state of the account, used to snapshot and restore it. Fails if the transaction log
+    /// of the account cannot be read from its store.
+    fn state(&self) -> io::Result<AccountState>;
+}
+
+/// Plain representation of everything an account needs to be restored
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountState {
+    pub client_id: ClientId,
+    /// Funds per currency, in order
+    pub balances: Vec<(Option<Currency>, Balance)>,
+    pub locked: bool,
+    /// Disputable transactions with the change they made to the available funds
+    pub transaction_log: Vec<(TransactionId, Option<Currency>, f64)>,
+    /// Transactions under dispute with the held amount
+    pub active_disputes: Vec<(TransactionId, Option<Currency>, f64)>,
+    /// State and number of disputes of every transaction that was disputed
+    pub dispute_states: Vec<(TransactionId, DisputeState, u32)>,
+    /// Withdrawals of savings accounts that did not settle yet with the ticks left, in order
+    pub pending_withdrawals: Vec<(TransactionId, Option<Currency>, f64, u32)>,
+    /// Authorizations that were neither captured nor voided with the held amount
+    pub authorizations: Vec<(TransactionId, Option<Currency>, f64)>,
+    /// Charged back transactions that were not represented with the amount taken
+    pub chargebacks: Vec<(TransactionId, Option<Currency>, f64)>,
+    /// Disputed amounts waiting for funds to be held under
+    /// [`NegativeBalancePolicy::HoldPending`], in order of dispute
+    pub pending_holds: Vec<(TransactionId, Option<Currency>, f64)>,
+}
+
+/// Owned copy of the funds and lock of an account, readable after the account moved on
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountView {
+    pub client_id: ClientId,
+    /// Funds per currency, in order
+    pub balances: Vec<(Option<Currency>, Balance)>,
+    pub locked: bool,
+}
+
+impl AccountView {
+    pub fn new(account: &dyn ClientAccount) -> Self {
+        AccountView {
+            client_id: account.get_client_id(),
+            balances: account
+                .currencies()
+                .into_iter()
+                .map(|currency| (currency, account.balance(currency)))
+                .collect(),
+            locked: account.is_locked(),
+        }
+    }
+
+    /// Funds in the currency, zero if the account never held any
+    pub fn balance(&self, currency: Option<Currency>) -> Balance {
+        self.balances
+            .iter()
+            .find(|(found, _)| *found == currency)
+            .map(|(_, balance)| *balance)
+            .unwrap_or_default()
+    }
 }
 
 #[derive(Debug)]
 pub struct BasicAccount {
     client_id: ClientId,
     // TODO: switch to working with Decimal
-    available: f64,
-    held: f64,
+    balances: BTreeMap<Option<Currency>, Balance>,
     locked: bool,
+    policies: AccountPolicies,
 
-    /// Keeps the amount by which the available funds have changed (-amount in withdrawals) in a
-    /// transaction.
-    /// Used to handle dispute transactions rather than to keep history of all transactions
-    transaction_log: HashMap<TransactionId, f64>,
-    /// Keeps the active disputes with the respective amount under dispute until it's resolved or
-    /// chargebacked
-    active_disputes: HashMap<TransactionId, f64>,
+    /// Keeps the currency and the amount by which the available funds have changed (-amount in
+    /// withdrawals) in a transaction.
+    /// Used to handle dispute transactions rather than to keep history of all transactions.
+    /// Kept in memory unless the account was given another store.
+    transaction_log: Box<dyn TransactionStore>,
+    /// Keeps the active disputes with the respective currency and amount under dispute until
+    /// it's resolved or chargebacked
+    active_disputes: HashMap<TransactionId, (Option<Currency>, f64)>,
+    /// State and number of disputes of the transactions that were disputed, the others being
+    /// undisputed
+    dispute_states: HashMap<TransactionId, (DisputeState, u32)>,
+    /// Keeps the open authorizations with the respective currency and held amount until they are
+    /// captured or voided
+    authorizations: HashMap<TransactionId, (Option<Currency>, f64)>,
+    /// Keeps the amount taken by chargebacks until they are represented
+    chargebacks: HashMap<TransactionId, (Option<Currency>, f64)>,
+    /// Disputed amounts that could not be held yet for lack of available funds, in order of
+    /// dispute, see [`NegativeBalancePolicy::HoldPending`]
+    pending_holds: Vec<(TransactionId, Option<Currency>, f64)>,
 }
 
 impl BasicAccount {
     pub fn new(client_id: ClientId) -> Self {
+        Self::with_policies(client_id, AccountPolicies::default())
+    }
+
+    pub fn with_lock_policy(client_id: ClientId, lock_policy: LockPolicy) -> Self {
+        Self::with_policies(
+            client_id,
+            AccountPolicies {
+                lock: lock_policy,
+                ..AccountPolicies::default()
+            },
+        )
+    }
+
+    pub fn with_policies(client_id: ClientId, policies: AccountPolicies) -> Self {
+        Self::with_store(client_id, policies, Box::new(HashMap::new()))
+    }
+
+    /// Account keeping its disputable transactions in `store`, which must be empty
+    pub fn with_store(
+        client_id: ClientId,
+        policies: AccountPolicies,
+        store: Box<dyn TransactionStore>,
+    ) -> Self {
         BasicAccount {
             client_id,
-            available: 0.0,
-            held: 0.0,
+            balances: BTreeMap::new(),
             locked: false,
+            policies,
 
-            transaction_log: HashMap::new(),
+            transaction_log: store,
             active_disputes: HashMap::new(),
+            dispute_states: HashMap::new(),
+            authorizations: HashMap::new(),
+            chargebacks: HashMap::new(),
+            pending_holds: Vec::new(),
+        }
+    }
+
+    pub fn from_state(state: AccountState, policies: AccountPolicies) -> Self {
+        Self::from_state_with_store(state, policies, Box::new(HashMap::new()))
+            .expect("In-memory store cannot fail")
+    }
+
+    /// Restores the account, writing its transaction log to an empty `store`
+    pub fn from_state_with_store(
+        state: AccountState,
+        policies: AccountPolicies,
+        mut store: Box<dyn TransactionStore>,
+    ) -> io::Result<Self> {
+        for (transaction_id, currency, amount) in &state.transaction_log {
+            store.insert(*transaction_id, (*currency, *amount))?;
+        }
+        let mut dispute_states: HashMap<_, _> = state
+            .dispute_states
+            .into_iter()
+            .map(|(id, dispute_state, cycles)| (id, (dispute_state, cycles)))
+            .collect();
+        // States of snapshots written before they were recorded
+        for (transaction_id, _, _) in &state.active_disputes {
+            dispute_states
+                .entry(*transaction_id)
+                .or_insert((DisputeState::Disputed, 1));
+        }
+        Ok(BasicAccount {
+            client_id: state.client_id,
+            balances: state.balances.into_iter().collect(),
+            locked: state.locked,
+            policies,
+
+            transaction_log: store,
+            active_disputes: state
+                .active_disputes
+                .into_iter()
+                .map(|(id, currency, amount)| (id, (currency, amount)))
+                .collect(),
+            dispute_states,
+            authorizations: state
+                .authorizations
+                .into_iter()
+                .map(|(id, currency, amount)| (id, (currency, amount)))
+                .collect(),
+            chargebacks: state
+                .chargebacks
+                .into_iter()
+                .map(|(id, currency, amount)| (id, (currency, amount)))
+                .collect(),
+            pending_holds: state.pending_holds,
+        })
+    }
+
+    fn check_lock(
+        &self,
+        transaction_type: TransactionType,
+        transaction_id: TransactionId,
+    ) -> Result<(), UpdateError> {
+        if self.locked && !self.policies.lock.allows(transaction_type) {
+            return Err(UpdateError::AccountLocked(transaction_id));
+        }
+        Ok(())
+    }
+
+    /// Puts the disputed portion of the transaction on hold, the whole of what is still
+    /// disputable unless `amount` is given
+    fn hold(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: Option<f64>,
+    ) -> Result<(), UpdateError> {
+        self.check_lock(TransactionType::Dispute, transaction_id)?;
+        let (currency, disputable) = self
+            .transaction_log
+            .get(transaction_id)
+            .map_err(|err| UpdateError::storage(transaction_id, err))?
+            .ok_or(UpdateError::TransactionNotFound(transaction_id))?;
+        if !self.policies.dispute.is_disputable(disputable) {
+            return Err(UpdateError::NotDisputable(transaction_id));
+        }
+        // Portions are given as positive amounts, withdrawals are logged with a negative one
+        let amount = match amount {
+            Some(amount) if amount > 0.0 && amount <= disputable.abs() + DUST => {
+                amount.min(disputable.abs()).copysign(disputable)
+            }
+            Some(amount) => {
+                return Err(UpdateError::InvalidDisputeAmount {
+                    transaction_id,
+                    amount,
+                    disputable: disputable.abs(),
+                })
+            }
+            None => disputable,
+        };
+        let available = self.balance(currency).available;
+        // Only disputes of deposits can push the available funds below zero
+        let held_amount = if amount > 0.0 && amount > available {
+            match self.policies.negative_balance {
+                NegativeBalancePolicy::Allow => amount,
+                NegativeBalancePolicy::RejectDispute => {
+                    return Err(UpdateError::DisputeExceedsAvailable {
+                        transaction_id,
+                        amount,
+                        available,
+                    })
+                }
+                NegativeBalancePolicy::HoldPartial | NegativeBalancePolicy::HoldPending => {
+                    available.max(0.0)
+                }
+            }
+        } else {
+            amount
+        };
+
+        // Only the rest of the transaction stays disputable, once nothing is left it is removed
+        // from the log so that it cannot be disputed twice
+        let remaining = disputable - amount;
+        if remaining.abs() < DUST {
+            self.transaction_log.remove(transaction_id).map(|_| ())
+        } else {
+            self.transaction_log
+                .insert(transaction_id, (currency, remaining))
         }
+        .map_err(|err| UpdateError::storage(transaction_id, err))?;
+        if self.policies.negative_balance == NegativeBalancePolicy::HoldPending
+            && amount > held_amount
+        {
+            match self
+                .pending_holds
+                .iter_mut()
+                .find(|(id, _, _)| *id == transaction_id)
+            {
+                Some((_, _, pending)) => *pending += amount - held_amount,
+                None => self
+                    .pending_holds
+                    .push((transaction_id, currency, amount - held_amount)),
+            }
+        }
+        self.active_disputes
+            .entry(transaction_id)
+            .or_insert((currency, 0.0))
+            .1 += held_amount;
+        // Further portions disputed while the dispute is open belong to the same cycle
+        let (state, cycles) = self.dispute_states.entry(transaction_id).or_default();
+        if *state != DisputeState::Disputed {
+            *state = DisputeState::Disputed;
+            *cycles += 1;
+        }
+        let balance = self.balance_mut(currency);
+        balance.available -= held_amount;
+        balance.held += held_amount;
+        Ok(())
+    }
+
+    /// Withdraws as long as the available funds stay at or above `floor`, which is below zero for
+    /// accounts with an overdraft
+    pub(crate) fn withdraw_down_to(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+        floor: f64,
+    ) -> Result<(), UpdateError> {
+        self.check_lock(TransactionType::Withdrawal, transaction_id)?;
+        let available = self.balance(currency).available;
+        if available - amount < floor {
+            return Err(UpdateError::InsufficientFunds {
+                transaction_id,
+                requested: amount,
+                available: available - floor,
+            });
+        }
+
+        // It's actually a bit unclear how disputing a withdrawal would work. Imagining an ATM,
+        // when the account holder withdraws the funds you can't really put those funds on hold
+        // anymore. We store the amount by which the available funds decreased and leave it to
+        // the dispute policy whether disputing it (with negative held funds) is allowed.
+        self.transaction_log
+            .insert(transaction_id, (currency, -amount))
+            .map_err(|err| UpdateError::storage(transaction_id, err))?;
+        self.balance_mut(currency).available -= amount;
+        Ok(())
+    }
+
+    /// Takes `amount` from the available funds without logging it or checking the funds, e.g. for
+    /// fees of an overdraft
+    pub(crate) fn charge(&mut self, amount: f64, currency: Option<Currency>) {
+        self.balance_mut(currency).available -= amount;
+    }
+
+    /// Adds `amount` to the held funds without touching the available funds, e.g. for withdrawals
+    /// of a savings account until they settle
+    pub(crate) fn change_held(&mut self, amount: f64, currency: Option<Currency>) {
+        self.balance_mut(currency).held += amount;
+    }
+
+    fn balance_mut(&mut self, currency: Option<Currency>) -> &mut Balance {
+        self.balances.entry(currency).or_default()
+    }
+
+    /// Holds what the available funds in the currency allow of the pending holds, oldest first,
+    /// once funds came in
+    fn fill_pending_holds(&mut self, currency: Option<Currency>) {
+        let mut available = self.balance(currency).available;
+        for (transaction_id, _, pending) in self
+            .pending_holds
+            .iter_mut()
+            .filter(|(_, held_in, _)| *held_in == currency)
+        {
+            if available <= 0.0 {
+                break;
+            }
+            let held = pending.min(available);
+            available -= held;
+            *pending -= held;
+            if let Some((_, disputed)) = self.active_disputes.get_mut(transaction_id) {
+                *disputed += held;
+            }
+            let balance = self.balances.entry(currency).or_default();
+            balance.available -= held;
+            balance.held += held;
+        }
+        self.pending_holds
+            .retain(|(_, _, pending)| *pending >= DUST);
+    }
+
+    /// What the dispute of the transaction still waits for to be held
+    fn pending_hold(&self, transaction_id: TransactionId) -> f64 {
+        self.pending_holds
+            .iter()
+            .find(|(id, _, _)| *id == transaction_id)
+            .map_or(0.0, |(_, _, pending)| *pending)
     }
 }
 
 impl ClientAccount for BasicAccount {
-    fn deposit(&mut self, transaction_id: TransactionId, amount: f64) {
-        self.available += amount;
-        self.transaction_log.insert(transaction_id, amount);
+    fn deposit(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), UpdateError> {
+        self.check_lock(TransactionType::Deposit, transaction_id)?;
+        self.transaction_log
+            .insert(transaction_id, (currency, amount))
+            .map_err(|err| UpdateError::storage(transaction_id, err))?;
+        self.balance_mut(currency).available += amount;
+        self.fill_pending_holds(currency);
+        Ok(())
     }
 
-    /// Does nothing if there are not enough available funds
-    fn withdraw(&mut self, transaction_id: TransactionId, amount: f64) {
-        if self.available >= amount {
-            self.available -= amount;
-            // It's actually a bit unclear to me how disputing a withdrawal would work.
-            // Imagining an ATM, when the account holder withdraws the funds you can't really put
-            // those funds on hold anymore.
-            // I will assume that what we aim for is an ability to reverse a transaction in dispute
-            // so here we store the amount by which the available funds decreased, but this also
-            // means that when you put this transaction on dispute the held funds can be
-            // negative, which might not make sense
-            self.transaction_log.insert(transaction_id, -amount);
-        }
+    /// Fails if there are not enough available funds in the currency
+    fn withdraw(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), UpdateError> {
+        self.withdraw_down_to(transaction_id, amount, currency, 0.0)
     }
 
-    fn dispute(&mut self, transaction_id: TransactionId) {
-        // remove transaction from the log so that it cannot be disputed twice
-        if let Some(amount) = self.transaction_log.remove(&transaction_id) {
-            self.active_disputes.insert(transaction_id, amount);
-            self.available -= amount;
-            self.held += amount;
+    fn adjust(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), UpdateError> {
+        let available = self.balance(currency).available;
+        if available + amount < 0.0 {
+            return Err(UpdateError::InsufficientFunds {
+                transaction_id,
+                requested: -amount,
+                available,
+            });
         }
+        self.balance_mut(currency).available += amount;
+        self.fill_pending_holds(currency);
+        Ok(())
     }
 
-    fn resolve(&mut self, transaction_id: TransactionId) {
-        // remove transaction from disputes so that it cannot be resolved twice
-        if let Some(amount) = self.active_disputes.remove(&transaction_id) {
-            self.held -= amount;
-            self.available += amount;
+    fn post_adjustment(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), UpdateError> {
+        self.transaction_log
+            .insert(transaction_id, (currency, amount))
+            .map_err(|err| UpdateError::storage(transaction_id, err))?;
+        self.balance_mut(currency).available += amount;
+        self.fill_pending_holds(currency);
+        Ok(())
+    }
+
+    fn dispute(&mut self, transaction_id: TransactionId) -> Result<(), UpdateError> {
+        self.hold(transaction_id, None)
+    }
+
+    fn dispute_partial(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+    ) -> Result<(), UpdateError> {
+        self.hold(transaction_id, Some(amount))
+    }
+
+    fn resolve(&mut self, transaction_id: TransactionId) -> Result<(), UpdateError> {
+        self.check_lock(TransactionType::Resolve, transaction_id)?;
+        let (currency, amount) = *self
+            .active_disputes
+            .get(&transaction_id)
+            .ok_or(UpdateError::NoActiveDispute(transaction_id))?;
+        let (_, cycles) = self.dispute_state(transaction_id);
+        // What was never held is disputed all the same
+        let pending = self.pending_hold(transaction_id);
+        if self.policies.max_dispute_cycles.allows_redispute(cycles) {
+            // The released funds become disputable again, next to any undisputed remainder
+            let remaining = self
+                .transaction_log
+                .get(transaction_id)
+                .map_err(|err| UpdateError::storage(transaction_id, err))?
+                .map_or(0.0, |(_, remaining)| remaining);
+            self.transaction_log
+                .insert(transaction_id, (currency, remaining + amount + pending))
+                .map_err(|err| UpdateError::storage(transaction_id, err))?;
         }
+        // remove transaction from disputes so that it cannot be resolved twice
+        self.active_disputes.remove(&transaction_id);
+        self.pending_holds
+            .retain(|(id, _, _)| *id != transaction_id);
+        self.dispute_states
+            .insert(transaction_id, (DisputeState::Resolved, cycles));
+        let balance = self.balance_mut(currency);
+        balance.held -= amount;
+        balance.available += amount;
+        self.fill_pending_holds(currency);
+        Ok(())
     }
 
-    fn chargeback(&mut self, transaction_id: TransactionId) {
+    fn chargeback(&mut self, transaction_id: TransactionId) -> Result<(), UpdateError> {
+        self.check_lock(TransactionType::Chargeback, transaction_id)?;
+        let (currency, amount) = *self
+            .active_disputes
+            .get(&transaction_id)
+            .ok_or(UpdateError::NoActiveDispute(transaction_id))?;
+        // The disputed part already left the log, an undisputed remainder stays disputable
         // remove transaction from disputes so that it cannot be chargebacked twice
-        if let Some(amount) = self.active_disputes.remove(&transaction_id) {
-            self.held -= amount;
-            self.locked = true;
+        self.active_disputes.remove(&transaction_id);
+        // Only what was held is charged back
+        self.pending_holds
+            .retain(|(id, _, _)| *id != transaction_id);
+        let (_, cycles) = self.dispute_state(transaction_id);
+        self.dispute_states
+            .insert(transaction_id, (DisputeState::ChargedBack, cycles));
+        // Chargebacks of further parts add up, so that a representment credits all of them
+        self.chargebacks
+            .entry(transaction_id)
+            .or_insert((currency, 0.0))
+            .1 += amount;
+        self.balance_mut(currency).held -= amount;
+        self.locked = true;
+        Ok(())
+    }
+
+    fn represent(&mut self, transaction_id: TransactionId) -> Result<(), UpdateError> {
+        let (currency, amount) = self
+            .chargebacks
+            .remove(&transaction_id)
+            .ok_or(UpdateError::NotChargedBack(transaction_id))?;
+        let (_, cycles) = self.dispute_state(transaction_id);
+        self.dispute_states
+            .insert(transaction_id, (DisputeState::Represented, cycles));
+        // Negative for withdrawals, whose chargeback credited the client
+        self.balance_mut(currency).available += amount;
+        self.fill_pending_holds(currency);
+        if self.policies.unlock_on_representment && self.chargebacks.is_empty() {
+            self.locked = false;
         }
+        Ok(())
     }
 
-    fn get_client_id(&self) -> ClientId {
-        self.client_id
+    fn authorize(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), UpdateError> {
+        self.check_lock(TransactionType::Authorize, transaction_id)?;
+        let available = self.balance(currency).available;
+        if available < amount {
+            return Err(UpdateError::InsufficientFunds {
+                transaction_id,
+                requested: amount,
+                available,
+            });
+        }
+        self.authorizations
+            .insert(transaction_id, (currency, amount));
+        let balance = self.balance_mut(currency);
+        balance.available -= amount;
+        balance.held += amount;
+        Ok(())
     }
 
-    fn get_total_funds(&self) -> f64 {
-        self.available + self.held
+    fn capture(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: Option<f64>,
+    ) -> Result<(), UpdateError> {
+        self.check_lock(TransactionType::Capture, transaction_id)?;
+        let (currency, authorized) = *self
+            .authorizations
+            .get(&transaction_id)
+            .ok_or(UpdateError::NoAuthorization(transaction_id))?;
+        let captured = match amount {
+            Some(amount) if amount > 0.0 && amount <= authorized + DUST => amount.min(authorized),
+            Some(amount) => {
+                return Err(UpdateError::InvalidCaptureAmount {
+                    transaction_id,
+                    amount,
+                    authorized,
+                })
+            }
+            None => authorized,
+        };
+        // Logged as a withdrawal would be, so that the payment can be disputed once captured
+        self.transaction_log
+            .insert(transaction_id, (currency, -captured))
+            .map_err(|err| UpdateError::storage(transaction_id, err))?;
+        self.authorizations.remove(&transaction_id);
+        let balance = self.balance_mut(currency);
+        balance.held -= authorized;
+        balance.available += authorized - captured;
+        self.fill_pending_holds(currency);
+        Ok(())
     }
 
-    fn get_available_funds(&self) -> f64 {
-        self.available
+    fn void(&mut self, transaction_id: TransactionId) -> Result<(), UpdateError> {
+        self.check_lock(TransactionType::Void, transaction_id)?;
+        let (currency, amount) = self
+            .authorizations
+            .remove(&transaction_id)
+            .ok_or(UpdateError::NoAuthorization(transaction_id))?;
+        let balance = self.balance_mut(currency);
+        balance.held -= amount;
+        balance.available += amount;
+        self.fill_pending_holds(currency);
+        Ok(())
     }
 
-    fn get_held_funds(&self) -> f64 {
-        self.held
+    fn dispute_state(&self, transaction_id: TransactionId) -> (DisputeState, u32) {
+        self.dispute_states
+            .get(&transaction_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn forget(&mut self, transaction_id: TransactionId) -> bool {
+        // A failed removal only leaves the transaction disputable
+        let _ = self.transaction_log.remove(transaction_id);
+        let disputed = self.active_disputes.contains_key(&transaction_id);
+        if !disputed {
+            self.dispute_states.remove(&transaction_id);
+            self.chargebacks.remove(&transaction_id);
+        }
+        disputed || self.authorizations.contains_key(&transaction_id)
+    }
+
+    fn get_client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    fn balance(&self, currency: Option<Currency>) -> Balance {
+        self.balances.get(&currency).copied().unwrap_or_default()
+    }
+
+    fn currencies(&self) -> Vec<Option<Currency>> {
+        if self.balances.is_empty() {
+            return vec![None];
+        }
+        self.balances.keys().copied().collect()
     }
 
     fn is_locked(&self) -> bool {
         self.locked
     }
+
+    fn set_locked(&mut self, locked: bool) {
+        self.locked = locked;
+    }
+
+    fn state(&self) -> io::Result<AccountState> {
+        let transaction_log = self.transaction_log.entries()?;
+        let mut active_disputes: Vec<_> = self
+            .active_disputes
+            .iter()
+            .map(|(id, (currency, amount))| (*id, *currency, *amount))
+            .collect();
+        active_disputes.sort_by_key(|(id, _, _)| *id);
+        let mut dispute_states: Vec<_> = self
+            .dispute_states
+            .iter()
+            .map(|(id, (state, cycles))| (*id, *state, *cycles))
+            .collect();
+        dispute_states.sort_by_key(|(id, _, _)| *id);
+        let mut authorizations: Vec<_> = self
+            .authorizations
+            .iter()
+            .map(|(id, (currency, amount))| (*id, *currency, *amount))
+            .collect();
+        authorizations.sort_by_key(|(id, _, _)| *id);
+        let mut chargebacks: Vec<_> = self
+            .chargebacks
+            .iter()
+            .map(|(id, (currency, amount))| (*id, *currency, *amount))
+            .collect();
+        chargebacks.sort_by_key(|(id, _, _)| *id);
+
+        Ok(AccountState {
+            client_id: self.client_id,
+            balances: self
+                .balances
+                .iter()
+                .map(|(currency, balance)| (*currency, *balance))
+                .collect(),
+            locked: self.locked,
+            transaction_log,
+            active_disputes,
+            dispute_states,
+            pending_withdrawals: Vec::new(),
+            authorizations,
+            chargebacks,
+            pending_holds: self.pending_holds.clone(),
+        })
+    }
+}
+
+/// Creates the accounts of a [`TransactionEngine`](crate::engine::TransactionEngine), so that
+/// library users can plug in their own [`ClientAccount`] implementation, e.g. one backed by an
+/// external system. Set in [`EngineConfig::account_factory`](crate::engine::EngineConfig).
+/// Factories are shared by the worker threads of a sharded engine.
+pub trait AccountFactory: Send + Sync + fmt::Debug {
+    /// New account of the client, keeping its disputable transactions in `store` unless it is
+    /// `None`, in which case they stay in memory
+    fn create(
+        &self,
+        client_id: ClientId,
+        policies: AccountPolicies,
+        store: Option<Box<dyn TransactionStore>>,
+    ) -> Box<dyn ClientAccount>;
+
+    /// Account restored from a snapshot, writing its transaction log to `store` if given. Fails
+    /// if the store cannot be written.
+    fn restore(
+        &self,
+        state: AccountState,
+        policies: AccountPolicies,
+        store: Option<Box<dyn TransactionStore>>,
+    ) -> io::Result<Box<dyn ClientAccount>>;
+}
+
+/// Factory of [`BasicAccount`]s, used unless the engine is given another one
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BasicAccountFactory;
+
+impl AccountFactory for BasicAccountFactory {
+    fn create(
+        &self,
+        client_id: ClientId,
+        policies: AccountPolicies,
+        store: Option<Box<dyn TransactionStore>>,
+    ) -> Box<dyn ClientAccount> {
+        match store {
+            Some(store) => Box::new(BasicAccount::with_store(client_id, policies, store)),
+            None => Box::new(BasicAccount::with_policies(client_id, policies)),
+        }
+    }
+
+    fn restore(
+        &self,
+        state: AccountState,
+        policies: AccountPolicies,
+        store: Option<Box<dyn TransactionStore>>,
+    ) -> io::Result<Box<dyn ClientAccount>> {
+        Ok(match store {
+            Some(store) => Box::new(BasicAccount::from_state_with_store(state, policies, store)?),
+            None => Box::new(BasicAccount::from_state(state, policies)),
+        })
+    }
+}
+
+/// Account type of every client, read from the `[accounts]` table of the configuration file,
+/// with the overdraft of overdraft accounts read from the `[overdraft]` table and the delay of
+/// savings accounts from the `[savings]` table:
+///
+/// ```toml
+/// [accounts]
+/// default = "basic"    # clients not listed below: basic, overdraft or savings
+/// 7 = "overdraft"
+/// 8 = "savings"
+///
+/// [overdraft]
+/// limit = 500
+/// fee = 25
+///
+/// [savings]
+/// withdrawal_delay = 3
+/// ```
+///
+/// The [`Tier`](crate::tier::Tier) of a client may set the type and overdraft of its account.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccountTypes {
+    /// Type of the clients not listed in `clients`, nor in a tier setting it
+    pub default: AccountKind,
+    pub clients: BTreeMap<ClientId, AccountKind>,
+    pub overdraft: OverdraftPolicy,
+    pub savings: SavingsPolicy,
+    pub tiers: Arc<Tiers>,
+}
+
+impl AccountTypes {
+    pub fn kind(&self, client_id: ClientId) -> AccountKind {
+        let tier = || self.tiers.policy(client_id).and_then(|tier| tier.account);
+        self.clients
+            .get(&client_id)
+            .copied()
+            .or_else(tier)
+            .unwrap_or(self.default)
+    }
+
+    /// Overdraft of the account of the client, that of its tier where it has one
+    pub fn overdraft(&self, client_id: ClientId) -> OverdraftPolicy {
+        match self.tiers.policy(client_id) {
+            Some(tier) => tier.overdraft(self.overdraft),
+            None => self.overdraft,
+        }
+    }
+
+    /// Reads the types of the `[accounts]` table, keeping the overdraft and savings policies
+    pub(crate) fn read_table(&mut self, table: &toml::Table) -> Result<(), ConfigError> {
+        for (key, entry) in &table.entries {
+            let kind = entry.parse(key)?;
+            match key.parse::<ClientId>() {
+                _ if key == "default" => self.default = kind,
+                Ok(client_id) => {
+                    self.clients.insert(client_id, kind);
+                }
+                Err(_) => {
+                    return Err(entry.invalid(format!(
+                        "expected 'default' or a client id in [accounts], found '{}'",
+                        key
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AccountFactory for AccountTypes {
+    fn create(
+        &self,
+        client_id: ClientId,
+        policies: AccountPolicies,
+        store: Option<Box<dyn TransactionStore>>,
+    ) -> Box<dyn ClientAccount> {
+        match (self.kind(client_id), store) {
+            (AccountKind::Basic, store) => BasicAccountFactory.create(client_id, policies, store),
+            (AccountKind::Overdraft, Some(store)) => Box::new(OverdraftAccount::with_store(
+                client_id,
+                policies,
+                self.overdraft(client_id),
+                store,
+            )),
+            (AccountKind::Overdraft, None) => Box::new(OverdraftAccount::with_policies(
+                client_id,
+                policies,
+                self.overdraft(client_id),
+            )),
+            (AccountKind::Savings, Some(store)) => Box::new(SavingsAccount::with_store(
+                client_id,
+                policies,
+                self.savings,
+                store,
+            )),
+            (AccountKind::Savings, None) => Box::new(SavingsAccount::with_policies(
+                client_id,
+                policies,
+                self.savings,
+            )),
+        }
+    }
+
+    fn restore(
+        &self,
+        state: AccountState,
+        policies: AccountPolicies,
+        store: Option<Box<dyn TransactionStore>>,
+    ) -> io::Result<Box<dyn ClientAccount>> {
+        let client_id = state.client_id;
+        match self.kind(client_id) {
+            AccountKind::Basic => BasicAccountFactory.restore(state, policies, store),
+            AccountKind::Overdraft => Ok(Box::new(OverdraftAccount::from_state(
+                state,
+                policies,
+                self.overdraft(client_id),
+                store,
+            )?)),
+            AccountKind::Savings => Ok(Box::new(SavingsAccount::from_state(
+                state,
+                policies,
+                self.savings,
+                store,
+            )?)),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     mod unit {
-        use crate::account::{BasicAccount, ClientAccount};
+        use crate::account::{Balance, BasicAccount, ClientAccount, DisputeState};
+        use crate::currency::Currency;
+        use crate::error::UpdateError;
+        use crate::policy::{
+            AccountPolicies, DisputeCycles, DisputePolicy, LockPolicy, NegativeBalancePolicy,
+        };
+        use crate::transaction::TransactionId;
 
         fn approx_eq(a: f64, b: f64) -> bool {
             (a - b).abs() < f64::EPSILON
@@ -140,8 +1073,8 @@ mod tests {
         fn deposit_and_withdraw_works() {
             let mut account = BasicAccount::new(0);
 
-            account.deposit(0, 2.0);
-            account.withdraw(1, 1.0);
+            account.deposit(TransactionId(0), 2.0, None).unwrap();
+            account.withdraw(TransactionId(1), 1.0, None).unwrap();
 
             assert!(approx_eq(account.get_available_funds(), 1.0));
         }
@@ -150,8 +1083,8 @@ mod tests {
         fn dispute_increases_held_funds() {
             let mut account = BasicAccount::new(0);
 
-            account.deposit(0, 2.0);
-            account.dispute(0);
+            account.deposit(TransactionId(0), 2.0, None).unwrap();
+            account.dispute(TransactionId(0)).unwrap();
 
             assert!(approx_eq(account.get_available_funds(), 0.0));
             assert!(approx_eq(account.get_held_funds(), 2.0));
@@ -161,9 +1094,9 @@ mod tests {
         fn resolving_dispute_brings_back_available_funds() {
             let mut account = BasicAccount::new(0);
 
-            account.deposit(0, 2.0);
-            account.dispute(0);
-            account.resolve(0);
+            account.deposit(TransactionId(0), 2.0, None).unwrap();
+            account.dispute(TransactionId(0)).unwrap();
+            account.resolve(TransactionId(0)).unwrap();
 
             assert!(approx_eq(account.get_available_funds(), 2.0));
             assert!(approx_eq(account.get_held_funds(), 0.0));
@@ -173,9 +1106,9 @@ mod tests {
         fn chargeback_removes_funds_and_locks_account() {
             let mut account = BasicAccount::new(0);
 
-            account.deposit(0, 2.0);
-            account.dispute(0);
-            account.chargeback(0);
+            account.deposit(TransactionId(0), 2.0, None).unwrap();
+            account.dispute(TransactionId(0)).unwrap();
+            account.chargeback(TransactionId(0)).unwrap();
 
             assert!(approx_eq(account.get_available_funds(), 0.0));
             assert!(approx_eq(account.get_held_funds(), 0.0));
@@ -186,12 +1119,12 @@ mod tests {
         fn withdrawing_with_not_enough_funds_has_no_effect() {
             let mut account = BasicAccount::new(0);
 
-            account.deposit(0, 2.0);
-            account.withdraw(1, 3.0);
+            account.deposit(TransactionId(0), 2.0, None).unwrap();
+            assert!(account.withdraw(TransactionId(1), 3.0, None).is_err());
 
             // Also check that disputing and resolving withdraw transaction does nothing
-            account.dispute(1);
-            account.resolve(1);
+            assert!(account.dispute(TransactionId(1)).is_err());
+            assert!(account.resolve(TransactionId(1)).is_err());
 
             assert!(approx_eq(account.get_available_funds(), 2.0));
         }
@@ -200,41 +1133,542 @@ mod tests {
         fn disputing_withdrawal_and_resolving_withdrawal_works() {
             let mut account = BasicAccount::new(0);
 
-            account.deposit(0, 5.0);
-            account.withdraw(1, 3.0);
+            account.deposit(TransactionId(0), 5.0, None).unwrap();
+            account.withdraw(TransactionId(1), 3.0, None).unwrap();
 
             // Also check that disputing and resolving withdraw transaction does nothing
-            account.dispute(1);
+            account.dispute(TransactionId(1)).unwrap();
             assert!(approx_eq(account.get_available_funds(), 5.0));
             assert!(approx_eq(account.get_held_funds(), -3.0));
 
-            account.resolve(1);
+            account.resolve(TransactionId(1)).unwrap();
+            assert!(approx_eq(account.get_available_funds(), 2.0));
+            assert!(approx_eq(account.get_held_funds(), 0.0));
+        }
+
+        #[test]
+        fn partial_dispute_holds_only_the_disputed_portion() {
+            let mut account = BasicAccount::new(0);
+            account.deposit(TransactionId(0), 10.0, None).unwrap();
+
+            account.dispute_partial(TransactionId(0), 4.0).unwrap();
+            assert!(approx_eq(account.get_available_funds(), 6.0));
+            assert!(approx_eq(account.get_held_funds(), 4.0));
+            assert_eq!(
+                account.dispute_partial(TransactionId(0), 7.0),
+                Err(UpdateError::InvalidDisputeAmount {
+                    transaction_id: TransactionId(0),
+                    amount: 7.0,
+                    disputable: 6.0
+                })
+            );
+            assert!(account.dispute_partial(TransactionId(0), 0.0).is_err());
+
+            // The remainder can be disputed while the first portion is still held
+            account.dispute_partial(TransactionId(0), 1.0).unwrap();
+            assert!(approx_eq(account.get_held_funds(), 5.0));
+            account.resolve(TransactionId(0)).unwrap();
+            assert!(approx_eq(account.get_available_funds(), 10.0));
+            assert!(approx_eq(account.get_held_funds(), 0.0));
+
+            account.dispute(TransactionId(0)).unwrap();
+            assert!(approx_eq(account.get_held_funds(), 5.0));
+            account.chargeback(TransactionId(0)).unwrap();
+            assert!(approx_eq(account.get_total_funds(), 5.0));
+            assert!(account.is_locked());
+        }
+
+        #[test]
+        fn partial_dispute_of_withdrawal_keeps_the_rest_disputable() {
+            let mut account = BasicAccount::new(0);
+            account.deposit(TransactionId(0), 5.0, None).unwrap();
+            account.withdraw(TransactionId(1), 3.0, None).unwrap();
+
+            account.dispute_partial(TransactionId(1), 1.0).unwrap();
+            assert!(approx_eq(account.get_available_funds(), 3.0));
+            assert!(approx_eq(account.get_held_funds(), -1.0));
+            account.dispute_partial(TransactionId(1), 2.0).unwrap();
+            assert!(approx_eq(account.get_held_funds(), -3.0));
+            assert_eq!(
+                account.dispute(TransactionId(1)),
+                Err(UpdateError::TransactionNotFound(TransactionId(1)))
+            );
+        }
+
+        #[test]
+        fn resolved_transaction_is_disputable_again_while_cycles_remain() {
+            let mut account = BasicAccount::with_policies(
+                0,
+                AccountPolicies {
+                    max_dispute_cycles: DisputeCycles(2),
+                    ..AccountPolicies::default()
+                },
+            );
+            account.deposit(TransactionId(0), 2.0, None).unwrap();
+            assert_eq!(
+                account.dispute_state(TransactionId(0)),
+                (DisputeState::Undisputed, 0)
+            );
+
+            account.dispute(TransactionId(0)).unwrap();
+            assert_eq!(
+                account.dispute_state(TransactionId(0)),
+                (DisputeState::Disputed, 1)
+            );
+            account.resolve(TransactionId(0)).unwrap();
+            assert_eq!(
+                account.dispute_state(TransactionId(0)),
+                (DisputeState::Resolved, 1)
+            );
+
+            account.dispute(TransactionId(0)).unwrap();
+            assert!(approx_eq(account.get_held_funds(), 2.0));
+            account.resolve(TransactionId(0)).unwrap();
+            assert_eq!(
+                account.dispute_state(TransactionId(0)),
+                (DisputeState::Resolved, 2)
+            );
+            assert_eq!(
+                account.dispute(TransactionId(0)),
+                Err(UpdateError::TransactionNotFound(TransactionId(0)))
+            );
+            assert!(approx_eq(account.get_available_funds(), 2.0));
+        }
+
+        #[test]
+        fn chargeback_keeps_the_undisputed_remainder() {
+            let mut account = BasicAccount::with_policies(
+                0,
+                AccountPolicies {
+                    max_dispute_cycles: DisputeCycles(3),
+                    ..AccountPolicies::default()
+                },
+            );
+            account.deposit(TransactionId(0), 5.0, None).unwrap();
+            account.dispute_partial(TransactionId(0), 2.0).unwrap();
+            account.chargeback(TransactionId(0)).unwrap();
+
+            assert_eq!(
+                account.dispute_state(TransactionId(0)),
+                (DisputeState::ChargedBack, 1)
+            );
+            assert!(approx_eq(account.get_available_funds(), 3.0));
+            account.set_locked(false);
+            account.dispute(TransactionId(0)).unwrap();
+            assert!(approx_eq(account.get_held_funds(), 3.0));
+            account.chargeback(TransactionId(0)).unwrap();
+            assert_eq!(
+                account.dispute_state(TransactionId(0)),
+                (DisputeState::ChargedBack, 2)
+            );
+            account.set_locked(false);
+            assert_eq!(
+                account.dispute(TransactionId(0)),
+                Err(UpdateError::TransactionNotFound(TransactionId(0)))
+            );
+
+            // The representment credits both charged back parts
+            account.represent(TransactionId(0)).unwrap();
+            assert!(approx_eq(account.get_available_funds(), 5.0));
+            assert!(approx_eq(account.get_total_funds(), 5.0));
+        }
+
+        #[test]
+        fn represented_chargebacks_are_credited_back() {
+            let mut account = BasicAccount::new(0);
+            account.deposit(TransactionId(0), 5.0, None).unwrap();
+            account.deposit(TransactionId(1), 2.0, None).unwrap();
+            assert_eq!(
+                account.represent(TransactionId(0)),
+                Err(UpdateError::NotChargedBack(TransactionId(0)))
+            );
+            for transaction_id in [0, 1] {
+                account.dispute(TransactionId(transaction_id)).unwrap();
+                account.chargeback(TransactionId(transaction_id)).unwrap();
+                account.set_locked(false);
+            }
+            account.set_locked(true);
+            assert!(approx_eq(account.get_available_funds(), 0.0));
+
+            account.represent(TransactionId(0)).unwrap();
+            assert_eq!(
+                account.dispute_state(TransactionId(0)),
+                (DisputeState::Represented, 1)
+            );
+            assert_eq!(
+                account.represent(TransactionId(0)),
+                Err(UpdateError::NotChargedBack(TransactionId(0)))
+            );
+            account.represent(TransactionId(1)).unwrap();
+            assert!(approx_eq(account.get_available_funds(), 7.0));
+            assert!(account.is_locked());
+
+            let mut account = BasicAccount::with_policies(
+                0,
+                AccountPolicies {
+                    unlock_on_representment: true,
+                    ..AccountPolicies::default()
+                },
+            );
+            account.deposit(TransactionId(0), 5.0, None).unwrap();
+            account.dispute(TransactionId(0)).unwrap();
+            account.chargeback(TransactionId(0)).unwrap();
+            account.represent(TransactionId(0)).unwrap();
+            assert!(!account.is_locked());
+            assert!(account.state().unwrap().chargebacks.is_empty());
+        }
+
+        fn dispute_after_withdrawal(policy: NegativeBalancePolicy) -> BasicAccount {
+            let mut account = BasicAccount::with_policies(
+                0,
+                AccountPolicies {
+                    negative_balance: policy,
+                    ..AccountPolicies::default()
+                },
+            );
+            account.deposit(TransactionId(0), 5.0, None).unwrap();
+            account.withdraw(TransactionId(1), 3.0, None).unwrap();
+            account
+        }
+
+        #[test]
+        fn dispute_exceeding_available_funds_goes_negative_when_allowed() {
+            let mut account = dispute_after_withdrawal(NegativeBalancePolicy::Allow);
+
+            account.dispute(TransactionId(0)).unwrap();
+
+            assert!(approx_eq(account.get_available_funds(), -3.0));
+            assert!(approx_eq(account.get_held_funds(), 5.0));
+        }
+
+        #[test]
+        fn dispute_exceeding_available_funds_can_be_rejected() {
+            let mut account = dispute_after_withdrawal(NegativeBalancePolicy::RejectDispute);
+
+            assert_eq!(
+                account.dispute(TransactionId(0)),
+                Err(UpdateError::DisputeExceedsAvailable {
+                    transaction_id: TransactionId(0),
+                    amount: 5.0,
+                    available: 2.0
+                })
+            );
+            assert!(approx_eq(account.get_available_funds(), 2.0));
+            assert!(approx_eq(account.get_held_funds(), 0.0));
+
+            // Transaction stays disputable once there are enough funds
+            account.deposit(TransactionId(2), 3.0, None).unwrap();
+            account.dispute(TransactionId(0)).unwrap();
+            assert!(approx_eq(account.get_available_funds(), 0.0));
+        }
+
+        #[test]
+        fn dispute_exceeding_available_funds_holds_partial_amount() {
+            let mut account = dispute_after_withdrawal(NegativeBalancePolicy::HoldPartial);
+
+            account.dispute(TransactionId(0)).unwrap();
+            assert!(approx_eq(account.get_available_funds(), 0.0));
+            assert!(approx_eq(account.get_held_funds(), 2.0));
+
+            account.chargeback(TransactionId(0)).unwrap();
+            assert!(approx_eq(account.get_total_funds(), 0.0));
+            assert!(account.is_locked());
+        }
+
+        #[test]
+        fn dispute_exceeding_available_funds_is_held_as_funds_come_in() {
+            let mut account = dispute_after_withdrawal(NegativeBalancePolicy::HoldPending);
+
+            account.dispute(TransactionId(0)).unwrap();
+            assert!(approx_eq(account.get_available_funds(), 0.0));
+            assert!(approx_eq(account.get_held_funds(), 2.0));
+            assert_eq!(
+                account.state().unwrap().pending_holds,
+                [(TransactionId(0), None, 3.0)]
+            );
+
+            account.deposit(TransactionId(2), 1.0, None).unwrap();
+            assert!(approx_eq(account.get_available_funds(), 0.0));
+            assert!(approx_eq(account.get_held_funds(), 3.0));
+            // Restored accounts keep waiting for the rest
+            let mut account = BasicAccount::from_state(account.state().unwrap(), account.policies);
+            assert_eq!(
+                account.state().unwrap().pending_holds,
+                [(TransactionId(0), None, 2.0)]
+            );
+
+            account.deposit(TransactionId(3), 4.0, None).unwrap();
+            assert!(approx_eq(account.get_available_funds(), 2.0));
+            assert!(approx_eq(account.get_held_funds(), 5.0));
+            assert!(account.state().unwrap().pending_holds.is_empty());
+
+            account.chargeback(TransactionId(0)).unwrap();
+            assert!(approx_eq(account.get_available_funds(), 2.0));
+            assert!(approx_eq(account.get_total_funds(), 2.0));
+            assert!(account.is_locked());
+        }
+
+        #[test]
+        fn pending_holds_end_with_their_dispute() {
+            let mut account = BasicAccount::with_policies(
+                0,
+                AccountPolicies {
+                    negative_balance: NegativeBalancePolicy::HoldPending,
+                    max_dispute_cycles: DisputeCycles(2),
+                    ..AccountPolicies::default()
+                },
+            );
+            account.deposit(TransactionId(0), 5.0, None).unwrap();
+            account.withdraw(TransactionId(1), 3.0, None).unwrap();
+
+            // Resolving releases what was held and drops the rest, which stays disputable
+            account.dispute(TransactionId(0)).unwrap();
+            account.resolve(TransactionId(0)).unwrap();
+            assert!(approx_eq(account.get_available_funds(), 2.0));
+            assert!(approx_eq(account.get_held_funds(), 0.0));
+            assert!(account.state().unwrap().pending_holds.is_empty());
+            account.deposit(TransactionId(2), 1.0, None).unwrap();
+            assert!(approx_eq(account.get_available_funds(), 3.0));
+
+            // Charging back only takes what was held
+            account.dispute(TransactionId(0)).unwrap();
+            assert_eq!(
+                account.state().unwrap().pending_holds,
+                [(TransactionId(0), None, 2.0)]
+            );
+            account.chargeback(TransactionId(0)).unwrap();
+            assert!(approx_eq(account.get_total_funds(), 0.0));
+            assert!(account.state().unwrap().pending_holds.is_empty());
+            account.set_locked(false);
+            account.deposit(TransactionId(3), 2.0, None).unwrap();
             assert!(approx_eq(account.get_available_funds(), 2.0));
             assert!(approx_eq(account.get_held_funds(), 0.0));
         }
 
-        // TODO: How to handle the case when you deposit, withdraw, and then dispute deposit. Could
-        // bring available funds to a negative value
+        #[test]
+        fn pending_holds_are_filled_in_order_of_dispute() {
+            let mut account = dispute_after_withdrawal(NegativeBalancePolicy::HoldPending);
+            account.deposit(TransactionId(2), 1.0, None).unwrap();
+            account.withdraw(TransactionId(3), 3.0, None).unwrap();
+
+            account.dispute(TransactionId(2)).unwrap();
+            account.dispute(TransactionId(0)).unwrap();
+            assert_eq!(
+                account.state().unwrap().pending_holds,
+                [(TransactionId(2), None, 1.0), (TransactionId(0), None, 5.0)]
+            );
+
+            account.deposit(TransactionId(4), 3.0, None).unwrap();
+            assert_eq!(
+                account.state().unwrap().pending_holds,
+                [(TransactionId(0), None, 3.0)]
+            );
+            assert_eq!(
+                account.state().unwrap().active_disputes,
+                [(TransactionId(0), None, 2.0), (TransactionId(2), None, 1.0)]
+            );
+            assert!(approx_eq(account.get_available_funds(), 0.0));
+        }
 
         #[test]
         fn transaction_cannot_be_disputed_twice() {
             let mut account = BasicAccount::new(0);
             let deposit_amount = 2.0;
 
-            account.deposit(0, deposit_amount);
+            account
+                .deposit(TransactionId(0), deposit_amount, None)
+                .unwrap();
 
-            account.dispute(0);
-            account.dispute(0);
+            account.dispute(TransactionId(0)).unwrap();
+            assert!(account.dispute(TransactionId(0)).is_err());
             assert!(approx_eq(account.get_held_funds(), deposit_amount));
             assert!(approx_eq(account.get_available_funds(), 0.0));
 
-            account.resolve(0);
+            account.resolve(TransactionId(0)).unwrap();
             assert!(approx_eq(account.get_available_funds(), deposit_amount));
             assert!(approx_eq(account.get_held_funds(), 0.0));
 
-            account.chargeback(0);
+            assert!(account.chargeback(TransactionId(0)).is_err());
             assert!(approx_eq(account.get_available_funds(), deposit_amount));
             assert!(approx_eq(account.get_held_funds(), 0.0));
         }
+
+        #[test]
+        fn locked_account_rejects_transactions() {
+            let mut account = BasicAccount::new(0);
+
+            account.deposit(TransactionId(0), 2.0, None).unwrap();
+            account.deposit(TransactionId(1), 3.0, None).unwrap();
+            account.dispute(TransactionId(0)).unwrap();
+            account.chargeback(TransactionId(0)).unwrap();
+
+            assert_eq!(
+                account.deposit(TransactionId(2), 1.0, None),
+                Err(UpdateError::AccountLocked(TransactionId(2)))
+            );
+            assert_eq!(
+                account.withdraw(TransactionId(3), 1.0, None),
+                Err(UpdateError::AccountLocked(TransactionId(3)))
+            );
+            assert_eq!(
+                account.dispute(TransactionId(1)),
+                Err(UpdateError::AccountLocked(TransactionId(1)))
+            );
+            assert!(approx_eq(account.get_available_funds(), 3.0));
+        }
+
+        #[test]
+        fn locked_account_settles_disputes_opened_before_the_lock() {
+            let mut account = BasicAccount::new(0);
+
+            account.deposit(TransactionId(0), 2.0, None).unwrap();
+            account.deposit(TransactionId(1), 3.0, None).unwrap();
+            account.deposit(TransactionId(2), 4.0, None).unwrap();
+            account.dispute(TransactionId(0)).unwrap();
+            account.dispute(TransactionId(1)).unwrap();
+            account.dispute(TransactionId(2)).unwrap();
+            account.chargeback(TransactionId(0)).unwrap();
+
+            account.resolve(TransactionId(1)).unwrap();
+            account.chargeback(TransactionId(2)).unwrap();
+            assert!(approx_eq(account.get_available_funds(), 3.0));
+            assert!(approx_eq(account.get_held_funds(), 0.0));
+            assert!(account.is_locked());
+        }
+
+        #[test]
+        fn locked_account_accepts_deposits_when_allowed() {
+            let mut account = BasicAccount::with_lock_policy(0, LockPolicy::AllowDeposits);
+
+            account.deposit(TransactionId(0), 2.0, None).unwrap();
+            account.dispute(TransactionId(0)).unwrap();
+            account.chargeback(TransactionId(0)).unwrap();
+
+            account.deposit(TransactionId(1), 1.0, None).unwrap();
+            assert_eq!(
+                account.withdraw(TransactionId(2), 1.0, None),
+                Err(UpdateError::AccountLocked(TransactionId(2)))
+            );
+            assert!(approx_eq(account.get_available_funds(), 1.0));
+        }
+
+        #[test]
+        fn chargeback_of_disputed_withdrawal_credits_client() {
+            let mut account = BasicAccount::new(0);
+
+            account.deposit(TransactionId(0), 5.0, None).unwrap();
+            account.withdraw(TransactionId(1), 3.0, None).unwrap();
+            account.dispute(TransactionId(1)).unwrap();
+            account.chargeback(TransactionId(1)).unwrap();
+
+            assert!(approx_eq(account.get_available_funds(), 5.0));
+            assert!(approx_eq(account.get_held_funds(), 0.0));
+            assert!(account.is_locked());
+        }
+
+        #[test]
+        fn withdrawals_are_not_disputable_with_deposits_only_policy() {
+            let mut account = BasicAccount::with_policies(
+                0,
+                AccountPolicies {
+                    dispute: DisputePolicy::DepositsOnly,
+                    ..AccountPolicies::default()
+                },
+            );
+
+            account.deposit(TransactionId(0), 5.0, None).unwrap();
+            account.withdraw(TransactionId(1), 3.0, None).unwrap();
+
+            assert_eq!(
+                account.dispute(TransactionId(1)),
+                Err(UpdateError::NotDisputable(TransactionId(1)))
+            );
+            assert!(approx_eq(account.get_available_funds(), 2.0));
+            assert!(approx_eq(account.get_held_funds(), 0.0));
+            account.dispute(TransactionId(0)).unwrap();
+        }
+
+        #[test]
+        fn authorization_holds_funds_until_captured_or_voided() {
+            let mut account = BasicAccount::new(0);
+            account.deposit(TransactionId(0), 10.0, None).unwrap();
+
+            account.authorize(TransactionId(1), 6.0, None).unwrap();
+            assert!(approx_eq(account.get_available_funds(), 4.0));
+            assert!(approx_eq(account.get_held_funds(), 6.0));
+            assert_eq!(
+                account.authorize(TransactionId(2), 5.0, None),
+                Err(UpdateError::InsufficientFunds {
+                    transaction_id: TransactionId(2),
+                    requested: 5.0,
+                    available: 4.0
+                })
+            );
+            assert_eq!(
+                account.dispute(TransactionId(1)),
+                Err(UpdateError::TransactionNotFound(TransactionId(1)))
+            );
+            assert_eq!(
+                account.capture(TransactionId(1), Some(7.0)),
+                Err(UpdateError::InvalidCaptureAmount {
+                    transaction_id: TransactionId(1),
+                    amount: 7.0,
+                    authorized: 6.0
+                })
+            );
+
+            // A partial capture releases the rest of the hold
+            account.capture(TransactionId(1), Some(2.5)).unwrap();
+            assert!(approx_eq(account.get_available_funds(), 7.5));
+            assert!(approx_eq(account.get_held_funds(), 0.0));
+            assert_eq!(
+                account.capture(TransactionId(1), None),
+                Err(UpdateError::NoAuthorization(TransactionId(1)))
+            );
+            // The captured amount is disputable like a withdrawal
+            account.dispute(TransactionId(1)).unwrap();
+            assert!(approx_eq(account.get_held_funds(), -2.5));
+            account.resolve(TransactionId(1)).unwrap();
+
+            account.authorize(TransactionId(3), 7.5, None).unwrap();
+            account.void(TransactionId(3)).unwrap();
+            assert!(approx_eq(account.get_available_funds(), 7.5));
+            assert!(approx_eq(account.get_held_funds(), 0.0));
+            assert_eq!(
+                account.void(TransactionId(3)),
+                Err(UpdateError::NoAuthorization(TransactionId(3)))
+            );
+        }
+
+        #[test]
+        fn funds_are_kept_per_currency() {
+            let eur: Currency = "EUR".parse().unwrap();
+            let usd: Currency = "USD".parse().unwrap();
+            let mut account = BasicAccount::new(0);
+
+            account.deposit(TransactionId(0), 5.0, Some(eur)).unwrap();
+            account.deposit(TransactionId(1), 1.0, None).unwrap();
+            // Funds in other currencies do not cover a withdrawal
+            assert_eq!(
+                account.withdraw(TransactionId(2), 2.0, Some(usd)),
+                Err(UpdateError::InsufficientFunds {
+                    transaction_id: TransactionId(2),
+                    requested: 2.0,
+                    available: 0.0
+                })
+            );
+            account.dispute(TransactionId(0)).unwrap();
+
+            assert_eq!(account.currencies(), vec![None, Some(eur)]);
+            assert_eq!(
+                account.balance(Some(eur)),
+                Balance {
+                    available: 0.0,
+                    held: 5.0
+                }
+            );
+            assert!(approx_eq(account.get_available_funds(), 1.0));
+            assert!(approx_eq(account.get_held_funds(), 0.0));
+        }
     }
 }
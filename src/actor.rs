@@ -0,0 +1,295 @@
+//! Engine run as actors: a dispatcher thread routes every message to the mailbox of the shard
+//! owning the client, and each shard thread owns its accounts outright.
+
+use crate::account::{AccountView, ClientId};
+use crate::engine::{EngineConfig, TransactionEngine};
+use crate::error::EngineError;
+use crate::output::AccountSnapshot;
+use crate::processor::TransactionProcessor;
+use crate::sharded::ShardRouter;
+use crate::transaction::{Origin, Transaction};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread::{self, JoinHandle};
+
+/// Messages waiting in a mailbox before the sender blocks
+const MAILBOX_CAPACITY: usize = 1024;
+
+enum Message {
+    Execute {
+        transaction: Transaction,
+        origin: Origin,
+        /// Where to send the result, if anybody waits for it
+        reply: Option<Sender<Result<(), EngineError>>>,
+    },
+    Account {
+        client_id: ClientId,
+        reply: Sender<Option<AccountView>>,
+    },
+    /// Sent to the shard of the client, or to every shard if `None`, each replying with the
+    /// funds of the client or of all of its accounts
+    Snapshots {
+        client_id: Option<ClientId>,
+        reply: Sender<Vec<AccountSnapshot>>,
+    },
+}
+
+/// Alternative to [`ConcurrentEngine`](crate::concurrent::ConcurrentEngine) without any lock:
+/// threads sharing the engine, e.g. behind an `Arc`, send messages to a dispatcher which routes
+/// them by client id to the mailboxes of the shards. Queries are messages too, answered once the
+/// shard processed the transactions queued before them, so a thread always reads its own writes.
+///
+/// Clients are partitioned as in a [`ShardedEngine`](crate::sharded::ShardedEngine) and
+/// transfers between clients of different shards are rejected.
+pub struct ActorEngine {
+    shards: usize,
+    dispatcher: SyncSender<Message>,
+    worker: JoinHandle<Vec<TransactionEngine>>,
+}
+
+impl ActorEngine {
+    pub fn new(shards: usize, config: EngineConfig) -> Self {
+        let shards = shards.max(1);
+        let (dispatcher, mailbox) = mpsc::sync_channel(MAILBOX_CAPACITY);
+        let worker = thread::spawn(move || dispatch(mailbox, shards, config));
+        ActorEngine {
+            shards,
+            dispatcher,
+            worker,
+        }
+    }
+
+    /// Executes the transaction on the shard of its client and waits for the result
+    pub fn submit(&self, transaction: Transaction) -> Result<(), EngineError> {
+        self.submit_from(transaction, Origin::Client)
+    }
+
+    /// Executes a transaction from the given source, see [`TransactionEngine::execute_from`]
+    pub fn submit_from(&self, transaction: Transaction, origin: Origin) -> Result<(), EngineError> {
+        let (reply, result) = mpsc::channel();
+        self.send(Message::Execute {
+            transaction,
+            origin,
+            reply: Some(reply),
+        });
+        result.recv().expect("Shard actor stopped unexpectedly")
+    }
+
+    /// Queues the transaction without waiting for it. Rejections only show in the metrics of
+    /// the engine. Blocks while the mailbox of the dispatcher is full.
+    pub fn post(&self, transaction: Transaction) {
+        self.send(Message::Execute {
+            transaction,
+            origin: Origin::Client,
+            reply: None,
+        });
+    }
+
+    /// Funds and lock of the client's account once the transactions sent before are executed
+    pub fn account(&self, client_id: ClientId) -> Option<AccountView> {
+        let (reply, account) = mpsc::channel();
+        self.send(Message::Account { client_id, reply });
+        account.recv().expect("Shard actor stopped unexpectedly")
+    }
+
+    /// Waits for the queued transactions and merges the shards into a single engine
+    pub fn into_engine(self) -> TransactionEngine {
+        // Closing the mailbox stops the dispatcher, which stops the shards in turn
+        drop(self.dispatcher);
+        let mut shards = self
+            .worker
+            .join()
+            .expect("Dispatcher actor panicked")
+            .into_iter();
+        let mut merged = shards.next().expect("Actor engine has at least one shard");
+        for engine in shards {
+            merged.absorb(engine);
+        }
+        merged
+    }
+
+    fn send(&self, message: Message) {
+        self.dispatcher
+            .send(message)
+            .expect("Dispatcher actor stopped unexpectedly");
+    }
+
+    /// Funds of the client, or of every account, once the transactions sent before are executed
+    fn snapshots(&self, client_id: Option<ClientId>) -> Vec<AccountSnapshot> {
+        let (reply, snapshots) = mpsc::channel();
+        self.send(Message::Snapshots { client_id, reply });
+        let replies = if client_id.is_some() { 1 } else { self.shards };
+        snapshots.iter().take(replies).flatten().collect()
+    }
+}
+
+impl TransactionProcessor for ActorEngine {
+    fn execute_from(
+        &mut self,
+        transaction: Transaction,
+        origin: Origin,
+    ) -> Result<(), EngineError> {
+        self.submit_from(transaction, origin)
+    }
+
+    fn account_snapshot(&self, client_id: ClientId) -> Option<AccountSnapshot> {
+        self.snapshots(Some(client_id)).pop()
+    }
+
+    fn iter_snapshots(&self) -> Box<dyn Iterator<Item = AccountSnapshot> + '_> {
+        Box::new(self.snapshots(None).into_iter())
+    }
+
+    fn finalize(self) -> (TransactionEngine, Vec<EngineError>) {
+        (self.into_engine(), Vec::new())
+    }
+}
+
+/// Routes the messages of `mailbox` to the shards until every sender is gone, then returns the
+/// engines of the shards
+fn dispatch(
+    mailbox: Receiver<Message>,
+    shards: usize,
+    config: EngineConfig,
+) -> Vec<TransactionEngine> {
+    let mut router = ShardRouter::new(shards);
+    let (senders, workers): (Vec<_>, Vec<_>) = (0..shards)
+        .map(|_| {
+            let (sender, mailbox) = mpsc::sync_channel(MAILBOX_CAPACITY);
+            let config = config.clone();
+            (sender, thread::spawn(move || run_shard(mailbox, config)))
+        })
+        .unzip();
+    for message in mailbox {
+        let shard = match &message {
+            Message::Execute { transaction, .. } => match router.route(transaction) {
+                Ok(shard) => shard,
+                Err(err) => {
+                    if let Message::Execute {
+                        reply: Some(reply), ..
+                    } = message
+                    {
+                        let _ = reply.send(Err(err));
+                    }
+                    continue;
+                }
+            },
+            Message::Account { client_id, .. }
+            | Message::Snapshots {
+                client_id: Some(client_id),
+                ..
+            } => router.shard_of(*client_id),
+            Message::Snapshots {
+                client_id: None,
+                reply,
+            } => {
+                for sender in &senders {
+                    let message = Message::Snapshots {
+                        client_id: None,
+                        reply: reply.clone(),
+                    };
+                    sender
+                        .send(message)
+                        .expect("Shard actor stopped unexpectedly");
+                }
+                continue;
+            }
+        };
+        senders[shard]
+            .send(message)
+            .expect("Shard actor stopped unexpectedly");
+    }
+    drop(senders);
+    workers
+        .into_iter()
+        .map(|worker| worker.join().expect("Shard actor panicked"))
+        .collect()
+}
+
+fn run_shard(mailbox: Receiver<Message>, config: EngineConfig) -> TransactionEngine {
+    let mut engine = TransactionEngine::with_config(config);
+    for message in mailbox {
+        match message {
+            Message::Execute {
+                transaction,
+                origin,
+                reply,
+            } => {
+                let result = engine.execute_from(transaction, origin);
+                if let Some(reply) = reply {
+                    // The sender may have given up waiting
+                    let _ = reply.send(result);
+                }
+            }
+            Message::Account { client_id, reply } => {
+                let account = engine
+                    .accounts
+                    .get(&client_id)
+                    .map(|account| AccountView::new(account.as_ref()));
+                let _ = reply.send(account);
+            }
+            Message::Snapshots { client_id, reply } => {
+                let snapshots = match client_id {
+                    Some(client_id) => engine.account_snapshot(client_id).into_iter().collect(),
+                    None => engine.iter_snapshots().collect(),
+                };
+                let _ = reply.send(snapshots);
+            }
+        }
+    }
+    engine
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::account::ClientId;
+        use crate::actor::ActorEngine;
+        use crate::engine::EngineConfig;
+        use crate::error::EngineError;
+        use crate::transaction::{transaction, TransactionId, TransactionType};
+        use std::sync::Arc;
+        use std::thread;
+
+        #[test]
+        fn actors_execute_transactions_from_many_threads() {
+            let engine = Arc::new(ActorEngine::new(4, EngineConfig::default()));
+            let workers: Vec<_> = (0..8u8)
+                .map(|worker| {
+                    let engine = Arc::clone(&engine);
+                    let client_id = ClientId::from(worker);
+                    thread::spawn(move || {
+                        for i in 0..100 {
+                            let transaction_id = u32::from(worker) * 1000 + i;
+                            engine.post(transaction(
+                                TransactionType::Deposit,
+                                client_id,
+                                transaction_id,
+                                Some(1.0),
+                            ));
+                        }
+                        // Queries are answered after the transactions posted before them
+                        let account = engine.account(client_id).unwrap();
+                        assert_eq!(account.balance(None).available, 100.0);
+                    })
+                })
+                .collect();
+            for worker in workers {
+                worker.join().unwrap();
+            }
+
+            assert!(engine.account(9).is_none());
+            assert_eq!(
+                engine.submit(transaction(TransactionType::Deposit, 5, 3000, Some(1.0))),
+                Err(EngineError::DuplicateTransaction(TransactionId(3000)))
+            );
+            engine
+                .submit(transaction(TransactionType::Dispute, 3, 3000, None))
+                .unwrap();
+
+            let engine = Arc::into_inner(engine).unwrap().into_engine();
+            assert_eq!(engine.accounts.len(), 8);
+            assert_eq!(engine.accounts[&3].get_held_funds(), 1.0);
+            assert_eq!(engine.metrics().total_processed(), 802);
+        }
+    }
+}
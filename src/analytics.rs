@@ -0,0 +1,354 @@
+//! Read-only statistics over the accounts of an engine, printed by the `analyze` subcommand for
+//! dashboards: the largest balances, the accounts with the most open disputes, how the balances
+//! are distributed and how many accounts are locked.
+
+use crate::account::ClientId;
+use crate::currency::Currency;
+use crate::engine::TransactionEngine;
+use crate::output::AccountSnapshot;
+use std::fmt;
+use std::io;
+
+/// Bounds between the buckets of the balance distribution. The first bucket holds the negative
+/// totals and the last those from the highest bound up.
+const DISTRIBUTION_BOUNDS: [f64; 8] = [0.0, 1.0, 10.0, 100.0, 1e3, 1e4, 1e5, 1e6];
+
+/// Account with open disputes
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisputedAccount {
+    pub client_id: ClientId,
+    pub disputes: usize,
+    /// Funds held by the disputes
+    pub held: f64,
+}
+
+/// Balances whose total falls within a range
+#[derive(Debug, Clone, PartialEq)]
+pub struct BalanceBucket {
+    /// Lowest total of the bucket, none for the negative totals
+    pub from: Option<f64>,
+    /// Total the bucket stops short of, none for the last bucket
+    pub to: Option<f64>,
+    pub balances: usize,
+}
+
+/// Summary statistics of the balances of an engine, one per account and currency, either of
+/// every currency or of a single one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis {
+    /// Accounts with a balance analyzed
+    pub accounts: usize,
+    pub locked: usize,
+    pub balances: usize,
+    /// Mean and median total of the balances, 0 without balances
+    pub mean: f64,
+    pub median: f64,
+    /// Largest totals first, ties in client order
+    pub top_balances: Vec<AccountSnapshot>,
+    /// Most open disputes first, ties by held funds then in client order
+    pub most_disputed: Vec<DisputedAccount>,
+    /// Balances per range of totals, in increasing order
+    pub distribution: Vec<BalanceBucket>,
+}
+
+impl Analysis {
+    /// Statistics of the balances in `currency`, or in every currency if none, listing up to
+    /// `top` balances and disputed accounts. Fails if the state of an account cannot be read.
+    pub fn new(
+        engine: &TransactionEngine,
+        top: usize,
+        currency: Option<Currency>,
+    ) -> io::Result<Self> {
+        let selected = |balance: Option<Currency>| currency.is_none() || balance == currency;
+        let balances: Vec<_> = engine
+            .sorted_snapshots()
+            .filter(|snapshot| selected(snapshot.currency))
+            .collect();
+        let mut clients: Vec<_> = balances
+            .iter()
+            .map(|snapshot| (snapshot.client, snapshot.locked))
+            .collect();
+        clients.dedup();
+
+        let mut totals: Vec<_> = balances.iter().map(|snapshot| snapshot.total).collect();
+        totals.sort_by(f64::total_cmp);
+        let mean = match totals.len() {
+            0 => 0.0,
+            len => totals.iter().sum::<f64>() / len as f64,
+        };
+        let median = match totals.len() {
+            0 => 0.0,
+            len if len % 2 == 0 => (totals[len / 2 - 1] + totals[len / 2]) / 2.0,
+            len => totals[len / 2],
+        };
+
+        let mut distribution: Vec<_> = (0..=DISTRIBUTION_BOUNDS.len())
+            .map(|bucket| BalanceBucket {
+                from: bucket
+                    .checked_sub(1)
+                    .map(|bound| DISTRIBUTION_BOUNDS[bound]),
+                to: DISTRIBUTION_BOUNDS.get(bucket).copied(),
+                balances: 0,
+            })
+            .collect();
+        for total in &totals {
+            let bucket = DISTRIBUTION_BOUNDS
+                .iter()
+                .position(|bound| total < bound)
+                .unwrap_or(DISTRIBUTION_BOUNDS.len());
+            distribution[bucket].balances += 1;
+        }
+
+        let mut top_balances = balances;
+        top_balances.sort_by(|left, right| {
+            right
+                .total
+                .total_cmp(&left.total)
+                .then(left.client.cmp(&right.client))
+        });
+        top_balances.truncate(top);
+
+        let mut most_disputed = Vec::new();
+        for account in engine.accounts.values() {
+            let disputes: Vec<_> = account
+                .state()?
+                .active_disputes
+                .into_iter()
+                .filter(|(_, currency, _)| selected(*currency))
+                .collect();
+            if !disputes.is_empty() {
+                most_disputed.push(DisputedAccount {
+                    client_id: account.get_client_id(),
+                    disputes: disputes.len(),
+                    held: disputes.iter().map(|(_, _, held)| held).sum(),
+                });
+            }
+        }
+        most_disputed.sort_by(|left, right| {
+            right
+                .disputes
+                .cmp(&left.disputes)
+                .then(right.held.total_cmp(&left.held))
+                .then(left.client_id.cmp(&right.client_id))
+        });
+        most_disputed.truncate(top);
+
+        Ok(Analysis {
+            accounts: clients.len(),
+            locked: clients.iter().filter(|(_, locked)| *locked).count(),
+            balances: totals.len(),
+            mean,
+            median,
+            top_balances,
+            most_disputed,
+            distribution,
+        })
+    }
+
+    /// Percentage of the accounts that are locked, 0 without accounts
+    pub fn locked_percent(&self) -> f64 {
+        match self.accounts {
+            0 => 0.0,
+            accounts => self.locked as f64 * 100.0 / accounts as f64,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let bound =
+            |bound: Option<f64>| bound.map_or("null".to_string(), |bound| bound.to_string());
+        let top_balances: Vec<_> = self
+            .top_balances
+            .iter()
+            .map(AccountSnapshot::to_json)
+            .collect();
+        let most_disputed: Vec<_> = self
+            .most_disputed
+            .iter()
+            .map(|account| {
+                format!(
+                    "{{\"client\":{},\"disputes\":{},\"held\":{:.4}}}",
+                    account.client_id, account.disputes, account.held
+                )
+            })
+            .collect();
+        let distribution: Vec<_> = self
+            .distribution
+            .iter()
+            .map(|bucket| {
+                format!(
+                    "{{\"from\":{},\"to\":{},\"balances\":{}}}",
+                    bound(bucket.from),
+                    bound(bucket.to),
+                    bucket.balances
+                )
+            })
+            .collect();
+        format!(
+            "{{\"accounts\":{},\"locked\":{},\"locked_percent\":{:.2},\"balances\":{},\
+             \"mean\":{:.4},\"median\":{:.4},\"top_balances\":[{}],\"most_disputed\":[{}],\
+             \"distribution\":[{}]}}",
+            self.accounts,
+            self.locked,
+            self.locked_percent(),
+            self.balances,
+            self.mean,
+            self.median,
+            top_balances.join(","),
+            most_disputed.join(","),
+            distribution.join(",")
+        )
+    }
+}
+
+impl fmt::Display for Analysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Accounts: {}, {} locked ({:.2}%)",
+            self.accounts,
+            self.locked,
+            self.locked_percent()
+        )?;
+        writeln!(
+            f,
+            "Balances: {}, mean total {:.4}, median total {:.4}",
+            self.balances, self.mean, self.median
+        )?;
+        writeln!(f, "Top balances")?;
+        writeln!(
+            f,
+            "  {:>10}  {:>8}  {:>16}  {:>16}  {:>16}",
+            "client", "currency", "available", "held", "total"
+        )?;
+        for balance in &self.top_balances {
+            let currency = balance
+                .currency
+                .map_or("-".to_string(), |currency| currency.to_string());
+            writeln!(
+                f,
+                "  {:>10}  {:>8}  {:>16.4}  {:>16.4}  {:>16.4}",
+                balance.client, currency, balance.available, balance.held, balance.total
+            )?;
+        }
+        writeln!(f, "Most disputed accounts")?;
+        writeln!(f, "  {:>10}  {:>8}  {:>16}", "client", "disputes", "held")?;
+        for account in &self.most_disputed {
+            writeln!(
+                f,
+                "  {:>10}  {:>8}  {:>16.4}",
+                account.client_id, account.disputes, account.held
+            )?;
+        }
+        writeln!(f, "Balance distribution")?;
+        for bucket in &self.distribution {
+            let range = match (bucket.from, bucket.to) {
+                (None, Some(to)) => format!("below {}", to),
+                (Some(from), Some(to)) => format!("{} to {}", from, to),
+                (Some(from), None) => format!("{} and above", from),
+                (None, None) => "any".to_string(),
+            };
+            writeln!(f, "  {:>18}  {}", range, bucket.balances)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::analytics::Analysis;
+        use crate::currency::Currency;
+        use crate::engine::TransactionEngine;
+        use crate::transaction::{transaction, Transaction, TransactionType};
+
+        fn engine() -> TransactionEngine {
+            let mut engine = TransactionEngine::new();
+            let euros = Transaction {
+                currency: Some("EUR".parse().unwrap()),
+                ..transaction(TransactionType::Deposit, 4, 8, Some(2000.0))
+            };
+            for transaction in [
+                transaction(TransactionType::Deposit, 1, 1, Some(5.0)),
+                transaction(TransactionType::Deposit, 2, 2, Some(150.0)),
+                transaction(TransactionType::Deposit, 2, 3, Some(50.0)),
+                transaction(TransactionType::Dispute, 2, 2, None),
+                transaction(TransactionType::Dispute, 2, 3, None),
+                transaction(TransactionType::Deposit, 3, 4, Some(0.5)),
+                transaction(TransactionType::Dispute, 3, 4, None),
+                transaction(TransactionType::Chargeback, 3, 4, None),
+                transaction(TransactionType::Deposit, 1, 5, Some(1.0)),
+                transaction(TransactionType::Dispute, 1, 5, None),
+                euros,
+            ] {
+                engine.execute(transaction).unwrap();
+            }
+            engine
+        }
+
+        #[test]
+        fn balances_disputes_and_locks_are_summed_up() {
+            let analysis = Analysis::new(&engine(), 2, None).unwrap();
+
+            assert_eq!(analysis.accounts, 4);
+            assert_eq!(analysis.locked, 1);
+            assert_eq!(analysis.locked_percent(), 25.0);
+            assert_eq!(analysis.balances, 4);
+            assert_eq!(analysis.mean, 551.5);
+            assert_eq!(analysis.median, 103.0);
+            let top: Vec<_> = analysis
+                .top_balances
+                .iter()
+                .map(|balance| (balance.client, balance.total))
+                .collect();
+            assert_eq!(top, [(4, 2000.0), (2, 200.0)]);
+            let disputed: Vec<_> = analysis
+                .most_disputed
+                .iter()
+                .map(|account| (account.client_id, account.disputes, account.held))
+                .collect();
+            assert_eq!(disputed, [(2, 2, 200.0), (1, 1, 1.0)]);
+            let distribution: Vec<_> = analysis
+                .distribution
+                .iter()
+                .map(|bucket| bucket.balances)
+                .collect();
+            assert_eq!(distribution, [0, 1, 1, 0, 1, 1, 0, 0, 0]);
+
+            let text = analysis.to_string();
+            assert!(text.starts_with(
+                "Accounts: 4, 1 locked (25.00%)\n\
+                 Balances: 4, mean total 551.5000, median total 103.0000\n"
+            ));
+            assert!(text.contains("           4       EUR         2000.0000"));
+            assert!(text.contains("           below 0  0\n"), "{}", text);
+            assert!(text.ends_with("   1000000 and above  0\n"), "{}", text);
+            let json = analysis.to_json();
+            assert!(json.starts_with(
+                "{\"accounts\":4,\"locked\":1,\"locked_percent\":25.00,\"balances\":4,\
+                 \"mean\":551.5000,\"median\":103.0000,\"top_balances\":[{\"client\":4,"
+            ));
+            assert!(json
+                .contains("\"most_disputed\":[{\"client\":2,\"disputes\":2,\"held\":200.0000},"));
+            assert!(
+                json.ends_with("{\"from\":1000000,\"to\":null,\"balances\":0}]}"),
+                "{}",
+                json
+            );
+        }
+
+        #[test]
+        fn analysis_can_be_restricted_to_a_currency() {
+            let euros: Currency = "EUR".parse().unwrap();
+            let analysis = Analysis::new(&engine(), 10, Some(euros)).unwrap();
+
+            assert_eq!(analysis.accounts, 1);
+            assert_eq!(analysis.balances, 1);
+            assert_eq!(analysis.median, 2000.0);
+            assert!(analysis.most_disputed.is_empty());
+
+            let empty = Analysis::new(&TransactionEngine::new(), 10, None).unwrap();
+            assert_eq!((empty.accounts, empty.mean, empty.median), (0, 0.0, 0.0));
+            assert_eq!(empty.locked_percent(), 0.0);
+        }
+    }
+}
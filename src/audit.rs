@@ -0,0 +1,385 @@
+use crate::account::ClientId;
+use crate::output::json_string;
+use crate::transaction::{Transaction, TransactionId};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Structured record of what the engine did with a transaction
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuditEvent {
+    /// Transaction changed the state of an account
+    Applied(Transaction),
+    /// Transaction was refused and left the accounts unchanged
+    Rejected {
+        transaction: Transaction,
+        reason: String,
+    },
+    DisputeOpened {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    },
+    Resolved {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    },
+    Chargeback {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    },
+    /// Chargeback credited back as the merchant won its representment
+    Represented {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    },
+    /// Dispute resolved by the engine since nobody settled it in time, see
+    /// [`DisputeExpiry`](crate::policy::DisputeExpiry)
+    DisputeExpired {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    },
+    AccountLocked {
+        client_id: ClientId,
+    },
+    /// Lock lifted by an administrator, or by the representment of the last chargeback under
+    /// [`EngineConfig::unlock_on_representment`](crate::engine::EngineConfig::unlock_on_representment)
+    AccountUnlocked {
+        client_id: ClientId,
+    },
+    /// Deposit or withdrawal undone by an administrative
+    /// [`TransactionType::Reversal`](crate::transaction::TransactionType::Reversal)
+    Reversed {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    },
+    /// Available funds changed by a manual
+    /// [`TransactionType::Adjustment`](crate::transaction::TransactionType::Adjustment), which
+    /// bypasses the funds check. `overdrawn` tells whether it left the available funds negative.
+    Adjusted {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: f64,
+        overdrawn: bool,
+    },
+    /// Account closed by an administrative
+    /// [`TransactionType::Close`](crate::transaction::TransactionType::Close)
+    AccountClosed {
+        client_id: ClientId,
+    },
+    /// Account of the client moved into that of `into` by an administrative
+    /// [`TransactionType::Merge`](crate::transaction::TransactionType::Merge)
+    AccountsMerged {
+        client_id: ClientId,
+        into: ClientId,
+    },
+    /// Transaction found suspicious by a [`RiskRule`](crate::risk::RiskRule), recorded before
+    /// the event telling whether it was applied
+    RiskFlagged {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        rule: &'static str,
+        reason: String,
+    },
+}
+
+impl AuditEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AuditEvent::Applied(_) => "applied",
+            AuditEvent::Rejected { .. } => "rejected",
+            AuditEvent::DisputeOpened { .. } => "dispute_opened",
+            AuditEvent::Resolved { .. } => "resolved",
+            AuditEvent::Chargeback { .. } => "chargeback",
+            AuditEvent::Represented { .. } => "represented",
+            AuditEvent::DisputeExpired { .. } => "dispute_expired",
+            AuditEvent::AccountLocked { .. } => "account_locked",
+            AuditEvent::AccountUnlocked { .. } => "account_unlocked",
+            AuditEvent::Reversed { .. } => "reversed",
+            AuditEvent::Adjusted { .. } => "adjusted",
+            AuditEvent::AccountClosed { .. } => "account_closed",
+            AuditEvent::AccountsMerged { .. } => "accounts_merged",
+            AuditEvent::RiskFlagged { .. } => "risk_flagged",
+        }
+    }
+
+    /// Single line json representation of the event
+    pub fn to_json(&self) -> String {
+        let fields = match self {
+            AuditEvent::Applied(transaction) => transaction_fields(transaction),
+            AuditEvent::Rejected {
+                transaction,
+                reason,
+            } => format!(
+                "{},\"reason\":{}",
+                transaction_fields(transaction),
+                json_string(reason)
+            ),
+            AuditEvent::DisputeOpened {
+                client_id,
+                transaction_id,
+            }
+            | AuditEvent::Resolved {
+                client_id,
+                transaction_id,
+            }
+            | AuditEvent::Chargeback {
+                client_id,
+                transaction_id,
+            }
+            | AuditEvent::Represented {
+                client_id,
+                transaction_id,
+            }
+            | AuditEvent::DisputeExpired {
+                client_id,
+                transaction_id,
+            }
+            | AuditEvent::Reversed {
+                client_id,
+                transaction_id,
+            } => format!("\"client\":{},\"tx\":{}", client_id, transaction_id),
+            AuditEvent::AccountLocked { client_id }
+            | AuditEvent::AccountUnlocked { client_id }
+            | AuditEvent::AccountClosed { client_id } => format!("\"client\":{}", client_id),
+            AuditEvent::Adjusted {
+                client_id,
+                transaction_id,
+                amount,
+                overdrawn,
+            } => format!(
+                "\"client\":{},\"tx\":{},\"amount\":{},\"overdrawn\":{}",
+                client_id, transaction_id, amount, overdrawn
+            ),
+            AuditEvent::AccountsMerged { client_id, into } => {
+                format!("\"client\":{},\"into\":{}", client_id, into)
+            }
+            AuditEvent::RiskFlagged {
+                client_id,
+                transaction_id,
+                rule,
+                reason,
+            } => format!(
+                "\"client\":{},\"tx\":{},\"rule\":\"{}\",\"reason\":{}",
+                client_id,
+                transaction_id,
+                rule,
+                json_string(reason)
+            ),
+        };
+        format!("{{\"event\":\"{}\",{}}}", self.name(), fields)
+    }
+}
+
+fn transaction_fields(transaction: &Transaction) -> String {
+    let amount = transaction
+        .amount
+        .map_or("null".to_string(), |amount| amount.to_string());
+    let mut fields = format!(
+        "\"type\":\"{}\",\"client\":{},\"tx\":{},\"amount\":{}",
+        transaction.transaction_type, transaction.client_id, transaction.transaction_id, amount
+    );
+    if let Some(to_client_id) = transaction.to_client_id {
+        fields.push_str(&format!(",\"to\":{}", to_client_id));
+    }
+    if let Some(currency) = transaction.currency {
+        fields.push_str(&format!(",\"currency\":\"{}\"", currency));
+    }
+    if let Some(timestamp) = transaction.timestamp {
+        fields.push_str(&format!(",\"timestamp\":\"{}\"", timestamp));
+    }
+    if !transaction.metadata.is_empty() {
+        let metadata: Vec<_> = transaction
+            .sorted_metadata()
+            .into_iter()
+            .map(|(name, value)| format!("{}:{}", json_string(name), json_string(value)))
+            .collect();
+        fields.push_str(&format!(",\"metadata\":{{{}}}", metadata.join(",")));
+    }
+    fields
+}
+
+/// Destination of audit events emitted by the engine
+pub trait AuditSink: Send {
+    fn record(&mut self, event: AuditEvent);
+
+    /// Makes sure every recorded event is persisted, reporting any failure since the last flush
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Appends events as json lines to a file
+pub struct JsonlAuditSink<W: Write + Send> {
+    writer: W,
+    /// First write failure, reported on flush so that recording never interrupts processing
+    error: Option<io::Error>,
+}
+
+impl<W: Write + Send> JsonlAuditSink<W> {
+    pub fn new(writer: W) -> Self {
+        JsonlAuditSink {
+            writer,
+            error: None,
+        }
+    }
+}
+
+impl JsonlAuditSink<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write + Send> AuditSink for JsonlAuditSink<W> {
+    fn record(&mut self, event: AuditEvent) {
+        if self.error.is_none() {
+            if let Err(err) = writeln!(self.writer, "{}", event.to_json()) {
+                self.error = Some(err);
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.writer.flush()
+    }
+}
+
+/// Keeps events in memory. Clones share the same events, so a clone can be handed to the
+/// engine while the original is used to inspect what was recorded.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryAuditSink {
+    events: Arc<Mutex<Vec<AuditEvent>>>,
+}
+
+impl InMemoryAuditSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn events(&self) -> Vec<AuditEvent> {
+        self.events
+            .lock()
+            .expect("Audit events lock poisoned")
+            .clone()
+    }
+}
+
+impl AuditSink for InMemoryAuditSink {
+    fn record(&mut self, event: AuditEvent) {
+        self.events
+            .lock()
+            .expect("Audit events lock poisoned")
+            .push(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::audit::{AuditEvent, AuditSink, InMemoryAuditSink, JsonlAuditSink};
+        use crate::engine::TransactionEngine;
+        use crate::transaction::{transaction, Origin, TransactionId, TransactionType};
+
+        #[test]
+        fn engine_emits_events_for_every_transaction() {
+            let sink = InMemoryAuditSink::new();
+            let mut engine = TransactionEngine::new();
+            engine.set_audit_sink(Box::new(sink.clone()));
+
+            let deposit = transaction(TransactionType::Deposit, 1, 1, Some(2.0));
+            let withdrawal = transaction(TransactionType::Withdrawal, 1, 2, Some(5.0));
+            let dispute = transaction(TransactionType::Dispute, 1, 1, None);
+            let chargeback = transaction(TransactionType::Chargeback, 1, 1, None);
+            for transaction in [&deposit, &withdrawal, &dispute, &chargeback] {
+                let _ = engine.execute(transaction.clone());
+            }
+
+            let events = sink.events();
+            assert_eq!(events.len(), 7);
+            assert_eq!(events[0], AuditEvent::Applied(deposit));
+            assert!(matches!(
+                &events[1],
+                AuditEvent::Rejected { transaction, reason }
+                    if *transaction == withdrawal && reason.contains("insufficient funds")
+            ));
+            assert_eq!(events[2], AuditEvent::Applied(dispute));
+            assert_eq!(
+                events[3],
+                AuditEvent::DisputeOpened {
+                    client_id: 1,
+                    transaction_id: TransactionId(1)
+                }
+            );
+            assert_eq!(events[4], AuditEvent::Applied(chargeback));
+            assert_eq!(
+                events[5],
+                AuditEvent::Chargeback {
+                    client_id: 1,
+                    transaction_id: TransactionId(1)
+                }
+            );
+            assert_eq!(events[6], AuditEvent::AccountLocked { client_id: 1 });
+        }
+
+        #[test]
+        fn jsonl_sink_writes_one_object_per_line() {
+            let mut buffer = Vec::new();
+            {
+                let mut sink = JsonlAuditSink::new(&mut buffer);
+                sink.record(AuditEvent::Rejected {
+                    transaction: transaction(TransactionType::Withdrawal, 1, 2, Some(1.5)),
+                    reason: "not \"enough\" funds".to_string(),
+                });
+                sink.record(AuditEvent::AccountLocked { client_id: 1 });
+                let mut deposit = transaction(TransactionType::Deposit, 1, 3, Some(2.0));
+                deposit.metadata.extend([
+                    ("reference".to_string(), "r-1".to_string()),
+                    ("merchant".to_string(), "ACME".to_string()),
+                ]);
+                sink.record(AuditEvent::Applied(deposit));
+                sink.flush().unwrap();
+            }
+
+            assert_eq!(
+                String::from_utf8(buffer).unwrap(),
+                "{\"event\":\"rejected\",\"type\":\"withdrawal\",\"client\":1,\"tx\":2,\
+                 \"amount\":1.5,\"reason\":\"not \\\"enough\\\" funds\"}\n\
+                 {\"event\":\"account_locked\",\"client\":1}\n\
+                 {\"event\":\"applied\",\"type\":\"deposit\",\"client\":1,\"tx\":3,\"amount\":2,\
+                 \"metadata\":{\"merchant\":\"ACME\",\"reference\":\"r-1\"}}\n"
+            );
+        }
+
+        #[test]
+        fn admin_locks_and_unlocks_are_audited() {
+            let sink = InMemoryAuditSink::new();
+            let mut engine = TransactionEngine::new();
+            engine.set_audit_sink(Box::new(sink.clone()));
+
+            let lock = transaction(TransactionType::Lock, 1, 1, None);
+            let unlock = transaction(TransactionType::Unlock, 1, 2, None);
+            let _ = engine.execute(lock.clone());
+            engine.execute_from(lock.clone(), Origin::Admin).unwrap();
+            engine.execute_from(unlock.clone(), Origin::Admin).unwrap();
+
+            let events = sink.events();
+            assert_eq!(events.len(), 5);
+            assert!(matches!(
+                &events[0],
+                AuditEvent::Rejected { transaction, .. } if *transaction == lock
+            ));
+            assert_eq!(events[1], AuditEvent::Applied(lock));
+            assert_eq!(events[2], AuditEvent::AccountLocked { client_id: 1 });
+            assert_eq!(events[3], AuditEvent::Applied(unlock));
+            assert_eq!(events[4], AuditEvent::AccountUnlocked { client_id: 1 });
+            assert_eq!(
+                events[4].to_json(),
+                "{\"event\":\"account_unlocked\",\"client\":1}"
+            );
+        }
+    }
+}
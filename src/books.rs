@@ -0,0 +1,216 @@
+//! Books of the engine as a whole, so that the funds of the accounts can be accounted for end to
+//! end: per currency, what deposits, interest and adjustments brought into the accounts and what
+//! withdrawals, chargebacks, fees and reversals took out of them. The funds of every account add
+//! up to the opening balance plus the net of those flows. Transfers and merges only move funds
+//! between accounts, and disputes and authorizations within them, so they leave the books as they
+//! are.
+
+use crate::account::{ClientAccount, DUST};
+use crate::currency::Currency;
+use crate::engine::TransactionEngine;
+use crate::transaction::TransactionType;
+use std::collections::BTreeMap;
+
+/// Funds that entered and left the accounts in a currency
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Flows {
+    /// Funds of the accounts when the books were opened, those restored from a snapshot written
+    /// before the engine kept books
+    pub opening: f64,
+    pub deposited: f64,
+    /// Withdrawn and captured, once settled for withdrawals that wait for it
+    pub withdrawn: f64,
+    /// Taken back by chargebacks, net of what representments credited back
+    pub charged_back: f64,
+    pub fees: f64,
+    pub interest: f64,
+    /// Net credited by adjustments, negative if they debited more than they credited
+    pub adjusted: f64,
+    /// Net taken out by reversals, negative if they credited more withdrawals than they took
+    /// deposits back
+    pub reversed: f64,
+}
+
+impl Flows {
+    /// Funds the accounts hold if the books balance
+    pub fn expected(&self) -> f64 {
+        self.opening + self.deposited - self.withdrawn - self.charged_back - self.fees
+            + self.interest
+            + self.adjusted
+            - self.reversed
+    }
+}
+
+/// Flows of the funds of every currency the engine saw, see the [module](self) documentation
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GlobalLedger {
+    flows: BTreeMap<Option<Currency>, Flows>,
+}
+
+impl GlobalLedger {
+    /// Flows of the currency, all zero if its funds never moved
+    pub fn flows(&self, currency: Option<Currency>) -> Flows {
+        self.flows.get(&currency).copied().unwrap_or_default()
+    }
+
+    /// Flows of every currency, in order
+    pub fn currencies(&self) -> impl Iterator<Item = (Option<Currency>, &Flows)> + '_ {
+        self.flows
+            .iter()
+            .map(|(currency, flows)| (*currency, flows))
+    }
+
+    /// Currencies whose funds over all accounts differ from what the books expect, with the
+    /// funds the accounts hold and those expected; none if the books balance
+    pub fn discrepancies(&self, engine: &TransactionEngine) -> Vec<(Option<Currency>, f64, f64)> {
+        let mut funds = funds(engine.accounts.values().map(AsRef::as_ref));
+        for currency in self.flows.keys() {
+            funds.entry(*currency).or_default();
+        }
+        funds
+            .into_iter()
+            .map(|(currency, held)| (currency, held, self.flows(currency).expected()))
+            .filter(|(_, held, expected)| (held - expected).abs() >= DUST)
+            .collect()
+    }
+
+    /// Whether transactions of the type can bring funds into the accounts or take them out
+    pub(crate) fn moves_funds(transaction_type: TransactionType) -> bool {
+        matches!(
+            transaction_type,
+            TransactionType::Deposit
+                | TransactionType::Withdrawal
+                | TransactionType::Capture
+                | TransactionType::Chargeback
+                | TransactionType::Represent
+                | TransactionType::Fee
+                | TransactionType::Interest
+                | TransactionType::Adjustment
+                | TransactionType::Reversal
+        )
+    }
+
+    /// Books the change of the funds of the accounts a transaction of the type applied to, given
+    /// as they were before and after it
+    pub(crate) fn record(
+        &mut self,
+        transaction_type: TransactionType,
+        before: &BTreeMap<Option<Currency>, f64>,
+        after: &BTreeMap<Option<Currency>, f64>,
+    ) {
+        let mut changes = after.clone();
+        for (currency, funds) in before {
+            *changes.entry(*currency).or_default() -= funds;
+        }
+        for (currency, change) in changes {
+            if change == 0.0 {
+                continue;
+            }
+            let flows = self.entry(currency);
+            match transaction_type {
+                TransactionType::Deposit => flows.deposited += change,
+                TransactionType::Withdrawal | TransactionType::Capture => flows.withdrawn -= change,
+                TransactionType::Chargeback | TransactionType::Represent => {
+                    flows.charged_back -= change
+                }
+                TransactionType::Fee => flows.fees -= change,
+                TransactionType::Interest => flows.interest += change,
+                TransactionType::Reversal => flows.reversed -= change,
+                TransactionType::Adjustment => flows.adjusted += change,
+                // Only the types moving funds are booked
+                _ => {}
+            }
+        }
+    }
+
+    /// Opens the books of the currencies they do not know yet with the funds of the accounts
+    pub(crate) fn open<'a>(&mut self, accounts: impl IntoIterator<Item = &'a dyn ClientAccount>) {
+        for (currency, funds) in funds(accounts) {
+            if !self.flows.contains_key(&currency) {
+                self.entry(currency).opening = funds;
+            }
+        }
+    }
+
+    pub(crate) fn entry(&mut self, currency: Option<Currency>) -> &mut Flows {
+        self.flows.entry(currency).or_default()
+    }
+
+    /// Adds the flows of another engine that processed a disjoint set of clients
+    pub(crate) fn absorb(&mut self, other: GlobalLedger) {
+        for (currency, other) in other.flows {
+            let flows = self.entry(currency);
+            flows.opening += other.opening;
+            flows.deposited += other.deposited;
+            flows.withdrawn += other.withdrawn;
+            flows.charged_back += other.charged_back;
+            flows.fees += other.fees;
+            flows.interest += other.interest;
+            flows.adjusted += other.adjusted;
+            flows.reversed += other.reversed;
+        }
+    }
+}
+
+/// Total funds of the accounts per currency
+pub(crate) fn funds<'a>(
+    accounts: impl IntoIterator<Item = &'a dyn ClientAccount>,
+) -> BTreeMap<Option<Currency>, f64> {
+    let mut funds = BTreeMap::new();
+    for account in accounts {
+        for currency in account.currencies() {
+            *funds.entry(currency).or_default() += account.balance(currency).total();
+        }
+    }
+    funds
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::engine::TransactionEngine;
+        use crate::transaction::{transaction, Origin, Transaction, TransactionType};
+
+        #[test]
+        fn books_balance_with_the_funds_of_the_accounts() {
+            let mut engine = TransactionEngine::new();
+            for transaction in [
+                transaction(TransactionType::Deposit, 1, 1, Some(10.0)),
+                transaction(TransactionType::Deposit, 2, 2, Some(4.0)),
+                transaction(TransactionType::Withdrawal, 1, 3, Some(3.0)),
+                transaction(TransactionType::Dispute, 2, 2, None),
+                transaction(TransactionType::Chargeback, 2, 2, None),
+                Transaction {
+                    to_client_id: Some(2),
+                    ..transaction(TransactionType::Transfer, 1, 4, Some(1.0))
+                },
+                // Rejected, so not booked
+                transaction(TransactionType::Withdrawal, 1, 5, Some(50.0)),
+            ] {
+                let _ = engine.execute(transaction);
+            }
+            engine
+                .execute_from(
+                    transaction(TransactionType::Fee, 1, 6, Some(0.5)),
+                    Origin::Admin,
+                )
+                .unwrap();
+
+            let books = engine.global_ledger();
+            let flows = books.flows(None);
+            assert_eq!(flows.deposited, 14.0);
+            assert_eq!(flows.withdrawn, 3.0);
+            assert_eq!(flows.charged_back, 4.0);
+            assert_eq!(flows.fees, 0.5);
+            assert_eq!(flows.expected(), 6.5);
+            assert!(books.discrepancies(&engine).is_empty());
+
+            // Restored engines keep the books
+            let path = std::env::temp_dir().join("rust-coding-test-books.snapshot");
+            engine.snapshot(&path).unwrap();
+            let restored = TransactionEngine::restore(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(restored.global_ledger(), books);
+        }
+    }
+}
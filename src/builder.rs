@@ -0,0 +1,333 @@
+//! Fluent construction of engines for library users, as an alternative to filling an
+//! [`EngineConfig`] and subscribing observers one call at a time:
+//!
+//! ```
+//! use rust_coding_test::{DisputePolicy, LockPolicy, Storage, TransactionEngine};
+//!
+//! let engine = TransactionEngine::builder()
+//!     .dispute_policy(DisputePolicy::DepositsOnly)
+//!     .lock_policy(LockPolicy::AllowDeposits)
+//!     .storage(Storage::Memory)
+//!     .shards(4)
+//!     .build_sharded();
+//! assert_eq!(engine.shards(), 4);
+//! ```
+//!
+//! [`EngineBuilder::build`] returns a single [`TransactionEngine`], the number of shards is that
+//! of the [`ShardedEngine`] and [`ConcurrentEngine`] built by the other methods. Observers added
+//! to the builder are notified of the changes of every shard, one shard at a time.
+
+use crate::account::{AccountFactory, Balance, ClientId, DisputeState};
+use crate::concurrent::ConcurrentEngine;
+use crate::currency::Currency;
+use crate::engine::{EngineConfig, TransactionEngine};
+use crate::observer::EngineObserver;
+use crate::policy::{
+    DisputeCycles, DisputeExpiry, DisputePolicy, DuplicatePolicy, LimitsPolicy, LockPolicy,
+    NegativeBalancePolicy, RetentionPolicy,
+};
+use crate::risk::RiskPolicy;
+use crate::sharded::ShardedEngine;
+use crate::storage::Storage;
+use crate::tier::Tiers;
+use crate::transaction::{Transaction, TransactionId};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Configuration and observers of the engines to build, see the [module](self) documentation
+#[derive(Default)]
+pub struct EngineBuilder {
+    config: EngineConfig,
+    observers: Vec<Box<dyn EngineObserver>>,
+    shards: usize,
+}
+
+impl EngineBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts from the given configuration, e.g. one read from `engine.toml`, in place of the
+    /// default one. Settings made before are overwritten.
+    pub fn config(mut self, config: EngineConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// See [`EngineConfig::lock_policy`]
+    pub fn lock_policy(mut self, lock_policy: LockPolicy) -> Self {
+        self.config.lock_policy = lock_policy;
+        self
+    }
+
+    /// See [`EngineConfig::unlock_on_representment`]
+    pub fn unlock_on_representment(mut self, unlock: bool) -> Self {
+        self.config.unlock_on_representment = unlock;
+        self
+    }
+
+    /// See [`EngineConfig::allow_adjustments`]
+    pub fn allow_adjustments(mut self, allow: bool) -> Self {
+        self.config.allow_adjustments = allow;
+        self
+    }
+
+    /// See [`EngineConfig::duplicate_policy`]
+    pub fn duplicate_policy(mut self, duplicate_policy: DuplicatePolicy) -> Self {
+        self.config.duplicate_policy = duplicate_policy;
+        self
+    }
+
+    /// See [`EngineConfig::negative_balance_policy`]
+    pub fn negative_balance_policy(mut self, policy: NegativeBalancePolicy) -> Self {
+        self.config.negative_balance_policy = policy;
+        self
+    }
+
+    /// See [`EngineConfig::dispute_policy`]
+    pub fn dispute_policy(mut self, dispute_policy: DisputePolicy) -> Self {
+        self.config.dispute_policy = dispute_policy;
+        self
+    }
+
+    /// See [`EngineConfig::max_dispute_cycles`]
+    pub fn max_dispute_cycles(mut self, cycles: DisputeCycles) -> Self {
+        self.config.max_dispute_cycles = cycles;
+        self
+    }
+
+    /// See [`EngineConfig::dispute_window`]
+    pub fn dispute_window(mut self, days: u32) -> Self {
+        self.config.dispute_window = Some(days);
+        self
+    }
+
+    /// See [`EngineConfig::dispute_expiry`]
+    pub fn dispute_expiry(mut self, expiry: DisputeExpiry) -> Self {
+        self.config.dispute_expiry = expiry;
+        self
+    }
+
+    /// See [`EngineConfig::limits`]
+    pub fn limits(mut self, limits: LimitsPolicy) -> Self {
+        self.config.limits = limits;
+        self
+    }
+
+    /// See [`EngineConfig::tiers`]
+    pub fn tiers(mut self, tiers: Tiers) -> Self {
+        self.config.tiers = Arc::new(tiers);
+        self
+    }
+
+    /// See [`EngineConfig::risk`]
+    pub fn risk(mut self, risk: RiskPolicy) -> Self {
+        self.config.risk = risk;
+        self
+    }
+
+    /// See [`EngineConfig::retention_policy`]
+    pub fn retention_policy(mut self, retention_policy: RetentionPolicy) -> Self {
+        self.config.retention_policy = retention_policy;
+        self
+    }
+
+    /// See [`EngineConfig::storage`]
+    pub fn storage(mut self, storage: Storage) -> Self {
+        self.config.storage = storage;
+        self
+    }
+
+    /// See [`EngineConfig::account_factory`]
+    pub fn account_factory(mut self, factory: Arc<dyn AccountFactory>) -> Self {
+        self.config.account_factory = Some(factory);
+        self
+    }
+
+    /// See [`EngineConfig::record_history`]
+    pub fn record_history(mut self, record: bool) -> Self {
+        self.config.record_history = record;
+        self
+    }
+
+    /// See [`EngineConfig::profile`]
+    pub fn profile(mut self, profile: bool) -> Self {
+        self.config.profile = profile;
+        self
+    }
+
+    /// Subscribes the observer to the engines built, see [`TransactionEngine::subscribe`]
+    pub fn observer(mut self, observer: Box<dyn EngineObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Shards of the engines built by [`EngineBuilder::build_sharded`] and
+    /// [`EngineBuilder::build_concurrent`], at least one
+    pub fn shards(mut self, shards: usize) -> Self {
+        self.shards = shards;
+        self
+    }
+
+    /// Configuration the engines are built with
+    pub fn engine_config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Single threaded engine, whatever the number of shards
+    pub fn build(self) -> TransactionEngine {
+        let mut engine = TransactionEngine::with_config(self.config);
+        for observer in self.observers {
+            engine.subscribe(observer);
+        }
+        engine
+    }
+
+    /// Engine applying the transactions of each shard on a thread of its own
+    pub fn build_sharded(self) -> ShardedEngine {
+        ShardedEngine::from_engines(self.build_shards())
+    }
+
+    /// Engine shared between threads, with a lock per shard
+    pub fn build_concurrent(self) -> ConcurrentEngine {
+        ConcurrentEngine::from_engines(self.build_shards())
+    }
+
+    fn build_shards(self) -> Vec<TransactionEngine> {
+        let observers: Vec<_> = self
+            .observers
+            .into_iter()
+            .map(|observer| Shared(Arc::new(Mutex::new(observer))))
+            .collect();
+        (0..self.shards.max(1))
+            .map(|_| {
+                let mut engine = TransactionEngine::with_config(self.config.clone());
+                for observer in &observers {
+                    engine.subscribe(Box::new(Shared(Arc::clone(&observer.0))));
+                }
+                engine
+            })
+            .collect()
+    }
+}
+
+/// Observer subscribed to several shards
+struct Shared(Arc<Mutex<Box<dyn EngineObserver>>>);
+
+impl Shared {
+    fn observer(&self) -> MutexGuard<'_, Box<dyn EngineObserver>> {
+        self.0.lock().expect("Observer lock poisoned")
+    }
+}
+
+impl EngineObserver for Shared {
+    fn on_applied(&mut self, transaction: &Transaction) {
+        self.observer().on_applied(transaction)
+    }
+
+    fn on_balance_changed(
+        &mut self,
+        client_id: ClientId,
+        currency: Option<Currency>,
+        before: Balance,
+        after: Balance,
+    ) {
+        self.observer()
+            .on_balance_changed(client_id, currency, before, after)
+    }
+
+    fn on_dispute(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        state: DisputeState,
+    ) {
+        self.observer().on_dispute(client_id, transaction_id, state)
+    }
+
+    fn on_lock_changed(&mut self, client_id: ClientId, locked: bool) {
+        self.observer().on_lock_changed(client_id, locked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::account::{Balance, ClientId};
+        use crate::currency::Currency;
+        use crate::engine::TransactionEngine;
+        use crate::error::{EngineError, UpdateError};
+        use crate::observer::EngineObserver;
+        use crate::policy::{DisputePolicy, LockPolicy};
+        use crate::processor::TransactionProcessor;
+        use crate::transaction::{transaction, TransactionId, TransactionType};
+        use std::sync::{Arc, Mutex};
+
+        /// Clients whose balance changed, in the order they were notified
+        struct Recorder(Arc<Mutex<Vec<ClientId>>>);
+
+        impl EngineObserver for Recorder {
+            fn on_balance_changed(
+                &mut self,
+                client_id: ClientId,
+                _currency: Option<Currency>,
+                _before: Balance,
+                _after: Balance,
+            ) {
+                self.0.lock().unwrap().push(client_id);
+            }
+        }
+
+        #[test]
+        fn engines_are_built_with_the_given_settings() {
+            let changed = Arc::new(Mutex::new(Vec::new()));
+            let mut engine = TransactionEngine::builder()
+                .dispute_policy(DisputePolicy::DepositsOnly)
+                .lock_policy(LockPolicy::AllowDeposits)
+                .observer(Box::new(Recorder(Arc::clone(&changed))))
+                .shards(3)
+                .build();
+
+            assert_eq!(engine.config().dispute_policy, DisputePolicy::DepositsOnly);
+            assert_eq!(engine.config().lock_policy, LockPolicy::AllowDeposits);
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(5.0)))
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Withdrawal, 1, 2, Some(1.0)))
+                .unwrap();
+            assert_eq!(
+                engine.execute(transaction(TransactionType::Dispute, 1, 2, None)),
+                Err(EngineError::Account {
+                    client_id: 1,
+                    source: UpdateError::NotDisputable(TransactionId(2)),
+                })
+            );
+            assert_eq!(*changed.lock().unwrap(), [1, 1]);
+        }
+
+        #[test]
+        fn observers_of_sharded_engines_see_every_shard() {
+            let changed = Arc::new(Mutex::new(Vec::new()));
+            let mut engine = TransactionEngine::builder()
+                .observer(Box::new(Recorder(Arc::clone(&changed))))
+                .shards(4)
+                .build_sharded();
+            assert_eq!(engine.shards(), 4);
+            for (client_id, tx) in (1..=8).zip(1..=8) {
+                engine.submit(transaction(
+                    TransactionType::Deposit,
+                    client_id,
+                    tx,
+                    Some(1.0),
+                ));
+            }
+            let (merged, rejected) = engine.finalize();
+
+            assert!(rejected.is_empty());
+            assert_eq!(merged.accounts.len(), 8);
+            let mut changed = changed.lock().unwrap().clone();
+            changed.sort();
+            assert_eq!(changed, (1..=8).collect::<Vec<ClientId>>());
+        }
+    }
+}
@@ -0,0 +1,246 @@
+//! Stream of the accounts changed by an engine, for downstream systems consuming deltas rather
+//! than the full state. A [`ChangeStream`] subscribes to the engine and collects the clients whose
+//! funds, lock or closure changed, including through expired disputes and settled withdrawals;
+//! every call to [`ChangeStream::emit`], e.g. after each batch of a service or window of a
+//! consumer, hands the snapshots of those accounts to a [`ChangeSink`] and starts over.
+//!
+//! ```
+//! use rust_coding_test::{
+//!     Amount, ChangeStream, Transaction, TransactionEngine, TransactionId, TransactionType,
+//! };
+//! use std::sync::mpsc;
+//!
+//! let (sender, receiver) = mpsc::channel();
+//! let mut engine = TransactionEngine::new();
+//! let mut changes = ChangeStream::new(&mut engine, Box::new(sender));
+//! for (client_id, transaction_id) in [(1, TransactionId(1)), (2, TransactionId(2))] {
+//!     engine
+//!         .execute(Transaction {
+//!             transaction_type: TransactionType::Deposit,
+//!             client_id,
+//!             transaction_id,
+//!             amount: Some(Amount::new(1.0).unwrap()),
+//!             to_client_id: None,
+//!             currency: None,
+//!             timestamp: None,
+//!             metadata: Default::default(),
+//!         })
+//!         .unwrap();
+//! }
+//! changes.emit(&engine).unwrap();
+//!
+//! let clients: Vec<_> = receiver.recv().unwrap().iter().map(|change| change.client).collect();
+//! assert_eq!(clients, [1, 2]);
+//! ```
+
+use crate::account::{Balance, ClientId};
+use crate::currency::Currency;
+use crate::engine::TransactionEngine;
+use crate::observer::EngineObserver;
+use crate::output::AccountSnapshot;
+use crate::transaction::{Transaction, TransactionType};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+/// Destination of the accounts changed since the previous emission
+pub trait ChangeSink: Send {
+    /// Receives one snapshot per currency of every changed account, in order of client id then
+    /// currency. Never called without changes.
+    fn emit(&mut self, changes: Vec<AccountSnapshot>) -> io::Result<()>;
+}
+
+/// Writes the changed accounts as json objects, one per line, flushed after every emission
+pub struct JsonlChangeSink<W: Write + Send> {
+    writer: W,
+}
+
+impl<W: Write + Send> JsonlChangeSink<W> {
+    pub fn new(writer: W) -> Self {
+        JsonlChangeSink { writer }
+    }
+}
+
+impl JsonlChangeSink<io::Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+impl JsonlChangeSink<File> {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(File::create(path)?))
+    }
+}
+
+impl<W: Write + Send> ChangeSink for JsonlChangeSink<W> {
+    fn emit(&mut self, changes: Vec<AccountSnapshot>) -> io::Result<()> {
+        for change in &changes {
+            writeln!(self.writer, "{}", change.to_json())?;
+        }
+        self.writer.flush()
+    }
+}
+
+/// Sends every emission as a whole, failing once the receiver is gone
+impl ChangeSink for Sender<Vec<AccountSnapshot>> {
+    fn emit(&mut self, changes: Vec<AccountSnapshot>) -> io::Result<()> {
+        self.send(changes)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "change receiver is gone"))
+    }
+}
+
+/// Observer collecting the clients whose account changed
+#[derive(Clone, Default)]
+pub(crate) struct Changed(Arc<Mutex<BTreeSet<ClientId>>>);
+
+impl Changed {
+    /// Collects the changes of the engine from now on
+    pub(crate) fn subscribe(engine: &mut TransactionEngine) -> Self {
+        let changed = Changed::default();
+        engine.subscribe(Box::new(changed.clone()));
+        changed
+    }
+
+    /// Number of accounts changed since the previous call and their snapshots as they are now.
+    /// Accounts merged away have no snapshot left and are not counted.
+    pub(crate) fn take_snapshots(
+        &self,
+        engine: &TransactionEngine,
+    ) -> (usize, Vec<AccountSnapshot>) {
+        let accounts: Vec<_> = self
+            .take()
+            .iter()
+            .filter_map(|client_id| engine.accounts.get(client_id))
+            .collect();
+        let snapshots = accounts
+            .iter()
+            .flat_map(|account| engine.account_snapshots(account.as_ref()))
+            .collect();
+        (accounts.len(), snapshots)
+    }
+
+    fn insert(&self, client_id: ClientId) {
+        self.0
+            .lock()
+            .expect("Changed accounts lock poisoned")
+            .insert(client_id);
+    }
+
+    fn take(&self) -> BTreeSet<ClientId> {
+        std::mem::take(&mut self.0.lock().expect("Changed accounts lock poisoned"))
+    }
+}
+
+impl EngineObserver for Changed {
+    fn on_applied(&mut self, transaction: &Transaction) {
+        // Closures and reserves change neither funds nor lock
+        if matches!(
+            transaction.transaction_type,
+            TransactionType::Close | TransactionType::Reserve
+        ) {
+            self.insert(transaction.client_id);
+        }
+    }
+
+    fn on_balance_changed(
+        &mut self,
+        client_id: ClientId,
+        _currency: Option<Currency>,
+        _before: Balance,
+        _after: Balance,
+    ) {
+        self.insert(client_id);
+    }
+
+    fn on_lock_changed(&mut self, client_id: ClientId, _locked: bool) {
+        self.insert(client_id);
+    }
+}
+
+/// Accounts changed by an engine since they were last emitted to a sink
+pub struct ChangeStream {
+    changed: Changed,
+    sink: Box<dyn ChangeSink>,
+}
+
+impl ChangeStream {
+    /// Subscribes to the changes of the engine, which must be given to every
+    /// [`ChangeStream::emit`]
+    pub fn new(engine: &mut TransactionEngine, sink: Box<dyn ChangeSink>) -> Self {
+        ChangeStream {
+            changed: Changed::subscribe(engine),
+            sink,
+        }
+    }
+
+    /// Emits the accounts changed since the previous call as they are now, returning how many
+    /// there were. Accounts merged away have no snapshot left and are not emitted. Changes that
+    /// the sink failed to take are lost.
+    pub fn emit(&mut self, engine: &TransactionEngine) -> io::Result<usize> {
+        let (accounts, changes) = self.changed.take_snapshots(engine);
+        if accounts > 0 {
+            self.sink.emit(changes)?;
+        }
+        Ok(accounts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::changes::ChangeStream;
+        use crate::engine::TransactionEngine;
+        use crate::output::AccountSnapshot;
+        use crate::transaction::{transaction, Origin, TransactionType};
+        use std::sync::mpsc;
+
+        #[test]
+        fn only_accounts_changed_since_the_last_emission_are_emitted() {
+            let (sender, receiver) = mpsc::channel();
+            let mut engine = TransactionEngine::new();
+            let mut changes = ChangeStream::new(&mut engine, Box::new(sender));
+            for transaction in [
+                transaction(TransactionType::Deposit, 2, 1, Some(2.0)),
+                transaction(TransactionType::Deposit, 1, 2, Some(1.0)),
+            ] {
+                engine.execute(transaction).unwrap();
+            }
+            assert_eq!(changes.emit(&engine).unwrap(), 2);
+
+            // Rejected transactions change nothing
+            let _ = engine.execute(transaction(TransactionType::Withdrawal, 1, 3, Some(5.0)));
+            assert_eq!(changes.emit(&engine).unwrap(), 0);
+            engine
+                .execute(transaction(TransactionType::Dispute, 2, 1, None))
+                .unwrap();
+            engine
+                .execute_from(
+                    transaction(TransactionType::Lock, 1, 4, None),
+                    Origin::Admin,
+                )
+                .unwrap();
+            assert_eq!(changes.emit(&engine).unwrap(), 2);
+
+            let batches: Vec<Vec<_>> = receiver
+                .try_iter()
+                .map(|batch| {
+                    batch
+                        .iter()
+                        .map(|change: &AccountSnapshot| (change.client, change.held, change.locked))
+                        .collect()
+                })
+                .collect();
+            assert_eq!(
+                batches,
+                [
+                    vec![(1, 0.0, false), (2, 0.0, false)],
+                    vec![(1, 0.0, true), (2, 2.0, false)],
+                ]
+            );
+        }
+    }
+}
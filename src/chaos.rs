@@ -0,0 +1,182 @@
+//! Failure injection for the durability tests of the write-ahead log, checkpoints and resumed
+//! runs, built with the `chaos` feature only. Processing is killed at chosen points:
+//!
+//! - [`FailPoint::AfterParse`], once a csv or ndjson row is parsed, before the engine sees it
+//! - [`FailPoint::BeforeApply`], once a transaction is logged, before it is applied
+//! - [`FailPoint::MidSnapshot`], once a checkpoint is written next to the previous one, before
+//!   it is synced and takes its place
+//!
+//! The binary reads its kill points from `RUST_CODING_TEST_CHAOS`, a comma separated list of
+//! `<point>:<hit>` such as `before-apply:25`, and exits with [`EXIT_CODE`] at the given hit of
+//! the point, counted over the whole process, without flushing or cleaning up anything:
+//!
+//! ```text
+//! RUST_CODING_TEST_CHAOS=mid-snapshot:2 cargo run --features chaos -- \
+//!     --checkpoint run.checkpoint --checkpoint-every 100 transactions.csv
+//! ```
+//!
+//! Library tests arm points of the current thread with [`arm`] instead, which panics at the
+//! hit, so that the state left on disk can be recovered from within the same test.
+
+use std::cell::RefCell;
+use std::env;
+use std::fmt;
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+/// Environment variable holding the kill points of the process
+pub const CHAOS_VAR: &str = "RUST_CODING_TEST_CHAOS";
+
+/// Exit code of a process killed at one of its kill points
+pub const EXIT_CODE: i32 = 86;
+
+/// Place in the processing where it can be killed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailPoint {
+    AfterParse,
+    BeforeApply,
+    MidSnapshot,
+}
+
+impl FailPoint {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailPoint::AfterParse => "after-parse",
+            FailPoint::BeforeApply => "before-apply",
+            FailPoint::MidSnapshot => "mid-snapshot",
+        }
+    }
+}
+
+impl fmt::Display for FailPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for FailPoint {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "after-parse" => Ok(FailPoint::AfterParse),
+            "before-apply" => Ok(FailPoint::BeforeApply),
+            "mid-snapshot" => Ok(FailPoint::MidSnapshot),
+            _ => Err(format!("unknown failure point '{}'", value)),
+        }
+    }
+}
+
+/// Kill points of the process, with the hits of each so far
+struct ProcessTrigger {
+    point: FailPoint,
+    at: u64,
+    hits: AtomicU64,
+}
+
+static PROCESS_TRIGGERS: OnceLock<Vec<ProcessTrigger>> = OnceLock::new();
+
+thread_local! {
+    /// Points armed by [`arm`] with the hit they panic at and the hits so far
+    static THREAD_TRIGGERS: RefCell<Vec<(FailPoint, u64, u64)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Parses kill points such as `after-parse:10,mid-snapshot:1`. Hits are counted from 1.
+pub fn parse_points(value: &str) -> Result<Vec<(FailPoint, u64)>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|point| !point.is_empty())
+        .map(|point| {
+            let (name, at) = point
+                .split_once(':')
+                .ok_or_else(|| format!("failure point '{}' is missing its hit", point))?;
+            match at.parse() {
+                Ok(at) if at > 0 => Ok((name.parse()?, at)),
+                _ => Err(format!("invalid hit '{}' of failure point '{}'", at, name)),
+            }
+        })
+        .collect()
+}
+
+/// Makes the `at`-th hit of the point on the current thread from now on panic
+pub fn arm(point: FailPoint, at: u64) {
+    THREAD_TRIGGERS.with(|triggers| triggers.borrow_mut().push((point, at, 0)));
+}
+
+/// Removes the points armed on the current thread
+pub fn disarm() {
+    THREAD_TRIGGERS.with(|triggers| triggers.borrow_mut().clear());
+}
+
+/// Kills the processing if this hit of the point is one it was armed for
+pub(crate) fn hit(point: FailPoint) {
+    let triggers = PROCESS_TRIGGERS.get_or_init(|| {
+        let points = env::var(CHAOS_VAR).unwrap_or_default();
+        let points = parse_points(&points).unwrap_or_else(|err| panic!("{}: {}", CHAOS_VAR, err));
+        points
+            .into_iter()
+            .map(|(point, at)| ProcessTrigger {
+                point,
+                at,
+                hits: AtomicU64::new(0),
+            })
+            .collect()
+    });
+    for trigger in triggers.iter().filter(|trigger| trigger.point == point) {
+        if trigger.hits.fetch_add(1, Ordering::SeqCst) + 1 == trigger.at {
+            eprintln!("Killed at hit {} of {}", trigger.at, point);
+            process::exit(EXIT_CODE);
+        }
+    }
+    let killed = THREAD_TRIGGERS.with(|triggers| {
+        let mut killed = None;
+        for (armed, at, hits) in triggers.borrow_mut().iter_mut() {
+            if *armed == point {
+                *hits += 1;
+                if hits == at {
+                    killed = Some(*at);
+                }
+            }
+        }
+        killed
+    });
+    if let Some(at) = killed {
+        panic!("Killed at hit {} of {}", at, point);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::chaos::{arm, disarm, hit, parse_points, FailPoint};
+        use std::panic;
+
+        #[test]
+        fn points_are_parsed_with_their_hit() {
+            assert_eq!(
+                parse_points("after-parse:10, mid-snapshot:1"),
+                Ok(vec![
+                    (FailPoint::AfterParse, 10),
+                    (FailPoint::MidSnapshot, 1)
+                ])
+            );
+            assert_eq!(parse_points(""), Ok(vec![]));
+            assert!(parse_points("before-apply").is_err());
+            assert!(parse_points("before-apply:0").is_err());
+            assert!(parse_points("mid-apply:3").is_err());
+        }
+
+        #[test]
+        fn armed_points_panic_at_their_hit_only() {
+            arm(FailPoint::BeforeApply, 2);
+            hit(FailPoint::BeforeApply);
+            hit(FailPoint::AfterParse);
+            assert!(panic::catch_unwind(|| hit(FailPoint::BeforeApply)).is_err());
+            hit(FailPoint::BeforeApply);
+            disarm();
+        }
+    }
+}
@@ -0,0 +1,329 @@
+//! Invariants of the engine state verified at the end of a run with `--check`, a safety net for
+//! production batches: a violation means the engine itself went wrong, whatever the input was.
+//!
+//! - replaying the recorded history of the run gives the balances and lock of every account,
+//! - held funds are those of the open disputes, authorizations and pending withdrawals,
+//! - held funds are only negative when disputed withdrawals may be charged back.
+
+use crate::account::{AccountView, ClientId, DUST};
+use crate::currency::Currency;
+use crate::engine::TransactionEngine;
+use crate::policy::DisputePolicy;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Invariant {
+    /// Replaying the recorded history gives the balances and lock of every account
+    Ledger,
+    /// Held funds are the sum of the open disputes, authorizations and pending withdrawals
+    Held,
+    /// Held funds are never negative unless withdrawals can be disputed
+    NegativeHeld,
+}
+
+impl fmt::Display for Invariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Invariant::Ledger => "ledger",
+            Invariant::Held => "held",
+            Invariant::NegativeHeld => "negative held",
+        })
+    }
+}
+
+/// An invariant that does not hold for the funds of a client in a currency
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub invariant: Invariant,
+    pub client_id: ClientId,
+    pub currency: Option<Currency>,
+    /// What was expected and found
+    pub detail: String,
+}
+
+/// Invariants violated by the accounts of an engine, none if it is sound
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvariantReport {
+    /// Accounts that were checked
+    pub accounts: usize,
+    /// Violations in order of client and currency
+    pub violations: Vec<Violation>,
+}
+
+impl InvariantReport {
+    /// Checks every account of `engine` and replays the history it recorded on `start`, the
+    /// engine the run started from, new or restored, with the same configuration. Only the
+    /// entries recorded after those of `start` are replayed, all of them unless `engine` was
+    /// created without [`record_history`](crate::engine::EngineConfig::record_history). Fails if
+    /// the state of an account cannot be read.
+    pub fn check(engine: &TransactionEngine, start: TransactionEngine) -> io::Result<Self> {
+        let mut report = InvariantReport {
+            accounts: engine.accounts.len(),
+            violations: Vec::new(),
+        };
+        let negative_held = matches!(
+            engine.config().dispute_policy,
+            DisputePolicy::ReverseWithdrawalOnChargeback
+        );
+        for account in engine.accounts.values() {
+            let state = account.state()?;
+            let mut holds: BTreeMap<Option<Currency>, f64> = BTreeMap::new();
+            for (_, currency, amount) in state.active_disputes.iter().chain(&state.authorizations) {
+                *holds.entry(*currency).or_default() += amount;
+            }
+            for (_, currency, amount, _) in &state.pending_withdrawals {
+                *holds.entry(*currency).or_default() += amount;
+            }
+            let mut violation = |invariant, currency, detail| {
+                report.violations.push(Violation {
+                    invariant,
+                    client_id: state.client_id,
+                    currency,
+                    detail,
+                })
+            };
+            for (currency, balance) in &state.balances {
+                let holds = holds.remove(currency).unwrap_or_default();
+                if (balance.held - holds).abs() > DUST {
+                    violation(
+                        Invariant::Held,
+                        *currency,
+                        format!(
+                            "held {:.4} but disputes, authorizations and pending withdrawals \
+                             account for {:.4}",
+                            balance.held, holds
+                        ),
+                    );
+                }
+                if balance.held < -DUST && !negative_held {
+                    violation(
+                        Invariant::NegativeHeld,
+                        *currency,
+                        format!(
+                            "held {:.4} although withdrawals cannot be disputed",
+                            balance.held
+                        ),
+                    );
+                }
+            }
+            for (currency, holds) in holds.into_iter().filter(|(_, holds)| holds.abs() > DUST) {
+                violation(
+                    Invariant::Held,
+                    currency,
+                    format!(
+                        "nothing held but disputes, authorizations and pending withdrawals \
+                         account for {:.4}",
+                        holds
+                    ),
+                );
+            }
+        }
+        report.replay(engine, start);
+        report
+            .violations
+            .sort_by_key(|violation| (violation.client_id, violation.currency));
+        Ok(report)
+    }
+
+    /// Replays the history of `engine` on `start` and compares the accounts
+    fn replay(&mut self, engine: &TransactionEngine, start: TransactionEngine) {
+        let recorded = start.ledger.next_sequence();
+        let mut replica = start.into_replica();
+        let mut entries = engine.ledger.in_order();
+        entries.retain(|entry| entry.sequence >= recorded);
+        for (entry, err) in replica.replay_history(&entries) {
+            let transaction = &entry.transaction;
+            self.violations.push(Violation {
+                invariant: Invariant::Ledger,
+                client_id: transaction.client_id,
+                currency: transaction.currency,
+                detail: format!(
+                    "#{} {} tx {} of the history cannot be replayed: {}",
+                    entry.sequence, transaction.transaction_type, transaction.transaction_id, err
+                ),
+            });
+        }
+
+        let clients: BTreeSet<_> = engine
+            .accounts
+            .keys()
+            .chain(replica.accounts.keys())
+            .copied()
+            .collect();
+        for client_id in clients {
+            let view = |engine: &TransactionEngine| {
+                engine
+                    .accounts
+                    .get(&client_id)
+                    .map(|account| AccountView::new(account.as_ref()))
+            };
+            let (actual, replayed) = (view(engine), view(&replica));
+            let currencies: BTreeSet<_> = actual
+                .iter()
+                .chain(&replayed)
+                .flat_map(|view| view.balances.iter().map(|(currency, _)| *currency))
+                .collect();
+            for currency in currencies {
+                let balance = |view: &Option<AccountView>| {
+                    view.as_ref()
+                        .map_or_else(Default::default, |view| view.balance(currency))
+                };
+                let (actual, replayed) = (balance(&actual), balance(&replayed));
+                if (actual.available - replayed.available).abs() > DUST
+                    || (actual.held - replayed.held).abs() > DUST
+                {
+                    self.violations.push(Violation {
+                        invariant: Invariant::Ledger,
+                        client_id,
+                        currency,
+                        detail: format!(
+                            "available {:.4}, held {:.4} but the history gives available {:.4}, \
+                             held {:.4}",
+                            actual.available, actual.held, replayed.available, replayed.held
+                        ),
+                    });
+                }
+            }
+            let locked = |view: &Option<AccountView>| view.as_ref().is_some_and(|view| view.locked);
+            if locked(&actual) != locked(&replayed) {
+                let state = |locked| if locked { "locked" } else { "unlocked" };
+                self.violations.push(Violation {
+                    invariant: Invariant::Ledger,
+                    client_id,
+                    currency: None,
+                    detail: format!(
+                        "{} but the history leaves it {}",
+                        state(locked(&actual)),
+                        state(locked(&replayed))
+                    ),
+                });
+            }
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+impl fmt::Display for InvariantReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Checked {} accounts, {} invariant violations",
+            self.accounts,
+            self.violations.len()
+        )?;
+        for violation in &self.violations {
+            let currency = violation
+                .currency
+                .map_or(String::new(), |currency| format!(" {}", currency));
+            writeln!(
+                f,
+                "  client {}{}, {}: {}",
+                violation.client_id, currency, violation.invariant, violation.detail
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::account::{Balance, BasicAccount};
+        use crate::check::{Invariant, InvariantReport};
+        use crate::engine::{EngineConfig, TransactionEngine};
+        use crate::policy::{DisputeExpiry, DisputePolicy};
+        use crate::transaction::{transaction, TransactionType};
+
+        #[test]
+        fn sound_engine_passes_every_check() {
+            let config = EngineConfig {
+                record_history: true,
+                dispute_expiry: DisputeExpiry {
+                    after_transactions: Some(2),
+                    after_days: None,
+                },
+                ..EngineConfig::default()
+            };
+            let mut engine = TransactionEngine::with_config(config.clone());
+            let mut transfer = transaction(TransactionType::Transfer, 1, 4, Some(2.0));
+            transfer.to_client_id = Some(2);
+            for transaction in [
+                transaction(TransactionType::Deposit, 1, 1, Some(10.0)),
+                transaction(TransactionType::Withdrawal, 1, 2, Some(3.0)),
+                transaction(TransactionType::Dispute, 1, 2, None),
+                transfer,
+                transaction(TransactionType::Deposit, 2, 5, Some(1.0)),
+                transaction(TransactionType::Authorize, 2, 6, Some(2.5)),
+                transaction(TransactionType::Withdrawal, 3, 7, Some(1.0)),
+                transaction(TransactionType::Deposit, 3, 8, Some(4.0)),
+                transaction(TransactionType::Dispute, 3, 8, None),
+                transaction(TransactionType::Chargeback, 3, 8, None),
+            ] {
+                let _ = engine.execute(transaction);
+            }
+
+            let report =
+                InvariantReport::check(&engine, TransactionEngine::with_config(config)).unwrap();
+
+            assert!(report.is_ok(), "{}", report);
+            assert_eq!(report.accounts, 3);
+            assert_eq!(
+                report.to_string(),
+                "Checked 3 accounts, 0 invariant violations\n"
+            );
+        }
+
+        #[test]
+        fn violations_are_reported_per_client() {
+            let config = EngineConfig {
+                record_history: true,
+                dispute_policy: DisputePolicy::DepositsOnly,
+                ..EngineConfig::default()
+            };
+            let mut engine = TransactionEngine::with_config(config.clone());
+            for transaction in [
+                transaction(TransactionType::Deposit, 1, 1, Some(10.0)),
+                transaction(TransactionType::Deposit, 2, 2, Some(5.0)),
+            ] {
+                engine.execute(transaction).unwrap();
+            }
+            // Funds moved behind the back of the engine
+            let mut state = engine.accounts[&2].state().unwrap();
+            state.balances[0].1 = Balance {
+                available: 6.0,
+                held: -1.0,
+            };
+            let account = BasicAccount::from_state(state, config.account_policies());
+            engine.accounts.insert(2, Box::new(account));
+
+            let report =
+                InvariantReport::check(&engine, TransactionEngine::with_config(config)).unwrap();
+
+            let violations: Vec<_> = report
+                .violations
+                .iter()
+                .map(|violation| (violation.client_id, violation.invariant))
+                .collect();
+            assert_eq!(
+                violations,
+                [
+                    (2, Invariant::Held),
+                    (2, Invariant::NegativeHeld),
+                    (2, Invariant::Ledger)
+                ]
+            );
+            assert_eq!(
+                report.to_string(),
+                "Checked 2 accounts, 3 invariant violations\n  \
+                 client 2, held: held -1.0000 but disputes, authorizations and pending withdrawals account for 0.0000\n  \
+                 client 2, negative held: held -1.0000 although withdrawals cannot be disputed\n  \
+                 client 2, ledger: available 6.0000, held -1.0000 but the history gives available 5.0000, held 0.0000\n"
+            );
+        }
+    }
+}
@@ -0,0 +1,2989 @@
+use rust_coding_test::log::Level;
+use rust_coding_test::{
+    Amount, AmountFormat, AmountRules, ClientFilter, ClientId, ColumnMapping, ConfigError,
+    ConfigFile, Currency, DiffFormat, DisputeCycles, DisputePolicy, DuplicatePolicy, EngineConfig,
+    ExportFormat, FeeSchedule, InputFormat, InputOrdering, JournalFormat, LimitsPolicy, LockPolicy,
+    NegativeBalancePolicy, OutputFormat, OutputOrder, PipelineCapacities, PrecisionPolicy,
+    RateLimits, ReportFormat, RetentionPolicy, SampledSource, StatementFormat, Timestamp,
+    TransactionId, TransactionSource, Webhooks, WindowPolicy, DEFAULT_TOLERANCE,
+};
+use rust_coding_test::{DiskStore, Storage, Workload};
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const USAGE: &str = "\
+Usage: rust-coding-test [OPTIONS] [--input] <PATH>
+       rust-coding-test serve [--listen <ADDR>] [SERVE OPTIONS]
+       rust-coding-test gen-data [GEN-DATA OPTIONS]
+       rust-coding-test apply-fees --restore <PATH> [APPLY-FEES OPTIONS]
+       rust-coding-test advance-time --restore <PATH> --schedule <PATH> [ADVANCE-TIME OPTIONS]
+       rust-coding-test query --restore <PATH> --client <ID> [QUERY OPTIONS]
+       rust-coding-test analyze --restore <PATH> [ANALYZE OPTIONS]
+       rust-coding-test statements --restore <PATH> --output-dir <DIR> [STATEMENTS OPTIONS]
+       rust-coding-test repl [REPL OPTIONS]
+       rust-coding-test merge-clients --restore <PATH> --from <ID> --into <ID> [MERGE OPTIONS]
+       rust-coding-test diff <LEFT> <RIGHT> [DIFF OPTIONS]
+       rust-coding-test verify-output <PATH>
+       rust-coding-test export --restore <PATH> [EXPORT OPTIONS]
+       rust-coding-test import <PATH> --snapshot <PATH> [IMPORT OPTIONS]
+
+Options:
+  -i, --input <PATH>      file with transactions to process, - to read from stdin, or a
+                          directory or pattern such as dumps/*.csv whose files are processed
+                          in file name order
+      --input-format <FORMAT>
+                          input format: csv or ndjson, detected from the extension by default
+      --map <COLUMN>=<NAME>
+                          read a column of the input from the column or field NAME, e.g.
+                          tx=transaction_id; may be repeated, columns are type, client, tx,
+                          amount, to, currency and timestamp
+      --metadata <COLUMNS>
+                          keep the values of these other columns or fields of the input, a
+                          comma separated list such as merchant,memo, with the transactions:
+                          they are written to the audit log and history but change no balance
+      --max-decimals <N>  refuse input amounts with more than N decimal places as malformed
+                          (default 4, at most 8); negative and non-finite amounts always are
+      --max-amount <AMOUNT>
+                          refuse larger input amounts as malformed (default 1000000000000)
+      --precision <POLICY>
+                          what happens to input amounts with more decimal places than
+                          --max-decimals: reject (default) as malformed, truncate or
+                          round-half-even; balances are written with --max-decimals places
+      --amount-format <FORMAT>
+                          how input csv amounts are written: plain (default) as 1234.56, en as
+                          1,234.56, de as 1.234,56, fr as 1 234,56, ch as 1'234.56, or the
+                          decimal separator followed by the grouping separator, such as ',.'
+      --fast-parse        read csv input by splitting its lines on commas instead of through
+                          serde, several times faster on large files; a quoted field cannot
+                          span lines
+      --read-ahead <ROWS> read input on a background thread, buffering up to ROWS rows
+      --parse-queue <ROWS>
+                          parse and validate the input on threads of their own, with up to
+                          ROWS parsed rows waiting for validation (default 1024); --stats shows
+                          the peak depths of the queues
+      --validate-queue <ROWS>
+                          same, with up to ROWS validated rows waiting for the engine
+                          (default 1024)
+      --ordering <MODE>   transaction ids of the input: unordered (default), strict to reject
+                          transactions arriving after a later one, or reorder:<N> to put up to
+                          N shuffled transactions back in order first
+      --threads <N>       process clients on N worker threads
+      --unordered         with --threads, read the files of a directory or pattern in parallel
+                          rather than one after the other
+      --parallel-read     same, but apply their rows in file order, as if read one after the
+                          other
+      --namespace-sources give the transactions of every input file ids of their own, for files
+                          reusing the same ids, e.g. the feeds of two acquirers; the file name
+                          and original id are kept as the source and source_tx metadata
+      --restore <PATH>    start from the engine state saved in a snapshot
+      --snapshot <PATH>   save the engine state after processing
+      --checkpoint <PATH> snapshot the engine state with the rows read every --checkpoint-every
+                          rows, removed once the run completes
+      --checkpoint-every <ROWS>
+                          rows between checkpoints (default 10000)
+      --resume            continue an interrupted run from --checkpoint if present, skipping
+                          the rows it already reflects
+      --audit-log <PATH>  write an audit event for every transaction as json lines
+      --journal <PATH>    append every applied transaction to PATH as double-entry journal
+                          entries on the available and held funds of the clients
+      --journal-format <FORMAT>
+                          format of the journal: ledger for ledger-cli and hledger, or
+                          beancount; beancount for .beancount and .bean files by default
+      --window-dir <DIR>  process the input as a stream of windows, writing the accounts changed
+                          by each to DIR/window-<N>.jsonl and a row per window with its
+                          transactions, rejections and duration to DIR/windows.csv
+      --window-size <N>   close a window every N transactions
+      --window-secs <SECS>
+                          close a window once it was open for SECS seconds, with its next
+                          transaction
+      --window-reset-metrics
+                          start the counters of the engine over with every window, so that
+                          --stats and --report-file only cover the last one
+  -o, --output <PATH>     write accounts to a file instead of stdout
+  -f, --format <FORMAT>   output format: csv (default), json for an array, jsonl for one object
+                          per line or table for aligned columns with totals; --output-format is
+                          the same
+      --sort-output <ORDER>
+                          order of the accounts: client to sort them by client id, so that the
+                          same input always gives the same output, or none (default)
+      --trailer           end the output with a line holding its row count, the sum of its
+                          totals and the SHA-256 digest of the lines above, which
+                          verify-output checks
+      --config <PATH>     read policies and I/O settings from a TOML file, engine.toml in the
+                          working directory by default; flags take precedence over it
+      --strict            fail on the first malformed row instead of skipping it
+      --atomic            apply the input all or nothing: if any row is malformed or rejected,
+                          the state is rolled back to where the run started and the run fails;
+                          the accounts and --snapshot are still written, unchanged
+      --max-rejects <N>   with --atomic, keep the input if at most N rows are malformed or
+                          rejected (default 0)
+      --validate          write a report of the malformed rows, rejected transactions and
+                          locked accounts the input would lead to instead of the accounts, and
+                          no snapshot or audit log; fails if any row is malformed or rejected
+      --clients <IDS>     only apply the transactions of these clients, a comma separated list of
+                          ids and ranges such as 1,5,100-200; transfers go by the debited client
+      --exclude-clients <IDS>
+                          skip the transactions of these clients
+      --limit <N>         only read the first N rows of the input, to iterate quickly on large
+                          files
+      --sample <RATE>     apply each row with probability RATE, between 0 and 1, e.g. 0.01;
+                          disputes of transactions left out are rejected
+      --seed <N>          seed of --sample, the same seed keeps the same rows (default 1)
+      --admin             treat the input as submitted by an administrator, accepting lock,
+                          unlock, reversal and merge rows; they are rejected otherwise
+      --rejects-file <PATH>
+                          write skipped malformed rows to a file for reprocessing
+      --dead-letters <PATH>
+                          hold back disputes, resolves, chargebacks and other rows referencing
+                          a transaction not seen yet, retry them at the end of the input and
+                          write those still failing to a file for manual review
+      --retry-after <N>   also retry them every N rows
+      --disputes-output <PATH>
+                          write the disputes left open by the run to a csv file, with the amount
+                          held and the number of transactions since each
+      --duplicates <MODE>  reused transaction ids: reject (default) or idempotent
+      --negative-balance <MODE>
+                          disputes exceeding available funds: allow (default), reject-dispute,
+                          hold-partial, or hold-pending to hold the rest as funds come in
+      --dispute-policy <MODE>
+                          reverse-withdrawals (default) or deposits-only
+      --max-dispute-cycles <N>
+                          times the same funds can be disputed, resolves making them disputable
+                          again until then (default 1)
+      --dispute-window <DAYS>
+                          reject disputes more than DAYS days after the disputed transaction,
+                          for rows with a timestamp column
+      --dispute-expiry <N>
+                          resolve disputes nobody settled within N further transactions
+      --dispute-expiry-days <DAYS>
+                          resolve disputes nobody settled within DAYS days, for rows with a
+                          timestamp column
+      --allow-locked-deposits
+                          keep accepting deposits on accounts locked by a chargeback
+      --unlock-on-representment
+                          unlock accounts once every chargeback of theirs was represented
+      --allow-adjustments accept adjustment rows of admin input, changing the available funds by
+                          their amount, negative for a debit, without checking them
+      --retention <MODE>  transactions kept disputable: unbounded (default), per-client:<N> for
+                          the last N of each client or global:<N> for the last N overall
+      --limits <PATH>     reject transactions breaking the limits in the [limits] table of a
+                          TOML file
+      --client-master <PATH>
+                          csv file of client,tier rows putting clients in the retail, business
+                          or internal tier, whose tables in the configuration file override
+                          the limits, overdraft and dispute policies of their accounts
+      --storage <KIND>    where disputable transactions are kept: memory (default) or disk
+      --storage-path <PATH>
+                          file backing --storage disk, truncated on startup
+      --progress          show the share of the input read and the time left on stderr, when it
+                          is a terminal
+      --stats             print a summary report of the run on stderr
+      --report-file <PATH>
+                          write the summary report of the run to a file
+      --dormant-after <N> list the open accounts without a transaction in the last N ones in the
+                          summary report, requires --stats or --report-file
+      --profile           print the p50 and p99 latencies of each transaction type and the
+                          accounts whose transactions took the most time on stderr
+      --check             verify the invariants of the accounts once the input is processed:
+                          replaying the history of the run gives every balance, held funds are
+                          those of open disputes, authorizations and pending withdrawals and
+                          only negative under reverse-withdrawals; violations are printed on
+                          stderr and fail the run before any output or snapshot is written
+  -v, --verbose           report skipped rows and rejected transactions on stderr
+      --log-level <LEVEL> error, warn, info, debug or trace; RUST_LOG is used when not given
+  -h, --help              print this message
+
+Serve options:
+      --listen <ADDR>     address to listen on, 127.0.0.1:8080 by default
+      --grpc-listen <ADDR>
+                          also serve the gRPC interface of proto/engine.proto on ADDR, in
+                          builds with the grpc feature
+      --wal <PATH>        log every transaction before applying it and replay the log on startup
+      --wal-sync-every <N>
+                          sync the log to disk at least every N transactions (default 256)
+                          and at the end of every request
+      --checkpoint <PATH> snapshot restored on startup if present and replaced periodically,
+                          truncating the log
+      --checkpoint-every <N>
+                          transactions between checkpoints (default 10000)
+      --state <DIR>       keep the engine state in DIR instead, as a snapshot saved every
+                          --checkpoint-every transactions and a log of the transactions since,
+                          synced as with --wal-sync-every; restored on startup
+      --admin-token <TOKEN>
+                          enable POST /admin/transactions, and POST /admin/undo/<N> reversing
+                          the last N deposits and withdrawals, for requests carrying
+                          Authorization: Bearer TOKEN
+      --rate-limit <RATE> refuse client submissions beyond RATE transactions per second over all
+                          clients with 429 Too Many Requests; RATE:BURST also sets how many are
+                          accepted at once, a second worth by default
+      --client-rate-limit <RATE>
+                          the same for the transactions of each client
+      --changes-output <PATH>
+                          write the accounts changed by every request as json lines to PATH, or
+                          to stdout with -, for downstream systems consuming deltas
+      --read-view-every <N>
+                          answer GET /accounts and GET /accounts/<ID> from a copy of the
+                          accounts republished every N transactions and after every request,
+                          so that queries never wait for a long batch but may lag behind it
+      --webhook <URL>     post the accounts locked and the chargebacks applied as json to URL,
+                          an http:// url; may be repeated
+      --webhook-secret <SECRET>
+                          key of the HMAC-SHA256 signature of every webhook payload, sent in the
+                          X-Webhook-Signature header; required with --webhook
+      --webhook-attempts <N>
+                          deliveries tried per event and webhook, with a backoff doubling from
+                          a second (default 5)
+      --restore, --audit-log, --log-level, --duplicates, --negative-balance, --dispute-policy,
+      --max-dispute-cycles, --dispute-window, --dispute-expiry, --dispute-expiry-days,
+      --allow-locked-deposits, --unlock-on-representment, --allow-adjustments, --retention,
+      --limits, --client-master, --storage, --storage-path and --config behave as for batch
+      processing
+
+Gen-data options, writing a synthetic csv file of transactions:
+      --rows <N>          rows to generate (default 1000000)
+      --clients <N>       clients making the transactions (default 1000)
+      --skew <S>          0 (default) spreads transactions evenly over clients, larger values
+                          concentrate them on few clients
+      --dispute-rate <R>  share of disputes, resolves and chargebacks, 0 to 1 (default 0.05)
+      --seed <N>          seed of the generator, the same seed gives the same file (default 1)
+  -o, --output <PATH>     write to a file instead of stdout
+
+Apply-fees options, charging fees and crediting interest to the accounts of a snapshot:
+      --restore <PATH>    snapshot holding the accounts, required
+      --fees <PATH>       read the schedule from the [fees] table of a TOML file instead of the
+                          configuration file
+      --snapshot <PATH>   save the engine state afterwards
+      --audit-log, -o, --output, -f, --format, --sort-output, --log-level and the options of
+      the engine behave as for batch processing
+
+Advance-time options, executing the standing orders of a schedule on the accounts of a snapshot:
+      --restore <PATH>    snapshot holding the accounts, required
+      --schedule <PATH>   csv file of standing orders with a type, client, tx, amount, every[,
+                          to][, currency][, start] header, required
+      --ticks <N>         ticks to let pass, executing the orders in ticks as they fall due
+                          (default 0)
+      --until <TIMESTAMP> then execute the orders in days, hours, minutes or seconds due by the
+                          timestamp
+      --snapshot <PATH>   save the engine state afterwards
+      --audit-log, -o, --output, -f, --format, --sort-output, --log-level and the options of
+      the engine behave as for batch processing
+
+Query options, printing the balances, open disputes and latest transactions of one client:
+      --restore <PATH>    snapshot holding the accounts, required
+      --client <ID>       client to look up, required
+      --recent <N>        latest ledger entries to show (default 10), only recorded with
+                          record_history in the configuration file
+      --as-of-tx <TX>     show the client as it was right after transaction TX, replaying the
+                          ledger, which must be complete: recorded since the first run with
+                          record_history in the configuration file
+  -f, --format <FORMAT>   text (default) or json
+      --config <PATH>     read the account types from a TOML file, engine.toml by default
+
+Analyze options, printing statistics over the accounts of a snapshot for dashboards:
+      --restore <PATH>    snapshot holding the accounts, required
+      --top <N>           largest balances and most disputed accounts to list (default 10)
+      --currency <CODE>   only analyze the balances and disputes in this currency
+  -f, --format <FORMAT>   text (default) for aligned tables, or json
+      --config <PATH>     read the account types from a TOML file, engine.toml by default
+
+Statements options, writing an account statement per client from the ledger of a snapshot:
+      --restore <PATH>    snapshot holding the accounts, required; its ledger must be complete:
+                          recorded since the first run with record_history in the configuration
+                          file
+      --output-dir <DIR>  directory of the statements, one client-<ID>.csv or .txt file per
+                          client, created if missing; required
+      --after-tx <TX>     start the statements right after transaction TX, with the balances it
+                          left as opening balances, instead of with the whole ledger
+      --client <ID>       only write the statement of the client
+  -f, --format <FORMAT>   csv (default) for a row per balance and transaction, or text
+      --config <PATH>     read the account types from a TOML file, engine.toml by default
+
+Repl options, typing transactions and inspecting accounts at a prompt, help listing the commands:
+      --restore <PATH>    start from the engine state saved in a snapshot
+      --admin             accept administrative transactions such as lock, unlock or adjustment
+      --log-level, --config and the options of the engine behave as for batch processing
+
+Merge options, moving the account of one client of a snapshot into that of another:
+      --restore <PATH>    snapshot holding the accounts, required
+      --from <ID>         client whose account is merged and removed, required
+      --into <ID>         client keeping the merged account, required; it is locked if either
+                          account was, and the merge fails if both accounts hold the same
+                          disputable transaction or the merged one has pending withdrawals
+      --snapshot <PATH>   save the engine state afterwards
+      --audit-log, -o, --output, -f, --format, --sort-output, --log-level and the options of
+      the engine behave as for batch processing
+
+Diff options, comparing the accounts of two csv outputs or snapshots and failing if they differ:
+      --tolerance <AMOUNT>
+                          largest difference between amounts still considered equal
+                          (default 0.00005)
+  -f, --format <FORMAT>   text (default), csv for a row per differing field, or json
+  -o, --output <PATH>     write the differences to a file instead of stdout
+      --config <PATH>     read the account types of snapshots from a TOML file, engine.toml by
+                          default
+
+Verify-output checks an output written with --trailer against its trailer, failing if it was
+changed or cut short
+
+Export options, writing the full state of a snapshot in the documented exchange format, readable
+by other versions of the engine and by other tools:
+      --restore <PATH>    snapshot holding the state, required
+  -f, --format <FORMAT>   jsonl (default), one json object per line
+  -o, --output <PATH>     write to a file instead of stdout
+      --config <PATH>     read the account types from a TOML file, engine.toml by default
+
+Import options, turning a state written by export into a snapshot of this version:
+      --snapshot <PATH>   snapshot to write, required
+      --config <PATH>     read the account types from a TOML file, engine.toml by default";
+
+/// Address the server listens on unless `--listen` is given
+const DEFAULT_LISTEN: &str = "127.0.0.1:8080";
+const DEFAULT_WAL_SYNC_EVERY: usize = 256;
+const DEFAULT_CHECKPOINT_EVERY: usize = 10_000;
+const DEFAULT_QUERY_RECENT: usize = 10;
+const DEFAULT_ANALYZE_TOP: usize = 10;
+const DEFAULT_SEED: u64 = 1;
+
+/// What the binary was asked to do
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Process a file of transactions and write the resulting accounts. Boxed as it is much
+    /// larger than the other commands.
+    Process(Box<Cli>),
+    /// Run an HTTP server accepting transactions, boxed for the same reason
+    Serve(Box<ServeCli>),
+    /// Write a synthetic file of transactions
+    GenData(GenDataCli),
+    /// Charge fees and credit interest to the accounts of a snapshot
+    ApplyFees(ApplyFeesCli),
+    /// Execute the standing orders of a schedule on the accounts of a snapshot
+    AdvanceTime(AdvanceTimeCli),
+    /// Print what a snapshot holds about one client
+    Query(QueryCli),
+    /// Print statistics over the accounts of a snapshot
+    Analyze(AnalyzeCli),
+    /// Write the account statements of the clients of a snapshot
+    Statements(StatementsCli),
+    /// Apply transactions typed at an interactive prompt
+    Repl(ReplCli),
+    /// Merge the account of a client of a snapshot into that of another
+    MergeClients(MergeClientsCli),
+    /// Compare the accounts of two outputs or snapshots
+    Diff(DiffCli),
+    /// Check an output against its trailer
+    VerifyOutput(VerifyOutputCli),
+    /// Write the state of a snapshot in the exchange format
+    Export(ExportCli),
+    /// Write a snapshot of a state in the exchange format
+    Import(ImportCli),
+}
+
+impl Command {
+    /// Parses arguments, excluding the program name
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, CliError> {
+        let mut args = args.into_iter().peekable();
+        match args.peek().map(String::as_str) {
+            Some("serve") => {
+                args.next();
+                ServeCli::parse(args).map(|cli| Command::Serve(Box::new(cli)))
+            }
+            Some("gen-data") => {
+                args.next();
+                GenDataCli::parse(args).map(Command::GenData)
+            }
+            Some("apply-fees") => {
+                args.next();
+                ApplyFeesCli::parse(args).map(Command::ApplyFees)
+            }
+            Some("advance-time") => {
+                args.next();
+                AdvanceTimeCli::parse(args).map(Command::AdvanceTime)
+            }
+            Some("query") => {
+                args.next();
+                QueryCli::parse(args).map(Command::Query)
+            }
+            Some("analyze") => {
+                args.next();
+                AnalyzeCli::parse(args).map(Command::Analyze)
+            }
+            Some("statements") => {
+                args.next();
+                StatementsCli::parse(args).map(Command::Statements)
+            }
+            Some("repl") => {
+                args.next();
+                ReplCli::parse(args).map(Command::Repl)
+            }
+            Some("merge-clients") => {
+                args.next();
+                MergeClientsCli::parse(args).map(Command::MergeClients)
+            }
+            Some("diff") => {
+                args.next();
+                DiffCli::parse(args).map(Command::Diff)
+            }
+            Some("verify-output") => {
+                args.next();
+                VerifyOutputCli::parse(args).map(Command::VerifyOutput)
+            }
+            Some("export") => {
+                args.next();
+                ExportCli::parse(args).map(Command::Export)
+            }
+            Some("import") => {
+                args.next();
+                ImportCli::parse(args).map(Command::Import)
+            }
+            _ => Cli::parse(args).map(|cli| Command::Process(Box::new(cli))),
+        }
+    }
+
+    /// Whether the command builds an engine, which can be configured by a file
+    pub fn reads_config(&self) -> bool {
+        !matches!(self, Command::GenData(_) | Command::VerifyOutput(_))
+    }
+
+    /// Configuration file given with `--config`
+    pub fn config_file(&self) -> Option<&Path> {
+        match self {
+            Command::Process(cli) => cli.engine.config_file.as_deref(),
+            Command::Serve(cli) => cli.engine.config_file.as_deref(),
+            Command::ApplyFees(cli) => cli.engine.config_file.as_deref(),
+            Command::AdvanceTime(cli) => cli.engine.config_file.as_deref(),
+            Command::Query(cli) => cli.config_file.as_deref(),
+            Command::Analyze(cli) => cli.config_file.as_deref(),
+            Command::Statements(cli) => cli.config_file.as_deref(),
+            Command::Repl(cli) => cli.engine.config_file.as_deref(),
+            Command::Diff(cli) => cli.config_file.as_deref(),
+            Command::MergeClients(cli) => cli.engine.config_file.as_deref(),
+            Command::Export(cli) => cli.config_file.as_deref(),
+            Command::Import(cli) => cli.config_file.as_deref(),
+            Command::GenData(_) | Command::VerifyOutput(_) => None,
+        }
+    }
+
+    /// Fills the settings not given on the command line from the configuration file
+    pub fn merge(&mut self, file: &ConfigFile) -> Result<(), CliError> {
+        match self {
+            Command::Process(cli) => cli.merge(file),
+            Command::Serve(cli) => cli.merge(file),
+            Command::ApplyFees(cli) => {
+                cli.batch.merge(file);
+                Ok(())
+            }
+            Command::AdvanceTime(cli) => {
+                cli.batch.merge(file);
+                Ok(())
+            }
+            Command::MergeClients(cli) => {
+                cli.batch.merge(file);
+                Ok(())
+            }
+            Command::Repl(cli) => {
+                cli.merge(file);
+                Ok(())
+            }
+            Command::GenData(_)
+            | Command::Query(_)
+            | Command::Analyze(_)
+            | Command::Statements(_)
+            | Command::Diff(_)
+            | Command::VerifyOutput(_)
+            | Command::Export(_)
+            | Command::Import(_) => Ok(()),
+        }
+    }
+}
+
+/// Backing store of the transaction logs of accounts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StorageKind {
+    #[default]
+    Memory,
+    Disk,
+}
+
+impl FromStr for StorageKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "memory" => Ok(StorageKind::Memory),
+            "disk" => Ok(StorageKind::Disk),
+            _ => Err(format!("unknown storage '{}'", s)),
+        }
+    }
+}
+
+/// Options shared by every command that builds an engine
+#[derive(Debug, Default, PartialEq)]
+pub struct EngineOptions {
+    pub allow_locked_deposits: bool,
+    pub unlock_on_representment: bool,
+    pub allow_adjustments: bool,
+    pub duplicate_policy: Option<DuplicatePolicy>,
+    pub negative_balance_policy: Option<NegativeBalancePolicy>,
+    pub dispute_policy: Option<DisputePolicy>,
+    pub max_dispute_cycles: Option<DisputeCycles>,
+    pub dispute_window: Option<u32>,
+    pub dispute_expiry_transactions: Option<u64>,
+    pub dispute_expiry_days: Option<u32>,
+    pub retention_policy: Option<RetentionPolicy>,
+    pub limits: Option<PathBuf>,
+    /// Tiers of the clients, over the `client_master` of the `[tiers]` table
+    pub client_master: Option<PathBuf>,
+    pub storage: StorageKind,
+    pub storage_path: Option<PathBuf>,
+    pub config_file: Option<PathBuf>,
+}
+
+impl EngineOptions {
+    /// Consumes the flag if it configures the engine. Returns whether it did.
+    fn parse_flag<I: Iterator<Item = String>>(
+        &mut self,
+        flag: &Flag,
+        args: &mut Args<I>,
+    ) -> Result<bool, CliError> {
+        match flag.name.as_str() {
+            "--duplicates" => self.duplicate_policy = Some(parse_value(flag, args.value(flag)?)?),
+            "--negative-balance" => {
+                self.negative_balance_policy = Some(parse_value(flag, args.value(flag)?)?)
+            }
+            "--dispute-policy" => self.dispute_policy = Some(parse_value(flag, args.value(flag)?)?),
+            "--max-dispute-cycles" => {
+                self.max_dispute_cycles = Some(parse_value(flag, args.value(flag)?)?)
+            }
+            "--dispute-window" => self.dispute_window = Some(parse_value(flag, args.value(flag)?)?),
+            "--dispute-expiry" => {
+                self.dispute_expiry_transactions =
+                    Some(parse_count(flag, args.value(flag)?)? as u64)
+            }
+            "--dispute-expiry-days" => {
+                self.dispute_expiry_days = Some(parse_value(flag, args.value(flag)?)?)
+            }
+            "--allow-locked-deposits" => self.allow_locked_deposits = true,
+            "--unlock-on-representment" => self.unlock_on_representment = true,
+            "--allow-adjustments" => self.allow_adjustments = true,
+            "--retention" => self.retention_policy = Some(parse_value(flag, args.value(flag)?)?),
+            "--limits" => self.limits = Some(PathBuf::from(args.value(flag)?)),
+            "--client-master" => self.client_master = Some(PathBuf::from(args.value(flag)?)),
+            "--storage" => self.storage = parse_value(flag, args.value(flag)?)?,
+            "--storage-path" => self.storage_path = Some(PathBuf::from(args.value(flag)?)),
+            "--config" => self.config_file = Some(PathBuf::from(args.value(flag)?)),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn check(&self) -> Result<(), CliError> {
+        match (self.storage, &self.storage_path) {
+            (StorageKind::Disk, None) => {
+                Err(CliError::RequiresFlag("--storage disk", "--storage-path"))
+            }
+            (StorageKind::Memory, Some(_)) => {
+                Err(CliError::RequiresFlag("--storage-path", "--storage disk"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Configuration of the configuration file overridden by the flags. Fails if the limits file
+    /// or the client master cannot be loaded or the storage file cannot be created.
+    pub fn config(&self, file: &ConfigFile) -> Result<EngineConfig, ConfigError> {
+        let client_master = self
+            .client_master
+            .as_ref()
+            .or(file.tiers.client_master.as_ref());
+        let mut config = match client_master {
+            Some(path) => {
+                let mut file = file.clone();
+                file.tiers.load_client_master(path)?;
+                file.engine_config()
+            }
+            None => file.engine_config(),
+        };
+        if self.allow_locked_deposits {
+            config.lock_policy = LockPolicy::AllowDeposits;
+        }
+        config.unlock_on_representment |= self.unlock_on_representment;
+        config.allow_adjustments |= self.allow_adjustments;
+        if let Some(policy) = self.duplicate_policy {
+            config.duplicate_policy = policy;
+        }
+        if let Some(policy) = self.negative_balance_policy {
+            config.negative_balance_policy = policy;
+        }
+        if let Some(policy) = self.dispute_policy {
+            config.dispute_policy = policy;
+        }
+        if let Some(cycles) = self.max_dispute_cycles {
+            config.max_dispute_cycles = cycles;
+        }
+        if let Some(days) = self.dispute_window {
+            config.dispute_window = Some(days);
+        }
+        if let Some(transactions) = self.dispute_expiry_transactions {
+            config.dispute_expiry.after_transactions = Some(transactions);
+        }
+        if let Some(days) = self.dispute_expiry_days {
+            config.dispute_expiry.after_days = Some(days);
+        }
+        if let Some(policy) = self.retention_policy {
+            config.retention_policy = policy;
+        }
+        if let Some(path) = &self.limits {
+            config.limits = LimitsPolicy::load(path)?;
+        }
+        if let (StorageKind::Disk, Some(path)) = (self.storage, &self.storage_path) {
+            config.storage = Storage::Disk(DiskStore::create(path)?);
+        }
+        Ok(config)
+    }
+}
+
+/// Output options shared by the batch steps run on a snapshot
+#[derive(Debug, Default, PartialEq)]
+pub struct BatchOptions {
+    pub snapshot: Option<PathBuf>,
+    pub audit_log: Option<PathBuf>,
+    pub output: Option<PathBuf>,
+    /// Csv unless given
+    pub format: Option<OutputFormat>,
+    /// Unsorted unless given
+    pub sort_output: Option<OutputOrder>,
+    pub log_level: Option<Level>,
+}
+
+impl BatchOptions {
+    /// Consumes the flag if it is an output option. Returns whether it did.
+    fn parse_flag<I: Iterator<Item = String>>(
+        &mut self,
+        flag: &Flag,
+        args: &mut Args<I>,
+    ) -> Result<bool, CliError> {
+        match flag.name.as_str() {
+            "--snapshot" => self.snapshot = Some(PathBuf::from(args.value(flag)?)),
+            "--audit-log" => self.audit_log = Some(PathBuf::from(args.value(flag)?)),
+            "-o" | "--output" => self.output = Some(PathBuf::from(args.value(flag)?)),
+            "-f" | "--format" | "--output-format" => {
+                self.format = Some(parse_value(flag, args.value(flag)?)?)
+            }
+            "--sort-output" => self.sort_output = Some(parse_value(flag, args.value(flag)?)?),
+            "--log-level" => self.log_level = Some(parse_value(flag, args.value(flag)?)?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Only the output format and order, audit log and log level of the I/O settings apply
+    pub fn merge(&mut self, file: &ConfigFile) {
+        self.format = self.format.or(file.io.format);
+        self.sort_output = self.sort_output.or(file.io.sort_output);
+        self.audit_log = self.audit_log.take().or_else(|| file.io.audit_log.clone());
+        self.log_level = self.log_level.or(file.io.log_level);
+    }
+}
+
+/// Command line options of the batch processor
+#[derive(Debug, PartialEq)]
+pub struct Cli {
+    pub input: PathBuf,
+    pub input_format: InputFormat,
+    /// Input names of the columns, set by `--map` over the configuration file
+    pub columns: ColumnMapping,
+    /// Bounds of the input amounts, the defaults of [`AmountRules`] unless given
+    pub max_decimals: Option<u32>,
+    pub max_amount: Option<f64>,
+    pub precision: Option<PrecisionPolicy>,
+    pub amount_format: Option<AmountFormat>,
+    /// Csv input is read by a [`FastCsvSource`](rust_coding_test::FastCsvSource)
+    pub fast_parse: bool,
+    pub read_ahead: Option<usize>,
+    /// Capacities of the queues of the [`Pipeline`](rust_coding_test::Pipeline), which is only
+    /// used if one is given
+    pub parse_queue: Option<usize>,
+    pub validate_queue: Option<usize>,
+    /// Unordered unless given
+    pub ordering: Option<InputOrdering>,
+    pub threads: Option<usize>,
+    /// Input files may be processed in parallel instead of in file name order
+    pub unordered: bool,
+    /// Input files are read in parallel and their rows applied in file name order
+    pub parallel_read: bool,
+    /// Transactions of different input files never share an id
+    pub namespace_sources: bool,
+    pub restore: Option<PathBuf>,
+    pub snapshot: Option<PathBuf>,
+    pub checkpoint: Option<PathBuf>,
+    pub checkpoint_every: usize,
+    /// Start from the checkpoint if present
+    pub resume: bool,
+    pub audit_log: Option<PathBuf>,
+    /// Double-entry journal the applied transactions are appended to
+    pub journal: Option<PathBuf>,
+    /// Told by the extension of the journal unless given
+    pub journal_format: Option<JournalFormat>,
+    /// Directory the windows of the input are written to, if processed as a stream of windows
+    pub window_dir: Option<PathBuf>,
+    /// Transactions and seconds after which a window closes
+    pub window_size: Option<usize>,
+    pub window_secs: Option<usize>,
+    pub window_reset_metrics: bool,
+    pub output: Option<PathBuf>,
+    pub rejects_file: Option<PathBuf>,
+    /// Rows referencing transactions that never arrived, retried through a
+    /// [`RetryBuffer`](rust_coding_test::RetryBuffer) if given
+    pub dead_letters: Option<PathBuf>,
+    /// Rows after which held back rows are retried, only at the end of the input if not given
+    pub retry_after: Option<usize>,
+    /// Csv report of the disputes still open once the input is processed
+    pub disputes_output: Option<PathBuf>,
+    /// Csv unless given
+    pub format: Option<OutputFormat>,
+    /// Unsorted unless given
+    pub sort_output: Option<OutputOrder>,
+    /// End the output with a [`Trailer`](crate::Trailer) line
+    pub trailer: bool,
+    pub strict: bool,
+    /// Roll back the whole input if more than `max_rejects` rows are malformed or rejected
+    pub atomic: bool,
+    pub max_rejects: Option<u64>,
+    /// Report the problems of the input instead of processing it
+    pub validate: bool,
+    /// Rows of other clients are counted but not applied
+    pub filter: ClientFilter,
+    /// Rows read from the input, all unless given
+    pub limit: Option<u64>,
+    /// Probability of applying each row, all of them unless given
+    pub sample: Option<f64>,
+    pub seed: Option<u64>,
+    /// Input comes from an administrator and may lock and unlock accounts
+    pub admin: bool,
+    pub verbose: bool,
+    pub log_level: Option<Level>,
+    pub progress: bool,
+    pub stats: bool,
+    pub report_file: Option<PathBuf>,
+    /// Transactions without activity after which accounts are reported as dormant
+    pub dormant_after: Option<u64>,
+    /// Report the latencies of the transactions per type and account
+    pub profile: bool,
+    /// Verify the invariants of the engine state once the input is processed
+    pub check: bool,
+    pub engine: EngineOptions,
+}
+
+/// Command line options of the HTTP server
+#[derive(Debug, PartialEq)]
+pub struct ServeCli {
+    pub listen: SocketAddr,
+    /// Address of the gRPC interface, not served unless given
+    pub grpc_listen: Option<SocketAddr>,
+    pub restore: Option<PathBuf>,
+    pub audit_log: Option<PathBuf>,
+    /// Where the accounts changed by every request are written, `-` for stdout
+    pub changes_output: Option<PathBuf>,
+    pub wal: Option<PathBuf>,
+    pub wal_sync_every: usize,
+    /// Directory of a [`FileStateStore`](rust_coding_test::FileStateStore)
+    pub state: Option<PathBuf>,
+    pub checkpoint: Option<PathBuf>,
+    pub checkpoint_every: usize,
+    pub admin_token: Option<String>,
+    /// Limits of client submissions, the `[rate_limits]` table where not given
+    pub rate_limits: RateLimits,
+    /// Transactions between the read views account queries are answered from, none to read the
+    /// engine itself
+    pub read_view_every: Option<usize>,
+    /// Notified of locks and chargebacks, the `[webhooks]` table where not given
+    pub webhooks: Webhooks,
+    pub log_level: Option<Level>,
+    pub engine: EngineOptions,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CliError {
+    /// `--help` was requested
+    Help,
+    MissingInput,
+    MissingValue(String),
+    InvalidValue {
+        flag: String,
+        value: String,
+    },
+    UnexpectedArgument(String),
+    ConflictingFlags(&'static str, &'static str),
+    /// The first flag is only valid together with the second
+    RequiresFlag(&'static str, &'static str),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Help => write!(f, "{}", USAGE),
+            CliError::MissingInput => write!(f, "no input file provided"),
+            CliError::MissingValue(flag) => write!(f, "{} requires a value", flag),
+            CliError::InvalidValue { flag, value } => {
+                write!(f, "invalid value '{}' for {}", value, flag)
+            }
+            CliError::UnexpectedArgument(arg) => write!(f, "unexpected argument '{}'", arg),
+            CliError::ConflictingFlags(first, second) => {
+                write!(f, "{} cannot be used together with {}", first, second)
+            }
+            CliError::RequiresFlag(flag, required) => write!(f, "{} requires {}", flag, required),
+        }
+    }
+}
+
+impl Cli {
+    /// Parses arguments, excluding the program name
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, CliError> {
+        let mut input = None;
+        let mut input_format = None;
+        let mut columns = ColumnMapping::default();
+        let mut max_decimals = None;
+        let mut precision = None;
+        let mut amount_format = None;
+        let mut fast_parse = false;
+        let mut max_amount = None;
+        let mut read_ahead = None;
+        let mut parse_queue = None;
+        let mut validate_queue = None;
+        let mut ordering = None;
+        let mut threads = None;
+        let mut unordered = false;
+        let mut parallel_read = false;
+        let mut namespace_sources = false;
+        let mut restore = None;
+        let mut snapshot = None;
+        let mut checkpoint = None;
+        let mut checkpoint_every = DEFAULT_CHECKPOINT_EVERY;
+        let mut resume = false;
+        let mut audit_log = None;
+        let mut journal = None;
+        let mut journal_format = None;
+        let mut window_dir = None;
+        let mut window_size = None;
+        let mut window_secs = None;
+        let mut window_reset_metrics = false;
+        let mut output = None;
+        let mut rejects_file = None;
+        let mut dead_letters = None;
+        let mut retry_after = None;
+        let mut disputes_output = None;
+        let mut format = None;
+        let mut sort_output = None;
+        let mut trailer = false;
+        let mut strict = false;
+        let mut atomic = false;
+        let mut max_rejects = None;
+        let mut validate = false;
+        let mut filter = ClientFilter::default();
+        let mut limit = None;
+        let mut sample = None;
+        let mut seed = None;
+        let mut admin = false;
+        let mut verbose = false;
+        let mut log_level = None;
+        let mut progress = false;
+        let mut stats = false;
+        let mut report_file = None;
+        let mut dormant_after = None;
+        let mut profile = false;
+        let mut check = false;
+        let mut engine = EngineOptions::default();
+
+        let mut args = Args::new(args);
+        while let Some(flag) = args.next_flag() {
+            if engine.parse_flag(&flag, &mut args)? {
+                continue;
+            }
+            match flag.name.as_str() {
+                "-h" | "--help" => return Err(CliError::Help),
+                "-i" | "--input" => input = Some(PathBuf::from(args.value(&flag)?)),
+                "--input-format" => input_format = Some(parse_value(&flag, args.value(&flag)?)?),
+                "--map" => {
+                    let value = args.value(&flag)?;
+                    let mapped = match value.split_once('=') {
+                        Some((column, name)) if !name.is_empty() => {
+                            columns.map(column, name).is_ok()
+                        }
+                        _ => false,
+                    };
+                    if !mapped {
+                        return Err(CliError::InvalidValue {
+                            flag: flag.name.clone(),
+                            value,
+                        });
+                    }
+                }
+                "--metadata" => {
+                    let value = args.value(&flag)?;
+                    if value
+                        .split(',')
+                        .any(|name| columns.capture(name.trim()).is_err())
+                    {
+                        return Err(CliError::InvalidValue {
+                            flag: flag.name.clone(),
+                            value,
+                        });
+                    }
+                }
+                "--max-decimals" => {
+                    let value = args.value(&flag)?;
+                    match value.parse::<u32>() {
+                        Ok(decimals) if decimals <= Amount::MAX_DECIMALS => {
+                            max_decimals = Some(decimals)
+                        }
+                        _ => {
+                            return Err(CliError::InvalidValue {
+                                flag: flag.name.clone(),
+                                value,
+                            })
+                        }
+                    }
+                }
+                "--precision" => precision = Some(parse_value(&flag, args.value(&flag)?)?),
+                "--amount-format" => amount_format = Some(parse_value(&flag, args.value(&flag)?)?),
+                "--fast-parse" => fast_parse = true,
+                "--max-amount" => {
+                    let value = args.value(&flag)?;
+                    match value.parse::<f64>() {
+                        Ok(amount) if amount > 0.0 && amount.is_finite() => {
+                            max_amount = Some(amount)
+                        }
+                        _ => {
+                            return Err(CliError::InvalidValue {
+                                flag: flag.name.clone(),
+                                value,
+                            })
+                        }
+                    }
+                }
+                "--read-ahead" => read_ahead = Some(parse_count(&flag, args.value(&flag)?)?),
+                "--parse-queue" => parse_queue = Some(parse_count(&flag, args.value(&flag)?)?),
+                "--validate-queue" => {
+                    validate_queue = Some(parse_count(&flag, args.value(&flag)?)?)
+                }
+                "--ordering" => ordering = Some(parse_value(&flag, args.value(&flag)?)?),
+                "--threads" => threads = Some(parse_count(&flag, args.value(&flag)?)?),
+                "--unordered" => unordered = true,
+                "--parallel-read" => parallel_read = true,
+                "--namespace-sources" => namespace_sources = true,
+                "--restore" => restore = Some(PathBuf::from(args.value(&flag)?)),
+                "--snapshot" => snapshot = Some(PathBuf::from(args.value(&flag)?)),
+                "--checkpoint" => checkpoint = Some(PathBuf::from(args.value(&flag)?)),
+                "--checkpoint-every" => checkpoint_every = parse_count(&flag, args.value(&flag)?)?,
+                "--resume" => resume = true,
+                "--audit-log" => audit_log = Some(PathBuf::from(args.value(&flag)?)),
+                "--journal" => journal = Some(PathBuf::from(args.value(&flag)?)),
+                "--journal-format" => {
+                    journal_format = Some(parse_value(&flag, args.value(&flag)?)?)
+                }
+                "--window-dir" => window_dir = Some(PathBuf::from(args.value(&flag)?)),
+                "--window-size" => window_size = Some(parse_count(&flag, args.value(&flag)?)?),
+                "--window-secs" => window_secs = Some(parse_count(&flag, args.value(&flag)?)?),
+                "--window-reset-metrics" => window_reset_metrics = true,
+                "-o" | "--output" => output = Some(PathBuf::from(args.value(&flag)?)),
+                "--rejects-file" => rejects_file = Some(PathBuf::from(args.value(&flag)?)),
+                "--dead-letters" => dead_letters = Some(PathBuf::from(args.value(&flag)?)),
+                "--retry-after" => retry_after = Some(parse_count(&flag, args.value(&flag)?)?),
+                "--disputes-output" => disputes_output = Some(PathBuf::from(args.value(&flag)?)),
+                "-f" | "--format" | "--output-format" => {
+                    format = Some(parse_value(&flag, args.value(&flag)?)?)
+                }
+                "--sort-output" => sort_output = Some(parse_value(&flag, args.value(&flag)?)?),
+                "--trailer" => trailer = true,
+                "--strict" => strict = true,
+                "--atomic" => atomic = true,
+                "--max-rejects" => max_rejects = Some(parse_value(&flag, args.value(&flag)?)?),
+                "--validate" => validate = true,
+                "--clients" => filter.include = Some(parse_value(&flag, args.value(&flag)?)?),
+                "--exclude-clients" => {
+                    filter.exclude = Some(parse_value(&flag, args.value(&flag)?)?)
+                }
+                "--limit" => limit = Some(parse_count(&flag, args.value(&flag)?)? as u64),
+                "--sample" => {
+                    let value = args.value(&flag)?;
+                    match parse_ratio(&flag, value.clone(), 1.0)? {
+                        0.0 => {
+                            return Err(CliError::InvalidValue {
+                                flag: flag.name.clone(),
+                                value,
+                            })
+                        }
+                        rate => sample = Some(rate),
+                    }
+                }
+                "--seed" => seed = Some(parse_value(&flag, args.value(&flag)?)?),
+                "--admin" => admin = true,
+                "-v" | "--verbose" => verbose = true,
+                "--progress" => progress = true,
+                "--stats" => stats = true,
+                "--report-file" => report_file = Some(PathBuf::from(args.value(&flag)?)),
+                "--dormant-after" => dormant_after = Some(parse_value(&flag, args.value(&flag)?)?),
+                "--profile" => profile = true,
+                "--check" => check = true,
+                "--log-level" => log_level = Some(parse_value(&flag, args.value(&flag)?)?),
+                _ if flag.is_option() => return Err(CliError::UnexpectedArgument(flag.arg)),
+                // Positional input path is kept for backwards compatibility
+                _ if input.is_none() => input = Some(PathBuf::from(flag.arg)),
+                _ => return Err(CliError::UnexpectedArgument(flag.arg)),
+            }
+        }
+
+        let input = input.ok_or(CliError::MissingInput)?;
+        let cli = Cli {
+            input_format: input_format.unwrap_or_else(|| InputFormat::from_path(&input)),
+            input,
+            columns,
+            max_decimals,
+            max_amount,
+            precision,
+            amount_format,
+            fast_parse,
+            read_ahead,
+            parse_queue,
+            validate_queue,
+            ordering,
+            threads,
+            unordered,
+            parallel_read,
+            namespace_sources,
+            restore,
+            snapshot,
+            checkpoint,
+            checkpoint_every,
+            resume,
+            audit_log,
+            journal,
+            journal_format,
+            window_dir,
+            window_size,
+            window_secs,
+            window_reset_metrics,
+            output,
+            rejects_file,
+            dead_letters,
+            retry_after,
+            disputes_output,
+            format,
+            sort_output,
+            trailer,
+            strict,
+            atomic,
+            max_rejects,
+            validate,
+            filter,
+            limit,
+            sample,
+            seed,
+            admin,
+            verbose,
+            log_level,
+            progress,
+            stats,
+            report_file,
+            dormant_after,
+            profile,
+            check,
+            engine,
+        };
+        cli.check_conflicts()?;
+        Ok(cli)
+    }
+
+    pub fn merge(&mut self, file: &ConfigFile) -> Result<(), CliError> {
+        let io = &file.io;
+        let mut columns = file.columns.clone();
+        columns.extend(&self.columns);
+        self.columns = columns;
+        self.format = self.format.or(io.format);
+        self.sort_output = self.sort_output.or(io.sort_output);
+        self.strict |= io.strict.unwrap_or(false);
+        self.max_decimals = self.max_decimals.or(io.max_decimals);
+        self.max_amount = self.max_amount.or(io.max_amount);
+        self.precision = self.precision.or(io.precision);
+        self.amount_format = self.amount_format.or(io.amount_format);
+        self.fast_parse |= io.fast_parse.unwrap_or(false);
+        self.read_ahead = self.read_ahead.or(io.read_ahead);
+        self.parse_queue = self.parse_queue.or(io.parse_queue);
+        self.validate_queue = self.validate_queue.or(io.validate_queue);
+        self.ordering = self.ordering.or(io.ordering);
+        self.threads = self.threads.or(io.threads);
+        self.unordered |= io.unordered.unwrap_or(false);
+        self.parallel_read |= io.parallel_read.unwrap_or(false);
+        self.namespace_sources |= io.namespace_sources.unwrap_or(false);
+        self.audit_log = self.audit_log.take().or_else(|| io.audit_log.clone());
+        self.journal = self.journal.take().or_else(|| io.journal.clone());
+        self.window_dir = self.window_dir.take().or_else(|| io.window_dir.clone());
+        self.window_size = self.window_size.or(io.window_size);
+        self.window_secs = self.window_secs.or(io.window_secs);
+        self.rejects_file = self.rejects_file.take().or_else(|| io.rejects_file.clone());
+        self.disputes_output = self
+            .disputes_output
+            .take()
+            .or_else(|| io.disputes_output.clone());
+        self.report_file = self.report_file.take().or_else(|| io.report_file.clone());
+        self.log_level = self.log_level.or(io.log_level);
+        // Settings of the file can conflict with flags
+        self.check_conflicts()
+    }
+
+    /// When the windows of the input close, with `--window-dir`
+    pub fn windows(&self) -> WindowPolicy {
+        WindowPolicy {
+            transactions: self.window_size.map(|size| size as u64),
+            interval: self
+                .window_secs
+                .map(|secs| Duration::from_secs(secs as u64)),
+            reset_metrics: self.window_reset_metrics,
+        }
+    }
+
+    /// Rules the amounts of the input are checked against
+    pub fn amounts(&self) -> AmountRules {
+        let defaults = AmountRules::default();
+        AmountRules {
+            max_decimals: self.max_decimals.unwrap_or(defaults.max_decimals),
+            max_amount: self.max_amount.unwrap_or(defaults.max_amount),
+            precision: self.precision.unwrap_or(defaults.precision),
+            format: self.amount_format.unwrap_or(defaults.format),
+        }
+    }
+
+    /// Source passing on the rows of `source` selected by the limit and sample
+    pub fn sampled(
+        &self,
+        source: Box<dyn TransactionSource + Send>,
+    ) -> Box<dyn TransactionSource + Send> {
+        if self.limit.is_none() && self.sample.is_none() {
+            return source;
+        }
+        let mut sampled = SampledSource::new(source);
+        if let Some(limit) = self.limit {
+            sampled = sampled.with_limit(limit);
+        }
+        if let Some(rate) = self.sample {
+            sampled = sampled.with_sample(rate, self.seed.unwrap_or(DEFAULT_SEED));
+        }
+        Box::new(sampled)
+    }
+
+    /// Capacities of the pipeline, `None` unless a queue is given
+    pub fn pipeline(&self) -> Option<PipelineCapacities> {
+        if self.parse_queue.is_none() && self.validate_queue.is_none() {
+            return None;
+        }
+        let defaults = PipelineCapacities::default();
+        Some(PipelineCapacities {
+            parsed: self.parse_queue.unwrap_or(defaults.parsed),
+            validated: self.validate_queue.unwrap_or(defaults.validated),
+        })
+    }
+
+    fn check_conflicts(&self) -> Result<(), CliError> {
+        self.engine.check()?;
+        if self.read_ahead.is_some() && self.pipeline().is_some() {
+            let queue = match self.parse_queue {
+                Some(_) => "--parse-queue",
+                None => "--validate-queue",
+            };
+            return Err(CliError::ConflictingFlags("--read-ahead", queue));
+        }
+        if self.unordered && self.threads.is_none() {
+            return Err(CliError::RequiresFlag("--unordered", "--threads"));
+        }
+        if self.parallel_read && self.threads.is_none() {
+            return Err(CliError::RequiresFlag("--parallel-read", "--threads"));
+        }
+        if self.parallel_read && self.unordered {
+            return Err(CliError::ConflictingFlags("--parallel-read", "--unordered"));
+        }
+        if self.threads.is_some() && self.restore.is_some() {
+            return Err(CliError::ConflictingFlags("--threads", "--restore"));
+        }
+        // Files read in parallel have no first rows
+        if self.unordered && self.limit.is_some() {
+            return Err(CliError::ConflictingFlags("--unordered", "--limit"));
+        }
+        if self.unordered && self.sample.is_some() {
+            return Err(CliError::ConflictingFlags("--unordered", "--sample"));
+        }
+        if self.parallel_read && self.limit.is_some() {
+            return Err(CliError::ConflictingFlags("--parallel-read", "--limit"));
+        }
+        if self.parallel_read && self.sample.is_some() {
+            return Err(CliError::ConflictingFlags("--parallel-read", "--sample"));
+        }
+        if self.seed.is_some() && self.sample.is_none() {
+            return Err(CliError::RequiresFlag("--seed", "--sample"));
+        }
+        if self.threads.is_some() && self.audit_log.is_some() {
+            return Err(CliError::ConflictingFlags("--threads", "--audit-log"));
+        }
+        if self.journal_format.is_some() && self.journal.is_none() {
+            return Err(CliError::RequiresFlag("--journal-format", "--journal"));
+        }
+        if self.threads.is_some() && self.journal.is_some() {
+            return Err(CliError::ConflictingFlags("--threads", "--journal"));
+        }
+        // Rows applied after the last checkpoint would be journaled twice
+        if self.resume && self.journal.is_some() {
+            return Err(CliError::ConflictingFlags("--resume", "--journal"));
+        }
+        if self.window_dir.is_none() {
+            if self.window_size.is_some() {
+                return Err(CliError::RequiresFlag("--window-size", "--window-dir"));
+            }
+            if self.window_secs.is_some() {
+                return Err(CliError::RequiresFlag("--window-secs", "--window-dir"));
+            }
+            if self.window_reset_metrics {
+                return Err(CliError::RequiresFlag(
+                    "--window-reset-metrics",
+                    "--window-dir",
+                ));
+            }
+        }
+        if self.threads.is_some() && self.window_dir.is_some() {
+            return Err(CliError::ConflictingFlags("--threads", "--window-dir"));
+        }
+        // Windows are numbered from the start of the run, and would replace those already written
+        if self.resume && self.window_dir.is_some() {
+            return Err(CliError::ConflictingFlags("--resume", "--window-dir"));
+        }
+        if self.threads.is_some() && self.checkpoint.is_some() {
+            return Err(CliError::ConflictingFlags("--threads", "--checkpoint"));
+        }
+        if self.resume && self.checkpoint.is_none() {
+            return Err(CliError::RequiresFlag("--resume", "--checkpoint"));
+        }
+        if self.resume && self.restore.is_some() {
+            return Err(CliError::ConflictingFlags("--resume", "--restore"));
+        }
+        // The ids given to transactions are not saved, so would clash with those of earlier runs
+        if self.namespace_sources && self.restore.is_some() {
+            return Err(CliError::ConflictingFlags(
+                "--namespace-sources",
+                "--restore",
+            ));
+        }
+        if self.namespace_sources && self.resume {
+            return Err(CliError::ConflictingFlags(
+                "--namespace-sources",
+                "--resume",
+            ));
+        }
+        if self.max_rejects.is_some() && !self.atomic {
+            return Err(CliError::RequiresFlag("--max-rejects", "--atomic"));
+        }
+        // Workers and checkpoints keep state out of reach of the rollback
+        if self.atomic && self.threads.is_some() {
+            return Err(CliError::ConflictingFlags("--atomic", "--threads"));
+        }
+        if self.atomic && self.checkpoint.is_some() {
+            return Err(CliError::ConflictingFlags("--atomic", "--checkpoint"));
+        }
+        // Entries are written as transactions are applied, before a rollback could undo them
+        if self.atomic && self.journal.is_some() {
+            return Err(CliError::ConflictingFlags("--atomic", "--journal"));
+        }
+        if self.atomic && self.window_dir.is_some() {
+            return Err(CliError::ConflictingFlags("--atomic", "--window-dir"));
+        }
+        if self.atomic && self.engine.storage == StorageKind::Disk {
+            return Err(CliError::ConflictingFlags("--atomic", "--storage disk"));
+        }
+        // Tables are for people, who have no use for a checksum
+        if self.trailer && self.format == Some(OutputFormat::Table) {
+            return Err(CliError::ConflictingFlags("--trailer", "--format table"));
+        }
+        if self.retry_after.is_some() && self.dead_letters.is_none() {
+            return Err(CliError::RequiresFlag("--retry-after", "--dead-letters"));
+        }
+        // Held back rows are neither in the checkpoints nor seen by the workers
+        if self.dead_letters.is_some() && self.threads.is_some() {
+            return Err(CliError::ConflictingFlags("--dead-letters", "--threads"));
+        }
+        if self.dead_letters.is_some() && self.checkpoint.is_some() {
+            return Err(CliError::ConflictingFlags("--dead-letters", "--checkpoint"));
+        }
+        if self.dormant_after.is_some() && !self.stats && self.report_file.is_none() {
+            return Err(CliError::RequiresFlag("--dormant-after", "--stats"));
+        }
+        Ok(())
+    }
+}
+
+impl ServeCli {
+    /// Parses the arguments following `serve`
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, CliError> {
+        let mut listen = None;
+        let mut grpc_listen = None;
+        let mut restore = None;
+        let mut audit_log = None;
+        let mut changes_output = None;
+        let mut wal = None;
+        let mut wal_sync_every = DEFAULT_WAL_SYNC_EVERY;
+        let mut state = None;
+        let mut checkpoint = None;
+        let mut checkpoint_every = DEFAULT_CHECKPOINT_EVERY;
+        let mut admin_token = None;
+        let mut rate_limits = RateLimits::default();
+        let mut read_view_every = None;
+        let mut webhooks = Webhooks::default();
+        let mut log_level = None;
+        let mut engine = EngineOptions::default();
+
+        let mut args = Args::new(args);
+        while let Some(flag) = args.next_flag() {
+            if engine.parse_flag(&flag, &mut args)? {
+                continue;
+            }
+            match flag.name.as_str() {
+                "-h" | "--help" => return Err(CliError::Help),
+                "--webhook" => webhooks.urls.push(parse_value(&flag, args.value(&flag)?)?),
+                "--webhook-secret" => webhooks.secret = args.value(&flag)?,
+                "--webhook-attempts" => {
+                    let attempts = parse_count(&flag, args.value(&flag)?)?;
+                    webhooks.max_attempts = Some(u32::try_from(attempts).unwrap_or(u32::MAX))
+                }
+                "--rate-limit" => {
+                    rate_limits.global = Some(parse_value(&flag, args.value(&flag)?)?)
+                }
+                "--client-rate-limit" => {
+                    rate_limits.per_client = Some(parse_value(&flag, args.value(&flag)?)?)
+                }
+                "--listen" => listen = Some(parse_value(&flag, args.value(&flag)?)?),
+                "--grpc-listen" => grpc_listen = Some(parse_value(&flag, args.value(&flag)?)?),
+                "--restore" => restore = Some(PathBuf::from(args.value(&flag)?)),
+                "--audit-log" => audit_log = Some(PathBuf::from(args.value(&flag)?)),
+                "--changes-output" => changes_output = Some(PathBuf::from(args.value(&flag)?)),
+                "--wal" => wal = Some(PathBuf::from(args.value(&flag)?)),
+                "--wal-sync-every" => wal_sync_every = parse_count(&flag, args.value(&flag)?)?,
+                "--state" => state = Some(PathBuf::from(args.value(&flag)?)),
+                "--log-level" => log_level = Some(parse_value(&flag, args.value(&flag)?)?),
+                "--checkpoint" => checkpoint = Some(PathBuf::from(args.value(&flag)?)),
+                "--checkpoint-every" => checkpoint_every = parse_count(&flag, args.value(&flag)?)?,
+                "--admin-token" => admin_token = Some(args.value(&flag)?),
+                "--read-view-every" => {
+                    read_view_every = Some(parse_count(&flag, args.value(&flag)?)?)
+                }
+                _ => return Err(CliError::UnexpectedArgument(flag.arg)),
+            }
+        }
+
+        if restore.is_some() && checkpoint.is_some() {
+            return Err(CliError::ConflictingFlags("--restore", "--checkpoint"));
+        }
+        if state.is_some() {
+            for (flag, set) in [
+                ("--wal", wal.is_some()),
+                ("--checkpoint", checkpoint.is_some()),
+                ("--restore", restore.is_some()),
+            ] {
+                if set {
+                    return Err(CliError::ConflictingFlags("--state", flag));
+                }
+            }
+        }
+        engine.check()?;
+        Ok(ServeCli {
+            listen: listen.unwrap_or_else(|| DEFAULT_LISTEN.parse().expect("Valid default")),
+            grpc_listen,
+            restore,
+            audit_log,
+            changes_output,
+            wal,
+            wal_sync_every,
+            state,
+            checkpoint,
+            checkpoint_every,
+            admin_token,
+            rate_limits,
+            read_view_every,
+            webhooks,
+            log_level,
+            engine,
+        })
+    }
+
+    /// Only the audit log and log level of the I/O settings apply to the server, along with the
+    /// rate limits and webhooks. Webhooks need a secret, from the flags or the file.
+    pub fn merge(&mut self, file: &ConfigFile) -> Result<(), CliError> {
+        self.audit_log = self.audit_log.take().or_else(|| file.io.audit_log.clone());
+        self.log_level = self.log_level.or(file.io.log_level);
+        self.rate_limits.global = self.rate_limits.global.or(file.rate_limits.global);
+        self.rate_limits.per_client = self.rate_limits.per_client.or(file.rate_limits.per_client);
+        if self.webhooks.urls.is_empty() {
+            self.webhooks.urls = file.webhooks.urls.clone();
+        }
+        if self.webhooks.secret.is_empty() {
+            self.webhooks.secret = file.webhooks.secret.clone();
+        }
+        self.webhooks.max_attempts = self.webhooks.max_attempts.or(file.webhooks.max_attempts);
+        self.webhooks.backoff = self.webhooks.backoff.or(file.webhooks.backoff);
+        if self.webhooks.is_enabled() && self.webhooks.secret.is_empty() {
+            return Err(CliError::RequiresFlag("--webhook", "--webhook-secret"));
+        }
+        Ok(())
+    }
+}
+
+/// Command line options of the fee and interest batch step
+#[derive(Debug, PartialEq)]
+pub struct ApplyFeesCli {
+    pub restore: PathBuf,
+    /// File holding the `[fees]` table, the configuration file unless given
+    pub fees: Option<PathBuf>,
+    pub batch: BatchOptions,
+    pub engine: EngineOptions,
+}
+
+impl ApplyFeesCli {
+    /// Parses the arguments following `apply-fees`
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, CliError> {
+        let mut restore = None;
+        let mut fees = None;
+        let mut batch = BatchOptions::default();
+        let mut engine = EngineOptions::default();
+
+        let mut args = Args::new(args);
+        while let Some(flag) = args.next_flag() {
+            if engine.parse_flag(&flag, &mut args)? || batch.parse_flag(&flag, &mut args)? {
+                continue;
+            }
+            match flag.name.as_str() {
+                "-h" | "--help" => return Err(CliError::Help),
+                "--restore" => restore = Some(PathBuf::from(args.value(&flag)?)),
+                "--fees" => fees = Some(PathBuf::from(args.value(&flag)?)),
+                _ => return Err(CliError::UnexpectedArgument(flag.arg)),
+            }
+        }
+
+        engine.check()?;
+        Ok(ApplyFeesCli {
+            restore: restore.ok_or(CliError::RequiresFlag("apply-fees", "--restore"))?,
+            fees,
+            batch,
+            engine,
+        })
+    }
+
+    /// Schedule of the file given with `--fees`, otherwise of the configuration file
+    pub fn schedule(&self, file: &ConfigFile) -> Result<FeeSchedule, ConfigError> {
+        match &self.fees {
+            Some(path) => FeeSchedule::load(path),
+            None => Ok(file.fees),
+        }
+    }
+}
+
+/// Command line options of the standing order batch step
+#[derive(Debug, PartialEq)]
+pub struct AdvanceTimeCli {
+    pub restore: PathBuf,
+    /// Csv file of standing orders
+    pub schedule: PathBuf,
+    pub ticks: u32,
+    /// Time the orders in seconds are executed up to, none of them unless given
+    pub until: Option<Timestamp>,
+    pub batch: BatchOptions,
+    pub engine: EngineOptions,
+}
+
+impl AdvanceTimeCli {
+    /// Parses the arguments following `advance-time`
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, CliError> {
+        let mut restore = None;
+        let mut schedule = None;
+        let mut ticks = 0;
+        let mut until = None;
+        let mut batch = BatchOptions::default();
+        let mut engine = EngineOptions::default();
+
+        let mut args = Args::new(args);
+        while let Some(flag) = args.next_flag() {
+            if engine.parse_flag(&flag, &mut args)? || batch.parse_flag(&flag, &mut args)? {
+                continue;
+            }
+            match flag.name.as_str() {
+                "-h" | "--help" => return Err(CliError::Help),
+                "--restore" => restore = Some(PathBuf::from(args.value(&flag)?)),
+                "--schedule" => schedule = Some(PathBuf::from(args.value(&flag)?)),
+                "--ticks" => ticks = parse_value(&flag, args.value(&flag)?)?,
+                "--until" => until = Some(parse_value(&flag, args.value(&flag)?)?),
+                _ => return Err(CliError::UnexpectedArgument(flag.arg)),
+            }
+        }
+
+        engine.check()?;
+        Ok(AdvanceTimeCli {
+            restore: restore.ok_or(CliError::RequiresFlag("advance-time", "--restore"))?,
+            schedule: schedule.ok_or(CliError::RequiresFlag("advance-time", "--schedule"))?,
+            ticks,
+            until,
+            batch,
+            engine,
+        })
+    }
+}
+
+/// Command line options of the account merge
+#[derive(Debug, PartialEq)]
+pub struct MergeClientsCli {
+    pub restore: PathBuf,
+    pub from: ClientId,
+    pub into: ClientId,
+    pub batch: BatchOptions,
+    pub engine: EngineOptions,
+}
+
+impl MergeClientsCli {
+    /// Parses the arguments following `merge-clients`
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, CliError> {
+        let mut restore = None;
+        let mut from = None;
+        let mut into = None;
+        let mut batch = BatchOptions::default();
+        let mut engine = EngineOptions::default();
+
+        let mut args = Args::new(args);
+        while let Some(flag) = args.next_flag() {
+            if engine.parse_flag(&flag, &mut args)? || batch.parse_flag(&flag, &mut args)? {
+                continue;
+            }
+            match flag.name.as_str() {
+                "-h" | "--help" => return Err(CliError::Help),
+                "--restore" => restore = Some(PathBuf::from(args.value(&flag)?)),
+                "--from" => from = Some(parse_value(&flag, args.value(&flag)?)?),
+                "--into" => into = Some(parse_value(&flag, args.value(&flag)?)?),
+                _ => return Err(CliError::UnexpectedArgument(flag.arg)),
+            }
+        }
+
+        engine.check()?;
+        Ok(MergeClientsCli {
+            restore: restore.ok_or(CliError::RequiresFlag("merge-clients", "--restore"))?,
+            from: from.ok_or(CliError::RequiresFlag("merge-clients", "--from"))?,
+            into: into.ok_or(CliError::RequiresFlag("merge-clients", "--into"))?,
+            batch,
+            engine,
+        })
+    }
+}
+
+/// Command line options of the client lookup
+#[derive(Debug, PartialEq)]
+pub struct QueryCli {
+    pub restore: PathBuf,
+    pub client_id: ClientId,
+    pub recent: usize,
+    /// Show the client as it was right after this transaction
+    pub as_of: Option<TransactionId>,
+    pub format: ReportFormat,
+    pub config_file: Option<PathBuf>,
+}
+
+impl QueryCli {
+    /// Parses the arguments following `query`
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, CliError> {
+        let mut restore = None;
+        let mut client_id = None;
+        let mut recent = DEFAULT_QUERY_RECENT;
+        let mut as_of = None;
+        let mut format = ReportFormat::default();
+        let mut config_file = None;
+
+        let mut args = Args::new(args);
+        while let Some(flag) = args.next_flag() {
+            match flag.name.as_str() {
+                "-h" | "--help" => return Err(CliError::Help),
+                "--restore" => restore = Some(PathBuf::from(args.value(&flag)?)),
+                "--client" => client_id = Some(parse_value(&flag, args.value(&flag)?)?),
+                "--recent" => recent = parse_value(&flag, args.value(&flag)?)?,
+                "--as-of-tx" => as_of = Some(parse_value(&flag, args.value(&flag)?)?),
+                "-f" | "--format" => format = parse_value(&flag, args.value(&flag)?)?,
+                "--config" => config_file = Some(PathBuf::from(args.value(&flag)?)),
+                _ => return Err(CliError::UnexpectedArgument(flag.arg)),
+            }
+        }
+
+        Ok(QueryCli {
+            restore: restore.ok_or(CliError::RequiresFlag("query", "--restore"))?,
+            client_id: client_id.ok_or(CliError::RequiresFlag("query", "--client"))?,
+            recent,
+            as_of,
+            format,
+            config_file,
+        })
+    }
+}
+
+/// Command line options of the statistics over the accounts of a snapshot
+#[derive(Debug, PartialEq)]
+pub struct AnalyzeCli {
+    pub restore: PathBuf,
+    pub top: usize,
+    /// Only currency analyzed, every currency unless given
+    pub currency: Option<Currency>,
+    pub format: ReportFormat,
+    pub config_file: Option<PathBuf>,
+}
+
+impl AnalyzeCli {
+    /// Parses the arguments following `analyze`
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, CliError> {
+        let mut restore = None;
+        let mut top = DEFAULT_ANALYZE_TOP;
+        let mut currency = None;
+        let mut format = ReportFormat::default();
+        let mut config_file = None;
+
+        let mut args = Args::new(args);
+        while let Some(flag) = args.next_flag() {
+            match flag.name.as_str() {
+                "-h" | "--help" => return Err(CliError::Help),
+                "--restore" => restore = Some(PathBuf::from(args.value(&flag)?)),
+                "--top" => top = parse_value(&flag, args.value(&flag)?)?,
+                "--currency" => currency = Some(parse_value(&flag, args.value(&flag)?)?),
+                "-f" | "--format" => format = parse_value(&flag, args.value(&flag)?)?,
+                "--config" => config_file = Some(PathBuf::from(args.value(&flag)?)),
+                _ => return Err(CliError::UnexpectedArgument(flag.arg)),
+            }
+        }
+
+        Ok(AnalyzeCli {
+            restore: restore.ok_or(CliError::RequiresFlag("analyze", "--restore"))?,
+            top,
+            currency,
+            format,
+            config_file,
+        })
+    }
+}
+
+/// Command line options of the account statements of a snapshot
+#[derive(Debug, PartialEq)]
+pub struct StatementsCli {
+    pub restore: PathBuf,
+    pub output_dir: PathBuf,
+    /// Transaction the statements start after, the whole ledger unless given
+    pub after: Option<TransactionId>,
+    /// Only client whose statement is written, every client unless given
+    pub client_id: Option<ClientId>,
+    pub format: StatementFormat,
+    pub config_file: Option<PathBuf>,
+}
+
+impl StatementsCli {
+    /// Parses the arguments following `statements`
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, CliError> {
+        let mut restore = None;
+        let mut output_dir = None;
+        let mut after = None;
+        let mut client_id = None;
+        let mut format = StatementFormat::default();
+        let mut config_file = None;
+
+        let mut args = Args::new(args);
+        while let Some(flag) = args.next_flag() {
+            match flag.name.as_str() {
+                "-h" | "--help" => return Err(CliError::Help),
+                "--restore" => restore = Some(PathBuf::from(args.value(&flag)?)),
+                "--output-dir" => output_dir = Some(PathBuf::from(args.value(&flag)?)),
+                "--after-tx" => after = Some(parse_value(&flag, args.value(&flag)?)?),
+                "--client" => client_id = Some(parse_value(&flag, args.value(&flag)?)?),
+                "-f" | "--format" => format = parse_value(&flag, args.value(&flag)?)?,
+                "--config" => config_file = Some(PathBuf::from(args.value(&flag)?)),
+                _ => return Err(CliError::UnexpectedArgument(flag.arg)),
+            }
+        }
+
+        Ok(StatementsCli {
+            restore: restore.ok_or(CliError::RequiresFlag("statements", "--restore"))?,
+            output_dir: output_dir.ok_or(CliError::RequiresFlag("statements", "--output-dir"))?,
+            after,
+            client_id,
+            format,
+            config_file,
+        })
+    }
+}
+
+/// Command line options of an interactive session
+#[derive(Debug, PartialEq)]
+pub struct ReplCli {
+    pub restore: Option<PathBuf>,
+    /// Transactions are submitted by an administrator
+    pub admin: bool,
+    pub log_level: Option<Level>,
+    pub engine: EngineOptions,
+}
+
+impl ReplCli {
+    /// Parses the arguments following `repl`
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, CliError> {
+        let mut restore = None;
+        let mut admin = false;
+        let mut log_level = None;
+        let mut engine = EngineOptions::default();
+
+        let mut args = Args::new(args);
+        while let Some(flag) = args.next_flag() {
+            if engine.parse_flag(&flag, &mut args)? {
+                continue;
+            }
+            match flag.name.as_str() {
+                "-h" | "--help" => return Err(CliError::Help),
+                "--restore" => restore = Some(PathBuf::from(args.value(&flag)?)),
+                "--admin" => admin = true,
+                "--log-level" => log_level = Some(parse_value(&flag, args.value(&flag)?)?),
+                _ => return Err(CliError::UnexpectedArgument(flag.arg)),
+            }
+        }
+
+        engine.check()?;
+        Ok(ReplCli {
+            restore,
+            admin,
+            log_level,
+            engine,
+        })
+    }
+
+    /// Fills the settings not given on the command line from the configuration file
+    pub fn merge(&mut self, file: &ConfigFile) {
+        self.log_level = self.log_level.or(file.io.log_level);
+    }
+}
+
+/// Command line options of the reconciliation of two sets of accounts
+#[derive(Debug, PartialEq)]
+pub struct DiffCli {
+    pub left: PathBuf,
+    pub right: PathBuf,
+    pub tolerance: f64,
+    pub format: DiffFormat,
+    pub output: Option<PathBuf>,
+    pub config_file: Option<PathBuf>,
+}
+
+impl DiffCli {
+    /// Parses the arguments following `diff`
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, CliError> {
+        let mut paths = Vec::new();
+        let mut tolerance = DEFAULT_TOLERANCE;
+        let mut format = DiffFormat::default();
+        let mut output = None;
+        let mut config_file = None;
+
+        let mut args = Args::new(args);
+        while let Some(flag) = args.next_flag() {
+            match flag.name.as_str() {
+                "-h" | "--help" => return Err(CliError::Help),
+                "--tolerance" => tolerance = parse_ratio(&flag, args.value(&flag)?, f64::MAX)?,
+                "-f" | "--format" => format = parse_value(&flag, args.value(&flag)?)?,
+                "-o" | "--output" => output = Some(PathBuf::from(args.value(&flag)?)),
+                "--config" => config_file = Some(PathBuf::from(args.value(&flag)?)),
+                _ if flag.is_option() => return Err(CliError::UnexpectedArgument(flag.arg)),
+                _ if paths.len() < 2 => paths.push(PathBuf::from(flag.arg)),
+                _ => return Err(CliError::UnexpectedArgument(flag.arg)),
+            }
+        }
+
+        let mut paths = paths.into_iter();
+        match (paths.next(), paths.next()) {
+            (Some(left), Some(right)) => Ok(DiffCli {
+                left,
+                right,
+                tolerance,
+                format,
+                output,
+                config_file,
+            }),
+            _ => Err(CliError::MissingInput),
+        }
+    }
+}
+
+/// Command line options of the check of an output against its trailer
+#[derive(Debug, PartialEq)]
+pub struct VerifyOutputCli {
+    pub path: PathBuf,
+}
+
+impl VerifyOutputCli {
+    /// Parses the arguments following `verify-output`
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, CliError> {
+        let mut path = None;
+
+        let mut args = Args::new(args);
+        while let Some(flag) = args.next_flag() {
+            match flag.name.as_str() {
+                "-h" | "--help" => return Err(CliError::Help),
+                _ if flag.is_option() => return Err(CliError::UnexpectedArgument(flag.arg)),
+                _ if path.is_none() => path = Some(PathBuf::from(flag.arg)),
+                _ => return Err(CliError::UnexpectedArgument(flag.arg)),
+            }
+        }
+
+        path.map(|path| VerifyOutputCli { path })
+            .ok_or(CliError::MissingInput)
+    }
+}
+
+/// Command line options of the export of a snapshot
+#[derive(Debug, PartialEq)]
+pub struct ExportCli {
+    pub restore: PathBuf,
+    pub format: ExportFormat,
+    pub output: Option<PathBuf>,
+    pub config_file: Option<PathBuf>,
+}
+
+impl ExportCli {
+    /// Parses the arguments following `export`
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, CliError> {
+        let mut restore = None;
+        let mut format = ExportFormat::default();
+        let mut output = None;
+        let mut config_file = None;
+
+        let mut args = Args::new(args);
+        while let Some(flag) = args.next_flag() {
+            match flag.name.as_str() {
+                "-h" | "--help" => return Err(CliError::Help),
+                "--restore" => restore = Some(PathBuf::from(args.value(&flag)?)),
+                "-f" | "--format" => format = parse_value(&flag, args.value(&flag)?)?,
+                "-o" | "--output" => output = Some(PathBuf::from(args.value(&flag)?)),
+                "--config" => config_file = Some(PathBuf::from(args.value(&flag)?)),
+                _ => return Err(CliError::UnexpectedArgument(flag.arg)),
+            }
+        }
+
+        Ok(ExportCli {
+            restore: restore.ok_or(CliError::RequiresFlag("export", "--restore"))?,
+            format,
+            output,
+            config_file,
+        })
+    }
+}
+
+/// Command line options of the import of an exported state
+#[derive(Debug, PartialEq)]
+pub struct ImportCli {
+    pub input: PathBuf,
+    pub snapshot: PathBuf,
+    pub config_file: Option<PathBuf>,
+}
+
+impl ImportCli {
+    /// Parses the arguments following `import`
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, CliError> {
+        let mut input = None;
+        let mut snapshot = None;
+        let mut config_file = None;
+
+        let mut args = Args::new(args);
+        while let Some(flag) = args.next_flag() {
+            match flag.name.as_str() {
+                "-h" | "--help" => return Err(CliError::Help),
+                "--snapshot" => snapshot = Some(PathBuf::from(args.value(&flag)?)),
+                "--config" => config_file = Some(PathBuf::from(args.value(&flag)?)),
+                _ if flag.is_option() => return Err(CliError::UnexpectedArgument(flag.arg)),
+                _ if input.is_none() => input = Some(PathBuf::from(flag.arg)),
+                _ => return Err(CliError::UnexpectedArgument(flag.arg)),
+            }
+        }
+
+        Ok(ImportCli {
+            input: input.ok_or(CliError::MissingInput)?,
+            snapshot: snapshot.ok_or(CliError::RequiresFlag("import", "--snapshot"))?,
+            config_file,
+        })
+    }
+}
+
+/// Command line options of the data generator
+#[derive(Debug, PartialEq)]
+pub struct GenDataCli {
+    pub workload: Workload,
+    pub output: Option<PathBuf>,
+}
+
+impl GenDataCli {
+    /// Parses the arguments following `gen-data`
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, CliError> {
+        let mut workload = Workload::default();
+        let mut output = None;
+
+        let mut args = Args::new(args);
+        while let Some(flag) = args.next_flag() {
+            match flag.name.as_str() {
+                "-h" | "--help" => return Err(CliError::Help),
+                "--rows" => workload.rows = parse_count(&flag, args.value(&flag)?)? as u64,
+                "--clients" => {
+                    workload.clients = parse_value(&flag, args.value(&flag)?)?;
+                    if workload.clients == 0 {
+                        return Err(CliError::InvalidValue {
+                            flag: flag.name,
+                            value: "0".to_string(),
+                        });
+                    }
+                }
+                "--skew" => workload.skew = parse_ratio(&flag, args.value(&flag)?, f64::MAX)?,
+                "--dispute-rate" => {
+                    workload.dispute_rate = parse_ratio(&flag, args.value(&flag)?, 1.0)?
+                }
+                "--seed" => workload.seed = parse_value(&flag, args.value(&flag)?)?,
+                "-o" | "--output" => output = Some(PathBuf::from(args.value(&flag)?)),
+                _ => return Err(CliError::UnexpectedArgument(flag.arg)),
+            }
+        }
+        Ok(GenDataCli { workload, output })
+    }
+}
+
+/// Argument as given on the command line
+struct Flag {
+    arg: String,
+    name: String,
+    /// Value of `--flag=value`
+    inline_value: Option<String>,
+}
+
+impl Flag {
+    fn is_option(&self) -> bool {
+        self.name.starts_with('-') && self.name != "-"
+    }
+}
+
+struct Args<I> {
+    args: I,
+}
+
+impl<I: Iterator<Item = String>> Args<I> {
+    fn new<A: IntoIterator<IntoIter = I>>(args: A) -> Self {
+        Args {
+            args: args.into_iter(),
+        }
+    }
+
+    fn next_flag(&mut self) -> Option<Flag> {
+        let arg = self.args.next()?;
+        // Support both `--flag value` and `--flag=value`
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => {
+                (name.to_string(), Some(value.to_string()))
+            }
+            _ => (arg.clone(), None),
+        };
+        Some(Flag {
+            arg,
+            name,
+            inline_value,
+        })
+    }
+
+    fn value(&mut self, flag: &Flag) -> Result<String, CliError> {
+        flag.inline_value
+            .clone()
+            .or_else(|| self.args.next())
+            .ok_or_else(|| CliError::MissingValue(flag.name.clone()))
+    }
+}
+
+fn parse_value<T: FromStr>(flag: &Flag, value: String) -> Result<T, CliError> {
+    value.parse().map_err(|_| CliError::InvalidValue {
+        flag: flag.name.clone(),
+        value,
+    })
+}
+
+/// Parses a strictly positive number
+fn parse_count(flag: &Flag, value: String) -> Result<usize, CliError> {
+    match parse_value(flag, value.clone())? {
+        0 => Err(CliError::InvalidValue {
+            flag: flag.name.clone(),
+            value,
+        }),
+        count => Ok(count),
+    }
+}
+
+/// Parses a number between 0 and `max`
+fn parse_ratio(flag: &Flag, value: String, max: f64) -> Result<f64, CliError> {
+    match parse_value::<f64>(flag, value.clone())? {
+        ratio if (0.0..=max).contains(&ratio) => Ok(ratio),
+        _ => Err(CliError::InvalidValue {
+            flag: flag.name.clone(),
+            value,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::cli::{
+            AdvanceTimeCli, AnalyzeCli, ApplyFeesCli, BatchOptions, Cli, CliError, Command,
+            DiffCli, EngineOptions, ExportCli, GenDataCli, ImportCli, MergeClientsCli, QueryCli,
+            ReplCli, ServeCli, StatementsCli, StorageKind, VerifyOutputCli, DEFAULT_ANALYZE_TOP,
+            DEFAULT_CHECKPOINT_EVERY, DEFAULT_QUERY_RECENT,
+        };
+        use rust_coding_test::log::Level;
+        use rust_coding_test::{
+            AmountFormat, AmountRules, ClientFilter, ColumnMapping, ConfigFile, DiffFormat,
+            DisputeCycles, DisputePolicy, DuplicatePolicy, ExportFormat, InputFormat,
+            InputOrdering, NegativeBalancePolicy, OutputFormat, OutputOrder, PrecisionPolicy,
+            RateLimit, RateLimits, ReportFormat, RetentionPolicy, StatementFormat, TransactionId,
+            Webhooks, Workload, DEFAULT_TOLERANCE,
+        };
+        use std::path::PathBuf;
+        use std::time::Duration;
+
+        fn parse(args: &[&str]) -> Result<Cli, CliError> {
+            Cli::parse(args.iter().map(|arg| arg.to_string()))
+        }
+
+        #[test]
+        fn positional_input_is_accepted() {
+            let cli = parse(&["file.csv"]).unwrap();
+
+            assert_eq!(cli.input, PathBuf::from("file.csv"));
+            assert_eq!(cli.input_format, InputFormat::Csv);
+            assert_eq!(cli.format, None);
+            assert!(!cli.strict && !cli.verbose && !cli.engine.allow_locked_deposits);
+        }
+
+        fn columns(mappings: &[(&str, &str)]) -> ColumnMapping {
+            let mut columns = ColumnMapping::default();
+            for (column, name) in mappings {
+                columns.map(column, name).unwrap();
+            }
+            columns
+        }
+
+        #[test]
+        fn all_flags_are_parsed() {
+            let cli = parse(&[
+                "--input",
+                "in.csv",
+                "--input-format",
+                "ndjson",
+                "--map",
+                "tx=transaction_id",
+                "--max-decimals",
+                "2",
+                "--max-amount",
+                "5000",
+                "--precision",
+                "round-half-even",
+                "--amount-format",
+                "de",
+                "--fast-parse",
+                "--parse-queue",
+                "64",
+                "--validate-queue",
+                "32",
+                "--ordering",
+                "reorder:16",
+                "--threads",
+                "4",
+                "--unordered",
+                "--namespace-sources",
+                "--snapshot",
+                "state.snapshot",
+                "-o",
+                "out.json",
+                "--format=json",
+                "--sort-output",
+                "client",
+                "--trailer",
+                "--rejects-file",
+                "rejects.csv",
+                "--disputes-output",
+                "disputes.csv",
+                "--strict",
+                "--validate",
+                "--clients",
+                "1,5,100-200",
+                "--exclude-clients",
+                "150",
+                "--admin",
+                "-v",
+                "--progress",
+                "--stats",
+                "--report-file",
+                "report.txt",
+                "--dormant-after",
+                "1000",
+                "--profile",
+                "--check",
+                "--log-level",
+                "debug",
+                "--allow-locked-deposits",
+                "--unlock-on-representment",
+                "--allow-adjustments",
+                "--duplicates",
+                "idempotent",
+                "--negative-balance",
+                "hold-partial",
+                "--dispute-policy",
+                "deposits-only",
+                "--max-dispute-cycles",
+                "2",
+                "--dispute-window",
+                "90",
+                "--dispute-expiry",
+                "500",
+                "--dispute-expiry-days",
+                "30",
+                "--retention",
+                "global:1000",
+                "--limits",
+                "limits.toml",
+                "--client-master",
+                "clients.csv",
+                "--storage",
+                "disk",
+                "--storage-path",
+                "logs.bin",
+                "--config",
+                "engine.toml",
+            ])
+            .unwrap();
+
+            assert_eq!(
+                cli,
+                Cli {
+                    input: PathBuf::from("in.csv"),
+                    input_format: InputFormat::Ndjson,
+                    columns: columns(&[("tx", "transaction_id")]),
+                    max_decimals: Some(2),
+                    max_amount: Some(5000.0),
+                    precision: Some(PrecisionPolicy::RoundHalfEven),
+                    amount_format: Some(AmountFormat {
+                        decimal_separator: ',',
+                        grouping_separator: Some('.'),
+                    }),
+                    fast_parse: true,
+                    read_ahead: None,
+                    parse_queue: Some(64),
+                    validate_queue: Some(32),
+                    ordering: Some(InputOrdering::Reorder(16)),
+                    threads: Some(4),
+                    unordered: true,
+                    parallel_read: false,
+                    namespace_sources: true,
+                    restore: None,
+                    snapshot: Some(PathBuf::from("state.snapshot")),
+                    checkpoint: None,
+                    checkpoint_every: DEFAULT_CHECKPOINT_EVERY,
+                    resume: false,
+                    audit_log: None,
+                    journal: None,
+                    journal_format: None,
+                    window_dir: None,
+                    window_size: None,
+                    window_secs: None,
+                    window_reset_metrics: false,
+                    output: Some(PathBuf::from("out.json")),
+                    rejects_file: Some(PathBuf::from("rejects.csv")),
+                    dead_letters: None,
+                    retry_after: None,
+                    disputes_output: Some(PathBuf::from("disputes.csv")),
+                    format: Some(OutputFormat::Json),
+                    sort_output: Some(OutputOrder::Client),
+                    trailer: true,
+                    strict: true,
+                    atomic: false,
+                    max_rejects: None,
+                    validate: true,
+                    filter: ClientFilter {
+                        include: Some("1,5,100-200".parse().unwrap()),
+                        exclude: Some("150".parse().unwrap()),
+                    },
+                    limit: None,
+                    sample: None,
+                    seed: None,
+                    admin: true,
+                    verbose: true,
+                    log_level: Some(Level::Debug),
+                    progress: true,
+                    stats: true,
+                    report_file: Some(PathBuf::from("report.txt")),
+                    dormant_after: Some(1000),
+                    profile: true,
+                    check: true,
+                    engine: EngineOptions {
+                        allow_locked_deposits: true,
+                        unlock_on_representment: true,
+                        allow_adjustments: true,
+                        duplicate_policy: Some(DuplicatePolicy::Idempotent),
+                        negative_balance_policy: Some(NegativeBalancePolicy::HoldPartial),
+                        dispute_policy: Some(DisputePolicy::DepositsOnly),
+                        max_dispute_cycles: Some(DisputeCycles(2)),
+                        dispute_window: Some(90),
+                        dispute_expiry_transactions: Some(500),
+                        dispute_expiry_days: Some(30),
+                        retention_policy: Some(RetentionPolicy::Global(1000)),
+                        limits: Some(PathBuf::from("limits.toml")),
+                        client_master: Some(PathBuf::from("clients.csv")),
+                        storage: StorageKind::Disk,
+                        storage_path: Some(PathBuf::from("logs.bin")),
+                        config_file: Some(PathBuf::from("engine.toml")),
+                    },
+                }
+            );
+        }
+
+        #[test]
+        fn input_format_is_detected_from_extension() {
+            assert_eq!(
+                parse(&["feed.ndjson"]).unwrap().input_format,
+                InputFormat::Ndjson
+            );
+        }
+
+        #[test]
+        fn invalid_arguments_are_rejected() {
+            assert_eq!(parse(&[]), Err(CliError::MissingInput));
+            assert_eq!(
+                parse(&["in.csv", "--output"]),
+                Err(CliError::MissingValue("--output".to_string()))
+            );
+            assert_eq!(
+                parse(&["in.csv", "--format", "xml"]),
+                Err(CliError::InvalidValue {
+                    flag: "--format".to_string(),
+                    value: "xml".to_string()
+                })
+            );
+            assert_eq!(
+                parse(&["in.csv", "--unknown"]),
+                Err(CliError::UnexpectedArgument("--unknown".to_string()))
+            );
+            assert_eq!(
+                parse(&["in.csv", "--threads", "2", "--restore", "state.snapshot"]),
+                Err(CliError::ConflictingFlags("--threads", "--restore"))
+            );
+            assert_eq!(
+                parse(&["in.csv", "--storage", "disk"]),
+                Err(CliError::RequiresFlag("--storage disk", "--storage-path"))
+            );
+            assert_eq!(
+                parse(&["in.csv", "--resume"]),
+                Err(CliError::RequiresFlag("--resume", "--checkpoint"))
+            );
+            assert_eq!(
+                parse(&["in.csv", "--threads", "2", "--checkpoint", "run.snapshot"]),
+                Err(CliError::ConflictingFlags("--threads", "--checkpoint"))
+            );
+            assert_eq!(
+                parse(&["in.csv", "--read-ahead", "8", "--validate-queue", "8"]),
+                Err(CliError::ConflictingFlags(
+                    "--read-ahead",
+                    "--validate-queue"
+                ))
+            );
+            assert_eq!(
+                parse(&["in.csv", "--seed", "42"]),
+                Err(CliError::RequiresFlag("--seed", "--sample"))
+            );
+            assert!(parse(&["in.csv", "--sample", "0"]).is_err());
+            assert!(parse(&["in.csv", "--sample", "1.5"]).is_err());
+            assert_eq!(
+                parse(&["in.csv", "--max-decimals", "9"]),
+                Err(CliError::InvalidValue {
+                    flag: "--max-decimals".to_string(),
+                    value: "9".to_string()
+                })
+            );
+            assert_eq!(
+                parse(&["dumps", "--threads", "2", "--unordered", "--limit", "10"]),
+                Err(CliError::ConflictingFlags("--unordered", "--limit"))
+            );
+            assert_eq!(
+                parse(&["dumps", "--parallel-read"]),
+                Err(CliError::RequiresFlag("--parallel-read", "--threads"))
+            );
+            assert_eq!(
+                parse(&["dumps", "--threads", "2", "--parallel-read", "--unordered"]),
+                Err(CliError::ConflictingFlags("--parallel-read", "--unordered"))
+            );
+            assert_eq!(
+                parse(&["in.csv", "--journal-format", "beancount"]),
+                Err(CliError::RequiresFlag("--journal-format", "--journal"))
+            );
+            assert_eq!(
+                parse(&["in.csv", "--window-size", "100"]),
+                Err(CliError::RequiresFlag("--window-size", "--window-dir"))
+            );
+            assert_eq!(
+                parse(&["in.csv", "--window-dir", "windows", "--threads", "2"]),
+                Err(CliError::ConflictingFlags("--threads", "--window-dir"))
+            );
+            assert_eq!(
+                parse(&["in.csv", "--threads", "2", "--journal", "books.ledger"]),
+                Err(CliError::ConflictingFlags("--threads", "--journal"))
+            );
+            assert_eq!(
+                parse(&[
+                    "in.csv",
+                    "--checkpoint",
+                    "run.snapshot",
+                    "--resume",
+                    "--journal",
+                    "books.ledger"
+                ]),
+                Err(CliError::ConflictingFlags("--resume", "--journal"))
+            );
+            assert_eq!(
+                parse(&["in.csv", "--atomic", "--journal", "books.ledger"]),
+                Err(CliError::ConflictingFlags("--atomic", "--journal"))
+            );
+            assert_eq!(
+                parse(&[
+                    "dumps",
+                    "--namespace-sources",
+                    "--restore",
+                    "state.snapshot"
+                ]),
+                Err(CliError::ConflictingFlags(
+                    "--namespace-sources",
+                    "--restore"
+                ))
+            );
+            assert_eq!(
+                parse(&["in.csv", "--max-rejects", "3"]),
+                Err(CliError::RequiresFlag("--max-rejects", "--atomic"))
+            );
+            assert_eq!(
+                parse(&["in.csv", "--atomic", "--threads", "2"]),
+                Err(CliError::ConflictingFlags("--atomic", "--threads"))
+            );
+            let cli = parse(&["in.csv", "--atomic", "--max-rejects", "3"]).unwrap();
+            assert!(cli.atomic);
+            assert_eq!(cli.max_rejects, Some(3));
+            assert_eq!(
+                parse(&["in.csv", "--trailer", "-f", "table"]),
+                Err(CliError::ConflictingFlags("--trailer", "--format table"))
+            );
+        }
+
+        #[test]
+        fn sampling_flags_are_parsed() {
+            let cli = parse(&[
+                "in.csv", "--limit", "1000", "--sample", "0.01", "--seed", "42",
+            ])
+            .unwrap();
+
+            assert_eq!(
+                (cli.limit, cli.sample, cli.seed),
+                (Some(1000), Some(0.01), Some(42))
+            );
+        }
+
+        #[test]
+        fn retry_flags_are_parsed() {
+            let cli = parse(&[
+                "in.csv",
+                "--dead-letters",
+                "dead.csv",
+                "--retry-after",
+                "100",
+            ])
+            .unwrap();
+
+            assert_eq!(cli.dead_letters, Some(PathBuf::from("dead.csv")));
+            assert_eq!(cli.retry_after, Some(100));
+            assert_eq!(
+                parse(&["in.csv", "--retry-after", "100"]),
+                Err(CliError::RequiresFlag("--retry-after", "--dead-letters"))
+            );
+            assert_eq!(
+                parse(&["in.csv", "--dead-letters", "dead.csv", "--threads", "2"]),
+                Err(CliError::ConflictingFlags("--dead-letters", "--threads"))
+            );
+        }
+
+        #[test]
+        fn checkpoint_flags_are_parsed() {
+            let cli = parse(&[
+                "in.csv",
+                "--checkpoint",
+                "run.snapshot",
+                "--checkpoint-every",
+                "500",
+                "--resume",
+            ])
+            .unwrap();
+
+            assert_eq!(cli.checkpoint, Some(PathBuf::from("run.snapshot")));
+            assert_eq!(cli.checkpoint_every, 500);
+            assert!(cli.resume);
+            assert_eq!(
+                parse(&["in.csv"]).unwrap().checkpoint_every,
+                DEFAULT_CHECKPOINT_EVERY
+            );
+        }
+
+        #[test]
+        fn serve_command_is_parsed() {
+            let command = Command::parse(
+                [
+                    "serve",
+                    "--listen",
+                    "0.0.0.0:9000",
+                    "--grpc-listen",
+                    "0.0.0.0:9001",
+                    "--duplicates=idempotent",
+                    "--admin-token",
+                    "s3cret",
+                    "--client-rate-limit",
+                    "5:20",
+                    "--changes-output",
+                    "-",
+                    "--read-view-every",
+                    "500",
+                ]
+                .iter()
+                .map(|arg| arg.to_string()),
+            )
+            .unwrap();
+
+            assert_eq!(
+                command,
+                Command::Serve(Box::new(ServeCli {
+                    listen: "0.0.0.0:9000".parse().unwrap(),
+                    grpc_listen: Some("0.0.0.0:9001".parse().unwrap()),
+                    restore: None,
+                    audit_log: None,
+                    changes_output: Some(PathBuf::from("-")),
+                    wal: None,
+                    wal_sync_every: 256,
+                    state: None,
+                    checkpoint: None,
+                    checkpoint_every: 10_000,
+                    admin_token: Some("s3cret".to_string()),
+                    rate_limits: RateLimits {
+                        global: None,
+                        per_client: Some(RateLimit {
+                            per_second: 5.0,
+                            burst: 20.0,
+                        }),
+                    },
+                    read_view_every: Some(500),
+                    webhooks: Webhooks::default(),
+                    log_level: None,
+                    engine: EngineOptions {
+                        duplicate_policy: Some(DuplicatePolicy::Idempotent),
+                        ..EngineOptions::default()
+                    },
+                }))
+            );
+            assert!(matches!(
+                Command::parse(["in.csv".to_string()]),
+                Ok(Command::Process(_))
+            ));
+            assert_eq!(
+                Command::parse(
+                    ["serve", "--state", "state", "--wal", "log.wal"]
+                        .iter()
+                        .map(|arg| arg.to_string())
+                ),
+                Err(CliError::ConflictingFlags("--state", "--wal"))
+            );
+        }
+
+        #[test]
+        fn serve_webhooks_are_merged_with_the_file() {
+            let serve =
+                |args: &[&str]| ServeCli::parse(args.iter().map(|arg| arg.to_string())).unwrap();
+            let file = ConfigFile::from_toml(
+                "[webhooks]\n\
+                 urls = \"http://risk/hooks\"\n\
+                 secret = \"from-file\"\n\
+                 backoff_ms = 250\n",
+            )
+            .unwrap();
+
+            let mut cli = serve(&[
+                "--webhook",
+                "http://localhost:9000/a",
+                "--webhook",
+                "http://localhost:9000/b",
+                "--webhook-attempts",
+                "3",
+            ]);
+            cli.merge(&file).unwrap();
+            assert_eq!(cli.webhooks.urls.len(), 2);
+            assert_eq!(cli.webhooks.urls[1].path, "/b");
+            assert_eq!(cli.webhooks.secret, "from-file");
+            assert_eq!(cli.webhooks.max_attempts, Some(3));
+            assert_eq!(cli.webhooks.backoff, Some(Duration::from_millis(250)));
+
+            assert_eq!(
+                serve(&["--webhook", "http://localhost/hooks"]).merge(&ConfigFile::default()),
+                Err(CliError::RequiresFlag("--webhook", "--webhook-secret"))
+            );
+            assert!(matches!(
+                ServeCli::parse(["--webhook".to_string(), "https://risk/hooks".to_string()]),
+                Err(CliError::InvalidValue { .. })
+            ));
+        }
+
+        #[test]
+        fn gen_data_command_is_parsed() {
+            let args = |args: &[&str]| Command::parse(args.iter().map(|arg| arg.to_string()));
+
+            assert_eq!(
+                args(&[
+                    "gen-data",
+                    "--rows",
+                    "100",
+                    "--clients=5",
+                    "--skew",
+                    "1.5",
+                    "--dispute-rate",
+                    "0.5",
+                    "--seed",
+                    "9",
+                    "-o",
+                    "data.csv",
+                ]),
+                Ok(Command::GenData(GenDataCli {
+                    workload: Workload {
+                        rows: 100,
+                        clients: 5,
+                        skew: 1.5,
+                        dispute_rate: 0.5,
+                        seed: 9,
+                    },
+                    output: Some(PathBuf::from("data.csv")),
+                }))
+            );
+            assert_eq!(
+                args(&["gen-data", "--dispute-rate", "2"]),
+                Err(CliError::InvalidValue {
+                    flag: "--dispute-rate".to_string(),
+                    value: "2".to_string()
+                })
+            );
+        }
+
+        #[test]
+        fn apply_fees_command_is_parsed() {
+            let args = |args: &[&str]| Command::parse(args.iter().map(|arg| arg.to_string()));
+
+            assert_eq!(
+                args(&[
+                    "apply-fees",
+                    "--restore",
+                    "state.snapshot",
+                    "--fees",
+                    "fees.toml",
+                    "--snapshot",
+                    "state.snapshot",
+                    "-f",
+                    "json",
+                    "--duplicates",
+                    "idempotent",
+                ]),
+                Ok(Command::ApplyFees(ApplyFeesCli {
+                    restore: PathBuf::from("state.snapshot"),
+                    fees: Some(PathBuf::from("fees.toml")),
+                    batch: BatchOptions {
+                        snapshot: Some(PathBuf::from("state.snapshot")),
+                        format: Some(OutputFormat::Json),
+                        ..BatchOptions::default()
+                    },
+                    engine: EngineOptions {
+                        duplicate_policy: Some(DuplicatePolicy::Idempotent),
+                        ..EngineOptions::default()
+                    },
+                }))
+            );
+            assert_eq!(
+                args(&["apply-fees", "--fees", "fees.toml"]),
+                Err(CliError::RequiresFlag("apply-fees", "--restore"))
+            );
+        }
+
+        #[test]
+        fn advance_time_command_is_parsed() {
+            let args = |args: &[&str]| Command::parse(args.iter().map(|arg| arg.to_string()));
+
+            assert_eq!(
+                args(&[
+                    "advance-time",
+                    "--restore",
+                    "state.snapshot",
+                    "--schedule",
+                    "orders.csv",
+                    "--ticks",
+                    "30",
+                    "--until",
+                    "2024-03-01",
+                ]),
+                Ok(Command::AdvanceTime(AdvanceTimeCli {
+                    restore: PathBuf::from("state.snapshot"),
+                    schedule: PathBuf::from("orders.csv"),
+                    ticks: 30,
+                    until: Some("2024-03-01".parse().unwrap()),
+                    batch: BatchOptions::default(),
+                    engine: EngineOptions::default(),
+                }))
+            );
+            assert_eq!(
+                args(&["advance-time", "--restore", "state.snapshot"]),
+                Err(CliError::RequiresFlag("advance-time", "--schedule"))
+            );
+        }
+
+        #[test]
+        fn merge_clients_command_is_parsed() {
+            let args = |args: &[&str]| Command::parse(args.iter().map(|arg| arg.to_string()));
+
+            assert_eq!(
+                args(&[
+                    "merge-clients",
+                    "--restore",
+                    "state.snapshot",
+                    "--from",
+                    "7",
+                    "--into=3",
+                    "--snapshot",
+                    "merged.snapshot",
+                ]),
+                Ok(Command::MergeClients(MergeClientsCli {
+                    restore: PathBuf::from("state.snapshot"),
+                    from: 7,
+                    into: 3,
+                    batch: BatchOptions {
+                        snapshot: Some(PathBuf::from("merged.snapshot")),
+                        ..BatchOptions::default()
+                    },
+                    engine: EngineOptions::default(),
+                }))
+            );
+            assert_eq!(
+                args(&[
+                    "merge-clients",
+                    "--restore",
+                    "state.snapshot",
+                    "--from",
+                    "7"
+                ]),
+                Err(CliError::RequiresFlag("merge-clients", "--into"))
+            );
+        }
+
+        #[test]
+        fn diff_command_is_parsed() {
+            let args = |args: &[&str]| Command::parse(args.iter().map(|arg| arg.to_string()));
+
+            assert_eq!(
+                args(&[
+                    "diff",
+                    "before.csv",
+                    "--tolerance",
+                    "0.01",
+                    "after.snapshot",
+                    "-f",
+                    "json",
+                ]),
+                Ok(Command::Diff(DiffCli {
+                    left: PathBuf::from("before.csv"),
+                    right: PathBuf::from("after.snapshot"),
+                    tolerance: 0.01,
+                    format: DiffFormat::Json,
+                    output: None,
+                    config_file: None,
+                }))
+            );
+            assert!(matches!(
+                args(&["diff", "before.csv", "after.csv"]),
+                Ok(Command::Diff(DiffCli {
+                    tolerance: DEFAULT_TOLERANCE,
+                    format: DiffFormat::Text,
+                    ..
+                }))
+            ));
+            assert_eq!(args(&["diff", "before.csv"]), Err(CliError::MissingInput));
+            assert!(args(&["diff", "a.csv", "b.csv", "c.csv"]).is_err());
+            assert!(args(&["diff", "a.csv", "b.csv", "--tolerance", "-1"]).is_err());
+        }
+
+        #[test]
+        fn verify_output_command_is_parsed() {
+            let args = |args: &[&str]| Command::parse(args.iter().map(|arg| arg.to_string()));
+
+            assert_eq!(
+                args(&["verify-output", "out.csv"]),
+                Ok(Command::VerifyOutput(VerifyOutputCli {
+                    path: PathBuf::from("out.csv"),
+                }))
+            );
+            assert_eq!(args(&["verify-output"]), Err(CliError::MissingInput));
+            assert!(args(&["verify-output", "a.csv", "b.csv"]).is_err());
+            assert!(args(&["verify-output", "a.csv", "--format", "json"]).is_err());
+        }
+
+        #[test]
+        fn export_and_import_commands_are_parsed() {
+            let args = |args: &[&str]| Command::parse(args.iter().map(|arg| arg.to_string()));
+
+            assert_eq!(
+                args(&["export", "--restore", "state.snapshot", "--format", "jsonl"]),
+                Ok(Command::Export(ExportCli {
+                    restore: PathBuf::from("state.snapshot"),
+                    format: ExportFormat::Jsonl,
+                    output: None,
+                    config_file: None,
+                }))
+            );
+            assert_eq!(
+                args(&["export", "--format", "jsonl"]),
+                Err(CliError::RequiresFlag("export", "--restore"))
+            );
+            assert!(args(&["export", "--restore", "a", "--format", "csv"]).is_err());
+            assert_eq!(
+                args(&["import", "state.jsonl", "--snapshot", "state.snapshot"]),
+                Ok(Command::Import(ImportCli {
+                    input: PathBuf::from("state.jsonl"),
+                    snapshot: PathBuf::from("state.snapshot"),
+                    config_file: None,
+                }))
+            );
+            assert_eq!(
+                args(&["import", "state.jsonl"]),
+                Err(CliError::RequiresFlag("import", "--snapshot"))
+            );
+            assert_eq!(
+                args(&["import", "--snapshot", "state.snapshot"]),
+                Err(CliError::MissingInput)
+            );
+        }
+
+        #[test]
+        fn analyze_command_is_parsed() {
+            let args = |args: &[&str]| Command::parse(args.iter().map(|arg| arg.to_string()));
+
+            assert_eq!(
+                args(&[
+                    "analyze",
+                    "--restore",
+                    "state.snapshot",
+                    "--top",
+                    "3",
+                    "--currency",
+                    "EUR",
+                    "-f",
+                    "json",
+                ]),
+                Ok(Command::Analyze(AnalyzeCli {
+                    restore: PathBuf::from("state.snapshot"),
+                    top: 3,
+                    currency: Some("EUR".parse().unwrap()),
+                    format: ReportFormat::Json,
+                    config_file: None,
+                }))
+            );
+            assert!(matches!(
+                args(&["analyze", "--restore", "state.snapshot"]),
+                Ok(Command::Analyze(AnalyzeCli {
+                    top: DEFAULT_ANALYZE_TOP,
+                    currency: None,
+                    format: ReportFormat::Text,
+                    ..
+                }))
+            ));
+            assert_eq!(
+                args(&["analyze", "--top", "3"]),
+                Err(CliError::RequiresFlag("analyze", "--restore"))
+            );
+        }
+
+        #[test]
+        fn query_command_is_parsed() {
+            let args = |args: &[&str]| Command::parse(args.iter().map(|arg| arg.to_string()));
+
+            assert_eq!(
+                args(&[
+                    "query",
+                    "--restore",
+                    "state.snapshot",
+                    "--client",
+                    "7",
+                    "--recent",
+                    "3",
+                    "--as-of-tx",
+                    "12",
+                    "-f",
+                    "json",
+                ]),
+                Ok(Command::Query(QueryCli {
+                    restore: PathBuf::from("state.snapshot"),
+                    client_id: 7,
+                    recent: 3,
+                    as_of: Some(TransactionId(12)),
+                    format: ReportFormat::Json,
+                    config_file: None,
+                }))
+            );
+            assert!(matches!(
+                args(&["query", "--restore", "state.snapshot", "--client", "7"]),
+                Ok(Command::Query(QueryCli {
+                    recent: DEFAULT_QUERY_RECENT,
+                    as_of: None,
+                    format: ReportFormat::Text,
+                    ..
+                }))
+            ));
+            assert_eq!(
+                args(&["query", "--restore", "state.snapshot"]),
+                Err(CliError::RequiresFlag("query", "--client"))
+            );
+        }
+
+        #[test]
+        fn repl_command_is_parsed() {
+            let args = |args: &[&str]| Command::parse(args.iter().map(|arg| arg.to_string()));
+
+            assert_eq!(
+                args(&["repl", "--restore", "state.snapshot", "--admin"]),
+                Ok(Command::Repl(ReplCli {
+                    restore: Some(PathBuf::from("state.snapshot")),
+                    admin: true,
+                    log_level: None,
+                    engine: EngineOptions::default(),
+                }))
+            );
+            assert!(matches!(
+                args(&["repl"]),
+                Ok(Command::Repl(ReplCli {
+                    restore: None,
+                    admin: false,
+                    ..
+                }))
+            ));
+            assert_eq!(
+                args(&["repl", "in.csv"]),
+                Err(CliError::UnexpectedArgument("in.csv".to_string()))
+            );
+        }
+
+        #[test]
+        fn statements_command_is_parsed() {
+            let args = |args: &[&str]| Command::parse(args.iter().map(|arg| arg.to_string()));
+
+            assert_eq!(
+                args(&[
+                    "statements",
+                    "--restore",
+                    "state.snapshot",
+                    "--output-dir",
+                    "statements",
+                    "--after-tx",
+                    "12",
+                    "--client",
+                    "7",
+                    "-f",
+                    "text",
+                ]),
+                Ok(Command::Statements(StatementsCli {
+                    restore: PathBuf::from("state.snapshot"),
+                    output_dir: PathBuf::from("statements"),
+                    after: Some(TransactionId(12)),
+                    client_id: Some(7),
+                    format: StatementFormat::Text,
+                    config_file: None,
+                }))
+            );
+            assert_eq!(
+                args(&["statements", "--restore", "state.snapshot"]),
+                Err(CliError::RequiresFlag("statements", "--output-dir"))
+            );
+        }
+
+        #[test]
+        fn flags_take_precedence_over_config_file() {
+            let file = ConfigFile::from_toml(
+                "[engine]\n\
+                 duplicates = \"idempotent\"\n\
+                 dispute_policy = \"deposits-only\"\n\
+                 [io]\n\
+                 format = \"json\"\n\
+                 strict = true\n\
+                 read_ahead = 8\n\
+                 max_decimals = 2\n\
+                 max_amount = 100\n\
+                 precision = \"truncate\"\n\
+                 amount_format = \",\"\n\
+                 [columns]\n\
+                 tx = \"id\"\n\
+                 client = \"client_id\"\n",
+            )
+            .unwrap();
+            let mut cli = parse(&[
+                "--format",
+                "csv",
+                "--duplicates",
+                "reject",
+                "--map",
+                "tx=transaction_id",
+                "--metadata",
+                "merchant, memo",
+                "--max-decimals",
+                "3",
+                "in.csv",
+            ])
+            .unwrap();
+            cli.merge(&file).unwrap();
+
+            assert_eq!(cli.format, Some(OutputFormat::Csv));
+            let mut expected = columns(&[("client", "client_id"), ("tx", "transaction_id")]);
+            expected.capture("memo").unwrap();
+            expected.capture("merchant").unwrap();
+            assert_eq!(cli.columns, expected);
+            assert!(matches!(
+                parse(&["--metadata", "merchant,amount", "in.csv"]),
+                Err(CliError::InvalidValue { .. })
+            ));
+            assert!(cli.strict);
+            assert_eq!(cli.read_ahead, Some(8));
+            assert_eq!(cli.pipeline(), None);
+            assert_eq!(
+                cli.amounts(),
+                AmountRules {
+                    max_decimals: 3,
+                    max_amount: 100.0,
+                    precision: PrecisionPolicy::Truncate,
+                    format: AmountFormat {
+                        decimal_separator: ',',
+                        grouping_separator: None,
+                    },
+                }
+            );
+            let config = cli.engine.config(&file).unwrap();
+            assert_eq!(config.duplicate_policy, DuplicatePolicy::Reject);
+            assert_eq!(config.dispute_policy, DisputePolicy::DepositsOnly);
+
+            let threads = ConfigFile::from_toml("[io]\nthreads = 2\n").unwrap();
+            let mut cli = parse(&["--restore", "state.snapshot", "in.csv"]).unwrap();
+            assert_eq!(
+                cli.merge(&threads),
+                Err(CliError::ConflictingFlags("--threads", "--restore"))
+            );
+        }
+    }
+}
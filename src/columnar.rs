@@ -0,0 +1,235 @@
+//! Transactions laid out in columns, for callers that already hold their data in Arrow record
+//! batches or Polars frames and would otherwise format it as csv only for the engine to parse it
+//! back. A [`ColumnBatch`] borrows the buffers of the columns as they are: the values of an Arrow
+//! `PrimitiveArray` are a plain slice and its nulls a bitmap in the layout [`Column`] reads, so
+//! that no row is copied or deserialized on the way. Columns are plain slices rather than arrow
+//! types, so that callers of any Arrow implementation hand over the buffers they hold.
+//!
+//! Transactions of a batch are in the default currency.
+
+use crate::account::ClientId;
+use crate::error::InputError;
+use crate::input::{AmountRules, TransactionSource};
+use crate::transaction::{Transaction, TransactionId, TransactionType};
+
+/// Values of a column with the nulls of an Arrow array: bit `i % 8` of byte `i / 8` of the
+/// validity bitmap is set when row `i` has a value. Without bitmap every row has one.
+#[derive(Debug, Clone, Copy)]
+pub struct Column<'a, T> {
+    values: &'a [T],
+    validity: Option<&'a [u8]>,
+}
+
+impl<'a, T: Copy> Column<'a, T> {
+    /// Column without nulls
+    pub fn new(values: &'a [T]) -> Self {
+        Column {
+            values,
+            validity: None,
+        }
+    }
+
+    /// Column whose rows are null where the bit of `validity` is not set
+    pub fn with_validity(values: &'a [T], validity: &'a [u8]) -> Self {
+        Column {
+            values,
+            validity: Some(validity),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Value of the row, `None` if it is null or past the end of the column
+    pub fn get(&self, row: usize) -> Option<T> {
+        let valid = self.validity.is_none_or(|validity| {
+            validity
+                .get(row / 8)
+                .is_some_and(|byte| byte & (1 << (row % 8)) != 0)
+        });
+        self.values.get(row).copied().filter(|_| valid)
+    }
+}
+
+impl<'a, T: Copy> From<&'a [T]> for Column<'a, T> {
+    fn from(values: &'a [T]) -> Self {
+        Column::new(values)
+    }
+}
+
+/// Rows of transactions split into one column per field, as in the csv header. Types come as
+/// [`TransactionType`] values, which a dictionary encoded Arrow column maps to once per key.
+#[derive(Debug, Clone, Copy)]
+pub struct ColumnBatch<'a> {
+    pub types: Column<'a, TransactionType>,
+    pub clients: Column<'a, ClientId>,
+    pub transactions: Column<'a, TransactionId>,
+    /// Null for transactions without amount such as disputes
+    pub amounts: Column<'a, f64>,
+    /// Credited client of transfers, all null if not given
+    pub to_clients: Option<Column<'a, ClientId>>,
+}
+
+impl<'a> ColumnBatch<'a> {
+    /// Rows of the batch, the longest of its columns
+    pub fn rows(&self) -> usize {
+        [
+            self.types.len(),
+            self.clients.len(),
+            self.transactions.len(),
+            self.amounts.len(),
+            self.to_clients.map_or(0, |column| column.len()),
+        ]
+        .into_iter()
+        .max()
+        .unwrap_or(0)
+    }
+
+    /// Source of the transactions of the batch in row order. Rows missing a type, client or
+    /// transaction id are malformed, as are those whose amount breaks `amounts`.
+    pub fn source(&self, amounts: AmountRules) -> ColumnSource<'a> {
+        ColumnSource {
+            batch: *self,
+            row: 0,
+            amounts,
+        }
+    }
+}
+
+/// Transactions of a [`ColumnBatch`], see [`ColumnBatch::source`]
+pub struct ColumnSource<'a> {
+    batch: ColumnBatch<'a>,
+    row: usize,
+    amounts: AmountRules,
+}
+
+impl ColumnSource<'_> {
+    fn transaction(&self, row: usize) -> Result<Transaction, String> {
+        let batch = &self.batch;
+        let missing = |column: &str| format!("row has no {}", column);
+        let transaction_type = batch.types.get(row).ok_or_else(|| missing("type"))?;
+        Ok(Transaction {
+            transaction_type,
+            client_id: batch.clients.get(row).ok_or_else(|| missing("client"))?,
+            transaction_id: batch.transactions.get(row).ok_or_else(|| missing("tx"))?,
+            amount: self
+                .amounts
+                .amount(transaction_type, batch.amounts.get(row))?,
+            to_client_id: batch.to_clients.and_then(|column| column.get(row)),
+            currency: None,
+            timestamp: None,
+            metadata: Default::default(),
+        })
+    }
+}
+
+impl TransactionSource for ColumnSource<'_> {
+    fn next_transaction(&mut self) -> Option<Result<Transaction, InputError>> {
+        if self.row >= self.batch.rows() {
+            return None;
+        }
+        let row = self.row;
+        self.row += 1;
+        Some(
+            self.transaction(row)
+                .map_err(|message| InputError::Malformed {
+                    line: row as u64 + 1,
+                    message,
+                    row: String::new(),
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::columnar::{Column, ColumnBatch};
+        use crate::engine::TransactionEngine;
+        use crate::error::InputError;
+        use crate::input::TransactionSource;
+        use crate::transaction::{TransactionId, TransactionType};
+
+        #[test]
+        fn batches_apply_their_rows_in_order() {
+            let types = [
+                TransactionType::Deposit,
+                TransactionType::Deposit,
+                TransactionType::Dispute,
+                TransactionType::Withdrawal,
+                TransactionType::Transfer,
+                TransactionType::Deposit,
+            ];
+            let amounts = [10.0, 4.0, f64::NAN, 20.0, 1.5, 1.23456];
+            let to_clients = [0, 0, 0, 0, 2, 0];
+            let batch = ColumnBatch {
+                types: Column::new(&types),
+                clients: Column::new(&[1, 2, 2, 1, 1, 3]),
+                transactions: Column::new(&[
+                    TransactionId(1),
+                    TransactionId(2),
+                    TransactionId(2),
+                    TransactionId(3),
+                    TransactionId(4),
+                    TransactionId(5),
+                ]),
+                // The dispute has no amount
+                amounts: Column::with_validity(&amounts, &[0b111011]),
+                to_clients: Some(Column::with_validity(&to_clients, &[0b010000])),
+            };
+            let mut engine = TransactionEngine::new();
+
+            let report = engine.execute_columns(&batch).unwrap();
+
+            assert_eq!(report.rows_read, 6);
+            assert_eq!(report.rejected.len(), 1);
+            assert!(report.rejected[0]
+                .to_string()
+                .starts_with("client 1: transaction 3: insufficient funds"));
+            assert!(matches!(
+                report.malformed[..],
+                [InputError::Malformed { line: 6, .. }]
+            ));
+            assert_eq!(engine.accounts[&1].get_available_funds(), 8.5);
+            let client_2 = &engine.accounts[&2];
+            assert_eq!(client_2.get_available_funds(), 1.5);
+            assert_eq!(client_2.get_held_funds(), 4.0);
+            assert!(!engine.accounts.contains_key(&3));
+        }
+
+        #[test]
+        fn rows_missing_a_required_field_are_malformed() {
+            let batch = ColumnBatch {
+                types: Column::new(&[TransactionType::Deposit; 3]),
+                clients: Column::with_validity(&[1, 1, 1], &[0b101]),
+                transactions: Column::new(&[TransactionId(1), TransactionId(2)]),
+                amounts: Column::new(&[1.0, 1.0, 1.0]),
+                to_clients: None,
+            };
+            let mut source = batch.source(Default::default());
+            let mut results = Vec::new();
+            while let Some(result) = source.next_transaction() {
+                results.push(result.map_err(|err| err.to_string()));
+            }
+
+            assert_eq!(results.len(), 3);
+            assert_eq!(
+                results[0].as_ref().unwrap().transaction_id,
+                TransactionId(1)
+            );
+            assert_eq!(
+                results[1].as_ref().unwrap_err(),
+                "malformed row on line 2: row has no client"
+            );
+            assert_eq!(
+                results[2].as_ref().unwrap_err(),
+                "malformed row on line 3: row has no tx"
+            );
+        }
+    }
+}
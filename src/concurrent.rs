@@ -0,0 +1,195 @@
+//! Engine handle shared by the threads of a multithreaded service.
+
+use crate::account::{AccountView, ClientId};
+use crate::engine::{EngineConfig, TransactionEngine};
+use crate::error::EngineError;
+use crate::output::AccountSnapshot;
+use crate::processor::TransactionProcessor;
+use crate::sharded::ShardRouter;
+use crate::transaction::{Origin, Transaction};
+use std::sync::{Mutex, MutexGuard};
+
+/// Engine that can be shared between threads, e.g. behind an `Arc`, without any locking of its
+/// own. Clients are partitioned across shards like in a
+/// [`ShardedEngine`](crate::sharded::ShardedEngine), each shard behind its own lock, so that
+/// transactions of clients on different shards are executed in parallel. Shards are mutexes
+/// rather than read-write locks since accounts are only `Send`.
+///
+/// Transfers between clients of different shards are rejected, a single shard behaves exactly
+/// as a [`TransactionEngine`].
+pub struct ConcurrentEngine {
+    shards: Vec<Mutex<TransactionEngine>>,
+    router: Mutex<ShardRouter>,
+}
+
+impl ConcurrentEngine {
+    pub fn new(shards: usize, config: EngineConfig) -> Self {
+        Self::from_engines(
+            (0..shards.max(1))
+                .map(|_| TransactionEngine::with_config(config.clone()))
+                .collect(),
+        )
+    }
+
+    /// Engine with one shard per given engine, which are expected to be new
+    pub(crate) fn from_engines(engines: Vec<TransactionEngine>) -> Self {
+        ConcurrentEngine {
+            router: Mutex::new(ShardRouter::new(engines.len())),
+            shards: engines.into_iter().map(Mutex::new).collect(),
+        }
+    }
+
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Executes the transaction on the shard of its client, blocking while another thread uses
+    /// that shard
+    pub fn submit(&self, transaction: Transaction) -> Result<(), EngineError> {
+        self.submit_from(transaction, Origin::Client)
+    }
+
+    /// Executes a transaction from the given source, see [`TransactionEngine::execute_from`]
+    pub fn submit_from(&self, transaction: Transaction, origin: Origin) -> Result<(), EngineError> {
+        let shard = self
+            .router
+            .lock()
+            .expect("Router lock poisoned")
+            .route(&transaction)?;
+        self.shard(shard).execute_from(transaction, origin)
+    }
+
+    /// Funds and lock of the client's account at the time of the call
+    pub fn account(&self, client_id: ClientId) -> Option<AccountView> {
+        let shard = self
+            .router
+            .lock()
+            .expect("Router lock poisoned")
+            .shard_of(client_id);
+        self.shard(shard)
+            .accounts
+            .get(&client_id)
+            .map(|account| AccountView::new(account.as_ref()))
+    }
+
+    /// Merges the shards into a single engine, e.g. to write its accounts or snapshot it
+    pub fn into_engine(self) -> TransactionEngine {
+        let mut shards = self
+            .shards
+            .into_iter()
+            .map(|shard| shard.into_inner().expect("Engine lock poisoned"));
+        let mut merged = shards
+            .next()
+            .expect("Concurrent engine has at least one shard");
+        for engine in shards {
+            merged.absorb(engine);
+        }
+        merged
+    }
+
+    fn shard(&self, shard: usize) -> MutexGuard<'_, TransactionEngine> {
+        self.shards[shard].lock().expect("Engine lock poisoned")
+    }
+}
+
+impl TransactionProcessor for ConcurrentEngine {
+    fn execute_from(
+        &mut self,
+        transaction: Transaction,
+        origin: Origin,
+    ) -> Result<(), EngineError> {
+        self.submit_from(transaction, origin)
+    }
+
+    fn account_snapshot(&self, client_id: ClientId) -> Option<AccountSnapshot> {
+        let shard = self
+            .router
+            .lock()
+            .expect("Router lock poisoned")
+            .shard_of(client_id);
+        self.shard(shard).account_snapshot(client_id)
+    }
+
+    /// Funds of the accounts of each shard at the time it is read
+    fn iter_snapshots(&self) -> Box<dyn Iterator<Item = AccountSnapshot> + '_> {
+        Box::new(
+            (0..self.shards())
+                .flat_map(|shard| self.shard(shard).iter_snapshots().collect::<Vec<_>>()),
+        )
+    }
+
+    fn finalize(self) -> (TransactionEngine, Vec<EngineError>) {
+        (self.into_engine(), Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::account::ClientId;
+        use crate::concurrent::ConcurrentEngine;
+        use crate::engine::EngineConfig;
+        use crate::error::EngineError;
+        use crate::transaction::{transaction, TransactionId, TransactionType};
+        use std::sync::Arc;
+        use std::thread;
+
+        #[test]
+        fn transactions_are_submitted_from_many_threads() {
+            let engine = Arc::new(ConcurrentEngine::new(4, EngineConfig::default()));
+            let workers: Vec<_> = (0..8u8)
+                .map(|worker| {
+                    let engine = Arc::clone(&engine);
+                    let client_id = ClientId::from(worker);
+                    thread::spawn(move || {
+                        for i in 0..100 {
+                            let transaction_id = u32::from(worker) * 1000 + i;
+                            engine
+                                .submit(transaction(
+                                    TransactionType::Deposit,
+                                    client_id,
+                                    transaction_id,
+                                    Some(1.0),
+                                ))
+                                .unwrap();
+                        }
+                    })
+                })
+                .collect();
+            for worker in workers {
+                worker.join().unwrap();
+            }
+
+            let account = engine.account(3).unwrap();
+            assert_eq!(account.balance(None).available, 100.0);
+            assert!(!account.locked);
+            assert!(engine.account(9).is_none());
+            // Duplicates are caught even when submitted for another client
+            assert!(matches!(
+                engine.submit(transaction(TransactionType::Deposit, 5, 3000, Some(1.0))),
+                Err(EngineError::DuplicateTransaction(TransactionId(3000)))
+            ));
+
+            let engine = Arc::into_inner(engine).unwrap().into_engine();
+            assert_eq!(engine.accounts.len(), 8);
+        }
+
+        #[test]
+        fn disputes_reach_the_shard_of_the_disputed_transaction() {
+            let engine = ConcurrentEngine::new(3, EngineConfig::default());
+            engine
+                .submit(transaction(TransactionType::Deposit, 1, 1, Some(2.0)))
+                .unwrap();
+            engine
+                .submit(transaction(TransactionType::Dispute, 1, 1, None))
+                .unwrap();
+            engine
+                .submit(transaction(TransactionType::Chargeback, 1, 1, None))
+                .unwrap();
+
+            let account = engine.account(1).unwrap();
+            assert_eq!(account.balance(None).total(), 0.0);
+            assert!(account.locked);
+        }
+    }
+}
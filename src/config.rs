@@ -0,0 +1,596 @@
+//! Optional `engine.toml` file holding the engine policies and I/O settings, so that deployments
+//! don't need long command lines. Every setting can be left out and command line flags take
+//! precedence over the file.
+//!
+//! ```toml
+//! [engine]
+//! allow_locked_deposits = false
+//! unlock_on_representment = false       # once every chargeback was represented
+//! allow_adjustments = false              # manual adjustments from admin input
+//! duplicates = "idempotent"              # reject or idempotent
+//! negative_balance = "hold-partial"      # allow, reject-dispute, hold-partial or hold-pending
+//! dispute_policy = "deposits-only"       # reverse-withdrawals or deposits-only
+//! max_dispute_cycles = 2                 # disputes of the same funds, 1 by default
+//! dispute_window_days = 90               # for transactions with a timestamp
+//! dispute_expiry_transactions = 10_000   # resolve disputes left open that long
+//! dispute_expiry_days = 30               # for disputes with a timestamp
+//! record_history = false
+//! retention = "per-client:1000"          # unbounded, per-client:<N> or global:<N>
+//!
+//! [limits]
+//! max_transaction_amount = 10_000
+//! daily_withdrawal_cap = 2_500
+//! max_transactions_per_client = 1_000
+//!
+//! [risk]                                 # see the risk module for every rule
+//! rapid_withdrawals = true
+//! disputed_deposits = true
+//! near_limit = true
+//! near_limit_threshold = 10_000
+//!
+//! [accounts]                             # basic unless given
+//! default = "basic"                      # basic, overdraft or savings
+//! 7 = "overdraft"                        # type of the account of client 7
+//!
+//! [overdraft]                            # of overdraft accounts
+//! limit = 500
+//! fee = 25
+//!
+//! [savings]                              # of savings accounts
+//! withdrawal_delay = 3                   # ticks until withdrawals settle
+//!
+//! [tiers]                                # retail unless given, see the tier module
+//! default = "retail"                     # retail, business or internal
+//! client_master = "clients.csv"          # client,tier rows
+//! 7 = "business"                         # tier of client 7
+//!
+//! [business]                             # policies of the tier, also [retail] and [internal]
+//! account = "overdraft"
+//! overdraft_limit = 5_000
+//! max_transaction_amount = 50_000
+//! negative_balance = "reject-dispute"
+//!
+//! [rate_limits]                          # of the server, see the throttle module
+//! global = 1_000                         # transactions per second
+//! per_client = 10
+//!
+//! [webhooks]                             # of the server, see the webhook module
+//! urls = "http://risk.internal:8080/hooks"
+//! secret = "shared-secret"
+//!
+//! [fees]                                 # applied by the apply-fees command
+//! maintenance_fee = 1.5
+//! waive_fee_above = 1_000
+//! interest_rate = 0.0001
+//!
+//! [io]
+//! format = "json"                        # csv, json, jsonl or table
+//! sort_output = "client"                 # client or none
+//! strict = true
+//! read_ahead = 1024
+//! parse_queue = 1024                     # pipeline queue capacities, instead of read_ahead
+//! validate_queue = 256
+//! ordering = "reorder:64"                # unordered, strict or reorder:<N>
+//! threads = 4
+//! max_decimals = 4                       # of input amounts, 4 by default
+//! max_amount = 1_000_000                 # larger input amounts are malformed, 10^12 by default
+//! precision = "round-half-even"          # of overly precise input amounts, reject by default
+//! amount_format = "de"                   # input amounts written as 1.234,56, plain by default
+//! fast_parse = true                      # read csv input without serde
+//! unordered = true                       # read the files of a directory in parallel
+//! parallel_read = false                  # same, applying their rows in file order
+//! namespace_sources = true               # give the transactions of every input file own ids
+//! audit_log = "audit.jsonl"
+//! journal = "books.beancount"            # double-entry journal of the applied transactions
+//! window_dir = "windows"                 # process the input as a stream of windows
+//! window_size = 10_000                   # transactions of a window
+//! window_secs = 60                       # seconds a window is open at most
+//! rejects_file = "rejects.csv"
+//! disputes_output = "disputes.csv"
+//! report_file = "report.txt"
+//! log_level = "info"
+//!
+//! [columns]                              # input names of the columns, the same by default
+//! tx = "transaction_id"
+//! client = "client_id"
+//! ```
+
+use crate::account::{AccountFactory, AccountTypes};
+use crate::engine::EngineConfig;
+use crate::error::ConfigError;
+use crate::fees::FeeSchedule;
+use crate::input::{AmountFormat, ColumnMapping, InputOrdering};
+use crate::log::Level;
+use crate::output::{OutputFormat, OutputOrder};
+use crate::policy::{
+    DisputeCycles, DisputeExpiry, DisputePolicy, DuplicatePolicy, LimitsPolicy, LockPolicy,
+    NegativeBalancePolicy, OverdraftPolicy, PrecisionPolicy, RetentionPolicy, SavingsPolicy,
+};
+use crate::risk::RiskPolicy;
+use crate::throttle::RateLimits;
+use crate::tier::{Tier, TierPolicy, Tiers};
+use crate::toml::{self, Entry};
+use crate::transaction::Amount;
+use crate::webhook::Webhooks;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Read from the working directory when no configuration file is given
+pub const DEFAULT_CONFIG_FILE: &str = "engine.toml";
+
+/// Contents of a configuration file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigFile {
+    /// `[engine]` table
+    pub engine: EngineSettings,
+    /// `[limits]` table
+    pub limits: LimitsPolicy,
+    /// `[risk]` table
+    pub risk: RiskPolicy,
+    /// `[accounts]`, `[overdraft]` and `[savings]` tables
+    pub accounts: AccountTypes,
+    /// `[tiers]` table and the tables named after each tier
+    pub tiers: Tiers,
+    /// `[fees]` table
+    pub fees: FeeSchedule,
+    /// `[rate_limits]` table
+    pub rate_limits: RateLimits,
+    /// `[webhooks]` table
+    pub webhooks: Webhooks,
+    /// `[io]` table
+    pub io: IoSettings,
+    /// `[columns]` table
+    pub columns: ColumnMapping,
+}
+
+/// Policies of the engine, `None` where the file leaves the default
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EngineSettings {
+    pub lock_policy: Option<LockPolicy>,
+    pub unlock_on_representment: Option<bool>,
+    pub allow_adjustments: Option<bool>,
+    pub duplicate_policy: Option<DuplicatePolicy>,
+    pub negative_balance_policy: Option<NegativeBalancePolicy>,
+    pub dispute_policy: Option<DisputePolicy>,
+    pub max_dispute_cycles: Option<DisputeCycles>,
+    pub dispute_window: Option<u32>,
+    pub dispute_expiry_transactions: Option<u64>,
+    pub dispute_expiry_days: Option<u32>,
+    pub retention_policy: Option<RetentionPolicy>,
+    pub record_history: Option<bool>,
+}
+
+/// Where transactions are read from and results written to, `None` where not set
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IoSettings {
+    pub format: Option<OutputFormat>,
+    pub sort_output: Option<OutputOrder>,
+    pub strict: Option<bool>,
+    pub read_ahead: Option<usize>,
+    pub parse_queue: Option<usize>,
+    pub validate_queue: Option<usize>,
+    pub ordering: Option<InputOrdering>,
+    pub max_decimals: Option<u32>,
+    pub max_amount: Option<f64>,
+    pub precision: Option<PrecisionPolicy>,
+    pub amount_format: Option<AmountFormat>,
+    pub fast_parse: Option<bool>,
+    pub threads: Option<usize>,
+    pub unordered: Option<bool>,
+    pub parallel_read: Option<bool>,
+    pub namespace_sources: Option<bool>,
+    pub audit_log: Option<PathBuf>,
+    pub journal: Option<PathBuf>,
+    pub window_dir: Option<PathBuf>,
+    pub window_size: Option<usize>,
+    pub window_secs: Option<usize>,
+    pub rejects_file: Option<PathBuf>,
+    pub disputes_output: Option<PathBuf>,
+    pub report_file: Option<PathBuf>,
+    pub log_level: Option<Level>,
+}
+
+impl ConfigFile {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+
+    /// Loads the file at `path` if given, otherwise [`DEFAULT_CONFIG_FILE`] if it exists
+    pub fn discover(path: Option<&Path>) -> Result<Self, ConfigError> {
+        match path {
+            Some(path) => Self::load(path),
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => Self::load(DEFAULT_CONFIG_FILE),
+            None => Ok(ConfigFile::default()),
+        }
+    }
+
+    pub fn from_toml(input: &str) -> Result<Self, ConfigError> {
+        let mut config = ConfigFile::default();
+        for (name, table) in toml::parse(input)? {
+            match name.as_str() {
+                "engine" => config.engine = EngineSettings::from_table(&table)?,
+                "limits" => config.limits = LimitsPolicy::from_table(&table)?,
+                "risk" => config.risk = RiskPolicy::from_table(&table)?,
+                "fees" => config.fees = FeeSchedule::from_table(&table)?,
+                "rate_limits" => config.rate_limits = RateLimits::from_table(&table)?,
+                "webhooks" => config.webhooks = Webhooks::from_table(&table)?,
+                "accounts" => config.accounts.read_table(&table)?,
+                "overdraft" => config.accounts.overdraft = OverdraftPolicy::from_table(&table)?,
+                "savings" => config.accounts.savings = SavingsPolicy::from_table(&table)?,
+                "tiers" => config.tiers.read_table(&table)?,
+                "io" => config.io = IoSettings::from_table(&table)?,
+                "columns" => config.columns = ColumnMapping::from_table(&table)?,
+                // Keys before the first header
+                "" => {
+                    if let Some((key, entry)) = table.entries.iter().next() {
+                        return Err(entry.invalid(format!("key '{}' is outside of a table", key)));
+                    }
+                }
+                _ => match name.parse::<Tier>() {
+                    Ok(tier) => {
+                        let policy = TierPolicy::from_table(&name, &table)?;
+                        config.tiers.policies.insert(tier, policy);
+                    }
+                    Err(_) => {
+                        return Err(ConfigError::Invalid {
+                            line: table.line,
+                            message: format!("unknown table '{}'", name),
+                        })
+                    }
+                },
+            }
+        }
+        Ok(config)
+    }
+
+    /// Engine configuration described by the file, with defaults for what it leaves out
+    pub fn engine_config(&self) -> EngineConfig {
+        let defaults = EngineConfig::default();
+        let engine = &self.engine;
+        let tiers = Arc::new(self.tiers.clone());
+        EngineConfig {
+            lock_policy: engine.lock_policy.unwrap_or(defaults.lock_policy),
+            unlock_on_representment: engine
+                .unlock_on_representment
+                .unwrap_or(defaults.unlock_on_representment),
+            allow_adjustments: engine
+                .allow_adjustments
+                .unwrap_or(defaults.allow_adjustments),
+            duplicate_policy: engine.duplicate_policy.unwrap_or(defaults.duplicate_policy),
+            negative_balance_policy: engine
+                .negative_balance_policy
+                .unwrap_or(defaults.negative_balance_policy),
+            dispute_policy: engine.dispute_policy.unwrap_or(defaults.dispute_policy),
+            max_dispute_cycles: engine
+                .max_dispute_cycles
+                .unwrap_or(defaults.max_dispute_cycles),
+            dispute_window: engine.dispute_window.or(defaults.dispute_window),
+            dispute_expiry: DisputeExpiry {
+                after_transactions: engine
+                    .dispute_expiry_transactions
+                    .or(defaults.dispute_expiry.after_transactions),
+                after_days: engine
+                    .dispute_expiry_days
+                    .or(defaults.dispute_expiry.after_days),
+            },
+            limits: self.limits,
+            risk: self.risk,
+            retention_policy: engine.retention_policy.unwrap_or(defaults.retention_policy),
+            record_history: engine.record_history.unwrap_or(defaults.record_history),
+            tiers: Arc::clone(&tiers),
+            // Every account is basic unless the file or the tiers say otherwise
+            account_factory: (self.accounts != AccountTypes::default()
+                || self.tiers.shapes_accounts())
+            .then(|| {
+                Arc::new(AccountTypes {
+                    tiers,
+                    ..self.accounts.clone()
+                }) as Arc<dyn AccountFactory>
+            }),
+            ..defaults
+        }
+    }
+}
+
+impl EngineSettings {
+    fn from_table(table: &toml::Table) -> Result<Self, ConfigError> {
+        let mut settings = EngineSettings::default();
+        for (key, entry) in &table.entries {
+            match key.as_str() {
+                "allow_locked_deposits" => {
+                    settings.lock_policy = Some(if entry.as_bool(key)? {
+                        LockPolicy::AllowDeposits
+                    } else {
+                        LockPolicy::RejectAll
+                    })
+                }
+                "unlock_on_representment" => {
+                    settings.unlock_on_representment = Some(entry.as_bool(key)?)
+                }
+                "allow_adjustments" => settings.allow_adjustments = Some(entry.as_bool(key)?),
+                "duplicates" => settings.duplicate_policy = Some(entry.parse(key)?),
+                "negative_balance" => settings.negative_balance_policy = Some(entry.parse(key)?),
+                "dispute_policy" => settings.dispute_policy = Some(entry.parse(key)?),
+                "max_dispute_cycles" => {
+                    let cycles = positive_count(key, entry)?;
+                    settings.max_dispute_cycles =
+                        Some(DisputeCycles(u32::try_from(cycles).unwrap_or(u32::MAX)))
+                }
+                "dispute_window_days" => settings.dispute_window = Some(days(key, entry)?),
+                "dispute_expiry_transactions" => {
+                    settings.dispute_expiry_transactions = Some(positive_count(key, entry)? as u64)
+                }
+                "dispute_expiry_days" => settings.dispute_expiry_days = Some(days(key, entry)?),
+                "retention" => settings.retention_policy = Some(entry.parse(key)?),
+                "record_history" => settings.record_history = Some(entry.as_bool(key)?),
+                _ => return Err(unknown_key("engine", key, entry)),
+            }
+        }
+        Ok(settings)
+    }
+}
+
+impl IoSettings {
+    fn from_table(table: &toml::Table) -> Result<Self, ConfigError> {
+        let mut settings = IoSettings::default();
+        for (key, entry) in &table.entries {
+            match key.as_str() {
+                "format" => settings.format = Some(entry.parse(key)?),
+                "sort_output" => settings.sort_output = Some(entry.parse(key)?),
+                "strict" => settings.strict = Some(entry.as_bool(key)?),
+                "read_ahead" => settings.read_ahead = Some(positive_count(key, entry)?),
+                "parse_queue" => settings.parse_queue = Some(positive_count(key, entry)?),
+                "validate_queue" => settings.validate_queue = Some(positive_count(key, entry)?),
+                "ordering" => settings.ordering = Some(entry.parse(key)?),
+                "max_decimals" => {
+                    settings.max_decimals = Some(
+                        u32::try_from(entry.as_count(key)?)
+                            .ok()
+                            .filter(|decimals| *decimals <= Amount::MAX_DECIMALS)
+                            .ok_or_else(|| {
+                                entry.invalid(format!(
+                                    "'{}' is above {}",
+                                    key,
+                                    Amount::MAX_DECIMALS
+                                ))
+                            })?,
+                    )
+                }
+                "max_amount" => match entry.as_number(key)? {
+                    amount if amount > 0.0 => settings.max_amount = Some(amount),
+                    _ => return Err(entry.invalid(format!("'{}' must be positive", key))),
+                },
+                "precision" => settings.precision = Some(entry.parse(key)?),
+                "amount_format" => settings.amount_format = Some(entry.parse(key)?),
+                "fast_parse" => settings.fast_parse = Some(entry.as_bool(key)?),
+                "threads" => settings.threads = Some(positive_count(key, entry)?),
+                "unordered" => settings.unordered = Some(entry.as_bool(key)?),
+                "parallel_read" => settings.parallel_read = Some(entry.as_bool(key)?),
+                "namespace_sources" => settings.namespace_sources = Some(entry.as_bool(key)?),
+                "audit_log" => settings.audit_log = Some(PathBuf::from(entry.as_str(key)?)),
+                "journal" => settings.journal = Some(PathBuf::from(entry.as_str(key)?)),
+                "window_dir" => settings.window_dir = Some(PathBuf::from(entry.as_str(key)?)),
+                "window_size" => settings.window_size = Some(positive_count(key, entry)?),
+                "window_secs" => settings.window_secs = Some(positive_count(key, entry)?),
+                "rejects_file" => settings.rejects_file = Some(PathBuf::from(entry.as_str(key)?)),
+                "disputes_output" => {
+                    settings.disputes_output = Some(PathBuf::from(entry.as_str(key)?))
+                }
+                "report_file" => settings.report_file = Some(PathBuf::from(entry.as_str(key)?)),
+                "log_level" => settings.log_level = Some(entry.parse(key)?),
+                _ => return Err(unknown_key("io", key, entry)),
+            }
+        }
+        Ok(settings)
+    }
+}
+
+fn days(key: &str, entry: &Entry) -> Result<u32, ConfigError> {
+    u32::try_from(entry.as_count(key)?)
+        .map_err(|_| entry.invalid(format!("'{}' is too large", key)))
+}
+
+fn positive_count(key: &str, entry: &Entry) -> Result<usize, ConfigError> {
+    match entry.as_count(key)? {
+        0 => Err(entry.invalid(format!("'{}' must be at least 1", key))),
+        count => Ok(count as usize),
+    }
+}
+
+fn unknown_key(table: &str, key: &str, entry: &Entry) -> ConfigError {
+    entry.invalid(format!("unknown key '{}' in [{}]", key, table))
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::account::AccountTypes;
+        use crate::config::{ConfigFile, IoSettings};
+        use crate::error::ConfigError;
+        use crate::input::{AmountFormat, InputOrdering};
+        use crate::log::Level;
+        use crate::output::{OutputFormat, OutputOrder};
+        use crate::policy::{
+            AccountKind, DisputeExpiry, DuplicatePolicy, LimitsPolicy, LockPolicy,
+            NegativeBalancePolicy, OverdraftPolicy, PrecisionPolicy, SavingsPolicy,
+        };
+        use std::path::PathBuf;
+
+        #[test]
+        fn settings_are_read_from_every_table() {
+            let config = ConfigFile::from_toml(
+                "[engine]\n\
+                 allow_locked_deposits = true\n\
+                 unlock_on_representment = true\n\
+                 allow_adjustments = true\n\
+                 duplicates = \"idempotent\"\n\
+                 dispute_expiry_transactions = 100\n\
+                 [limits]\n\
+                 max_transactions_per_client = 5\n\
+                 [risk]\n\
+                 disputed_deposits = true\n\
+                 [fees]\n\
+                 maintenance_fee = 2\n\
+                 [rate_limits]\n\
+                 per_client = 5\n\
+                 [webhooks]\n\
+                 urls = \"http://localhost:8080/hooks, http://risk/\"\n\
+                 max_attempts = 3\n\
+                 [io]\n\
+                 format = \"json\"\n\
+                 sort_output = \"client\"\n\
+                 threads = 2\n\
+                 ordering = \"strict\"\n\
+                 parse_queue = 64\n\
+                 max_decimals = 2\n\
+                 precision = \"round-half-even\"\n\
+                 amount_format = \"fr\"\n\
+                 fast_parse = true\n\
+                 namespace_sources = true\n\
+                 audit_log = \"audit.jsonl\"\n\
+                 journal = \"books.ledger\"\n\
+                 window_dir = \"windows\"\n\
+                 window_secs = 30\n\
+                 disputes_output = \"disputes.csv\"\n\
+                 log_level = \"debug\"\n\
+                 [columns]\n\
+                 tx = \"transaction_id\"\n",
+            )
+            .unwrap();
+
+            let engine = config.engine_config();
+            assert_eq!(engine.lock_policy, LockPolicy::AllowDeposits);
+            assert!(engine.unlock_on_representment);
+            assert!(engine.allow_adjustments);
+            assert_eq!(engine.duplicate_policy, DuplicatePolicy::Idempotent);
+            assert_eq!(engine.negative_balance_policy, NegativeBalancePolicy::Allow);
+            assert_eq!(
+                engine.dispute_expiry,
+                DisputeExpiry {
+                    after_transactions: Some(100),
+                    after_days: None,
+                }
+            );
+            assert_eq!(
+                engine.limits,
+                LimitsPolicy {
+                    max_transactions_per_client: Some(5),
+                    ..LimitsPolicy::default()
+                }
+            );
+            assert!(engine.risk.disputed_deposits.enabled && !engine.risk.near_limit.enabled);
+            assert_eq!(config.fees.maintenance_fee, Some(2.0));
+            assert!(config.rate_limits.global.is_none() && config.rate_limits.per_client.is_some());
+            assert_eq!(
+                config
+                    .webhooks
+                    .urls
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>(),
+                ["http://localhost:8080/hooks", "http://risk:80/"]
+            );
+            assert_eq!(config.webhooks.max_attempts, Some(3));
+            assert_eq!(config.columns.name("tx"), "transaction_id");
+            assert_eq!(
+                config.io,
+                IoSettings {
+                    format: Some(OutputFormat::Json),
+                    sort_output: Some(OutputOrder::Client),
+                    threads: Some(2),
+                    ordering: Some(InputOrdering::Strict),
+                    parse_queue: Some(64),
+                    max_decimals: Some(2),
+                    precision: Some(PrecisionPolicy::RoundHalfEven),
+                    amount_format: Some(AmountFormat {
+                        decimal_separator: ',',
+                        grouping_separator: Some(' '),
+                    }),
+                    fast_parse: Some(true),
+                    namespace_sources: Some(true),
+                    audit_log: Some(PathBuf::from("audit.jsonl")),
+                    journal: Some(PathBuf::from("books.ledger")),
+                    window_dir: Some(PathBuf::from("windows")),
+                    window_secs: Some(30),
+                    disputes_output: Some(PathBuf::from("disputes.csv")),
+                    log_level: Some(Level::Debug),
+                    ..IoSettings::default()
+                }
+            );
+        }
+
+        #[test]
+        fn unknown_settings_are_rejected() {
+            for (input, line) in [
+                ("[engine]\nlock = true\n", 2),
+                ("[engine]\nduplicates = \"sometimes\"\n", 2),
+                ("[io]\nthreads = 0\n", 2),
+                ("[io]\nordering = \"reorder:0\"\n", 2),
+                ("[io]\nmax_amount = 0\n", 2),
+                ("[engine]\nmax_dispute_cycles = 0\n", 2),
+                ("[engine]\ndispute_expiry_transactions = 0\n", 2),
+                ("[accounts]\nalice = \"basic\"\n", 2),
+                ("[accounts]\n1 = \"gold\"\n", 2),
+                ("[overdraft]\nlimit = -5\n", 2),
+                ("[savings]\nwithdrawal_delay = 1.5\n", 2),
+                ("[tiers]\n1 = \"gold\"\n", 2),
+                ("[business]\noverdraft = 5\n", 2),
+                ("[internal]\nmax_transaction_amount = 0\n", 2),
+                ("[columns]\nid = \"transaction_id\"\n", 2),
+                ("\n[output]\n", 2),
+                ("strict = true\n", 1),
+            ] {
+                assert!(
+                    matches!(
+                        ConfigFile::from_toml(input),
+                        Err(ConfigError::Invalid { line: found, .. }) if found == line
+                    ),
+                    "{}",
+                    input
+                );
+            }
+        }
+
+        #[test]
+        fn account_types_are_read_per_client() {
+            let config = ConfigFile::from_toml(
+                "[overdraft]\n\
+                 limit = 100\n\
+                 fee = 2.5\n\
+                 [accounts]\n\
+                 default = \"overdraft\"\n\
+                 3 = \"basic\"\n\
+                 4 = \"savings\"\n\
+                 [savings]\n\
+                 withdrawal_delay = 2\n",
+            )
+            .unwrap();
+
+            assert_eq!(
+                config.accounts,
+                AccountTypes {
+                    default: AccountKind::Overdraft,
+                    clients: [(3, AccountKind::Basic), (4, AccountKind::Savings)]
+                        .into_iter()
+                        .collect(),
+                    overdraft: OverdraftPolicy {
+                        limit: 100.0,
+                        fee: 2.5,
+                    },
+                    savings: SavingsPolicy {
+                        withdrawal_delay: 2,
+                    },
+                    tiers: Default::default(),
+                }
+            );
+            assert_eq!(config.accounts.kind(1), AccountKind::Overdraft);
+            assert_eq!(config.accounts.kind(3), AccountKind::Basic);
+            assert!(config.engine_config().account_factory.is_some());
+            assert!(ConfigFile::default()
+                .engine_config()
+                .account_factory
+                .is_none());
+        }
+    }
+}
@@ -0,0 +1,419 @@
+//! Consumer loop applying transactions read from a message stream, such as a Kafka topic.
+//!
+//! The loop only relies on [`MessageStream`] so the broker client lives in an adapter. Offsets
+//! are committed once the messages up to them can no longer be lost: right after they are applied
+//! when no snapshots are taken, or once a snapshot including them has been written otherwise.
+//! Recovery then means restoring the latest snapshot and consuming from the committed offset.
+//!
+//! Streams that deliver messages again after a crash, e.g. because the commit of their offset
+//! was lost, are consumed exactly once by naming them with [`Consumer::with_source`] over an
+//! engine with a write-ahead log or state store: every transaction is executed at its
+//! [`InputPosition`] and the redelivered ones are skipped.
+//!
+//! A consumer given [`RateLimits`] with [`Consumer::with_rate_limits`] waits before executing a
+//! transaction over them rather than refusing it, slowing down to the allowed rate.
+//!
+//! A consumer given a [`ChangeSink`] with [`Consumer::with_changes`] emits the accounts changed by
+//! the messages of every window, i.e. every snapshot interval or message without snapshots, once
+//! they are durable and before their offset is committed.
+
+use crate::changes::{ChangeSink, ChangeStream};
+use crate::engine::TransactionEngine;
+use crate::error::ConsumerError;
+use crate::ingest::InputPosition;
+use crate::input::{deserialize_row, parse_json_transaction, AmountRules, InputFormat};
+use crate::log;
+use crate::throttle::{RateLimiter, RateLimits};
+use crate::transaction::Transaction;
+use std::io;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Instant;
+
+/// Message as delivered by the stream
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub offset: u64,
+    pub payload: Vec<u8>,
+}
+
+/// Source of messages with offsets that can be committed
+pub trait MessageStream {
+    /// Waits for the next message. `None` means the stream was closed.
+    fn poll(&mut self) -> io::Result<Option<Message>>;
+
+    /// Marks every message up to and including `offset` as processed
+    fn commit(&mut self, offset: u64) -> io::Result<()>;
+}
+
+/// Counts of what the consumer did with the transactions it read
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ConsumerStats {
+    pub applied: u64,
+    pub rejected: u64,
+    /// Messages that could not be parsed. They are committed so they do not block the stream.
+    pub malformed: u64,
+    /// Transactions skipped as the engine already executed them, see [`Consumer::with_source`]
+    pub redelivered: u64,
+    /// Transactions that waited for a rate limit, see [`Consumer::with_rate_limits`]
+    pub throttled: u64,
+}
+
+pub struct Consumer<S: MessageStream> {
+    stream: S,
+    engine: TransactionEngine,
+    format: InputFormat,
+    snapshot: Option<(PathBuf, u64)>,
+    /// Name of the stream in the high-water marks of the engine
+    source: Option<String>,
+    rate_limiter: Option<RateLimiter>,
+    changes: Option<ChangeStream>,
+    /// Offset of the last applied message not committed yet
+    pending_offset: Option<u64>,
+    messages_since_snapshot: u64,
+    stats: ConsumerStats,
+}
+
+impl<S: MessageStream> Consumer<S> {
+    /// Messages hold csv rows without a header (`type,client,tx,amount`) or json objects,
+    /// depending on `format`
+    pub fn new(stream: S, engine: TransactionEngine, format: InputFormat) -> Self {
+        Consumer {
+            stream,
+            engine,
+            format,
+            snapshot: None,
+            source: None,
+            rate_limiter: None,
+            changes: None,
+            pending_offset: None,
+            messages_since_snapshot: 0,
+            stats: ConsumerStats::default(),
+        }
+    }
+
+    /// Writes a snapshot to `path` every `interval` messages and when the stream is closed
+    pub fn with_snapshots<P: Into<PathBuf>>(mut self, path: P, interval: u64) -> Self {
+        self.snapshot = Some((path.into(), interval.max(1)));
+        self
+    }
+
+    /// Executes the transactions of every message at their position in the stream named
+    /// `source`, skipping those the engine executed already
+    pub fn with_source<N: Into<String>>(mut self, source: N) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    /// Waits before executing a transaction until `limits` allow it
+    pub fn with_rate_limits(mut self, limits: RateLimits) -> Self {
+        self.rate_limiter = limits.is_enabled().then(|| RateLimiter::new(limits));
+        self
+    }
+
+    /// Emits the accounts changed by every window of messages to `sink`
+    pub fn with_changes(mut self, sink: Box<dyn ChangeSink>) -> Self {
+        self.changes = Some(ChangeStream::new(&mut self.engine, sink));
+        self
+    }
+
+    pub fn engine(&self) -> &TransactionEngine {
+        &self.engine
+    }
+
+    pub fn into_engine(self) -> TransactionEngine {
+        self.engine
+    }
+
+    pub fn stats(&self) -> ConsumerStats {
+        self.stats
+    }
+
+    /// Consumes messages until the stream is closed
+    pub fn run(&mut self) -> Result<ConsumerStats, ConsumerError> {
+        while let Some(message) = self.stream.poll()? {
+            self.apply(&message);
+            self.pending_offset = Some(message.offset);
+            self.messages_since_snapshot += 1;
+
+            match &self.snapshot {
+                Some((_, interval)) if self.messages_since_snapshot < *interval => {}
+                _ => self.checkpoint()?,
+            }
+        }
+        self.checkpoint()?;
+        Ok(self.stats)
+    }
+
+    fn apply(&mut self, message: &Message) {
+        match parse_message(&message.payload, self.format) {
+            Ok(transactions) => {
+                for (index, transaction) in transactions.into_iter().enumerate() {
+                    self.throttle(&transaction);
+                    let result = match &self.source {
+                        Some(source) => {
+                            let position =
+                                InputPosition::new(source.as_str(), message.offset, index as u32);
+                            self.engine.execute_at(transaction, position)
+                        }
+                        None => Some(self.engine.execute(transaction)),
+                    };
+                    match result {
+                        None => self.stats.redelivered += 1,
+                        Some(Ok(())) => self.stats.applied += 1,
+                        Some(Err(err)) => {
+                            log::warn(
+                                "Rejected transaction",
+                                &[("offset", &message.offset), ("reason", &err)],
+                            );
+                            self.stats.rejected += 1
+                        }
+                    }
+                }
+            }
+            Err(err) => {
+                log::warn(
+                    "Skipped malformed message",
+                    &[("offset", &message.offset), ("error", &err)],
+                );
+                self.stats.malformed += 1
+            }
+        }
+    }
+
+    /// Sleeps until the rate limits allow the transaction
+    fn throttle(&mut self, transaction: &Transaction) {
+        let Some(limiter) = &mut self.rate_limiter else {
+            return;
+        };
+        let mut waited = false;
+        while let Err(throttled) = limiter.acquire(&[transaction.client_id], Instant::now()) {
+            if !waited {
+                waited = true;
+                self.stats.throttled += 1;
+                self.engine.metrics.record_throttled(throttled.limit, 1);
+                log::debug("Throttled transaction", &[("reason", &throttled)]);
+            }
+            // A single transaction is always within the burst, which is at least 1
+            thread::sleep(throttled.retry_after.unwrap_or_default());
+        }
+    }
+
+    /// Makes the applied messages durable, then commits their offset
+    fn checkpoint(&mut self) -> Result<(), ConsumerError> {
+        let Some(offset) = self.pending_offset else {
+            return Ok(());
+        };
+        self.engine.flush_audit().map_err(ConsumerError::Audit)?;
+        self.engine
+            .sync_wal()
+            .map_err(|err| ConsumerError::Snapshot(err.into()))?;
+        if let Some((path, _)) = &self.snapshot {
+            self.engine.checkpoint(path)?;
+        }
+        if let Some(changes) = &mut self.changes {
+            changes.emit(&self.engine).map_err(ConsumerError::Changes)?;
+        }
+        self.stream.commit(offset)?;
+        self.pending_offset = None;
+        self.messages_since_snapshot = 0;
+        Ok(())
+    }
+}
+
+fn parse_message(payload: &[u8], format: InputFormat) -> Result<Vec<Transaction>, String> {
+    let payload = std::str::from_utf8(payload).map_err(|err| err.to_string())?;
+    match format {
+        InputFormat::Ndjson => payload
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(parse_json_transaction)
+            .collect(),
+        InputFormat::Csv => csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(payload.as_bytes())
+            .records()
+            .map(|record| {
+                let record = record.map_err(|err| err.to_string())?;
+                deserialize_row(&record, Some(&AmountRules::default()))
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::consumer::{Consumer, ConsumerStats, Message, MessageStream};
+        use crate::engine::{EngineConfig, TransactionEngine};
+        use crate::input::InputFormat;
+        use crate::state::MemoryStateStore;
+        use crate::throttle::RateLimits;
+        use std::collections::VecDeque;
+        use std::io;
+        use std::sync::mpsc;
+        use std::time::{Duration, Instant};
+
+        struct QueueStream {
+            messages: VecDeque<Message>,
+            commits: Vec<u64>,
+        }
+
+        impl QueueStream {
+            fn new(payloads: &[&str]) -> Self {
+                QueueStream {
+                    messages: payloads
+                        .iter()
+                        .enumerate()
+                        .map(|(offset, payload)| Message {
+                            offset: offset as u64,
+                            payload: payload.as_bytes().to_vec(),
+                        })
+                        .collect(),
+                    commits: Vec::new(),
+                }
+            }
+        }
+
+        impl MessageStream for &mut QueueStream {
+            fn poll(&mut self) -> io::Result<Option<Message>> {
+                Ok(self.messages.pop_front())
+            }
+
+            fn commit(&mut self, offset: u64) -> io::Result<()> {
+                self.commits.push(offset);
+                Ok(())
+            }
+        }
+
+        #[test]
+        fn messages_are_applied_and_committed() {
+            let mut stream = QueueStream::new(&[
+                "deposit,1,1,2.0",
+                "not a transaction",
+                "withdrawal,1,2,5.0\ndeposit,2,3,1.0",
+            ]);
+            let mut consumer =
+                Consumer::new(&mut stream, TransactionEngine::new(), InputFormat::Csv);
+
+            let stats = consumer.run().unwrap();
+
+            assert_eq!(
+                stats,
+                ConsumerStats {
+                    applied: 2,
+                    rejected: 1,
+                    malformed: 1,
+                    redelivered: 0,
+                    throttled: 0,
+                }
+            );
+            assert_eq!(consumer.engine().accounts[&1].get_available_funds(), 2.0);
+            drop(consumer);
+            assert_eq!(stream.commits, vec![0, 1, 2]);
+        }
+
+        #[test]
+        fn offsets_are_committed_with_snapshots() {
+            let path = std::env::temp_dir().join("consumer_offsets_are_committed.snapshot");
+            let mut stream = QueueStream::new(&[
+                r#"{"type":"deposit","client":1,"tx":1,"amount":1.0}"#,
+                r#"{"type":"deposit","client":1,"tx":2,"amount":1.0}"#,
+                r#"{"type":"deposit","client":1,"tx":3,"amount":1.0}"#,
+            ]);
+            let mut consumer =
+                Consumer::new(&mut stream, TransactionEngine::new(), InputFormat::Ndjson)
+                    .with_snapshots(&path, 2);
+
+            consumer.run().unwrap();
+            drop(consumer);
+            let restored = TransactionEngine::restore(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(stream.commits, vec![1, 2]);
+            assert_eq!(restored.accounts[&1].get_available_funds(), 3.0);
+        }
+
+        #[test]
+        fn changed_accounts_are_emitted_per_window() {
+            let path = std::env::temp_dir().join("consumer_changed_accounts_are_emitted.snapshot");
+            let (sender, receiver) = mpsc::channel();
+            let mut stream = QueueStream::new(&[
+                "deposit,1,1,1.0",
+                "deposit,2,2,1.0",
+                "withdrawal,1,3,5.0",
+                "deposit,2,4,1.0",
+            ]);
+            let mut consumer =
+                Consumer::new(&mut stream, TransactionEngine::new(), InputFormat::Csv)
+                    .with_snapshots(&path, 2)
+                    .with_changes(Box::new(sender));
+
+            consumer.run().unwrap();
+            drop(consumer);
+            std::fs::remove_file(&path).unwrap();
+
+            let windows: Vec<Vec<_>> = receiver
+                .try_iter()
+                .map(|changes| {
+                    changes
+                        .iter()
+                        .map(|change| (change.client, change.available))
+                        .collect()
+                })
+                .collect();
+            assert_eq!(windows, [vec![(1, 1.0), (2, 1.0)], vec![(2, 2.0)]]);
+        }
+
+        #[test]
+        fn consumer_waits_for_the_rate_limits() {
+            let mut stream =
+                QueueStream::new(&["deposit,1,1,1.0\ndeposit,1,2,1.0", "deposit,2,3,1.0"]);
+            let mut consumer =
+                Consumer::new(&mut stream, TransactionEngine::new(), InputFormat::Csv)
+                    .with_rate_limits(RateLimits {
+                        global: Some("200:1".parse().unwrap()),
+                        per_client: None,
+                    });
+            let started = Instant::now();
+
+            let stats = consumer.run().unwrap();
+
+            assert_eq!((stats.applied, stats.throttled), (3, 2));
+            assert!(started.elapsed() >= Duration::from_millis(10));
+            assert_eq!(consumer.engine().metrics().total_throttled(), 2);
+        }
+
+        #[test]
+        fn redelivered_messages_are_applied_once() {
+            let store = MemoryStateStore::new();
+            let open = || {
+                TransactionEngine::open_store(Box::new(store.clone()), EngineConfig::default())
+                    .unwrap()
+            };
+            let payloads = [
+                "deposit,1,1,5.0",
+                "deposit,1,2,1.0\ndispute,1,1,",
+                "resolve,1,1,",
+            ];
+            let mut stream = QueueStream::new(&payloads[..2]);
+            Consumer::new(&mut stream, open(), InputFormat::Csv)
+                .with_source("payments")
+                .run()
+                .unwrap();
+
+            // The commits were lost: every message is delivered again, followed by a new one
+            let mut stream = QueueStream::new(&payloads);
+            let mut consumer =
+                Consumer::new(&mut stream, open(), InputFormat::Csv).with_source("payments");
+            let stats = consumer.run().unwrap();
+
+            assert_eq!(stats.redelivered, 3);
+            assert_eq!(stats.applied, 1);
+            assert_eq!(stats.rejected, 0);
+            assert_eq!(consumer.engine().accounts[&1].get_available_funds(), 6.0);
+            assert_eq!(consumer.engine().high_water_mark("payments"), Some((2, 0)));
+        }
+    }
+}
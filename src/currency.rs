@@ -0,0 +1,68 @@
+//! Currency codes carried by transactions and tracked separately by accounts.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// Three letter currency code such as `EUR`, stored upper case. Transactions without a
+/// currency are tracked under `None` wherever an `Option<Currency>` is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub fn as_str(&self) -> &str {
+        // Only ascii letters are ever stored
+        std::str::from_utf8(&self.0).unwrap_or_default()
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Currency {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.as_bytes() {
+            [a, b, c] if value.bytes().all(|byte| byte.is_ascii_alphabetic()) => Ok(Currency([
+                a.to_ascii_uppercase(),
+                b.to_ascii_uppercase(),
+                c.to_ascii_uppercase(),
+            ])),
+            _ => Err(format!("invalid currency code '{}'", value)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::currency::Currency;
+
+        #[test]
+        fn codes_are_normalised_to_upper_case() {
+            let currency: Currency = "eur".parse().unwrap();
+
+            assert_eq!(currency.to_string(), "EUR");
+            assert_eq!(currency, "EUR".parse().unwrap());
+            assert!("EURO".parse::<Currency>().is_err());
+            assert!("E1R".parse::<Currency>().is_err());
+        }
+    }
+}
@@ -0,0 +1,318 @@
+//! Reconciliation of two sets of accounts, such as the outputs of two runs over the same input or
+//! the engine states saved before and after a migration, printed by the `diff` subcommand.
+
+use crate::account::ClientId;
+use crate::currency::Currency;
+use crate::output::AccountSnapshot;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Amounts differing by at most this much are equal by default, as the output is rounded to 4
+/// decimal places
+pub const DEFAULT_TOLERANCE: f64 = 0.00005;
+
+/// Format of a [`Reconciliation`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiffFormat {
+    /// Lines meant to be read by people
+    #[default]
+    Text,
+    /// One row per differing field
+    Csv,
+    Json,
+}
+
+impl FromStr for DiffFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(DiffFormat::Text),
+            "csv" => Ok(DiffFormat::Csv),
+            "json" => Ok(DiffFormat::Json),
+            _ => Err(format!("unknown diff format '{}'", value)),
+        }
+    }
+}
+
+/// Funds of a client in one currency that differ between the two sides
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountDiff {
+    pub client: ClientId,
+    pub currency: Option<Currency>,
+    /// None if the account is only on the right
+    pub left: Option<AccountSnapshot>,
+    /// None if the account is only on the left
+    pub right: Option<AccountSnapshot>,
+    /// Differing fields among `available`, `held`, `total` and `locked`, all of them when the
+    /// account is only on one side
+    pub fields: Vec<&'static str>,
+}
+
+impl AccountDiff {
+    fn new(
+        left: Option<AccountSnapshot>,
+        right: Option<AccountSnapshot>,
+        tolerance: f64,
+    ) -> Option<Self> {
+        let any = left.as_ref().or(right.as_ref())?;
+        let (client, currency) = (any.client, any.currency);
+        let fields = match (&left, &right) {
+            (Some(left), Some(right)) => {
+                let mut fields = Vec::new();
+                for (field, left, right) in [
+                    ("available", left.available, right.available),
+                    ("held", left.held, right.held),
+                    ("total", left.total, right.total),
+                ] {
+                    if (left - right).abs() > tolerance {
+                        fields.push(field);
+                    }
+                }
+                if left.locked != right.locked {
+                    fields.push("locked");
+                }
+                fields
+            }
+            _ => FIELDS.to_vec(),
+        };
+        if fields.is_empty() {
+            return None;
+        }
+        Some(AccountDiff {
+            client,
+            currency,
+            left,
+            right,
+            fields,
+        })
+    }
+}
+
+const FIELDS: [&str; 4] = ["available", "held", "total", "locked"];
+
+/// Value of a field of an account, empty for a missing account
+fn field(snapshot: Option<&AccountSnapshot>, field: &str) -> String {
+    match (snapshot, field) {
+        (None, _) => String::new(),
+        (Some(snapshot), "available") => format!("{:.4}", snapshot.available),
+        (Some(snapshot), "held") => format!("{:.4}", snapshot.held),
+        (Some(snapshot), "total") => format!("{:.4}", snapshot.total),
+        (Some(snapshot), _) => snapshot.locked.to_string(),
+    }
+}
+
+/// Accounts of two sides matched by client and currency, keeping those that differ
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reconciliation {
+    /// Accounts found on either side
+    pub compared: usize,
+    /// In order of client and currency
+    pub diffs: Vec<AccountDiff>,
+}
+
+impl Reconciliation {
+    /// Compares two sets of accounts, amounts differing by at most `tolerance` being equal
+    pub fn new<L, R>(left: L, right: R, tolerance: f64) -> Self
+    where
+        L: IntoIterator<Item = AccountSnapshot>,
+        R: IntoIterator<Item = AccountSnapshot>,
+    {
+        let mut accounts: BTreeMap<_, (Option<_>, Option<_>)> = BTreeMap::new();
+        for snapshot in left {
+            let key = (snapshot.client, snapshot.currency);
+            accounts.entry(key).or_default().0 = Some(snapshot);
+        }
+        for snapshot in right {
+            let key = (snapshot.client, snapshot.currency);
+            accounts.entry(key).or_default().1 = Some(snapshot);
+        }
+        Reconciliation {
+            compared: accounts.len(),
+            diffs: accounts
+                .into_values()
+                .filter_map(|(left, right)| AccountDiff::new(left, right, tolerance))
+                .collect(),
+        }
+    }
+
+    /// Whether both sides hold the same accounts
+    pub fn is_clean(&self) -> bool {
+        self.diffs.is_empty()
+    }
+
+    pub fn to_json(&self) -> String {
+        let side = |snapshot: &Option<AccountSnapshot>| {
+            snapshot
+                .as_ref()
+                .map_or("null".to_string(), AccountSnapshot::to_json)
+        };
+        let diffs: Vec<_> = self
+            .diffs
+            .iter()
+            .map(|diff| {
+                let currency = diff.currency.map_or(String::new(), |currency| {
+                    format!(",\"currency\":\"{}\"", currency)
+                });
+                let fields: Vec<_> = diff
+                    .fields
+                    .iter()
+                    .map(|field| format!("\"{}\"", field))
+                    .collect();
+                format!(
+                    "{{\"client\":{}{},\"fields\":[{}],\"left\":{},\"right\":{}}}",
+                    diff.client,
+                    currency,
+                    fields.join(","),
+                    side(&diff.left),
+                    side(&diff.right)
+                )
+            })
+            .collect();
+        format!(
+            "{{\"compared\":{},\"differences\":[{}]}}",
+            self.compared,
+            diffs.join(",")
+        )
+    }
+
+    /// Csv with a `client,currency,field,left,right` header and a row per differing field, the
+    /// value of a missing account being empty
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("client,currency,field,left,right\n");
+        for diff in &self.diffs {
+            let currency = diff
+                .currency
+                .map_or(String::new(), |currency| currency.to_string());
+            for name in &diff.fields {
+                csv.push_str(&format!(
+                    "{},{},{},{},{}\n",
+                    diff.client,
+                    currency,
+                    name,
+                    field(diff.left.as_ref(), name),
+                    field(diff.right.as_ref(), name)
+                ));
+            }
+        }
+        csv
+    }
+}
+
+impl fmt::Display for Reconciliation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Compared {} accounts, {} differ",
+            self.compared,
+            self.diffs.len()
+        )?;
+        for diff in &self.diffs {
+            write!(f, "  client {}", diff.client)?;
+            if let Some(currency) = diff.currency {
+                write!(f, " {}", currency)?;
+            }
+            match (&diff.left, &diff.right) {
+                (Some(_), None) => writeln!(f, ": only on the left")?,
+                (None, Some(_)) => writeln!(f, ": only on the right")?,
+                (left, right) => {
+                    let fields: Vec<_> = diff
+                        .fields
+                        .iter()
+                        .map(|name| {
+                            format!(
+                                "{} {} != {}",
+                                name,
+                                field(left.as_ref(), name),
+                                field(right.as_ref(), name)
+                            )
+                        })
+                        .collect();
+                    writeln!(f, ": {}", fields.join(", "))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::account::ClientId;
+        use crate::currency::Currency;
+        use crate::diff::{Reconciliation, DEFAULT_TOLERANCE};
+        use crate::output::AccountSnapshot;
+
+        fn snapshot(client: ClientId, available: f64, held: f64, locked: bool) -> AccountSnapshot {
+            AccountSnapshot {
+                client,
+                available,
+                held,
+                total: available + held,
+                locked,
+                reserved: 0.0,
+                closed: false,
+                currency: None,
+            }
+        }
+
+        #[test]
+        fn accounts_differing_beyond_the_tolerance_are_reported() {
+            let eur: Currency = "EUR".parse().unwrap();
+            let left = vec![
+                snapshot(1, 1.0, 0.0, false),
+                snapshot(2, 2.0, 1.0, false),
+                snapshot(3, 5.0, 0.0, false),
+            ];
+            let right = vec![
+                snapshot(3, 5.0, 0.0, true),
+                snapshot(1, 1.00001, 0.0, false),
+                snapshot(2, 2.5, 0.5, false),
+                AccountSnapshot {
+                    currency: Some(eur),
+                    ..snapshot(4, 1.0, 0.0, false)
+                },
+            ];
+
+            let reconciliation = Reconciliation::new(left, right, DEFAULT_TOLERANCE);
+
+            assert!(!reconciliation.is_clean());
+            assert_eq!(reconciliation.compared, 4);
+            assert_eq!(
+                reconciliation.to_string(),
+                "Compared 4 accounts, 3 differ\n\
+                 \x20 client 2: available 2.0000 != 2.5000, held 1.0000 != 0.5000\n\
+                 \x20 client 3: locked false != true\n\
+                 \x20 client 4 EUR: only on the right\n"
+            );
+            assert_eq!(
+                reconciliation.to_csv(),
+                "client,currency,field,left,right\n\
+                 2,,available,2.0000,2.5000\n2,,held,1.0000,0.5000\n3,,locked,false,true\n\
+                 4,EUR,available,,1.0000\n4,EUR,held,,0.0000\n4,EUR,total,,1.0000\n\
+                 4,EUR,locked,,false\n"
+            );
+            assert_eq!(
+                reconciliation.to_json(),
+                "{\"compared\":4,\"differences\":[\
+                 {\"client\":2,\"fields\":[\"available\",\"held\"],\
+                 \"left\":{\"client\":2,\"available\":2.0000,\"held\":1.0000,\"total\":3.0000,\"locked\":false},\
+                 \"right\":{\"client\":2,\"available\":2.5000,\"held\":0.5000,\"total\":3.0000,\"locked\":false}},\
+                 {\"client\":3,\"fields\":[\"locked\"],\
+                 \"left\":{\"client\":3,\"available\":5.0000,\"held\":0.0000,\"total\":5.0000,\"locked\":false},\
+                 \"right\":{\"client\":3,\"available\":5.0000,\"held\":0.0000,\"total\":5.0000,\"locked\":true}},\
+                 {\"client\":4,\"currency\":\"EUR\",\"fields\":[\"available\",\"held\",\"total\",\"locked\"],\
+                 \"left\":null,\
+                 \"right\":{\"client\":4,\"available\":1.0000,\"held\":0.0000,\"total\":1.0000,\"locked\":false,\"currency\":\"EUR\"}}]}"
+            );
+            let loose = Reconciliation::new(
+                vec![snapshot(1, 1.0, 0.0, false)],
+                vec![snapshot(1, 1.2, 0.0, false)],
+                0.5,
+            );
+            assert!(loose.is_clean());
+        }
+    }
+}
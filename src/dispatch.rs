@@ -0,0 +1,233 @@
+//! Feeding a [`ShardedEngine`] from several input files read at once, without changing the
+//! result. Each reader thread submits the rows of its file with the position of the file in the
+//! input. Rows of the first unfinished file go to the shards as they arrive. Rows of later files
+//! wait until every file before theirs is finished, and then go in the order they were read.
+//!
+//! The engine therefore sees the transactions in input order, file after file and row after row,
+//! which is the order the single threaded engine applies them in. Each shard applies what it is
+//! sent first in, first out, so the transactions of every client, and the disputes routed to the
+//! shard of the transaction they refer to, are applied in input order too. Only the parsing of
+//! the files happens in parallel.
+//!
+//! ```
+//! use rust_coding_test::{
+//!     Amount, EngineConfig, OrderedDispatcher, ShardedEngine, Transaction, TransactionId,
+//!     TransactionType,
+//! };
+//! use std::thread;
+//!
+//! let deposit = |transaction_id| Transaction {
+//!     transaction_type: TransactionType::Deposit,
+//!     client_id: 1,
+//!     transaction_id: TransactionId(transaction_id),
+//!     amount: Some(Amount::new(1.0).unwrap()),
+//!     to_client_id: None,
+//!     currency: None,
+//!     timestamp: None,
+//!     metadata: Default::default(),
+//! };
+//! let dispatcher = OrderedDispatcher::new(ShardedEngine::new(2, EngineConfig::default()));
+//! thread::scope(|scope| {
+//!     for file in 0..2 {
+//!         let dispatcher = &dispatcher;
+//!         scope.spawn(move || {
+//!             dispatcher.submit(file, deposit(file as u32 + 1));
+//!             dispatcher.finish_file(file);
+//!         });
+//!     }
+//! });
+//! let (engine, rejected) = dispatcher.finish();
+//! assert!(rejected.is_empty());
+//! assert_eq!(engine.accounts[&1].get_available_funds(), 2.0);
+//! ```
+
+use crate::engine::TransactionEngine;
+use crate::error::EngineError;
+use crate::sharded::ShardedEngine;
+use crate::transaction::{Origin, Transaction};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::{Condvar, Mutex, MutexGuard};
+
+/// Rows of a file waiting for the files before it by default, before its reader blocks
+pub const DEFAULT_PENDING_CAPACITY: usize = 64 * 1024;
+
+/// Applies the rows of input files read in parallel to a [`ShardedEngine`] in input order, see
+/// the [module](self) documentation
+pub struct OrderedDispatcher {
+    state: Mutex<DispatchState>,
+    /// Notified whenever the head file changes
+    advanced: Condvar,
+    capacity: usize,
+}
+
+struct DispatchState {
+    engine: ShardedEngine,
+    /// First unfinished file, whose rows are sent to the shards as they are submitted
+    head: usize,
+    /// Rows of the files after the head, in the order they were read
+    pending: BTreeMap<usize, VecDeque<(Transaction, Origin)>>,
+    /// Files after the head that are finished
+    finished: BTreeSet<usize>,
+}
+
+impl OrderedDispatcher {
+    pub fn new(engine: ShardedEngine) -> Self {
+        Self::with_capacity(engine, DEFAULT_PENDING_CAPACITY)
+    }
+
+    /// Dispatcher holding up to `capacity` rows of every file waiting for the files before it.
+    /// Readers of such files block once the capacity is reached, which the reader of the head
+    /// file never does, so memory stays bounded when later files are read faster.
+    pub fn with_capacity(engine: ShardedEngine, capacity: usize) -> Self {
+        OrderedDispatcher {
+            state: Mutex::new(DispatchState {
+                engine,
+                head: 0,
+                pending: BTreeMap::new(),
+                finished: BTreeSet::new(),
+            }),
+            advanced: Condvar::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Queues a client transaction read from the file at the given position in the input, see
+    /// [`OrderedDispatcher::submit_from`]
+    pub fn submit(&self, file: usize, transaction: Transaction) {
+        self.submit_from(file, transaction, Origin::Client)
+    }
+
+    /// Queues a transaction read from the file at the given position in the input. Rows of a
+    /// file must be submitted in the order they are read, by a single thread.
+    pub fn submit_from(&self, file: usize, transaction: Transaction, origin: Origin) {
+        let mut state = self.state();
+        while file > state.head
+            && state.pending.get(&file).map_or(0, VecDeque::len) >= self.capacity
+        {
+            state = self.advanced.wait(state).expect("Dispatcher lock poisoned");
+        }
+        if file == state.head {
+            state.engine.submit_from(transaction, origin);
+        } else {
+            state
+                .pending
+                .entry(file)
+                .or_default()
+                .push_back((transaction, origin));
+        }
+    }
+
+    /// Records that every row of the file was submitted, including when reading it failed, so
+    /// that the rows of the files after it stop waiting
+    pub fn finish_file(&self, file: usize) {
+        let mut state = self.state();
+        state.finished.insert(file);
+        let head = state.head;
+        loop {
+            let finished = state.head;
+            if !state.finished.remove(&finished) {
+                break;
+            }
+            state.head += 1;
+            if let Some(rows) = state.pending.remove(&(finished + 1)) {
+                for (transaction, origin) in rows {
+                    state.engine.submit_from(transaction, origin);
+                }
+            }
+        }
+        if state.head != head {
+            self.advanced.notify_all();
+        }
+    }
+
+    /// Waits for all queued transactions and merges the shards, see [`ShardedEngine::finish`].
+    /// Rows of files still waiting, e.g. after a file that was never read, are applied first in
+    /// file order.
+    pub fn finish(self) -> (TransactionEngine, Vec<EngineError>) {
+        let mut state = self.state.into_inner().expect("Dispatcher lock poisoned");
+        for (transaction, origin) in std::mem::take(&mut state.pending).into_values().flatten() {
+            state.engine.submit_from(transaction, origin);
+        }
+        state.engine.finish()
+    }
+
+    fn state(&self) -> MutexGuard<'_, DispatchState> {
+        self.state.lock().expect("Dispatcher lock poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::dispatch::OrderedDispatcher;
+        use crate::engine::{EngineConfig, TransactionEngine};
+        use crate::error::{EngineError, UpdateError};
+        use crate::sharded::ShardedEngine;
+        use crate::transaction::{transaction, TransactionId, TransactionType};
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::thread;
+        use std::time::Duration;
+
+        #[test]
+        fn later_files_wait_for_the_files_before_them() {
+            let dispatcher =
+                OrderedDispatcher::with_capacity(ShardedEngine::new(3, EngineConfig::default()), 2);
+            let first_done = AtomicBool::new(false);
+            thread::scope(|scope| {
+                // The second file is read first, and overdraws the account if applied first
+                scope.spawn(|| {
+                    for transaction_id in 10..15 {
+                        dispatcher.submit(
+                            1,
+                            transaction(TransactionType::Withdrawal, 1, transaction_id, Some(1.0)),
+                        );
+                    }
+                    dispatcher.finish_file(1);
+                    assert!(first_done.load(Ordering::SeqCst));
+                });
+                scope.spawn(|| {
+                    thread::sleep(Duration::from_millis(20));
+                    dispatcher.submit(0, transaction(TransactionType::Deposit, 1, 1, Some(4.0)));
+                    first_done.store(true, Ordering::SeqCst);
+                    dispatcher.finish_file(0);
+                });
+            });
+
+            let (engine, rejected) = dispatcher.finish();
+            assert_eq!(
+                rejected,
+                vec![EngineError::Account {
+                    client_id: 1,
+                    source: UpdateError::InsufficientFunds {
+                        transaction_id: TransactionId(14),
+                        requested: 1.0,
+                        available: 0.0,
+                    },
+                }]
+            );
+            assert_eq!(engine.accounts[&1].get_available_funds(), 0.0);
+        }
+
+        #[test]
+        fn rows_of_files_never_finished_are_applied_in_file_order() {
+            let dispatcher = OrderedDispatcher::new(ShardedEngine::new(2, EngineConfig::default()));
+            dispatcher.submit(2, transaction(TransactionType::Withdrawal, 1, 3, Some(1.0)));
+            dispatcher.submit(1, transaction(TransactionType::Deposit, 1, 2, Some(1.0)));
+            dispatcher.finish_file(2);
+
+            let (engine, rejected) = dispatcher.finish();
+            let mut single = TransactionEngine::new();
+            single
+                .execute(transaction(TransactionType::Deposit, 1, 2, Some(1.0)))
+                .unwrap();
+            single
+                .execute(transaction(TransactionType::Withdrawal, 1, 3, Some(1.0)))
+                .unwrap();
+            assert!(rejected.is_empty());
+            assert_eq!(
+                engine.sorted_snapshots().collect::<Vec<_>>(),
+                single.sorted_snapshots().collect::<Vec<_>>()
+            );
+        }
+    }
+}
@@ -1,43 +1,4157 @@
-use crate::account::{BasicAccount, ClientAccount, ClientId};
-use crate::transaction::{Transaction, TransactionType};
-use std::collections::HashMap;
+use crate::account::{
+    AccountFactory, AccountState, BasicAccountFactory, ClientAccount, ClientId, DisputeState, DUST,
+};
+use crate::audit::{AuditEvent, AuditSink};
+use crate::books::{self, GlobalLedger};
+use crate::builder::EngineBuilder;
+use crate::columnar::ColumnBatch;
+use crate::currency::Currency;
+use crate::error::{
+    EngineError, ExchangeError, InputError, Limit, MergeConflict, SnapshotError, UpdateError,
+};
+use crate::exchange;
+use crate::expiry::{Expiry, OpenDispute};
+use crate::fees::{FeeSchedule, FeeSummary};
+use crate::filter::ClientFilter;
+use crate::ingest::{HighWaterMarks, InputPosition};
+use crate::input::{open_source, AmountRules, InputFormat};
+use crate::ledger::{History, Ledger, LedgerEntry};
+use crate::log::{self, Level};
+use crate::metrics::{Clock, EngineMetrics};
+use crate::observer::{self, EngineObserver, Observed};
+use crate::output::{AccountSnapshot, AccountWriter, OutputOrder};
+use crate::policy::{
+    AccountPolicies, DisputeCycles, DisputeExpiry, DisputePolicy, DuplicatePolicy, LimitsPolicy,
+    LockPolicy, NegativeBalancePolicy, RetentionPolicy,
+};
+use crate::profile::Profile;
+use crate::projection::AccountProjection;
+use crate::report::ValidationReport;
+use crate::retention::Retention;
+use crate::risk::{RiskPolicy, RiskRule};
+use crate::schedule::{Schedule, ScheduleSummary};
+use crate::snapshot;
+use crate::state::StateStore;
+use crate::storage::Storage;
+use crate::tier::Tiers;
+use crate::timestamp::Timestamp;
+use crate::transaction::{Amount, Origin, Transaction, TransactionId, TransactionType};
+use crate::wal::{WalEntry, WriteAheadLog};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
 
+/// Behavioural knobs of the engine
+#[derive(Debug, Clone, Default)]
+pub struct EngineConfig {
+    /// Which transactions are still accepted on accounts locked by a chargeback
+    pub lock_policy: LockPolicy,
+    /// Lift the lock of accounts once every chargeback of theirs was represented
+    pub unlock_on_representment: bool,
+    /// Accept [`TransactionType::Adjustment`]s from admin sources, rejected otherwise
+    pub allow_adjustments: bool,
+    /// How deposits and withdrawals reusing a transaction id are handled
+    pub duplicate_policy: DuplicatePolicy,
+    /// What happens when a dispute exceeds the available funds
+    pub negative_balance_policy: NegativeBalancePolicy,
+    /// Which transactions can be disputed and what a chargeback of a withdrawal means
+    pub dispute_policy: DisputePolicy,
+    /// How often the same funds can be disputed again after a resolve
+    pub max_dispute_cycles: DisputeCycles,
+    /// Days after which a transaction can no longer be disputed, counted between the timestamps
+    /// of the transaction and of the dispute. Not enforced when either has no timestamp.
+    pub dispute_window: Option<u32>,
+    /// When open disputes are resolved by the engine if nobody settles them. Resolves refused by
+    /// the account, e.g. because it got locked since, leave the dispute open.
+    pub dispute_expiry: DisputeExpiry,
+    /// Limits on the amounts and number of transactions of every client
+    pub limits: LimitsPolicy,
+    /// Tier of every client, whose policies override the ones above for its account. Tiers
+    /// changing the type or overdraft of accounts need an [`AccountTypes`] factory sharing them.
+    ///
+    /// [`AccountTypes`]: crate::account::AccountTypes
+    pub tiers: Arc<Tiers>,
+    /// Built-in rules flagging suspicious transactions
+    pub risk: RiskPolicy,
+    /// How many transactions stay disputable
+    pub retention_policy: RetentionPolicy,
+    /// Where accounts keep their disputable transactions
+    pub storage: Storage,
+    /// Creates and restores the accounts of clients, [`BasicAccount`]s if not set
+    ///
+    /// [`BasicAccount`]: crate::account::BasicAccount
+    pub account_factory: Option<Arc<dyn AccountFactory>>,
+    /// Retain every applied transaction in a ledger so that history can be queried.
+    /// Off by default since memory grows with the number of transactions.
+    pub record_history: bool,
+    /// Record the latency of every transaction per type and per account, see [`Profile`]
+    pub profile: bool,
+}
+
+impl EngineConfig {
+    /// Policies given to every account created by the engine
+    pub fn account_policies(&self) -> AccountPolicies {
+        AccountPolicies {
+            lock: self.lock_policy,
+            negative_balance: self.negative_balance_policy,
+            dispute: self.dispute_policy,
+            max_dispute_cycles: self.max_dispute_cycles,
+            unlock_on_representment: self.unlock_on_representment,
+        }
+    }
+
+    /// Policies given to the account of the client, those of its tier where it has any
+    pub fn account_policies_for(&self, client_id: ClientId) -> AccountPolicies {
+        let policies = self.account_policies();
+        match self.tiers.policy(client_id) {
+            Some(tier) => tier.account_policies(policies),
+            None => policies,
+        }
+    }
+
+    /// Limits on the transactions of the client, those of its tier where it has any
+    pub fn limits_for(&self, client_id: ClientId) -> LimitsPolicy {
+        match self.tiers.policy(client_id) {
+            Some(tier) => tier.limits(self.limits),
+            None => self.limits,
+        }
+    }
+
+    /// Factory of the accounts created or restored by the engine
+    pub fn account_factory(&self) -> &dyn AccountFactory {
+        match &self.account_factory {
+            Some(factory) => factory.as_ref(),
+            None => &BasicAccountFactory,
+        }
+    }
+}
+
+/// Deposit, withdrawal or transfer seen by the engine, whether it was applied or not
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SeenTransaction {
+    pub(crate) client_id: ClientId,
+    pub(crate) transaction_type: TransactionType,
+    pub(crate) amount: f64,
+    /// Credited client of a transfer
+    pub(crate) to_client_id: Option<ClientId>,
+    pub(crate) currency: Option<Currency>,
+    pub(crate) timestamp: Option<Timestamp>,
+    pub(crate) applied: bool,
+}
+
+/// What a client used up of the configured limits. Only the limits that are set are tracked.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct ClientUsage {
+    /// Applied transactions of the client
+    pub(crate) transactions: u64,
+    /// Withdrawn and transferred away since the last reset, per currency
+    pub(crate) withdrawn: BTreeMap<Option<Currency>, f64>,
+    /// Day of the last timestamped withdrawal. Withdrawals with a timestamp on another day start
+    /// a new day for the client.
+    pub(crate) day: Option<u64>,
+}
+
+impl ClientUsage {
+    /// Whether a withdrawal at `timestamp` falls on another day than the last timestamped one
+    fn is_new_day(&self, timestamp: Option<Timestamp>) -> bool {
+        match (self.day, timestamp) {
+            (Some(day), Some(timestamp)) => timestamp.day() != day,
+            _ => false,
+        }
+    }
+}
+
+/// State of an engine saved by [`TransactionEngine::begin_batch`], to apply a batch of
+/// transactions all or nothing
+pub struct Batch {
+    saved: Vec<u8>,
+    /// Transactions the engine had rejected when the batch began
+    rejected: u64,
+}
+
+impl Batch {
+    /// Transactions of the batch the engine rejected so far
+    pub fn rejected(&self, engine: &TransactionEngine) -> u64 {
+        engine.metrics.total_rejected() - self.rejected
+    }
+}
+
+/// Applies transactions to client accounts, keeping the state of every account it has seen
 pub struct TransactionEngine {
     /// State of client accounts. Will create a new account if the mentioned client id
     /// isn't present.
     pub accounts: HashMap<ClientId, Box<dyn ClientAccount>>,
+    /// Every deposit, withdrawal and transfer seen so far. Used to detect reused transaction ids
+    /// and to validate the client on rows referencing an earlier transaction.
+    pub(crate) seen_transactions: HashMap<TransactionId, SeenTransaction>,
+    config: EngineConfig,
+    pub(crate) usage: HashMap<ClientId, ClientUsage>,
+    /// Accounts closed by a [`TransactionType::Close`], which take no further transactions
+    pub(crate) closed: HashSet<ClientId>,
+    /// Reserves of the funds of clients per currency set by [`TransactionType::Reserve`], in place
+    /// of that of their tier
+    pub(crate) reserves: HashMap<(ClientId, Option<Currency>), f64>,
+    /// Log sequence number of the last transaction applied to each account, to find dormant ones
+    pub(crate) last_active: HashMap<ClientId, u64>,
+    /// Order in which seen transactions are evicted under a bounded retention policy
+    pub(crate) retention: Retention,
+    /// Open disputes, resolved once they expire under the dispute expiry policy
+    pub(crate) expiry: Expiry,
+    pub(crate) ledger: Ledger,
+    /// Funds that entered and left the accounts, see [`TransactionEngine::global_ledger`]
+    pub(crate) books: GlobalLedger,
+    /// Number of transactions executed, including rejected ones. Identifies the entries of the
+    /// write-ahead log already reflected in the state.
+    pub(crate) lsn: u64,
+    /// Rows of a batch input reflected in the state, see [`TransactionEngine::checkpoint_at`]
+    pub(crate) input_offset: u64,
+    /// Highest position executed per input, see [`TransactionEngine::execute_at`]
+    pub(crate) ingested: HighWaterMarks,
+    pub(crate) metrics: EngineMetrics,
+    /// Latencies per type and account, if profiling is enabled
+    profile: Option<Profile>,
+    /// Receives an event for every applied or rejected transaction if set
+    audit_sink: Option<Box<dyn AuditSink>>,
+    /// Rules of the risk policy followed by those added with [`TransactionEngine::add_risk_rule`]
+    risk_rules: Vec<Box<dyn RiskRule>>,
+    /// Notified of every applied transaction, in the order they subscribed
+    observers: Vec<Box<dyn EngineObserver>>,
+    /// Every transaction is appended to it before being applied, if set
+    wal: Option<WriteAheadLog>,
+    /// Every transaction is recorded in it before being applied, if set and there is no `wal`
+    store: Option<Box<dyn StateStore>>,
 }
 
 impl TransactionEngine {
     pub fn new() -> Self {
+        Self::with_config(EngineConfig::default())
+    }
+
+    /// Builder of an engine, or of a sharded or concurrent one, see [`EngineBuilder`]
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder::new()
+    }
+
+    pub fn with_config(config: EngineConfig) -> Self {
         Self {
             accounts: HashMap::new(),
+            seen_transactions: HashMap::new(),
+            retention: Retention::new(config.retention_policy),
+            expiry: Expiry::new(config.dispute_expiry),
+            risk_rules: config.risk.rules(),
+            profile: config.profile.then(Profile::default),
+            config,
+            usage: HashMap::new(),
+            closed: HashSet::new(),
+            reserves: HashMap::new(),
+            last_active: HashMap::new(),
+            ledger: Ledger::new(),
+            books: GlobalLedger::default(),
+            lsn: 0,
+            input_offset: 0,
+            ingested: HighWaterMarks::default(),
+            metrics: EngineMetrics::default(),
+            audit_sink: None,
+            observers: Vec::new(),
+            wal: None,
+            store: None,
+        }
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Funds brought into and taken out of the accounts by the transactions applied so far, which
+    /// the funds of the accounts add up to
+    pub fn global_ledger(&self) -> &GlobalLedger {
+        &self.books
+    }
+
+    /// Counters and latencies of the transactions executed so far
+    pub fn metrics(&self) -> &EngineMetrics {
+        &self.metrics
+    }
+
+    /// Starts the counters and latencies over, e.g. for every window of a stream
+    pub fn reset_metrics(&mut self) {
+        self.metrics = EngineMetrics::default();
+    }
+
+    /// Latencies of the transactions executed so far, if [`EngineConfig::profile`] is enabled
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    pub fn set_audit_sink(&mut self, sink: Box<dyn AuditSink>) {
+        self.audit_sink = Some(sink);
+    }
+
+    /// Calls the observer back on every later change of the accounts, see [`EngineObserver`]
+    pub fn subscribe(&mut self, observer: Box<dyn EngineObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// Evaluates a rule of the library user on every later transaction, next to those of the
+    /// [`RiskPolicy`]
+    pub fn add_risk_rule(&mut self, rule: Box<dyn RiskRule>) {
+        self.risk_rules.push(rule);
+    }
+
+    /// Flushes the audit sink, if any
+    pub fn flush_audit(&mut self) -> io::Result<()> {
+        match self.audit_sink.as_mut() {
+            Some(sink) => sink.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Replays the entries of the write-ahead log at `path` that are not part of the current
+    /// state yet, then logs every further transaction to it. Should be called right after
+    /// creating or restoring the engine. Returns the number of replayed entries.
+    pub fn open_wal<P: AsRef<Path>>(&mut self, path: P, sync_every: usize) -> io::Result<usize> {
+        let (wal, entries) = WriteAheadLog::open(path, sync_every)?;
+        let replayed = self.replay(entries);
+        self.wal = Some(wal);
+        Ok(replayed)
+    }
+
+    /// Engine with the state of a [`StateStore`]: the state it saved last, or a new engine with
+    /// `config`, with the transactions recorded since applied again. Every further transaction is
+    /// recorded in the store.
+    pub fn open_store(
+        mut store: Box<dyn StateStore>,
+        config: EngineConfig,
+    ) -> Result<Self, SnapshotError> {
+        let (engine, recorded) = store.load(config.clone())?;
+        let mut engine = engine.unwrap_or_else(|| Self::with_config(config));
+        engine.replay(recorded);
+        engine.store = Some(store);
+        Ok(engine)
+    }
+
+    /// Applies the logged entries that are not part of the state yet. Returns how many.
+    fn replay(&mut self, entries: Vec<WalEntry>) -> usize {
+        // Replayed transactions were audited and observed when they were first executed
+        let audit_sink = self.audit_sink.take();
+        let observers = std::mem::take(&mut self.observers);
+        let mut replayed = 0;
+        for entry in entries {
+            if entry.lsn <= self.lsn {
+                continue;
+            }
+            self.lsn = entry.lsn;
+            // Transactions read from a position are logged as submitted by clients, other ones
+            // only when they were authorized
+            let origin = match &entry.position {
+                Some(position) => {
+                    self.ingested.advance(position);
+                    Origin::Client
+                }
+                None => Origin::Admin,
+            };
+            let _ = self.apply(entry.transaction, origin);
+            replayed += 1;
+        }
+        self.audit_sink = audit_sink;
+        self.observers = observers;
+        replayed
+    }
+
+    /// Makes every logged transaction durable, if a write-ahead log or state store is open
+    pub fn sync_wal(&mut self) -> io::Result<()> {
+        match (self.wal.as_mut(), self.store.as_mut()) {
+            (Some(wal), _) => wal.sync(),
+            (None, Some(store)) => store.sync(),
+            (None, None) => Ok(()),
+        }
+    }
+
+    /// Saves the current state in the state store the engine was opened with, if any
+    pub fn save_state(&mut self) -> Result<(), SnapshotError> {
+        let Some(mut store) = self.store.take() else {
+            return Ok(());
+        };
+        let saved = store.save(self);
+        self.store = Some(store);
+        saved
+    }
+
+    /// Atomically replaces the snapshot at `path` with the current state, then truncates the
+    /// write-ahead log since its entries are all included in the snapshot
+    pub fn checkpoint<P: AsRef<Path>>(&mut self, path: P) -> Result<(), SnapshotError> {
+        snapshot::write_atomically(self, path.as_ref())?;
+        if let Some(wal) = self.wal.as_mut() {
+            wal.truncate()?;
         }
+        Ok(())
+    }
+
+    /// Same as [`TransactionEngine::checkpoint`], also recording that the first `input_offset`
+    /// rows of a batch input are reflected in the state, so that an interrupted run can resume
+    /// after them
+    pub fn checkpoint_at<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        input_offset: u64,
+    ) -> Result<(), SnapshotError> {
+        self.input_offset = input_offset;
+        self.checkpoint(path)
+    }
+
+    /// Rows of the batch input already reflected in an engine restored from a checkpoint written
+    /// by [`TransactionEngine::checkpoint_at`], 0 otherwise
+    pub fn input_offset(&self) -> u64 {
+        self.input_offset
+    }
+
+    /// Offset and index of the highest position of `source` executed with
+    /// [`TransactionEngine::execute_at`], `None` if no transaction of it was
+    pub fn high_water_mark(&self, source: &str) -> Option<(u64, u32)> {
+        self.ingested.get(source)
+    }
+}
+
+impl Default for TransactionEngine {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl TransactionEngine {
-    pub fn execute(&mut self, transaction: Transaction) {
+    /// Applies a single transaction submitted by a client. Rejected transactions leave the
+    /// accounts unchanged.
+    pub fn execute(&mut self, transaction: Transaction) -> Result<(), EngineError> {
+        self.execute_from(transaction, Origin::Client)
+    }
+
+    /// Applies a single transaction from the given source, which decides whether administrative
+    /// types are accepted
+    pub fn execute_from(
+        &mut self,
+        transaction: Transaction,
+        origin: Origin,
+    ) -> Result<(), EngineError> {
+        self.execute_logged(transaction, origin, None)
+    }
+
+    /// Applies a transaction submitted by a client and read from `position` of an input that may
+    /// deliver it more than once, see the [`ingest`](crate::ingest) module. `None` if a
+    /// transaction at or after that position of the same source was executed already, in which
+    /// case it is skipped. The position is logged along with the transaction, so that it is
+    /// known to be executed again after a restart.
+    pub fn execute_at(
+        &mut self,
+        transaction: Transaction,
+        position: InputPosition,
+    ) -> Option<Result<(), EngineError>> {
+        if self.ingested.includes(&position) {
+            return None;
+        }
+        let result = self.execute_logged(transaction, Origin::Client, Some(&position));
+        if !matches!(result, Err(EngineError::WalWrite { .. })) {
+            self.ingested.advance(&position);
+        }
+        Some(result)
+    }
+
+    /// Logs the transaction if a write-ahead log or state store is open, then applies it.
+    /// Transactions without a position are only logged when authorized.
+    fn execute_logged(
+        &mut self,
+        transaction: Transaction,
+        origin: Origin,
+        position: Option<&InputPosition>,
+    ) -> Result<(), EngineError> {
+        if position.is_some() || authorized(&transaction, origin) {
+            let lsn = self.lsn + 1;
+            let written = match (self.wal.as_mut(), self.store.as_mut()) {
+                (Some(wal), _) => wal.append(lsn, &transaction, position),
+                (None, Some(store)) => store.record(lsn, &transaction, position),
+                (None, None) => Ok(()),
+            };
+            written.map_err(|err| EngineError::WalWrite {
+                transaction_id: transaction.transaction_id,
+                message: err.to_string(),
+            })?;
+        }
+        #[cfg(feature = "chaos")]
+        crate::chaos::hit(crate::chaos::FailPoint::BeforeApply);
+        self.lsn += 1;
+        self.apply(transaction, origin)
+    }
+
+    /// Rules finding the transaction suspicious, with their reasons
+    fn assess(&mut self, transaction: &Transaction) -> Vec<(&'static str, String)> {
+        let lsn = self.lsn;
+        let flags: Vec<_> = self
+            .risk_rules
+            .iter_mut()
+            .filter_map(|rule| {
+                rule.evaluate(lsn, transaction)
+                    .map(|reason| (rule.name(), reason))
+            })
+            .collect();
+        for (rule, reason) in &flags {
+            self.metrics.record_risk_flag(rule);
+            log::info(
+                "Flagged suspicious transaction",
+                &[
+                    ("client", &transaction.client_id),
+                    ("tx", &transaction.transaction_id),
+                    ("rule", rule),
+                    ("reason", reason),
+                ],
+            );
+        }
+        flags
+    }
+
+    fn apply(&mut self, transaction: Transaction, origin: Origin) -> Result<(), EngineError> {
+        let started = Clock::now();
+        let transaction_type = transaction.transaction_type;
+        let (client_id, transaction_id) = (transaction.client_id, transaction.transaction_id);
+        let timestamp = transaction.timestamp;
+        let accounts = self.accounts.len();
+        // Only keep a copy of the transaction around when somebody needs it
+        let copy =
+            (self.audit_sink.is_some() || self.config.record_history || !self.observers.is_empty())
+                .then(|| transaction.clone());
+        // Accounts the transaction can change as they were before it
+        let mut clients = [client_id, transaction.to_client_id.unwrap_or(client_id)];
+        clients.sort_unstable();
+        let clients = &clients[..if clients[0] == clients[1] { 1 } else { 2 }];
+        let observed = self.observe(clients);
+        // Representments can lift the lock, which is audited
+        let was_locked = self.audit_sink.is_some()
+            && self
+                .accounts
+                .get(&client_id)
+                .is_some_and(|account| account.is_locked());
+        let flags = self.assess(&transaction);
+        let funds = GlobalLedger::moves_funds(transaction_type).then(|| self.funds(clients));
+        let result = match flags.first() {
+            _ if !authorized(&transaction, origin) => Err(EngineError::AdminOnly(transaction_id)),
+            Some((rule, reason)) if self.config.risk.block => Err(EngineError::RiskFlagged {
+                client_id,
+                transaction_id,
+                rule,
+                reason: reason.clone(),
+            }),
+            _ => self.dispatch(transaction),
+        };
+        if self.accounts.len() > accounts {
+            self.metrics.record_account_created();
+        }
+        let elapsed = started.elapsed();
+        self.metrics.record(transaction_type, &result, elapsed);
+        if let Some(profile) = &mut self.profile {
+            profile.record(transaction_type, client_id, elapsed);
+        }
+        if log::enabled(Level::Debug) {
+            log::debug(
+                "Executed transaction",
+                &[
+                    ("type", &transaction_type),
+                    ("client", &client_id),
+                    ("tx", &transaction_id),
+                    (
+                        "result",
+                        &result.as_ref().map_or_else(|err| err.code(), |_| "applied"),
+                    ),
+                ],
+            );
+        }
+        if let Some(sink) = self.audit_sink.as_mut() {
+            for (rule, reason) in flags {
+                sink.record(AuditEvent::RiskFlagged {
+                    client_id,
+                    transaction_id,
+                    rule,
+                    reason,
+                });
+            }
+        }
+        if let Some(transaction) = copy {
+            if result.is_ok() {
+                if self.config.record_history {
+                    self.ledger.record(transaction.clone());
+                }
+                for observer in &mut self.observers {
+                    observer.on_applied(&transaction);
+                }
+                let dispute = match transaction_type {
+                    TransactionType::Dispute => Some(DisputeState::Disputed),
+                    TransactionType::Resolve => Some(DisputeState::Resolved),
+                    TransactionType::Chargeback => Some(DisputeState::ChargedBack),
+                    TransactionType::Represent => Some(DisputeState::Represented),
+                    _ => None,
+                };
+                self.notify(
+                    clients,
+                    observed,
+                    dispute.map(|state| (client_id, transaction_id, state)),
+                );
+            }
+            self.audit(transaction, &result, was_locked);
+        }
+        if result.is_ok() {
+            if let Some(before) = funds {
+                let after = self.funds(clients);
+                self.books.record(transaction_type, &before, &after);
+            }
+            for client_id in clients {
+                self.last_active.insert(*client_id, self.lsn);
+            }
+            match transaction_type {
+                TransactionType::Dispute => self.expiry.open(
+                    transaction_id,
+                    OpenDispute {
+                        client_id,
+                        opened: self.lsn,
+                        timestamp,
+                    },
+                ),
+                TransactionType::Resolve | TransactionType::Chargeback => {
+                    self.expiry.close(transaction_id)
+                }
+                _ => {}
+            }
+        }
+        self.expire_disputes(timestamp);
+        result
+    }
+
+    /// Resolves the disputes that expired under the dispute expiry policy, with `now` the
+    /// timestamp of the transaction just executed. Each is recorded in the history as a resolve
+    /// and audited as [`AuditEvent::DisputeExpired`].
+    fn expire_disputes(&mut self, now: Option<Timestamp>) {
+        for (client_id, transaction_id) in self.expiry.expire(self.lsn, now) {
+            let observed = self.observe(&[client_id]);
+            let result = match self.accounts.get_mut(&client_id) {
+                Some(account) => account.resolve(transaction_id),
+                None => continue,
+            };
+            if let Err(err) = result {
+                log::warn(
+                    "Could not resolve expired dispute",
+                    &[
+                        ("client", &client_id),
+                        ("tx", &transaction_id),
+                        ("error", &err),
+                    ],
+                );
+                continue;
+            }
+            self.metrics.record_expired_dispute();
+            self.notify(
+                &[client_id],
+                observed,
+                Some((client_id, transaction_id, DisputeState::Resolved)),
+            );
+            if self.config.record_history {
+                self.ledger.record(Transaction {
+                    transaction_type: TransactionType::Resolve,
+                    client_id,
+                    transaction_id,
+                    amount: None,
+                    to_client_id: None,
+                    currency: None,
+                    timestamp: now,
+                    metadata: Default::default(),
+                });
+            }
+            if let Some(sink) = self.audit_sink.as_mut() {
+                sink.record(AuditEvent::DisputeExpired {
+                    client_id,
+                    transaction_id,
+                });
+            }
+        }
+    }
+
+    /// Applies every transaction of a csv or ndjson file, the format guessed from its extension,
+    /// as a client would submit them. Meant for bindings to other languages, which get the
+    /// problems found in the file back in the report. Only fails if the file cannot be read.
+    pub fn process_file<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<ValidationReport, InputError> {
+        let path = path.as_ref();
+        let mut source = open_source(path, InputFormat::from_path(path))?;
+        ValidationReport::collect(self, &mut source, Origin::Client, &ClientFilter::default())
+    }
+
+    /// Applies every row of a batch of columns as a client would submit them, without going
+    /// through csv, e.g. straight from the buffers of an Arrow record batch. Amounts are checked
+    /// against the default [`AmountRules`]. Rows that cannot be read are malformed, so this only
+    /// fails if the batch as a whole cannot be.
+    pub fn execute_columns(
+        &mut self,
+        batch: &ColumnBatch<'_>,
+    ) -> Result<ValidationReport, EngineError> {
+        let mut source = batch.source(AmountRules::default());
+        ValidationReport::collect(self, &mut source, Origin::Client, &ClientFilter::default())
+            .map_err(|err| EngineError::UnreadableBatch(err.to_string()))
+    }
+
+    /// Applied transactions of the client, empty unless `record_history` is enabled
+    pub fn history(&self, client_id: ClientId) -> History<'_> {
+        self.ledger.history(client_id)
+    }
+
+    fn dispatch(&mut self, transaction: Transaction) -> Result<(), EngineError> {
+        let client_id = transaction.client_id;
+        // Closed accounts take nothing, not even transfers or merges into them
+        if let Some(closed) = [Some(client_id), transaction.to_client_id]
+            .into_iter()
+            .flatten()
+            .find(|client_id| self.closed.contains(client_id))
+        {
+            return Err(EngineError::AccountClosed {
+                client_id: closed,
+                transaction_id: transaction.transaction_id,
+            });
+        }
+        // Administrative transactions do not count towards the limits of the client
+        let max_transactions = self
+            .config
+            .limits_for(client_id)
+            .max_transactions_per_client
+            .filter(|_| !transaction.transaction_type.is_admin());
+        if let Some(max) = max_transactions {
+            if self
+                .usage
+                .get(&client_id)
+                .map_or(0, |usage| usage.transactions)
+                >= max
+            {
+                return Err(EngineError::LimitExceeded {
+                    client_id,
+                    transaction_id: transaction.transaction_id,
+                    limit: Limit::TransactionCount(max),
+                });
+            }
+        }
+
+        let result = match transaction.transaction_type {
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Authorize => {
+                self.execute_new(transaction)
+            }
+            TransactionType::Transfer => self.execute_transfer(transaction),
+            TransactionType::Dispute
+            | TransactionType::Resolve
+            | TransactionType::Chargeback
+            | TransactionType::Capture
+            | TransactionType::Void
+            | TransactionType::Represent => self.execute_reference(transaction),
+            TransactionType::Lock | TransactionType::Unlock => self.execute_admin(transaction),
+            TransactionType::Fee | TransactionType::Interest => {
+                self.execute_adjustment(transaction)
+            }
+            TransactionType::Reversal => self.execute_reversal(transaction),
+            TransactionType::Merge => self.execute_merge(transaction),
+            TransactionType::Adjustment => self.execute_ledger_adjustment(transaction),
+            TransactionType::Close => self.execute_close(transaction),
+            TransactionType::Reserve => self.execute_reserve(transaction),
+        };
+        if result.is_ok() && max_transactions.is_some() {
+            self.usage.entry(client_id).or_default().transactions += 1;
+        }
+        result
+    }
+
+    /// Charges the fees and credits the interest of the schedule to every unlocked account, in
+    /// order of client id, as an end-of-day batch step. Every charge and credit is executed as an
+    /// administrative [`TransactionType::Fee`] or [`TransactionType::Interest`] transaction, so
+    /// it is logged, audited and recorded in the history like any other.
+    pub fn apply_fees(&mut self, schedule: &FeeSchedule) -> FeeSummary {
+        let mut summary = FeeSummary::default();
+        let mut clients: Vec<ClientId> = self.accounts.keys().copied().collect();
+        clients.sort_unstable();
+        for client_id in clients {
+            let account = &self.accounts[&client_id];
+            if account.is_locked() {
+                summary.skipped_locked += 1;
+                continue;
+            }
+            let available = account.balance(schedule.currency).available;
+            for transaction in schedule.transactions(client_id, available) {
+                match self.execute_from(transaction.clone(), Origin::Admin) {
+                    Ok(()) => summary.record(&transaction),
+                    Err(err) => summary.errors.push(err),
+                }
+            }
+        }
+        summary
+    }
+
+    /// Merges the account of `client_id` into that of `into`, e.g. when the same customer was
+    /// registered under both ids. Executed as an administrative [`TransactionType::Merge`] of the
+    /// client with transaction id 0, so it is logged, audited and recorded in the history like
+    /// any other. See [`EngineError::MergeConflict`] for the merges that are refused.
+    pub fn merge_clients(
+        &mut self,
+        client_id: ClientId,
+        into: ClientId,
+    ) -> Result<(), EngineError> {
+        let merge = Transaction {
+            transaction_type: TransactionType::Merge,
+            client_id,
+            transaction_id: TransactionId(0),
+            amount: None,
+            to_client_id: Some(into),
+            currency: None,
+            timestamp: None,
+            metadata: Default::default(),
+        };
+        self.execute_from(merge, Origin::Admin)
+    }
+
+    /// Closes the account of the client, e.g. when the customer leaves, so that it takes no further
+    /// transactions and is written as closed. Executed as an administrative
+    /// [`TransactionType::Close`] of the client with transaction id 0, so it is logged, audited and
+    /// recorded in the history like any other. Refused with [`EngineError::HeldFunds`] while the
+    /// account holds funds in any currency.
+    pub fn close_account(&mut self, client_id: ClientId) -> Result<(), EngineError> {
+        let close = Transaction {
+            transaction_type: TransactionType::Close,
+            client_id,
+            transaction_id: TransactionId(0),
+            amount: None,
+            to_client_id: None,
+            currency: None,
+            timestamp: None,
+            metadata: Default::default(),
+        };
+        self.execute_from(close, Origin::Admin)
+    }
+
+    /// Whether the account of the client was closed
+    pub fn is_closed(&self, client_id: ClientId) -> bool {
+        self.closed.contains(&client_id)
+    }
+
+    /// Keeps `reserve` of the available funds of the client in the currency out of reach of its
+    /// withdrawals, authorizations and transfers, in place of the reserve of its tier. Executed as
+    /// an administrative [`TransactionType::Reserve`] of the client with transaction id 0, so it
+    /// is logged, audited and recorded in the history like any other. A reserve of 0 releases the
+    /// funds whatever the tier.
+    pub fn set_reserve(
+        &mut self,
+        client_id: ClientId,
+        currency: Option<Currency>,
+        reserve: Amount,
+    ) -> Result<(), EngineError> {
+        let transaction = Transaction {
+            transaction_type: TransactionType::Reserve,
+            client_id,
+            transaction_id: TransactionId(0),
+            amount: Some(reserve),
+            to_client_id: None,
+            currency,
+            timestamp: None,
+            metadata: Default::default(),
+        };
+        self.execute_from(transaction, Origin::Admin)
+    }
+
+    /// Part of the available funds of the client in the currency that withdrawals, authorizations
+    /// and transfers cannot take: the reserve set for the account if any, otherwise that of its
+    /// tier for funds without currency, otherwise none
+    pub fn reserve(&self, client_id: ClientId, currency: Option<Currency>) -> f64 {
+        self.reserves
+            .get(&(client_id, currency))
+            .copied()
+            .or_else(|| {
+                currency
+                    .is_none()
+                    .then(|| self.config.tiers.policy(client_id)?.reserve)
+                    .flatten()
+            })
+            .unwrap_or(0.0)
+    }
+
+    /// Open accounts to which no transaction was applied in the last `transactions` ones executed
+    /// by the engine, in order of client id. Accounts restored from snapshots that did not record
+    /// their activity count as last active at the restored position.
+    pub fn dormant_accounts(&self, transactions: u64) -> Vec<ClientId> {
+        let mut dormant: Vec<_> = self
+            .accounts
+            .keys()
+            .filter(|client_id| !self.closed.contains(client_id))
+            .filter(|client_id| {
+                let last_active = self.last_active.get(client_id).copied().unwrap_or_default();
+                self.lsn.saturating_sub(last_active) >= transactions
+            })
+            .copied()
+            .collect();
+        dormant.sort_unstable();
+        dormant
+    }
+
+    /// Undoes an applied deposit or withdrawal for an operational correction, outside of the
+    /// dispute flow. Executed as an administrative [`TransactionType::Reversal`] of the owner of
+    /// the transaction, so it is logged, audited and recorded in the history like any other.
+    pub fn reverse(&mut self, transaction_id: TransactionId) -> Result<(), EngineError> {
+        let seen = self
+            .seen_transactions
+            .get(&transaction_id)
+            .filter(|seen| seen.applied)
+            .ok_or(EngineError::UnknownTransaction(transaction_id))?;
+        let reversal = Transaction {
+            transaction_type: TransactionType::Reversal,
+            client_id: seen.client_id,
+            transaction_id,
+            amount: Some(Amount::unchecked(seen.amount)),
+            to_client_id: None,
+            currency: seen.currency,
+            timestamp: None,
+            metadata: Default::default(),
+        };
+        self.execute_from(reversal, Origin::Admin)
+    }
+
+    /// Reverses the last `count` deposits and withdrawals in the history, newest first, e.g.
+    /// those of a file applied twice, and returns their ids. Needs
+    /// [`EngineConfig::record_history`]; fewer are undone if the history holds fewer, none without
+    /// it. Transactions already reversed, and their reversals, are passed over.
+    ///
+    /// Nothing is undone if any of them cannot be: the undo is refused as
+    /// [`EngineError::NotReversible`] if one is neither a deposit nor a withdrawal, such as a
+    /// dispute, a lock or a transfer, or is under dispute or charged back, and with the errors of
+    /// [`TransactionEngine::reverse`] if its account is locked or closed. Undoing the newest
+    /// transactions first restores the funds they found, so reversals only fail once checked if
+    /// earlier reversals already took those funds; the undo then stops, keeping what it undid.
+    pub fn undo_last(&mut self, count: usize) -> Result<Vec<TransactionId>, EngineError> {
+        let mut reversed = HashSet::new();
+        let mut undone = Vec::new();
+        for entry in self.ledger.in_order().into_iter().rev() {
+            if undone.len() == count {
+                break;
+            }
+            let transaction = &entry.transaction;
+            match transaction.transaction_type {
+                TransactionType::Reversal => {
+                    reversed.insert(transaction.transaction_id);
+                }
+                TransactionType::Deposit | TransactionType::Withdrawal
+                    if reversed.contains(&transaction.transaction_id) => {}
+                _ => undone.push(transaction.clone()),
+            }
+        }
+        for transaction in &undone {
+            self.check_undoable(transaction)?;
+        }
+        for transaction in &undone {
+            self.reverse(transaction.transaction_id)?;
+        }
+        Ok(undone
+            .iter()
+            .map(|transaction| transaction.transaction_id)
+            .collect())
+    }
+
+    /// Whether [`TransactionEngine::reverse`] would take the transaction back as it stands
+    fn check_undoable(&self, transaction: &Transaction) -> Result<(), EngineError> {
+        let transaction_id = transaction.transaction_id;
+        let client_id = transaction.client_id;
+        if !matches!(
+            transaction.transaction_type,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        ) {
+            return Err(EngineError::NotReversible(transaction_id));
+        }
+        if self.closed.contains(&client_id) {
+            return Err(EngineError::AccountClosed {
+                client_id,
+                transaction_id,
+            });
+        }
         let account = self
+            .seen_transactions
+            .get(&transaction_id)
+            .filter(|seen| seen.applied)
+            .and_then(|_| self.accounts.get(&client_id))
+            .ok_or(EngineError::UnknownTransaction(transaction_id))?;
+        check_lock(
+            self.config.lock_policy,
+            account.as_ref(),
+            TransactionType::Reversal,
+            transaction_id,
+        )?;
+        if matches!(
+            account.dispute_state(transaction_id).0,
+            DisputeState::Disputed | DisputeState::ChargedBack
+        ) {
+            return Err(EngineError::NotReversible(transaction_id));
+        }
+        Ok(())
+    }
+
+    /// Starts a new day for the daily withdrawal cap. Only needed for transactions without a
+    /// timestamp, timestamped ones are counted per day of their timestamp.
+    pub fn reset_daily_limits(&mut self) {
+        for usage in self.usage.values_mut() {
+            usage.withdrawn.clear();
+        }
+    }
+
+    /// Lets `ticks` ticks pass on every account, settling the withdrawals of savings accounts whose
+    /// delay ran out. What a tick stands for, e.g. a day or a batch of transactions, is up to the
+    /// caller.
+    pub fn advance(&mut self, ticks: u32) {
+        let mut clients = Vec::new();
+        if !self.observers.is_empty() {
+            clients.extend(self.accounts.keys().copied());
+            clients.sort_unstable();
+        }
+        let observed = self.observe(&clients);
+        let before = books::funds(self.accounts.values().map(AsRef::as_ref));
+        for account in self.accounts.values_mut() {
+            account.advance(ticks);
+        }
+        // Withdrawals that settled leave the accounts
+        let after = books::funds(self.accounts.values().map(AsRef::as_ref));
+        self.books
+            .record(TransactionType::Withdrawal, &before, &after);
+        self.notify(&clients, observed, None);
+    }
+
+    /// Lets `ticks` ticks pass as [`TransactionEngine::advance`] does, then time up to `now`,
+    /// executing the transactions of the standing orders of `schedule` as they fall due: after
+    /// each tick those in ticks, then those in seconds in order of their timestamps. They are
+    /// executed as client transactions, so they are logged, audited and recorded in the history
+    /// like any other.
+    pub fn advance_time(
+        &mut self,
+        schedule: &mut Schedule,
+        ticks: u32,
+        now: Option<Timestamp>,
+    ) -> ScheduleSummary {
+        let mut summary = ScheduleSummary::default();
+        let mut run = |engine: &mut Self, transactions: Vec<Transaction>| {
+            for transaction in transactions {
+                match engine.execute(transaction) {
+                    Ok(()) => summary.executed += 1,
+                    Err(err) => summary.errors.push(err),
+                }
+            }
+        };
+        for _ in 0..ticks {
+            self.advance(1);
+            run(self, schedule.tick());
+        }
+        if let Some(now) = now {
+            run(self, schedule.until(now));
+        }
+        summary
+    }
+
+    /// Engine with the accounts as they were right after the transaction `transaction_id`, the
+    /// first one recorded with that id, e.g. to tell the balance of a client before a dispute of
+    /// it. The history of this engine is replayed on a new one, which records it up to the
+    /// transaction. Needs the full history, recorded with [`EngineConfig::record_history`] since
+    /// the engine was new and carried over by the snapshots it was restored from. The history of
+    /// a merged client is that of the client it was merged into, even before the merge.
+    pub fn state_at(
+        &self,
+        transaction_id: TransactionId,
+    ) -> Result<TransactionEngine, EngineError> {
+        let entries = self.ledger.in_order();
+        let end = entries
+            .iter()
+            .position(|entry| entry.transaction.transaction_id == transaction_id)
+            .ok_or(EngineError::UnknownTransaction(transaction_id))?;
+        let mut replica = Self::with_config(self.config.clone()).into_replica();
+        replica.config.record_history = true;
+        for (entry, err) in replica.replay_history(&entries[..=end]) {
+            log::warn(
+                "Could not replay ledger entry",
+                &[("sequence", &entry.sequence), ("error", &err)],
+            );
+        }
+        Ok(replica)
+    }
+
+    /// Applies entries of a history again in order, as administrative transactions so that
+    /// nothing the client was allowed to do is refused. Returns those that could not be applied
+    /// with the reason.
+    pub(crate) fn replay_history<'a>(
+        &mut self,
+        entries: &[&'a LedgerEntry],
+    ) -> Vec<(&'a LedgerEntry, EngineError)> {
+        let mut failed = Vec::new();
+        for &entry in entries {
+            let transaction = entry.transaction.clone();
+            // The history of a merged client was rewritten as made by the client it was merged
+            // into, which leaves nothing to merge unless its account predates the history
+            if transaction.transaction_type == TransactionType::Merge
+                && !self.accounts.contains_key(&transaction.client_id)
+            {
+                continue;
+            }
+            if let Err(err) = self.execute_from(transaction, Origin::Admin) {
+                failed.push((entry, err));
+            }
+        }
+        failed
+    }
+
+    /// Engine with the accounts of this one that applies transactions again the way it did, to
+    /// replay its history: limits, risk rules, retention and the dispute window only ever reject
+    /// transactions, which are not recorded, and the resolves of expired disputes are recorded.
+    /// Logs, stores and the audit sink are left out.
+    pub(crate) fn into_replica(mut self) -> Self {
+        self.config.limits = LimitsPolicy::default();
+        Arc::make_mut(&mut self.config.tiers).clear_limits();
+        self.config.risk.block = false;
+        self.config.dispute_window = None;
+        self.config.dispute_expiry = DisputeExpiry::default();
+        self.config.retention_policy = RetentionPolicy::Unbounded;
+        self.config.record_history = false;
+        self.config.profile = false;
+        self.profile = None;
+        self.risk_rules.clear();
+        self.retention = Retention::new(RetentionPolicy::Unbounded);
+        self.expiry = Expiry::new(DisputeExpiry::default());
+        self.audit_sink = None;
+        self.observers.clear();
+        self.wal = None;
+        self.store = None;
+        self
+    }
+
+    /// Writes the full state of the engine so that processing can later continue from it
+    pub fn snapshot<P: AsRef<Path>>(&self, path: P) -> Result<(), SnapshotError> {
+        snapshot::write_snapshot(self, BufWriter::new(File::create(path)?))
+    }
+
+    /// Recreates an engine from a snapshot written by [`TransactionEngine::snapshot`]
+    pub fn restore<P: AsRef<Path>>(path: P) -> Result<Self, SnapshotError> {
+        Self::restore_with_config(path, EngineConfig::default())
+    }
+
+    pub fn restore_with_config<P: AsRef<Path>>(
+        path: P,
+        config: EngineConfig,
+    ) -> Result<Self, SnapshotError> {
+        snapshot::read_snapshot(File::open(path)?, config)
+    }
+
+    /// Writes the full state of the engine in the documented [`exchange`] format, for other
+    /// versions of the engine and external tools
+    pub fn export<W: Write>(&self, writer: W) -> io::Result<()> {
+        exchange::export(self, writer)
+    }
+
+    /// Recreates an engine from a state written by [`TransactionEngine::export`], possibly by
+    /// another version of the engine
+    pub fn import<R: BufRead>(reader: R, config: EngineConfig) -> Result<Self, ExchangeError> {
+        exchange::import(reader, config)
+    }
+
+    /// Saves the state in memory so that the transactions executed next, e.g. the rows of a file
+    /// that must settle as a whole, can be discarded with [`TransactionEngine::rollback`]. Keeping
+    /// them takes nothing but dropping the batch. Accounts kept on disk cannot be staged.
+    pub fn begin_batch(&self) -> Result<Batch, SnapshotError> {
+        if let Storage::Disk(_) = self.config.storage {
+            return Err(SnapshotError::Io(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "batches cannot stage accounts kept on disk",
+            )));
+        }
+        let mut saved = Vec::new();
+        snapshot::write_snapshot(self, &mut saved)?;
+        Ok(Batch {
+            saved,
+            rejected: self.metrics.total_rejected(),
+        })
+    }
+
+    /// Independent copy of the engine, to apply hypothetical transactions to, e.g. those of a file
+    /// of disputes, inspect the accounts they leave and drop it. The copy keeps its accounts in
+    /// memory even if these are kept on disk, and starts without metrics, audit sink, observers,
+    /// risk rules or write-ahead log, so that nothing it does is seen outside of it.
+    pub fn fork(&self) -> Result<TransactionEngine, SnapshotError> {
+        let mut saved = Vec::new();
+        snapshot::write_snapshot(self, &mut saved)?;
+        let config = EngineConfig {
+            storage: Storage::Memory,
+            ..self.config.clone()
+        };
+        snapshot::read_snapshot(saved.as_slice(), config)
+    }
+
+    /// Returns the accounts, seen transactions and history to what they were when the batch
+    /// began. Metrics, the audit sink and observers keep what they saw of the discarded
+    /// transactions, as does the write-ahead log, which a checkpoint should truncate.
+    pub fn rollback(&mut self, batch: Batch) -> Result<(), SnapshotError> {
+        let saved = snapshot::read_snapshot(batch.saved.as_slice(), self.config.clone())?;
+        self.accounts = saved.accounts;
+        self.seen_transactions = saved.seen_transactions;
+        self.usage = saved.usage;
+        self.closed = saved.closed;
+        self.reserves = saved.reserves;
+        self.last_active = saved.last_active;
+        self.retention = saved.retention;
+        self.expiry = saved.expiry;
+        self.ledger = saved.ledger;
+        self.books = saved.books;
+        self.lsn = saved.lsn;
+        self.input_offset = saved.input_offset;
+        self.ingested = saved.ingested;
+        Ok(())
+    }
+
+    /// Total funds of the accounts of `clients` per currency
+    fn funds(&self, clients: &[ClientId]) -> BTreeMap<Option<Currency>, f64> {
+        books::funds(
+            clients
+                .iter()
+                .filter_map(|client_id| self.accounts.get(client_id))
+                .map(AsRef::as_ref),
+        )
+    }
+
+    /// Funds and lock of the accounts of `clients`, nothing unless somebody observes them
+    fn observe(&self, clients: &[ClientId]) -> Vec<Observed> {
+        if self.observers.is_empty() {
+            return Vec::new();
+        }
+        clients
+            .iter()
+            .map(|client_id| {
+                Observed::of(
+                    *client_id,
+                    self.accounts.get(client_id).map(|account| account.as_ref()),
+                )
+            })
+            .collect()
+    }
+
+    /// Tells the observers how the accounts of `clients` changed since they were `observed`
+    fn notify(
+        &mut self,
+        clients: &[ClientId],
+        observed: Vec<Observed>,
+        dispute: Option<(ClientId, TransactionId, DisputeState)>,
+    ) {
+        if self.observers.is_empty() {
+            return;
+        }
+        let now = self.observe(clients);
+        observer::notify(&mut self.observers, &observed, &now, dispute);
+    }
+
+    fn audit(
+        &mut self,
+        transaction: Transaction,
+        result: &Result<(), EngineError>,
+        was_locked: bool,
+    ) {
+        let locked = self
             .accounts
-            .entry(transaction.client_id)
-            .or_insert_with(|| Box::new(BasicAccount::new(transaction.client_id)));
+            .get(&transaction.client_id)
+            .is_some_and(|account| account.is_locked());
+        // Adjustments are flagged since they bypass the funds check
+        let available = self
+            .accounts
+            .get(&transaction.client_id)
+            .map_or(0.0, |account| {
+                account.balance(transaction.currency).available
+            });
+        let sink = match self.audit_sink.as_mut() {
+            Some(sink) => sink,
+            None => return,
+        };
+        if let Err(err) = result {
+            sink.record(AuditEvent::Rejected {
+                transaction,
+                reason: err.to_string(),
+            });
+            return;
+        }
 
-        match transaction.transaction_type {
-            TransactionType::Deposit => {
-                if let Some(amount) = transaction.amount {
-                    account.deposit(transaction.transaction_id, amount)
+        let client_id = transaction.client_id;
+        let transaction_id = transaction.transaction_id;
+        let transaction_type = transaction.transaction_type;
+        let to_client_id = transaction.to_client_id;
+        let amount = transaction.amount.unwrap_or_default().value();
+        sink.record(AuditEvent::Applied(transaction));
+        match transaction_type {
+            TransactionType::Dispute => sink.record(AuditEvent::DisputeOpened {
+                client_id,
+                transaction_id,
+            }),
+            TransactionType::Resolve => sink.record(AuditEvent::Resolved {
+                client_id,
+                transaction_id,
+            }),
+            TransactionType::Chargeback => {
+                sink.record(AuditEvent::Chargeback {
+                    client_id,
+                    transaction_id,
+                });
+                if locked && !was_locked {
+                    sink.record(AuditEvent::AccountLocked { client_id });
                 }
-                // TODO: log bad transaction type if there is no amount for deposit/withdrawal
             }
-            TransactionType::Withdrawal => {
-                if let Some(amount) = transaction.amount {
-                    account.withdraw(transaction.transaction_id, amount)
+            TransactionType::Represent => {
+                sink.record(AuditEvent::Represented {
+                    client_id,
+                    transaction_id,
+                });
+                if was_locked && !locked {
+                    sink.record(AuditEvent::AccountUnlocked { client_id });
+                }
+            }
+            TransactionType::Lock => sink.record(AuditEvent::AccountLocked { client_id }),
+            TransactionType::Unlock => sink.record(AuditEvent::AccountUnlocked { client_id }),
+            TransactionType::Reversal => sink.record(AuditEvent::Reversed {
+                client_id,
+                transaction_id,
+            }),
+            TransactionType::Merge => {
+                if let Some(into) = to_client_id {
+                    sink.record(AuditEvent::AccountsMerged { client_id, into })
                 }
             }
-            TransactionType::Dispute => account.dispute(transaction.transaction_id),
-            TransactionType::Resolve => account.resolve(transaction.transaction_id),
-            TransactionType::Chargeback => account.chargeback(transaction.transaction_id),
+            TransactionType::Close => sink.record(AuditEvent::AccountClosed { client_id }),
+            TransactionType::Adjustment => sink.record(AuditEvent::Adjusted {
+                client_id,
+                transaction_id,
+                amount,
+                overdrawn: available < 0.0,
+            }),
+            TransactionType::Deposit
+            | TransactionType::Withdrawal
+            | TransactionType::Transfer
+            | TransactionType::Fee
+            | TransactionType::Interest
+            | TransactionType::Authorize
+            | TransactionType::Capture
+            | TransactionType::Void
+            | TransactionType::Reserve => {}
+        }
+    }
+
+    /// Takes over the accounts of another engine that processed a disjoint set of clients
+    pub(crate) fn absorb(&mut self, other: TransactionEngine) {
+        self.accounts.extend(other.accounts);
+        self.seen_transactions.extend(other.seen_transactions);
+        self.usage.extend(other.usage);
+        self.closed.extend(other.closed);
+        self.reserves.extend(other.reserves);
+        // Activity is tracked by the log sequence numbers of the other engine, which follow ours
+        let lsn = self.lsn;
+        self.last_active.extend(
+            other
+                .last_active
+                .into_iter()
+                .map(|(client_id, last_active)| (client_id, lsn + last_active)),
+        );
+        self.retention.absorb(other.retention);
+        self.expiry.absorb(other.expiry, self.lsn);
+        self.ledger.absorb(other.ledger);
+        self.books.absorb(other.books);
+        self.lsn += other.lsn;
+        self.metrics.merge(&other.metrics);
+        if let (Some(profile), Some(other)) = (&mut self.profile, &other.profile) {
+            profile.merge(other);
+        }
+    }
+
+    /// Funds without currency of the client's account, if the client has one
+    pub fn account_snapshot(&self, client_id: ClientId) -> Option<AccountSnapshot> {
+        self.accounts
+            .get(&client_id)
+            .map(|account| AccountSnapshot {
+                reserved: self.reserve(client_id, None),
+                closed: self.is_closed(client_id),
+                ..AccountSnapshot::new(account.as_ref(), None)
+            })
+    }
+
+    /// One snapshot per currency of the account with its reserve, marked closed if it was
+    pub(crate) fn account_snapshots<'a>(
+        &'a self,
+        account: &'a dyn ClientAccount,
+    ) -> impl Iterator<Item = AccountSnapshot> + 'a {
+        let client_id = account.get_client_id();
+        let closed = self.is_closed(client_id);
+        AccountSnapshot::all(account).map(move |snapshot| AccountSnapshot {
+            reserved: self.reserve(client_id, snapshot.currency),
+            closed,
+            ..snapshot
+        })
+    }
+
+    /// Funds of every account, one snapshot per currency of each, in no particular order
+    pub fn iter_snapshots(&self) -> impl Iterator<Item = AccountSnapshot> + '_ {
+        self.accounts
+            .values()
+            .flat_map(|account| self.account_snapshots(account.as_ref()))
+    }
+
+    /// Same as [`TransactionEngine::iter_snapshots`] ordered by client id then currency
+    pub fn sorted_snapshots(&self) -> impl Iterator<Item = AccountSnapshot> + '_ {
+        let accounts: BTreeMap<_, _> = self.accounts.iter().collect();
+        accounts
+            .into_values()
+            .flat_map(|account| self.account_snapshots(account.as_ref()))
+    }
+
+    /// Snapshots of every account in the given order
+    pub fn snapshots(&self, order: OutputOrder) -> Box<dyn Iterator<Item = AccountSnapshot> + '_> {
+        match order {
+            OutputOrder::None => Box::new(self.iter_snapshots()),
+            OutputOrder::Client => Box::new(self.sorted_snapshots()),
+        }
+    }
+
+    /// Writes the snapshots of every account in the given order with the columns of the
+    /// projection after the standard ones. The writer is left to be finished.
+    pub fn write_projected(
+        &self,
+        writer: &mut dyn AccountWriter,
+        order: OutputOrder,
+        projection: &dyn AccountProjection,
+    ) -> io::Result<()> {
+        writer.set_projected_columns(projection.columns());
+        for snapshot in self.snapshots(order) {
+            let account = self.accounts[&snapshot.client].as_ref();
+            writer.write_projected(&snapshot, &projection.row(account, &snapshot)?)?;
+        }
+        Ok(())
+    }
+
+    /// Client that owns the given deposit or withdrawal, if it was applied. The owner of a
+    /// transfer is the debited client.
+    pub fn transaction_owner(&self, transaction_id: TransactionId) -> Option<ClientId> {
+        self.seen_transactions
+            .get(&transaction_id)
+            .filter(|seen| seen.applied)
+            .map(|seen| seen.client_id)
+    }
+
+    /// Deposits and withdrawals create the account if needed and become disputable. Authorizations
+    /// create it as well, and only become disputable once captured.
+    fn execute_new(&mut self, transaction: Transaction) -> Result<(), EngineError> {
+        let client_id = transaction.client_id;
+        let transaction_id = transaction.transaction_id;
+        let amount = transaction
+            .amount
+            .map(Amount::value)
+            .ok_or(EngineError::MissingAmount(transaction_id))?;
+        if !self.register_new(&transaction, amount)? {
+            return Ok(());
+        }
+        // Authorizations count towards the daily withdrawal cap when they are made, whether or not
+        // they are captured later
+        let withdrawal = matches!(
+            transaction.transaction_type,
+            TransactionType::Withdrawal | TransactionType::Authorize
+        );
+        self.check_amount_limits(&transaction, amount, withdrawal)?;
+
+        let lock_policy = self.config.lock_policy;
+        let reserve = self.reserve(client_id, transaction.currency);
+        let account = self.account_mut(client_id);
+        check_lock(
+            lock_policy,
+            account.as_ref(),
+            transaction.transaction_type,
+            transaction_id,
+        )?;
+        if withdrawal {
+            check_reserve(account.as_ref(), reserve, &transaction, amount)?;
+        }
+
+        let result = match transaction.transaction_type {
+            TransactionType::Withdrawal => {
+                account.withdraw(transaction_id, amount, transaction.currency)
+            }
+            TransactionType::Authorize => {
+                account.authorize(transaction_id, amount, transaction.currency)
+            }
+            _ => account.deposit(transaction_id, amount, transaction.currency),
+        };
+        result.map_err(|source| EngineError::Account { client_id, source })?;
+
+        if withdrawal {
+            self.record_withdrawal(&transaction, amount);
+        }
+        self.mark_applied(transaction_id);
+        Ok(())
+    }
+
+    /// Transfers debit the client and credit the destination under the same transaction id.
+    /// Each leg is disputable by its own client like a withdrawal and a deposit respectively.
+    fn execute_transfer(&mut self, transaction: Transaction) -> Result<(), EngineError> {
+        let client_id = transaction.client_id;
+        let transaction_id = transaction.transaction_id;
+        let amount = transaction
+            .amount
+            .map(Amount::value)
+            .ok_or(EngineError::MissingAmount(transaction_id))?;
+        let to_client_id = transaction
+            .to_client_id
+            .ok_or(EngineError::MissingDestination(transaction_id))?;
+        if to_client_id == client_id {
+            return Err(EngineError::SelfTransfer(transaction_id));
+        }
+        if !self.register_new(&transaction, amount)? {
+            return Ok(());
+        }
+        self.check_amount_limits(&transaction, amount, true)?;
+
+        // Both accounts are checked before either is changed so that a refused transfer leaves
+        // them untouched, and the destination is only created once the transfer is applied.
+        // Deposits are otherwise only refused when their log cannot be written, which leaves the
+        // destination unchanged, so the destination is credited first and the credit taken back
+        // if the debit is refused.
+        let lock_policy = self.config.lock_policy;
+        let reserve = self.reserve(client_id, transaction.currency);
+        let source = self.account_mut(client_id);
+        check_lock(
+            lock_policy,
+            source.as_ref(),
+            TransactionType::Transfer,
+            transaction_id,
+        )?;
+        check_reserve(source.as_ref(), reserve, &transaction, amount)?;
+        if let Some(destination) = self.accounts.get(&to_client_id) {
+            check_lock(
+                lock_policy,
+                destination.as_ref(),
+                TransactionType::Deposit,
+                transaction_id,
+            )?;
+        }
+        let created = !self.accounts.contains_key(&to_client_id);
+        let credited =
+            self.account_mut(to_client_id)
+                .deposit(transaction_id, amount, transaction.currency);
+        if let Err(source) = credited {
+            if created {
+                self.accounts.remove(&to_client_id);
+            }
+            return Err(EngineError::Account {
+                client_id: to_client_id,
+                source,
+            });
+        }
+        let debited =
+            self.account_mut(client_id)
+                .withdraw(transaction_id, amount, transaction.currency);
+        if let Err(source) = debited {
+            self.take_back_credit(to_client_id, transaction_id, amount, &transaction, created);
+            return Err(EngineError::Account { client_id, source });
+        }
+
+        self.record_withdrawal(&transaction, amount);
+        self.mark_applied(transaction_id);
+        Ok(())
+    }
+
+    /// Undoes the credit of a transfer whose debit was refused, dropping the destination if the
+    /// transfer created it. Nothing of the credit stays behind in an on-disk store.
+    fn take_back_credit(
+        &mut self,
+        to_client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: f64,
+        transaction: &Transaction,
+        created: bool,
+    ) {
+        let Some(destination) = self.accounts.get_mut(&to_client_id) else {
+            return;
+        };
+        if !created {
+            // Taken back as an adjustment, which may leave the available funds negative if the
+            // credit went to disputes waiting for funds. Its log entry replaces that of the
+            // credit and is forgotten with it, and the store that just logged the credit is not
+            // expected to refuse it.
+            let _ = destination.post_adjustment(transaction_id, -amount, transaction.currency);
+        }
+        destination.forget(transaction_id);
+        if created {
+            self.accounts.remove(&to_client_id);
+        }
+    }
+
+    /// Records a new deposit, withdrawal or transfer. Returns false for an idempotent
+    /// redelivery, which must not be applied again.
+    fn register_new(
+        &mut self,
+        transaction: &Transaction,
+        amount: f64,
+    ) -> Result<bool, EngineError> {
+        let transaction_id = transaction.transaction_id;
+        let seen = SeenTransaction {
+            client_id: transaction.client_id,
+            transaction_type: transaction.transaction_type,
+            amount,
+            to_client_id: transaction.to_client_id,
+            currency: transaction.currency,
+            timestamp: transaction.timestamp,
+            applied: false,
+        };
+        if let Some(previous) = self.seen_transactions.get(&transaction_id) {
+            let redelivery = SeenTransaction {
+                applied: previous.applied,
+                ..seen
+            };
+            return match self.config.duplicate_policy {
+                DuplicatePolicy::Idempotent if *previous == redelivery => Ok(false),
+                _ => Err(EngineError::DuplicateTransaction(transaction_id)),
+            };
+        }
+        // Recorded before applying so that rejected rows cannot be retried under the same id
+        self.seen_transactions.insert(transaction_id, seen);
+        for (client_id, transaction_id) in
+            self.retention.retain(transaction.client_id, transaction_id)
+        {
+            self.evict(client_id, transaction_id);
+        }
+        Ok(true)
+    }
+
+    /// Forgets a transaction evicted by the retention policy. Transactions under dispute stay
+    /// known to the engine so that the dispute can still be settled.
+    fn evict(&mut self, client_id: ClientId, transaction_id: TransactionId) {
+        let to_client_id = self
+            .seen_transactions
+            .get(&transaction_id)
+            .and_then(|seen| seen.to_client_id);
+        let mut disputed = false;
+        for client_id in [Some(client_id), to_client_id].into_iter().flatten() {
+            if let Some(account) = self.accounts.get_mut(&client_id) {
+                disputed |= account.forget(transaction_id);
+            }
+        }
+        if !disputed {
+            self.seen_transactions.remove(&transaction_id);
+        }
+        self.metrics.record_eviction();
+    }
+
+    /// Checks the amount against the configured limits. `withdrawal` is set for transactions
+    /// taking funds away from the client, which count towards the daily withdrawal cap.
+    fn check_amount_limits(
+        &self,
+        transaction: &Transaction,
+        amount: f64,
+        withdrawal: bool,
+    ) -> Result<(), EngineError> {
+        let limits = self.config.limits_for(transaction.client_id);
+        let exceeded = |limit| EngineError::LimitExceeded {
+            client_id: transaction.client_id,
+            transaction_id: transaction.transaction_id,
+            limit,
+        };
+        if let Some(max) = limits.max_transaction_amount {
+            if amount > max {
+                return Err(exceeded(Limit::TransactionAmount(max)));
+            }
+        }
+        if let Some(cap) = limits.daily_withdrawal_cap.filter(|_| withdrawal) {
+            let withdrawn = self
+                .usage
+                .get(&transaction.client_id)
+                .filter(|usage| !usage.is_new_day(transaction.timestamp))
+                .and_then(|usage| usage.withdrawn.get(&transaction.currency))
+                .copied()
+                .unwrap_or(0.0);
+            if withdrawn + amount > cap {
+                return Err(exceeded(Limit::DailyWithdrawals(cap)));
+            }
+        }
+        Ok(())
+    }
+
+    fn record_withdrawal(&mut self, transaction: &Transaction, amount: f64) {
+        if self
+            .config
+            .limits_for(transaction.client_id)
+            .daily_withdrawal_cap
+            .is_some()
+        {
+            let usage = self.usage.entry(transaction.client_id).or_default();
+            if usage.is_new_day(transaction.timestamp) {
+                usage.withdrawn.clear();
+            }
+            if let Some(timestamp) = transaction.timestamp {
+                usage.day = Some(timestamp.day());
+            }
+            *usage.withdrawn.entry(transaction.currency).or_insert(0.0) += amount;
+        }
+    }
+
+    fn mark_applied(&mut self, transaction_id: TransactionId) {
+        if let Some(seen) = self.seen_transactions.get_mut(&transaction_id) {
+            seen.applied = true;
+        }
+    }
+
+    /// Account of the client, created if needed
+    fn account_mut(&mut self, client_id: ClientId) -> &mut Box<dyn ClientAccount> {
+        let config = &self.config;
+        self.accounts.entry(client_id).or_insert_with(|| {
+            config.account_factory().create(
+                client_id,
+                config.account_policies_for(client_id),
+                config.storage.open(client_id),
+            )
+        })
+    }
+
+    /// Locks freeze the account, creating it if needed so that a client can be frozen before its
+    /// first transaction. Unlocks lift the lock of an existing account. Neither registers its
+    /// transaction id.
+    fn execute_admin(&mut self, transaction: Transaction) -> Result<(), EngineError> {
+        let client_id = transaction.client_id;
+        if transaction.transaction_type == TransactionType::Lock {
+            self.account_mut(client_id).set_locked(true);
+            return Ok(());
+        }
+        let account = self
+            .accounts
+            .get_mut(&client_id)
+            .ok_or(EngineError::UnknownClient {
+                client_id,
+                transaction_id: transaction.transaction_id,
+            })?;
+        account.set_locked(false);
+        Ok(())
+    }
+
+    /// Fees and interest change the available funds of an existing account without becoming
+    /// disputable. Neither registers its transaction id.
+    fn execute_adjustment(&mut self, transaction: Transaction) -> Result<(), EngineError> {
+        let client_id = transaction.client_id;
+        let transaction_id = transaction.transaction_id;
+        let amount = transaction
+            .amount
+            .map(Amount::value)
+            .ok_or(EngineError::MissingAmount(transaction_id))?;
+        let account = self
+            .accounts
+            .get_mut(&client_id)
+            .ok_or(EngineError::UnknownClient {
+                client_id,
+                transaction_id,
+            })?;
+        check_lock(
+            self.config.lock_policy,
+            account.as_ref(),
+            transaction.transaction_type,
+            transaction_id,
+        )?;
+        let amount = if transaction.transaction_type == TransactionType::Fee {
+            -amount
+        } else {
+            amount
+        };
+        account
+            .adjust(transaction_id, amount, transaction.currency)
+            .map_err(|source| EngineError::Account { client_id, source })
+    }
+
+    /// Closures need the funds of the account to be settled: nothing may be held in any currency.
+    /// What is available stays on the closed account, to be paid out outside of the engine.
+    fn execute_close(&mut self, transaction: Transaction) -> Result<(), EngineError> {
+        let client_id = transaction.client_id;
+        let transaction_id = transaction.transaction_id;
+        let account = self
+            .accounts
+            .get(&client_id)
+            .ok_or(EngineError::UnknownClient {
+                client_id,
+                transaction_id,
+            })?;
+        for currency in account.currencies() {
+            let held = account.balance(currency).held;
+            if held != 0.0 {
+                return Err(EngineError::HeldFunds {
+                    client_id,
+                    transaction_id,
+                    currency,
+                    held,
+                });
+            }
+        }
+        self.closed.insert(client_id);
+        Ok(())
+    }
+
+    /// Reserves are set on existing accounts, locked or not, and may exceed the available funds,
+    /// in which case nothing can be withdrawn until deposits cover them
+    fn execute_reserve(&mut self, transaction: Transaction) -> Result<(), EngineError> {
+        let client_id = transaction.client_id;
+        let transaction_id = transaction.transaction_id;
+        let reserve = transaction
+            .amount
+            .map(Amount::value)
+            .ok_or(EngineError::MissingAmount(transaction_id))?;
+        if !self.accounts.contains_key(&client_id) {
+            return Err(EngineError::UnknownClient {
+                client_id,
+                transaction_id,
+            });
+        }
+        self.reserves
+            .insert((client_id, transaction.currency), reserve);
+        Ok(())
+    }
+
+    /// Ledger adjustments change the available funds by their signed amount without checking
+    /// them, and are registered like deposits and withdrawals so that they can be disputed. They
+    /// do not count towards the amount limits of the client.
+    fn execute_ledger_adjustment(&mut self, transaction: Transaction) -> Result<(), EngineError> {
+        let client_id = transaction.client_id;
+        let transaction_id = transaction.transaction_id;
+        if !self.config.allow_adjustments {
+            return Err(EngineError::AdjustmentsDisabled(transaction_id));
+        }
+        let amount = transaction
+            .amount
+            .map(Amount::value)
+            .ok_or(EngineError::MissingAmount(transaction_id))?;
+        let account = self
+            .accounts
+            .get(&client_id)
+            .ok_or(EngineError::UnknownClient {
+                client_id,
+                transaction_id,
+            })?;
+        check_lock(
+            self.config.lock_policy,
+            account.as_ref(),
+            transaction.transaction_type,
+            transaction_id,
+        )?;
+        if !self.register_new(&transaction, amount)? {
+            return Ok(());
+        }
+        self.account_mut(client_id)
+            .post_adjustment(transaction_id, amount, transaction.currency)
+            .map_err(|source| EngineError::Account { client_id, source })?;
+        self.mark_applied(transaction_id);
+        Ok(())
+    }
+
+    /// Reversals take the full amount of a deposit back from the available funds, or credit that
+    /// of a withdrawal, whatever the amount of the row. The reversed transaction counts as never
+    /// applied from then on, so it can neither be disputed nor reversed again. Transactions under
+    /// dispute or charged back are left to the dispute flow.
+    fn execute_reversal(&mut self, transaction: Transaction) -> Result<(), EngineError> {
+        let client_id = transaction.client_id;
+        let transaction_id = transaction.transaction_id;
+        let seen = self
+            .seen_transactions
+            .get(&transaction_id)
+            .filter(|seen| seen.applied)
+            .ok_or(EngineError::UnknownTransaction(transaction_id))?;
+        if seen.client_id != client_id {
+            return Err(EngineError::ClientMismatch {
+                transaction_id,
+                owner: seen.client_id,
+                client_id,
+            });
+        }
+        let amount = match seen.transaction_type {
+            TransactionType::Deposit => -seen.amount,
+            TransactionType::Withdrawal => seen.amount,
+            _ => return Err(EngineError::NotReversible(transaction_id)),
+        };
+        let currency = seen.currency;
+        let account = self
+            .accounts
+            .get_mut(&client_id)
+            .ok_or(EngineError::UnknownTransaction(transaction_id))?;
+        check_lock(
+            self.config.lock_policy,
+            account.as_ref(),
+            TransactionType::Reversal,
+            transaction_id,
+        )?;
+        if matches!(
+            account.dispute_state(transaction_id).0,
+            DisputeState::Disputed | DisputeState::ChargedBack
+        ) {
+            return Err(EngineError::NotReversible(transaction_id));
+        }
+        account
+            .adjust(transaction_id, amount, currency)
+            .map_err(|source| EngineError::Account { client_id, source })?;
+        account.forget(transaction_id);
+        if let Some(seen) = self.seen_transactions.get_mut(&transaction_id) {
+            seen.applied = false;
+        }
+        Ok(())
+    }
+
+    /// Rebuilds the account of the `to` client with the funds, disputable transactions, disputes
+    /// and authorizations of both accounts, then removes the account of the client. The merged
+    /// account is locked if either was. Every record of the client, such as its history, open
+    /// disputes and the transactions it owns, is rewritten as that of the `to` client so that
+    /// later disputes are issued by it. Both accounts must exist, and merges of accounts sharing
+    /// a disputable transaction or an authorization or of accounts with pending withdrawals are
+    /// refused. Does not register its transaction id.
+    fn execute_merge(&mut self, transaction: Transaction) -> Result<(), EngineError> {
+        let client_id = transaction.client_id;
+        let transaction_id = transaction.transaction_id;
+        let into = transaction
+            .to_client_id
+            .ok_or(EngineError::MissingDestination(transaction_id))?;
+        let conflict = |conflict| EngineError::MergeConflict {
+            transaction_id,
+            client_id,
+            into,
+            conflict,
+        };
+        if into == client_id {
+            return Err(conflict(MergeConflict::SameClient));
+        }
+        let state = |client_id| {
+            let account = self
+                .accounts
+                .get(&client_id)
+                .ok_or(EngineError::UnknownClient {
+                    client_id,
+                    transaction_id,
+                })?;
+            account.state().map_err(|err| EngineError::Account {
+                client_id,
+                source: UpdateError::storage(transaction_id, err),
+            })
+        };
+        let (source, target) = (state(client_id)?, state(into)?);
+        if !source.pending_withdrawals.is_empty() {
+            return Err(conflict(MergeConflict::PendingWithdrawals));
+        }
+        let held: HashSet<TransactionId> = target
+            .transaction_log
+            .iter()
+            .chain(&target.authorizations)
+            .map(|(id, _, _)| *id)
+            .chain(target.dispute_states.iter().map(|(id, _, _)| *id))
+            .collect();
+        let shared = source
+            .transaction_log
+            .iter()
+            .chain(&source.authorizations)
+            .map(|(id, _, _)| *id)
+            .chain(source.dispute_states.iter().map(|(id, _, _)| *id))
+            .find(|id| held.contains(id));
+        if let Some(shared) = shared {
+            return Err(conflict(MergeConflict::SharedTransaction(shared)));
+        }
+
+        let mut merged = AccountState {
+            locked: source.locked || target.locked,
+            ..target
+        };
+        for (currency, balance) in source.balances {
+            match merged
+                .balances
+                .iter_mut()
+                .find(|(held, _)| *held == currency)
+            {
+                Some((_, merged)) => {
+                    merged.available += balance.available;
+                    merged.held += balance.held;
+                }
+                None => merged.balances.push((currency, balance)),
+            }
+        }
+        merged
+            .transaction_log
+            .extend(source.transaction_log.iter().copied());
+        merged.transaction_log.sort_by_key(|(id, _, _)| *id);
+        merged.active_disputes.extend(source.active_disputes);
+        merged.dispute_states.extend(source.dispute_states);
+        merged.authorizations.extend(source.authorizations);
+        merged.authorizations.sort_by_key(|(id, _, _)| *id);
+        merged.pending_holds.extend(source.pending_holds);
+        let account = self
+            .config
+            .account_factory()
+            .restore(
+                merged,
+                self.config.account_policies_for(into),
+                self.config.storage.open(into),
+            )
+            .map_err(|err| EngineError::Account {
+                client_id: into,
+                source: UpdateError::Storage {
+                    transaction_id,
+                    message: err.to_string(),
+                },
+            })?;
+        self.accounts.insert(into, account);
+        // Nothing of the merged account stays behind in an on-disk store
+        if let Some(mut merged) = self.accounts.remove(&client_id) {
+            for (id, _, _) in &source.transaction_log {
+                merged.forget(*id);
+            }
+        }
+
+        for seen in self.seen_transactions.values_mut() {
+            if seen.client_id == client_id {
+                seen.client_id = into;
+            }
+            if seen.to_client_id == Some(client_id) {
+                seen.to_client_id = Some(into);
+            }
+        }
+        // Funds reserved on the merged account stay reserved on top of those of the other
+        let reserves: Vec<_> = self
+            .reserves
+            .keys()
+            .filter(|(reserved, _)| *reserved == client_id)
+            .copied()
+            .collect();
+        for (reserved, currency) in reserves {
+            let reserve = self.reserves.remove(&(reserved, currency)).unwrap_or(0.0);
+            let merged = self.reserve(into, currency) + reserve;
+            self.reserves.insert((into, currency), merged);
+        }
+        if let Some(usage) = self.usage.remove(&client_id) {
+            let merged = self.usage.entry(into).or_default();
+            merged.transactions += usage.transactions;
+            for (currency, amount) in usage.withdrawn {
+                *merged.withdrawn.entry(currency).or_insert(0.0) += amount;
+            }
+            merged.day = merged.day.max(usage.day);
+        }
+        self.retention.remap(client_id, into);
+        self.expiry.remap(client_id, into);
+        self.ledger.remap(client_id, into);
+        Ok(())
+    }
+
+    /// Disputes, resolves, chargebacks, captures and voids are only routed to the client owning
+    /// the referenced transaction and never create accounts
+    fn execute_reference(&mut self, transaction: Transaction) -> Result<(), EngineError> {
+        let client_id = transaction.client_id;
+        let transaction_id = transaction.transaction_id;
+        let seen = self
+            .seen_transactions
+            .get(&transaction_id)
+            .filter(|seen| seen.applied)
+            .ok_or(EngineError::UnknownTransaction(transaction_id))?;
+        // Either leg of a transfer can be referenced by its own client
+        if seen.client_id != client_id && seen.to_client_id != Some(client_id) {
+            return Err(EngineError::ClientMismatch {
+                transaction_id,
+                owner: seen.client_id,
+                client_id,
+            });
+        }
+        // Rows without a currency refer to the transaction in whatever currency it was made
+        if let Some(currency) = transaction.currency {
+            if seen.currency != Some(currency) {
+                return Err(EngineError::CurrencyMismatch {
+                    transaction_id,
+                    expected: seen.currency,
+                    currency,
+                });
+            }
+        }
+        if let (TransactionType::Dispute, Some(days), Some(made), Some(disputed)) = (
+            transaction.transaction_type,
+            self.config.dispute_window,
+            seen.timestamp,
+            transaction.timestamp,
+        ) {
+            if !disputed.is_within_days(made, days) {
+                return Err(EngineError::DisputeWindowExpired {
+                    transaction_id,
+                    days,
+                });
+            }
+        }
+        let account = self
+            .accounts
+            .get_mut(&client_id)
+            .ok_or(EngineError::UnknownTransaction(transaction_id))?;
+        check_lock(
+            self.config.lock_policy,
+            account.as_ref(),
+            transaction.transaction_type,
+            transaction_id,
+        )?;
+
+        let result = match transaction.transaction_type {
+            TransactionType::Dispute => match transaction.amount {
+                Some(amount) => account.dispute_partial(transaction_id, amount.value()),
+                None => account.dispute(transaction_id),
+            },
+            TransactionType::Resolve => account.resolve(transaction_id),
+            TransactionType::Capture => {
+                account.capture(transaction_id, transaction.amount.map(Amount::value))
+            }
+            TransactionType::Void => account.void(transaction_id),
+            TransactionType::Represent => account.represent(transaction_id),
+            _ => account.chargeback(transaction_id),
+        };
+        result.map_err(|source| EngineError::Account { client_id, source })
+    }
+}
+
+fn authorized(transaction: &Transaction, origin: Origin) -> bool {
+    origin == Origin::Admin || !transaction.transaction_type.is_admin()
+}
+
+/// Refuses to take the available funds of the account below the reserve
+fn check_reserve(
+    account: &dyn ClientAccount,
+    reserve: f64,
+    transaction: &Transaction,
+    amount: f64,
+) -> Result<(), EngineError> {
+    let available = account.balance(transaction.currency).available;
+    if reserve > 0.0 && available - amount < reserve - DUST {
+        return Err(EngineError::ReserveBreached {
+            client_id: transaction.client_id,
+            transaction_id: transaction.transaction_id,
+            available,
+            reserve,
+        });
+    }
+    Ok(())
+}
+
+/// Enforced by the engine as well so that any account implementation respects the lock policy
+fn check_lock(
+    lock_policy: LockPolicy,
+    account: &dyn ClientAccount,
+    transaction_type: TransactionType,
+    transaction_id: TransactionId,
+) -> Result<(), EngineError> {
+    if account.is_locked() && !lock_policy.allows(transaction_type) {
+        return Err(EngineError::Account {
+            client_id: account.get_client_id(),
+            source: UpdateError::AccountLocked(transaction_id),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::account::{
+            AccountFactory, AccountState, BasicAccount, BasicAccountFactory, ClientAccount,
+            ClientId,
+        };
+        use crate::audit::{AuditEvent, InMemoryAuditSink};
+        use crate::currency::Currency;
+        use crate::engine::{EngineConfig, TransactionEngine};
+        use crate::error::{EngineError, Limit, MergeConflict, SnapshotError, UpdateError};
+        use crate::fees::FeeSchedule;
+        use crate::output::{AccountSnapshot, OutputOrder};
+        use crate::policy::{
+            AccountPolicies, DisputeExpiry, DuplicatePolicy, LimitsPolicy, LockPolicy,
+            RetentionPolicy,
+        };
+        use crate::risk::{NearLimit, RiskPolicy, RiskRule};
+        use crate::schedule::{Interval, Schedule, StandingOrder};
+        use crate::snapshot::{read_snapshot, write_snapshot};
+        use crate::storage::{DiskStore, LogEntry, Storage, TransactionStore};
+        use crate::tier::{Tier, TierPolicy, Tiers};
+        use crate::timestamp::Timestamp;
+        use crate::transaction::{
+            transaction, Amount, Origin, Transaction, TransactionId, TransactionType,
+        };
+        use std::collections::HashMap;
+        use std::io;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        fn transfer(transaction_id: u32, amount: f64, to_client_id: ClientId) -> Transaction {
+            Transaction {
+                to_client_id: Some(to_client_id),
+                ..transaction(TransactionType::Transfer, 1, transaction_id, Some(amount))
+            }
+        }
+
+        fn lock_account(engine: &mut TransactionEngine) {
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(2.0)))
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Dispute, 1, 1, None))
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Chargeback, 1, 1, None))
+                .unwrap();
+        }
+
+        #[test]
+        fn locked_account_rejects_transactions_by_default() {
+            let mut engine = TransactionEngine::new();
+            lock_account(&mut engine);
+
+            assert_eq!(
+                engine.execute(transaction(TransactionType::Deposit, 1, 2, Some(1.0))),
+                Err(EngineError::Account {
+                    client_id: 1,
+                    source: UpdateError::AccountLocked(TransactionId(2))
+                })
+            );
+            assert_eq!(engine.accounts[&1].get_total_funds(), 0.0);
+        }
+
+        #[test]
+        fn open_disputes_are_settled_on_locked_accounts() {
+            let mut engine = TransactionEngine::new();
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(5.0)))
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 2, Some(3.0)))
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Dispute, 1, 1, None))
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Dispute, 1, 2, None))
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Chargeback, 1, 1, None))
+                .unwrap();
+
+            engine
+                .execute(transaction(TransactionType::Resolve, 1, 2, None))
+                .unwrap();
+            assert_eq!(engine.accounts[&1].get_held_funds(), 0.0);
+            assert_eq!(engine.accounts[&1].get_available_funds(), 3.0);
+
+            assert_eq!(
+                engine.execute(transaction(TransactionType::Chargeback, 1, 2, None)),
+                Err(EngineError::Account {
+                    client_id: 1,
+                    source: UpdateError::NoActiveDispute(TransactionId(2))
+                })
+            );
+            assert!(engine.accounts[&1].is_locked());
+        }
+
+        #[test]
+        fn lock_policy_can_allow_deposits() {
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                lock_policy: LockPolicy::AllowDeposits,
+                ..EngineConfig::default()
+            });
+            lock_account(&mut engine);
+
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 2, Some(1.0)))
+                .unwrap();
+            assert!(engine
+                .execute(transaction(TransactionType::Withdrawal, 1, 3, Some(1.0)))
+                .is_err());
+            assert_eq!(engine.accounts[&1].get_available_funds(), 1.0);
+        }
+
+        #[test]
+        fn admin_can_lock_and_unlock_accounts() {
+            let mut engine = TransactionEngine::new();
+            lock_account(&mut engine);
+
+            engine
+                .execute_from(
+                    transaction(TransactionType::Unlock, 1, 2, None),
+                    Origin::Admin,
+                )
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 3, Some(1.0)))
+                .unwrap();
+            assert!(!engine.accounts[&1].is_locked());
+            assert_eq!(engine.accounts[&1].get_available_funds(), 1.0);
+
+            // Locking reuses no transaction id, the deposit remains disputable
+            engine
+                .execute_from(
+                    transaction(TransactionType::Lock, 1, 3, None),
+                    Origin::Admin,
+                )
+                .unwrap();
+            assert!(engine.accounts[&1].is_locked());
+            assert_eq!(engine.transaction_owner(TransactionId(3)), Some(1));
+
+            // A client can be frozen before its first transaction
+            engine
+                .execute_from(
+                    Transaction {
+                        client_id: 2,
+                        ..transaction(TransactionType::Lock, 1, 4, None)
+                    },
+                    Origin::Admin,
+                )
+                .unwrap();
+            assert!(engine.accounts[&2].is_locked());
+            assert_eq!(
+                engine.execute_from(
+                    Transaction {
+                        client_id: 3,
+                        ..transaction(TransactionType::Unlock, 1, 5, None)
+                    },
+                    Origin::Admin,
+                ),
+                Err(EngineError::UnknownClient {
+                    client_id: 3,
+                    transaction_id: TransactionId(5)
+                })
+            );
+        }
+
+        #[test]
+        fn clients_cannot_lock_or_unlock_accounts() {
+            let mut engine = TransactionEngine::new();
+            lock_account(&mut engine);
+
+            assert_eq!(
+                engine.execute(transaction(TransactionType::Unlock, 1, 2, None)),
+                Err(EngineError::AdminOnly(TransactionId(2)))
+            );
+            assert_eq!(
+                engine.execute(Transaction {
+                    client_id: 2,
+                    ..transaction(TransactionType::Lock, 1, 3, None)
+                }),
+                Err(EngineError::AdminOnly(TransactionId(3)))
+            );
+            assert!(engine.accounts[&1].is_locked());
+            assert!(!engine.accounts.contains_key(&2));
+            assert!(engine.metrics().rejected().eq([("admin_only", 2)]));
+        }
+
+        #[test]
+        fn dispute_from_another_client_is_rejected() {
+            let mut engine = TransactionEngine::new();
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(2.0)))
+                .unwrap();
+
+            let dispute = Transaction {
+                client_id: 2,
+                ..transaction(TransactionType::Dispute, 1, 1, None)
+            };
+
+            assert_eq!(
+                engine.execute(dispute),
+                Err(EngineError::ClientMismatch {
+                    transaction_id: TransactionId(1),
+                    owner: 1,
+                    client_id: 2
+                })
+            );
+            assert_eq!(engine.accounts[&1].get_held_funds(), 0.0);
+            assert!(!engine.accounts.contains_key(&2));
+        }
+
+        #[test]
+        fn dispute_of_unknown_transaction_does_not_create_account() {
+            let mut engine = TransactionEngine::new();
+
+            assert_eq!(
+                engine.execute(transaction(TransactionType::Dispute, 1, 5, None)),
+                Err(EngineError::UnknownTransaction(TransactionId(5)))
+            );
+            assert!(engine.accounts.is_empty());
+        }
+
+        fn replayed_input() -> Vec<Transaction> {
+            let rows = [
+                transaction(TransactionType::Deposit, 1, 1, Some(5.0)),
+                transaction(TransactionType::Withdrawal, 1, 2, Some(2.0)),
+                transaction(TransactionType::Withdrawal, 1, 3, Some(10.0)),
+            ];
+            rows.iter().chain(rows.iter()).cloned().collect()
+        }
+
+        #[test]
+        fn reused_transaction_id_is_rejected() {
+            let mut engine = TransactionEngine::new();
+            let results: Vec<_> = replayed_input()
+                .into_iter()
+                .map(|transaction| engine.execute(transaction))
+                .collect();
+
+            assert_eq!(
+                results[3],
+                Err(EngineError::DuplicateTransaction(TransactionId(1)))
+            );
+            assert_eq!(
+                results[4],
+                Err(EngineError::DuplicateTransaction(TransactionId(2)))
+            );
+            assert_eq!(
+                results[5],
+                Err(EngineError::DuplicateTransaction(TransactionId(3)))
+            );
+            assert_eq!(engine.accounts[&1].get_available_funds(), 3.0);
+        }
+
+        #[test]
+        fn replayed_input_is_idempotent() {
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                duplicate_policy: DuplicatePolicy::Idempotent,
+                ..EngineConfig::default()
+            });
+            let results: Vec<_> = replayed_input()
+                .into_iter()
+                .map(|transaction| engine.execute(transaction))
+                .collect();
+
+            assert!(results[3..].iter().all(|result| result.is_ok()));
+            assert_eq!(engine.accounts[&1].get_available_funds(), 3.0);
+
+            // Same id with a different amount is still a conflict
+            assert_eq!(
+                engine.execute(transaction(TransactionType::Deposit, 1, 1, Some(6.0))),
+                Err(EngineError::DuplicateTransaction(TransactionId(1)))
+            );
+        }
+
+        #[test]
+        fn transfer_moves_funds_between_clients() {
+            let mut engine = TransactionEngine::new();
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(5.0)))
+                .unwrap();
+
+            engine.execute(transfer(2, 2.0, 2)).unwrap();
+
+            assert_eq!(engine.accounts[&1].get_available_funds(), 3.0);
+            assert_eq!(engine.accounts[&2].get_available_funds(), 2.0);
+            assert_eq!(engine.transaction_owner(TransactionId(2)), Some(1));
+        }
+
+        #[test]
+        fn refused_transfer_changes_neither_account() {
+            let mut engine = TransactionEngine::new();
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(5.0)))
+                .unwrap();
+
+            assert!(matches!(
+                engine.execute(transfer(2, 6.0, 2)),
+                Err(EngineError::Account {
+                    client_id: 1,
+                    source: UpdateError::InsufficientFunds { .. }
+                })
+            ));
+            assert_eq!(
+                engine.execute(transfer(3, 1.0, 1)),
+                Err(EngineError::SelfTransfer(TransactionId(3)))
+            );
+            assert_eq!(
+                engine.execute(transaction(TransactionType::Transfer, 1, 4, Some(1.0))),
+                Err(EngineError::MissingDestination(TransactionId(4)))
+            );
+            assert_eq!(engine.accounts[&1].get_available_funds(), 5.0);
+            // Destinations are only created by applied transfers
+            assert!(!engine.accounts.contains_key(&2));
+
+            // A locked destination refuses the credit, so the debit is not made either
+            let deposit = Transaction {
+                client_id: 2,
+                ..transaction(TransactionType::Deposit, 1, 5, Some(1.0))
+            };
+            engine.execute(deposit).unwrap();
+            for transaction_type in [TransactionType::Dispute, TransactionType::Chargeback] {
+                engine
+                    .execute(Transaction {
+                        client_id: 2,
+                        ..transaction(transaction_type, 1, 5, None)
+                    })
+                    .unwrap();
+            }
+            assert_eq!(
+                engine.execute(transfer(6, 1.0, 2)),
+                Err(EngineError::Account {
+                    client_id: 2,
+                    source: UpdateError::AccountLocked(TransactionId(6))
+                })
+            );
+            assert_eq!(engine.accounts[&1].get_available_funds(), 5.0);
+        }
+
+        #[test]
+        fn both_legs_of_transfer_are_disputable() {
+            let mut engine = TransactionEngine::new();
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(5.0)))
+                .unwrap();
+            engine.execute(transfer(2, 2.0, 2)).unwrap();
+
+            engine
+                .execute(Transaction {
+                    client_id: 2,
+                    ..transaction(TransactionType::Dispute, 1, 2, None)
+                })
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Dispute, 1, 2, None))
+                .unwrap();
+
+            assert_eq!(engine.accounts[&2].get_held_funds(), 2.0);
+            assert_eq!(engine.accounts[&2].get_available_funds(), 0.0);
+            assert_eq!(engine.accounts[&1].get_held_funds(), -2.0);
+            assert_eq!(engine.accounts[&1].get_available_funds(), 5.0);
+            assert!(matches!(
+                engine.execute(Transaction {
+                    client_id: 3,
+                    ..transaction(TransactionType::Dispute, 1, 2, None)
+                }),
+                Err(EngineError::ClientMismatch { owner: 1, .. })
+            ));
+        }
+
+        #[test]
+        fn dispute_in_another_currency_is_rejected() {
+            let eur = "EUR".parse().unwrap();
+            let mut engine = TransactionEngine::new();
+            engine
+                .execute(Transaction {
+                    currency: Some(eur),
+                    ..transaction(TransactionType::Deposit, 1, 1, Some(2.0))
+                })
+                .unwrap();
+
+            let usd = "USD".parse().unwrap();
+            assert_eq!(
+                engine.execute(Transaction {
+                    currency: Some(usd),
+                    ..transaction(TransactionType::Dispute, 1, 1, None)
+                }),
+                Err(EngineError::CurrencyMismatch {
+                    transaction_id: TransactionId(1),
+                    expected: Some(eur),
+                    currency: usd
+                })
+            );
+            engine
+                .execute(Transaction {
+                    currency: Some(eur),
+                    ..transaction(TransactionType::Dispute, 1, 1, None)
+                })
+                .unwrap();
+            assert_eq!(engine.accounts[&1].balance(Some(eur)).held, 2.0);
+        }
+
+        fn limited(limits: LimitsPolicy) -> TransactionEngine {
+            TransactionEngine::with_config(EngineConfig {
+                limits,
+                ..EngineConfig::default()
+            })
+        }
+
+        #[test]
+        fn transactions_above_max_amount_are_rejected() {
+            let mut engine = limited(LimitsPolicy {
+                max_transaction_amount: Some(10.0),
+                ..LimitsPolicy::default()
+            });
+
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(10.0)))
+                .unwrap();
+            assert_eq!(
+                engine.execute(transaction(TransactionType::Deposit, 1, 2, Some(10.5))),
+                Err(EngineError::LimitExceeded {
+                    client_id: 1,
+                    transaction_id: TransactionId(2),
+                    limit: Limit::TransactionAmount(10.0)
+                })
+            );
+            assert_eq!(engine.accounts[&1].get_total_funds(), 10.0);
+        }
+
+        #[test]
+        fn withdrawals_and_transfers_count_towards_daily_cap() {
+            let mut engine = limited(LimitsPolicy {
+                daily_withdrawal_cap: Some(5.0),
+                ..LimitsPolicy::default()
+            });
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(20.0)))
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Withdrawal, 1, 2, Some(3.0)))
+                .unwrap();
+            engine.execute(transfer(3, 2.0, 2)).unwrap();
+
+            assert_eq!(
+                engine.execute(transaction(TransactionType::Withdrawal, 1, 4, Some(0.5))),
+                Err(EngineError::LimitExceeded {
+                    client_id: 1,
+                    transaction_id: TransactionId(4),
+                    limit: Limit::DailyWithdrawals(5.0)
+                })
+            );
+            engine.reset_daily_limits();
+            engine
+                .execute(transaction(TransactionType::Withdrawal, 1, 5, Some(0.5)))
+                .unwrap();
+            assert_eq!(engine.accounts[&1].get_available_funds(), 14.5);
+        }
+
+        fn at(transaction: Transaction, timestamp: &str) -> Transaction {
+            Transaction {
+                timestamp: Some(timestamp.parse::<Timestamp>().unwrap()),
+                metadata: Default::default(),
+                ..transaction
+            }
+        }
+
+        #[test]
+        fn daily_cap_starts_over_on_the_day_of_the_timestamp() {
+            let mut engine = limited(LimitsPolicy {
+                daily_withdrawal_cap: Some(5.0),
+                ..LimitsPolicy::default()
+            });
+            for (transaction_id, timestamp) in [(1, "2024-03-01T09:00Z"), (2, "2024-03-01T23:59Z")]
+            {
+                let deposit = transaction(TransactionType::Deposit, 1, transaction_id, Some(10.0));
+                engine.execute(at(deposit, timestamp)).unwrap();
+            }
+            let withdrawal = |transaction_id, timestamp| {
+                at(
+                    transaction(TransactionType::Withdrawal, 1, transaction_id, Some(3.0)),
+                    timestamp,
+                )
+            };
+            engine.execute(withdrawal(3, "2024-03-01T10:00Z")).unwrap();
+
+            assert!(engine.execute(withdrawal(4, "2024-03-01T23:00Z")).is_err());
+            engine.execute(withdrawal(5, "2024-03-02T00:30Z")).unwrap();
+            // Rows without a timestamp count towards the current day
+            assert!(engine
+                .execute(transaction(TransactionType::Withdrawal, 1, 6, Some(3.0)))
+                .is_err());
+            assert_eq!(engine.accounts[&1].get_available_funds(), 14.0);
+        }
+
+        #[test]
+        fn disputes_are_rejected_outside_of_the_dispute_window() {
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                dispute_window: Some(90),
+                ..EngineConfig::default()
+            });
+            let deposit = |transaction_id| {
+                at(
+                    transaction(TransactionType::Deposit, 1, transaction_id, Some(1.0)),
+                    "2024-01-01T10:00:00Z",
+                )
+            };
+            let dispute =
+                |transaction_id| transaction(TransactionType::Dispute, 1, transaction_id, None);
+            for transaction_id in 1..=3 {
+                engine.execute(deposit(transaction_id)).unwrap();
+            }
+
+            engine
+                .execute(at(dispute(1), "2024-03-31T10:00:00Z"))
+                .unwrap();
+            assert_eq!(
+                engine.execute(at(dispute(2), "2024-04-01T10:00:00Z")),
+                Err(EngineError::DisputeWindowExpired {
+                    transaction_id: TransactionId(2),
+                    days: 90
+                })
+            );
+            // Disputes without a timestamp cannot be checked
+            engine.execute(dispute(3)).unwrap();
+            assert_eq!(engine.accounts[&1].get_held_funds(), 2.0);
+            assert!(engine
+                .metrics()
+                .rejected()
+                .eq([("dispute_window_expired", 1)]));
+        }
+
+        #[test]
+        fn suspicious_transactions_are_flagged_and_blocked_if_configured() {
+            #[derive(Debug)]
+            struct LargeWithdrawals;
+
+            impl RiskRule for LargeWithdrawals {
+                fn name(&self) -> &'static str {
+                    "large_withdrawals"
+                }
+
+                fn evaluate(&mut self, _lsn: u64, transaction: &Transaction) -> Option<String> {
+                    (transaction.transaction_type == TransactionType::Withdrawal
+                        && transaction.amount > Some(Amount::new(50.0).unwrap()))
+                    .then(|| "large".to_string())
+                }
+            }
+
+            let risk = RiskPolicy {
+                near_limit: NearLimit {
+                    enabled: true,
+                    threshold: 100.0,
+                    margin: 0.1,
+                },
+                ..RiskPolicy::default()
+            };
+            let sink = InMemoryAuditSink::new();
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                risk,
+                ..EngineConfig::default()
+            });
+            engine.set_audit_sink(Box::new(sink.clone()));
+            engine.add_risk_rule(Box::new(LargeWithdrawals));
+            let deposit = transaction(TransactionType::Deposit, 1, 1, Some(95.0));
+            engine.execute(deposit.clone()).unwrap();
+            // Flagged even though it is rejected
+            let _ = engine.execute(transaction(TransactionType::Withdrawal, 1, 2, Some(99.0)));
+
+            let flagged = |transaction_id, rule, reason: &str| AuditEvent::RiskFlagged {
+                client_id: 1,
+                transaction_id,
+                rule,
+                reason: reason.to_string(),
+            };
+            let events = sink.events();
+            assert_eq!(
+                events[..2],
+                [
+                    flagged(
+                        TransactionId(1),
+                        "near_limit",
+                        "amount 95.0000 just under 100.0000"
+                    ),
+                    AuditEvent::Applied(deposit.clone()),
+                ]
+            );
+            assert_eq!(
+                events[2..4],
+                [
+                    flagged(
+                        TransactionId(2),
+                        "near_limit",
+                        "amount 99.0000 just under 100.0000"
+                    ),
+                    flagged(TransactionId(2), "large_withdrawals", "large"),
+                ]
+            );
+            assert!(engine
+                .metrics()
+                .risk_flags()
+                .eq([("large_withdrawals", 1), ("near_limit", 2)]));
+
+            let mut blocking = TransactionEngine::with_config(EngineConfig {
+                risk: RiskPolicy {
+                    block: true,
+                    ..risk
+                },
+                ..EngineConfig::default()
+            });
+            assert_eq!(
+                blocking.execute(deposit),
+                Err(EngineError::RiskFlagged {
+                    client_id: 1,
+                    transaction_id: TransactionId(1),
+                    rule: "near_limit",
+                    reason: "amount 95.0000 just under 100.0000".to_string(),
+                })
+            );
+            assert!(blocking.accounts.is_empty());
+        }
+
+        #[test]
+        fn disputes_left_open_are_resolved_once_they_expire() {
+            let sink = InMemoryAuditSink::new();
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                dispute_expiry: DisputeExpiry {
+                    after_transactions: Some(2),
+                    after_days: Some(30),
+                },
+                record_history: true,
+                ..EngineConfig::default()
+            });
+            engine.set_audit_sink(Box::new(sink.clone()));
+            for transaction_id in 1..=3 {
+                engine
+                    .execute(transaction(
+                        TransactionType::Deposit,
+                        1,
+                        transaction_id,
+                        Some(1.0),
+                    ))
+                    .unwrap();
+            }
+
+            engine
+                .execute(transaction(TransactionType::Dispute, 1, 1, None))
+                .unwrap();
+            // Rejected transactions count as well
+            let _ = engine.execute(transaction(TransactionType::Withdrawal, 1, 4, Some(10.0)));
+            engine
+                .execute(transaction(TransactionType::Dispute, 1, 3, None))
+                .unwrap();
+            assert_eq!(engine.accounts[&1].get_held_funds(), 1.0);
+            // Settled disputes do not expire
+            engine
+                .execute(transaction(TransactionType::Resolve, 1, 3, None))
+                .unwrap();
+            engine
+                .execute(at(
+                    transaction(TransactionType::Dispute, 1, 2, None),
+                    "2024-01-01",
+                ))
+                .unwrap();
+            // More than 30 days after the dispute of 2, before it expires by count
+            engine
+                .execute(at(
+                    transaction(TransactionType::Deposit, 1, 5, Some(1.0)),
+                    "2024-02-01",
+                ))
+                .unwrap();
+
+            assert_eq!(engine.accounts[&1].get_held_funds(), 0.0);
+            assert_eq!(engine.accounts[&1].get_available_funds(), 4.0);
+            assert_eq!(engine.metrics().expired_disputes(), 2);
+            let expired: Vec<_> = sink
+                .events()
+                .into_iter()
+                .filter(|event| matches!(event, AuditEvent::DisputeExpired { .. }))
+                .collect();
+            assert_eq!(
+                expired,
+                [
+                    AuditEvent::DisputeExpired {
+                        client_id: 1,
+                        transaction_id: TransactionId(1)
+                    },
+                    AuditEvent::DisputeExpired {
+                        client_id: 1,
+                        transaction_id: TransactionId(2)
+                    },
+                ]
+            );
+            assert_eq!(
+                engine
+                    .history(1)
+                    .of_type(TransactionType::Resolve)
+                    .map(|entry| entry.transaction.transaction_id.0)
+                    .collect::<Vec<_>>(),
+                [1, 3, 2]
+            );
+        }
+
+        #[test]
+        fn clients_are_limited_to_max_transactions() {
+            let mut engine = limited(LimitsPolicy {
+                max_transactions_per_client: Some(2),
+                ..LimitsPolicy::default()
+            });
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(1.0)))
+                .unwrap();
+            // Rejected transactions do not count
+            assert!(engine
+                .execute(transaction(TransactionType::Withdrawal, 1, 2, Some(5.0)))
+                .is_err());
+            engine
+                .execute(transaction(TransactionType::Dispute, 1, 1, None))
+                .unwrap();
+
+            let err = engine
+                .execute(transaction(TransactionType::Resolve, 1, 1, None))
+                .unwrap_err();
+            assert_eq!(err.code(), "transaction_count_limit");
+            assert_eq!(engine.accounts[&1].get_held_funds(), 1.0);
+        }
+
+        #[test]
+        fn evicted_transactions_are_no_longer_disputable() {
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                retention_policy: RetentionPolicy::PerClient(2),
+                ..EngineConfig::default()
+            });
+            for transaction_id in 1..=3 {
+                engine
+                    .execute(transaction(
+                        TransactionType::Deposit,
+                        1,
+                        transaction_id,
+                        Some(1.0),
+                    ))
+                    .unwrap();
+            }
+
+            assert_eq!(
+                engine.execute(transaction(TransactionType::Dispute, 1, 1, None)),
+                Err(EngineError::UnknownTransaction(TransactionId(1)))
+            );
+            engine
+                .execute(transaction(TransactionType::Dispute, 1, 2, None))
+                .unwrap();
+            assert_eq!(engine.metrics().evictions(), 1);
+            assert_eq!(engine.seen_transactions.len(), 2);
+            assert_eq!(
+                engine.accounts[&1].state().unwrap().transaction_log.len(),
+                1
+            );
+        }
+
+        #[test]
+        fn disputed_transactions_can_be_settled_after_eviction() {
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                retention_policy: RetentionPolicy::Global(1),
+                ..EngineConfig::default()
+            });
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(2.0)))
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Dispute, 1, 1, None))
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 2, Some(1.0)))
+                .unwrap();
+
+            engine
+                .execute(transaction(TransactionType::Resolve, 1, 1, None))
+                .unwrap();
+            assert_eq!(engine.accounts[&1].get_available_funds(), 3.0);
+            engine.execute(transfer(3, 1.0, 2)).unwrap();
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 4, Some(1.0)))
+                .unwrap();
+            // Both legs of the evicted transfer are forgotten
+            assert!(engine.accounts[&2]
+                .state()
+                .unwrap()
+                .transaction_log
+                .is_empty());
+        }
+
+        #[test]
+        fn disk_storage_behaves_as_memory() {
+            let path = std::env::temp_dir().join("rust-coding-test-engine-storage.log");
+            let mut on_disk = TransactionEngine::with_config(EngineConfig {
+                storage: Storage::Disk(DiskStore::create(&path).unwrap()),
+                ..EngineConfig::default()
+            });
+            let mut in_memory = TransactionEngine::new();
+            for engine in [&mut on_disk, &mut in_memory] {
+                engine
+                    .execute(transaction(TransactionType::Deposit, 1, 1, Some(5.0)))
+                    .unwrap();
+                engine.execute(transfer(2, 2.0, 2)).unwrap();
+                engine
+                    .execute(transaction(TransactionType::Dispute, 1, 1, None))
+                    .unwrap();
+                engine
+                    .execute(Transaction {
+                        client_id: 2,
+                        ..transaction(TransactionType::Dispute, 1, 2, None)
+                    })
+                    .unwrap();
+                engine
+                    .execute(transaction(TransactionType::Chargeback, 1, 1, None))
+                    .unwrap();
+            }
+
+            for client_id in [1, 2] {
+                assert_eq!(
+                    on_disk.accounts[&client_id].state().unwrap(),
+                    in_memory.accounts[&client_id].state().unwrap()
+                );
+            }
+            assert!(on_disk.accounts[&1].is_locked());
+            std::fs::remove_file(&path).unwrap();
+        }
+
+        #[test]
+        fn fees_and_interest_are_applied_to_unlocked_accounts() {
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                record_history: true,
+                ..EngineConfig::default()
+            });
+            for (client_id, transaction_id, amount) in [(1, 1, 100.0), (2, 2, 0.5), (3, 3, 2000.0)]
+            {
+                engine
+                    .execute(Transaction {
+                        client_id,
+                        ..transaction(TransactionType::Deposit, 1, transaction_id, Some(amount))
+                    })
+                    .unwrap();
+            }
+            engine
+                .execute_from(
+                    Transaction {
+                        client_id: 4,
+                        ..transaction(TransactionType::Lock, 1, 0, None)
+                    },
+                    Origin::Admin,
+                )
+                .unwrap();
+
+            let summary = engine.apply_fees(&FeeSchedule {
+                maintenance_fee: Some(1.0),
+                waive_fee_above: Some(1000.0),
+                interest_rate: Some(0.01),
+                currency: None,
+            });
+
+            assert_eq!(engine.accounts[&1].get_available_funds(), 100.0);
+            assert_eq!(engine.accounts[&2].get_available_funds(), 0.0);
+            assert_eq!(engine.accounts[&3].get_available_funds(), 2020.0);
+            assert_eq!(engine.accounts[&4].get_available_funds(), 0.0);
+            assert_eq!((summary.fees_charged, summary.interest_credited), (2, 3));
+            assert_eq!(summary.skipped_locked, 1);
+            assert!(summary.errors.is_empty());
+            // Synthetic transactions are part of the history but never disputable
+            let history: Vec<_> = engine
+                .history(1)
+                .map(|entry| entry.transaction.transaction_type)
+                .collect();
+            assert_eq!(
+                history,
+                [
+                    TransactionType::Deposit,
+                    TransactionType::Interest,
+                    TransactionType::Fee
+                ]
+            );
+            assert_eq!(
+                engine.execute(transaction(TransactionType::Dispute, 1, 0, None)),
+                Err(EngineError::UnknownTransaction(TransactionId(0)))
+            );
+        }
+
+        #[test]
+        fn standing_orders_are_executed_as_time_passes() {
+            let order = |transaction_type, transaction_id, amount, every| StandingOrder {
+                transaction_type,
+                client_id: 1,
+                transaction_id,
+                amount: Amount::unchecked(amount),
+                to_client_id: None,
+                currency: None,
+                every,
+                start: None,
+            };
+            let mut schedule = Schedule::default();
+            schedule.register(order(
+                TransactionType::Deposit,
+                TransactionId(100),
+                10.0,
+                Interval::Ticks(1),
+            ));
+            schedule.register(order(
+                TransactionType::Withdrawal,
+                TransactionId(200),
+                15.0,
+                Interval::Ticks(2),
+            ));
+            schedule.register(StandingOrder {
+                to_client_id: Some(2),
+                start: Some(Timestamp::from_secs(86_400)),
+                ..order(
+                    TransactionType::Transfer,
+                    TransactionId(300),
+                    20.0,
+                    Interval::Seconds(86_400),
+                )
+            });
+            let mut engine = TransactionEngine::new();
+
+            let summary = engine.advance_time(&mut schedule, 3, None);
+            assert_eq!((summary.executed, summary.errors.len()), (4, 0));
+            assert_eq!(engine.accounts[&1].get_available_funds(), 15.0);
+
+            // The transfer is due after the withdrawal of the tick and refused
+            let summary = engine.advance_time(&mut schedule, 1, Some(Timestamp::from_secs(86_400)));
+            assert_eq!(summary.executed, 2);
+            assert!(matches!(summary.errors[..], [EngineError::Account { .. }]));
+            assert_eq!(engine.accounts[&1].get_available_funds(), 10.0);
+            let summary =
+                engine.advance_time(&mut schedule, 1, Some(Timestamp::from_secs(172_800)));
+            assert_eq!((summary.executed, summary.errors.len()), (2, 0));
+            assert_eq!(engine.accounts[&1].get_available_funds(), 0.0);
+            assert_eq!(engine.accounts[&2].get_available_funds(), 20.0);
+            assert_eq!(engine.transaction_owner(TransactionId(301)), Some(1));
+        }
+
+        #[test]
+        fn authorizations_are_captured_or_voided_by_their_client() {
+            let mut engine = limited(LimitsPolicy {
+                daily_withdrawal_cap: Some(8.0),
+                ..LimitsPolicy::default()
+            });
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(10.0)))
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Authorize, 1, 2, Some(4.0)))
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Authorize, 1, 3, Some(3.0)))
+                .unwrap();
+            assert_eq!(engine.accounts[&1].get_held_funds(), 7.0);
+            // Authorizations count towards the daily cap when they are made
+            assert_eq!(
+                engine.execute(transaction(TransactionType::Authorize, 1, 4, Some(2.0))),
+                Err(EngineError::LimitExceeded {
+                    client_id: 1,
+                    transaction_id: TransactionId(4),
+                    limit: Limit::DailyWithdrawals(8.0)
+                })
+            );
+
+            let mut capture = transaction(TransactionType::Capture, 1, 2, Some(1.5));
+            capture.client_id = 2;
+            assert_eq!(
+                engine.execute(capture),
+                Err(EngineError::ClientMismatch {
+                    transaction_id: TransactionId(2),
+                    owner: 1,
+                    client_id: 2
+                })
+            );
+            engine
+                .execute(transaction(TransactionType::Capture, 1, 2, Some(1.5)))
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Void, 1, 3, None))
+                .unwrap();
+            assert_eq!(
+                engine.execute(transaction(TransactionType::Void, 1, 1, None)),
+                Err(EngineError::Account {
+                    client_id: 1,
+                    source: UpdateError::NoAuthorization(TransactionId(1))
+                })
+            );
+            assert_eq!(
+                engine.reverse(TransactionId(2)),
+                Err(EngineError::NotReversible(TransactionId(2)))
+            );
+            assert_eq!(engine.accounts[&1].get_available_funds(), 8.5);
+            assert_eq!(engine.accounts[&1].get_held_funds(), 0.0);
+        }
+
+        #[test]
+        fn representments_credit_back_chargebacks_of_locked_accounts() {
+            let sink = InMemoryAuditSink::new();
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                unlock_on_representment: true,
+                ..EngineConfig::default()
+            });
+            lock_account(&mut engine);
+            engine.set_audit_sink(Box::new(sink.clone()));
+
+            assert_eq!(
+                engine.execute(transaction(TransactionType::Represent, 1, 2, None)),
+                Err(EngineError::UnknownTransaction(TransactionId(2)))
+            );
+            engine
+                .execute(transaction(TransactionType::Represent, 1, 1, None))
+                .unwrap();
+            assert_eq!(engine.accounts[&1].get_available_funds(), 2.0);
+            assert!(!engine.accounts[&1].is_locked());
+            assert_eq!(
+                engine.execute(transaction(TransactionType::Represent, 1, 1, None)),
+                Err(EngineError::Account {
+                    client_id: 1,
+                    source: UpdateError::NotChargedBack(TransactionId(1))
+                })
+            );
+            assert_eq!(
+                sink.events()[2..4],
+                [
+                    AuditEvent::Represented {
+                        client_id: 1,
+                        transaction_id: TransactionId(1)
+                    },
+                    AuditEvent::AccountUnlocked { client_id: 1 },
+                ]
+            );
+        }
+
+        #[test]
+        fn reversals_undo_deposits_and_withdrawals() {
+            let sink = InMemoryAuditSink::new();
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                record_history: true,
+                ..EngineConfig::default()
+            });
+            engine.set_audit_sink(Box::new(sink.clone()));
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(10.0)))
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Withdrawal, 1, 2, Some(4.0)))
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 3, Some(1.0)))
+                .unwrap();
+            engine.execute(transfer(4, 1.0, 2)).unwrap();
+            engine
+                .execute(transaction(TransactionType::Dispute, 1, 3, None))
+                .unwrap();
+
+            engine.reverse(TransactionId(2)).unwrap();
+            assert_eq!(engine.accounts[&1].get_available_funds(), 9.0);
+            // The deposited funds must still be available
+            assert!(matches!(
+                engine.reverse(TransactionId(1)),
+                Err(EngineError::Account {
+                    source: UpdateError::InsufficientFunds { .. },
+                    ..
+                })
+            ));
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 5, Some(1.0)))
+                .unwrap();
+            engine.reverse(TransactionId(1)).unwrap();
+            assert_eq!(engine.accounts[&1].get_available_funds(), 0.0);
+            assert_eq!(engine.accounts[&1].get_held_funds(), 1.0);
+
+            // Reversed transactions are no longer applied
+            assert_eq!(
+                engine.reverse(TransactionId(1)),
+                Err(EngineError::UnknownTransaction(TransactionId(1)))
+            );
+            assert_eq!(
+                engine.execute(transaction(TransactionType::Dispute, 1, 1, None)),
+                Err(EngineError::UnknownTransaction(TransactionId(1)))
+            );
+            assert_eq!(
+                engine.reverse(TransactionId(3)),
+                Err(EngineError::NotReversible(TransactionId(3)))
+            );
+            assert_eq!(
+                engine.reverse(TransactionId(4)),
+                Err(EngineError::NotReversible(TransactionId(4)))
+            );
+            assert_eq!(
+                engine.execute(transaction(TransactionType::Reversal, 1, 3, None)),
+                Err(EngineError::AdminOnly(TransactionId(3)))
+            );
+            let history: Vec<_> = engine
+                .history(1)
+                .map(|entry| entry.transaction.transaction_type)
+                .collect();
+            assert_eq!(
+                history[history.len() - 3..],
+                [
+                    TransactionType::Reversal,
+                    TransactionType::Deposit,
+                    TransactionType::Reversal
+                ]
+            );
+            assert!(sink.events().contains(&AuditEvent::Reversed {
+                client_id: 1,
+                transaction_id: TransactionId(2),
+            }));
+        }
+
+        #[test]
+        fn the_last_deposits_and_withdrawals_are_undone() {
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                record_history: true,
+                ..EngineConfig::default()
+            });
+            for (transaction_type, transaction_id, amount) in [
+                (TransactionType::Deposit, 1, Some(10.0)),
+                (TransactionType::Deposit, 2, Some(5.0)),
+                (TransactionType::Dispute, 2, None),
+                (TransactionType::Resolve, 2, None),
+                (TransactionType::Deposit, 3, Some(2.0)),
+                (TransactionType::Withdrawal, 4, Some(4.0)),
+            ] {
+                engine
+                    .execute(transaction(transaction_type, 1, transaction_id, amount))
+                    .unwrap();
+            }
+
+            assert_eq!(engine.undo_last(1).unwrap(), [TransactionId(4)]);
+            assert_eq!(engine.accounts[&1].get_available_funds(), 17.0);
+            // The reversal of the withdrawal is passed over
+            assert_eq!(engine.undo_last(1).unwrap(), [TransactionId(3)]);
+            assert_eq!(engine.accounts[&1].get_available_funds(), 15.0);
+            // Nothing is undone past the resolve
+            assert_eq!(
+                engine.undo_last(2),
+                Err(EngineError::NotReversible(TransactionId(2)))
+            );
+            assert_eq!(engine.accounts[&1].get_available_funds(), 15.0);
+            assert_eq!(engine.undo_last(0).unwrap(), []);
+
+            let mut engine = TransactionEngine::new();
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(10.0)))
+                .unwrap();
+            // Nothing to undo without the history
+            assert_eq!(engine.undo_last(1).unwrap(), []);
+        }
+
+        #[test]
+        fn rolled_back_batches_leave_no_trace_in_the_state() {
+            let mut engine = TransactionEngine::new();
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(10.0)))
+                .unwrap();
+            let batch = engine.begin_batch().unwrap();
+            engine
+                .execute(transaction(TransactionType::Withdrawal, 1, 2, Some(4.0)))
+                .unwrap();
+            let _ = engine.execute(transaction(TransactionType::Withdrawal, 1, 3, Some(50.0)));
+            assert_eq!(batch.rejected(&engine), 1);
+            engine.rollback(batch).unwrap();
+
+            assert_eq!(engine.accounts[&1].get_available_funds(), 10.0);
+            assert_eq!(engine.lsn, 1);
+            // The ids of the discarded batch can be used again
+            engine
+                .execute(transaction(TransactionType::Withdrawal, 1, 2, Some(1.0)))
+                .unwrap();
+            assert_eq!(engine.accounts[&1].get_available_funds(), 9.0);
+            // Metrics still count what was executed
+            assert_eq!(engine.metrics().total_processed(), 4);
+        }
+
+        #[test]
+        fn forks_apply_transactions_without_touching_the_engine() {
+            let mut engine = TransactionEngine::new();
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(10.0)))
+                .unwrap();
+
+            let mut fork = engine.fork().unwrap();
+            fork.execute(transaction(TransactionType::Dispute, 1, 1, None))
+                .unwrap();
+            fork.execute(transaction(TransactionType::Chargeback, 1, 1, None))
+                .unwrap();
+            assert!(fork.accounts[&1].is_locked());
+            assert_eq!(fork.accounts[&1].get_total_funds(), 0.0);
+            // Transactions seen before the fork are still duplicates in it
+            assert_eq!(
+                fork.execute(transaction(TransactionType::Deposit, 1, 1, Some(1.0))),
+                Err(EngineError::DuplicateTransaction(TransactionId(1)))
+            );
+
+            assert!(!engine.accounts[&1].is_locked());
+            assert_eq!(engine.accounts[&1].get_total_funds(), 10.0);
+            assert_eq!(engine.metrics().total_processed(), 1);
+            engine
+                .execute(transaction(TransactionType::Withdrawal, 1, 2, Some(3.0)))
+                .unwrap();
+            assert_eq!(fork.accounts[&1].get_total_funds(), 0.0);
+        }
+
+        #[test]
+        fn adjustments_bypass_the_funds_check_and_can_be_disputed() {
+            let adjustment = |transaction_id, amount| {
+                transaction(TransactionType::Adjustment, 1, transaction_id, Some(amount))
+            };
+            let mut disabled = TransactionEngine::new();
+            disabled
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(1.0)))
+                .unwrap();
+            assert_eq!(
+                disabled.execute_from(adjustment(2, -5.0), Origin::Admin),
+                Err(EngineError::AdjustmentsDisabled(TransactionId(2)))
+            );
+
+            let sink = InMemoryAuditSink::new();
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                allow_adjustments: true,
+                record_history: true,
+                ..EngineConfig::default()
+            });
+            engine.set_audit_sink(Box::new(sink.clone()));
+            assert_eq!(
+                engine.execute_from(adjustment(1, 1.0), Origin::Admin),
+                Err(EngineError::UnknownClient {
+                    client_id: 1,
+                    transaction_id: TransactionId(1)
+                })
+            );
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 2, Some(1.0)))
+                .unwrap();
+            assert_eq!(
+                engine.execute(adjustment(3, 2.0)),
+                Err(EngineError::AdminOnly(TransactionId(3)))
+            );
+            engine
+                .execute_from(adjustment(3, -1.5), Origin::Admin)
+                .unwrap();
+            engine
+                .execute_from(adjustment(4, 2.5), Origin::Admin)
+                .unwrap();
+            assert_eq!(engine.accounts[&1].get_available_funds(), 2.0);
+            assert_eq!(
+                engine.execute_from(adjustment(4, 2.5), Origin::Admin),
+                Err(EngineError::DuplicateTransaction(TransactionId(4)))
+            );
+
+            // Credits are disputed like deposits, debits like withdrawals
+            engine
+                .execute(transaction(TransactionType::Dispute, 1, 4, None))
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Dispute, 1, 3, None))
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Chargeback, 1, 3, None))
+                .unwrap();
+            assert_eq!(engine.accounts[&1].get_available_funds(), 1.0);
+            assert_eq!(engine.accounts[&1].get_held_funds(), 2.5);
+            assert_eq!(engine.history(1).count(), 6);
+            assert_eq!(
+                sink.events()
+                    .into_iter()
+                    .filter(|event| matches!(event, AuditEvent::Adjusted { .. }))
+                    .collect::<Vec<_>>(),
+                [
+                    AuditEvent::Adjusted {
+                        client_id: 1,
+                        transaction_id: TransactionId(3),
+                        amount: -1.5,
+                        overdrawn: true,
+                    },
+                    AuditEvent::Adjusted {
+                        client_id: 1,
+                        transaction_id: TransactionId(4),
+                        amount: 2.5,
+                        overdrawn: false,
+                    },
+                ]
+            );
+        }
+
+        #[test]
+        fn closed_accounts_take_no_further_transactions() {
+            let sink = InMemoryAuditSink::new();
+            let mut engine = TransactionEngine::new();
+            engine.set_audit_sink(Box::new(sink.clone()));
+            let of = |client_id, transaction| Transaction {
+                client_id,
+                ..transaction
+            };
+            assert_eq!(
+                engine.close_account(1),
+                Err(EngineError::UnknownClient {
+                    client_id: 1,
+                    transaction_id: TransactionId(0)
+                })
+            );
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(2.0)))
+                .unwrap();
+            engine
+                .execute(of(
+                    2,
+                    transaction(TransactionType::Deposit, 1, 2, Some(1.0)),
+                ))
+                .unwrap();
+            engine
+                .execute(of(
+                    3,
+                    transaction(TransactionType::Deposit, 1, 3, Some(1.0)),
+                ))
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Dispute, 1, 1, None))
+                .unwrap();
+            assert_eq!(
+                engine.close_account(1),
+                Err(EngineError::HeldFunds {
+                    client_id: 1,
+                    transaction_id: TransactionId(0),
+                    currency: None,
+                    held: 2.0
+                })
+            );
+            assert_eq!(
+                engine.execute(transaction(TransactionType::Close, 1, 4, None)),
+                Err(EngineError::AdminOnly(TransactionId(4)))
+            );
+            engine
+                .execute(transaction(TransactionType::Resolve, 1, 1, None))
+                .unwrap();
+            engine.close_account(1).unwrap();
+            assert!(engine.is_closed(1));
+
+            assert_eq!(
+                engine.execute(transaction(TransactionType::Deposit, 1, 5, Some(1.0))),
+                Err(EngineError::AccountClosed {
+                    client_id: 1,
+                    transaction_id: TransactionId(5)
+                })
+            );
+            let transfer = Transaction {
+                to_client_id: Some(1),
+                ..of(2, transaction(TransactionType::Transfer, 1, 6, Some(1.0)))
+            };
+            assert_eq!(
+                engine.execute(transfer),
+                Err(EngineError::AccountClosed {
+                    client_id: 1,
+                    transaction_id: TransactionId(6)
+                })
+            );
+            assert_eq!(
+                engine.merge_clients(2, 1),
+                Err(EngineError::AccountClosed {
+                    client_id: 1,
+                    transaction_id: TransactionId(0)
+                })
+            );
+            assert_eq!(
+                engine.close_account(1),
+                Err(EngineError::AccountClosed {
+                    client_id: 1,
+                    transaction_id: TransactionId(0)
+                })
+            );
+            let snapshot = engine.account_snapshot(1).unwrap();
+            assert!(snapshot.closed);
+            assert_eq!(snapshot.available, 2.0);
+            assert!(!engine.account_snapshot(2).unwrap().closed);
+            assert!(sink
+                .events()
+                .contains(&AuditEvent::AccountClosed { client_id: 1 }));
+
+            // Closed accounts are not dormant, the others are since their last transaction out of
+            // for the 13 transactions executed, rejected ones included
+            assert_eq!(engine.dormant_accounts(10), [2]);
+            assert_eq!(engine.dormant_accounts(9), [2, 3]);
+            assert!(engine.dormant_accounts(11).is_empty());
+        }
+
+        #[test]
+        fn reserves_keep_a_minimum_balance_on_the_account() {
+            let mut tiers = Tiers::default();
+            tiers.clients.insert(2, Tier::Business);
+            tiers.policies.insert(
+                Tier::Business,
+                TierPolicy {
+                    reserve: Some(5.0),
+                    ..Default::default()
+                },
+            );
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                tiers: Arc::new(tiers),
+                ..Default::default()
+            });
+            let of = |client_id, transaction| Transaction {
+                client_id,
+                ..transaction
+            };
+            assert_eq!(
+                engine.set_reserve(1, None, Amount::unchecked(3.0)),
+                Err(EngineError::UnknownClient {
+                    client_id: 1,
+                    transaction_id: TransactionId(0)
+                })
+            );
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(10.0)))
+                .unwrap();
+            engine
+                .execute(of(
+                    2,
+                    transaction(TransactionType::Deposit, 1, 2, Some(10.0)),
+                ))
+                .unwrap();
+            assert_eq!(
+                engine.execute(transaction(TransactionType::Reserve, 1, 3, Some(3.0))),
+                Err(EngineError::AdminOnly(TransactionId(3)))
+            );
+            engine.set_reserve(1, None, Amount::unchecked(3.0)).unwrap();
+            assert_eq!(engine.reserve(1, None), 3.0);
+            assert_eq!(engine.reserve(2, None), 5.0);
+
+            let breached = |client_id, transaction_id, reserve| EngineError::ReserveBreached {
+                client_id,
+                transaction_id,
+                available: 10.0,
+                reserve,
+            };
+            assert_eq!(
+                engine.execute(transaction(TransactionType::Withdrawal, 1, 4, Some(8.0))),
+                Err(breached(1, TransactionId(4), 3.0))
+            );
+            assert_eq!(
+                engine.execute(transaction(TransactionType::Authorize, 1, 5, Some(7.5))),
+                Err(breached(1, TransactionId(5), 3.0))
+            );
+            let transfer = Transaction {
+                to_client_id: Some(1),
+                ..of(2, transaction(TransactionType::Transfer, 1, 6, Some(6.0)))
+            };
+            assert_eq!(
+                engine.execute(transfer),
+                Err(breached(2, TransactionId(6), 5.0))
+            );
+            engine
+                .execute(transaction(TransactionType::Withdrawal, 1, 7, Some(7.0)))
+                .unwrap();
+            engine
+                .execute(of(
+                    2,
+                    transaction(TransactionType::Withdrawal, 1, 8, Some(5.0)),
+                ))
+                .unwrap();
+
+            let snapshot = engine.account_snapshot(1).unwrap();
+            assert_eq!(snapshot.reserved, 3.0);
+            assert!(snapshot
+                .to_json_with_decimals(4)
+                .contains(r#""reserved":3.0000"#));
+            assert_eq!(engine.account_snapshot(2).unwrap().reserved, 5.0);
+
+            let mut saved = Vec::new();
+            write_snapshot(&engine, &mut saved).unwrap();
+            let mut restored = read_snapshot(saved.as_slice(), engine.config().clone()).unwrap();
+            assert_eq!(restored.reserve(1, None), 3.0);
+            assert_eq!(restored.reserve(2, None), 5.0);
+            // An explicit reserve, even of nothing, replaces that of the tier
+            restored.set_reserve(2, None, Amount::ZERO).unwrap();
+            restored
+                .execute(of(
+                    2,
+                    transaction(TransactionType::Withdrawal, 1, 9, Some(5.0)),
+                ))
+                .unwrap();
+            assert_eq!(restored.account_snapshot(2).unwrap().available, 0.0);
+        }
+
+        #[test]
+        fn merged_accounts_keep_funds_disputes_and_history() {
+            let sink = InMemoryAuditSink::new();
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                record_history: true,
+                ..EngineConfig::default()
+            });
+            engine.set_audit_sink(Box::new(sink.clone()));
+            let of = |client_id, transaction| Transaction {
+                client_id,
+                ..transaction
+            };
+            for transaction in [
+                transaction(TransactionType::Deposit, 1, 1, Some(10.0)),
+                of(2, transaction(TransactionType::Deposit, 1, 2, Some(5.0))),
+                of(2, transaction(TransactionType::Deposit, 1, 3, Some(3.0))),
+                of(2, transaction(TransactionType::Dispute, 1, 3, None)),
+                transfer(4, 1.0, 3),
+            ] {
+                engine.execute(transaction).unwrap();
+            }
+
+            engine.merge_clients(2, 1).unwrap();
+            assert!(!engine.accounts.contains_key(&2));
+            assert_eq!(engine.accounts[&1].get_available_funds(), 14.0);
+            assert_eq!(engine.accounts[&1].get_held_funds(), 3.0);
+            // The disputes and transactions of the merged client are now those of client 1
+            engine
+                .execute(transaction(TransactionType::Resolve, 1, 3, None))
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Dispute, 1, 2, None))
+                .unwrap();
+            assert_eq!(engine.accounts[&1].get_held_funds(), 5.0);
+            assert!(engine
+                .history(1)
+                .any(|entry| entry.transaction.transaction_id == TransactionId(2)));
+            assert!(sink.events().contains(&AuditEvent::AccountsMerged {
+                client_id: 2,
+                into: 1,
+            }));
+
+            let mut snapshot = Vec::new();
+            write_snapshot(&engine, &mut snapshot).unwrap();
+            let mut restored = read_snapshot(snapshot.as_slice(), EngineConfig::default()).unwrap();
+            assert_eq!(restored.accounts[&1].get_total_funds(), 17.0);
+            assert_eq!(restored.history(1).count(), engine.history(1).count());
+
+            let conflict = |conflict| EngineError::MergeConflict {
+                transaction_id: TransactionId(0),
+                client_id: 3,
+                into: 1,
+                conflict,
+            };
+            // Both legs of the transfer are disputable
+            assert_eq!(
+                restored.merge_clients(3, 1),
+                Err(conflict(MergeConflict::SharedTransaction(TransactionId(4))))
+            );
+            assert_eq!(
+                restored.merge_clients(1, 1),
+                Err(EngineError::MergeConflict {
+                    transaction_id: TransactionId(0),
+                    client_id: 1,
+                    into: 1,
+                    conflict: MergeConflict::SameClient,
+                })
+            );
+            assert_eq!(
+                restored.merge_clients(9, 1),
+                Err(EngineError::UnknownClient {
+                    client_id: 9,
+                    transaction_id: TransactionId(0)
+                })
+            );
+            restored
+                .execute_from(
+                    of(5, transaction(TransactionType::Lock, 1, 6, None)),
+                    Origin::Admin,
+                )
+                .unwrap();
+            restored.merge_clients(5, 3).unwrap();
+            assert!(restored.accounts[&3].is_locked());
+            assert_eq!(
+                restored.execute(of(5, transaction(TransactionType::Merge, 1, 7, None))),
+                Err(EngineError::AdminOnly(TransactionId(7)))
+            );
+        }
+
+        #[test]
+        fn accounts_are_replayed_as_they_were_after_a_transaction() {
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                record_history: true,
+                ..EngineConfig::default()
+            });
+            let of = |client_id, transaction| Transaction {
+                client_id,
+                ..transaction
+            };
+            for transaction in [
+                transaction(TransactionType::Deposit, 1, 1, Some(10.0)),
+                of(2, transaction(TransactionType::Deposit, 1, 2, Some(5.0))),
+                transaction(TransactionType::Withdrawal, 1, 3, Some(4.0)),
+                of(2, transaction(TransactionType::Dispute, 1, 2, None)),
+            ] {
+                engine.execute(transaction).unwrap();
+            }
+            engine.merge_clients(2, 1).unwrap();
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 6, Some(1.0)))
+                .unwrap();
+
+            // The history of the merged client is that of client 1 by now
+            let before_dispute = engine.state_at(TransactionId(3)).unwrap();
+            assert!(!before_dispute.accounts.contains_key(&2));
+            assert_eq!(before_dispute.accounts[&1].get_available_funds(), 11.0);
+            assert_eq!(before_dispute.accounts[&1].get_held_funds(), 0.0);
+            assert_eq!(before_dispute.history(1).count(), 3);
+            // The first transaction with the id is the deposit, its dispute came later
+            let deposited = engine.state_at(TransactionId(2)).unwrap();
+            assert_eq!(deposited.accounts[&1].get_available_funds(), 15.0);
+            assert_eq!(deposited.accounts[&1].get_held_funds(), 0.0);
+
+            let now = engine.state_at(TransactionId(6)).unwrap();
+            assert!(now.sorted_snapshots().eq(engine.sorted_snapshots()));
+            assert!(matches!(
+                engine.state_at(TransactionId(99)),
+                Err(EngineError::UnknownTransaction(TransactionId(99)))
+            ));
+        }
+
+        #[test]
+        fn fees_are_administrative_and_must_be_covered() {
+            let mut engine = TransactionEngine::new();
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(1.0)))
+                .unwrap();
+
+            assert_eq!(
+                engine.execute(transaction(TransactionType::Fee, 1, 0, Some(0.5))),
+                Err(EngineError::AdminOnly(TransactionId(0)))
+            );
+            assert_eq!(
+                engine.execute_from(
+                    transaction(TransactionType::Fee, 1, 0, Some(1.5)),
+                    Origin::Admin
+                ),
+                Err(EngineError::Account {
+                    client_id: 1,
+                    source: UpdateError::InsufficientFunds {
+                        transaction_id: TransactionId(0),
+                        requested: 1.5,
+                        available: 1.0,
+                    },
+                })
+            );
+            assert_eq!(
+                engine.execute_from(
+                    Transaction {
+                        client_id: 2,
+                        ..transaction(TransactionType::Interest, 1, 0, Some(1.0))
+                    },
+                    Origin::Admin
+                ),
+                Err(EngineError::UnknownClient {
+                    client_id: 2,
+                    transaction_id: TransactionId(0),
+                })
+            );
+            assert_eq!(engine.accounts[&1].get_available_funds(), 1.0);
+        }
+
+        /// Basic accounts, counting how many were created and restored
+        #[derive(Debug, Default)]
+        struct CountingFactory {
+            created: AtomicUsize,
+            restored: AtomicUsize,
+        }
+
+        impl AccountFactory for CountingFactory {
+            fn create(
+                &self,
+                client_id: ClientId,
+                policies: AccountPolicies,
+                store: Option<Box<dyn TransactionStore>>,
+            ) -> Box<dyn ClientAccount> {
+                self.created.fetch_add(1, Ordering::Relaxed);
+                BasicAccountFactory.create(client_id, policies, store)
+            }
+
+            fn restore(
+                &self,
+                state: AccountState,
+                policies: AccountPolicies,
+                store: Option<Box<dyn TransactionStore>>,
+            ) -> io::Result<Box<dyn ClientAccount>> {
+                self.restored.fetch_add(1, Ordering::Relaxed);
+                BasicAccountFactory.restore(state, policies, store)
+            }
+        }
+
+        #[test]
+        fn accounts_are_created_and_restored_by_the_factory() {
+            let factory = Arc::new(CountingFactory::default());
+            let config = EngineConfig {
+                account_factory: Some(factory.clone()),
+                ..EngineConfig::default()
+            };
+            let mut engine = TransactionEngine::with_config(config.clone());
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(5.0)))
+                .unwrap();
+            engine.execute(transfer(2, 2.0, 2)).unwrap();
+            let _ = engine.execute(transaction(TransactionType::Dispute, 1, 9, None));
+            assert_eq!(factory.created.load(Ordering::Relaxed), 2);
+
+            let path = std::env::temp_dir().join("rust-coding-test-engine-factory.snapshot");
+            engine.snapshot(&path).unwrap();
+            let restored = TransactionEngine::restore_with_config(&path, config).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(factory.restored.load(Ordering::Relaxed), 2);
+            assert_eq!(restored.accounts[&2].get_available_funds(), 2.0);
+        }
+
+        /// Store whose entries cannot be read back, as a corrupt log on disk
+        #[derive(Debug, Default)]
+        struct UnreadableStore(HashMap<TransactionId, LogEntry>);
+
+        impl TransactionStore for UnreadableStore {
+            fn insert(&mut self, transaction_id: TransactionId, entry: LogEntry) -> io::Result<()> {
+                TransactionStore::insert(&mut self.0, transaction_id, entry)
+            }
+
+            fn get(&self, transaction_id: TransactionId) -> io::Result<Option<LogEntry>> {
+                TransactionStore::get(&self.0, transaction_id)
+            }
+
+            fn remove(&mut self, transaction_id: TransactionId) -> io::Result<Option<LogEntry>> {
+                TransactionStore::remove(&mut self.0, transaction_id)
+            }
+
+            fn entries(&self) -> io::Result<Vec<(TransactionId, Option<Currency>, f64)>> {
+                Err(io::Error::new(io::ErrorKind::InvalidData, "corrupt log"))
+            }
+        }
+
+        #[derive(Debug)]
+        struct UnreadableFactory;
+
+        impl AccountFactory for UnreadableFactory {
+            fn create(
+                &self,
+                client_id: ClientId,
+                policies: AccountPolicies,
+                _: Option<Box<dyn TransactionStore>>,
+            ) -> Box<dyn ClientAccount> {
+                let store = Box::new(UnreadableStore::default());
+                Box::new(BasicAccount::with_store(client_id, policies, store))
+            }
+
+            fn restore(
+                &self,
+                state: AccountState,
+                policies: AccountPolicies,
+                store: Option<Box<dyn TransactionStore>>,
+            ) -> io::Result<Box<dyn ClientAccount>> {
+                BasicAccountFactory.restore(state, policies, store)
+            }
+        }
+
+        /// Store of client 2 refusing to log transactions from id 10 on, as a full disk
+        #[derive(Debug, Default)]
+        struct FullStore(HashMap<TransactionId, LogEntry>);
+
+        impl TransactionStore for FullStore {
+            fn insert(&mut self, transaction_id: TransactionId, entry: LogEntry) -> io::Result<()> {
+                if transaction_id.0 >= 10 {
+                    return Err(io::Error::other("disk full"));
+                }
+                TransactionStore::insert(&mut self.0, transaction_id, entry)
+            }
+
+            fn get(&self, transaction_id: TransactionId) -> io::Result<Option<LogEntry>> {
+                TransactionStore::get(&self.0, transaction_id)
+            }
+
+            fn remove(&mut self, transaction_id: TransactionId) -> io::Result<Option<LogEntry>> {
+                TransactionStore::remove(&mut self.0, transaction_id)
+            }
+
+            fn entries(&self) -> io::Result<Vec<(TransactionId, Option<Currency>, f64)>> {
+                TransactionStore::entries(&self.0)
+            }
+        }
+
+        #[derive(Debug)]
+        struct FullFactory;
+
+        impl AccountFactory for FullFactory {
+            fn create(
+                &self,
+                client_id: ClientId,
+                policies: AccountPolicies,
+                store: Option<Box<dyn TransactionStore>>,
+            ) -> Box<dyn ClientAccount> {
+                if client_id != 2 {
+                    return BasicAccountFactory.create(client_id, policies, store);
+                }
+                Box::new(BasicAccount::with_store(
+                    client_id,
+                    policies,
+                    Box::new(FullStore::default()),
+                ))
+            }
+
+            fn restore(
+                &self,
+                state: AccountState,
+                policies: AccountPolicies,
+                store: Option<Box<dyn TransactionStore>>,
+            ) -> io::Result<Box<dyn ClientAccount>> {
+                BasicAccountFactory.restore(state, policies, store)
+            }
+        }
+
+        #[test]
+        fn transfer_refused_by_the_destination_store_changes_nothing() {
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                account_factory: Some(Arc::new(FullFactory)),
+                ..EngineConfig::default()
+            });
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(5.0)))
+                .unwrap();
+
+            assert!(matches!(
+                engine.execute(transfer(10, 2.0, 2)),
+                Err(EngineError::Account {
+                    client_id: 2,
+                    source: UpdateError::Storage { .. },
+                })
+            ));
+            assert_eq!(engine.accounts[&1].get_available_funds(), 5.0);
+            assert!(!engine.accounts.contains_key(&2));
+
+            engine
+                .execute(Transaction {
+                    client_id: 2,
+                    ..transaction(TransactionType::Deposit, 1, 2, Some(1.0))
+                })
+                .unwrap();
+            assert!(engine.execute(transfer(11, 2.0, 2)).is_err());
+            assert_eq!(engine.accounts[&1].get_available_funds(), 5.0);
+            assert_eq!(engine.accounts[&2].get_available_funds(), 1.0);
+        }
+
+        #[test]
+        fn transfer_refused_by_the_source_takes_back_the_credit() {
+            let mut engine = TransactionEngine::new();
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(1.0)))
+                .unwrap();
+            engine
+                .execute(Transaction {
+                    client_id: 2,
+                    ..transaction(TransactionType::Deposit, 1, 2, Some(1.0))
+                })
+                .unwrap();
+
+            assert!(engine.execute(transfer(3, 2.0, 2)).is_err());
+            assert_eq!(engine.accounts[&1].get_available_funds(), 1.0);
+            assert_eq!(engine.accounts[&2].get_available_funds(), 1.0);
+            assert_eq!(
+                engine.accounts[&2].state().unwrap().transaction_log.len(),
+                1
+            );
+        }
+
+        #[test]
+        fn unreadable_logs_fail_snapshots_and_merges() {
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                account_factory: Some(Arc::new(UnreadableFactory)),
+                ..EngineConfig::default()
+            });
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(5.0)))
+                .unwrap();
+            engine.execute(transfer(2, 2.0, 2)).unwrap();
+
+            let path = std::env::temp_dir().join("rust-coding-test-engine-unreadable.snapshot");
+            let snapshot = engine.snapshot(&path);
+            let _ = std::fs::remove_file(&path);
+            assert!(
+                matches!(snapshot, Err(SnapshotError::Io(_))),
+                "{:?}",
+                snapshot
+            );
+            assert!(matches!(
+                engine.merge_clients(1, 2),
+                Err(EngineError::Account {
+                    client_id: 1,
+                    source: UpdateError::Storage { .. },
+                })
+            ));
+        }
+
+        #[test]
+        fn accounts_are_read_as_owned_snapshots() {
+            let mut engine = TransactionEngine::new();
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(3.0)))
+                .unwrap();
+            engine
+                .execute(Transaction {
+                    currency: Some("EUR".parse().unwrap()),
+                    ..transaction(TransactionType::Deposit, 1, 2, Some(1.0))
+                })
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Dispute, 1, 1, None))
+                .unwrap();
+
+            let snapshot = engine.account_snapshot(1).unwrap();
+            // Snapshots do not follow later changes of the account
+            engine
+                .execute(transaction(TransactionType::Resolve, 1, 1, None))
+                .unwrap();
+
+            assert_eq!(
+                snapshot,
+                AccountSnapshot {
+                    client: 1,
+                    available: 0.0,
+                    held: 3.0,
+                    total: 3.0,
+                    locked: false,
+                    reserved: 0.0,
+                    closed: false,
+                    currency: None,
+                }
+            );
+            assert!(engine.account_snapshot(2).is_none());
+            let mut currencies: Vec<_> = engine
+                .iter_snapshots()
+                .map(|snapshot| (snapshot.currency, snapshot.available))
+                .collect();
+            currencies.sort_by_key(|(currency, _)| *currency);
+            assert_eq!(
+                currencies,
+                [(None, 3.0), (Some("EUR".parse().unwrap()), 1.0)]
+            );
+        }
+
+        #[test]
+        fn snapshots_can_be_ordered_by_client() {
+            let mut engine = TransactionEngine::new();
+            for client_id in [40u8, 3, 17, 250, 1, 99, 8] {
+                engine
+                    .execute(Transaction {
+                        client_id: ClientId::from(client_id),
+                        ..transaction(TransactionType::Deposit, 1, u32::from(client_id), Some(1.0))
+                    })
+                    .unwrap();
+            }
+            engine
+                .execute(Transaction {
+                    client_id: 3,
+                    currency: Some("EUR".parse().unwrap()),
+                    ..transaction(TransactionType::Deposit, 1, 1000, Some(1.0))
+                })
+                .unwrap();
+
+            let order: Vec<_> = engine
+                .snapshots(OutputOrder::Client)
+                .map(|snapshot| (snapshot.client, snapshot.currency.is_some()))
+                .collect();
+
+            assert_eq!(
+                order,
+                [
+                    (1, false),
+                    (3, false),
+                    (3, true),
+                    (8, false),
+                    (17, false),
+                    (40, false),
+                    (99, false),
+                    (250, false)
+                ]
+            );
+            assert_eq!(engine.snapshots(OutputOrder::None).count(), order.len());
+        }
+
+        #[test]
+        fn files_are_processed_into_records() {
+            let path = std::env::temp_dir().join("rust-coding-test-engine-process.csv");
+            std::fs::write(
+                &path,
+                "type,client,tx,amount\n\
+                 deposit,2,1,5.0\n\
+                 deposit,1,2,3.0\n\
+                 withdrawal,1,3,10.0\n\
+                 deposit,x,4,1.0\n",
+            )
+            .unwrap();
+            let mut engine = TransactionEngine::new();
+
+            let report = engine.process_file(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!((report.rejected.len(), report.malformed.len()), (1, 1));
+            let records: Vec<_> = engine
+                .sorted_snapshots()
+                .map(|snapshot| (snapshot.client, snapshot.total))
+                .collect();
+            assert_eq!(records, [(1, 3.0), (2, 5.0)]);
+            assert!(engine.process_file("missing.csv").is_err());
         }
     }
 }
@@ -0,0 +1,881 @@
+use crate::account::ClientId;
+use crate::currency::Currency;
+use crate::transaction::{Transaction, TransactionId};
+use std::{fmt, io};
+
+/// Reasons why an operation on a client account was refused
+#[derive(Debug, Clone, PartialEq)]
+pub enum UpdateError {
+    /// Withdrawal for more than the available funds
+    InsufficientFunds {
+        transaction_id: TransactionId,
+        requested: f64,
+        available: f64,
+    },
+    /// Dispute references a transaction that is unknown or already disputed
+    TransactionNotFound(TransactionId),
+    /// Dispute policy does not allow disputing this kind of transaction
+    NotDisputable(TransactionId),
+    /// Dispute would push the available funds below zero and the policy forbids it
+    DisputeExceedsAvailable {
+        transaction_id: TransactionId,
+        amount: f64,
+        available: f64,
+    },
+    /// Partial dispute for a non-positive amount or more than is still disputable
+    InvalidDisputeAmount {
+        transaction_id: TransactionId,
+        amount: f64,
+        disputable: f64,
+    },
+    /// Resolve or chargeback references a transaction that is not under dispute
+    NoActiveDispute(TransactionId),
+    /// Representment references a transaction that was not charged back or already represented
+    NotChargedBack(TransactionId),
+    /// Capture or void references a transaction that is not an open authorization
+    NoAuthorization(TransactionId),
+    /// Capture for a non-positive amount or more than was authorized
+    InvalidCaptureAmount {
+        transaction_id: TransactionId,
+        amount: f64,
+        authorized: f64,
+    },
+    /// Account is locked and the lock policy does not allow the transaction
+    AccountLocked(TransactionId),
+    /// The transaction log of the account could not be read or written
+    Storage {
+        transaction_id: TransactionId,
+        message: String,
+    },
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpdateError::InsufficientFunds {
+                transaction_id,
+                requested,
+                available,
+            } => write!(
+                f,
+                "transaction {}: insufficient funds, requested {:.4} but only {:.4} available",
+                transaction_id, requested, available
+            ),
+            UpdateError::TransactionNotFound(transaction_id) => write!(
+                f,
+                "transaction {} is unknown or already disputed",
+                transaction_id
+            ),
+            UpdateError::NotDisputable(transaction_id) => {
+                write!(f, "transaction {} cannot be disputed", transaction_id)
+            }
+            UpdateError::DisputeExceedsAvailable {
+                transaction_id,
+                amount,
+                available,
+            } => write!(
+                f,
+                "transaction {}: disputing {:.4} exceeds the {:.4} available",
+                transaction_id, amount, available
+            ),
+            UpdateError::InvalidDisputeAmount {
+                transaction_id,
+                amount,
+                disputable,
+            } => write!(
+                f,
+                "transaction {}: cannot dispute {:.4}, {:.4} is still disputable",
+                transaction_id, amount, disputable
+            ),
+            UpdateError::NoActiveDispute(transaction_id) => {
+                write!(f, "transaction {} is not under dispute", transaction_id)
+            }
+            UpdateError::NotChargedBack(transaction_id) => {
+                write!(f, "transaction {} was not charged back", transaction_id)
+            }
+            UpdateError::NoAuthorization(transaction_id) => {
+                write!(
+                    f,
+                    "transaction {} is not an open authorization",
+                    transaction_id
+                )
+            }
+            UpdateError::InvalidCaptureAmount {
+                transaction_id,
+                amount,
+                authorized,
+            } => write!(
+                f,
+                "transaction {}: cannot capture {:.4}, {:.4} is authorized",
+                transaction_id, amount, authorized
+            ),
+            UpdateError::AccountLocked(transaction_id) => {
+                write!(
+                    f,
+                    "transaction {} rejected, account is locked",
+                    transaction_id
+                )
+            }
+            UpdateError::Storage {
+                transaction_id,
+                message,
+            } => write!(
+                f,
+                "transaction {}: transaction log failed: {}",
+                transaction_id, message
+            ),
+        }
+    }
+}
+
+impl UpdateError {
+    pub(crate) fn storage(transaction_id: TransactionId, err: io::Error) -> Self {
+        UpdateError::Storage {
+            transaction_id,
+            message: err.to_string(),
+        }
+    }
+
+    pub fn transaction_id(&self) -> TransactionId {
+        match self {
+            UpdateError::InsufficientFunds { transaction_id, .. }
+            | UpdateError::DisputeExceedsAvailable { transaction_id, .. }
+            | UpdateError::InvalidDisputeAmount { transaction_id, .. }
+            | UpdateError::InvalidCaptureAmount { transaction_id, .. }
+            | UpdateError::Storage { transaction_id, .. } => *transaction_id,
+            UpdateError::TransactionNotFound(transaction_id)
+            | UpdateError::NotDisputable(transaction_id)
+            | UpdateError::NoActiveDispute(transaction_id)
+            | UpdateError::NotChargedBack(transaction_id)
+            | UpdateError::NoAuthorization(transaction_id)
+            | UpdateError::AccountLocked(transaction_id) => *transaction_id,
+        }
+    }
+
+    /// Stable identifier of the kind of error, e.g. to label metrics
+    pub fn code(&self) -> &'static str {
+        match self {
+            UpdateError::InsufficientFunds { .. } => "insufficient_funds",
+            UpdateError::TransactionNotFound(_) => "transaction_not_found",
+            UpdateError::NotDisputable(_) => "not_disputable",
+            UpdateError::DisputeExceedsAvailable { .. } => "dispute_exceeds_available",
+            UpdateError::InvalidDisputeAmount { .. } => "invalid_dispute_amount",
+            UpdateError::NoActiveDispute(_) => "no_active_dispute",
+            UpdateError::NotChargedBack(_) => "not_charged_back",
+            UpdateError::NoAuthorization(_) => "no_authorization",
+            UpdateError::InvalidCaptureAmount { .. } => "invalid_capture_amount",
+            UpdateError::AccountLocked(_) => "account_locked",
+            UpdateError::Storage { .. } => "storage",
+        }
+    }
+}
+
+impl std::error::Error for UpdateError {}
+
+/// Errors returned by the transaction engine when a transaction could not be applied
+#[derive(Debug, Clone, PartialEq)]
+pub enum EngineError {
+    /// Deposit or withdrawal without an amount
+    MissingAmount(TransactionId),
+    /// Deposit or withdrawal reuses the id of an earlier transaction
+    DuplicateTransaction(TransactionId),
+    /// Transfer without a destination client
+    MissingDestination(TransactionId),
+    /// Transfer whose destination is the debited client
+    SelfTransfer(TransactionId),
+    /// Transfer or merge between clients processed by different shards of a
+    /// [`ShardedEngine`](crate::sharded::ShardedEngine) or
+    /// [`ConcurrentEngine`](crate::concurrent::ConcurrentEngine)
+    CrossShardTransfer(TransactionId),
+    /// Dispute, resolve or chargeback references a transaction that was never applied
+    UnknownTransaction(TransactionId),
+    /// Dispute, resolve or chargeback issued by a client that does not own the transaction
+    ClientMismatch {
+        transaction_id: TransactionId,
+        owner: ClientId,
+        client_id: ClientId,
+    },
+    /// Dispute, resolve or chargeback in another currency than the referenced transaction
+    CurrencyMismatch {
+        transaction_id: TransactionId,
+        expected: Option<Currency>,
+        currency: Currency,
+    },
+    /// Dispute of a transaction that took effect longer ago than the configured dispute window
+    DisputeWindowExpired {
+        transaction_id: TransactionId,
+        days: u32,
+    },
+    /// The client account refused the operation
+    Account {
+        client_id: ClientId,
+        source: UpdateError,
+    },
+    /// The transaction breaks one of the configured [`LimitsPolicy`](crate::policy::LimitsPolicy)
+    /// limits
+    LimitExceeded {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        limit: Limit,
+    },
+    /// Withdrawal, authorization or transfer that would take the available funds below the reserve
+    /// of the account, see
+    /// [`TransactionEngine::reserve`](crate::engine::TransactionEngine::reserve)
+    ReserveBreached {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        available: f64,
+        reserve: f64,
+    },
+    /// Administrative transaction submitted by a source that is not trusted with them
+    AdminOnly(TransactionId),
+    /// Adjustment submitted to an engine that does not allow them, see
+    /// [`EngineConfig::allow_adjustments`](crate::engine::EngineConfig::allow_adjustments)
+    AdjustmentsDisabled(TransactionId),
+    /// Transaction of a closed account, or transfer or merge into one
+    AccountClosed {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    },
+    /// Closure of an account still holding funds under dispute, authorization or settlement
+    HeldFunds {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        currency: Option<Currency>,
+        held: f64,
+    },
+    /// Unlock, fee or interest of a client without an account
+    UnknownClient {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    },
+    /// Reversal of a transfer, or of a transaction under dispute or charged back
+    NotReversible(TransactionId),
+    /// Merge of two accounts that cannot be combined
+    MergeConflict {
+        transaction_id: TransactionId,
+        client_id: ClientId,
+        into: ClientId,
+        conflict: MergeConflict,
+    },
+    /// Transaction flagged by a risk rule of an engine blocking suspicious transactions, see
+    /// [`RiskPolicy::block`](crate::risk::RiskPolicy::block)
+    RiskFlagged {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        rule: &'static str,
+        reason: String,
+    },
+    /// The transaction could not be appended to the write-ahead log and was not applied
+    WalWrite {
+        transaction_id: TransactionId,
+        message: String,
+    },
+    /// Batch of columns whose rows could not be read, see
+    /// [`TransactionEngine::execute_columns`](crate::engine::TransactionEngine::execute_columns).
+    /// It refers to no single transaction, so its transaction id is 0.
+    UnreadableBatch(String),
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::MissingAmount(transaction_id) => {
+                write!(f, "transaction {} is missing an amount", transaction_id)
+            }
+            EngineError::DuplicateTransaction(transaction_id) => {
+                write!(f, "transaction id {} was already used", transaction_id)
+            }
+            EngineError::UnknownTransaction(transaction_id) => {
+                write!(f, "transaction {} was never applied", transaction_id)
+            }
+            EngineError::MissingDestination(transaction_id) => {
+                write!(
+                    f,
+                    "transfer {} is missing a destination client",
+                    transaction_id
+                )
+            }
+            EngineError::SelfTransfer(transaction_id) => {
+                write!(
+                    f,
+                    "transfer {} has the same source and destination",
+                    transaction_id
+                )
+            }
+            EngineError::CrossShardTransfer(transaction_id) => write!(
+                f,
+                "transaction {} spans two shards and cannot be applied atomically",
+                transaction_id
+            ),
+            EngineError::ClientMismatch {
+                transaction_id,
+                owner,
+                client_id,
+            } => write!(
+                f,
+                "client {} cannot reference transaction {} owned by client {}",
+                client_id, transaction_id, owner
+            ),
+            EngineError::CurrencyMismatch {
+                transaction_id,
+                expected,
+                currency,
+            } => write!(
+                f,
+                "transaction {} was made in {}, not {}",
+                transaction_id,
+                expected.map_or("no currency".to_string(), |expected| expected.to_string()),
+                currency
+            ),
+            EngineError::DisputeWindowExpired {
+                transaction_id,
+                days,
+            } => write!(
+                f,
+                "transaction {} is older than the dispute window of {} days",
+                transaction_id, days
+            ),
+            EngineError::Account { client_id, source } => {
+                write!(f, "client {}: {}", client_id, source)
+            }
+            EngineError::LimitExceeded {
+                client_id,
+                transaction_id,
+                limit,
+            } => write!(
+                f,
+                "client {}: transaction {} exceeds {}",
+                client_id, transaction_id, limit
+            ),
+            EngineError::ReserveBreached {
+                client_id,
+                transaction_id,
+                available,
+                reserve,
+            } => write!(
+                f,
+                "client {}: transaction {} would take the available funds of {:.4} below the \
+                 reserve of {:.4}",
+                client_id, transaction_id, available, reserve
+            ),
+            EngineError::AdminOnly(transaction_id) => write!(
+                f,
+                "transaction {} is administrative and was not submitted by an admin",
+                transaction_id
+            ),
+            EngineError::AdjustmentsDisabled(transaction_id) => write!(
+                f,
+                "transaction {} is an adjustment and adjustments are not allowed",
+                transaction_id
+            ),
+            EngineError::AccountClosed {
+                client_id,
+                transaction_id,
+            } => write!(
+                f,
+                "transaction {} refers to client {} whose account is closed",
+                transaction_id, client_id
+            ),
+            EngineError::HeldFunds {
+                client_id,
+                transaction_id,
+                currency,
+                held,
+            } => {
+                let currency = currency.map_or(String::new(), |currency| format!(" {}", currency));
+                write!(
+                    f,
+                    "transaction {} cannot close the account of client {} holding {:.4}{}",
+                    transaction_id, client_id, held, currency
+                )
+            }
+            EngineError::UnknownClient {
+                client_id,
+                transaction_id,
+            } => write!(
+                f,
+                "transaction {} refers to client {} which has no account",
+                transaction_id, client_id
+            ),
+            EngineError::NotReversible(transaction_id) => write!(
+                f,
+                "transaction {} cannot be reversed, only deposits and withdrawals that are not \
+                 under dispute or charged back can",
+                transaction_id
+            ),
+            EngineError::MergeConflict {
+                client_id,
+                into,
+                conflict,
+                ..
+            } => write!(
+                f,
+                "client {} cannot be merged into client {}: {}",
+                client_id, into, conflict
+            ),
+            EngineError::RiskFlagged {
+                client_id,
+                transaction_id,
+                rule,
+                reason,
+            } => write!(
+                f,
+                "client {}: transaction {} was blocked by risk rule {}: {}",
+                client_id, transaction_id, rule, reason
+            ),
+            EngineError::WalWrite {
+                transaction_id,
+                message,
+            } => write!(
+                f,
+                "transaction {} could not be logged: {}",
+                transaction_id, message
+            ),
+            EngineError::UnreadableBatch(message) => {
+                write!(f, "batch of columns could not be read: {}", message)
+            }
+        }
+    }
+}
+
+impl EngineError {
+    /// Transaction that was rejected
+    pub fn transaction_id(&self) -> TransactionId {
+        match self {
+            EngineError::MissingAmount(transaction_id)
+            | EngineError::DuplicateTransaction(transaction_id)
+            | EngineError::UnknownTransaction(transaction_id)
+            | EngineError::MissingDestination(transaction_id)
+            | EngineError::SelfTransfer(transaction_id)
+            | EngineError::CrossShardTransfer(transaction_id)
+            | EngineError::AdminOnly(transaction_id)
+            | EngineError::AdjustmentsDisabled(transaction_id)
+            | EngineError::NotReversible(transaction_id) => *transaction_id,
+            EngineError::ClientMismatch { transaction_id, .. }
+            | EngineError::CurrencyMismatch { transaction_id, .. }
+            | EngineError::DisputeWindowExpired { transaction_id, .. }
+            | EngineError::LimitExceeded { transaction_id, .. }
+            | EngineError::ReserveBreached { transaction_id, .. }
+            | EngineError::AccountClosed { transaction_id, .. }
+            | EngineError::HeldFunds { transaction_id, .. }
+            | EngineError::UnknownClient { transaction_id, .. }
+            | EngineError::MergeConflict { transaction_id, .. }
+            | EngineError::RiskFlagged { transaction_id, .. }
+            | EngineError::WalWrite { transaction_id, .. } => *transaction_id,
+            EngineError::Account { source, .. } => source.transaction_id(),
+            EngineError::UnreadableBatch(_) => TransactionId(0),
+        }
+    }
+
+    /// Stable identifier of the kind of error, e.g. to label metrics. Errors of the account
+    /// report the code of the underlying [`UpdateError`].
+    pub fn code(&self) -> &'static str {
+        match self {
+            EngineError::MissingAmount(_) => "missing_amount",
+            EngineError::DuplicateTransaction(_) => "duplicate_transaction",
+            EngineError::UnknownTransaction(_) => "unknown_transaction",
+            EngineError::MissingDestination(_) => "missing_destination",
+            EngineError::SelfTransfer(_) => "self_transfer",
+            EngineError::CrossShardTransfer(_) => "cross_shard_transfer",
+            EngineError::ClientMismatch { .. } => "client_mismatch",
+            EngineError::CurrencyMismatch { .. } => "currency_mismatch",
+            EngineError::DisputeWindowExpired { .. } => "dispute_window_expired",
+            EngineError::Account { source, .. } => source.code(),
+            EngineError::LimitExceeded { limit, .. } => limit.code(),
+            EngineError::ReserveBreached { .. } => "reserve_breached",
+            EngineError::AdminOnly(_) => "admin_only",
+            EngineError::AdjustmentsDisabled(_) => "adjustments_disabled",
+            EngineError::AccountClosed { .. } => "account_closed",
+            EngineError::HeldFunds { .. } => "held_funds",
+            EngineError::UnknownClient { .. } => "unknown_client",
+            EngineError::NotReversible(_) => "not_reversible",
+            EngineError::MergeConflict { .. } => "merge_conflict",
+            EngineError::RiskFlagged { .. } => "risk_flagged",
+            EngineError::WalWrite { .. } => "wal_write",
+            EngineError::UnreadableBatch(_) => "unreadable_batch",
+        }
+    }
+}
+
+impl std::error::Error for EngineError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EngineError::Account { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Why two accounts cannot be merged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeConflict {
+    /// The account would be merged into itself
+    SameClient,
+    /// Both accounts hold the transaction, e.g. a transfer between them that is still disputable
+    SharedTransaction(TransactionId),
+    /// The merged account has withdrawals that did not settle yet, which the account merged into
+    /// may not be able to settle
+    PendingWithdrawals,
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeConflict::SameClient => write!(f, "both are the same account"),
+            MergeConflict::SharedTransaction(transaction_id) => {
+                write!(f, "both accounts hold transaction {}", transaction_id)
+            }
+            MergeConflict::PendingWithdrawals => write!(f, "withdrawals are pending settlement"),
+        }
+    }
+}
+
+/// Limit broken by a transaction, with its configured value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    TransactionAmount(f64),
+    DailyWithdrawals(f64),
+    TransactionCount(u64),
+}
+
+impl Limit {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Limit::TransactionAmount(_) => "transaction_amount_limit",
+            Limit::DailyWithdrawals(_) => "daily_withdrawal_limit",
+            Limit::TransactionCount(_) => "transaction_count_limit",
+        }
+    }
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::TransactionAmount(max) => {
+                write!(f, "the maximum transaction amount of {:.4}", max)
+            }
+            Limit::DailyWithdrawals(cap) => write!(f, "the daily withdrawal cap of {:.4}", cap),
+            Limit::TransactionCount(max) => write!(f, "the limit of {} transactions", max),
+        }
+    }
+}
+
+/// Errors raised while reading transactions from an input
+#[derive(Debug)]
+pub enum InputError {
+    /// The input could not be read at all
+    Io(io::Error),
+    /// A single row could not be parsed, the following rows may still be readable
+    Malformed {
+        line: u64,
+        message: String,
+        /// The row as read so that it can be fixed and processed again, empty if unavailable
+        row: String,
+    },
+    /// The input is compressed with the named format, which this build cannot decompress
+    Compressed(&'static str),
+    /// The header of the input lacks a required column, under the given name
+    MissingColumn { column: &'static str, name: String },
+    /// The transaction arrived after a later one had been passed on, too late to be reordered
+    OutOfOrder {
+        transaction: Box<Transaction>,
+        previous: TransactionId,
+    },
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputError::Io(err) => write!(f, "failed to read input: {}", err),
+            InputError::Malformed { line, message, .. } => {
+                write!(f, "malformed row on line {}: {}", line, message)
+            }
+            InputError::Compressed(compression) => {
+                let tool = if *compression == "zstd" {
+                    "zstdcat"
+                } else {
+                    "zcat"
+                };
+                write!(
+                    f,
+                    "input is {} compressed, which this build cannot decompress; pipe it \
+                     through {} and read from stdin with '-' instead",
+                    compression, tool
+                )
+            }
+            InputError::MissingColumn { column, name } if *column == name => {
+                write!(f, "input has no '{}' column", column)
+            }
+            InputError::MissingColumn { column, name } => write!(
+                f,
+                "input has no '{}' column, from which '{}' is read",
+                name, column
+            ),
+            InputError::OutOfOrder {
+                transaction,
+                previous,
+            } => write!(
+                f,
+                "transaction {} arrived after transaction {}, outside of the reordering window",
+                transaction.transaction_id, previous
+            ),
+        }
+    }
+}
+
+impl InputError {
+    /// Whether no further row of the input can be read
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, InputError::Io(_) | InputError::MissingColumn { .. })
+    }
+}
+
+impl std::error::Error for InputError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            InputError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+// Only compares the kind of io errors since io::Error is not comparable
+impl PartialEq for InputError {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (InputError::Io(a), InputError::Io(b)) => a.kind() == b.kind(),
+            (
+                InputError::Malformed { line, message, row },
+                InputError::Malformed {
+                    line: other_line,
+                    message: other_message,
+                    row: other_row,
+                },
+            ) => line == other_line && message == other_message && row == other_row,
+            (InputError::Compressed(a), InputError::Compressed(b)) => a == b,
+            (
+                InputError::MissingColumn { column, name },
+                InputError::MissingColumn {
+                    column: other_column,
+                    name: other_name,
+                },
+            ) => column == other_column && name == other_name,
+            (
+                InputError::OutOfOrder {
+                    transaction,
+                    previous,
+                },
+                InputError::OutOfOrder {
+                    transaction: other_transaction,
+                    previous: other_previous,
+                },
+            ) => transaction == other_transaction && previous == other_previous,
+            _ => false,
+        }
+    }
+}
+
+impl From<io::Error> for InputError {
+    fn from(err: io::Error) -> Self {
+        InputError::Io(err)
+    }
+}
+
+impl From<csv::Error> for InputError {
+    fn from(err: csv::Error) -> Self {
+        let line = err.position().map_or(0, |position| position.line());
+        let message = err.to_string();
+        match err.into_kind() {
+            csv::ErrorKind::Io(err) => InputError::Io(err),
+            _ => InputError::Malformed {
+                line,
+                message,
+                row: String::new(),
+            },
+        }
+    }
+}
+
+/// Errors raised while writing or reading an engine snapshot
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    /// Snapshot was written by a newer version of the engine
+    UnsupportedVersion(u32),
+    Malformed {
+        line: u64,
+        message: String,
+    },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "snapshot i/o failed: {}", err),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {}", version)
+            }
+            SnapshotError::Malformed { line, message } => {
+                write!(f, "malformed snapshot on line {}: {}", line, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SnapshotError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        SnapshotError::Io(err)
+    }
+}
+
+impl From<csv::Error> for SnapshotError {
+    fn from(err: csv::Error) -> Self {
+        let line = err.position().map_or(0, |position| position.line());
+        let message = err.to_string();
+        match err.into_kind() {
+            csv::ErrorKind::Io(err) => SnapshotError::Io(err),
+            _ => SnapshotError::Malformed { line, message },
+        }
+    }
+}
+
+/// Errors raised while reading an engine state written in the [exchange](crate::exchange)
+/// format
+#[derive(Debug)]
+pub enum ExchangeError {
+    Io(io::Error),
+    /// File is not in the exchange format, or in a newer version of it
+    Unsupported {
+        format: String,
+        version: u32,
+    },
+    Malformed {
+        line: u64,
+        message: String,
+    },
+    /// An account could not be restored from its records
+    Account(SnapshotError),
+}
+
+impl fmt::Display for ExchangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExchangeError::Io(err) => write!(f, "reading the export failed: {}", err),
+            ExchangeError::Unsupported { format, version } => {
+                write!(
+                    f,
+                    "unsupported export format '{}' version {}",
+                    format, version
+                )
+            }
+            ExchangeError::Malformed { line, message } => {
+                write!(f, "malformed export on line {}: {}", line, message)
+            }
+            ExchangeError::Account(err) => write!(f, "restoring an account failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ExchangeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExchangeError::Io(err) => Some(err),
+            ExchangeError::Account(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ExchangeError {
+    fn from(err: io::Error) -> Self {
+        ExchangeError::Io(err)
+    }
+}
+
+/// Errors that stop a [`Consumer`](crate::consumer::Consumer)
+#[derive(Debug)]
+pub enum ConsumerError {
+    /// Polling or committing on the message stream failed
+    Stream(io::Error),
+    Audit(io::Error),
+    Snapshot(SnapshotError),
+    /// The sink of a [`ChangeStream`](crate::changes::ChangeStream) failed
+    Changes(io::Error),
+}
+
+impl fmt::Display for ConsumerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConsumerError::Stream(err) => write!(f, "message stream failed: {}", err),
+            ConsumerError::Audit(err) => write!(f, "failed to write audit log: {}", err),
+            ConsumerError::Snapshot(err) => write!(f, "{}", err),
+            ConsumerError::Changes(err) => write!(f, "failed to emit changed accounts: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ConsumerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConsumerError::Stream(err)
+            | ConsumerError::Audit(err)
+            | ConsumerError::Changes(err) => Some(err),
+            ConsumerError::Snapshot(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for ConsumerError {
+    fn from(err: io::Error) -> Self {
+        ConsumerError::Stream(err)
+    }
+}
+
+impl From<SnapshotError> for ConsumerError {
+    fn from(err: SnapshotError) -> Self {
+        ConsumerError::Snapshot(err)
+    }
+}
+
+/// Errors raised while loading a configuration file
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Invalid { line: u64, message: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "failed to read configuration: {}", err),
+            ConfigError::Invalid { line, message } => {
+                write!(f, "invalid configuration on line {}: {}", line, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> Self {
+        ConfigError::Io(err)
+    }
+}
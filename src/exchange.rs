@@ -0,0 +1,676 @@
+//! Documented exchange format of the engine state, written by `export` and read by `import`, for
+//! moving accounts between versions of the engine and for tools of other teams. Unlike
+//! [snapshots](crate::snapshot), whose records follow the internals of the engine, every line is
+//! a flat json object with named fields, tagged by its `kind`. The first line is always the
+//! header:
+//!
+//! ```text
+//! {"kind":"header","format":"rust-coding-test/engine-state","version":1,"lsn":<lsn>
+//!     [,"offset":<rows>]}
+//! {"kind":"input","source":<name>,"offset":<offset>,"index":<index>}
+//! {"kind":"account","client":<id>,"locked":<bool>[,"closed":true][,"last_active":<lsn>]}
+//! {"kind":"balance","client":<id>[,"currency":<code>],"available":<amount>,"held":<amount>}
+//! {"kind":"reserve","client":<id>,"amount":<amount>[,"currency":<code>]}
+//! {"kind":"disputable","client":<id>,"tx":<tx>,"amount":<amount>[,"currency":<code>]}
+//! {"kind":"dispute","client":<id>,"tx":<tx>,"state":<state>,"disputes":<count>}
+//! {"kind":"hold","client":<id>,"tx":<tx>,"amount":<amount>[,"currency":<code>]}
+//! {"kind":"pending_hold","client":<id>,"tx":<tx>,"amount":<amount>[,"currency":<code>]}
+//! {"kind":"authorization","client":<id>,"tx":<tx>,"amount":<amount>[,"currency":<code>]}
+//! {"kind":"chargeback","client":<id>,"tx":<tx>,"amount":<amount>[,"currency":<code>]}
+//! {"kind":"pending_withdrawal","client":<id>,"tx":<tx>,"amount":<amount>,"ticks":<ticks>
+//!     [,"currency":<code>]}
+//! {"kind":"transaction","tx":<tx>,"client":<id>,"type":<type>,"amount":<amount>,
+//!     "applied":<bool>[,"to":<id>][,"currency":<code>][,"timestamp":<time>]}
+//! {"kind":"usage","client":<id>,"transactions":<count>[,"day":<day>]}
+//! {"kind":"withdrawn","client":<id>,"amount":<amount>[,"currency":<code>]}
+//! {"kind":"retained","client":<id>,"tx":<tx>}
+//! {"kind":"opened","client":<id>,"tx":<tx>,"lsn":<lsn>[,"timestamp":<time>]}
+//! {"kind":"books"[,"currency":<code>],"opening":<amount>,"deposited":<amount>,
+//!     "withdrawn":<amount>,"charged_back":<amount>,"fees":<amount>,"interest":<amount>,
+//!     "adjusted":<amount>,"reversed":<amount>}
+//! {"kind":"ledger","client":<id>,"sequence":<n>,"type":<type>,"tx":<tx>[,"amount":<amount>]
+//!     [,"to":<id>][,"currency":<code>][,"timestamp":<time>][,"metadata.<name>":<value>]}
+//! ```
+//!
+//! Records of an account follow its `account` record. `reserve` records hold the reserves set for
+//! the account, in place of those of its tier. `disputable` records are the transactions
+//! that can still be disputed with the change they made to the available funds, `dispute` records
+//! the state of every transaction that was disputed, `hold` records the funds held by open
+//! disputes and `pending_hold` records those waiting for funds to be held. `transaction` records
+//! are every deposit, withdrawal and transfer the engine has seen, applied or not. `usage` and
+//! `withdrawn` records hold what clients used up of the limits, `day` being in days since the unix
+//! epoch. `retained` records list the transactions kept under a bounded retention policy, oldest
+//! first, and `opened` records when open disputes were opened, in order of opening. `books` records
+//! hold the [flows](crate::books::Flows) of the funds of every currency and `ledger` records the
+//! recorded history, transfers once for the debited client. Amounts are json numbers written with
+//! full precision, timestamps strings such as `"2024-03-01T12:00:00Z"`.
+//!
+//! Readers skip records of unknown kinds and fields they do not know, so that exports may gain
+//! them without a new version. The version only changes when a field changes meaning or goes
+//! away, and readers keep accepting every earlier version.
+
+use crate::account::{AccountState, Balance, ClientId};
+use crate::books::Flows;
+use crate::engine::{EngineConfig, SeenTransaction, TransactionEngine};
+use crate::error::ExchangeError;
+use crate::expiry::OpenDispute;
+use crate::ingest::InputPosition;
+use crate::input::parse_flat_object;
+use crate::ledger::LedgerEntry;
+use crate::output::json_string;
+use crate::snapshot;
+use crate::transaction::Transaction;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{self, Write as _};
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+
+/// Name of the format in the header of every export
+pub const EXCHANGE_FORMAT: &str = "rust-coding-test/engine-state";
+pub const EXCHANGE_VERSION: u32 = 1;
+
+/// Prefix of the fields holding the metadata of `ledger` records
+const METADATA_PREFIX: &str = "metadata.";
+
+/// Encodings of an export
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportFormat {
+    /// One json object per line
+    #[default]
+    Jsonl,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "jsonl" => Ok(ExportFormat::Jsonl),
+            _ => Err(format!("unknown export format '{}'", value)),
+        }
+    }
+}
+
+/// Json object of one record, built field by field
+struct Record(String);
+
+impl Record {
+    fn new(kind: &str) -> Self {
+        Record(format!("{{\"kind\":{}", json_string(kind)))
+    }
+
+    /// Number or boolean, written as is
+    fn number(mut self, name: &str, value: impl fmt::Display) -> Self {
+        let _ = write!(self.0, ",{}:{}", json_string(name), value);
+        self
+    }
+
+    fn string(mut self, name: &str, value: impl fmt::Display) -> Self {
+        let _ = write!(
+            self.0,
+            ",{}:{}",
+            json_string(name),
+            json_string(&value.to_string())
+        );
+        self
+    }
+
+    fn optional_number(self, name: &str, value: Option<impl fmt::Display>) -> Self {
+        match value {
+            Some(value) => self.number(name, value),
+            None => self,
+        }
+    }
+
+    fn optional_string(self, name: &str, value: Option<impl fmt::Display>) -> Self {
+        match value {
+            Some(value) => self.string(name, value),
+            None => self,
+        }
+    }
+
+    fn write<W: Write>(mut self, writer: &mut W) -> io::Result<()> {
+        self.0.push('}');
+        writeln!(writer, "{}", self.0)
+    }
+}
+
+/// Writes the full state of the engine in the exchange format, see the [module](self)
+/// documentation
+pub fn export<W: Write>(engine: &TransactionEngine, mut writer: W) -> io::Result<()> {
+    let offset = Some(engine.input_offset).filter(|offset| *offset > 0);
+    Record::new("header")
+        .string("format", EXCHANGE_FORMAT)
+        .number("version", EXCHANGE_VERSION)
+        .number("lsn", engine.lsn)
+        .optional_number("offset", offset)
+        .write(&mut writer)?;
+    for position in engine.ingested.iter() {
+        Record::new("input")
+            .string("source", &position.source)
+            .number("offset", position.offset)
+            .number("index", position.index)
+            .write(&mut writer)?;
+    }
+
+    // Sorted so that exports of the same state are identical
+    let accounts: BTreeMap<_, _> = engine.accounts.iter().collect();
+    for (client_id, account) in accounts {
+        let state = account.state()?;
+        let closed = engine.closed.contains(client_id);
+        Record::new("account")
+            .number("client", client_id)
+            .number("locked", state.locked)
+            .optional_number("closed", closed.then_some(true))
+            .optional_number("last_active", engine.last_active.get(client_id))
+            .write(&mut writer)?;
+        for (currency, balance) in &state.balances {
+            Record::new("balance")
+                .number("client", client_id)
+                .optional_string("currency", *currency)
+                .number("available", balance.available)
+                .number("held", balance.held)
+                .write(&mut writer)?;
+        }
+        let reserves: BTreeMap<_, _> = engine
+            .reserves
+            .iter()
+            .filter(|((reserved, _), _)| reserved == client_id)
+            .map(|((_, currency), reserve)| (currency, reserve))
+            .collect();
+        for (currency, reserve) in reserves {
+            Record::new("reserve")
+                .number("client", client_id)
+                .number("amount", reserve)
+                .optional_string("currency", *currency)
+                .write(&mut writer)?;
+        }
+        for (kind, entries) in [
+            ("disputable", &state.transaction_log),
+            ("hold", &state.active_disputes),
+            ("pending_hold", &state.pending_holds),
+            ("authorization", &state.authorizations),
+            ("chargeback", &state.chargebacks),
+        ] {
+            for (transaction_id, currency, amount) in entries {
+                Record::new(kind)
+                    .number("client", client_id)
+                    .number("tx", transaction_id)
+                    .number("amount", amount)
+                    .optional_string("currency", *currency)
+                    .write(&mut writer)?;
+            }
+        }
+        for (transaction_id, dispute_state, disputes) in &state.dispute_states {
+            Record::new("dispute")
+                .number("client", client_id)
+                .number("tx", transaction_id)
+                .string("state", dispute_state.as_str())
+                .number("disputes", disputes)
+                .write(&mut writer)?;
+        }
+        for (transaction_id, currency, amount, ticks) in &state.pending_withdrawals {
+            Record::new("pending_withdrawal")
+                .number("client", client_id)
+                .number("tx", transaction_id)
+                .number("amount", amount)
+                .number("ticks", ticks)
+                .optional_string("currency", *currency)
+                .write(&mut writer)?;
+        }
+    }
+
+    let seen: BTreeMap<_, _> = engine.seen_transactions.iter().collect();
+    for (transaction_id, seen) in seen {
+        Record::new("transaction")
+            .number("tx", transaction_id)
+            .number("client", seen.client_id)
+            .string("type", seen.transaction_type)
+            .number("amount", seen.amount)
+            .number("applied", seen.applied)
+            .optional_number("to", seen.to_client_id)
+            .optional_string("currency", seen.currency)
+            .optional_string("timestamp", seen.timestamp)
+            .write(&mut writer)?;
+    }
+
+    let usage: BTreeMap<_, _> = engine.usage.iter().collect();
+    for (client_id, usage) in usage {
+        Record::new("usage")
+            .number("client", client_id)
+            .number("transactions", usage.transactions)
+            .optional_number("day", usage.day)
+            .write(&mut writer)?;
+        for (currency, amount) in &usage.withdrawn {
+            Record::new("withdrawn")
+                .number("client", client_id)
+                .number("amount", amount)
+                .optional_string("currency", *currency)
+                .write(&mut writer)?;
+        }
+    }
+
+    for (client_id, transaction_id) in engine.retention.retained() {
+        Record::new("retained")
+            .number("client", client_id)
+            .number("tx", transaction_id)
+            .write(&mut writer)?;
+    }
+    for (transaction_id, dispute) in engine.expiry.tracked() {
+        Record::new("opened")
+            .number("client", dispute.client_id)
+            .number("tx", transaction_id)
+            .number("lsn", dispute.opened)
+            .optional_string("timestamp", dispute.timestamp)
+            .write(&mut writer)?;
+    }
+    for (currency, flows) in engine.books.currencies() {
+        Record::new("books")
+            .optional_string("currency", currency)
+            .number("opening", flows.opening)
+            .number("deposited", flows.deposited)
+            .number("withdrawn", flows.withdrawn)
+            .number("charged_back", flows.charged_back)
+            .number("fees", flows.fees)
+            .number("interest", flows.interest)
+            .number("adjusted", flows.adjusted)
+            .number("reversed", flows.reversed)
+            .write(&mut writer)?;
+    }
+
+    let clients: BTreeSet<_> = engine.ledger.clients().collect();
+    for client_id in clients {
+        for entry in engine.ledger.history(client_id) {
+            let transaction = &entry.transaction;
+            // Credited leg of a transfer, restored from the debited one
+            if transaction.client_id != client_id {
+                continue;
+            }
+            let mut record = Record::new("ledger")
+                .number("client", client_id)
+                .number("sequence", entry.sequence)
+                .string("type", transaction.transaction_type)
+                .number("tx", transaction.transaction_id)
+                .optional_number("amount", transaction.amount)
+                .optional_number("to", transaction.to_client_id)
+                .optional_string("currency", transaction.currency)
+                .optional_string("timestamp", transaction.timestamp);
+            for (name, value) in transaction.sorted_metadata() {
+                record = record.string(&format!("{}{}", METADATA_PREFIX, name), value);
+            }
+            record.write(&mut writer)?;
+        }
+    }
+
+    writer.flush()
+}
+
+/// Fields of one record by name
+struct Fields {
+    line: u64,
+    values: HashMap<String, String>,
+}
+
+impl Fields {
+    fn kind(&self) -> Option<&str> {
+        self.values.get("kind").map(String::as_str)
+    }
+
+    fn field<T: FromStr>(&self, name: &str) -> Result<T, ExchangeError> {
+        self.optional(name)?
+            .ok_or_else(|| self.malformed(format!("missing field '{}'", name)))
+    }
+
+    /// Missing and null fields are `None`
+    fn optional<T: FromStr>(&self, name: &str) -> Result<Option<T>, ExchangeError> {
+        match self.values.get(name).map(String::as_str) {
+            None | Some("") => Ok(None),
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| self.malformed(format!("invalid {} '{}'", name, value))),
+        }
+    }
+
+    fn malformed(&self, message: String) -> ExchangeError {
+        ExchangeError::Malformed {
+            line: self.line,
+            message,
+        }
+    }
+}
+
+/// State of the account a record belongs to, which must come after the `account` record
+fn account<'a>(
+    states: &'a mut BTreeMap<ClientId, AccountState>,
+    fields: &Fields,
+) -> Result<&'a mut AccountState, ExchangeError> {
+    let client_id: ClientId = fields.field("client")?;
+    states
+        .get_mut(&client_id)
+        .ok_or_else(|| fields.malformed(format!("record of unknown account {}", client_id)))
+}
+
+/// Recreates an engine with `config` from a state written by [`export`], see the
+/// [module](self) documentation
+pub fn import<R: BufRead>(
+    reader: R,
+    config: EngineConfig,
+) -> Result<TransactionEngine, ExchangeError> {
+    let mut engine = TransactionEngine::with_config(config);
+    let mut states: BTreeMap<ClientId, AccountState> = BTreeMap::new();
+    let mut header = false;
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields = Fields {
+            line: index as u64 + 1,
+            values: parse_flat_object(&line)
+                .map_err(|message| ExchangeError::Malformed {
+                    line: index as u64 + 1,
+                    message,
+                })?
+                .into_iter()
+                .collect(),
+        };
+        if !header {
+            if fields.kind() != Some("header") {
+                return Err(fields.malformed("missing header".to_string()));
+            }
+            let format: String = fields.field("format")?;
+            let version: u32 = fields.field("version")?;
+            if format != EXCHANGE_FORMAT || version > EXCHANGE_VERSION {
+                return Err(ExchangeError::Unsupported { format, version });
+            }
+            engine.lsn = fields.field("lsn")?;
+            engine.input_offset = fields.optional("offset")?.unwrap_or(0);
+            header = true;
+            continue;
+        }
+
+        match fields.kind() {
+            Some("input") => engine.ingested.advance(&InputPosition {
+                source: fields.field("source")?,
+                offset: fields.field("offset")?,
+                index: fields.field("index")?,
+            }),
+            Some("account") => {
+                let client_id: ClientId = fields.field("client")?;
+                states.insert(
+                    client_id,
+                    AccountState {
+                        client_id,
+                        balances: Vec::new(),
+                        locked: fields.field("locked")?,
+                        transaction_log: Vec::new(),
+                        active_disputes: Vec::new(),
+                        dispute_states: Vec::new(),
+                        pending_withdrawals: Vec::new(),
+                        authorizations: Vec::new(),
+                        chargebacks: Vec::new(),
+                        pending_holds: Vec::new(),
+                    },
+                );
+                if fields.optional("closed")?.unwrap_or(false) {
+                    engine.closed.insert(client_id);
+                }
+                if let Some(lsn) = fields.optional("last_active")? {
+                    engine.last_active.insert(client_id, lsn);
+                }
+            }
+            Some("balance") => {
+                let state = account(&mut states, &fields)?;
+                state.balances.push((
+                    fields.optional("currency")?,
+                    Balance {
+                        available: fields.field("available")?,
+                        held: fields.field("held")?,
+                    },
+                ));
+            }
+            Some("reserve") => {
+                account(&mut states, &fields)?;
+                engine.reserves.insert(
+                    (fields.field("client")?, fields.optional("currency")?),
+                    fields.field("amount")?,
+                );
+            }
+            Some(
+                kind @ ("disputable" | "hold" | "pending_hold" | "authorization" | "chargeback"),
+            ) => {
+                let state = account(&mut states, &fields)?;
+                let entry = (
+                    fields.field("tx")?,
+                    fields.optional("currency")?,
+                    fields.field("amount")?,
+                );
+                match kind {
+                    "disputable" => state.transaction_log.push(entry),
+                    "hold" => state.active_disputes.push(entry),
+                    "pending_hold" => state.pending_holds.push(entry),
+                    "authorization" => state.authorizations.push(entry),
+                    _ => state.chargebacks.push(entry),
+                }
+            }
+            Some("dispute") => {
+                let state = account(&mut states, &fields)?;
+                state.dispute_states.push((
+                    fields.field("tx")?,
+                    fields.field("state")?,
+                    fields.field("disputes")?,
+                ));
+            }
+            Some("pending_withdrawal") => {
+                let state = account(&mut states, &fields)?;
+                state.pending_withdrawals.push((
+                    fields.field("tx")?,
+                    fields.optional("currency")?,
+                    fields.field("amount")?,
+                    fields.field("ticks")?,
+                ));
+            }
+            Some("transaction") => {
+                engine.seen_transactions.insert(
+                    fields.field("tx")?,
+                    SeenTransaction {
+                        client_id: fields.field("client")?,
+                        transaction_type: fields.field("type")?,
+                        amount: fields.field("amount")?,
+                        to_client_id: fields.optional("to")?,
+                        currency: fields.optional("currency")?,
+                        timestamp: fields.optional("timestamp")?,
+                        applied: fields.field("applied")?,
+                    },
+                );
+            }
+            Some("usage") => {
+                let usage = engine.usage.entry(fields.field("client")?).or_default();
+                usage.transactions = fields.field("transactions")?;
+                usage.day = fields.optional("day")?;
+            }
+            Some("withdrawn") => {
+                engine
+                    .usage
+                    .entry(fields.field("client")?)
+                    .or_default()
+                    .withdrawn
+                    .insert(fields.optional("currency")?, fields.field("amount")?);
+            }
+            Some("retained") => engine
+                .retention
+                .restore(fields.field("client")?, fields.field("tx")?),
+            Some("opened") => engine.expiry.open(
+                fields.field("tx")?,
+                OpenDispute {
+                    client_id: fields.field("client")?,
+                    opened: fields.field("lsn")?,
+                    timestamp: fields.optional("timestamp")?,
+                },
+            ),
+            Some("books") => {
+                *engine.books.entry(fields.optional("currency")?) = Flows {
+                    opening: fields.field("opening")?,
+                    deposited: fields.field("deposited")?,
+                    withdrawn: fields.field("withdrawn")?,
+                    charged_back: fields.field("charged_back")?,
+                    fees: fields.field("fees")?,
+                    interest: fields.field("interest")?,
+                    adjusted: fields.field("adjusted")?,
+                    reversed: fields.field("reversed")?,
+                }
+            }
+            Some("ledger") => {
+                let mut metadata: Vec<_> = fields
+                    .values
+                    .iter()
+                    .filter_map(|(name, value)| {
+                        name.strip_prefix(METADATA_PREFIX)
+                            .map(|name| (name.to_string(), value.clone()))
+                    })
+                    .collect();
+                metadata.sort();
+                engine.ledger.restore_entry(LedgerEntry {
+                    sequence: fields.field("sequence")?,
+                    transaction: Transaction {
+                        transaction_type: fields.field("type")?,
+                        client_id: fields.field("client")?,
+                        transaction_id: fields.field("tx")?,
+                        amount: fields.optional("amount")?,
+                        to_client_id: fields.optional("to")?,
+                        currency: fields.optional("currency")?,
+                        timestamp: fields.optional("timestamp")?,
+                        metadata: metadata.into_iter().collect(),
+                    },
+                });
+            }
+            Some(_) => {}
+            None => return Err(fields.malformed("record without a kind".to_string())),
+        }
+    }
+
+    if !header {
+        return Err(ExchangeError::Malformed {
+            line: 1,
+            message: "empty export".to_string(),
+        });
+    }
+    snapshot::restore_accounts(&mut engine, states).map_err(ExchangeError::Account)?;
+    Ok(engine)
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::engine::{EngineConfig, TransactionEngine};
+        use crate::error::ExchangeError;
+        use crate::snapshot::write_snapshot;
+        use crate::transaction::{transaction, Amount, TransactionType};
+
+        fn snapshot_bytes(engine: &TransactionEngine) -> Vec<u8> {
+            let mut buffer = Vec::new();
+            write_snapshot(engine, &mut buffer).unwrap();
+            buffer
+        }
+
+        fn import(input: &str) -> Result<TransactionEngine, ExchangeError> {
+            TransactionEngine::import(input.as_bytes(), EngineConfig::default())
+        }
+
+        #[test]
+        fn imported_state_is_the_exported_one() {
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                record_history: true,
+                ..EngineConfig::default()
+            });
+            let mut deposit = transaction(TransactionType::Deposit, 1, 1, Some(0.1));
+            deposit.timestamp = Some("2024-03-01T12:00:00Z".parse().unwrap());
+            deposit.metadata.extend([
+                ("merchant".to_string(), "ACME, \"Inc.\"".to_string()),
+                ("memo".to_string(), String::new()),
+            ]);
+            let mut in_euros = transaction(TransactionType::Deposit, 2, 2, Some(5.0));
+            in_euros.currency = Some("EUR".parse().unwrap());
+            let mut transfer = transaction(TransactionType::Transfer, 1, 5, Some(0.05));
+            transfer.to_client_id = Some(3);
+            for transaction in [
+                deposit,
+                in_euros,
+                transaction(TransactionType::Deposit, 2, 3, Some(1.0)),
+                transaction(TransactionType::Dispute, 1, 1, None),
+                transaction(TransactionType::Resolve, 1, 1, None),
+                transaction(TransactionType::Dispute, 2, 3, None),
+                transaction(TransactionType::Chargeback, 2, 3, None),
+                transfer,
+            ] {
+                engine.execute(transaction).unwrap();
+            }
+            engine
+                .set_reserve(2, Some("EUR".parse().unwrap()), Amount::unchecked(2.5))
+                .unwrap();
+
+            let mut exported = Vec::new();
+            engine.export(&mut exported).unwrap();
+            let imported =
+                TransactionEngine::import(exported.as_slice(), engine.config().clone()).unwrap();
+
+            let mut exported_again = Vec::new();
+            imported.export(&mut exported_again).unwrap();
+            assert_eq!(
+                String::from_utf8(exported_again).unwrap(),
+                String::from_utf8(exported).unwrap()
+            );
+            assert_eq!(snapshot_bytes(&imported), snapshot_bytes(&engine));
+            assert!(imported.accounts[&2].is_locked());
+            assert_eq!(imported.reserve(2, Some("EUR".parse().unwrap())), 2.5);
+            assert_eq!(
+                imported.history(1).collect::<Vec<_>>(),
+                engine.history(1).collect::<Vec<_>>()
+            );
+        }
+
+        #[test]
+        fn unknown_kinds_and_fields_are_skipped() {
+            let engine = import(concat!(
+                r#"{"kind":"header","format":"rust-coding-test/engine-state","version":1,"#,
+                r#""lsn":1,"writer":"next"}"#,
+                "\n",
+                r#"{"kind":"account","client":1,"locked":false,"tier":"business"}"#,
+                "\n",
+                r#"{"kind":"balance","client":1,"available":1.5,"held":0}"#,
+                "\n",
+                r#"{"kind":"standing_order","client":1,"every":7}"#,
+                "\n",
+            ))
+            .unwrap();
+
+            assert_eq!(engine.accounts[&1].get_available_funds(), 1.5);
+        }
+
+        #[test]
+        fn other_formats_and_newer_versions_are_rejected() {
+            let newer = import(
+                r#"{"kind":"header","format":"rust-coding-test/engine-state","version":2,"lsn":0}"#,
+            );
+            assert!(matches!(
+                newer,
+                Err(ExchangeError::Unsupported { version: 2, .. })
+            ));
+            assert!(matches!(
+                import("snapshot,19\n"),
+                Err(ExchangeError::Malformed { line: 1, .. })
+            ));
+            let orphan = import(concat!(
+                r#"{"kind":"header","format":"rust-coding-test/engine-state","version":1,"lsn":0}"#,
+                "\n",
+                r#"{"kind":"balance","client":4,"available":1,"held":0}"#,
+            ));
+            assert_eq!(
+                orphan.err().map(|err| err.to_string()).as_deref(),
+                Some("malformed export on line 2: record of unknown account 4")
+            );
+        }
+    }
+}
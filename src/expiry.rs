@@ -0,0 +1,182 @@
+//! Open disputes tracked until they are settled, or expire under a [`DisputeExpiry`] policy.
+
+use crate::account::ClientId;
+use crate::policy::DisputeExpiry;
+use crate::timestamp::Timestamp;
+use crate::transaction::TransactionId;
+use std::collections::{HashMap, VecDeque};
+
+/// When a dispute was opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct OpenDispute {
+    pub(crate) client_id: ClientId,
+    /// Log sequence number of the dispute
+    pub(crate) opened: u64,
+    pub(crate) timestamp: Option<Timestamp>,
+}
+
+/// Disputes that were not settled and did not expire yet
+#[derive(Debug, Default)]
+pub(crate) struct Expiry {
+    policy: DisputeExpiry,
+    open: HashMap<TransactionId, OpenDispute>,
+    /// Disputes in order of opening. Entries of settled disputes are skipped when reached.
+    order: VecDeque<(u64, TransactionId)>,
+}
+
+impl Expiry {
+    pub(crate) fn new(policy: DisputeExpiry) -> Self {
+        Expiry {
+            policy,
+            ..Expiry::default()
+        }
+    }
+
+    /// Starts tracking the dispute of a transaction, replacing an earlier one
+    pub(crate) fn open(&mut self, transaction_id: TransactionId, dispute: OpenDispute) {
+        self.open.insert(transaction_id, dispute);
+        self.order.push_back((dispute.opened, transaction_id));
+    }
+
+    /// Stops tracking a resolved or charged back dispute
+    pub(crate) fn close(&mut self, transaction_id: TransactionId) {
+        self.open.remove(&transaction_id);
+    }
+
+    /// Whether the dispute of the transaction is tracked
+    pub(crate) fn is_tracked(&self, transaction_id: TransactionId) -> bool {
+        self.open.contains_key(&transaction_id)
+    }
+
+    /// When the open dispute of the transaction was opened, if it is tracked
+    pub(crate) fn opened(&self, transaction_id: TransactionId) -> Option<&OpenDispute> {
+        self.open.get(&transaction_id)
+    }
+
+    /// Removes and returns the disputes that expired once the engine reached log sequence number
+    /// `lsn`, with `now` the timestamp of the last transaction. Disputes expire in the order they
+    /// were opened, so a dispute only expires by its timestamp once the earlier ones did.
+    pub(crate) fn expire(
+        &mut self,
+        lsn: u64,
+        now: Option<Timestamp>,
+    ) -> Vec<(ClientId, TransactionId)> {
+        let mut expired = Vec::new();
+        while let Some(&(opened, transaction_id)) = self.order.front() {
+            let dispute = match self.open.get(&transaction_id) {
+                Some(dispute) if dispute.opened == opened => *dispute,
+                // Settled, or disputed again later
+                _ => {
+                    self.order.pop_front();
+                    continue;
+                }
+            };
+            let by_count = self
+                .policy
+                .after_transactions
+                .is_some_and(|count| lsn.saturating_sub(opened) >= count);
+            let by_time = match (self.policy.after_days, dispute.timestamp, now) {
+                (Some(days), Some(timestamp), Some(now)) => !now.is_within_days(timestamp, days),
+                _ => false,
+            };
+            if !by_count && !by_time {
+                break;
+            }
+            self.order.pop_front();
+            self.open.remove(&transaction_id);
+            expired.push((dispute.client_id, transaction_id));
+        }
+        expired
+    }
+
+    /// Tracks the disputes of `from` as disputes of `into`
+    pub(crate) fn remap(&mut self, from: ClientId, into: ClientId) {
+        for dispute in self.open.values_mut() {
+            if dispute.client_id == from {
+                dispute.client_id = into;
+            }
+        }
+    }
+
+    /// Tracked disputes in order of opening. Restoring them in this order with
+    /// [`Expiry::open`] recreates the same expiry order.
+    pub(crate) fn tracked(&self) -> impl Iterator<Item = (TransactionId, OpenDispute)> + '_ {
+        self.order.iter().filter_map(|&(opened, transaction_id)| {
+            self.open
+                .get(&transaction_id)
+                .filter(|dispute| dispute.opened == opened)
+                .map(|dispute| (transaction_id, *dispute))
+        })
+    }
+
+    /// Takes over the disputes of another engine that processed a disjoint set of clients and
+    /// whose log sequence numbers follow the first `lsn` of this engine
+    pub(crate) fn absorb(&mut self, other: Expiry, lsn: u64) {
+        let mut merged: Vec<_> = self
+            .tracked()
+            .chain(other.tracked().map(|(transaction_id, dispute)| {
+                (
+                    transaction_id,
+                    OpenDispute {
+                        opened: dispute.opened + lsn,
+                        ..dispute
+                    },
+                )
+            }))
+            .collect();
+        merged.sort_by_key(|(_, dispute)| dispute.opened);
+        self.open.clear();
+        self.order.clear();
+        for (transaction_id, dispute) in merged {
+            self.open(transaction_id, dispute);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::expiry::{Expiry, OpenDispute};
+        use crate::policy::DisputeExpiry;
+        use crate::timestamp::Timestamp;
+        use crate::transaction::TransactionId;
+
+        fn dispute(opened: u64, timestamp: Option<u64>) -> OpenDispute {
+            OpenDispute {
+                client_id: 1,
+                opened,
+                timestamp: timestamp.map(Timestamp::from_secs),
+            }
+        }
+
+        #[test]
+        fn disputes_expire_after_transactions_or_days() {
+            let mut expiry = Expiry::new(DisputeExpiry {
+                after_transactions: Some(3),
+                after_days: Some(1),
+            });
+            expiry.open(TransactionId(1), dispute(1, None));
+            expiry.open(TransactionId(2), dispute(2, Some(0)));
+            expiry.open(TransactionId(3), dispute(3, None));
+            expiry.close(TransactionId(3));
+
+            assert!(expiry.expire(3, None).is_empty());
+            assert_eq!(expiry.expire(4, None), vec![(1, TransactionId(1))]);
+            // A day after the dispute of 2, before it expires by count
+            assert_eq!(
+                expiry.expire(4, Some(Timestamp::from_secs(86_401))),
+                vec![(1, TransactionId(2))]
+            );
+            assert!(expiry.expire(10, None).is_empty());
+            assert_eq!(expiry.tracked().count(), 0);
+
+            // Disputes are tracked without the policy but never expire
+            let mut disabled = Expiry::default();
+            disabled.open(TransactionId(1), dispute(1, None));
+            assert!(disabled.is_tracked(TransactionId(1)));
+            assert!(disabled
+                .expire(100, Some(Timestamp::from_secs(86_401)))
+                .is_empty());
+        }
+    }
+}
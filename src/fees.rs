@@ -0,0 +1,265 @@
+//! End-of-day batch step charging maintenance fees and crediting interest on available funds.
+//!
+//! The schedule is read from the `[fees]` table of a TOML file:
+//!
+//! ```toml
+//! [fees]
+//! maintenance_fee = 1.5        # charged to every account, at most its available funds
+//! waive_fee_above = 1_000      # no fee for accounts with at least this much available
+//! interest_rate = 0.0001       # credited on positive available funds, per run
+//! currency = "EUR"             # funds without a currency unless given
+//! ```
+
+use crate::account::ClientId;
+use crate::currency::Currency;
+use crate::error::{ConfigError, EngineError};
+use crate::toml;
+use crate::transaction::{Amount, Transaction, TransactionId, TransactionType};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Transaction id of the synthetic fee and interest transactions. They never become disputable
+/// and so never clash with the ids of the input.
+pub const ADJUSTMENT_TRANSACTION_ID: TransactionId = TransactionId(0);
+
+/// Fees and interest applied by
+/// [`TransactionEngine::apply_fees`](crate::engine::TransactionEngine::apply_fees), nothing
+/// unless set
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FeeSchedule {
+    /// Charged to every account, capped to its available funds so that no account goes negative
+    pub maintenance_fee: Option<f64>,
+    /// Accounts with at least this much available after interest are not charged the fee
+    pub waive_fee_above: Option<f64>,
+    /// Share of the available funds credited as interest on every run, rounded down to four
+    /// decimals. Accounts without positive available funds earn nothing.
+    pub interest_rate: Option<f64>,
+    /// Currency whose funds are charged and credited, funds without a currency if not set
+    pub currency: Option<Currency>,
+}
+
+impl FeeSchedule {
+    /// Reads the `[fees]` table of a TOML file. Other tables are ignored.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+
+    pub fn from_toml(input: &str) -> Result<Self, ConfigError> {
+        match toml::parse(input)?.get("fees") {
+            Some(table) => Self::from_table(table),
+            None => Ok(FeeSchedule::default()),
+        }
+    }
+
+    pub(crate) fn from_table(table: &toml::Table) -> Result<Self, ConfigError> {
+        let mut schedule = FeeSchedule::default();
+        for (key, entry) in &table.entries {
+            match key.as_str() {
+                "maintenance_fee" => schedule.maintenance_fee = Some(positive(key, entry)?),
+                "waive_fee_above" => schedule.waive_fee_above = Some(positive(key, entry)?),
+                "interest_rate" => schedule.interest_rate = Some(positive(key, entry)?),
+                "currency" => schedule.currency = Some(entry.parse(key)?),
+                _ => return Err(entry.invalid(format!("unknown key '{}' in [fees]", key))),
+            }
+        }
+        Ok(schedule)
+    }
+
+    /// Interest then fee owed by a client with `available` funds, each left out when nothing is
+    /// owed
+    pub fn transactions(&self, client_id: ClientId, available: f64) -> Vec<Transaction> {
+        let mut transactions = Vec::new();
+        let interest = match self.interest_rate {
+            Some(rate) if available > 0.0 => (available * rate * 10_000.0).floor() / 10_000.0,
+            _ => 0.0,
+        };
+        if interest > 0.0 {
+            transactions.push(self.transaction(TransactionType::Interest, client_id, interest));
+        }
+        let available = available + interest;
+        let waived = self
+            .waive_fee_above
+            .is_some_and(|threshold| available >= threshold);
+        let fee = match self.maintenance_fee {
+            Some(fee) if !waived && available > 0.0 => fee.min(available),
+            _ => 0.0,
+        };
+        if fee > 0.0 {
+            transactions.push(self.transaction(TransactionType::Fee, client_id, fee));
+        }
+        transactions
+    }
+
+    fn transaction(
+        &self,
+        transaction_type: TransactionType,
+        client_id: ClientId,
+        amount: f64,
+    ) -> Transaction {
+        Transaction {
+            transaction_type,
+            client_id,
+            transaction_id: ADJUSTMENT_TRANSACTION_ID,
+            amount: Some(Amount::unchecked(amount)),
+            to_client_id: None,
+            currency: self.currency,
+            timestamp: None,
+            metadata: Default::default(),
+        }
+    }
+}
+
+fn positive(key: &str, entry: &toml::Entry) -> Result<f64, ConfigError> {
+    match entry.as_number(key)? {
+        value if value > 0.0 => Ok(value),
+        value => Err(entry.invalid(format!("'{}' must be positive, found {}", key, value))),
+    }
+}
+
+/// What a run of the fee schedule charged and credited
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FeeSummary {
+    /// Accounts that were charged a fee
+    pub fees_charged: u64,
+    pub fees_total: f64,
+    /// Accounts that were credited interest
+    pub interest_credited: u64,
+    pub interest_total: f64,
+    /// Locked accounts, which are left untouched
+    pub skipped_locked: u64,
+    /// Fees and interest the engine refused
+    pub errors: Vec<EngineError>,
+}
+
+impl FeeSummary {
+    pub(crate) fn record(&mut self, transaction: &Transaction) {
+        let amount = transaction.amount.unwrap_or_default();
+        if transaction.transaction_type == TransactionType::Fee {
+            self.fees_charged += 1;
+            self.fees_total += amount;
+        } else {
+            self.interest_credited += 1;
+            self.interest_total += amount;
+        }
+    }
+}
+
+impl fmt::Display for FeeSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Charged {:.4} in fees to {} accounts",
+            self.fees_total, self.fees_charged
+        )?;
+        writeln!(
+            f,
+            "Credited {:.4} in interest to {} accounts",
+            self.interest_total, self.interest_credited
+        )?;
+        writeln!(f, "Skipped {} locked accounts", self.skipped_locked)?;
+        if !self.errors.is_empty() {
+            writeln!(f, "Rejected {} fees or interest", self.errors.len())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::error::ConfigError;
+        use crate::fees::FeeSchedule;
+        use crate::transaction::TransactionType;
+
+        fn owed(schedule: &FeeSchedule, available: f64) -> Vec<(TransactionType, f64)> {
+            schedule
+                .transactions(1, available)
+                .into_iter()
+                .map(|transaction| {
+                    (
+                        transaction.transaction_type,
+                        transaction.amount.unwrap().value(),
+                    )
+                })
+                .collect()
+        }
+
+        #[test]
+        fn schedule_is_read_from_toml() {
+            let schedule = FeeSchedule::from_toml(
+                "[fees]\n\
+                 maintenance_fee = 1.5\n\
+                 waive_fee_above = 1_000\n\
+                 interest_rate = 0.01\n\
+                 currency = \"eur\"\n",
+            )
+            .unwrap();
+
+            assert_eq!(
+                schedule,
+                FeeSchedule {
+                    maintenance_fee: Some(1.5),
+                    waive_fee_above: Some(1000.0),
+                    interest_rate: Some(0.01),
+                    currency: Some("EUR".parse().unwrap()),
+                }
+            );
+            assert_eq!(FeeSchedule::from_toml("").unwrap(), FeeSchedule::default());
+            for input in [
+                "[fees]\nmonthly_fee = 1\n",
+                "[fees]\ninterest_rate = -0.1\n",
+                "[fees]\ncurrency = \"euro\"\n",
+            ] {
+                assert!(
+                    matches!(
+                        FeeSchedule::from_toml(input),
+                        Err(ConfigError::Invalid { line: 2, .. })
+                    ),
+                    "{}",
+                    input
+                );
+            }
+        }
+
+        #[test]
+        fn fees_are_capped_and_waived_and_interest_rounded_down() {
+            let schedule = FeeSchedule {
+                maintenance_fee: Some(2.0),
+                waive_fee_above: Some(100.0),
+                interest_rate: Some(0.001),
+                currency: None,
+            };
+
+            assert_eq!(
+                owed(&schedule, 50.0),
+                [
+                    (TransactionType::Interest, 0.05),
+                    (TransactionType::Fee, 2.0)
+                ]
+            );
+            assert_eq!(
+                owed(&schedule, 12.3456),
+                [
+                    (TransactionType::Interest, 0.0123),
+                    (TransactionType::Fee, 2.0)
+                ]
+            );
+            assert_eq!(
+                owed(&schedule, 0.5),
+                [
+                    (TransactionType::Interest, 0.0005),
+                    (TransactionType::Fee, 0.5005)
+                ]
+            );
+            assert_eq!(owed(&schedule, 0.0009), [(TransactionType::Fee, 0.0009)]);
+            // Interest can lift the account above the threshold
+            assert_eq!(
+                owed(&schedule, 99.95),
+                [(TransactionType::Interest, 0.0999)]
+            );
+            assert_eq!(owed(&schedule, 0.0), []);
+            assert_eq!(owed(&schedule, -3.0), []);
+        }
+    }
+}
@@ -0,0 +1,358 @@
+//! Stable C ABI over the engine, so that services written in other languages can link against
+//! the `cdylib` or `staticlib` build of the library. The functions are declared in
+//! `include/rust_coding_test.h`, which is kept in sync with this module by hand.
+//!
+//! Engines are opaque handles created by [`engine_new`] and released by [`engine_free`].
+//! Transactions submitted one at a time are in the default currency. Whole csv or ndjson files
+//! are applied with [`engine_process_file`] and the resulting accounts read back as csv with
+//! [`engine_accounts_csv`], which is all a Python ctypes wrapper such as
+//! `python/rust_coding_test.py` needs. Builds with the `client-id-u32` or `client-id-u64`
+//! feature widen the client ids of the interface, which C callers match by defining
+//! `ENGINE_CLIENT_ID_U32` or `ENGINE_CLIENT_ID_U64` before including the header.
+
+use crate::account::ClientId;
+use crate::engine::TransactionEngine;
+use crate::output::accounts_csv;
+use crate::transaction::{Amount, Transaction, TransactionId, TransactionType};
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+
+/// The transaction was applied
+pub const ENGINE_OK: i32 = 0;
+/// The engine rejected the transaction, see [`engine_last_error`]
+pub const ENGINE_REJECTED: i32 = 1;
+/// The client has no account
+pub const ENGINE_NOT_FOUND: i32 = 2;
+/// A pointer was null or the transaction type unknown, see [`engine_last_error`]
+pub const ENGINE_INVALID_ARGUMENT: i32 = -1;
+/// The file could not be read, see [`engine_last_error`]
+pub const ENGINE_IO_ERROR: i32 = -2;
+
+/// Credited client of an [`EngineTransaction`], signed so that other transactions can leave it
+/// negative
+#[cfg(not(any(feature = "client-id-u32", feature = "client-id-u64")))]
+pub type EngineToClient = i32;
+/// Credited client of an [`EngineTransaction`], signed so that other transactions can leave it
+/// negative
+#[cfg(any(feature = "client-id-u32", feature = "client-id-u64"))]
+pub type EngineToClient = i64;
+
+/// Engine behind the handle, with the message of the last failed call
+pub struct Engine {
+    engine: TransactionEngine,
+    last_error: Option<CString>,
+    /// Returned by the last call of [`engine_accounts_csv`]
+    accounts: Option<CString>,
+}
+
+impl Engine {
+    fn fail(&mut self, code: i32, message: String) -> i32 {
+        // Messages never contain nul bytes, but the handle must not panic across the boundary
+        self.last_error = CString::new(message).ok();
+        code
+    }
+}
+
+/// Transaction submitted by [`engine_submit_transaction`]
+#[repr(C)]
+pub struct EngineTransaction {
+    /// Nul terminated name of the type as in csv input, e.g. `deposit`
+    pub transaction_type: *const c_char,
+    pub client: ClientId,
+    pub tx: TransactionId,
+    /// NaN for transactions without amount such as disputes
+    pub amount: f64,
+    /// Credited client of transfers, negative for other transactions
+    pub to_client: EngineToClient,
+}
+
+/// What [`engine_process_file`] found in a file
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct EngineFileReport {
+    /// Rows of the file, including malformed ones
+    pub rows_read: u64,
+    /// Rows that could not be read as a transaction and were skipped
+    pub malformed: u64,
+    /// Transactions the engine rejected
+    pub rejected: u64,
+    /// Accounts the file locked
+    pub locked: u64,
+}
+
+/// Funds of an account filled in by [`engine_get_account`]
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct EngineAccount {
+    pub available: f64,
+    pub held: f64,
+    pub total: f64,
+    pub locked: bool,
+}
+
+/// Creates an engine with the default configuration, released with [`engine_free`]
+#[no_mangle]
+pub extern "C" fn engine_new() -> *mut Engine {
+    Box::into_raw(Box::new(Engine {
+        engine: TransactionEngine::new(),
+        last_error: None,
+        accounts: None,
+    }))
+}
+
+/// Releases an engine. Null is ignored.
+///
+/// # Safety
+///
+/// `engine` must have been returned by [`engine_new`] and not released yet.
+#[no_mangle]
+pub unsafe extern "C" fn engine_free(engine: *mut Engine) {
+    if !engine.is_null() {
+        drop(Box::from_raw(engine));
+    }
+}
+
+/// Applies a transaction, returning [`ENGINE_OK`], [`ENGINE_REJECTED`] or
+/// [`ENGINE_INVALID_ARGUMENT`]
+///
+/// # Safety
+///
+/// `engine` must be a live handle and `transaction` null or valid, with a `transaction_type`
+/// that is null or a nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn engine_submit_transaction(
+    engine: *mut Engine,
+    transaction: *const EngineTransaction,
+) -> i32 {
+    let Some(engine) = engine.as_mut() else {
+        return ENGINE_INVALID_ARGUMENT;
+    };
+    let Some(transaction) = transaction.as_ref() else {
+        return engine.fail(ENGINE_INVALID_ARGUMENT, "transaction is null".to_string());
+    };
+    if transaction.transaction_type.is_null() {
+        return engine.fail(
+            ENGINE_INVALID_ARGUMENT,
+            "transaction type is null".to_string(),
+        );
+    }
+    let name = CStr::from_ptr(transaction.transaction_type).to_string_lossy();
+    let transaction_type = match name.parse::<TransactionType>() {
+        Ok(transaction_type) => transaction_type,
+        Err(message) => return engine.fail(ENGINE_INVALID_ARGUMENT, message),
+    };
+    let amount = match transaction.amount {
+        amount if amount.is_nan() => None,
+        amount => match Amount::of(transaction_type, amount) {
+            Ok(amount) => Some(amount),
+            Err(message) => return engine.fail(ENGINE_INVALID_ARGUMENT, message),
+        },
+    };
+    let result = engine.engine.execute(Transaction {
+        transaction_type,
+        client_id: transaction.client,
+        transaction_id: transaction.tx,
+        amount,
+        to_client_id: ClientId::try_from(transaction.to_client).ok(),
+        currency: None,
+        timestamp: None,
+        metadata: Default::default(),
+    });
+    match result {
+        Ok(()) => {
+            engine.last_error = None;
+            ENGINE_OK
+        }
+        Err(err) => engine.fail(ENGINE_REJECTED, err.to_string()),
+    }
+}
+
+/// Applies every transaction of a csv or ndjson file, the format guessed from its extension, as
+/// [`TransactionEngine::process_file`]. Returns [`ENGINE_OK`] with `report` filled in, skipping
+/// malformed rows and rejected transactions, [`ENGINE_IO_ERROR`] if the file cannot be read or
+/// [`ENGINE_INVALID_ARGUMENT`].
+///
+/// # Safety
+///
+/// `engine` must be a live handle, `path` null or a nul terminated string and `report` null or
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn engine_process_file(
+    engine: *mut Engine,
+    path: *const c_char,
+    report: *mut EngineFileReport,
+) -> i32 {
+    let Some(engine) = engine.as_mut() else {
+        return ENGINE_INVALID_ARGUMENT;
+    };
+    let (false, Some(report)) = (path.is_null(), report.as_mut()) else {
+        return engine.fail(
+            ENGINE_INVALID_ARGUMENT,
+            "path or report is null".to_string(),
+        );
+    };
+    let path = CStr::from_ptr(path).to_string_lossy().into_owned();
+    match engine.engine.process_file(path) {
+        Ok(validation) => {
+            *report = EngineFileReport {
+                rows_read: validation.rows_read,
+                malformed: validation.malformed.len() as u64,
+                rejected: validation.rejected.len() as u64,
+                locked: validation.locked.len() as u64,
+            };
+            engine.last_error = None;
+            ENGINE_OK
+        }
+        Err(err) => engine.fail(ENGINE_IO_ERROR, err.to_string()),
+    }
+}
+
+/// Accounts of the engine as csv ordered by client, as the batch command writes them with a
+/// currency column if any account holds a currency. Owned by the engine and valid until the next
+/// call of this function or [`engine_free`]; null for a null handle.
+///
+/// # Safety
+///
+/// `engine` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn engine_accounts_csv(engine: *mut Engine) -> *const c_char {
+    let Some(engine) = engine.as_mut() else {
+        return ptr::null();
+    };
+    // Csv of accounts never contains nul bytes
+    engine.accounts = CString::new(accounts_csv(&engine.engine)).ok();
+    engine
+        .accounts
+        .as_ref()
+        .map_or(ptr::null(), |accounts| accounts.as_ptr())
+}
+
+/// Fills `account` with the funds of the client, returning [`ENGINE_OK`],
+/// [`ENGINE_NOT_FOUND`] or [`ENGINE_INVALID_ARGUMENT`]
+///
+/// # Safety
+///
+/// `engine` must be a live handle and `account` null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn engine_get_account(
+    engine: *const Engine,
+    client: ClientId,
+    account: *mut EngineAccount,
+) -> i32 {
+    let (Some(engine), Some(account)) = (engine.as_ref(), account.as_mut()) else {
+        return ENGINE_INVALID_ARGUMENT;
+    };
+    match engine.engine.account_snapshot(client) {
+        Some(snapshot) => {
+            *account = EngineAccount {
+                available: snapshot.available,
+                held: snapshot.held,
+                total: snapshot.total,
+                locked: snapshot.locked,
+            };
+            ENGINE_OK
+        }
+        None => ENGINE_NOT_FOUND,
+    }
+}
+
+/// Message of the last failed call on the engine, null if the last transaction or file was
+/// applied. The message belongs to the engine and is valid until its next call.
+///
+/// # Safety
+///
+/// `engine` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn engine_last_error(engine: *const Engine) -> *const c_char {
+    engine
+        .as_ref()
+        .and_then(|engine| engine.last_error.as_ref())
+        .map_or(ptr::null(), |message| message.as_ptr())
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::ffi::*;
+        use std::ffi::{CStr, CString};
+        use std::ptr;
+
+        fn submit(engine: *mut Engine, transaction_type: &str, tx: u32, amount: f64) -> i32 {
+            let transaction_type = CString::new(transaction_type).unwrap();
+            let transaction = EngineTransaction {
+                transaction_type: transaction_type.as_ptr(),
+                client: 1,
+                tx: TransactionId(tx),
+                amount,
+                to_client: -1,
+            };
+            unsafe { engine_submit_transaction(engine, &transaction) }
+        }
+
+        #[test]
+        fn engines_are_driven_through_the_c_abi() {
+            let engine = engine_new();
+
+            assert_eq!(submit(engine, "deposit", 1, 5.0), ENGINE_OK);
+            assert_eq!(submit(engine, "dispute", 1, f64::NAN), ENGINE_OK);
+            assert!(unsafe { engine_last_error(engine) }.is_null());
+            assert_eq!(submit(engine, "withdrawal", 2, 1.0), ENGINE_REJECTED);
+            let message = unsafe { CStr::from_ptr(engine_last_error(engine)) };
+            assert!(message.to_str().unwrap().contains("insufficient"));
+            assert_eq!(submit(engine, "refund", 3, 1.0), ENGINE_INVALID_ARGUMENT);
+
+            let mut account = EngineAccount::default();
+            assert_eq!(
+                unsafe { engine_get_account(engine, 1, &mut account) },
+                ENGINE_OK
+            );
+            assert_eq!(
+                account,
+                EngineAccount {
+                    available: 0.0,
+                    held: 5.0,
+                    total: 5.0,
+                    locked: false,
+                }
+            );
+            assert_eq!(
+                unsafe { engine_get_account(engine, 2, &mut account) },
+                ENGINE_NOT_FOUND
+            );
+            assert_eq!(
+                unsafe { engine_submit_transaction(engine, ptr::null()) },
+                ENGINE_INVALID_ARGUMENT
+            );
+            unsafe { engine_free(engine) };
+        }
+
+        #[test]
+        fn files_are_applied_through_the_c_abi() {
+            let engine = engine_new();
+            let mut report = EngineFileReport::default();
+            let path = CString::new("assets/test_with_disputes.csv").unwrap();
+            assert_eq!(
+                unsafe { engine_process_file(engine, path.as_ptr(), &mut report) },
+                ENGINE_OK
+            );
+            assert!(report.rows_read > 0);
+            let accounts = unsafe { CStr::from_ptr(engine_accounts_csv(engine)) };
+            assert!(accounts
+                .to_str()
+                .unwrap()
+                .starts_with("client,available,held,total,locked\n"));
+
+            let missing = CString::new("assets/missing.csv").unwrap();
+            assert_eq!(
+                unsafe { engine_process_file(engine, missing.as_ptr(), &mut report) },
+                ENGINE_IO_ERROR
+            );
+            assert!(!unsafe { engine_last_error(engine) }.is_null());
+            assert_eq!(
+                unsafe { engine_process_file(engine, ptr::null(), &mut report) },
+                ENGINE_INVALID_ARGUMENT
+            );
+            unsafe { engine_free(engine) };
+        }
+    }
+}
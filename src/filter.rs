@@ -0,0 +1,100 @@
+//! Selection of the clients whose transactions are applied, e.g. to debug the ledger of a single
+//! customer out of a large feed.
+
+use crate::account::ClientId;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// Client ids written as a comma separated list of ids and inclusive ranges, e.g. `1,5,100-200`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientSet {
+    ranges: Vec<RangeInclusive<ClientId>>,
+}
+
+impl ClientSet {
+    pub fn contains(&self, client_id: ClientId) -> bool {
+        self.ranges.iter().any(|range| range.contains(&client_id))
+    }
+}
+
+impl FromStr for ClientSet {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid client ids '{}'", value);
+        let id = |id: &str| id.trim().parse::<ClientId>().map_err(|_| invalid());
+        let ranges = value
+            .split(',')
+            .map(|part| match part.split_once('-') {
+                Some((first, last)) => {
+                    let (first, last) = (id(first)?, id(last)?);
+                    if first <= last {
+                        Ok(first..=last)
+                    } else {
+                        Err(invalid())
+                    }
+                }
+                None => id(part).map(|id| id..=id),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(ClientSet { ranges })
+    }
+}
+
+/// Clients whose transactions are applied: those of `include`, or every client if not set, less
+/// those of `exclude`. Transfers are kept or dropped by their debited client.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientFilter {
+    pub include: Option<ClientSet>,
+    pub exclude: Option<ClientSet>,
+}
+
+impl ClientFilter {
+    pub fn allows(&self, client_id: ClientId) -> bool {
+        self.include
+            .as_ref()
+            .is_none_or(|include| include.contains(client_id))
+            && !self
+                .exclude
+                .as_ref()
+                .is_some_and(|exclude| exclude.contains(client_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::account::ClientId;
+        use crate::filter::{ClientFilter, ClientSet};
+
+        #[test]
+        fn client_sets_are_parsed_from_ids_and_ranges() {
+            let set: ClientSet = "1, 5,100-200".parse().unwrap();
+
+            assert!(set.contains(1) && set.contains(5));
+            assert!(set.contains(100) && set.contains(150) && set.contains(200));
+            assert!(!set.contains(2) && !set.contains(201));
+            let too_large = (u128::from(ClientId::MAX) + 1).to_string();
+            for invalid in ["", "1,,2", "200-100", "1-", "a", &too_large] {
+                assert!(invalid.parse::<ClientSet>().is_err(), "{}", invalid);
+            }
+        }
+
+        #[test]
+        fn excluded_clients_win_over_included_ones() {
+            let filter = ClientFilter {
+                include: Some("1-10".parse().unwrap()),
+                exclude: Some("5".parse().unwrap()),
+            };
+
+            assert!(filter.allows(1) && filter.allows(10));
+            assert!(!filter.allows(5) && !filter.allows(11));
+            assert!(ClientFilter::default().allows(5));
+            let exclude_only = ClientFilter {
+                include: None,
+                exclude: Some("5".parse().unwrap()),
+            };
+            assert!(exclude_only.allows(6) && !exclude_only.allows(5));
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//! Synthetic transactions for benchmarks and load tests, written by the `gen-data` command.
+//!
+//! Workloads are deterministic for a given seed. Disputes refer to recent deposits of the same
+//! client and are later resolved or, more rarely, chargebacked, so that every row exercises the
+//! engine rather than being rejected up front.
+
+use crate::account::ClientId;
+use crate::transaction::{Amount, Transaction, TransactionId, TransactionType};
+use std::io::{self, Write};
+
+/// Deposits that can still be disputed, per workload
+const DISPUTABLE: usize = 1024;
+
+/// Shape of a generated workload
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Workload {
+    pub rows: u64,
+    pub clients: ClientId,
+    /// 0 spreads transactions evenly over clients, larger values concentrate them on low client
+    /// ids
+    pub skew: f64,
+    /// Share of rows that are disputes, resolves or chargebacks, between 0 and 1
+    pub dispute_rate: f64,
+    pub seed: u64,
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        Workload {
+            rows: 1_000_000,
+            clients: 1_000,
+            skew: 0.0,
+            dispute_rate: 0.05,
+            seed: 1,
+        }
+    }
+}
+
+impl Workload {
+    pub fn transactions(&self) -> Generator {
+        Generator {
+            workload: *self,
+            rng: Rng::new(self.seed),
+            generated: 0,
+            next_id: TransactionId(1),
+            deposits: Vec::with_capacity(DISPUTABLE),
+            disputes: Vec::new(),
+        }
+    }
+
+    /// Writes the workload as csv with a `type,client,tx,amount` header. Returns the number of
+    /// rows written.
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> io::Result<u64> {
+        writeln!(writer, "type,client,tx,amount")?;
+        let mut rows = 0;
+        for transaction in self.transactions() {
+            let transaction_type = transaction.transaction_type.as_str();
+            match transaction.amount {
+                Some(amount) => writeln!(
+                    writer,
+                    "{},{},{},{:.4}",
+                    transaction_type, transaction.client_id, transaction.transaction_id, amount
+                )?,
+                None => writeln!(
+                    writer,
+                    "{},{},{},",
+                    transaction_type, transaction.client_id, transaction.transaction_id
+                )?,
+            }
+            rows += 1;
+        }
+        writer.flush()?;
+        Ok(rows)
+    }
+}
+
+/// Transactions of a [`Workload`]
+pub struct Generator {
+    workload: Workload,
+    rng: Rng,
+    generated: u64,
+    next_id: TransactionId,
+    /// Recent deposits, overwritten at random once full
+    deposits: Vec<(ClientId, TransactionId)>,
+    disputes: Vec<(ClientId, TransactionId)>,
+}
+
+impl Generator {
+    fn client(&mut self) -> ClientId {
+        let clients = self.workload.clients.max(1);
+        let position = self.rng.unit().powf(1.0 + self.workload.skew.max(0.0));
+        1 + ((position * clients as f64) as ClientId).min(clients - 1)
+    }
+
+    /// Up to `max` with four decimals
+    fn amount(&mut self, max: u64) -> Amount {
+        Amount::unchecked((1 + self.rng.below(max * 10_000)) as f64 / 10_000.0)
+    }
+
+    fn referencing(&mut self, transaction_type: TransactionType) -> Option<Transaction> {
+        let (client_id, transaction_id) = if transaction_type == TransactionType::Dispute {
+            if self.deposits.is_empty() {
+                return None;
+            }
+            let index = self.rng.below(self.deposits.len() as u64) as usize;
+            let disputed = self.deposits.swap_remove(index);
+            self.disputes.push(disputed);
+            disputed
+        } else {
+            if self.disputes.is_empty() {
+                return None;
+            }
+            let index = self.rng.below(self.disputes.len() as u64) as usize;
+            self.disputes.swap_remove(index)
+        };
+        Some(Transaction {
+            transaction_type,
+            client_id,
+            transaction_id,
+            amount: None,
+            to_client_id: None,
+            currency: None,
+            timestamp: None,
+            metadata: Default::default(),
+        })
+    }
+}
+
+impl Iterator for Generator {
+    type Item = Transaction;
+
+    fn next(&mut self) -> Option<Transaction> {
+        if self.generated >= self.workload.rows {
+            return None;
+        }
+        self.generated += 1;
+
+        if self.rng.unit() < self.workload.dispute_rate {
+            // Half open a dispute and the rest settle one, one settlement in ten is a chargeback
+            let transaction_type = match self.rng.below(20) {
+                0..=9 => TransactionType::Dispute,
+                10..=18 => TransactionType::Resolve,
+                _ => TransactionType::Chargeback,
+            };
+            if let Some(transaction) = self
+                .referencing(transaction_type)
+                .or_else(|| self.referencing(TransactionType::Dispute))
+            {
+                return Some(transaction);
+            }
+        }
+
+        let transaction_type = if self.rng.below(3) < 2 {
+            TransactionType::Deposit
+        } else {
+            TransactionType::Withdrawal
+        };
+        let client_id = self.client();
+        let transaction_id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        if transaction_type == TransactionType::Deposit {
+            if self.deposits.len() < DISPUTABLE {
+                self.deposits.push((client_id, transaction_id));
+            } else {
+                let index = self.rng.below(DISPUTABLE as u64) as usize;
+                self.deposits[index] = (client_id, transaction_id);
+            }
+        }
+        Some(Transaction {
+            transaction_type,
+            client_id,
+            transaction_id,
+            // Smaller withdrawals so that most of them are covered by earlier deposits
+            amount: Some(if transaction_type == TransactionType::Deposit {
+                self.amount(100)
+            } else {
+                self.amount(25)
+            }),
+            to_client_id: None,
+            currency: None,
+            timestamp: None,
+            metadata: Default::default(),
+        })
+    }
+}
+
+/// xorshift64*, statistically good enough for synthetic data and sampling
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        // The state must never be zero
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+
+    /// Uniform in `[0, 1)`
+    pub(crate) fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::generate::Workload;
+        use crate::input::{CsvSource, TransactionSource};
+        use crate::transaction::TransactionType;
+
+        #[test]
+        fn workloads_are_deterministic_and_readable() {
+            let workload = Workload {
+                rows: 500,
+                clients: 10,
+                skew: 1.0,
+                dispute_rate: 0.2,
+                seed: 7,
+            };
+            let mut first = Vec::new();
+            let mut second = Vec::new();
+            assert_eq!(workload.write_csv(&mut first).unwrap(), 500);
+            workload.write_csv(&mut second).unwrap();
+            assert_eq!(first, second);
+
+            let mut source = CsvSource::new(first.as_slice());
+            let mut disputes = 0;
+            while let Some(transaction) = source.next_transaction() {
+                let transaction = transaction.unwrap();
+                assert!((1..=10).contains(&transaction.client_id));
+                disputes += usize::from(transaction.transaction_type == TransactionType::Dispute);
+            }
+            assert!(disputes > 0);
+        }
+
+        #[test]
+        fn skew_favours_low_client_ids() {
+            let workload = Workload {
+                rows: 2_000,
+                clients: 100,
+                skew: 3.0,
+                dispute_rate: 0.0,
+                ..Workload::default()
+            };
+            let low = workload
+                .transactions()
+                .filter(|transaction| transaction.client_id <= 10)
+                .count();
+            // Uniformly this would be about a tenth
+            assert!(low > 800, "{}", low);
+        }
+    }
+}
@@ -0,0 +1,788 @@
+//! gRPC interface of `proto/engine.proto`, served by [`Server::serve_grpc`] next to the HTTP
+//! API of the same [`Server`].
+//!
+//! Calls are carried over cleartext HTTP/2 by [`crate::http2`] and their messages encoded as
+//! protobuf by hand, fields the contract does not know being skipped. Submissions go through
+//! [`Server`] like those of `POST /transactions`: they are applied on behalf of clients, count
+//! against the rate limits and are answered once durable. Every message of `SubmitStream` is a
+//! submission of its own, answered as soon as it is applied, and the first one refused ends the
+//! call. Account queries see what `GET /accounts` sees, including the read view of the server.
+//!
+//! Failures are reported as gRPC statuses: `INVALID_ARGUMENT` for malformed messages,
+//! `NOT_FOUND` for accounts that do not exist, `RESOURCE_EXHAUSTED` for submissions over the
+//! rate limits or messages larger than the HTTP API accepts, `UNIMPLEMENTED` for unknown
+//! methods and compressed messages, and `INTERNAL` for submissions that could not be made
+//! durable.
+
+use crate::account::ClientId;
+use crate::currency::Currency;
+use crate::error::EngineError;
+use crate::http2::{Connection, Event, Headers};
+use crate::input::AmountRules;
+use crate::log;
+use crate::output::AccountSnapshot;
+use crate::server::{Refused, Server, MAX_BODY_SIZE};
+use crate::transaction::{Origin, Transaction, TransactionId, TransactionType};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+/// Path prefix of the methods of the service
+const SERVICE: &str = "/engine.v1.TransactionEngine/";
+
+const OK: u32 = 0;
+const INVALID_ARGUMENT: u32 = 3;
+const NOT_FOUND: u32 = 5;
+const RESOURCE_EXHAUSTED: u32 = 8;
+const UNIMPLEMENTED: u32 = 12;
+const INTERNAL: u32 = 13;
+
+const CONTENT_TYPE: &str = "application/grpc";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Method {
+    Submit,
+    SubmitStream,
+    GetAccount,
+    ListAccounts,
+}
+
+/// Status a call ends with
+#[derive(Debug, PartialEq)]
+struct Status {
+    code: u32,
+    message: String,
+}
+
+impl Status {
+    fn new(code: u32, message: impl Into<String>) -> Self {
+        Status {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Call in progress on a stream
+struct Call {
+    method: Method,
+    path: String,
+    /// Request data not yet read as messages
+    buffer: Vec<u8>,
+    /// Whether the response headers were sent, so that the status goes into trailers
+    responded: bool,
+}
+
+impl Server {
+    /// Accepts gRPC connections until the listener fails, serving each on its own thread
+    pub fn serve_grpc(&self, listener: TcpListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let server = self.clone();
+            thread::spawn(move || {
+                // The client may already be gone, there is nobody to report the error to
+                let _ = server.handle_grpc_connection(stream);
+            });
+        }
+        Ok(())
+    }
+
+    fn handle_grpc_connection(&self, stream: TcpStream) -> io::Result<()> {
+        let reader = BufReader::new(stream.try_clone()?);
+        self.serve_calls(reader, BufWriter::new(stream))
+    }
+
+    /// Serves the calls of an HTTP/2 connection until the client closes it
+    fn serve_calls<R: BufRead, W: Write>(&self, reader: R, writer: W) -> io::Result<()> {
+        let mut connection = Connection::accept(reader, writer)?;
+        let mut calls: HashMap<u32, Call> = HashMap::new();
+        while let Some(event) = connection.next_event()? {
+            let (stream, data, end) = match event {
+                Event::Request {
+                    stream,
+                    headers,
+                    end,
+                } => {
+                    match open(&headers) {
+                        Ok(mut call) => {
+                            // Results of a stream of submissions follow as they are applied
+                            if call.method == Method::SubmitStream {
+                                connection.send_headers(stream, &response_headers(), false);
+                                call.responded = true;
+                            }
+                            calls.insert(stream, call);
+                        }
+                        Err(Err(status)) => {
+                            connection.send_headers(stream, &[(":status", status)], true);
+                            continue;
+                        }
+                        Err(Ok(status)) => {
+                            finish(&mut connection, stream, status, false);
+                            continue;
+                        }
+                    }
+                    (stream, Vec::new(), end)
+                }
+                Event::Data { stream, data, end } => (stream, data, end),
+                Event::Reset(stream) => {
+                    calls.remove(&stream);
+                    continue;
+                }
+            };
+            let Some(call) = calls.get_mut(&stream) else {
+                continue;
+            };
+            call.buffer.extend(data);
+            if let Some(status) = self.advance(&mut connection, stream, call, end) {
+                log::debug(
+                    "Handled call",
+                    &[("method", &call.path), ("status", &status.code)],
+                );
+                finish(&mut connection, stream, status, call.responded);
+                calls.remove(&stream);
+            }
+        }
+        connection.flush()
+    }
+
+    /// Answers what the call received so far, returning its status once it is over
+    fn advance<R: BufRead, W: Write>(
+        &self,
+        connection: &mut Connection<R, W>,
+        stream: u32,
+        call: &mut Call,
+        end: bool,
+    ) -> Option<Status> {
+        if call.method == Method::SubmitStream {
+            loop {
+                let message = match take_message(&mut call.buffer) {
+                    Ok(Some(message)) => message,
+                    Ok(None) => break,
+                    Err(status) => return Some(status),
+                };
+                let responses = match decode_transaction(&message) {
+                    Ok(transaction) => self.submit_messages(vec![transaction]),
+                    Err(message) => Err(Status::new(INVALID_ARGUMENT, message)),
+                };
+                match responses {
+                    Ok(responses) => send_messages(connection, stream, responses),
+                    Err(status) => return Some(status),
+                }
+            }
+            return match (end, call.buffer.is_empty()) {
+                (false, _) => None,
+                (true, true) => Some(Status::new(OK, "")),
+                (true, false) => Some(Status::new(INVALID_ARGUMENT, "truncated message")),
+            };
+        }
+
+        if call.buffer.len() > MAX_BODY_SIZE + 5 {
+            return Some(Status::new(RESOURCE_EXHAUSTED, "request is too large"));
+        }
+        if !end {
+            return None;
+        }
+        let responses = match take_message(&mut call.buffer) {
+            Ok(Some(message)) if call.buffer.is_empty() => self.unary(call.method, &message),
+            Ok(_) => Err(Status::new(INVALID_ARGUMENT, "expected a single message")),
+            Err(status) => Err(status),
+        };
+        match responses {
+            Ok(responses) => {
+                connection.send_headers(stream, &response_headers(), false);
+                call.responded = true;
+                send_messages(connection, stream, responses);
+                Some(Status::new(OK, ""))
+            }
+            Err(status) => Some(status),
+        }
+    }
+
+    /// Response messages of a call with a single request message
+    fn unary(&self, method: Method, request: &[u8]) -> Result<Vec<Vec<u8>>, Status> {
+        let invalid = |message| Status::new(INVALID_ARGUMENT, message);
+        match method {
+            Method::Submit | Method::SubmitStream => {
+                let transaction = decode_transaction(request).map_err(invalid)?;
+                self.submit_messages(vec![transaction])
+            }
+            Method::GetAccount => {
+                let (client_id, currency) = decode_account_request(request).map_err(invalid)?;
+                match self.snapshot(client_id, currency) {
+                    Some(snapshot) => Ok(vec![encode_account(&snapshot)]),
+                    None => Err(Status::new(NOT_FOUND, "no such account")),
+                }
+            }
+            Method::ListAccounts => {
+                // The request has no fields, but has to be a message
+                decode_fields(request).map_err(invalid)?;
+                Ok(self.snapshots().iter().map(encode_account).collect())
+            }
+        }
+    }
+
+    /// Applies the transactions as a client submission, returning a result message for each
+    fn submit_messages(&self, transactions: Vec<Transaction>) -> Result<Vec<Vec<u8>>, Status> {
+        match self.execute(transactions, Origin::Client) {
+            Ok(results) => Ok(results
+                .iter()
+                .map(|(transaction_id, result)| encode_result(*transaction_id, result))
+                .collect()),
+            Err(Refused::Throttled(throttled)) => {
+                Err(Status::new(RESOURCE_EXHAUSTED, throttled.to_string()))
+            }
+            Err(Refused::Failed(message)) => Err(Status::new(INTERNAL, message)),
+        }
+    }
+}
+
+/// Call of the request headers, or why it is refused: an HTTP status for requests that are not
+/// gRPC calls, a gRPC status for calls the service does not take
+fn open(headers: &Headers) -> Result<Call, Result<Status, &'static str>> {
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    };
+    if header(":method") != Some("POST") {
+        return Err(Err("405"));
+    }
+    match header("content-type") {
+        Some(CONTENT_TYPE | "application/grpc+proto") => {}
+        _ => return Err(Err("415")),
+    }
+    let path = header(":path").unwrap_or_default();
+    let method = match path.strip_prefix(SERVICE) {
+        Some("Submit") => Method::Submit,
+        Some("SubmitStream") => Method::SubmitStream,
+        Some("GetAccount") => Method::GetAccount,
+        Some("ListAccounts") => Method::ListAccounts,
+        _ => {
+            return Err(Ok(Status::new(
+                UNIMPLEMENTED,
+                format!("unknown method '{}'", path),
+            )))
+        }
+    };
+    Ok(Call {
+        method,
+        path: path.to_string(),
+        buffer: Vec::new(),
+        responded: false,
+    })
+}
+
+fn response_headers() -> [(&'static str, &'static str); 2] {
+    [(":status", "200"), ("content-type", CONTENT_TYPE)]
+}
+
+fn send_messages<R: BufRead, W: Write>(
+    connection: &mut Connection<R, W>,
+    stream: u32,
+    messages: Vec<Vec<u8>>,
+) {
+    for message in messages {
+        let mut framed = Vec::with_capacity(message.len() + 5);
+        framed.push(0);
+        framed.extend((message.len() as u32).to_be_bytes());
+        framed.extend(message);
+        connection.send_data(stream, framed, false);
+    }
+}
+
+/// Ends the call with the status, in trailers after the response or as the whole response
+fn finish<R: BufRead, W: Write>(
+    connection: &mut Connection<R, W>,
+    stream: u32,
+    status: Status,
+    responded: bool,
+) {
+    let code = status.code.to_string();
+    let message = percent_encode(&status.message);
+    let mut headers = Vec::new();
+    if !responded {
+        headers.extend(response_headers());
+    }
+    headers.push(("grpc-status", code.as_str()));
+    if !message.is_empty() {
+        headers.push(("grpc-message", message.as_str()));
+    }
+    connection.send_headers(stream, &headers, true);
+}
+
+/// Status message as the `grpc-message` header carries it
+fn percent_encode(message: &str) -> String {
+    let mut encoded = String::with_capacity(message.len());
+    for byte in message.bytes() {
+        match byte {
+            b' '..=b'~' if byte != b'%' => encoded.push(char::from(byte)),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Next message of the request data, `None` until it was received in full
+fn take_message(buffer: &mut Vec<u8>) -> Result<Option<Vec<u8>>, Status> {
+    let Some(&[compressed, a, b, c, d]) = buffer.get(..5) else {
+        return Ok(None);
+    };
+    if compressed != 0 {
+        return Err(Status::new(
+            UNIMPLEMENTED,
+            "compressed messages are not supported",
+        ));
+    }
+    let length = u32::from_be_bytes([a, b, c, d]) as usize;
+    if length > MAX_BODY_SIZE {
+        return Err(Status::new(RESOURCE_EXHAUSTED, "message is too large"));
+    }
+    if buffer.len() < 5 + length {
+        return Ok(None);
+    }
+    let message = buffer[5..5 + length].to_vec();
+    buffer.drain(..5 + length);
+    Ok(Some(message))
+}
+
+/// Value of a protobuf field by wire type
+#[derive(Debug, PartialEq)]
+enum Value<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+/// Numbers and values of the fields of a protobuf message, in the order they are encoded
+fn decode_fields(mut message: &[u8]) -> Result<Vec<(u64, Value<'_>)>, String> {
+    let mut fields = Vec::new();
+    while !message.is_empty() {
+        let key = decode_varint(&mut message)?;
+        let value = match key & 0x7 {
+            0 => Value::Varint(decode_varint(&mut message)?),
+            1 => Value::Fixed64(u64::from_le_bytes(
+                take(&mut message, 8)?.try_into().unwrap_or_default(),
+            )),
+            2 => {
+                let length = usize::try_from(decode_varint(&mut message)?)
+                    .map_err(|_| "field is too long".to_string())?;
+                Value::Bytes(take(&mut message, length)?)
+            }
+            5 => Value::Fixed32(u32::from_le_bytes(
+                take(&mut message, 4)?.try_into().unwrap_or_default(),
+            )),
+            wire_type => return Err(format!("unsupported wire type {}", wire_type)),
+        };
+        fields.push((key >> 3, value));
+    }
+    Ok(fields)
+}
+
+fn take<'a>(message: &mut &'a [u8], length: usize) -> Result<&'a [u8], String> {
+    if message.len() < length {
+        return Err("truncated message".to_string());
+    }
+    let (taken, rest) = message.split_at(length);
+    *message = rest;
+    Ok(taken)
+}
+
+fn decode_varint(message: &mut &[u8]) -> Result<u64, String> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *take(message, 1)?.first().unwrap_or(&0);
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("varint is too long".to_string())
+}
+
+/// Client id of a field, failing for ids wider than [`ClientId`]
+fn client_id<T: TryFrom<u64>>(value: u64) -> Result<T, String> {
+    T::try_from(value).map_err(|_| format!("client id {} is out of range", value))
+}
+
+fn string(bytes: &[u8]) -> Result<&str, String> {
+    std::str::from_utf8(bytes).map_err(|_| "string is not valid utf-8".to_string())
+}
+
+fn wrong_type(field: u64) -> String {
+    format!("field {} has the wrong wire type", field)
+}
+
+/// Transaction of a `Transaction` message, with its amount checked as the HTTP API checks it
+fn decode_transaction(message: &[u8]) -> Result<Transaction, String> {
+    let (mut kind, mut client, mut tx, mut amount) = (0, 0, 0, None);
+    let (mut to, mut currency, mut timestamp) = (None, None, None);
+    for (field, value) in decode_fields(message)? {
+        match (field, value) {
+            (1, Value::Varint(value)) => kind = value,
+            (2, Value::Varint(value)) => client = value,
+            (3, Value::Varint(value)) => tx = value,
+            (4, Value::Fixed64(value)) => amount = Some(f64::from_bits(value)),
+            (5, Value::Varint(value)) => to = Some(value),
+            (6, Value::Bytes(value)) => currency = Some(string(value)?.parse()?),
+            (7, Value::Bytes(value)) => timestamp = Some(string(value)?.parse()?),
+            (1..=7, _) => return Err(wrong_type(field)),
+            _ => {}
+        }
+    }
+    // Numbered from 1 in the order of the contract, 0 being unspecified
+    let transaction_type = usize::try_from(kind)
+        .ok()
+        .and_then(|kind| kind.checked_sub(1))
+        .and_then(|index| TransactionType::ALL.get(index).copied())
+        .ok_or_else(|| format!("invalid transaction type {}", kind))?;
+    Ok(Transaction {
+        transaction_type,
+        client_id: client_id(client)?,
+        transaction_id: TransactionId(
+            u32::try_from(tx).map_err(|_| format!("transaction id {} is out of range", tx))?,
+        ),
+        amount: AmountRules::default().amount(transaction_type, amount)?,
+        to_client_id: to.map(client_id).transpose()?,
+        currency,
+        timestamp,
+        metadata: HashMap::new(),
+    })
+}
+
+/// Client and currency of a `GetAccountRequest` message
+fn decode_account_request(message: &[u8]) -> Result<(ClientId, Option<Currency>), String> {
+    let (mut client, mut currency) = (0, None);
+    for (field, value) in decode_fields(message)? {
+        match (field, value) {
+            (1, Value::Varint(value)) => client = value,
+            (2, Value::Bytes(value)) => currency = Some(string(value)?.parse()?),
+            (1..=2, _) => return Err(wrong_type(field)),
+            _ => {}
+        }
+    }
+    Ok((client_id(client)?, currency))
+}
+
+fn encode_varint(message: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        message.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    message.push(value as u8);
+}
+
+/// Appends an integer or boolean field, left out at its default of 0 as proto3 does
+fn encode_uint(message: &mut Vec<u8>, field: u64, value: impl Into<u64>) {
+    let value = value.into();
+    if value != 0 {
+        encode_varint(message, field << 3);
+        encode_varint(message, value);
+    }
+}
+
+fn encode_double(message: &mut Vec<u8>, field: u64, value: f64) {
+    if value != 0.0 {
+        encode_varint(message, field << 3 | 1);
+        message.extend(value.to_bits().to_le_bytes());
+    }
+}
+
+fn encode_string(message: &mut Vec<u8>, field: u64, value: &str) {
+    encode_varint(message, field << 3 | 2);
+    encode_varint(message, value.len() as u64);
+    message.extend(value.as_bytes());
+}
+
+/// `SubmitResult` message of a transaction
+fn encode_result(transaction_id: TransactionId, result: &Result<(), EngineError>) -> Vec<u8> {
+    let mut message = Vec::new();
+    encode_uint(&mut message, 1, transaction_id.0);
+    match result {
+        Ok(()) => encode_uint(&mut message, 2, true),
+        Err(err) => encode_string(&mut message, 3, &err.to_string()),
+    }
+    message
+}
+
+/// `AccountState` message of the funds of an account
+fn encode_account(snapshot: &AccountSnapshot) -> Vec<u8> {
+    let mut message = Vec::new();
+    encode_uint(&mut message, 1, snapshot.client);
+    encode_double(&mut message, 2, snapshot.available);
+    encode_double(&mut message, 3, snapshot.held);
+    encode_double(&mut message, 4, snapshot.total);
+    encode_uint(&mut message, 5, snapshot.locked);
+    if let Some(currency) = snapshot.currency {
+        encode_string(&mut message, 6, currency.as_str());
+    }
+    encode_uint(&mut message, 7, snapshot.closed);
+    encode_double(&mut message, 8, snapshot.reserved);
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::account::ClientId;
+        use crate::engine::TransactionEngine;
+        use crate::grpc::{
+            decode_fields, decode_transaction, encode_double, encode_string, encode_uint,
+            percent_encode, Value,
+        };
+        use crate::http2::{encode_header, Decoder, PREFACE};
+        use crate::server::Server;
+        use crate::throttle::RateLimits;
+        use crate::transaction::{TransactionId, TransactionType};
+        use std::io::Cursor;
+
+        fn frame(kind: u8, flags: u8, stream: u32, payload: &[u8]) -> Vec<u8> {
+            let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+            frame.extend([kind, flags]);
+            frame.extend(stream.to_be_bytes());
+            frame.extend(payload);
+            frame
+        }
+
+        fn framed(message: &[u8]) -> Vec<u8> {
+            let mut framed = vec![0];
+            framed.extend((message.len() as u32).to_be_bytes());
+            framed.extend(message);
+            framed
+        }
+
+        fn deposit(client: u64, tx: u64, amount: f64) -> Vec<u8> {
+            let mut message = Vec::new();
+            encode_uint(&mut message, 1, 1u64);
+            encode_uint(&mut message, 2, client);
+            encode_uint(&mut message, 3, tx);
+            encode_double(&mut message, 4, amount);
+            message
+        }
+
+        /// Request headers of a call of the method
+        fn call(method: &str) -> Vec<u8> {
+            let mut block = Vec::new();
+            encode_header(&mut block, ":method", "POST");
+            encode_header(&mut block, ":scheme", "http");
+            encode_header(
+                &mut block,
+                ":path",
+                &format!("/engine.v1.TransactionEngine/{}", method),
+            );
+            encode_header(&mut block, "content-type", "application/grpc");
+            encode_header(&mut block, "te", "trailers");
+            block
+        }
+
+        /// What the server answered on every stream: headers and trailers, then messages
+        struct Answer {
+            headers: Vec<Vec<(String, String)>>,
+            messages: Vec<Vec<u8>>,
+        }
+
+        impl Answer {
+            fn header(&self, name: &str) -> Option<&str> {
+                self.headers
+                    .iter()
+                    .flatten()
+                    .find(|(header, _)| header == name)
+                    .map(|(_, value)| value.as_str())
+            }
+        }
+
+        /// Serves the frames after the preface and settings, returning the answer of each stream
+        fn serve(server: &Server, frames: &[Vec<u8>], streams: &[u32]) -> Vec<Answer> {
+            let mut input = PREFACE.to_vec();
+            input.extend(frame(0x4, 0, 0, &[]));
+            input.extend(frames.concat());
+            let mut output = Vec::new();
+            server.serve_calls(Cursor::new(input), &mut output).unwrap();
+
+            let mut decoder = Decoder::default();
+            let mut answers: Vec<_> = streams
+                .iter()
+                .map(|_| Answer {
+                    headers: Vec::new(),
+                    messages: Vec::new(),
+                })
+                .collect();
+            let mut data = vec![Vec::new(); streams.len()];
+            let mut output = output.as_slice();
+            while !output.is_empty() {
+                let length = u32::from_be_bytes([0, output[0], output[1], output[2]]) as usize;
+                let stream = u32::from_be_bytes([output[5], output[6], output[7], output[8]]);
+                let payload = &output[9..9 + length];
+                if let Some(index) = streams.iter().position(|id| *id == stream) {
+                    match output[3] {
+                        0x0 => data[index].extend(payload),
+                        0x1 => answers[index]
+                            .headers
+                            .push(decoder.decode(payload).unwrap()),
+                        _ => {}
+                    }
+                }
+                output = &output[9 + length..];
+            }
+            for (answer, mut data) in answers.iter_mut().zip(data) {
+                while !data.is_empty() {
+                    let length = u32::from_be_bytes([data[1], data[2], data[3], data[4]]) as usize;
+                    answer.messages.push(data[5..5 + length].to_vec());
+                    data.drain(..5 + length);
+                }
+            }
+            answers
+        }
+
+        fn field(message: &[u8], number: u64) -> Option<Value<'_>> {
+            decode_fields(message)
+                .unwrap()
+                .into_iter()
+                .find(|(field, _)| *field == number)
+                .map(|(_, value)| value)
+        }
+
+        #[test]
+        fn transactions_are_decoded_and_checked() {
+            let mut message = deposit(3, 9, 1.5);
+            encode_string(&mut message, 6, "eur");
+            // Unknown fields are skipped
+            encode_string(&mut message, 15, "ignored");
+            let transaction = decode_transaction(&message).unwrap();
+            assert_eq!(transaction.transaction_type, TransactionType::Deposit);
+            assert_eq!(transaction.client_id, 3);
+            assert_eq!(transaction.transaction_id, TransactionId(9));
+            assert_eq!(transaction.amount.unwrap().value(), 1.5);
+            assert_eq!(transaction.currency.unwrap().as_str(), "EUR");
+
+            let mut dispute = Vec::new();
+            encode_uint(&mut dispute, 1, 3u64);
+            encode_uint(&mut dispute, 2, 3u64);
+            encode_uint(&mut dispute, 3, 9u64);
+            let dispute = decode_transaction(&dispute).unwrap();
+            assert_eq!(dispute.transaction_type, TransactionType::Dispute);
+            assert_eq!(dispute.amount, None);
+
+            assert!(decode_transaction(&deposit(3, 9, -1.0)).is_err());
+            assert!(decode_transaction(&deposit(3, 1 << 32, 1.0)).is_err());
+            // Ids wider than those of the build are out of range
+            assert_eq!(
+                decode_transaction(&deposit(u64::MAX, 9, 1.0)).is_ok(),
+                std::mem::size_of::<ClientId>() == 8
+            );
+            assert!(decode_transaction(&[0x08]).is_err());
+            assert!(decode_transaction(&[]).is_err());
+            assert_eq!(percent_encode("100% ok\n"), "100%25 ok%0A");
+        }
+
+        #[test]
+        fn calls_are_answered() {
+            let server = Server::new(TransactionEngine::new());
+            let mut get = Vec::new();
+            encode_uint(&mut get, 1, 1u64);
+            let mut missing = Vec::new();
+            encode_uint(&mut missing, 1, 2u64);
+            let answers = serve(
+                &server,
+                &[
+                    frame(0x1, 0x4, 1, &call("Submit")),
+                    frame(0x0, 0x1, 1, &framed(&deposit(1, 1, 2.5))),
+                    // Rejected by the engine, answered with the reason
+                    frame(0x1, 0x4, 3, &call("Submit")),
+                    frame(0x0, 0x1, 3, &framed(&deposit(1, 1, 2.5))),
+                    frame(0x1, 0x4, 5, &call("GetAccount")),
+                    frame(0x0, 0x1, 5, &framed(&get)),
+                    frame(0x1, 0x4, 7, &call("GetAccount")),
+                    frame(0x0, 0x1, 7, &framed(&missing)),
+                    frame(0x1, 0x4, 9, &call("ListAccounts")),
+                    frame(0x0, 0x1, 9, &framed(&[])),
+                    frame(0x1, 0x4, 11, &call("Unknown")),
+                    frame(0x0, 0x1, 11, &framed(&[])),
+                    frame(0x1, 0x4, 13, &call("Submit")),
+                    frame(0x0, 0x1, 13, &framed(&[0xff])),
+                ],
+                &[1, 3, 5, 7, 9, 11, 13],
+            );
+
+            assert_eq!(answers[0].header("grpc-status"), Some("0"));
+            assert_eq!(answers[0].header("content-type"), Some("application/grpc"));
+            assert_eq!(answers[0].messages.len(), 1);
+            assert_eq!(field(&answers[0].messages[0], 1), Some(Value::Varint(1)));
+            assert_eq!(field(&answers[0].messages[0], 2), Some(Value::Varint(1)));
+
+            assert_eq!(answers[1].header("grpc-status"), Some("0"));
+            assert_eq!(field(&answers[1].messages[0], 2), None);
+            assert!(matches!(
+                field(&answers[1].messages[0], 3),
+                Some(Value::Bytes(_))
+            ));
+
+            assert_eq!(answers[2].header("grpc-status"), Some("0"));
+            assert_eq!(
+                field(&answers[2].messages[0], 2),
+                Some(Value::Fixed64(2.5f64.to_bits()))
+            );
+            assert_eq!(answers[3].header("grpc-status"), Some("5"));
+            assert!(answers[3].messages.is_empty());
+
+            assert_eq!(answers[4].header("grpc-status"), Some("0"));
+            assert_eq!(answers[4].messages.len(), 1);
+            assert_eq!(answers[5].header("grpc-status"), Some("12"));
+            assert_eq!(answers[6].header("grpc-status"), Some("3"));
+        }
+
+        #[test]
+        fn stream_of_submissions_ends_at_the_first_malformed() {
+            let server = Server::new(TransactionEngine::new());
+            let answers = serve(
+                &server,
+                &[
+                    frame(0x1, 0x4, 1, &call("SubmitStream")),
+                    frame(0x0, 0, 1, &framed(&deposit(1, 1, 1.0))),
+                    frame(
+                        0x0,
+                        0,
+                        1,
+                        &[framed(&deposit(1, 2, 1.0)), framed(&[0xff])].concat(),
+                    ),
+                    frame(0x0, 0x1, 1, &framed(&deposit(1, 4, 1.0))),
+                ],
+                &[1],
+            );
+            assert_eq!(answers[0].messages.len(), 2);
+            assert_eq!(field(&answers[0].messages[1], 1), Some(Value::Varint(2)));
+            assert_eq!(answers[0].header("grpc-status"), Some("3"));
+            assert_eq!(server.snapshots()[0].available, 2.0);
+        }
+
+        #[test]
+        fn stream_of_submissions_ends_at_the_first_throttled() {
+            let server = Server::new(TransactionEngine::new()).with_rate_limits(RateLimits {
+                // Two submissions, then none for minutes
+                global: Some("0.01:2".parse().unwrap()),
+                per_client: None,
+            });
+            let answers = serve(
+                &server,
+                &[
+                    frame(0x1, 0x4, 1, &call("SubmitStream")),
+                    frame(0x0, 0, 1, &framed(&deposit(1, 1, 1.0))),
+                    frame(
+                        0x0,
+                        0,
+                        1,
+                        &[framed(&deposit(1, 2, 1.0)), framed(&deposit(1, 3, 1.0))].concat(),
+                    ),
+                    frame(0x0, 0x1, 1, &framed(&deposit(1, 4, 1.0))),
+                ],
+                &[1],
+            );
+            assert_eq!(answers[0].messages.len(), 2);
+            assert_eq!(field(&answers[0].messages[1], 1), Some(Value::Varint(2)));
+            assert_eq!(answers[0].header("grpc-status"), Some("8"));
+            assert_eq!(server.snapshots()[0].available, 2.0);
+        }
+    }
+}
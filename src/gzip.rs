@@ -0,0 +1,549 @@
+//! Gzip as specified in RFC 1952, with the DEFLATE data of every member inflated as specified in
+//! RFC 1951, for input compressed by `gzip` or exported as `.csv.gz`. The data is decoded as it
+//! is read, keeping only the last 32 KiB of output that back references may copy from.
+
+use std::io::{self, BufRead, Read};
+use std::mem;
+
+/// Magic bytes every gzip member starts with
+pub(crate) const MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Distance back references can reach
+const WINDOW: usize = 1 << 15;
+
+/// Longest code of a Huffman code of DEFLATE
+const MAX_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Order in which the lengths of the code length code are stored
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const END_OF_BLOCK: u16 = 256;
+
+/// Decompressed bytes of the gzip members read from `inner`, one after the other. Corrupt input
+/// fails the read with [`io::ErrorKind::InvalidData`], truncated input with
+/// [`io::ErrorKind::UnexpectedEof`].
+pub(crate) struct GzipDecoder<R> {
+    input: BitReader<R>,
+    state: State,
+    /// Whether the current block is the last of its member
+    last: bool,
+    /// Bytes of a back reference left to copy, with its distance
+    copy: (usize, usize),
+    window: Box<[u8; WINDOW]>,
+    /// Bytes written by the current member, the lower bits giving the next position of `window`
+    written: usize,
+    crc: u32,
+}
+
+enum State {
+    Header,
+    Block,
+    /// Bytes left of a stored block
+    Stored(usize),
+    Compressed(Box<Codes>),
+    Trailer,
+    Done,
+}
+
+struct Codes {
+    literals: Huffman,
+    distances: Huffman,
+}
+
+impl<R: BufRead> GzipDecoder<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        GzipDecoder {
+            input: BitReader {
+                inner,
+                bits: 0,
+                count: 0,
+            },
+            state: State::Header,
+            last: false,
+            copy: (0, 0),
+            window: Box::new([0; WINDOW]),
+            written: 0,
+            crc: !0,
+        }
+    }
+
+    fn header(&mut self) -> io::Result<()> {
+        if self.input.take(16)? != u32::from(u16::from_le_bytes(MAGIC)) {
+            return Err(invalid("not in gzip format"));
+        }
+        if self.input.take(8)? != 8 {
+            return Err(invalid("unknown compression method"));
+        }
+        let flags = self.input.take(8)?;
+        if flags & 0xe0 != 0 {
+            return Err(invalid("reserved header flags are set"));
+        }
+        // Modification time, extra flags and operating system
+        self.input.skip_bytes(6)?;
+        if flags & 0x04 != 0 {
+            let extra = self.input.take(16)?;
+            self.input.skip_bytes(extra as usize)?;
+        }
+        // Name and comment, each terminated by a zero byte
+        for flag in [0x08, 0x10] {
+            if flags & flag != 0 {
+                while self.input.take(8)? != 0 {}
+            }
+        }
+        if flags & 0x02 != 0 {
+            self.input.skip_bytes(2)?;
+        }
+        self.written = 0;
+        self.crc = !0;
+        Ok(())
+    }
+
+    fn block(&mut self) -> io::Result<State> {
+        self.last = self.input.take(1)? == 1;
+        match self.input.take(2)? {
+            0 => {
+                self.input.align();
+                let length = self.input.take(16)?;
+                if self.input.take(16)? != !length & 0xffff {
+                    return Err(invalid("stored block length is corrupt"));
+                }
+                Ok(State::Stored(length as usize))
+            }
+            1 => Ok(State::Compressed(Box::new(Codes::fixed()))),
+            2 => Ok(State::Compressed(Box::new(self.dynamic_codes()?))),
+            _ => Err(invalid("invalid block type")),
+        }
+    }
+
+    fn dynamic_codes(&mut self) -> io::Result<Codes> {
+        let literals = self.input.take(5)? as usize + 257;
+        let distances = self.input.take(5)? as usize + 1;
+        let code_lengths = self.input.take(4)? as usize + 4;
+        if literals > 286 || distances > 30 {
+            return Err(invalid("too many length or distance codes"));
+        }
+        let mut lengths = [0; 19];
+        for &symbol in &CODE_LENGTH_ORDER[..code_lengths] {
+            lengths[symbol] = self.input.take(3)? as u8;
+        }
+        let code_length_code = Huffman::new(&lengths)?;
+
+        let mut lengths = vec![0; literals + distances];
+        let mut index = 0;
+        while index < lengths.len() {
+            let (length, repeat) = match code_length_code.decode(&mut self.input)? {
+                symbol @ 0..=15 => (symbol as u8, 1),
+                16 if index == 0 => return Err(invalid("repeated code length without a first")),
+                16 => (lengths[index - 1], 3 + self.input.take(2)?),
+                17 => (0, 3 + self.input.take(3)?),
+                _ => (0, 11 + self.input.take(7)?),
+            };
+            let end = index + repeat as usize;
+            if end > lengths.len() {
+                return Err(invalid("too many code lengths"));
+            }
+            lengths[index..end].fill(length);
+            index = end;
+        }
+        if lengths[usize::from(END_OF_BLOCK)] == 0 {
+            return Err(invalid("missing end of block code"));
+        }
+        Ok(Codes {
+            literals: Huffman::new(&lengths[..literals])?,
+            distances: Huffman::new(&lengths[literals..])?,
+        })
+    }
+
+    /// Fills `buf` from the stored block with `left` bytes left, returns the bytes written
+    fn stored(&mut self, buf: &mut [u8], left: usize) -> io::Result<usize> {
+        let count = left.min(buf.len());
+        for byte in &mut buf[..count] {
+            *byte = self.input.take(8)? as u8;
+        }
+        self.output(&buf[..count]);
+        Ok(count)
+    }
+
+    /// Fills `buf` from the compressed block, returns the bytes written and whether the block
+    /// ended
+    fn compressed(&mut self, codes: &Codes, buf: &mut [u8]) -> io::Result<(usize, bool)> {
+        let mut filled = 0;
+        loop {
+            while self.copy.0 > 0 && filled < buf.len() {
+                let (length, distance) = self.copy;
+                let byte = self.window[(self.written - distance) % WINDOW];
+                self.window[self.written % WINDOW] = byte;
+                self.written += 1;
+                buf[filled] = byte;
+                filled += 1;
+                self.copy.0 = length - 1;
+            }
+            if filled == buf.len() {
+                self.crc = crc32(self.crc, &buf[..filled]);
+                return Ok((filled, false));
+            }
+            let symbol = codes.literals.decode(&mut self.input)?;
+            match symbol {
+                0..=255 => {
+                    self.window[self.written % WINDOW] = symbol as u8;
+                    self.written += 1;
+                    buf[filled] = symbol as u8;
+                    filled += 1;
+                }
+                END_OF_BLOCK => {
+                    self.crc = crc32(self.crc, &buf[..filled]);
+                    return Ok((filled, true));
+                }
+                _ => {
+                    let index = usize::from(symbol - 257);
+                    if index >= LENGTH_BASE.len() {
+                        return Err(invalid("invalid length code"));
+                    }
+                    let length = u32::from(LENGTH_BASE[index])
+                        + self.input.take(u32::from(LENGTH_EXTRA[index]))?;
+                    let index = usize::from(codes.distances.decode(&mut self.input)?);
+                    if index >= DISTANCE_BASE.len() {
+                        return Err(invalid("invalid distance code"));
+                    }
+                    let distance = u32::from(DISTANCE_BASE[index])
+                        + self.input.take(u32::from(DISTANCE_EXTRA[index]))?;
+                    if distance as usize > self.written.min(WINDOW) {
+                        return Err(invalid("distance too far back"));
+                    }
+                    self.copy = (length as usize, distance as usize);
+                }
+            }
+        }
+    }
+
+    /// Adds bytes not written through the window to it and to the checksum
+    fn output(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.window[self.written % WINDOW] = byte;
+            self.written += 1;
+        }
+        self.crc = crc32(self.crc, bytes);
+    }
+
+    /// Checks the trailer of the member, returns whether another member follows
+    fn trailer(&mut self) -> io::Result<bool> {
+        self.input.align();
+        if self.input.take(32)? != !self.crc {
+            return Err(invalid("crc mismatch"));
+        }
+        if self.input.take(32)? != self.written as u32 {
+            return Err(invalid("length mismatch"));
+        }
+        self.input.has_more()
+    }
+}
+
+impl<R: BufRead> Read for GzipDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while !buf.is_empty() {
+            match mem::replace(&mut self.state, State::Done) {
+                State::Header => {
+                    self.header()?;
+                    self.state = State::Block;
+                }
+                State::Block => self.state = self.block()?,
+                State::Stored(left) => {
+                    let count = self.stored(buf, left)?;
+                    self.state = match left - count {
+                        0 => self.end_of_block(),
+                        left => State::Stored(left),
+                    };
+                    if count > 0 {
+                        return Ok(count);
+                    }
+                }
+                State::Compressed(codes) => {
+                    let (count, ended) = self.compressed(&codes, buf)?;
+                    self.state = if ended {
+                        self.end_of_block()
+                    } else {
+                        State::Compressed(codes)
+                    };
+                    if count > 0 {
+                        return Ok(count);
+                    }
+                }
+                State::Trailer => {
+                    if self.trailer()? {
+                        self.state = State::Header;
+                    }
+                }
+                State::Done => return Ok(0),
+            }
+        }
+        Ok(0)
+    }
+}
+
+impl<R> GzipDecoder<R> {
+    fn end_of_block(&self) -> State {
+        if self.last {
+            State::Trailer
+        } else {
+            State::Block
+        }
+    }
+}
+
+impl Codes {
+    fn fixed() -> Self {
+        let mut literals = [8; 288];
+        literals[144..256].fill(9);
+        literals[256..280].fill(7);
+        Codes {
+            literals: Huffman::new(&literals).expect("the fixed code is complete"),
+            distances: Huffman::new(&[5; 30]).expect("the fixed code is not oversubscribed"),
+        }
+    }
+}
+
+/// Canonical Huffman code, decoded through a table indexed by the next `bits` bits of input
+struct Huffman {
+    /// Symbol of every entry shifted left by 4, or'ed with the length of its code. Entries of
+    /// unused codes are 0.
+    table: Vec<u16>,
+    bits: u32,
+}
+
+impl Huffman {
+    /// Code of the symbols with the given code lengths, 0 for symbols without a code
+    fn new(lengths: &[u8]) -> io::Result<Self> {
+        let bits = lengths.iter().copied().max().unwrap_or(0).into();
+        let mut counts = [0u32; MAX_BITS as usize + 1];
+        for &length in lengths {
+            counts[usize::from(length)] += 1;
+        }
+        counts[0] = 0;
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = 2 * left - count as i32;
+            if left < 0 {
+                return Err(invalid("oversubscribed huffman code"));
+            }
+        }
+
+        let mut next = [0u32; MAX_BITS as usize + 2];
+        for length in 1..=MAX_BITS as usize {
+            next[length + 1] = (next[length] + counts[length]) << 1;
+        }
+        let mut table = vec![0; 1usize << bits];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length == 0 {
+                continue;
+            }
+            let length = u32::from(length);
+            let code = next[length as usize];
+            next[length as usize] += 1;
+            // Codes are packed starting with their most significant bit
+            let reversed = code.reverse_bits() >> (32 - length);
+            let entry = (symbol as u16) << 4 | length as u16;
+            for index in (reversed as usize..table.len()).step_by(1 << length) {
+                table[index] = entry;
+            }
+        }
+        Ok(Huffman { table, bits })
+    }
+
+    fn decode<R: BufRead>(&self, input: &mut BitReader<R>) -> io::Result<u16> {
+        let entry = self.table[input.peek(self.bits)? as usize];
+        if entry == 0 {
+            return Err(invalid("invalid huffman code"));
+        }
+        input.consume(u32::from(entry & 0xf))?;
+        Ok(entry >> 4)
+    }
+}
+
+/// Bits of the input, least significant bit of every byte first
+struct BitReader<R> {
+    inner: R,
+    bits: u64,
+    /// Bits of `bits` read from `inner` but not consumed yet
+    count: u32,
+}
+
+impl<R: BufRead> BitReader<R> {
+    fn refill(&mut self) -> io::Result<()> {
+        while self.count <= 56 {
+            let buf = self.inner.fill_buf()?;
+            if buf.is_empty() {
+                break;
+            }
+            let taken = buf.len().min(((64 - self.count) / 8) as usize);
+            for &byte in &buf[..taken] {
+                self.bits |= u64::from(byte) << self.count;
+                self.count += 8;
+            }
+            self.inner.consume(taken);
+        }
+        Ok(())
+    }
+
+    /// Next `count` bits without consuming them, padded with zeros at the end of the input
+    fn peek(&mut self, count: u32) -> io::Result<u32> {
+        if self.count < count {
+            self.refill()?;
+        }
+        Ok((self.bits & ((1 << count) - 1)) as u32)
+    }
+
+    fn consume(&mut self, count: u32) -> io::Result<()> {
+        if count > self.count {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "gzip input is truncated",
+            ));
+        }
+        self.bits >>= count;
+        self.count -= count;
+        Ok(())
+    }
+
+    /// Next `count` bits, at most 32
+    fn take(&mut self, count: u32) -> io::Result<u32> {
+        let bits = self.peek(count)?;
+        self.consume(count)?;
+        Ok(bits)
+    }
+
+    fn skip_bytes(&mut self, count: usize) -> io::Result<()> {
+        for _ in 0..count {
+            self.take(8)?;
+        }
+        Ok(())
+    }
+
+    /// Drops the bits left of the current byte
+    fn align(&mut self) {
+        let partial = self.count % 8;
+        self.bits >>= partial;
+        self.count -= partial;
+    }
+
+    fn has_more(&mut self) -> io::Result<bool> {
+        Ok(self.count > 0 || !self.inner.fill_buf()?.is_empty())
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid gzip input: {}", message),
+    )
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xedb88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+}
+
+/// CRC-32 of the gzip trailer, updating the inverted `crc` with `bytes`
+fn crc32(mut crc: u32, bytes: &[u8]) -> u32 {
+    for &byte in bytes {
+        crc = CRC_TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::gzip::GzipDecoder;
+        use std::io::{self, Read};
+
+        fn decompress(input: &[u8]) -> io::Result<Vec<u8>> {
+            let mut output = Vec::new();
+            GzipDecoder::new(input).read_to_end(&mut output)?;
+            Ok(output)
+        }
+
+        // Hello, hello, hello! compressed by python's gzip module at levels 0 and 9, and at level 9
+        // with a file name
+        const STORED: &[u8] = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x00\xff\x01\x14\x00\xeb\xff\
+            Hello, hello, hello!\xac\xeb.\xe4\x14\x00\x00\x00";
+        const FIXED: &[u8] = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\xff\xf3H\xcd\xc9\xc9\xd7Q\
+            \xc8@\xa2\x14\x01\xac\xeb.\xe4\x14\x00\x00\x00";
+        const NAMED: &[u8] = b"\x1f\x8b\x08\x08\x00\x00\x00\x00\x02\xffa.csv\x00\xf3H\xcd\xc9\
+            \xc9\xd7Q\xc8@\xa2\x14\x01\xac\xeb.\xe4\x14\x00\x00\x00";
+
+        #[test]
+        fn stored_and_fixed_blocks_are_inflated() {
+            for input in [STORED, FIXED, NAMED] {
+                assert_eq!(decompress(input).unwrap(), b"Hello, hello, hello!");
+            }
+            let members = [FIXED, STORED].concat();
+            assert_eq!(
+                decompress(&members).unwrap(),
+                b"Hello, hello, hello!Hello, hello, hello!"
+            );
+        }
+
+        #[test]
+        fn dynamic_blocks_are_inflated() {
+            assert_eq!(
+                decompress(include_bytes!("../assets/test_with_disputes.csv.gz")).unwrap(),
+                include_bytes!("../assets/test_with_disputes.csv")
+            );
+        }
+
+        #[test]
+        fn corrupt_input_is_an_error() {
+            let mut crc = FIXED.to_vec();
+            crc[FIXED.len() - 8] ^= 1;
+            let errors = [
+                decompress(&crc).unwrap_err(),
+                decompress(&FIXED[..FIXED.len() - 3]).unwrap_err(),
+                decompress(&[FIXED, b"trailing"].concat()).unwrap_err(),
+            ];
+            assert_eq!(errors[0].to_string(), "invalid gzip input: crc mismatch");
+            assert_eq!(errors[1].kind(), io::ErrorKind::UnexpectedEof);
+            assert_eq!(
+                errors[2].to_string(),
+                "invalid gzip input: not in gzip format"
+            );
+        }
+    }
+}
@@ -0,0 +1,1053 @@
+//! Server side of cleartext HTTP/2 as specified in RFC 9113, with the header compression of RFC
+//! 7541, carrying the gRPC interface of [`crate::grpc`]. Clients connect with prior knowledge,
+//! as gRPC clients do without TLS. A [`Connection`] hands the requests and request bodies of its
+//! streams out as [`Event`]s in the order their frames arrive and queues the responses, writing
+//! them as far as the flow control windows of the client allow.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, BufRead, Write};
+
+/// First bytes a client sends, ahead of its settings
+pub(crate) const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+const NO_ERROR: u32 = 0x0;
+const PROTOCOL_ERROR: u32 = 0x1;
+const FLOW_CONTROL_ERROR: u32 = 0x3;
+const STREAM_CLOSED: u32 = 0x5;
+const FRAME_SIZE_ERROR: u32 = 0x6;
+const REFUSED_STREAM: u32 = 0x7;
+const COMPRESSION_ERROR: u32 = 0x9;
+
+/// Window of every stream and of the connection until the settings say otherwise
+const DEFAULT_WINDOW: i64 = 65_535;
+const MAX_WINDOW: i64 = (1 << 31) - 1;
+/// Largest frame either side sends until the other allows larger ones. Frames sent by clients
+/// are never allowed to be larger.
+const DEFAULT_FRAME_SIZE: usize = 16_384;
+/// Streams a client may have open at once, further ones are refused
+const MAX_STREAMS: u32 = 128;
+/// Largest header block of a request, as compressed
+const MAX_HEADER_BLOCK: usize = 64 * 1024;
+
+/// Header names and values of a request, in the order they were sent
+pub(crate) type Headers = Vec<(String, String)>;
+
+/// What a client sent on one of its streams
+#[derive(Debug, PartialEq)]
+pub(crate) enum Event {
+    /// A new stream, `end` if the request has no body
+    Request {
+        stream: u32,
+        headers: Headers,
+        end: bool,
+    },
+    /// Part of the body of a request, `end` with its last part
+    Data {
+        stream: u32,
+        data: Vec<u8>,
+        end: bool,
+    },
+    /// The client cancelled the stream, which takes no further response
+    Reset(u32),
+}
+
+/// Type, flags, stream and payload of a frame
+type Frame = (u8, u8, u32, Vec<u8>);
+
+/// Connection failing as a whole, reported to the client with a GOAWAY frame
+enum Failure {
+    Io(io::Error),
+    Protocol(u32, &'static str),
+}
+
+impl From<io::Error> for Failure {
+    fn from(err: io::Error) -> Self {
+        Failure::Io(err)
+    }
+}
+
+/// State of a stream until both sides ended it
+struct Stream {
+    /// Bytes of data the client accepts on the stream
+    window: i64,
+    /// Whether the client ended its side
+    remote_closed: bool,
+    queued: VecDeque<Outbound>,
+}
+
+enum Outbound {
+    /// Encoded header block
+    Headers(Vec<u8>, bool),
+    /// Data with the bytes already written, ending the stream if set
+    Data(Vec<u8>, usize, bool),
+}
+
+/// Server side of a connection reading frames from `R` and writing them to `W`
+pub(crate) struct Connection<R, W> {
+    reader: R,
+    writer: W,
+    decoder: Decoder,
+    /// Bytes of data the client accepts on the connection
+    window: i64,
+    /// Window of new streams, from the settings of the client
+    initial_window: i64,
+    /// Largest frame the client accepts
+    max_frame: usize,
+    streams: BTreeMap<u32, Stream>,
+    /// Highest stream the client opened, earlier ones without state are closed
+    last_stream: u32,
+}
+
+impl<R: BufRead, W: Write> Connection<R, W> {
+    /// Reads the preface of the client and sends the settings of the server
+    pub(crate) fn accept(mut reader: R, mut writer: W) -> io::Result<Self> {
+        let mut preface = [0; PREFACE.len()];
+        reader.read_exact(&mut preface)?;
+        if preface != PREFACE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not an HTTP/2 connection preface",
+            ));
+        }
+        let mut settings = Vec::new();
+        for (id, value) in [
+            (SETTINGS_MAX_CONCURRENT_STREAMS, MAX_STREAMS),
+            (SETTINGS_MAX_HEADER_LIST_SIZE, MAX_HEADER_BLOCK as u32),
+        ] {
+            settings.extend(id.to_be_bytes());
+            settings.extend(value.to_be_bytes());
+        }
+        write_frame(&mut writer, SETTINGS, 0, 0, &settings)?;
+        writer.flush()?;
+        Ok(Connection {
+            reader,
+            writer,
+            decoder: Decoder::default(),
+            window: DEFAULT_WINDOW,
+            initial_window: DEFAULT_WINDOW,
+            max_frame: DEFAULT_FRAME_SIZE,
+            streams: BTreeMap::new(),
+            last_stream: 0,
+        })
+    }
+
+    /// Next request or part of a request body, answering the frames of the connection itself in
+    /// the meantime. `None` once the client closed the connection.
+    pub(crate) fn next_event(&mut self) -> io::Result<Option<Event>> {
+        loop {
+            self.flush()?;
+            match self.read_event() {
+                Ok(Some(Some(event))) => return Ok(Some(event)),
+                Ok(Some(None)) => {}
+                Ok(None) => return Ok(None),
+                Err(Failure::Io(err)) => return Err(err),
+                Err(Failure::Protocol(code, message)) => {
+                    let mut payload = self.last_stream.to_be_bytes().to_vec();
+                    payload.extend(code.to_be_bytes());
+                    payload.extend(message.as_bytes());
+                    // The client may not listen anymore, the connection is closed either way
+                    let _ = write_frame(&mut self.writer, GOAWAY, 0, 0, &payload)
+                        .and_then(|()| self.writer.flush());
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("HTTP/2 protocol error: {}", message),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Queues the headers of a response, or its trailers after its data
+    pub(crate) fn send_headers(&mut self, stream: u32, headers: &[(&str, &str)], end: bool) {
+        let mut block = Vec::new();
+        for (name, value) in headers {
+            encode_header(&mut block, name, value);
+        }
+        if let Some(state) = self.streams.get_mut(&stream) {
+            state.queued.push_back(Outbound::Headers(block, end));
+        }
+    }
+
+    /// Queues data of a response
+    pub(crate) fn send_data(&mut self, stream: u32, data: Vec<u8>, end: bool) {
+        if let Some(state) = self.streams.get_mut(&stream) {
+            state.queued.push_back(Outbound::Data(data, 0, end));
+        }
+    }
+
+    /// Writes what was queued as far as the windows allow. Streams whose response ended are
+    /// closed, cancelling their request if the client did not end it yet.
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        let Connection {
+            writer,
+            window,
+            max_frame,
+            streams,
+            ..
+        } = self;
+        let mut closed = Vec::new();
+        for (&id, stream) in streams.iter_mut() {
+            while let Some(outbound) = stream.queued.front_mut() {
+                match outbound {
+                    Outbound::Headers(block, end) => {
+                        write_headers(writer, id, block, *end, *max_frame)?;
+                        if *end {
+                            closed.push((id, stream.remote_closed));
+                        }
+                        stream.queued.pop_front();
+                    }
+                    Outbound::Data(data, written, end) => {
+                        let allowed = (*window).min(stream.window).max(0) as usize;
+                        let count = (data.len() - *written).min(allowed).min(*max_frame);
+                        let last = *written + count == data.len();
+                        if count == 0 && !(last && *end) {
+                            break;
+                        }
+                        let flags = if last && *end { END_STREAM } else { 0 };
+                        write_frame(writer, DATA, flags, id, &data[*written..*written + count])?;
+                        *window -= count as i64;
+                        stream.window -= count as i64;
+                        *written += count;
+                        if !last {
+                            continue;
+                        }
+                        if *end {
+                            closed.push((id, stream.remote_closed));
+                        }
+                        stream.queued.pop_front();
+                    }
+                }
+            }
+        }
+        for (id, remote_closed) in closed {
+            streams.remove(&id);
+            // The rest of the request is of no use anymore
+            if !remote_closed {
+                write_frame(writer, RST_STREAM, 0, id, &NO_ERROR.to_be_bytes())?;
+            }
+        }
+        writer.flush()
+    }
+
+    /// Handles the next frame: `None` at the end of the connection, `Some(None)` for frames
+    /// that are not an event
+    fn read_event(&mut self) -> Result<Option<Option<Event>>, Failure> {
+        let Some((kind, flags, id, payload)) = self.read_frame()? else {
+            return Ok(None);
+        };
+        match kind {
+            DATA => self.data(flags, id, payload).map(Some),
+            HEADERS => self.headers(flags, id, payload).map(Some),
+            PRIORITY if id == 0 => Err(Failure::Protocol(PROTOCOL_ERROR, "priority of stream 0")),
+            RST_STREAM => {
+                if id == 0 || payload.len() != 4 {
+                    return Err(Failure::Protocol(PROTOCOL_ERROR, "invalid stream reset"));
+                }
+                Ok(Some(self.streams.remove(&id).map(|_| Event::Reset(id))))
+            }
+            SETTINGS => self.settings(flags, id, &payload).map(|()| Some(None)),
+            PING => {
+                if id != 0 || payload.len() != 8 {
+                    return Err(Failure::Protocol(FRAME_SIZE_ERROR, "invalid ping"));
+                }
+                if flags & ACK == 0 {
+                    write_frame(&mut self.writer, PING, ACK, 0, &payload)?;
+                }
+                Ok(Some(None))
+            }
+            GOAWAY => Ok(None),
+            WINDOW_UPDATE => self.window_update(id, &payload).map(|()| Some(None)),
+            PUSH_PROMISE | CONTINUATION => {
+                Err(Failure::Protocol(PROTOCOL_ERROR, "unexpected frame"))
+            }
+            // Priorities are not followed and frames of unknown types are ignored
+            _ => Ok(Some(None)),
+        }
+    }
+
+    /// Next frame, `None` at the end of the connection
+    fn read_frame(&mut self) -> Result<Option<Frame>, Failure> {
+        if self.reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        let mut header = [0; 9];
+        self.reader.read_exact(&mut header)?;
+        let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        if length > DEFAULT_FRAME_SIZE {
+            return Err(Failure::Protocol(FRAME_SIZE_ERROR, "frame too large"));
+        }
+        let id = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff;
+        let mut payload = vec![0; length];
+        self.reader.read_exact(&mut payload)?;
+        Ok(Some((header[3], header[4], id, payload)))
+    }
+
+    fn data(&mut self, flags: u8, id: u32, payload: Vec<u8>) -> Result<Option<Event>, Failure> {
+        if id == 0 || id > self.last_stream {
+            return Err(Failure::Protocol(PROTOCOL_ERROR, "data on an idle stream"));
+        }
+        // Padding counts towards the windows like the data
+        let credit = payload.len() as u32;
+        if credit > 0 {
+            write_frame(&mut self.writer, WINDOW_UPDATE, 0, 0, &credit.to_be_bytes())?;
+        }
+        let data = unpad(flags, payload)?;
+        let end = flags & END_STREAM != 0;
+        // Streams closed by a response or a reset take no more data
+        let Some(stream) = self.streams.get_mut(&id) else {
+            return Ok(None);
+        };
+        if stream.remote_closed {
+            write_frame(
+                &mut self.writer,
+                RST_STREAM,
+                0,
+                id,
+                &STREAM_CLOSED.to_be_bytes(),
+            )?;
+            self.streams.remove(&id);
+            return Ok(Some(Event::Reset(id)));
+        }
+        stream.remote_closed = end;
+        if credit > 0 && !end {
+            write_frame(
+                &mut self.writer,
+                WINDOW_UPDATE,
+                0,
+                id,
+                &credit.to_be_bytes(),
+            )?;
+        }
+        Ok(Some(Event::Data {
+            stream: id,
+            data,
+            end,
+        }))
+    }
+
+    fn headers(&mut self, flags: u8, id: u32, payload: Vec<u8>) -> Result<Option<Event>, Failure> {
+        if id == 0 {
+            return Err(Failure::Protocol(PROTOCOL_ERROR, "headers of stream 0"));
+        }
+        let mut block = unpad(flags, payload)?;
+        if flags & PRIORITY_FLAG != 0 {
+            if block.len() < 5 {
+                return Err(Failure::Protocol(FRAME_SIZE_ERROR, "headers too short"));
+            }
+            block.drain(..5);
+        }
+        let mut ended = flags & END_HEADERS != 0;
+        while !ended {
+            match self.read_frame()? {
+                Some((CONTINUATION, flags, continued, payload)) if continued == id => {
+                    block.extend(payload);
+                    ended = flags & END_HEADERS != 0;
+                }
+                _ => return Err(Failure::Protocol(PROTOCOL_ERROR, "expected continuation")),
+            }
+            if block.len() > MAX_HEADER_BLOCK {
+                return Err(Failure::Protocol(PROTOCOL_ERROR, "header block too large"));
+            }
+        }
+        // Decoded even for streams that are refused, the table of the decoder depends on it
+        let headers = self
+            .decoder
+            .decode(&block)
+            .map_err(|message| Failure::Protocol(COMPRESSION_ERROR, message))?;
+        let end = flags & END_STREAM != 0;
+
+        if let Some(stream) = self.streams.get_mut(&id) {
+            // Trailers of the request
+            if stream.remote_closed || !end {
+                return Err(Failure::Protocol(
+                    PROTOCOL_ERROR,
+                    "headers after the request",
+                ));
+            }
+            stream.remote_closed = true;
+            return Ok(Some(Event::Data {
+                stream: id,
+                data: Vec::new(),
+                end,
+            }));
+        }
+        if id <= self.last_stream {
+            return Ok(None);
+        }
+        if id.is_multiple_of(2) {
+            return Err(Failure::Protocol(
+                PROTOCOL_ERROR,
+                "stream opened by the server",
+            ));
+        }
+        self.last_stream = id;
+        if self.streams.len() >= MAX_STREAMS as usize {
+            write_frame(
+                &mut self.writer,
+                RST_STREAM,
+                0,
+                id,
+                &REFUSED_STREAM.to_be_bytes(),
+            )?;
+            return Ok(None);
+        }
+        self.streams.insert(
+            id,
+            Stream {
+                window: self.initial_window,
+                remote_closed: end,
+                queued: VecDeque::new(),
+            },
+        );
+        Ok(Some(Event::Request {
+            stream: id,
+            headers,
+            end,
+        }))
+    }
+
+    fn settings(&mut self, flags: u8, id: u32, payload: &[u8]) -> Result<(), Failure> {
+        if id != 0 {
+            return Err(Failure::Protocol(PROTOCOL_ERROR, "settings of a stream"));
+        }
+        if flags & ACK != 0 {
+            return match payload.is_empty() {
+                true => Ok(()),
+                false => Err(Failure::Protocol(
+                    FRAME_SIZE_ERROR,
+                    "settings ack with a payload",
+                )),
+            };
+        }
+        if !payload.len().is_multiple_of(6) {
+            return Err(Failure::Protocol(FRAME_SIZE_ERROR, "invalid settings"));
+        }
+        for setting in payload.chunks(6) {
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+            match u16::from_be_bytes([setting[0], setting[1]]) {
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    let value = i64::from(value);
+                    if value > MAX_WINDOW {
+                        return Err(Failure::Protocol(FLOW_CONTROL_ERROR, "window too large"));
+                    }
+                    for stream in self.streams.values_mut() {
+                        stream.window += value - self.initial_window;
+                    }
+                    self.initial_window = value;
+                }
+                SETTINGS_MAX_FRAME_SIZE => {
+                    if !(DEFAULT_FRAME_SIZE as u32..1 << 24).contains(&value) {
+                        return Err(Failure::Protocol(PROTOCOL_ERROR, "invalid max frame size"));
+                    }
+                    self.max_frame = value as usize;
+                }
+                // Header blocks written by the server never use the dynamic table, whatever its
+                // size
+                SETTINGS_HEADER_TABLE_SIZE => {}
+                _ => {}
+            }
+        }
+        write_frame(&mut self.writer, SETTINGS, ACK, 0, &[])?;
+        Ok(())
+    }
+
+    fn window_update(&mut self, id: u32, payload: &[u8]) -> Result<(), Failure> {
+        let [a, b, c, d] = payload else {
+            return Err(Failure::Protocol(FRAME_SIZE_ERROR, "invalid window update"));
+        };
+        let increment = i64::from(u32::from_be_bytes([*a, *b, *c, *d]) & 0x7fff_ffff);
+        if increment == 0 {
+            return Err(Failure::Protocol(PROTOCOL_ERROR, "window update of 0"));
+        }
+        let window = match id {
+            0 => &mut self.window,
+            id => match self.streams.get_mut(&id) {
+                Some(stream) => &mut stream.window,
+                None => return Ok(()),
+            },
+        };
+        *window += increment;
+        if *window > MAX_WINDOW {
+            return Err(Failure::Protocol(FLOW_CONTROL_ERROR, "window too large"));
+        }
+        Ok(())
+    }
+}
+
+/// Payload of a frame without its padding
+fn unpad(flags: u8, mut payload: Vec<u8>) -> Result<Vec<u8>, Failure> {
+    if flags & PADDED == 0 {
+        return Ok(payload);
+    }
+    let padding = usize::from(*payload.first().unwrap_or(&0));
+    if payload.is_empty() || padding >= payload.len() {
+        return Err(Failure::Protocol(PROTOCOL_ERROR, "invalid padding"));
+    }
+    payload.truncate(payload.len() - padding);
+    payload.remove(0);
+    Ok(payload)
+}
+
+fn write_frame(
+    writer: &mut impl Write,
+    kind: u8,
+    flags: u8,
+    stream: u32,
+    payload: &[u8],
+) -> io::Result<()> {
+    let length = (payload.len() as u32).to_be_bytes();
+    writer.write_all(&[length[1], length[2], length[3], kind, flags])?;
+    writer.write_all(&stream.to_be_bytes())?;
+    writer.write_all(payload)
+}
+
+/// Writes a header block in a HEADERS frame and as many CONTINUATION frames as it needs
+fn write_headers(
+    writer: &mut impl Write,
+    stream: u32,
+    block: &[u8],
+    end: bool,
+    max_frame: usize,
+) -> io::Result<()> {
+    let mut chunks = block.chunks(max_frame).peekable();
+    let mut kind = HEADERS;
+    let mut flags = if end { END_STREAM } else { 0 };
+    // An empty block still takes a frame
+    let first: &[u8] = chunks.next().unwrap_or(&[]);
+    let mut chunk = first;
+    loop {
+        let last = chunks.peek().is_none();
+        if last {
+            flags |= END_HEADERS;
+        }
+        write_frame(writer, kind, flags, stream, chunk)?;
+        match chunks.next() {
+            Some(next) => chunk = next,
+            None => return Ok(()),
+        }
+        kind = CONTINUATION;
+        flags = 0;
+    }
+}
+
+/// Header fields of the static table of HPACK, indexed from 1
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// Size of the dynamic table of HPACK until the client is told otherwise, which it never is
+const TABLE_SIZE: usize = 4096;
+
+/// Code lengths of the Huffman code of HPACK by symbol, 256 being the end of string. The code is
+/// canonical, so that the codes follow from the lengths.
+const HUFFMAN_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 30, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6, 5, 5,
+    5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10, 13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6, 15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6,
+    6, 5, 6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28, 20, 22, 20, 20, 22, 22, 22, 23, 22,
+    23, 23, 23, 23, 23, 24, 23, 24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24, 22,
+    21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23, 21, 21, 22, 21, 23, 22, 23, 23, 20,
+    22, 22, 22, 23, 22, 22, 23, 26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25, 19,
+    21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27, 20, 24, 20, 21, 22, 21, 21, 23, 22,
+    22, 25, 25, 24, 24, 26, 23, 26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26, 30,
+];
+
+const END_OF_STRING: u16 = 256;
+
+/// Decoder of the header blocks of one connection, with the dynamic table they build up
+pub(crate) struct Decoder {
+    /// Newest entry first
+    table: VecDeque<(String, String)>,
+    /// Size of the entries as HPACK counts it
+    size: usize,
+    max_size: usize,
+    huffman: Huffman,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder {
+            table: VecDeque::new(),
+            size: 0,
+            max_size: TABLE_SIZE,
+            huffman: Huffman::new(),
+        }
+    }
+}
+
+impl Decoder {
+    pub(crate) fn decode(&mut self, block: &[u8]) -> Result<Headers, &'static str> {
+        let mut headers = Vec::new();
+        let mut position = 0;
+        while let Some(&byte) = block.get(position) {
+            if byte & 0x80 != 0 {
+                let index = integer(block, &mut position, 7)?;
+                headers.push(self.entry(index)?);
+            } else if byte & 0x40 != 0 {
+                let header = self.literal(block, &mut position, 6)?;
+                self.insert(header.clone());
+                headers.push(header);
+            } else if byte & 0x20 != 0 {
+                let size = integer(block, &mut position, 5)?;
+                if size > TABLE_SIZE {
+                    return Err("table size update above the setting");
+                }
+                self.max_size = size;
+                self.evict(0);
+            } else {
+                // Without indexing or never indexed, which only matters to intermediaries
+                headers.push(self.literal(block, &mut position, 4)?);
+            }
+        }
+        Ok(headers)
+    }
+
+    /// Field at the index of the static table followed by the dynamic one
+    fn entry(&self, index: usize) -> Result<(String, String), &'static str> {
+        match index {
+            0 => Err("index 0"),
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Ok((name.to_string(), value.to_string()))
+            }
+            _ => self
+                .table
+                .get(index - 62)
+                .cloned()
+                .ok_or("index out of range"),
+        }
+    }
+
+    /// Literal field whose name is indexed with the given prefix, or follows if the index is 0
+    fn literal(
+        &self,
+        block: &[u8],
+        position: &mut usize,
+        prefix: u32,
+    ) -> Result<(String, String), &'static str> {
+        let name = match integer(block, position, prefix)? {
+            0 => self.string(block, position)?,
+            index => self.entry(index)?.0,
+        };
+        Ok((name, self.string(block, position)?))
+    }
+
+    fn string(&self, block: &[u8], position: &mut usize) -> Result<String, &'static str> {
+        let huffman = block.get(*position).ok_or("truncated string")? & 0x80 != 0;
+        let length = integer(block, position, 7)?;
+        let end = position
+            .checked_add(length)
+            .filter(|end| *end <= block.len())
+            .ok_or("truncated string")?;
+        let bytes = &block[*position..end];
+        *position = end;
+        let bytes = if huffman {
+            self.huffman.decode(bytes)?
+        } else {
+            bytes.to_vec()
+        };
+        String::from_utf8(bytes).map_err(|_| "header is not valid utf-8")
+    }
+
+    fn insert(&mut self, header: (String, String)) {
+        let size = header.0.len() + header.1.len() + 32;
+        self.evict(size);
+        // Entries larger than the table empty it and are not added
+        if size <= self.max_size {
+            self.size += size;
+            self.table.push_front(header);
+        }
+    }
+
+    /// Evicts the oldest entries until `size` more fit
+    fn evict(&mut self, size: usize) {
+        while self.size + size > self.max_size {
+            match self.table.pop_back() {
+                Some((name, value)) => self.size -= name.len() + value.len() + 32,
+                None => break,
+            }
+        }
+    }
+}
+
+/// Integer with a prefix of the given bits at `position`, advancing past it
+fn integer(block: &[u8], position: &mut usize, prefix: u32) -> Result<usize, &'static str> {
+    let mask = (1 << prefix) - 1;
+    let mut value = usize::from(*block.get(*position).ok_or("truncated integer")? & mask);
+    *position += 1;
+    if value < usize::from(mask) {
+        return Ok(value);
+    }
+    for shift in (0..28).step_by(7) {
+        let byte = *block.get(*position).ok_or("truncated integer")?;
+        *position += 1;
+        value += usize::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("integer too large")
+}
+
+fn encode_integer(block: &mut Vec<u8>, flags: u8, prefix: u32, mut value: usize) {
+    let mask = (1usize << prefix) - 1;
+    if value < mask {
+        block.push(flags | value as u8);
+        return;
+    }
+    block.push(flags | mask as u8);
+    value -= mask;
+    while value >= 0x80 {
+        block.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    block.push(value as u8);
+}
+
+/// Appends a header to the block, indexed if the static table holds it, as a literal without
+/// indexing otherwise
+pub(crate) fn encode_header(block: &mut Vec<u8>, name: &str, value: &str) {
+    if let Some(index) = STATIC_TABLE
+        .iter()
+        .position(|entry| *entry == (name, value))
+    {
+        return encode_integer(block, 0x80, 7, index + 1);
+    }
+    match STATIC_TABLE.iter().position(|(entry, _)| *entry == name) {
+        Some(index) => encode_integer(block, 0x00, 4, index + 1),
+        None => {
+            block.push(0x00);
+            encode_integer(block, 0x00, 7, name.len());
+            block.extend(name.as_bytes());
+        }
+    }
+    encode_integer(block, 0x00, 7, value.len());
+    block.extend(value.as_bytes());
+}
+
+/// Canonical Huffman code of HPACK, decoded a bit at a time
+struct Huffman {
+    /// First code of every length
+    first: [u32; 31],
+    /// Index in `symbols` of the symbol of the first code of every length
+    offsets: [usize; 31],
+    counts: [u32; 31],
+    /// Symbols ordered by their code
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new() -> Self {
+        let mut counts = [0u32; 31];
+        for &length in &HUFFMAN_LENGTHS {
+            counts[usize::from(length)] += 1;
+        }
+        let mut symbols: Vec<u16> = (0..=END_OF_STRING).collect();
+        symbols.sort_by_key(|&symbol| HUFFMAN_LENGTHS[usize::from(symbol)]);
+        let (mut first, mut offsets) = ([0u32; 31], [0usize; 31]);
+        let (mut code, mut offset) = (0, 0);
+        for length in 1..31 {
+            code = (code + counts[length - 1]) << 1;
+            offset += counts[length - 1] as usize;
+            first[length] = code;
+            offsets[length] = offset;
+        }
+        Huffman {
+            first,
+            offsets,
+            counts,
+            symbols,
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Vec<u8>, &'static str> {
+        let mut decoded = Vec::with_capacity(bytes.len() * 8 / 5);
+        let (mut code, mut length) = (0u32, 0usize);
+        for bit in bytes
+            .iter()
+            .flat_map(|&byte| (0..8).rev().map(move |shift| u32::from(byte >> shift) & 1))
+        {
+            code = code << 1 | bit;
+            length += 1;
+            if length > 30 {
+                return Err("invalid huffman code");
+            }
+            let index = code.wrapping_sub(self.first[length]);
+            if index < self.counts[length] {
+                match self.symbols[self.offsets[length] + index as usize] {
+                    END_OF_STRING => return Err("end of string in huffman data"),
+                    symbol => decoded.push(symbol as u8),
+                }
+                code = 0;
+                length = 0;
+            }
+        }
+        // Padding is a prefix of the end of string, which is all ones
+        if length > 7 || code != (1 << length) - 1 {
+            return Err("invalid huffman padding");
+        }
+        Ok(decoded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::http2::{encode_header, Connection, Decoder, Event, PREFACE};
+        use std::io::Cursor;
+
+        fn hex(hex: &str) -> Vec<u8> {
+            let hex: String = hex.split_whitespace().collect();
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+                .collect()
+        }
+
+        fn headers(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect()
+        }
+
+        #[test]
+        fn huffman_coded_requests_of_rfc_7541_are_decoded() {
+            // Requests of appendix C.4, sharing the dynamic table
+            let mut decoder = Decoder::default();
+            assert_eq!(
+                decoder
+                    .decode(&hex("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff"))
+                    .unwrap(),
+                headers(&[
+                    (":method", "GET"),
+                    (":scheme", "http"),
+                    (":path", "/"),
+                    (":authority", "www.example.com"),
+                ])
+            );
+            assert_eq!(
+                decoder
+                    .decode(&hex("8286 84be 5886 a8eb 1064 9cbf"))
+                    .unwrap(),
+                headers(&[
+                    (":method", "GET"),
+                    (":scheme", "http"),
+                    (":path", "/"),
+                    (":authority", "www.example.com"),
+                    ("cache-control", "no-cache"),
+                ])
+            );
+            assert_eq!(
+                decoder
+                    .decode(&hex(
+                        "8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf"
+                    ))
+                    .unwrap(),
+                headers(&[
+                    (":method", "GET"),
+                    (":scheme", "https"),
+                    (":path", "/index.html"),
+                    (":authority", "www.example.com"),
+                    ("custom-key", "custom-value"),
+                ])
+            );
+            assert_eq!(decoder.size, 164);
+            assert!(decoder.decode(&hex("41 83 f1 e3 c3")).is_err());
+        }
+
+        #[test]
+        fn encoded_headers_are_decoded() {
+            let mut block = Vec::new();
+            for (name, value) in [
+                (":status", "200"),
+                ("content-type", "application/grpc"),
+                ("grpc-message", &"x".repeat(200)),
+            ] {
+                encode_header(&mut block, name, value);
+            }
+            assert_eq!(block[0], 0x88);
+            assert_eq!(
+                Decoder::default().decode(&block).unwrap(),
+                headers(&[
+                    (":status", "200"),
+                    ("content-type", "application/grpc"),
+                    ("grpc-message", &"x".repeat(200)),
+                ])
+            );
+        }
+
+        fn frame(kind: u8, flags: u8, stream: u32, payload: &[u8]) -> Vec<u8> {
+            let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+            frame.extend([kind, flags]);
+            frame.extend(stream.to_be_bytes());
+            frame.extend(payload);
+            frame
+        }
+
+        /// Type, flags and stream of the frames written by the server
+        fn frames(mut output: &[u8]) -> Vec<(u8, u8, u32, Vec<u8>)> {
+            let mut frames = Vec::new();
+            while !output.is_empty() {
+                let length = u32::from_be_bytes([0, output[0], output[1], output[2]]) as usize;
+                let stream = u32::from_be_bytes([output[5], output[6], output[7], output[8]]);
+                frames.push((output[3], output[4], stream, output[9..9 + length].to_vec()));
+                output = &output[9 + length..];
+            }
+            frames
+        }
+
+        #[test]
+        fn responses_wait_for_the_window_of_the_client() {
+            let mut block = Vec::new();
+            encode_header(&mut block, ":method", "POST");
+            encode_header(&mut block, ":path", "/echo");
+            let mut input = PREFACE.to_vec();
+            // Windows of 10 bytes per stream
+            input.extend(frame(0x4, 0, 0, &[0, 4, 0, 0, 0, 10]));
+            input.extend(frame(0x1, 0x4, 1, &block));
+            input.extend(frame(0x0, 0x1, 1, b"hello"));
+            input.extend(frame(0x6, 0, 0, b"12345678"));
+            input.extend(frame(0x8, 0, 1, &16u32.to_be_bytes()));
+
+            let mut output = Vec::new();
+            let mut connection = Connection::accept(Cursor::new(input), &mut output).unwrap();
+            let mut events = Vec::new();
+            while let Some(event) = connection.next_event().unwrap() {
+                if let Event::Data {
+                    stream, end: true, ..
+                } = event
+                {
+                    connection.send_headers(stream, &[(":status", "200")], false);
+                    connection.send_data(stream, b"0123456789abcdef".to_vec(), false);
+                    connection.send_headers(stream, &[("grpc-status", "0")], true);
+                }
+                events.push(event);
+            }
+            connection.flush().unwrap();
+            drop(connection);
+
+            assert_eq!(
+                events,
+                [
+                    Event::Request {
+                        stream: 1,
+                        headers: headers(&[(":method", "POST"), (":path", "/echo")]),
+                        end: false,
+                    },
+                    Event::Data {
+                        stream: 1,
+                        data: b"hello".to_vec(),
+                        end: true,
+                    },
+                ]
+            );
+            let frames: Vec<_> = frames(&output)
+                .into_iter()
+                .map(|(kind, flags, stream, payload)| match kind {
+                    0x0 => (kind, flags, stream, payload),
+                    _ => (kind, flags, stream, Vec::new()),
+                })
+                .collect();
+            assert_eq!(
+                frames,
+                [
+                    // Settings of the server, then the ack of those of the client
+                    (0x4, 0, 0, Vec::new()),
+                    (0x4, 0x1, 0, Vec::new()),
+                    // Credit of the connection window, the stream ended
+                    (0x8, 0, 0, Vec::new()),
+                    (0x1, 0x4, 1, Vec::new()),
+                    (0x0, 0, 1, b"0123456789".to_vec()),
+                    (0x6, 0x1, 0, Vec::new()),
+                    (0x0, 0, 1, b"abcdef".to_vec()),
+                    (0x1, 0x5, 1, Vec::new()),
+                ]
+            );
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! Bookkeeping of inputs delivered at least once, such as a Kafka topic or a file read again
+//! after a restart, so that their transactions are applied exactly once.
+//!
+//! Every transaction read from such an input is executed at its [`InputPosition`]. The engine
+//! keeps the highest position it executed per source as a high-water mark and skips the
+//! transactions at or below it. Marks are stored in snapshots and in the write-ahead log entry
+//! of each transaction, so they are persisted atomically with the state change they describe:
+//! after a restart the engine knows exactly which deliveries it already applied, whether they
+//! were saved in the snapshot or replayed from the log.
+
+use std::collections::BTreeMap;
+
+/// Where a transaction was read from: the `index`th transaction of the message or row at
+/// `offset` of `source`. Offsets of a source must increase in the order they are delivered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputPosition {
+    pub source: String,
+    pub offset: u64,
+    pub index: u32,
+}
+
+impl InputPosition {
+    pub fn new<S: Into<String>>(source: S, offset: u64, index: u32) -> Self {
+        InputPosition {
+            source: source.into(),
+            offset,
+            index,
+        }
+    }
+}
+
+/// Highest position executed per source
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct HighWaterMarks {
+    marks: BTreeMap<String, (u64, u32)>,
+}
+
+impl HighWaterMarks {
+    pub(crate) fn get(&self, source: &str) -> Option<(u64, u32)> {
+        self.marks.get(source).copied()
+    }
+
+    /// Whether the transaction at `position` was executed already
+    pub(crate) fn includes(&self, position: &InputPosition) -> bool {
+        self.get(&position.source)
+            .is_some_and(|mark| (position.offset, position.index) <= mark)
+    }
+
+    /// Raises the mark of the source to `position`
+    pub(crate) fn advance(&mut self, position: &InputPosition) {
+        let mark = (position.offset, position.index);
+        match self.marks.get_mut(&position.source) {
+            Some(current) => *current = mark.max(*current),
+            None => {
+                self.marks.insert(position.source.clone(), mark);
+            }
+        }
+    }
+
+    /// Marks in order of source
+    pub(crate) fn iter(&self) -> impl Iterator<Item = InputPosition> + '_ {
+        self.marks
+            .iter()
+            .map(|(source, &(offset, index))| InputPosition::new(source.as_str(), offset, index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::ingest::{HighWaterMarks, InputPosition};
+
+        #[test]
+        fn marks_only_move_forward_per_source() {
+            let mut marks = HighWaterMarks::default();
+            marks.advance(&InputPosition::new("a", 4, 1));
+            marks.advance(&InputPosition::new("a", 3, 7));
+            marks.advance(&InputPosition::new("b", 0, 0));
+
+            assert_eq!(marks.get("a"), Some((4, 1)));
+            assert!(marks.includes(&InputPosition::new("a", 4, 0)));
+            assert!(marks.includes(&InputPosition::new("a", 2, 9)));
+            assert!(!marks.includes(&InputPosition::new("a", 4, 2)));
+            assert!(!marks.includes(&InputPosition::new("c", 0, 0)));
+            assert_eq!(marks.iter().count(), 2);
+        }
+    }
+}
@@ -0,0 +1,2414 @@
+use crate::account::ClientId;
+use crate::currency::Currency;
+use crate::error::{ConfigError, InputError};
+use crate::generate::Rng;
+use crate::gzip::{self, GzipDecoder};
+use crate::log;
+use crate::output::json_string;
+use crate::policy::PrecisionPolicy;
+use crate::timestamp::Timestamp;
+use crate::toml;
+use crate::transaction::{
+    fits_decimals, scale_to_decimals, Amount, Transaction, TransactionId, TransactionType,
+};
+use csv::{ReaderBuilder, StringRecord, Trim};
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::cmp::{self, Reverse};
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// Supported formats of transaction feeds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Csv,
+    /// One json object per line
+    Ndjson,
+}
+
+impl InputFormat {
+    /// Detects the format from the file extension, defaulting to csv
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("ndjson") | Some("jsonl") => InputFormat::Ndjson,
+            _ => InputFormat::Csv,
+        }
+    }
+
+    /// Extensions of the files picked from a directory of input files
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            InputFormat::Csv => &["csv"],
+            InputFormat::Ndjson => &["ndjson", "jsonl"],
+        }
+    }
+
+    /// Writes the transaction back as a row of this format, without a trailing newline. Metadata
+    /// is only written to ndjson, as fields of its own.
+    pub fn format_row(&self, transaction: &Transaction) -> String {
+        let amount = transaction.amount.map(|amount| amount.to_string());
+        let to = transaction.to_client_id.map(|to| to.to_string());
+        let currency = transaction.currency.map(|currency| currency.to_string());
+        let timestamp = transaction.timestamp.map(|timestamp| timestamp.to_string());
+        match self {
+            InputFormat::Csv => {
+                let mut fields = vec![
+                    transaction.transaction_type.to_string(),
+                    transaction.client_id.to_string(),
+                    transaction.transaction_id.to_string(),
+                    amount.unwrap_or_default(),
+                    to.unwrap_or_default(),
+                    currency.unwrap_or_default(),
+                    timestamp.unwrap_or_default(),
+                ];
+                while fields.last().is_some_and(String::is_empty) {
+                    fields.pop();
+                }
+                fields.join(",")
+            }
+            InputFormat::Ndjson => {
+                let mut row = format!(
+                    "{{\"type\":\"{}\",\"client\":{},\"tx\":{}",
+                    transaction.transaction_type, transaction.client_id, transaction.transaction_id
+                );
+                if let Some(amount) = amount {
+                    row.push_str(&format!(",\"amount\":{}", amount));
+                }
+                if let Some(to) = to {
+                    row.push_str(&format!(",\"to\":{}", to));
+                }
+                if let Some(currency) = currency {
+                    row.push_str(&format!(",\"currency\":{}", json_string(&currency)));
+                }
+                if let Some(timestamp) = timestamp {
+                    row.push_str(&format!(",\"timestamp\":{}", json_string(&timestamp)));
+                }
+                for (name, value) in transaction.sorted_metadata() {
+                    row.push_str(&format!(",{}:{}", json_string(name), json_string(value)));
+                }
+                row.push('}');
+                row
+            }
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "csv" => Ok(InputFormat::Csv),
+            "ndjson" => Ok(InputFormat::Ndjson),
+            _ => Err(format!("unknown input format '{}'", value)),
+        }
+    }
+}
+
+/// Whether transaction ids of an input must increase, for feeds that are supposed to be ordered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputOrdering {
+    /// Transactions are processed as they arrive
+    #[default]
+    Unordered,
+    /// Transactions arriving after a later one are rejected
+    Strict,
+    /// Up to the given number of transactions are held back and passed on in id order, so that
+    /// slightly shuffled feeds are put back in order. Transactions arriving too late to be
+    /// reordered are rejected.
+    Reorder(usize),
+}
+
+impl InputOrdering {
+    /// Transactions held back, `None` if ids are not checked at all
+    pub fn window(&self) -> Option<usize> {
+        match self {
+            InputOrdering::Unordered => None,
+            InputOrdering::Strict => Some(0),
+            InputOrdering::Reorder(window) => Some(*window),
+        }
+    }
+}
+
+impl FromStr for InputOrdering {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once(':') {
+            None if value == "unordered" => Ok(InputOrdering::Unordered),
+            None if value == "strict" => Ok(InputOrdering::Strict),
+            Some(("reorder", window)) => match window.parse() {
+                Ok(0) | Err(_) => Err(format!("invalid reordering window '{}'", window)),
+                Ok(window) => Ok(InputOrdering::Reorder(window)),
+            },
+            _ => Err(format!("unknown input ordering '{}'", value)),
+        }
+    }
+}
+
+/// Stream of transactions read from some input
+pub trait TransactionSource {
+    /// Returns `None` once the input is exhausted. Malformed rows are returned as errors and
+    /// reading can continue past them.
+    fn next_transaction(&mut self) -> Option<Result<Transaction, InputError>>;
+}
+
+impl<S: TransactionSource + ?Sized> TransactionSource for Box<S> {
+    fn next_transaction(&mut self) -> Option<Result<Transaction, InputError>> {
+        (**self).next_transaction()
+    }
+}
+
+/// Path read as stdin by [`open_source`]
+pub const STDIN: &str = "-";
+
+/// Opens a file, or stdin for [`STDIN`], as a source of transactions in the given format.
+/// Gzip input is decompressed as it is read and zstd input rejected up front rather than as a
+/// malformed first row.
+pub fn open_source<P: AsRef<Path>>(
+    path: P,
+    format: InputFormat,
+) -> Result<Box<dyn TransactionSource + Send>, InputError> {
+    open(
+        path.as_ref(),
+        format,
+        &ColumnMapping::default(),
+        AmountRules::default(),
+        false,
+        None,
+    )
+}
+
+/// Same as [`open_source`], adding the bytes read from the input to `read` as it is consumed,
+/// e.g. to report progress from another thread
+pub fn open_source_counting<P: AsRef<Path>>(
+    path: P,
+    format: InputFormat,
+    read: Arc<AtomicU64>,
+) -> Result<Box<dyn TransactionSource + Send>, InputError> {
+    open(
+        path.as_ref(),
+        format,
+        &ColumnMapping::default(),
+        AmountRules::default(),
+        false,
+        Some(read),
+    )
+}
+
+/// Same as [`open_source`], reading the columns under the names of `columns`, checking amounts
+/// against `amounts`, reading csv with a [`FastCsvSource`] if `fast_parse` and counting the
+/// bytes read in `read` if given
+pub fn open_source_with<P: AsRef<Path>>(
+    path: P,
+    format: InputFormat,
+    columns: &ColumnMapping,
+    amounts: AmountRules,
+    fast_parse: bool,
+    read: Option<Arc<AtomicU64>>,
+) -> Result<Box<dyn TransactionSource + Send>, InputError> {
+    open(path.as_ref(), format, columns, amounts, fast_parse, read)
+}
+
+fn open(
+    path: &Path,
+    format: InputFormat,
+    columns: &ColumnMapping,
+    amounts: AmountRules,
+    fast_parse: bool,
+    read: Option<Arc<AtomicU64>>,
+) -> Result<Box<dyn TransactionSource + Send>, InputError> {
+    let mut reader: Box<dyn Read + Send> = if path == Path::new(STDIN) {
+        Box::new(io::stdin())
+    } else if is_url(path) {
+        open_url(path)?
+    } else {
+        Box::new(File::open(path)?)
+    };
+    if let Some(read) = read {
+        reader = Box::new(CountingReader {
+            inner: reader,
+            read,
+        });
+    }
+    let reader = decompressed(BufReader::new(reader))?;
+    let options = ReaderOptions {
+        columns: columns.clone(),
+        amounts,
+    };
+    Ok(match format {
+        InputFormat::Csv if fast_parse => {
+            Box::new(FastCsvSource::new(reader).with_options(options))
+        }
+        InputFormat::Csv => Box::new(CsvSource::new(reader).with_options(options)),
+        InputFormat::Ndjson => Box::new(
+            NdjsonSource::new(reader)
+                .with_columns(options.columns)
+                .with_amounts(options.amounts),
+        ),
+    })
+}
+
+/// Whether the input path is the url of an object rather than a local file
+fn is_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| URL_SCHEMES.iter().any(|scheme| path.starts_with(scheme)))
+}
+
+/// Schemes of the input paths read from object storage
+const URL_SCHEMES: [&str; 3] = ["s3://", "http://", "https://"];
+
+#[cfg(feature = "object-store")]
+fn open_url(path: &Path) -> io::Result<Box<dyn Read + Send>> {
+    crate::object_store::open(&path.to_string_lossy())
+}
+
+#[cfg(not(feature = "object-store"))]
+fn open_url(path: &Path) -> io::Result<Box<dyn Read + Send>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "reading {} needs the object-store feature, e.g. cargo build --features object-store",
+            path.display()
+        ),
+    ))
+}
+
+struct CountingReader<R> {
+    inner: R,
+    read: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.read.fetch_add(count as u64, Ordering::Relaxed);
+        Ok(count)
+    }
+}
+
+/// Files to read for an input path, in file name order: the files of a directory with an
+/// extension of the format, the files matching a pattern such as `dumps/*.csv` in its last
+/// component, where `*` matches any run of characters and `?` a single one, or else the path
+/// itself, e.g. the url of an object. Fails if a directory or pattern matches no file.
+pub fn discover_inputs<P: AsRef<Path>>(path: P, format: InputFormat) -> io::Result<Vec<PathBuf>> {
+    let path = path.as_ref();
+    if is_url(path) {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let pattern = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .filter(|name| !path.is_dir() && name.contains(['*', '?']));
+    let directory = if path.is_dir() {
+        path
+    } else if pattern.is_some() {
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        }
+    } else {
+        return Ok(vec![path.to_path_buf()]);
+    };
+    let matches = |file: &Path| match pattern {
+        Some(pattern) => file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| glob_matches(pattern.as_bytes(), name.as_bytes())),
+        None => file
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| format.extensions().contains(&ext)),
+    };
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let file = entry?.path();
+        if file.is_file() && matches(&file) {
+            files.push(file);
+        }
+    }
+    if files.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no input files found at {}", path.display()),
+        ));
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Whether `name` matches a pattern of literal bytes, `*` and `?`
+fn glob_matches(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| glob_matches(rest, &name[skip..])),
+        Some((b'?', rest)) => !name.is_empty() && glob_matches(rest, &name[1..]),
+        Some((byte, rest)) => name.first() == Some(byte) && glob_matches(rest, &name[1..]),
+    }
+}
+
+/// Reads several files one after the other as a single source, opening each when the previous
+/// one is exhausted. A file that cannot be opened is returned as an error in its place and
+/// reading continues with the next file. Line numbers of malformed rows are those within their
+/// file.
+pub struct MultiFileSource {
+    paths: std::vec::IntoIter<PathBuf>,
+    format: InputFormat,
+    /// File being read with the rows read from it so far
+    current: Option<(PathBuf, Box<dyn TransactionSource + Send>, u64)>,
+    /// Files opened so far
+    opened: usize,
+    columns: ColumnMapping,
+    amounts: AmountRules,
+    fast_parse: bool,
+    read: Option<Arc<AtomicU64>>,
+    namespaces: Option<TransactionNamespaces>,
+}
+
+impl MultiFileSource {
+    pub fn new(paths: Vec<PathBuf>, format: InputFormat) -> Self {
+        MultiFileSource {
+            paths: paths.into_iter(),
+            format,
+            current: None,
+            opened: 0,
+            columns: ColumnMapping::default(),
+            amounts: AmountRules::default(),
+            fast_parse: false,
+            read: None,
+            namespaces: None,
+        }
+    }
+
+    /// Reads the columns of every file under the names of `columns`
+    pub fn with_columns(mut self, columns: ColumnMapping) -> Self {
+        self.columns = columns;
+        self
+    }
+
+    /// Checks the amounts of every file against `amounts`
+    pub fn with_amounts(mut self, amounts: AmountRules) -> Self {
+        self.amounts = amounts;
+        self
+    }
+
+    /// Reads every csv file with a [`FastCsvSource`]
+    pub fn fast_parse(mut self) -> Self {
+        self.fast_parse = true;
+        self
+    }
+
+    /// Adds the bytes read from every file to `read`, as [`open_source_counting`] does
+    pub fn counting(mut self, read: Arc<AtomicU64>) -> Self {
+        self.read = Some(read);
+        self
+    }
+
+    /// Gives the transactions of every file ids of their own with [`TransactionNamespaces`], for
+    /// files reusing the same ids, e.g. the feeds of two acquirers
+    pub fn with_namespaces(mut self) -> Self {
+        self.namespaces = Some(TransactionNamespaces::default());
+        self
+    }
+}
+
+impl TransactionSource for MultiFileSource {
+    fn next_transaction(&mut self) -> Option<Result<Transaction, InputError>> {
+        loop {
+            if let Some((path, source, rows)) = self.current.as_mut() {
+                if let Some(mut result) = source.next_transaction() {
+                    *rows += 1;
+                    if let (Some(namespaces), Ok(transaction)) =
+                        (self.namespaces.as_mut(), result.as_mut())
+                    {
+                        let name = path.file_name().unwrap_or(path.as_os_str());
+                        let name = name.to_string_lossy();
+                        if let Err(message) = namespaces.assign(self.opened, &name, transaction) {
+                            result = Err(InputError::Malformed {
+                                line: *rows,
+                                message,
+                                row: String::new(),
+                            });
+                        }
+                    }
+                    return Some(result);
+                }
+                log::info(
+                    "Finished input file",
+                    &[("file", &path.display()), ("rows", rows)],
+                );
+                self.current = None;
+            }
+            let path = self.paths.next()?;
+            match open(
+                &path,
+                self.format,
+                &self.columns,
+                self.amounts,
+                self.fast_parse,
+                self.read.clone(),
+            ) {
+                Ok(source) => {
+                    self.current = Some((path, source, 0));
+                    self.opened += 1;
+                }
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+/// Metadata key under which [`TransactionNamespaces`] keeps the source of a transaction
+pub const SOURCE_KEY: &str = "source";
+
+/// Metadata key under which [`TransactionNamespaces`] keeps the id of a transaction in its source
+pub const SOURCE_TRANSACTION_KEY: &str = "source_tx";
+
+/// Ids of the transactions of several sources reusing the same ids, e.g. the feeds of two
+/// acquirers merged in one run. Every transaction is given the id allocated to its source and
+/// id when either was first seen, so that disputes, resolutions and chargebacks still match the
+/// transactions of their own source, never those of another. The name of the source and the
+/// original id are kept in the metadata of the transaction, and so in its ledger entry.
+///
+/// Ids are allocated from 1 in order of first sight and remembered for the whole run, one entry
+/// per transaction.
+#[derive(Debug, Default)]
+pub struct TransactionNamespaces {
+    ids: HashMap<(usize, TransactionId), TransactionId>,
+    last: TransactionId,
+}
+
+impl TransactionNamespaces {
+    /// Replaces the id of `transaction` read from the source numbered `source` and named `name`,
+    /// failing once every id has been allocated
+    pub fn assign(
+        &mut self,
+        source: usize,
+        name: &str,
+        transaction: &mut Transaction,
+    ) -> Result<(), String> {
+        let original = transaction.transaction_id;
+        let id = match self.ids.get(&(source, original)) {
+            Some(id) => *id,
+            None => match self.last.checked_add(1) {
+                Some(id) => {
+                    self.last = id;
+                    self.ids.insert((source, original), id);
+                    id
+                }
+                None => return Err("no transaction ids left for the input files".to_string()),
+            },
+        };
+        transaction.transaction_id = id;
+        transaction
+            .metadata
+            .insert(SOURCE_KEY.to_string(), name.to_string());
+        transaction
+            .metadata
+            .insert(SOURCE_TRANSACTION_KEY.to_string(), original.to_string());
+        Ok(())
+    }
+}
+
+/// Input decompressed if it starts with the magic bytes of gzip, as it is otherwise. Zstd input
+/// is recognised and rejected.
+fn decompressed<R: BufRead + Send + 'static>(
+    mut reader: R,
+) -> Result<Box<dyn BufRead + Send>, InputError> {
+    let start = reader.fill_buf()?;
+    if start.starts_with(&gzip::MAGIC) {
+        Ok(Box::new(BufReader::new(GzipDecoder::new(reader))))
+    } else if start.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Err(InputError::Compressed("zstd"))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Columns of csv input, in the order used when writing rows back. `to` is only needed by
+/// transfers, `currency` by multi-currency feeds and `timestamp` by feeds stating when
+/// transactions took effect; all three may be left out of the header.
+pub const CSV_COLUMNS: [&str; 7] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "to",
+    "currency",
+    "timestamp",
+];
+
+/// Row of csv input under the names of [`CSV_COLUMNS`], read into a [`Transaction`] once its
+/// amount is checked
+#[derive(Deserialize)]
+struct Row {
+    #[serde(rename = "type")]
+    transaction_type: TransactionType,
+    #[serde(rename = "client")]
+    client_id: ClientId,
+    #[serde(rename = "tx")]
+    transaction_id: TransactionId,
+    /// Read by the [`AmountFormat`] of the rules
+    amount: Option<String>,
+    #[serde(rename = "to", default)]
+    to_client_id: Option<ClientId>,
+    #[serde(default)]
+    currency: Option<Currency>,
+    #[serde(default)]
+    timestamp: Option<Timestamp>,
+}
+
+impl Row {
+    /// Transaction of the row, with the amount read and normalized by `amounts`. Without rules
+    /// the amount is read as a plain number and taken as it is, if it makes an [`Amount`].
+    fn into_transaction(self, amounts: Option<&AmountRules>) -> Result<Transaction, String> {
+        Ok(Transaction {
+            transaction_type: self.transaction_type,
+            client_id: self.client_id,
+            transaction_id: self.transaction_id,
+            amount: read_amount(self.transaction_type, self.amount.as_deref(), amounts)?,
+            to_client_id: self.to_client_id,
+            currency: self.currency,
+            timestamp: self.timestamp,
+            metadata: HashMap::new(),
+        })
+    }
+}
+
+/// Amount of a transaction of the type read and normalized by `amounts`, as a plain number taken
+/// as it is without rules
+fn read_amount(
+    transaction_type: TransactionType,
+    amount: Option<&str>,
+    amounts: Option<&AmountRules>,
+) -> Result<Option<Amount>, String> {
+    let format = amounts.map_or(AmountFormat::PLAIN, |amounts| amounts.format);
+    let value = amount.map(|amount| format.parse(amount)).transpose()?;
+    match amounts {
+        Some(amounts) => amounts.amount(transaction_type, value),
+        None => value
+            .map(|amount| Amount::of(transaction_type, amount))
+            .transpose(),
+    }
+}
+
+/// Columns every csv input must have
+const REQUIRED_COLUMNS: [&str; 3] = ["type", "client", "tx"];
+
+/// Names under which an input holds the columns of [`CSV_COLUMNS`], for upstream systems naming
+/// them differently, e.g. `transaction_id` for `tx`. Applies to csv headers and ndjson fields
+/// alike; columns without a mapping are read under their own name. Other columns are ignored
+/// unless they are captured into the [`metadata`](Transaction::metadata) of the transactions.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ColumnMapping {
+    /// Input name of every mapped column
+    names: BTreeMap<&'static str, String>,
+    /// Input names of the columns captured as metadata
+    captured: BTreeSet<String>,
+}
+
+impl ColumnMapping {
+    /// Reads `column` from the input column `name`. Fails for columns the engine does not know.
+    pub fn map(&mut self, column: &str, name: &str) -> Result<(), String> {
+        let column = CSV_COLUMNS
+            .into_iter()
+            .find(|known| *known == column)
+            .ok_or_else(|| {
+                format!(
+                    "unknown column '{}', expected one of {}",
+                    column,
+                    CSV_COLUMNS.join(", ")
+                )
+            })?;
+        self.names.insert(column, name.to_string());
+        Ok(())
+    }
+
+    /// Keeps the values of the input column `name` in the metadata of the transactions, under
+    /// the same name. Fails for the columns the engine reads itself.
+    pub fn capture(&mut self, name: &str) -> Result<(), String> {
+        if name.is_empty() || self.column(name) != name || CSV_COLUMNS.contains(&name) {
+            return Err(format!("column '{}' cannot be captured as metadata", name));
+        }
+        self.captured.insert(name.to_string());
+        Ok(())
+    }
+
+    /// Adds the mappings and captured columns of `other`, replacing the mappings of the same
+    /// columns
+    pub fn extend(&mut self, other: &ColumnMapping) {
+        self.names.extend(
+            other
+                .names
+                .iter()
+                .map(|(column, name)| (*column, name.clone())),
+        );
+        self.captured.extend(other.captured.iter().cloned());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.captured.is_empty()
+    }
+
+    /// Input names of the columns captured as metadata
+    pub fn captured(&self) -> impl Iterator<Item = &str> {
+        self.captured.iter().map(String::as_str)
+    }
+
+    /// Name of the column in the input
+    pub fn name<'a>(&'a self, column: &'a str) -> &'a str {
+        self.names.get(column).map_or(column, String::as_str)
+    }
+
+    /// Column read from the input column `name`. Columns mapped elsewhere are ignored so that
+    /// they cannot clash with the one they are read from.
+    fn column<'a>(&'a self, name: &'a str) -> &'a str {
+        match self.names.iter().find(|(_, mapped)| *mapped == name) {
+            Some((column, _)) => column,
+            None if self.names.contains_key(name) => "",
+            None => name,
+        }
+    }
+
+    /// Header of the input under the names of [`CSV_COLUMNS`]. Fails if a required column is
+    /// missing.
+    fn headers(&self, headers: &StringRecord) -> Result<StringRecord, InputError> {
+        let headers: StringRecord = headers.iter().map(|name| self.column(name)).collect();
+        match REQUIRED_COLUMNS
+            .into_iter()
+            .find(|column| !headers.iter().any(|header| header == *column))
+        {
+            Some(column) => Err(InputError::MissingColumn {
+                column,
+                name: self.name(column).to_string(),
+            }),
+            None => Ok(headers),
+        }
+    }
+
+    /// Reads the `[columns]` table of a configuration file, mapping columns to input names. Its
+    /// `metadata` key lists the columns to capture, separated by commas.
+    pub(crate) fn from_table(table: &toml::Table) -> Result<Self, ConfigError> {
+        let mut columns = ColumnMapping::default();
+        let mut captured = None;
+        for (key, entry) in &table.entries {
+            if key == "metadata" {
+                captured = Some((entry.as_str(key)?, entry));
+                continue;
+            }
+            columns
+                .map(key, entry.as_str(key)?)
+                .map_err(|err| entry.invalid(format!("{} in [columns]", err)))?;
+        }
+        // Captured once every mapping is known, as mapped names cannot be captured
+        if let Some((names, entry)) = captured {
+            for name in names.split(',') {
+                columns
+                    .capture(name.trim())
+                    .map_err(|err| entry.invalid(format!("{} in [columns]", err)))?;
+            }
+        }
+        Ok(columns)
+    }
+
+    /// Adds the captured fields among `fields` to the metadata of the transaction, leaving out
+    /// empty ones
+    fn capture_fields<'a>(
+        &self,
+        transaction: &mut Transaction,
+        fields: impl Iterator<Item = (&'a str, &'a str)>,
+    ) {
+        if self.captured.is_empty() {
+            return;
+        }
+        for (name, value) in fields {
+            if !value.is_empty() && self.captured.contains(name) {
+                transaction
+                    .metadata
+                    .insert(name.to_string(), value.to_string());
+            }
+        }
+    }
+}
+
+/// How the amounts of csv input are written, for feeds from locales with other separators, e.g.
+/// `1.234,56` with a decimal comma. The field must then be quoted or the comma would split it.
+/// Json numbers always have a decimal point, so ndjson input is read as [`AmountFormat::PLAIN`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AmountFormat {
+    pub decimal_separator: char,
+    /// Separator between groups of three digits before the decimal separator, left out when
+    /// reading
+    pub grouping_separator: Option<char>,
+}
+
+impl AmountFormat {
+    /// Decimal point without grouping, e.g. `1234.56`, the default
+    pub const PLAIN: AmountFormat = AmountFormat {
+        decimal_separator: '.',
+        grouping_separator: None,
+    };
+
+    /// Known locales by name, `plain` for [`AmountFormat::PLAIN`], `en` for `1,234.56`, `de`
+    /// for `1.234,56`, `fr` for `1 234,56` and `ch` for `1'234.56`
+    pub const LOCALES: [(&'static str, AmountFormat); 5] = [
+        ("plain", AmountFormat::PLAIN),
+        ("en", AmountFormat::with('.', ',')),
+        ("de", AmountFormat::with(',', '.')),
+        ("fr", AmountFormat::with(',', ' ')),
+        ("ch", AmountFormat::with('.', '\'')),
+    ];
+
+    const fn with(decimal_separator: char, grouping_separator: char) -> Self {
+        AmountFormat {
+            decimal_separator,
+            grouping_separator: Some(grouping_separator),
+        }
+    }
+
+    /// Number written in this format. Grouping separators are only accepted between groups of
+    /// three digits, so that `1.234` under a decimal comma is never taken for 1.234.
+    pub fn parse(&self, amount: &str) -> Result<f64, String> {
+        let invalid = |reason: &str| format!("amount '{}' {}", amount, reason);
+        let plain = if *self == AmountFormat::PLAIN {
+            Cow::Borrowed(amount)
+        } else {
+            let (integer, fraction) = match amount.split_once(self.decimal_separator) {
+                Some((integer, fraction)) => (integer, Some(fraction)),
+                None => (amount, None),
+            };
+            let mut plain = match self.grouping_separator {
+                Some(grouping) if integer.contains(grouping) => {
+                    let digits = integer.trim_start_matches(['-', '+']);
+                    let mut groups = digits.split(grouping);
+                    let leading = groups.next().unwrap_or_default().len();
+                    if !(1..=3).contains(&leading) || groups.any(|group| group.len() != 3) {
+                        return Err(invalid(&format!(
+                            "has misplaced grouping separators '{}'",
+                            grouping
+                        )));
+                    }
+                    integer.replace(grouping, "")
+                }
+                _ => integer.to_string(),
+            };
+            if let Some(fraction) = fraction {
+                if fraction.contains(self.decimal_separator) {
+                    return Err(invalid(&format!(
+                        "has more than one decimal separator '{}'",
+                        self.decimal_separator
+                    )));
+                }
+                if self
+                    .grouping_separator
+                    .is_some_and(|grouping| fraction.contains(grouping))
+                {
+                    return Err(invalid(
+                        "has grouping separators after the decimal separator",
+                    ));
+                }
+                plain.push('.');
+                plain.push_str(fraction);
+            }
+            Cow::Owned(plain)
+        };
+        plain.parse().map_err(|_| invalid("is not a number"))
+    }
+}
+
+impl Default for AmountFormat {
+    fn default() -> Self {
+        AmountFormat::PLAIN
+    }
+}
+
+impl FromStr for AmountFormat {
+    type Err = String;
+
+    /// The name of one of the [`AmountFormat::LOCALES`], or the decimal separator followed by
+    /// the grouping separator if any, e.g. `,.` for `1.234,56`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Some((_, format)) = Self::LOCALES.iter().find(|(name, _)| *name == value) {
+            return Ok(*format);
+        }
+        let separator = |c: &char| !c.is_alphanumeric() && !matches!(c, '-' | '+');
+        let mut chars = value.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some(decimal), grouping, None)
+                if separator(&decimal)
+                    && grouping
+                        .is_none_or(|grouping| separator(&grouping) && grouping != decimal) =>
+            {
+                Ok(AmountFormat {
+                    decimal_separator: decimal,
+                    grouping_separator: grouping,
+                })
+            }
+            _ => Err(format!(
+                "unknown amount format '{}', expected one of plain, en, de, fr, ch or the decimal \
+                 separator followed by the grouping separator",
+                value
+            )),
+        }
+    }
+}
+
+/// Bounds and format of the amounts read from the input. `f64` takes negative, non-finite, huge
+/// and overly precise amounts alike, so rows breaking these rules are malformed rather than
+/// applied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AmountRules {
+    /// Decimal places an amount may have, 4 by default and at most [`Amount::MAX_DECIMALS`].
+    /// Balances are written with as many.
+    pub max_decimals: u32,
+    /// Largest amount accepted, 10^12 by default
+    pub max_amount: f64,
+    /// What happens to amounts with more decimal places
+    pub precision: PrecisionPolicy,
+    /// How amounts are written in csv input
+    pub format: AmountFormat,
+}
+
+impl Default for AmountRules {
+    fn default() -> Self {
+        AmountRules {
+            max_decimals: 4,
+            max_amount: 1e12,
+            precision: PrecisionPolicy::default(),
+            format: AmountFormat::default(),
+        }
+    }
+}
+
+impl AmountRules {
+    /// Fails for amounts that are not finite, negative, above [`AmountRules::max_amount`] or
+    /// with more than [`AmountRules::max_decimals`] decimal places under
+    /// [`PrecisionPolicy::Reject`]
+    pub fn check(&self, amount: f64) -> Result<(), String> {
+        self.normalize(amount).map(|_| ())
+    }
+
+    /// Amount with at most [`AmountRules::max_decimals`] decimal places as the
+    /// [`AmountRules::precision`] policy decides, failing as [`AmountRules::check`]
+    pub fn normalize(&self, amount: f64) -> Result<f64, String> {
+        if !amount.is_finite() {
+            return Err(format!("amount {} is not a finite number", amount));
+        }
+        if amount < 0.0 {
+            return Err(format!("amount {} is negative", amount));
+        }
+        if amount > self.max_amount {
+            return Err(format!(
+                "amount {} is above the maximum of {}",
+                amount, self.max_amount
+            ));
+        }
+        if fits_decimals(amount, self.max_decimals) {
+            return Ok(amount);
+        }
+        let (scaled, tolerance) = scale_to_decimals(amount, self.max_decimals);
+        let places = match self.precision {
+            PrecisionPolicy::Reject => {
+                return Err(format!(
+                    "amount {} has more than {} decimal places",
+                    amount, self.max_decimals
+                ))
+            }
+            PrecisionPolicy::Truncate => scaled.floor(),
+            PrecisionPolicy::RoundHalfEven => {
+                let floor = scaled.floor();
+                match scaled - floor - 0.5 {
+                    half if half.abs() <= tolerance && floor % 2.0 == 0.0 => floor,
+                    half if half.abs() <= tolerance => floor + 1.0,
+                    _ => scaled.round(),
+                }
+            }
+        };
+        Ok(places / 10f64.powf(f64::from(self.max_decimals)))
+    }
+
+    /// Normalized amount of a transaction of the type, if any. Adjustments debit the account
+    /// with negative amounts, which are normalized like the positive ones.
+    pub(crate) fn amount(
+        &self,
+        transaction_type: TransactionType,
+        amount: Option<f64>,
+    ) -> Result<Option<Amount>, String> {
+        let amount = match amount {
+            Some(amount) if transaction_type == TransactionType::Adjustment => {
+                self.normalize(amount.abs())?.copysign(amount)
+            }
+            Some(amount) => self.normalize(amount)?,
+            None => return Ok(None),
+        };
+        Amount::of(transaction_type, amount).map(Some)
+    }
+}
+
+/// How the csv readers read their input: the names of the columns and the rules of the amounts
+#[derive(Debug, Clone, Default)]
+pub struct ReaderOptions {
+    pub columns: ColumnMapping,
+    pub amounts: AmountRules,
+}
+
+/// Reads transactions from csv with a `type, client, tx, amount[, to][, currency]` header, or
+/// the columns of a [`ColumnMapping`]
+pub struct CsvSource<R: Read> {
+    reader: csv::Reader<R>,
+    record: StringRecord,
+    options: ReaderOptions,
+    /// Whether the header was checked and put under the standard column names
+    mapped: bool,
+}
+
+impl<R: Read> CsvSource<R> {
+    pub fn new(reader: R) -> Self {
+        let reader = ReaderBuilder::new()
+            .trim(Trim::All)
+            // Require flexible since the "amount" field may sometimes be unspecified
+            .flexible(true)
+            .from_reader(reader);
+        CsvSource {
+            reader,
+            record: StringRecord::new(),
+            options: ReaderOptions::default(),
+            mapped: false,
+        }
+    }
+
+    /// Reads the input with `options` instead of the defaults
+    pub fn with_options(mut self, options: ReaderOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Reads the columns under the names of `columns`
+    pub fn with_columns(mut self, columns: ColumnMapping) -> Self {
+        self.options.columns = columns;
+        self
+    }
+
+    /// Checks amounts against `amounts` instead of the default rules
+    pub fn with_amounts(mut self, amounts: AmountRules) -> Self {
+        self.options.amounts = amounts;
+        self
+    }
+}
+
+impl<R: Read> TransactionSource for CsvSource<R> {
+    fn next_transaction(&mut self) -> Option<Result<Transaction, InputError>> {
+        match self.reader.read_record(&mut self.record) {
+            Ok(false) => return None,
+            Ok(true) => {}
+            Err(err) => return Some(Err(err.into())),
+        }
+        // Checked on the first row so that an input without rows is never refused. A missing
+        // column fails every row.
+        if !self.mapped {
+            let headers = self
+                .reader
+                .headers()
+                .map_err(InputError::from)
+                .and_then(|headers| self.options.columns.headers(headers));
+            match headers {
+                Ok(headers) => self.reader.set_headers(headers),
+                Err(err) => return Some(Err(err)),
+            }
+            self.mapped = true;
+        }
+        let headers = match self.reader.headers() {
+            Ok(headers) => headers,
+            Err(err) => return Some(Err(err.into())),
+        };
+        let ReaderOptions { columns, amounts } = &self.options;
+        let transaction = self
+            .record
+            .deserialize(Some(headers))
+            .map_err(InputError::from)
+            .and_then(|row: Row| match row.into_transaction(Some(amounts)) {
+                Ok(mut transaction) => {
+                    columns
+                        .capture_fields(&mut transaction, headers.iter().zip(self.record.iter()));
+                    #[cfg(feature = "chaos")]
+                    crate::chaos::hit(crate::chaos::FailPoint::AfterParse);
+                    Ok(transaction)
+                }
+                Err(message) => Err(InputError::Malformed {
+                    line: self.record.position().map_or(0, |position| position.line()),
+                    message,
+                    row: String::new(),
+                }),
+            });
+        Some(transaction.map_err(|mut err| {
+            if let InputError::Malformed { row, .. } = &mut err {
+                *row = csv_row(headers, &self.record);
+            }
+            err
+        }))
+    }
+}
+
+/// Writes the record as a csv line with the columns in the order of [`CSV_COLUMNS`], leaving
+/// out trailing empty fields
+fn csv_row(headers: &StringRecord, record: &StringRecord) -> String {
+    let mut fields: Vec<_> = CSV_COLUMNS
+        .iter()
+        .map(|column| {
+            headers
+                .iter()
+                .position(|header| header == *column)
+                .and_then(|index| record.get(index))
+                .unwrap_or_default()
+        })
+        .collect();
+    while fields.last() == Some(&"") {
+        fields.pop();
+    }
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    // Writing to memory cannot fail
+    let _ = writer.write_record(fields);
+    let line = writer.into_inner().unwrap_or_default();
+    String::from_utf8_lossy(&line).trim_end().to_string()
+}
+
+/// Reads transactions from csv input without serde, splitting every line on its commas and
+/// parsing the fields where they lie in the line, which is kept from one row to the next. Rows
+/// are read without allocating unless columns are captured as metadata or the row is malformed.
+/// Lines with quoted fields, rare in feeds, are read by the csv crate instead, so unlike with
+/// [`CsvSource`] a quoted field cannot span lines.
+pub struct FastCsvSource<R: BufRead> {
+    reader: R,
+    /// Line being read
+    line: Vec<u8>,
+    /// Lines read so far
+    number: u64,
+    /// Byte ranges of the fields of an unquoted line
+    fields: Vec<Range<usize>>,
+    /// Fields of a quoted line
+    record: StringRecord,
+    /// Header as read, then under the names of [`CSV_COLUMNS`] once checked on the first row
+    headers: Option<StringRecord>,
+    /// Field of every column of [`CSV_COLUMNS`], known once the header is checked
+    positions: Option<[Option<usize>; CSV_COLUMNS.len()]>,
+    options: ReaderOptions,
+}
+
+impl<R: BufRead> FastCsvSource<R> {
+    pub fn new(reader: R) -> Self {
+        FastCsvSource {
+            reader,
+            line: Vec::new(),
+            number: 0,
+            fields: Vec::new(),
+            record: StringRecord::new(),
+            headers: None,
+            positions: None,
+            options: ReaderOptions::default(),
+        }
+    }
+
+    /// Reads the input with `options` instead of the defaults, as [`CsvSource::with_options`]
+    pub fn with_options(mut self, options: ReaderOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+impl<R: BufRead> TransactionSource for FastCsvSource<R> {
+    fn next_transaction(&mut self) -> Option<Result<Transaction, InputError>> {
+        let number = loop {
+            self.line.clear();
+            match self.reader.read_until(b'\n', &mut self.line) {
+                Ok(0) => return None,
+                Ok(_) => self.number += 1,
+                Err(err) => return Some(Err(err.into())),
+            }
+            if !self.line.trim_ascii().is_empty() {
+                break self.number;
+            }
+        };
+        let malformed = |message: String, row: String| InputError::Malformed {
+            line: number,
+            message,
+            row,
+        };
+        let Ok(line) = std::str::from_utf8(&self.line) else {
+            return Some(Err(malformed(
+                "row is not valid utf-8".to_string(),
+                String::new(),
+            )));
+        };
+        let quoted = line.contains('"');
+        if quoted {
+            let mut reader = ReaderBuilder::new()
+                .has_headers(false)
+                .trim(Trim::All)
+                .flexible(true)
+                .from_reader(line.as_bytes());
+            if let Err(err) = reader.read_record(&mut self.record) {
+                return Some(Err(malformed(err.to_string(), line.trim().to_string())));
+            }
+        } else {
+            split_fields(line, &mut self.fields);
+        }
+        let (fields, record) = (&self.fields, &self.record);
+        let field = |index: usize| match quoted {
+            true => record.get(index).unwrap_or_default(),
+            false => fields
+                .get(index)
+                .map_or("", |range| line[range.clone()].trim()),
+        };
+        let field_count = if quoted { record.len() } else { fields.len() };
+
+        let Some(headers) = &mut self.headers else {
+            // Leaving out the byte order mark some tools start files with, as the csv crate does
+            let header = |index| match field(index) {
+                name if index == 0 => name.trim_start_matches('\u{feff}'),
+                name => name,
+            };
+            self.headers = Some((0..field_count).map(header).collect());
+            return self.next_transaction();
+        };
+        // Checked on the first row so that an input without rows is never refused. A missing
+        // column fails every row.
+        let positions = match self.positions {
+            Some(positions) => positions,
+            None => match self.options.columns.headers(headers) {
+                Ok(mapped) => {
+                    *headers = mapped;
+                    let positions = CSV_COLUMNS
+                        .map(|column| headers.iter().position(|header| header == column));
+                    self.positions = Some(positions);
+                    positions
+                }
+                Err(err) => return Some(Err(err)),
+            },
+        };
+        let ReaderOptions { columns, amounts } = &self.options;
+        let transaction = parse_fields(positions, field, amounts).map(|mut transaction| {
+            columns.capture_fields(
+                &mut transaction,
+                headers
+                    .iter()
+                    .enumerate()
+                    .map(|(index, header)| (header, field(index))),
+            );
+            transaction
+        });
+        #[cfg(feature = "chaos")]
+        if transaction.is_ok() {
+            crate::chaos::hit(crate::chaos::FailPoint::AfterParse);
+        }
+        Some(transaction.map_err(|message| {
+            let record: StringRecord = (0..field_count).map(field).collect();
+            malformed(message, csv_row(headers, &record))
+        }))
+    }
+}
+
+/// Puts the byte ranges of the comma separated fields of an unquoted line in `fields`
+fn split_fields(line: &str, fields: &mut Vec<Range<usize>>) {
+    fields.clear();
+    let mut start = 0;
+    for (index, byte) in line.bytes().enumerate() {
+        if byte == b',' {
+            fields.push(start..index);
+            start = index + 1;
+        }
+    }
+    fields.push(start..line.len());
+}
+
+/// Transaction of the fields of a row read by [`FastCsvSource`], given the field holding every
+/// column of [`CSV_COLUMNS`], with the amount read and normalized by `amounts`
+fn parse_fields<'a>(
+    positions: [Option<usize>; CSV_COLUMNS.len()],
+    field: impl Fn(usize) -> &'a str,
+    amounts: &AmountRules,
+) -> Result<Transaction, String> {
+    let [transaction_type, client_id, transaction_id, amount, to_client_id, currency, timestamp] =
+        positions.map(|position| position.map_or("", &field));
+    let required = |column: &str, value: &'a str| match value {
+        "" => Err(format!("missing field `{}`", column)),
+        value => Ok(value),
+    };
+    let optional = |value: &'a str| Some(value).filter(|value| !value.is_empty());
+    let client = |value: &str| {
+        value
+            .parse::<ClientId>()
+            .map_err(|_| format!("invalid client id '{}'", value))
+    };
+    let transaction_type: TransactionType = required("type", transaction_type)?.parse()?;
+    Ok(Transaction {
+        transaction_type,
+        client_id: client(required("client", client_id)?)?,
+        transaction_id: required("tx", transaction_id)?.parse()?,
+        amount: read_amount(transaction_type, optional(amount), Some(amounts))?,
+        to_client_id: optional(to_client_id).map(client).transpose()?,
+        currency: optional(currency).map(str::parse).transpose()?,
+        timestamp: optional(timestamp).map(str::parse).transpose()?,
+        metadata: HashMap::new(),
+    })
+}
+
+/// Reads transactions from newline-delimited json objects with the same field names as the
+/// csv header, e.g. `{"type": "deposit", "client": 1, "tx": 1, "amount": 1.5}`
+pub struct NdjsonSource<R: BufRead> {
+    lines: io::Lines<R>,
+    line: u64,
+    columns: ColumnMapping,
+    amounts: AmountRules,
+}
+
+impl<R: BufRead> NdjsonSource<R> {
+    pub fn new(reader: R) -> Self {
+        NdjsonSource {
+            lines: reader.lines(),
+            line: 0,
+            columns: ColumnMapping::default(),
+            amounts: AmountRules::default(),
+        }
+    }
+
+    /// Reads the fields under the names of `columns`
+    pub fn with_columns(mut self, columns: ColumnMapping) -> Self {
+        self.columns = columns;
+        self
+    }
+
+    /// Checks amounts against `amounts` instead of the default rules
+    pub fn with_amounts(mut self, amounts: AmountRules) -> Self {
+        self.amounts = amounts;
+        self
+    }
+}
+
+impl<R: BufRead> TransactionSource for NdjsonSource<R> {
+    fn next_transaction(&mut self) -> Option<Result<Transaction, InputError>> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(err) => return Some(Err(err.into())),
+            };
+            self.line += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let transaction = parse_json_fields(&line, &self.columns, self.amounts);
+            #[cfg(feature = "chaos")]
+            if transaction.is_ok() {
+                crate::chaos::hit(crate::chaos::FailPoint::AfterParse);
+            }
+            return Some(transaction.map_err(|message| InputError::Malformed {
+                line: self.line,
+                message,
+                row: line.trim().to_string(),
+            }));
+        }
+    }
+}
+
+/// Reads from another source on a background thread so that reading and parsing overlap with
+/// processing. At most `capacity` transactions are buffered; the reader thread blocks once the
+/// buffer is full, so memory stays bounded when the input is faster than the engine.
+pub struct ReadAheadSource {
+    receiver: Receiver<Result<Transaction, InputError>>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl ReadAheadSource {
+    pub fn spawn<S: TransactionSource + Send + 'static>(mut source: S, capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        let reader = thread::spawn(move || {
+            while let Some(result) = source.next_transaction() {
+                // Receiver was dropped, nobody is interested in the rest of the input
+                if sender.send(result).is_err() {
+                    break;
+                }
+            }
+        });
+        ReadAheadSource {
+            receiver,
+            reader: Some(reader),
+        }
+    }
+}
+
+impl TransactionSource for ReadAheadSource {
+    fn next_transaction(&mut self) -> Option<Result<Transaction, InputError>> {
+        match self.receiver.recv() {
+            Ok(result) => Some(result),
+            Err(_) => {
+                // Reader has finished, surface a panic of the reader thread as an io error
+                let reader = self.reader.take()?;
+                reader.join().err().map(|_| {
+                    Err(InputError::Io(io::Error::other(
+                        "input reader thread panicked",
+                    )))
+                })
+            }
+        }
+    }
+}
+
+/// Passes on only part of the rows of another source to iterate quickly on large inputs: the first
+/// `limit` rows, each kept at random with probability `rate`. Rows are sampled whether they are
+/// well-formed or not, and the same seed keeps the same rows of the same input. Disputes and
+/// other rows referring to transactions that were left out are rejected by the engine.
+pub struct SampledSource<S: TransactionSource> {
+    source: S,
+    /// Rows left to read, unlimited if `None`
+    remaining: Option<u64>,
+    rate: f64,
+    rng: Rng,
+}
+
+impl<S: TransactionSource> SampledSource<S> {
+    /// Passes on every row until limited or sampled
+    pub fn new(source: S) -> Self {
+        SampledSource {
+            source,
+            remaining: None,
+            rate: 1.0,
+            rng: Rng::new(1),
+        }
+    }
+
+    /// Stops after the first `limit` rows of the source, including those not sampled
+    pub fn with_limit(mut self, limit: u64) -> Self {
+        self.remaining = Some(limit);
+        self
+    }
+
+    /// Keeps each row with probability `rate`, between 0 and 1
+    pub fn with_sample(mut self, rate: f64, seed: u64) -> Self {
+        self.rate = rate;
+        self.rng = Rng::new(seed);
+        self
+    }
+}
+
+impl<S: TransactionSource> TransactionSource for SampledSource<S> {
+    fn next_transaction(&mut self) -> Option<Result<Transaction, InputError>> {
+        loop {
+            if let Some(remaining) = self.remaining.as_mut() {
+                *remaining = remaining.checked_sub(1)?;
+            }
+            let result = self.source.next_transaction()?;
+            // Drawn for every row so that sampled rows only depend on their position
+            if self.rng.unit() < self.rate {
+                return Some(result);
+            }
+        }
+    }
+}
+
+/// Checks that the ids of deposits, withdrawals and transfers read from another source increase,
+/// reordering them within a window. Disputes, resolves, chargebacks and administrative rows refer
+/// to earlier ids, they keep their place after the transactions read before them. Malformed rows
+/// are passed on right away.
+///
+/// A transaction whose id is lower than that of a transaction already passed on is returned as
+/// [`InputError::OutOfOrder`]. Repeated ids are passed on so that the duplicate policy of the
+/// engine applies to them.
+pub struct OrderedSource<S: TransactionSource> {
+    source: S,
+    window: usize,
+    pending: BinaryHeap<Reverse<Pending>>,
+    /// Transactions read so far, used to keep the input order between equal keys
+    arrived: u64,
+    /// Highest new id read from the source
+    highest_read: Option<TransactionId>,
+    /// New id of the last transaction passed on
+    last: Option<TransactionId>,
+    exhausted: bool,
+}
+
+impl<S: TransactionSource> OrderedSource<S> {
+    /// Holds back up to `window` transactions, 0 only checks the order
+    pub fn new(source: S, window: usize) -> Self {
+        OrderedSource {
+            source,
+            window,
+            pending: BinaryHeap::with_capacity(window + 1),
+            arrived: 0,
+            highest_read: None,
+            last: None,
+            exhausted: false,
+        }
+    }
+
+    fn hold(&mut self, transaction: Transaction) -> Result<(), InputError> {
+        let key = if transaction.transaction_type.has_new_id() {
+            if let Some(previous) = self.last.filter(|last| transaction.transaction_id < *last) {
+                return Err(InputError::OutOfOrder {
+                    transaction: Box::new(transaction),
+                    previous,
+                });
+            }
+            self.highest_read = self.highest_read.max(Some(transaction.transaction_id));
+            transaction.transaction_id
+        } else {
+            self.highest_read.unwrap_or_default()
+        };
+        self.arrived += 1;
+        self.pending.push(Reverse(Pending {
+            key,
+            arrival: self.arrived,
+            transaction,
+        }));
+        Ok(())
+    }
+
+    fn release(&mut self) -> Option<Result<Transaction, InputError>> {
+        let Reverse(pending) = self.pending.pop()?;
+        if pending.transaction.transaction_type.has_new_id() {
+            self.last = Some(pending.key);
+        }
+        Some(Ok(pending.transaction))
+    }
+}
+
+impl<S: TransactionSource> TransactionSource for OrderedSource<S> {
+    fn next_transaction(&mut self) -> Option<Result<Transaction, InputError>> {
+        while !self.exhausted && self.pending.len() <= self.window {
+            match self.source.next_transaction() {
+                None => self.exhausted = true,
+                Some(Ok(transaction)) => {
+                    if let Err(err) = self.hold(transaction) {
+                        return Some(Err(err));
+                    }
+                }
+                Some(Err(err)) => return Some(Err(err)),
+            }
+        }
+        self.release()
+    }
+}
+
+/// Transaction held back by an [`OrderedSource`], ordered by key and then arrival
+struct Pending {
+    key: TransactionId,
+    arrival: u64,
+    transaction: Transaction,
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        (self.key, self.arrival) == (other.key, other.arrival)
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        (self.key, self.arrival).cmp(&(other.key, other.arrival))
+    }
+}
+
+/// Deserializes a row holding `type,client,tx,amount[,to[,currency[,timestamp]]]` without a
+/// header, with the amount normalized by `amounts` if given
+pub(crate) fn deserialize_row(
+    row: &StringRecord,
+    amounts: Option<&AmountRules>,
+) -> Result<Transaction, String> {
+    let headers = StringRecord::from(CSV_COLUMNS.to_vec());
+    let row: StringRecord = row.iter().map(str::trim).collect();
+    row.deserialize::<Row>(Some(&headers))
+        .map_err(|err| err.to_string())?
+        .into_transaction(amounts)
+}
+
+/// Converts the object into a csv record so that the same serde rules apply to both formats.
+/// Amounts are checked against the default [`AmountRules`].
+pub(crate) fn parse_json_transaction(line: &str) -> Result<Transaction, String> {
+    parse_json_fields(line, &ColumnMapping::default(), AmountRules::default())
+}
+
+/// Same as [`parse_json_transaction`], reading the fields under the names of `columns` and
+/// checking amounts against `amounts`
+fn parse_json_fields(
+    line: &str,
+    columns: &ColumnMapping,
+    amounts: AmountRules,
+) -> Result<Transaction, String> {
+    let fields = parse_flat_object(line)?;
+    let headers: StringRecord = fields.iter().map(|(key, _)| columns.column(key)).collect();
+    let record: StringRecord = fields.iter().map(|(_, value)| value.as_str()).collect();
+    let amounts = AmountRules {
+        format: AmountFormat::PLAIN,
+        ..amounts
+    };
+    let mut transaction = record
+        .deserialize::<Row>(Some(&headers))
+        .map_err(|err| err.to_string())?
+        .into_transaction(Some(&amounts))?;
+    columns.capture_fields(
+        &mut transaction,
+        fields
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str())),
+    );
+    Ok(transaction)
+}
+
+/// Parses a json object whose values are scalars. Values are returned as their textual
+/// representation, with `null` mapped to an empty string.
+pub(crate) fn parse_flat_object(input: &str) -> Result<Vec<(String, String)>, String> {
+    let mut chars = input.trim().chars().peekable();
+    let mut fields = Vec::new();
+
+    let skip_whitespace = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+    };
+
+    if chars.next() != Some('{') {
+        return Err("expected a json object".to_string());
+    }
+    skip_whitespace(&mut chars);
+    if chars.peek() == Some(&'}') {
+        chars.next();
+    } else {
+        loop {
+            skip_whitespace(&mut chars);
+            if chars.next() != Some('"') {
+                return Err("expected a string key".to_string());
+            }
+            let key = parse_string(&mut chars)?;
+            skip_whitespace(&mut chars);
+            if chars.next() != Some(':') {
+                return Err(format!("expected ':' after key '{}'", key));
+            }
+            skip_whitespace(&mut chars);
+            let value = match chars.peek() {
+                Some('"') => {
+                    chars.next();
+                    parse_string(&mut chars)?
+                }
+                Some(_) => {
+                    let mut literal = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if c == ',' || c == '}' || c.is_whitespace() {
+                            break;
+                        }
+                        literal.push(c);
+                        chars.next();
+                    }
+                    match literal.as_str() {
+                        "null" => String::new(),
+                        "true" | "false" => literal,
+                        _ if literal.parse::<f64>().is_ok() => literal,
+                        _ => return Err(format!("invalid value for key '{}'", key)),
+                    }
+                }
+                None => return Err("unexpected end of object".to_string()),
+            };
+            fields.push((key, value));
+            skip_whitespace(&mut chars);
+            match chars.next() {
+                Some(',') => continue,
+                Some('}') => break,
+                _ => return Err("expected ',' or '}'".to_string()),
+            }
+        }
+    }
+
+    skip_whitespace(&mut chars);
+    if chars.next().is_some() {
+        return Err("unexpected content after object".to_string());
+    }
+    Ok(fields)
+}
+
+/// Parses the rest of a string whose opening quote was already consumed
+fn parse_string(chars: &mut impl Iterator<Item = char>) -> Result<String, String> {
+    let mut value = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(value),
+            Some('\\') => match chars.next() {
+                Some('"') => value.push('"'),
+                Some('\\') => value.push('\\'),
+                Some('/') => value.push('/'),
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some('r') => value.push('\r'),
+                Some('u') => {
+                    let code: String = chars.take(4).collect();
+                    let c = u32::from_str_radix(&code, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| format!("invalid unicode escape '\\u{}'", code))?;
+                    value.push(c);
+                }
+                _ => return Err("invalid escape sequence".to_string()),
+            },
+            Some(c) => value.push(c),
+            None => return Err("unterminated string".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::error::InputError;
+        use crate::input::{
+            decompressed, discover_inputs, glob_matches, AmountFormat, AmountRules, ColumnMapping,
+            CsvSource, FastCsvSource, InputFormat, InputOrdering, MultiFileSource, NdjsonSource,
+            OrderedSource, ReadAheadSource, ReaderOptions, SampledSource, TransactionSource,
+            SOURCE_KEY, SOURCE_TRANSACTION_KEY,
+        };
+        use crate::policy::PrecisionPolicy;
+        use crate::transaction::{Amount, Transaction, TransactionId, TransactionType};
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::sync::Arc;
+
+        fn read_all(mut source: impl TransactionSource) -> Vec<Result<Transaction, InputError>> {
+            std::iter::from_fn(|| source.next_transaction()).collect()
+        }
+
+        fn deposit() -> Transaction {
+            Transaction {
+                transaction_type: TransactionType::Deposit,
+                client_id: 1,
+                transaction_id: TransactionId(2),
+                amount: Some(Amount::unchecked(1.5)),
+                to_client_id: None,
+                currency: None,
+                timestamp: None,
+                metadata: Default::default(),
+            }
+        }
+
+        fn dispute() -> Transaction {
+            Transaction {
+                transaction_type: TransactionType::Dispute,
+                client_id: 1,
+                transaction_id: TransactionId(2),
+                amount: None,
+                to_client_id: None,
+                currency: None,
+                timestamp: None,
+                metadata: Default::default(),
+            }
+        }
+
+        #[test]
+        fn format_is_detected_from_extension() {
+            assert_eq!(InputFormat::from_path("a.ndjson"), InputFormat::Ndjson);
+            assert_eq!(InputFormat::from_path("a.jsonl"), InputFormat::Ndjson);
+            assert_eq!(InputFormat::from_path("a.csv"), InputFormat::Csv);
+            assert_eq!(InputFormat::from_path("a"), InputFormat::Csv);
+        }
+
+        #[test]
+        fn csv_source_reads_rows_with_and_without_amount() {
+            let input = "type, client, tx, amount\ndeposit, 1, 2, 1.5\ndispute, 1, 2\n";
+
+            let transactions = read_all(CsvSource::new(input.as_bytes()));
+
+            assert_eq!(transactions, vec![Ok(deposit()), Ok(dispute())]);
+        }
+
+        #[test]
+        fn transfers_read_destination_from_to_column() {
+            let input =
+                "type, client, tx, amount, to\ntransfer, 1, 3, 0.5, 2\ndeposit, 1, 2, 1.5,\n";
+
+            let transactions = read_all(CsvSource::new(input.as_bytes()));
+
+            assert_eq!(
+                transactions,
+                vec![
+                    Ok(Transaction {
+                        transaction_type: TransactionType::Transfer,
+                        client_id: 1,
+                        transaction_id: TransactionId(3),
+                        amount: Some(Amount::unchecked(0.5)),
+                        to_client_id: Some(2),
+                        currency: None,
+                        timestamp: None,
+                        metadata: Default::default(),
+                    }),
+                    Ok(deposit())
+                ]
+            );
+        }
+
+        fn mapping(mappings: &[(&str, &str)]) -> ColumnMapping {
+            let mut columns = ColumnMapping::default();
+            for (column, name) in mappings {
+                columns.map(column, name).unwrap();
+            }
+            columns
+        }
+
+        #[test]
+        fn mapped_columns_are_read_from_their_input_names() {
+            let columns = mapping(&[("tx", "transaction_id"), ("client", "client_id")]);
+            // An unmapped "tx" column would clash with the mapped one and is ignored
+            let csv = "type, client_id, transaction_id, amount, tx\n\
+                       deposit, 1, 2, 1.5, 9\n\
+                       dispute, 1, 2,,\n";
+            let ndjson =
+                "{\"type\":\"deposit\",\"client_id\":1,\"transaction_id\":2,\"amount\":1.5}\n";
+
+            let transactions =
+                read_all(CsvSource::new(csv.as_bytes()).with_columns(columns.clone()));
+            assert_eq!(transactions, vec![Ok(deposit()), Ok(dispute())]);
+            let transactions = read_all(NdjsonSource::new(ndjson.as_bytes()).with_columns(columns));
+            assert_eq!(transactions, vec![Ok(deposit())]);
+            assert!(mapping(&[]).map("id", "transaction_id").is_err());
+        }
+
+        #[test]
+        fn missing_required_columns_are_named() {
+            let input = "type, client_id, tx, amount\ndeposit, 1, 2, 1.5\n";
+            let columns = mapping(&[("tx", "transaction_id")]);
+
+            let unmapped = read_all(CsvSource::new(input.as_bytes()));
+            let mapped = read_all(
+                CsvSource::new("type, client, tx\ndeposit, 1, 2\n".as_bytes())
+                    .with_columns(columns.clone()),
+            );
+            let empty = read_all(CsvSource::new("type, client\n".as_bytes()).with_columns(columns));
+
+            let err = unmapped[0].as_ref().unwrap_err();
+            assert!(err.is_fatal());
+            assert_eq!(err.to_string(), "input has no 'client' column");
+            assert_eq!(
+                mapped[0].as_ref().unwrap_err().to_string(),
+                "input has no 'transaction_id' column, from which 'tx' is read"
+            );
+            // Inputs without rows are never refused
+            assert!(empty.is_empty());
+        }
+
+        #[test]
+        fn amounts_breaking_the_rules_are_malformed() {
+            let rules = AmountRules::default();
+            for amount in [0.0, 1.5, 0.0001, 12345678.1234, 1e12] {
+                assert_eq!(rules.check(amount), Ok(()), "{}", amount);
+            }
+            for (amount, message) in [
+                (-1.0, "amount -1 is negative"),
+                (f64::NAN, "amount NaN is not a finite number"),
+                (f64::INFINITY, "amount inf is not a finite number"),
+                (
+                    2e12,
+                    "amount 2000000000000 is above the maximum of 1000000000000",
+                ),
+                (0.12345, "amount 0.12345 has more than 4 decimal places"),
+            ] {
+                assert_eq!(rules.check(amount), Err(message.to_string()));
+            }
+
+            let input = "type, client, tx, amount\n\
+                         deposit, 1, 2, 1.5\n\
+                         deposit, 1, 3, NaN\n\
+                         withdrawal, 1, 4, 1.25\n";
+            let strict = AmountRules {
+                max_decimals: 1,
+                max_amount: 10.0,
+                ..AmountRules::default()
+            };
+            let transactions = read_all(CsvSource::new(input.as_bytes()).with_amounts(strict));
+
+            assert_eq!(transactions[0], Ok(deposit()));
+            match &transactions[1] {
+                Err(InputError::Malformed { line, row, .. }) => {
+                    assert_eq!(*line, 3);
+                    assert_eq!(row, "deposit,1,3,NaN");
+                }
+                other => panic!("expected a malformed row, got {:?}", other),
+            }
+            assert!(matches!(
+                transactions[2],
+                Err(InputError::Malformed { line: 4, .. })
+            ));
+            let ndjson = "{\"type\":\"deposit\",\"client\":1,\"tx\":2,\"amount\":-1.5}\n";
+            assert!(matches!(
+                read_all(NdjsonSource::new(ndjson.as_bytes()))[0],
+                Err(InputError::Malformed { line: 1, .. })
+            ));
+
+            // Only adjustments can debit with a negative amount
+            let adjustments = "type, client, tx, amount\n\
+                               adjustment, 1, 2, -1.5\n\
+                               adjustment, 1, 3, -0.12345\n";
+            let transactions = read_all(CsvSource::new(adjustments.as_bytes()));
+            assert_eq!(
+                transactions[0].as_ref().unwrap().amount,
+                Amount::signed(-1.5).ok()
+            );
+            assert!(matches!(
+                transactions[1],
+                Err(InputError::Malformed { line: 3, .. })
+            ));
+        }
+
+        #[test]
+        fn overly_precise_amounts_follow_the_precision_policy() {
+            let rules = |precision| AmountRules {
+                max_decimals: 2,
+                precision,
+                ..AmountRules::default()
+            };
+            let truncate = rules(PrecisionPolicy::Truncate);
+            let round = rules(PrecisionPolicy::RoundHalfEven);
+            for (amount, truncated, rounded) in [
+                (1.23, 1.23, 1.23),
+                (1.2399, 1.23, 1.24),
+                (0.125, 0.12, 0.12),
+                (0.135, 0.13, 0.14),
+                (2.00000001, 2.0, 2.0),
+                (12345.678, 12345.67, 12345.68),
+            ] {
+                assert_eq!(truncate.normalize(amount), Ok(truncated), "{}", amount);
+                assert_eq!(round.normalize(amount), Ok(rounded), "{}", amount);
+            }
+            assert!(rules(PrecisionPolicy::Reject).normalize(1.234).is_err());
+
+            let input = "type, client, tx, amount
+                         deposit, 1, 2, 1.00009
+                         adjustment, 1, 3, -0.125
+";
+            let transactions = read_all(CsvSource::new(input.as_bytes()).with_amounts(truncate));
+            assert_eq!(
+                transactions[0].as_ref().unwrap().amount,
+                Amount::new(1.0).ok()
+            );
+            assert_eq!(
+                transactions[1].as_ref().unwrap().amount,
+                Amount::signed(-0.12).ok()
+            );
+        }
+
+        #[test]
+        fn amounts_are_read_in_the_format_of_the_input() {
+            let de: AmountFormat = "de".parse().unwrap();
+            for (amount, expected) in [
+                ("1.234,56", 1234.56),
+                ("1,5", 1.5),
+                ("12", 12.0),
+                ("1.234.567", 1234567.0),
+                ("-1.000,25", -1000.25),
+            ] {
+                assert_eq!(de.parse(amount), Ok(expected), "{}", amount);
+            }
+            for (amount, message) in [
+                ("1.5", "amount '1.5' has misplaced grouping separators '.'"),
+                (
+                    "1234.567",
+                    "amount '1234.567' has misplaced grouping separators '.'",
+                ),
+                (
+                    "1,2,3",
+                    "amount '1,2,3' has more than one decimal separator ','",
+                ),
+                (
+                    "1,234.5",
+                    "amount '1,234.5' has grouping separators after the decimal separator",
+                ),
+                ("1,5x", "amount '1,5x' is not a number"),
+            ] {
+                assert_eq!(de.parse(amount), Err(message.to_string()));
+            }
+            assert_eq!("plain".parse(), Ok(AmountFormat::PLAIN));
+            assert_eq!(
+                ",".parse(),
+                Ok(AmountFormat {
+                    decimal_separator: ',',
+                    grouping_separator: None,
+                })
+            );
+            assert_eq!(
+                "'.".parse::<AmountFormat>().unwrap().parse("1.000'5"),
+                Ok(1000.5)
+            );
+            for invalid in ["", "xx", ",,", "1,", ",.;", "ru"] {
+                assert!(invalid.parse::<AmountFormat>().is_err(), "{}", invalid);
+            }
+
+            let input = "type, client, tx, amount\n\
+                         deposit, 1, 2,\"1,5\"\n\
+                         deposit, 1, 3, 1.5\n\
+                         dispute, 1, 2,\n";
+            let rules = AmountRules {
+                format: de,
+                ..AmountRules::default()
+            };
+            let transactions = read_all(CsvSource::new(input.as_bytes()).with_amounts(rules));
+            assert_eq!(transactions[0], Ok(deposit()));
+            assert!(matches!(
+                &transactions[1],
+                Err(InputError::Malformed { line: 3, message, .. })
+                    if message == "amount '1.5' has misplaced grouping separators '.'"
+            ));
+            assert_eq!(transactions[2], Ok(dispute()));
+            // Json numbers are plain whatever the format
+            let ndjson = "{\"type\":\"deposit\",\"client\":1,\"tx\":2,\"amount\":1.5}\n";
+            assert_eq!(
+                read_all(NdjsonSource::new(ndjson.as_bytes()).with_amounts(rules)),
+                vec![Ok(deposit())]
+            );
+        }
+
+        #[test]
+        fn captured_columns_are_kept_as_metadata() {
+            let mut columns = mapping(&[("tx", "transaction_id")]);
+            columns.capture("merchant").unwrap();
+            columns.capture("memo").unwrap();
+            let csv = "type, client, transaction_id, amount, merchant, memo, other\n\
+                       deposit, 1, 2, 1.5,\"ACME, Inc.\",, x\n";
+            let ndjson = "{\"type\":\"deposit\",\"client\":1,\"transaction_id\":2,\"amount\":1.5,\
+                          \"merchant\":\"ACME, Inc.\",\"memo\":null,\"other\":\"x\"}\n";
+            let mut expected = deposit();
+            expected
+                .metadata
+                .insert("merchant".to_string(), "ACME, Inc.".to_string());
+
+            let transactions =
+                read_all(CsvSource::new(csv.as_bytes()).with_columns(columns.clone()));
+            assert_eq!(transactions, vec![Ok(expected.clone())]);
+            let transactions = read_all(NdjsonSource::new(ndjson.as_bytes()).with_columns(columns));
+            assert_eq!(transactions, vec![Ok(expected.clone())]);
+            assert_eq!(
+                InputFormat::Ndjson.format_row(&expected),
+                "{\"type\":\"deposit\",\"client\":1,\"tx\":2,\"amount\":1.5,\
+                 \"merchant\":\"ACME, Inc.\"}"
+            );
+            for column in ["amount", "transaction_id", ""] {
+                assert!(mapping(&[("tx", "transaction_id")])
+                    .capture(column)
+                    .is_err());
+            }
+        }
+
+        #[test]
+        fn fast_csv_source_reads_the_rows_of_the_csv_source() {
+            let mut columns = mapping(&[("tx", "transaction_id")]);
+            columns.capture("merchant").unwrap();
+            let wide = "\u{feff}client, type, transaction_id, amount, to, currency, timestamp, merchant\r\n\
+                        1, deposit, 2, 1.5,,,,\r\n\
+                        \r\n\
+                        1,transfer,3,0.5,2,eur,2024-03-01,\"ACME, Inc.\"\n\
+                        1, transfer, 4, 0.5, x,,,\n\
+                        1, deposit, 5, 1.5,, euro,,\n";
+            let narrow = "type, client, transaction_id, amount\n\
+                          deposit, 1, 2, 1.5\n\
+                          dispute, 1, 2\n\
+                          gift, 1, 4, 1.5\n\
+                          deposit,, 5, 1.5\n\
+                          deposit, -1, 6, 1\n\
+                          deposit, 1, 7, 1.23456\n\
+                          withdrawal, 1, 8,\"-1\"\n\
+                          withdrawal, 1, 9, \"1.5\"\n";
+            for input in [wide, narrow] {
+                let expected =
+                    read_all(CsvSource::new(input.as_bytes()).with_columns(columns.clone()));
+                let options = ReaderOptions {
+                    columns: columns.clone(),
+                    ..ReaderOptions::default()
+                };
+                let transactions =
+                    read_all(FastCsvSource::new(input.as_bytes()).with_options(options));
+
+                assert_eq!(transactions.len(), expected.len());
+                assert_eq!(
+                    transactions.iter().filter(|result| result.is_ok()).count(),
+                    2
+                );
+                for (transaction, expected) in transactions.iter().zip(&expected) {
+                    match (transaction, expected) {
+                        (Ok(transaction), Ok(expected)) => assert_eq!(transaction, expected),
+                        (
+                            Err(InputError::Malformed { line, row, .. }),
+                            Err(InputError::Malformed {
+                                line: expected_line,
+                                row: expected_row,
+                                ..
+                            }),
+                        ) => assert_eq!((line, row), (expected_line, expected_row)),
+                        other => panic!("unexpected {:?}", other),
+                    }
+                }
+            }
+
+            // A missing column fails every row
+            let input = "type, client, amount\ndeposit, 1, 1.5\ndeposit, 1, 1.5\n";
+            let transactions = read_all(FastCsvSource::new(input.as_bytes()));
+            assert!(transactions.iter().all(|result| matches!(
+                result,
+                Err(InputError::MissingColumn { column: "tx", .. })
+            )));
+            assert_eq!(transactions.len(), 2);
+            assert!(read_all(FastCsvSource::new("type, client, tx\n".as_bytes())).is_empty());
+        }
+
+        #[test]
+        fn ndjson_source_reads_objects() {
+            let input = "{\"type\": \"deposit\", \"client\": 1, \"tx\": 2, \"amount\": 1.5}\n\
+                         \n\
+                         {\"tx\":2,\"client\":1,\"type\":\"dispute\",\"amount\":null}\n";
+
+            let transactions = read_all(NdjsonSource::new(input.as_bytes()));
+
+            assert_eq!(transactions, vec![Ok(deposit()), Ok(dispute())]);
+        }
+
+        #[test]
+        fn ndjson_source_reports_malformed_lines_and_continues() {
+            let input = "{\"type\": \"deposit\", \"client\": 1, \"tx\": 2, \"amount\": 1.5\n\
+                         {\"type\": \"refund\", \"client\": 1, \"tx\": 2}\n\
+                         {\"type\": \"dispute\", \"client\": 1, \"tx\": 2}\n";
+
+            let transactions = read_all(NdjsonSource::new(input.as_bytes()));
+
+            assert!(matches!(
+                transactions[0],
+                Err(InputError::Malformed { line: 1, .. })
+            ));
+            assert!(matches!(
+                transactions[1],
+                Err(InputError::Malformed { line: 2, .. })
+            ));
+            assert_eq!(transactions[2], Ok(dispute()));
+        }
+
+        #[test]
+        fn malformed_csv_row_is_returned_in_column_order() {
+            let input = "client, type, tx, amount\n\
+                         1, deposit, 2, 1.5\n\
+                         1, gift, 3,\"1,5\"\n";
+
+            let transactions = read_all(CsvSource::new(input.as_bytes()));
+
+            assert_eq!(transactions[0], Ok(deposit()));
+            match &transactions[1] {
+                Err(InputError::Malformed { line, row, .. }) => {
+                    assert_eq!(*line, 3);
+                    assert_eq!(row, "gift,1,3,\"1,5\"");
+                }
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+
+        #[test]
+        fn read_ahead_source_yields_rows_in_order() {
+            let input: String = std::iter::once("type, client, tx, amount\n".to_string())
+                .chain((0..100).map(|tx| format!("deposit, 1, {}, 1.0\n", tx)))
+                .collect();
+
+            let transactions = read_all(ReadAheadSource::spawn(
+                CsvSource::new(std::io::Cursor::new(input)),
+                1,
+            ));
+
+            let ids: Vec<_> = transactions
+                .into_iter()
+                .map(|transaction| transaction.unwrap().transaction_id.0)
+                .collect();
+            assert_eq!(ids, (0..100).collect::<Vec<_>>());
+        }
+
+        #[test]
+        fn sampled_source_keeps_the_same_rows_for_a_seed() {
+            let input: String = std::iter::once("type, client, tx, amount\n".to_string())
+                .chain((0..1000).map(|tx| format!("deposit, 1, {}, 1.0\n", tx)))
+                .collect();
+            let ids = |source: SampledSource<CsvSource<&[u8]>>| -> Vec<_> {
+                read_all(source)
+                    .into_iter()
+                    .map(|transaction| transaction.unwrap().transaction_id.0)
+                    .collect()
+            };
+            let source = || SampledSource::new(CsvSource::new(input.as_bytes()));
+
+            assert_eq!(ids(source().with_limit(3)), [0, 1, 2]);
+            let sampled = ids(source().with_sample(0.1, 42));
+            assert!((50..150).contains(&sampled.len()), "{}", sampled.len());
+            assert_eq!(ids(source().with_sample(0.1, 42)), sampled);
+            assert_ne!(ids(source().with_sample(0.1, 7)), sampled);
+            // Rows left out count towards the limit
+            let limited = ids(source().with_limit(500).with_sample(0.1, 42));
+            assert_eq!(limited, sampled[..limited.len()]);
+            assert!(limited.iter().all(|tx| *tx < 500));
+            assert!(sampled[limited.len()] >= 500);
+        }
+
+        #[test]
+        fn gzip_input_is_decompressed() {
+            let read = |input: &'static [u8]| -> Result<Vec<u8>, InputError> {
+                let mut output = Vec::new();
+                decompressed(input)?.read_to_end(&mut output)?;
+                Ok(output)
+            };
+            assert_eq!(
+                read(include_bytes!("../assets/test_with_disputes.csv.gz")),
+                Ok(include_bytes!("../assets/test_with_disputes.csv").to_vec())
+            );
+            assert_eq!(read(b"type,client"), Ok(b"type,client".to_vec()));
+            assert_eq!(
+                read(b"\x28\xb5\x2f\xfd").unwrap_err(),
+                InputError::Compressed("zstd")
+            );
+        }
+
+        fn rows(input: &str) -> CsvSource<&[u8]> {
+            CsvSource::new(input.as_bytes())
+        }
+
+        #[test]
+        fn ordered_source_reorders_within_the_window() {
+            let input = "type, client, tx, amount\n\
+                         deposit, 1, 2, 1.0\n\
+                         deposit, 1, 1, 1.0\n\
+                         dispute, 1, 2,\n\
+                         withdrawal, 1, 4, 1.0\n\
+                         deposit, 1, 3, 1.0\n\
+                         deposit, 1, 5, 1.0\n";
+
+            let transactions: Vec<_> = read_all(OrderedSource::new(rows(input), 2))
+                .into_iter()
+                .map(|transaction| {
+                    let transaction = transaction.unwrap();
+                    (transaction.transaction_type, transaction.transaction_id.0)
+                })
+                .collect();
+
+            // The dispute stays after the deposits read before it but ahead of later ones
+            assert_eq!(
+                transactions,
+                vec![
+                    (TransactionType::Deposit, 1),
+                    (TransactionType::Deposit, 2),
+                    (TransactionType::Dispute, 2),
+                    (TransactionType::Deposit, 3),
+                    (TransactionType::Withdrawal, 4),
+                    (TransactionType::Deposit, 5),
+                ]
+            );
+        }
+
+        #[test]
+        fn transactions_too_late_to_reorder_are_rejected() {
+            let input = "type, client, tx, amount\n\
+                         deposit, 1, 3, 1.0\n\
+                         deposit, 1, 4, 1.0\n\
+                         deposit, 1, 2, 1.5\n\
+                         deposit, 1, 4, 1.0\n\
+                         refund, 1, 5, 1.0\n";
+
+            let transactions = read_all(OrderedSource::new(rows(input), 0));
+
+            assert_eq!(
+                transactions[0].as_ref().unwrap().transaction_id,
+                TransactionId(3)
+            );
+            assert_eq!(
+                transactions[1].as_ref().unwrap().transaction_id,
+                TransactionId(4)
+            );
+            assert_eq!(
+                transactions[2],
+                Err(InputError::OutOfOrder {
+                    transaction: Box::new(deposit()),
+                    previous: TransactionId(4),
+                })
+            );
+            // Repeated ids are left to the duplicate policy of the engine
+            assert_eq!(
+                transactions[3].as_ref().unwrap().transaction_id,
+                TransactionId(4)
+            );
+            assert!(matches!(
+                transactions[4],
+                Err(InputError::Malformed { line: 6, .. })
+            ));
+        }
+
+        #[test]
+        fn ordering_is_parsed() {
+            assert_eq!("unordered".parse(), Ok(InputOrdering::Unordered));
+            assert_eq!("strict".parse(), Ok(InputOrdering::Strict));
+            assert_eq!("reorder:64".parse(), Ok(InputOrdering::Reorder(64)));
+            for invalid in ["reorder:0", "reorder:", "reorder", "sorted"] {
+                assert!(invalid.parse::<InputOrdering>().is_err(), "{}", invalid);
+            }
+        }
+
+        #[test]
+        fn rows_are_formatted_back_in_the_input_format() {
+            let transfer = Transaction {
+                transaction_type: TransactionType::Transfer,
+                client_id: 1,
+                transaction_id: TransactionId(3),
+                amount: Some(Amount::unchecked(0.5)),
+                to_client_id: Some(2),
+                currency: None,
+                timestamp: None,
+                metadata: Default::default(),
+            };
+
+            for transaction in [deposit(), dispute(), transfer] {
+                let csv = format!(
+                    "type,client,tx,amount,to\n{}\n",
+                    InputFormat::Csv.format_row(&transaction)
+                );
+                let ndjson = InputFormat::Ndjson.format_row(&transaction);
+                assert_eq!(read_all(rows(&csv)), vec![Ok(transaction.clone())]);
+                assert_eq!(
+                    read_all(NdjsonSource::new(ndjson.as_bytes())),
+                    vec![Ok(transaction)]
+                );
+            }
+            assert_eq!(InputFormat::Csv.format_row(&dispute()), "dispute,1,2");
+        }
+
+        #[test]
+        fn file_names_are_matched_against_patterns() {
+            for (pattern, name, matches) in [
+                ("*.csv", "2024-03-01T10.csv", true),
+                ("*.csv", "notes.txt", false),
+                ("dump-??.csv", "dump-07.csv", true),
+                ("dump-??.csv", "dump-7.csv", false),
+                ("*-*.csv", "a-b-c.csv", true),
+                ("exact.csv", "exact.csv", true),
+            ] {
+                assert_eq!(
+                    glob_matches(pattern.as_bytes(), name.as_bytes()),
+                    matches,
+                    "{} {}",
+                    pattern,
+                    name
+                );
+            }
+        }
+
+        #[test]
+        fn files_of_a_directory_are_read_in_name_order() {
+            let directory = std::env::temp_dir().join("rust-coding-test-input-directory");
+            let _ = std::fs::remove_dir_all(&directory);
+            std::fs::create_dir(&directory).unwrap();
+            for (name, contents) in [
+                ("02.csv", "type,client,tx,amount\ndeposit,1,2,1.0\n"),
+                ("01.csv", "type,client,tx,amount\ndeposit,1,1,1.0\nbogus\n"),
+                ("03.txt", "not transactions\n"),
+                ("10.csv", "type,client,tx,amount\nwithdrawal,1,3,0.5\n"),
+            ] {
+                std::fs::write(directory.join(name), contents).unwrap();
+            }
+
+            let files = discover_inputs(&directory, InputFormat::Csv).unwrap();
+            let matched = discover_inputs(directory.join("0?.csv"), InputFormat::Csv).unwrap();
+            let missing = discover_inputs(directory.join("*.ndjson"), InputFormat::Ndjson);
+            let read = Arc::new(AtomicU64::new(0));
+            let results = read_all(
+                MultiFileSource::new(files.clone(), InputFormat::Csv).counting(read.clone()),
+            );
+            std::fs::remove_dir_all(&directory).unwrap();
+
+            let names: Vec<_> = files
+                .iter()
+                .map(|file| file.file_name().unwrap().to_str().unwrap())
+                .collect();
+            assert_eq!(names, ["01.csv", "02.csv", "10.csv"]);
+            // Every byte of the files read, and none of the other file
+            assert_eq!(read.load(Ordering::Relaxed), 44 + 38 + 41);
+            assert_eq!(matched, files[..2]);
+            assert!(missing.is_err());
+            let ids: Vec<_> = results
+                .iter()
+                .map(|result| {
+                    result
+                        .as_ref()
+                        .map(|transaction| transaction.transaction_id)
+                })
+                .collect();
+            assert!(matches!(
+                ids[..],
+                [
+                    Ok(TransactionId(1)),
+                    Err(InputError::Malformed { line: 3, .. }),
+                    Ok(TransactionId(2)),
+                    Ok(TransactionId(3))
+                ]
+            ));
+            assert_eq!(
+                discover_inputs("-", InputFormat::Csv).unwrap(),
+                [std::path::PathBuf::from("-")]
+            );
+        }
+
+        #[test]
+        fn files_reusing_transaction_ids_are_given_ids_of_their_own() {
+            let directory = std::env::temp_dir().join("rust-coding-test-input-namespaces");
+            let _ = std::fs::remove_dir_all(&directory);
+            std::fs::create_dir(&directory).unwrap();
+            for (name, contents) in [
+                (
+                    "a.csv",
+                    "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,2.0\ndispute,1,1,\n",
+                ),
+                (
+                    "b.csv",
+                    "type,client,tx,amount\ndispute,2,2,\ndeposit,2,1,3.0\ndeposit,2,2,4.0\n",
+                ),
+            ] {
+                std::fs::write(directory.join(name), contents).unwrap();
+            }
+
+            let files = discover_inputs(&directory, InputFormat::Csv).unwrap();
+            let results = read_all(MultiFileSource::new(files, InputFormat::Csv).with_namespaces());
+            std::fs::remove_dir_all(&directory).unwrap();
+
+            let ids: Vec<_> = results
+                .into_iter()
+                .map(|result| {
+                    let transaction = result.unwrap();
+                    (
+                        transaction.transaction_id,
+                        transaction.metadata[SOURCE_KEY].clone(),
+                        transaction.metadata[SOURCE_TRANSACTION_KEY].clone(),
+                    )
+                })
+                .map(|(id, source, original)| format!("{} {}:{}", id, source, original))
+                .collect();
+            // Disputes refer to the transaction of their own file, even one read after them
+            assert_eq!(
+                ids,
+                [
+                    "1 a.csv:1",
+                    "2 a.csv:2",
+                    "1 a.csv:1",
+                    "3 b.csv:2",
+                    "4 b.csv:1",
+                    "3 b.csv:2"
+                ]
+            );
+        }
+    }
+}
@@ -0,0 +1,612 @@
+//! Applied transactions as a double-entry journal, in the plain text format of ledger-cli or of
+//! beancount. Every entry posts the funds a transaction moved on the accounts of the clients,
+//! `Liabilities:Clients:<id>:Available` and `Liabilities:Clients:<id>:Held`, since they are owed
+//! to the clients, and balances them per currency against the account the funds came from or
+//! went to:
+//!
+//! - `Assets:Settlement` for deposits, withdrawals, captures and reversals,
+//! - `Assets:Chargebacks` for chargebacks and representments,
+//! - `Income:Fees`, `Expenses:Interest` and `Equity:Adjustments` for fees, interest and
+//!   adjustments.
+//!
+//! Disputes, authorizations, transfers and merges only move funds between the client accounts,
+//! so their postings balance on their own. Disputes resolved as they expire and withdrawals
+//! settled by [`TransactionEngine::advance`] get entries of their own. A client whose chargebacks
+//! took more than they had is left with a debit balance, the amount to collect or write off.
+//!
+//! ```
+//! use rust_coding_test::{
+//!     Amount, Journal, JournalFormat, Transaction, TransactionEngine, TransactionId,
+//!     TransactionType,
+//! };
+//! use std::io;
+//!
+//! let mut engine = TransactionEngine::new();
+//! let journal = Journal::new(&mut engine, io::sink(), JournalFormat::Ledger);
+//! engine
+//!     .execute(Transaction {
+//!         transaction_type: TransactionType::Deposit,
+//!         client_id: 1,
+//!         transaction_id: TransactionId(1),
+//!         amount: Some(Amount::new(2.5).unwrap()),
+//!         to_client_id: None,
+//!         currency: None,
+//!         timestamp: Some("2024-03-01".parse().unwrap()),
+//!         metadata: Default::default(),
+//!     })
+//!     .unwrap();
+//! journal.finish().unwrap();
+//! ```
+//!
+//! written as
+//!
+//! ```text
+//! 2024-03-01 * deposit tx 1 of client 1
+//!     Liabilities:Clients:1:Available                  -2.5000 FUNDS
+//!     Assets:Settlement                                 2.5000 FUNDS
+//! ```
+
+use crate::account::{Balance, ClientId, DisputeState};
+use crate::currency::Currency;
+use crate::engine::TransactionEngine;
+use crate::observer::EngineObserver;
+use crate::output::DEFAULT_DECIMALS;
+use crate::timestamp::Timestamp;
+use crate::transaction::{Transaction, TransactionId, TransactionType};
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Commodity of the funds kept without a currency
+pub const DEFAULT_COMMODITY: &str = "FUNDS";
+
+/// Width the account names are padded to, so that the amounts line up
+const ACCOUNT_WIDTH: usize = 40;
+
+/// Plain text accounting format a [`Journal`] is written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JournalFormat {
+    /// ledger-cli, also read by hledger
+    #[default]
+    Ledger,
+    /// beancount, which needs every account opened before its first posting
+    Beancount,
+}
+
+impl JournalFormat {
+    /// Beancount for files named `.beancount` or `.bean`, ledger otherwise
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        match path
+            .as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("beancount" | "bean") => JournalFormat::Beancount,
+            _ => JournalFormat::Ledger,
+        }
+    }
+}
+
+impl FromStr for JournalFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "ledger" => Ok(JournalFormat::Ledger),
+            "beancount" => Ok(JournalFormat::Beancount),
+            _ => Err(format!("unknown journal format '{}'", value)),
+        }
+    }
+}
+
+/// Writes what an engine applies as journal entries, see the [module](self) documentation. The
+/// entry of a transaction is complete once the engine applies the next one, so the last entry is
+/// only written by [`Journal::finish`].
+pub struct Journal<W: Write + Send + 'static> {
+    state: Arc<Mutex<JournalState<W>>>,
+}
+
+impl<W: Write + Send + 'static> Journal<W> {
+    /// Subscribes to the transactions the engine applies from now on. Entries of transactions
+    /// without a timestamp are dated the day the journal is created.
+    pub fn new(engine: &mut TransactionEngine, writer: W, format: JournalFormat) -> Self {
+        let today = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let state = Arc::new(Mutex::new(JournalState {
+            writer,
+            format,
+            decimals: DEFAULT_DECIMALS,
+            date: Timestamp::from_secs(today),
+            last_date: None,
+            opened: BTreeSet::new(),
+            entry: None,
+            error: None,
+        }));
+        engine.subscribe(Box::new(Recorder(state.clone())));
+        Journal { state }
+    }
+
+    /// Dates the entries of transactions without a timestamp, and those of the disputes and
+    /// withdrawals settled without a transaction before the first one, on the day of `date`
+    pub fn with_date(self, date: Timestamp) -> Self {
+        self.lock().date = date;
+        self
+    }
+
+    /// Writes amounts with `decimals` decimal places, [`DEFAULT_DECIMALS`] by default. Movements
+    /// smaller than that are left out.
+    pub fn with_decimals(self, decimals: usize) -> Self {
+        self.lock().decimals = decimals;
+        self
+    }
+
+    /// Writes the last entry and flushes the writer, returning the first error writing the
+    /// journal failed with. Entries applied after that are lost.
+    pub fn finish(self) -> io::Result<()> {
+        let mut state = self.lock();
+        state.complete();
+        if let Some(err) = state.error.take() {
+            return Err(err);
+        }
+        state.writer.flush()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, JournalState<W>> {
+        self.state.lock().expect("Journal lock poisoned")
+    }
+}
+
+impl Journal<BufWriter<File>> {
+    /// Journal appended to the file at `path`, created if missing. Accounts the file already
+    /// opens are not opened again in beancount.
+    pub fn append<P: AsRef<Path>>(
+        engine: &mut TransactionEngine,
+        path: P,
+        format: JournalFormat,
+    ) -> io::Result<Self> {
+        let opened = match fs::read_to_string(&path) {
+            Ok(existing) => opened_accounts(&existing),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => BTreeSet::new(),
+            Err(err) => return Err(err),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let journal = Self::new(engine, BufWriter::new(file), format);
+        journal.lock().opened = opened;
+        Ok(journal)
+    }
+}
+
+/// Accounts of `open` directives in a beancount journal
+fn opened_accounts(journal: &str) -> BTreeSet<String> {
+    journal
+        .lines()
+        .filter_map(
+            |line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [_, "open", account, ..] => Some(account.to_string()),
+                _ => None,
+            },
+        )
+        .collect()
+}
+
+struct JournalState<W> {
+    writer: W,
+    format: JournalFormat,
+    decimals: usize,
+    /// Date of the transactions without a timestamp
+    date: Timestamp,
+    /// Date of the last entry, that of the funds settled without a transaction after it
+    last_date: Option<Timestamp>,
+    /// Accounts opened in beancount
+    opened: BTreeSet<String>,
+    /// Entry of the last transaction, until the engine moves on to the next
+    entry: Option<Entry>,
+    /// First write failure, reported by [`Journal::finish`] so that recording never interrupts
+    /// processing
+    error: Option<io::Error>,
+}
+
+impl<W: Write> JournalState<W> {
+    /// Writes the pending entry, if it posted anything
+    fn complete(&mut self) {
+        let Some(entry) = self.entry.take() else {
+            return;
+        };
+        self.last_date = Some(entry.date);
+        let postings = entry.balanced(self.decimals);
+        if postings.is_empty() || self.error.is_some() {
+            return;
+        }
+        let text = self.render(&entry, &postings);
+        if let Err(err) = self.writer.write_all(text.as_bytes()) {
+            self.error = Some(err);
+        }
+    }
+
+    fn render(&mut self, entry: &Entry, postings: &[Posting]) -> String {
+        let mut text = String::new();
+        let date = &entry.date.to_string()[..10];
+        let indent = match self.format {
+            JournalFormat::Ledger => {
+                let _ = writeln!(text, "{} * {}", date, entry.narration);
+                "    "
+            }
+            JournalFormat::Beancount => {
+                let mut opens = String::new();
+                for posting in postings {
+                    if self.opened.insert(posting.account.clone()) {
+                        // Opened at the epoch, as entries are not necessarily in date order
+                        let _ = writeln!(opens, "1970-01-01 open {}", posting.account);
+                    }
+                }
+                if !opens.is_empty() {
+                    let _ = writeln!(text, "{}", opens);
+                }
+                let _ = writeln!(text, "{} * \"{}\"", date, entry.narration);
+                "  "
+            }
+        };
+        for posting in postings {
+            let _ = writeln!(
+                text,
+                "{}{:<width$}  {:>14} {}",
+                indent,
+                posting.account,
+                units_to_string(posting.units, self.decimals),
+                posting
+                    .currency
+                    .as_ref()
+                    .map_or(DEFAULT_COMMODITY, Currency::as_str),
+                width = ACCOUNT_WIDTH,
+            );
+        }
+        text.push('\n');
+        text
+    }
+}
+
+/// Observer subscribed to the engine, sharing the state of its [`Journal`]
+struct Recorder<W>(Arc<Mutex<JournalState<W>>>);
+
+impl<W: Write + Send> Recorder<W> {
+    fn lock(&self) -> std::sync::MutexGuard<'_, JournalState<W>> {
+        self.0.lock().expect("Journal lock poisoned")
+    }
+}
+
+impl<W: Write + Send> EngineObserver for Recorder<W> {
+    fn on_applied(&mut self, transaction: &Transaction) {
+        let mut state = self.lock();
+        state.complete();
+        let date = transaction.timestamp.unwrap_or(state.date);
+        state.entry = Some(Entry::of(transaction, date));
+    }
+
+    fn on_balance_changed(
+        &mut self,
+        client_id: ClientId,
+        currency: Option<Currency>,
+        before: Balance,
+        after: Balance,
+    ) {
+        let mut state = self.lock();
+        // A transaction changes the funds of each of its clients and currencies at most once,
+        // before any dispute or lock it notifies of, so anything else was settled without one
+        if !state
+            .entry
+            .as_ref()
+            .is_some_and(|entry| entry.accepts(client_id, currency))
+        {
+            state.complete();
+            let date = state.last_date.unwrap_or(state.date);
+            state.entry = Some(Entry::settlement(date));
+        }
+        let Some(entry) = state.entry.as_mut() else {
+            return;
+        };
+        entry.changed.insert((client_id, currency));
+        for (kind, change) in [
+            ("Available", after.available - before.available),
+            ("Held", after.held - before.held),
+        ] {
+            // Funds owed to the client are credits, written negative
+            entry.postings.push((
+                format!("Liabilities:Clients:{}:{}", client_id, kind),
+                currency,
+                -change,
+            ));
+        }
+    }
+
+    fn on_dispute(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        state: DisputeState,
+    ) {
+        let mut journal = self.lock();
+        let Some(entry) = journal.entry.as_mut() else {
+            return;
+        };
+        entry.notified = true;
+        // Only expiries resolve disputes without a transaction
+        if entry.transaction_type.is_none() && state == DisputeState::Resolved {
+            entry.narration = format!(
+                "expired dispute of tx {} of client {}",
+                transaction_id, client_id
+            );
+        }
+    }
+
+    fn on_lock_changed(&mut self, _client_id: ClientId, _locked: bool) {
+        if let Some(entry) = self.lock().entry.as_mut() {
+            entry.notified = true;
+        }
+    }
+}
+
+/// Entry of a transaction, or of funds settled without one, until it is complete
+struct Entry {
+    date: Timestamp,
+    narration: String,
+    /// None for funds settled without a transaction
+    transaction_type: Option<TransactionType>,
+    /// Clients whose funds the transaction can change, any for funds settled without one
+    clients: Option<[ClientId; 2]>,
+    /// Changes of the funds, in units of the currency
+    postings: Vec<(String, Option<Currency>, f64)>,
+    /// Funds already posted, per client and currency
+    changed: BTreeSet<(ClientId, Option<Currency>)>,
+    /// Whether a dispute or lock was notified, after which the transaction changes no funds
+    notified: bool,
+}
+
+/// Line of an entry, with the amount in units of the smallest decimal place written
+struct Posting {
+    account: String,
+    currency: Option<Currency>,
+    units: i128,
+}
+
+impl Entry {
+    fn of(transaction: &Transaction, date: Timestamp) -> Self {
+        let mut narration = format!(
+            "{} tx {} of client {}",
+            transaction.transaction_type, transaction.transaction_id, transaction.client_id
+        );
+        if let Some(to_client_id) = transaction.to_client_id {
+            let _ = write!(narration, " to client {}", to_client_id);
+        }
+        Entry {
+            date,
+            narration,
+            transaction_type: Some(transaction.transaction_type),
+            clients: Some([
+                transaction.client_id,
+                transaction.to_client_id.unwrap_or(transaction.client_id),
+            ]),
+            postings: Vec::new(),
+            changed: BTreeSet::new(),
+            notified: false,
+        }
+    }
+
+    fn settlement(date: Timestamp) -> Self {
+        Entry {
+            date,
+            narration: "settled withdrawals".to_string(),
+            transaction_type: None,
+            clients: None,
+            postings: Vec::new(),
+            changed: BTreeSet::new(),
+            notified: false,
+        }
+    }
+
+    fn accepts(&self, client_id: ClientId, currency: Option<Currency>) -> bool {
+        !self.notified
+            && !self.changed.contains(&(client_id, currency))
+            && self
+                .clients
+                .is_none_or(|clients| clients.contains(&client_id))
+    }
+
+    /// Account the funds entering or leaving the client accounts are balanced against
+    fn counterpart(&self) -> &'static str {
+        match self.transaction_type {
+            Some(TransactionType::Chargeback | TransactionType::Represent) => "Assets:Chargebacks",
+            Some(TransactionType::Fee) => "Income:Fees",
+            Some(TransactionType::Interest) => "Expenses:Interest",
+            Some(TransactionType::Adjustment) => "Equity:Adjustments",
+            _ => "Assets:Settlement",
+        }
+    }
+
+    /// Postings rounded to `decimals` places without those that round to zero, followed by a
+    /// posting on the counterpart for every currency they leave unbalanced
+    fn balanced(&self, decimals: usize) -> Vec<Posting> {
+        let scale = 10f64.powi(decimals as i32);
+        let mut postings: Vec<Posting> = self
+            .postings
+            .iter()
+            .map(|(account, currency, amount)| Posting {
+                account: account.clone(),
+                currency: *currency,
+                units: (amount * scale).round() as i128,
+            })
+            .filter(|posting| posting.units != 0)
+            .collect();
+        let currencies: BTreeSet<_> = postings.iter().map(|posting| posting.currency).collect();
+        for currency in currencies {
+            let units: i128 = postings
+                .iter()
+                .filter(|posting| posting.currency == currency)
+                .map(|posting| posting.units)
+                .sum();
+            if units != 0 {
+                postings.push(Posting {
+                    account: self.counterpart().to_string(),
+                    currency,
+                    units: -units,
+                });
+            }
+        }
+        postings
+    }
+}
+
+fn units_to_string(units: i128, decimals: usize) -> String {
+    let digits = format!("{:0>width$}", units.unsigned_abs(), width = decimals + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - decimals);
+    let sign = if units < 0 { "-" } else { "" };
+    if decimals == 0 {
+        format!("{}{}", sign, whole)
+    } else {
+        format!("{}{}.{}", sign, whole, fraction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::account::ClientId;
+        use crate::engine::{EngineConfig, TransactionEngine};
+        use crate::journal::{Journal, JournalFormat};
+        use crate::policy::DisputeExpiry;
+        use crate::transaction::{transaction, Origin, Transaction, TransactionType};
+        use std::io::{self, Write};
+        use std::sync::{Arc, Mutex};
+
+        /// Writer whose output stays readable once the journal is finished
+        #[derive(Clone, Default)]
+        struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+        impl Buffer {
+            fn text(&self) -> String {
+                String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+            }
+        }
+
+        impl Write for Buffer {
+            fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(bytes)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        /// Transaction of the day the postings are dated
+        fn dated(
+            transaction_type: TransactionType,
+            client_id: ClientId,
+            transaction_id: u32,
+            amount: Option<f64>,
+        ) -> Transaction {
+            Transaction {
+                timestamp: Some("2024-03-01".parse().unwrap()),
+                ..transaction(transaction_type, client_id, transaction_id, amount)
+            }
+        }
+
+        #[test]
+        fn postings_balance_against_where_the_funds_went() {
+            let mut engine = TransactionEngine::new();
+            let buffer = Buffer::default();
+            let journal = Journal::new(&mut engine, buffer.clone(), JournalFormat::Ledger);
+            for transaction in [
+                dated(TransactionType::Deposit, 1, 1, Some(5.0)),
+                dated(TransactionType::Withdrawal, 1, 2, Some(1.5)),
+                dated(TransactionType::Dispute, 1, 1, None),
+                dated(TransactionType::Chargeback, 1, 1, None),
+            ] {
+                engine.execute(transaction).unwrap();
+            }
+            journal.finish().unwrap();
+
+            assert_eq!(
+                buffer.text(),
+                "2024-03-01 * deposit tx 1 of client 1\n\
+                 \x20   Liabilities:Clients:1:Available                  -5.0000 FUNDS\n\
+                 \x20   Assets:Settlement                                 5.0000 FUNDS\n\
+                 \n\
+                 2024-03-01 * withdrawal tx 2 of client 1\n\
+                 \x20   Liabilities:Clients:1:Available                   1.5000 FUNDS\n\
+                 \x20   Assets:Settlement                                -1.5000 FUNDS\n\
+                 \n\
+                 2024-03-01 * dispute tx 1 of client 1\n\
+                 \x20   Liabilities:Clients:1:Available                   5.0000 FUNDS\n\
+                 \x20   Liabilities:Clients:1:Held                       -5.0000 FUNDS\n\
+                 \n\
+                 2024-03-01 * chargeback tx 1 of client 1\n\
+                 \x20   Liabilities:Clients:1:Held                        5.0000 FUNDS\n\
+                 \x20   Assets:Chargebacks                               -5.0000 FUNDS\n\
+                 \n"
+            );
+        }
+
+        #[test]
+        fn beancount_opens_accounts_once_and_expiries_get_their_own_entry() {
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                dispute_expiry: DisputeExpiry {
+                    after_transactions: Some(1),
+                    after_days: None,
+                },
+                ..EngineConfig::default()
+            });
+            let buffer = Buffer::default();
+            let journal = Journal::new(&mut engine, buffer.clone(), JournalFormat::Beancount);
+            for transaction in [
+                dated(TransactionType::Deposit, 1, 1, Some(2.0)),
+                dated(TransactionType::Dispute, 1, 1, None),
+                dated(TransactionType::Deposit, 2, 2, Some(1.0)),
+            ] {
+                engine.execute(transaction).unwrap();
+            }
+            journal.finish().unwrap();
+
+            let text = buffer.text();
+            assert_eq!(
+                text.matches("open Liabilities:Clients:1:Available").count(),
+                1
+            );
+            assert!(text.contains("1970-01-01 open Liabilities:Clients:1:Held\n"));
+            assert!(text.contains(
+                "2024-03-01 * \"deposit tx 2 of client 2\"\n\
+                 \x20 Liabilities:Clients:2:Available                  -1.0000 FUNDS\n\
+                 \x20 Assets:Settlement                                 1.0000 FUNDS\n"
+            ));
+            assert!(text.ends_with(
+                "2024-03-01 * \"expired dispute of tx 1 of client 1\"\n\
+                 \x20 Liabilities:Clients:1:Available                  -2.0000 FUNDS\n\
+                 \x20 Liabilities:Clients:1:Held                        2.0000 FUNDS\n\n"
+            ));
+        }
+
+        #[test]
+        fn transactions_moving_nothing_have_no_entry() {
+            let mut engine = TransactionEngine::new();
+            let buffer = Buffer::default();
+            let journal = Journal::new(&mut engine, buffer.clone(), JournalFormat::Ledger);
+            engine
+                .execute(dated(TransactionType::Deposit, 1, 1, Some(1.0)))
+                .unwrap();
+            engine
+                .execute(dated(TransactionType::Dispute, 1, 7, None))
+                .unwrap_err();
+            engine
+                .execute_from(dated(TransactionType::Lock, 1, 3, None), Origin::Admin)
+                .unwrap();
+            journal.finish().unwrap();
+
+            assert_eq!(buffer.text().matches(" * ").count(), 1);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! String in, string out API over the engine meant to be exported to JavaScript, so that the
+//! engine can run client-side, e.g. in a browser based reconciliation demo. The wasm-bindgen
+//! attributes belong in a wrapper crate compiled to `wasm32-unknown-unknown`, which exports
+//! [`process_csv`] and [`Session`] as they are. Nothing here touches files, threads or the clock,
+//! which std only stubs out on that target.
+
+use crate::engine::TransactionEngine;
+use crate::input::{parse_json_transaction, CsvSource, TransactionSource};
+use crate::output::{accounts_csv, json_string};
+use crate::server::status_json;
+
+/// Applies the csv transactions of `input` to a new engine and returns the accounts as csv,
+/// ordered by client. Malformed rows and rejected transactions are skipped as in batch runs;
+/// an input that cannot be read at all, e.g. one missing a column, is an error.
+pub fn process_csv(input: &str) -> Result<String, String> {
+    let mut session = Session::new();
+    let mut source = CsvSource::new(input.as_bytes());
+    while let Some(transaction) = source.next_transaction() {
+        match transaction {
+            Ok(transaction) => {
+                let _ = session.engine.execute(transaction);
+            }
+            Err(err) if err.is_fatal() => return Err(err.to_string()),
+            Err(_) => {}
+        }
+    }
+    Ok(session.accounts())
+}
+
+/// Engine fed one transaction at a time
+#[derive(Default)]
+pub struct Session {
+    engine: TransactionEngine,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session::default()
+    }
+
+    /// Applies a transaction written as a json object, as accepted by `POST /transactions` of
+    /// the server. Returns the status of the transaction in the same format as the server,
+    /// `{"tx":1,"status":"applied"}` or `{"tx":1,"status":"rejected","reason":"..."}`, or
+    /// `{"error":"..."}` if the object is not a transaction.
+    pub fn submit(&mut self, transaction: &str) -> String {
+        match parse_json_transaction(transaction) {
+            Ok(transaction) => {
+                let transaction_id = transaction.transaction_id;
+                status_json(transaction_id, &self.engine.execute(transaction))
+            }
+            Err(message) => format!("{{\"error\":{}}}", json_string(&message)),
+        }
+    }
+
+    /// Accounts as csv ordered by client, with a currency column if any account holds a
+    /// currency
+    pub fn accounts(&self) -> String {
+        accounts_csv(&self.engine)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::js::{process_csv, Session};
+
+        #[test]
+        fn csv_input_is_processed_into_accounts() {
+            let accounts = process_csv(
+                "type,client,tx,amount\n\
+                 deposit,2,1,5.0\n\
+                 deposit,1,2,3.0\n\
+                 withdrawal,1,3,10.0\n\
+                 deposit,oops,4,1.0\n",
+            )
+            .unwrap();
+
+            assert_eq!(
+                accounts,
+                "client,available,held,total,locked\n\
+                 1,3.0000,0.0000,3.0000,false\n\
+                 2,5.0000,0.0000,5.0000,false\n"
+            );
+            assert!(process_csv("type,tx,amount\ndeposit,1,5.0\n").is_err());
+        }
+
+        #[test]
+        fn submitted_transactions_report_their_status() {
+            let mut session = Session::new();
+
+            assert_eq!(
+                session.submit(r#"{"type":"deposit","client":1,"tx":1,"amount":2.5}"#),
+                r#"{"tx":1,"status":"applied"}"#
+            );
+            assert!(session
+                .submit(r#"{"type":"withdrawal","client":1,"tx":2,"amount":9.0}"#)
+                .starts_with(r#"{"tx":2,"status":"rejected","reason":"#));
+            assert!(session
+                .submit(r#"{"type":"deposit"}"#)
+                .starts_with(r#"{"error":"#));
+            assert_eq!(
+                session.accounts(),
+                "client,available,held,total,locked\n1,2.5000,0.0000,2.5000,false\n"
+            );
+        }
+    }
+}
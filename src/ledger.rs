@@ -0,0 +1,285 @@
+use crate::account::ClientId;
+use crate::timestamp::Timestamp;
+use crate::transaction::{Transaction, TransactionId, TransactionType};
+use std::collections::HashMap;
+use std::ops::{Bound, RangeBounds};
+
+/// Applied transaction as retained in the ledger
+#[derive(Debug, Clone, PartialEq)]
+pub struct LedgerEntry {
+    /// Order in which the engine applied the transaction
+    pub sequence: u64,
+    pub transaction: Transaction,
+}
+
+/// Full history of applied transactions, grouped per client
+#[derive(Debug, Default)]
+pub struct Ledger {
+    entries: HashMap<ClientId, Vec<LedgerEntry>>,
+    next_sequence: u64,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Transfers are recorded in the history of both clients
+    pub fn record(&mut self, transaction: Transaction) {
+        self.restore_entry(LedgerEntry {
+            sequence: self.next_sequence,
+            transaction,
+        });
+    }
+
+    /// Adds an entry that was recorded earlier, e.g. when restoring a snapshot
+    pub(crate) fn restore_entry(&mut self, entry: LedgerEntry) {
+        self.next_sequence = self.next_sequence.max(entry.sequence + 1);
+        if let Some(to_client_id) = entry.transaction.to_client_id {
+            self.entries
+                .entry(to_client_id)
+                .or_default()
+                .push(entry.clone());
+        }
+        self.entries
+            .entry(entry.transaction.client_id)
+            .or_default()
+            .push(entry);
+    }
+
+    /// Takes over the entries of a ledger that recorded a disjoint set of clients
+    pub(crate) fn absorb(&mut self, other: Ledger) {
+        self.next_sequence = self.next_sequence.max(other.next_sequence);
+        self.entries.extend(other.entries);
+    }
+
+    /// Rewrites every entry of `from` as made by `into` and moves them to the history of `into`
+    pub(crate) fn remap(&mut self, from: ClientId, into: ClientId) {
+        let remap = |client_id: &mut ClientId| {
+            if *client_id == from {
+                *client_id = into;
+            }
+        };
+        for entry in self.entries.values_mut().flatten() {
+            remap(&mut entry.transaction.client_id);
+            if let Some(to_client_id) = entry.transaction.to_client_id.as_mut() {
+                remap(to_client_id);
+            }
+        }
+        let Some(moved) = self.entries.remove(&from) else {
+            return;
+        };
+        let entries = self.entries.entry(into).or_default();
+        entries.extend(moved);
+        // Transfers between both clients were in both histories
+        entries.sort_by_key(|entry| entry.sequence);
+        entries.dedup_by_key(|entry| entry.sequence);
+    }
+
+    pub fn history(&self, client_id: ClientId) -> History<'_> {
+        History {
+            entries: self
+                .entries
+                .get(&client_id)
+                .map_or(&[][..], |entries| entries.as_slice())
+                .iter(),
+            transaction_type: None,
+            transaction_ids: (Bound::Unbounded, Bound::Unbounded),
+            timestamps: None,
+        }
+    }
+
+    pub(crate) fn clients(&self) -> impl Iterator<Item = ClientId> + '_ {
+        self.entries.keys().copied()
+    }
+
+    /// Entries of every client in no particular order, transfers once per client
+    pub(crate) fn entries(&self) -> impl Iterator<Item = &LedgerEntry> + '_ {
+        self.entries.values().flatten()
+    }
+
+    /// Entries of every client in the order they were applied, transfers once. Shards of a
+    /// sharded engine number their entries separately, but never share clients.
+    pub(crate) fn in_order(&self) -> Vec<&LedgerEntry> {
+        let mut entries: Vec<_> = self.entries().collect();
+        entries.sort_by_key(|entry| {
+            let transaction = &entry.transaction;
+            (
+                entry.sequence,
+                transaction.client_id,
+                transaction.transaction_id,
+            )
+        });
+        entries.dedup();
+        entries
+    }
+
+    /// Sequence of the next recorded entry
+    pub(crate) fn next_sequence(&self) -> u64 {
+        self.next_sequence
+    }
+}
+
+/// Applied transactions of a client in the order they were applied
+pub struct History<'a> {
+    entries: std::slice::Iter<'a, LedgerEntry>,
+    transaction_type: Option<TransactionType>,
+    transaction_ids: (Bound<TransactionId>, Bound<TransactionId>),
+    timestamps: Option<(Bound<Timestamp>, Bound<Timestamp>)>,
+}
+
+impl History<'_> {
+    /// Only keeps transactions of the given type
+    pub fn of_type(mut self, transaction_type: TransactionType) -> Self {
+        self.transaction_type = Some(transaction_type);
+        self
+    }
+
+    /// Only keeps transactions whose id is within the range
+    pub fn transaction_ids<R: RangeBounds<TransactionId>>(mut self, range: R) -> Self {
+        self.transaction_ids = (range.start_bound().cloned(), range.end_bound().cloned());
+        self
+    }
+
+    /// Only keeps transactions whose timestamp is within the range, e.g. those of one day.
+    /// Transactions without a timestamp are left out.
+    pub fn between<R: RangeBounds<Timestamp>>(mut self, range: R) -> Self {
+        self.timestamps = Some((range.start_bound().cloned(), range.end_bound().cloned()));
+        self
+    }
+}
+
+impl<'a> Iterator for History<'a> {
+    type Item = &'a LedgerEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let transaction_type = self.transaction_type;
+        let transaction_ids = self.transaction_ids;
+        let timestamps = self.timestamps;
+        self.entries.find(|entry| {
+            transaction_type.is_none_or(|t| entry.transaction.transaction_type == t)
+                && transaction_ids.contains(&entry.transaction.transaction_id)
+                && timestamps.is_none_or(|range| {
+                    entry
+                        .transaction
+                        .timestamp
+                        .is_some_and(|timestamp| range.contains(&timestamp))
+                })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::engine::{EngineConfig, TransactionEngine};
+        use crate::timestamp::{Timestamp, SECONDS_PER_DAY};
+        use crate::transaction::{transaction, Transaction, TransactionId, TransactionType};
+
+        fn engine_with_history() -> TransactionEngine {
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                record_history: true,
+                ..EngineConfig::default()
+            });
+            for transaction in [
+                transaction(TransactionType::Deposit, 1, 1, Some(5.0)),
+                transaction(TransactionType::Deposit, 2, 2, Some(1.0)),
+                transaction(TransactionType::Withdrawal, 1, 3, Some(2.0)),
+                transaction(TransactionType::Withdrawal, 1, 4, Some(20.0)),
+                transaction(TransactionType::Dispute, 1, 1, None),
+                transaction(TransactionType::Deposit, 1, 5, Some(1.0)),
+            ] {
+                let _ = engine.execute(transaction);
+            }
+            engine
+        }
+
+        fn ids<'a>(entries: impl Iterator<Item = &'a crate::ledger::LedgerEntry>) -> Vec<u32> {
+            entries
+                .map(|entry| entry.transaction.transaction_id.0)
+                .collect()
+        }
+
+        #[test]
+        fn history_contains_applied_transactions_of_client_in_order() {
+            let engine = engine_with_history();
+
+            let history: Vec<_> = engine.history(1).collect();
+
+            assert_eq!(ids(history.iter().copied()), vec![1, 3, 1, 5]);
+            assert!(history.windows(2).all(|w| w[0].sequence < w[1].sequence));
+            assert_eq!(ids(engine.history(2)), vec![2]);
+            assert_eq!(engine.history(3).count(), 0);
+        }
+
+        #[test]
+        fn history_can_be_filtered() {
+            let engine = engine_with_history();
+
+            assert_eq!(
+                ids(engine.history(1).of_type(TransactionType::Deposit)),
+                vec![1, 5]
+            );
+            assert_eq!(
+                ids(engine
+                    .history(1)
+                    .transaction_ids(TransactionId(2)..=TransactionId(5))),
+                vec![3, 5]
+            );
+            assert_eq!(
+                ids(engine
+                    .history(1)
+                    .of_type(TransactionType::Dispute)
+                    .transaction_ids(..TransactionId(2))),
+                vec![1]
+            );
+        }
+
+        #[test]
+        fn history_can_be_filtered_by_time() {
+            let mut engine = engine_with_history();
+            for (transaction_id, timestamp) in [(6, "2024-03-01T09:00Z"), (7, "2024-03-02T09:00Z")]
+            {
+                engine
+                    .execute(Transaction {
+                        timestamp: timestamp.parse().ok(),
+                        metadata: Default::default(),
+                        ..transaction(TransactionType::Deposit, 1, transaction_id, Some(1.0))
+                    })
+                    .unwrap();
+            }
+            let day: Timestamp = "2024-03-01".parse().unwrap();
+            let next_day = Timestamp::from_secs(day.as_secs() + SECONDS_PER_DAY);
+
+            assert_eq!(ids(engine.history(1).between(day..next_day)), vec![6]);
+            assert_eq!(ids(engine.history(1).between(day..)), vec![6, 7]);
+        }
+
+        #[test]
+        fn history_is_not_recorded_by_default() {
+            let mut engine = TransactionEngine::new();
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(5.0)))
+                .unwrap();
+
+            assert_eq!(engine.history(1).count(), 0);
+        }
+
+        #[test]
+        fn transfer_is_recorded_for_both_clients() {
+            let mut engine = engine_with_history();
+            engine
+                .execute(Transaction {
+                    to_client_id: Some(1),
+                    ..transaction(TransactionType::Transfer, 2, 6, Some(1.0))
+                })
+                .unwrap();
+
+            assert_eq!(
+                ids(engine.history(1).of_type(TransactionType::Transfer)),
+                vec![6]
+            );
+            assert_eq!(ids(engine.history(2)), vec![2, 6]);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Toy engine that processes deposits, withdrawals and disputes against client accounts.
+//!
+//! The engine can be embedded directly by feeding it [`Transaction`]s and reading back the
+//! state of [`ClientAccount`]s:
+//!
+//! ```
+//! use rust_coding_test::{
+//!     Amount, ClientAccount, Transaction, TransactionEngine, TransactionId, TransactionType,
+//! };
+//!
+//! let mut engine = TransactionEngine::new();
+//! engine
+//!     .execute(Transaction {
+//!         transaction_type: TransactionType::Deposit,
+//!         client_id: 1,
+//!         transaction_id: TransactionId(1),
+//!         amount: Some(Amount::new(2.5).unwrap()),
+//!         to_client_id: None,
+//!         currency: None,
+//!         timestamp: None,
+//!         metadata: Default::default(),
+//!     })
+//!     .unwrap();
+//!
+//! assert_eq!(engine.accounts[&1].get_available_funds(), 2.5);
+//! ```
+
+pub mod account;
+pub mod actor;
+pub mod analytics;
+pub mod audit;
+pub mod books;
+pub mod builder;
+pub mod changes;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod check;
+pub mod columnar;
+pub mod concurrent;
+pub mod config;
+pub mod consumer;
+pub mod currency;
+pub mod diff;
+pub mod dispatch;
+pub mod engine;
+pub mod error;
+pub mod exchange;
+mod expiry;
+pub mod fees;
+pub mod ffi;
+pub mod filter;
+pub mod generate;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+mod gzip;
+#[cfg(feature = "grpc")]
+mod http2;
+pub mod ingest;
+pub mod input;
+pub mod journal;
+pub mod js;
+pub mod ledger;
+pub mod log;
+pub mod metrics;
+#[cfg(feature = "object-store")]
+pub mod object_store;
+pub mod observer;
+pub mod output;
+pub mod overdraft;
+pub mod pipeline;
+pub mod policy;
+pub mod processor;
+pub mod profile;
+pub mod projection;
+pub mod query;
+pub mod repl;
+pub mod report;
+mod retention;
+pub mod retry;
+pub mod risk;
+pub mod savings;
+pub mod schedule;
+pub mod server;
+mod sha256;
+pub mod sharded;
+pub mod snapshot;
+pub mod state;
+pub mod statement;
+pub mod storage;
+pub mod throttle;
+pub mod tier;
+pub mod timestamp;
+pub mod toml;
+pub mod transaction;
+pub mod wal;
+pub mod webhook;
+pub mod window;
+
+pub use account::{
+    AccountFactory, AccountState, AccountTypes, AccountView, Balance, BasicAccount,
+    BasicAccountFactory, ClientAccount, ClientId, DisputeState,
+};
+pub use actor::ActorEngine;
+pub use analytics::{Analysis, BalanceBucket, DisputedAccount};
+pub use audit::{AuditEvent, AuditSink, InMemoryAuditSink, JsonlAuditSink};
+pub use books::{Flows, GlobalLedger};
+pub use builder::EngineBuilder;
+pub use changes::{ChangeSink, ChangeStream, JsonlChangeSink};
+pub use check::{Invariant, InvariantReport, Violation};
+pub use columnar::{Column, ColumnBatch, ColumnSource};
+pub use concurrent::ConcurrentEngine;
+pub use config::{ConfigFile, EngineSettings, IoSettings};
+pub use consumer::{Consumer, ConsumerStats, Message, MessageStream};
+pub use currency::Currency;
+pub use diff::{AccountDiff, DiffFormat, Reconciliation, DEFAULT_TOLERANCE};
+pub use dispatch::{OrderedDispatcher, DEFAULT_PENDING_CAPACITY};
+pub use engine::{Batch, EngineConfig, TransactionEngine};
+pub use error::{
+    ConfigError, ConsumerError, EngineError, ExchangeError, InputError, Limit, MergeConflict,
+    SnapshotError, UpdateError,
+};
+pub use exchange::{ExportFormat, EXCHANGE_FORMAT, EXCHANGE_VERSION};
+pub use fees::{FeeSchedule, FeeSummary};
+pub use ffi::{Engine, EngineAccount, EngineToClient, EngineTransaction};
+pub use filter::{ClientFilter, ClientSet};
+pub use generate::Workload;
+pub use ingest::InputPosition;
+pub use input::{
+    discover_inputs, open_source, open_source_counting, open_source_with, AmountFormat,
+    AmountRules, ColumnMapping, CsvSource, FastCsvSource, InputFormat, InputOrdering,
+    MultiFileSource, NdjsonSource, OrderedSource, ReadAheadSource, ReaderOptions, SampledSource,
+    TransactionNamespaces, TransactionSource, CSV_COLUMNS,
+};
+pub use journal::{Journal, JournalFormat, DEFAULT_COMMODITY};
+pub use js::{process_csv, Session};
+pub use ledger::{History, LedgerEntry};
+pub use metrics::EngineMetrics;
+pub use observer::EngineObserver;
+pub use output::{
+    verify_output, AccountSnapshot, AccountWriter, CsvAccountWriter, JsonAccountWriter,
+    JsonlAccountWriter, OutputFormat, OutputOrder, TableAccountWriter, Trailer, TrailerWriter,
+    DEFAULT_DECIMALS,
+};
+pub use overdraft::OverdraftAccount;
+pub use pipeline::{Pipeline, PipelineCapacities, PipelineMetrics, QueueDepths};
+pub use policy::{
+    AccountKind, AccountPolicies, DisputeCycles, DisputeExpiry, DisputePolicy, DuplicatePolicy,
+    LimitsPolicy, LockPolicy, NegativeBalancePolicy, OverdraftPolicy, PrecisionPolicy,
+    RetentionPolicy, SavingsPolicy,
+};
+pub use processor::TransactionProcessor;
+pub use profile::{Latencies, Profile};
+pub use projection::{AccountProjection, LastTransaction, OpenDisputes, ProjectedValue};
+pub use query::{ClientReport, ReportFormat};
+pub use repl::Repl;
+pub use report::{ActiveDispute, DisputeReport, RunReport, ValidationReport};
+pub use retry::{DeadLetter, RetryBuffer};
+pub use risk::{DisputedDeposits, NearLimit, RapidWithdrawals, RiskPolicy, RiskRule};
+pub use savings::SavingsAccount;
+pub use schedule::{Interval, Schedule, ScheduleSummary, StandingOrder};
+pub use server::{ReadView, Server};
+pub use sharded::ShardedEngine;
+pub use state::{FileStateStore, MemoryStateStore, StateStore};
+pub use statement::{Statement, StatementFormat};
+pub use storage::{DiskStore, Storage, TransactionStore};
+pub use throttle::{RateLimit, RateLimiter, RateLimits, Throttled};
+pub use tier::{Tier, TierPolicy, Tiers};
+pub use timestamp::Timestamp;
+pub use transaction::{Amount, Origin, Transaction, TransactionId, TransactionType};
+pub use wal::{WalEntry, WriteAheadLog};
+pub use webhook::{
+    EndpointStats, WebhookEvent, WebhookNotifier, WebhookStats, WebhookUrl, Webhooks,
+};
+pub use window::{DirWindowSink, WindowPolicy, WindowReport, WindowSink, Windows};
@@ -0,0 +1,208 @@
+//! Leveled, structured logging to stderr.
+//!
+//! Events carry a message and key-value fields and are prefixed with the spans open on the
+//! current thread, e.g.
+//!
+//! ```text
+//!  WARN ingest{file=input.csv}: Rejected transaction tx=5 reason="transaction 5 was never applied"
+//! ```
+//!
+//! The maximum level is global and defaults to [`Level::Error`].
+
+use std::cell::RefCell;
+use std::fmt::{self, Display, Write as _};
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(format!("unknown log level '{}'", value)),
+        }
+    }
+}
+
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Error as u8);
+
+thread_local! {
+    /// Formatted spans entered on this thread, outermost first
+    static SPANS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Level selected by a `RUST_LOG` style filter such as `warn,rust_coding_test=debug`. Directives
+/// for other targets are ignored.
+pub fn parse_filter(filter: &str) -> Option<Level> {
+    let mut level = None;
+    for directive in filter.split(',').map(str::trim) {
+        match directive.split_once('=') {
+            Some(("rust_coding_test" | "rust-coding-test", value)) => {
+                return value.parse().ok();
+            }
+            Some(_) => {}
+            None => level = directive.parse().ok().or(level),
+        }
+    }
+    level
+}
+
+/// Key-value pair attached to an event or span
+pub type Field<'a> = (&'a str, &'a dyn Display);
+
+/// Writes the event to stderr if its level is enabled
+pub fn event(level: Level, message: &str, fields: &[Field]) {
+    if !enabled(level) {
+        return;
+    }
+    let line = SPANS.with(|spans| format_event(level, &spans.borrow(), message, fields));
+    // Nothing sensible can be done when stderr is gone
+    let _ = writeln!(std::io::stderr().lock(), "{}", line);
+}
+
+pub fn warn(message: &str, fields: &[Field]) {
+    event(Level::Warn, message, fields)
+}
+
+pub fn info(message: &str, fields: &[Field]) {
+    event(Level::Info, message, fields)
+}
+
+pub fn debug(message: &str, fields: &[Field]) {
+    event(Level::Debug, message, fields)
+}
+
+/// Scope whose name and fields prefix every event logged on the thread while it is entered.
+/// Leaving it logs how long it was entered at debug level.
+pub struct Span {
+    started: Instant,
+}
+
+impl Span {
+    pub fn enter(name: &str, fields: &[Field]) -> Self {
+        let mut span = name.to_string();
+        if !fields.is_empty() {
+            span.push('{');
+            write_fields(&mut span, fields, ",");
+            span.push('}');
+        }
+        SPANS.with(|spans| spans.borrow_mut().push(span));
+        Span {
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let elapsed = self.started.elapsed();
+        debug("close", &[("elapsed", &format_args!("{:?}", elapsed))]);
+        SPANS.with(|spans| spans.borrow_mut().pop());
+    }
+}
+
+fn format_event(level: Level, spans: &[String], message: &str, fields: &[Field]) -> String {
+    let mut line = format!("{:>5} ", level.as_str());
+    if !spans.is_empty() {
+        line.push_str(&spans.join(":"));
+        line.push_str(": ");
+    }
+    line.push_str(message);
+    if !fields.is_empty() {
+        line.push(' ');
+        write_fields(&mut line, fields, " ");
+    }
+    line
+}
+
+/// Values are quoted when they contain whitespace or quotes so that lines stay parseable
+fn write_fields(out: &mut String, fields: &[Field], separator: &str) {
+    for (i, (key, value)) in fields.iter().enumerate() {
+        if i > 0 {
+            out.push_str(separator);
+        }
+        let value = value.to_string();
+        let quote = value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"');
+        let _ = if quote {
+            write!(out, "{}={:?}", key, value)
+        } else {
+            write!(out, "{}={}", key, value)
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::log::{format_event, parse_filter, Level};
+
+        #[test]
+        fn events_are_formatted_with_spans_and_fields() {
+            let spans = vec!["ingest{file=in.csv}".to_string()];
+
+            assert_eq!(
+                format_event(
+                    Level::Warn,
+                    &spans,
+                    "Rejected transaction",
+                    &[("tx", &5), ("reason", &"not enough funds")]
+                ),
+                " WARN ingest{file=in.csv}: Rejected transaction tx=5 reason=\"not enough funds\""
+            );
+            assert_eq!(format_event(Level::Debug, &[], "done", &[]), "DEBUG done");
+        }
+
+        #[test]
+        fn filter_selects_level_of_this_crate() {
+            assert_eq!(parse_filter("debug"), Some(Level::Debug));
+            assert_eq!(
+                parse_filter("warn,rust_coding_test=trace,hyper=info"),
+                Some(Level::Trace)
+            );
+            assert_eq!(parse_filter("hyper=info"), None);
+            assert_eq!(parse_filter("loud"), None);
+        }
+    }
+}
@@ -1,42 +1,1218 @@
-use crate::engine::TransactionEngine;
-use crate::transaction::Transaction;
-use csv::{ReaderBuilder, Trim};
+use crate::cli::{
+    AdvanceTimeCli, AnalyzeCli, ApplyFeesCli, Cli, CliError, Command, DiffCli, ExportCli,
+    GenDataCli, ImportCli, MergeClientsCli, QueryCli, ReplCli, ServeCli, StatementsCli,
+    VerifyOutputCli,
+};
+use crate::progress::Progress;
+use rust_coding_test::input::STDIN;
+use rust_coding_test::log::{self, Level, Span};
+use rust_coding_test::{
+    discover_inputs, open_source_with, AccountSnapshot, AccountWriter, Analysis, ClientReport,
+    ConcurrentEngine, ConfigFile, CsvAccountWriter, DeadLetter, DiffFormat, DirWindowSink,
+    DisputeReport, EngineConfig, EngineError, ExportFormat, FileStateStore, InputError,
+    InputFormat, InvariantReport, Journal, JournalFormat, JsonAccountWriter, JsonlAccountWriter,
+    JsonlAuditSink, JsonlChangeSink, MultiFileSource, OrderedDispatcher, OrderedSource, Origin,
+    OutputFormat, OutputOrder, Pipeline, PipelineMetrics, ReadAheadSource, Reconciliation, Repl,
+    ReportFormat, RetryBuffer, RunReport, Schedule, Server, ShardedEngine, SnapshotError,
+    Statement, StatementFormat, Storage, TableAccountWriter, Trailer, TrailerWriter, Transaction,
+    TransactionEngine, TransactionNamespaces, TransactionProcessor, TransactionSource,
+    ValidationReport, WindowReport, Windows, CSV_COLUMNS, DEFAULT_DECIMALS,
+};
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufWriter, IsTerminal, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
 
-mod account;
-mod engine;
-mod transaction;
+mod cli;
+mod progress;
 
 fn main() {
-    // TODO: use clap for better CLI interface
-    let path = std::env::args().nth(1).expect("No file path provided");
-
-    let mut reader = ReaderBuilder::new()
-        .trim(Trim::All)
-        // Require flexible since the "amount" field may sometimes be unspecified
-        .flexible(true)
-        .from_path(path)
-        .expect("Failed to build file reader");
-
-    let mut transaction_engine = TransactionEngine::new();
-
-    for result in reader.deserialize() {
-        let transaction: Transaction = result.expect("Failed to deserialize");
-        transaction_engine.execute(transaction);
-    }
-
-    // TODO: Could move to a special writer object or use csv writer
-    println!("client, available, held, total, locked");
-    for account in transaction_engine.accounts.values() {
-        println!(
-            "{}",
-            format!(
-                "{}, {:.4}, {:.4}, {:.4}, {}",
-                account.get_client_id(),
-                account.get_available_funds(),
-                account.get_held_funds(),
-                account.get_total_funds(),
-                account.is_locked(),
-            ),
+    let mut command = match Command::parse(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(CliError::Help) => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("Error: {}\n\n{}", err, cli::USAGE);
+            process::exit(2);
+        }
+    };
+
+    let discovered = if command.reads_config() {
+        ConfigFile::discover(command.config_file())
+    } else {
+        Ok(ConfigFile::default())
+    };
+    let file = match discovered {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    };
+    if let Err(err) = command.merge(&file) {
+        eprintln!("Error: {}\n\n{}", err, cli::USAGE);
+        process::exit(2);
+    }
+
+    let (log_level, verbose) = match &command {
+        Command::Process(cli) => (cli.log_level, cli.verbose),
+        Command::Serve(cli) => (cli.log_level, false),
+        Command::ApplyFees(cli) => (cli.batch.log_level, false),
+        Command::AdvanceTime(cli) => (cli.batch.log_level, false),
+        Command::MergeClients(cli) => (cli.batch.log_level, false),
+        Command::Repl(cli) => (cli.log_level, false),
+        Command::GenData(_)
+        | Command::Query(_)
+        | Command::Analyze(_)
+        | Command::Statements(_)
+        | Command::Diff(_)
+        | Command::VerifyOutput(_)
+        | Command::Export(_)
+        | Command::Import(_) => (None, false),
+    };
+    log::set_max_level(
+        log_level
+            .or_else(|| {
+                env::var("RUST_LOG")
+                    .ok()
+                    .and_then(|filter| log::parse_filter(&filter))
+            })
+            .unwrap_or(if verbose { Level::Warn } else { Level::Error }),
+    );
+
+    let result = match &command {
+        Command::Process(cli) if cli.validate => validate(cli, &file),
+        Command::Process(cli) => run(cli, &file),
+        Command::Serve(cli) => serve(cli, &file),
+        Command::GenData(cli) => gen_data(cli),
+        Command::ApplyFees(cli) => apply_fees(cli, &file),
+        Command::AdvanceTime(cli) => advance_time(cli, &file),
+        Command::Query(cli) => query(cli, &file),
+        Command::Analyze(cli) => analyze(cli, &file),
+        Command::Statements(cli) => statements(cli, &file),
+        Command::Repl(cli) => repl(cli, &file),
+        Command::MergeClients(cli) => merge_clients(cli, &file),
+        Command::Diff(cli) => diff(cli, &file),
+        Command::VerifyOutput(cli) => verify(cli),
+        Command::Export(cli) => export(cli, &file),
+        Command::Import(cli) => import(cli, &file),
+    };
+    if let Err(err) = result {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run(cli: &Cli, file: &ConfigFile) -> Result<(), Box<dyn Error>> {
+    let started = Instant::now();
+    let _span = Span::enter("ingest", &[("file", &cli.input.display())]);
+    let inputs = discover_inputs(&cli.input, cli.input_format)?;
+    let progress = if cli.progress {
+        Progress::start(input_size(&inputs))
+    } else {
+        None
+    };
+    let read = progress.as_ref().map(Progress::counter);
+    let parallel = (cli.unordered || cli.parallel_read) && inputs.len() > 1;
+    let (mut source, pipeline) = if parallel {
+        (None, None)
+    } else {
+        let (source, pipeline) = open_inputs(inputs.clone(), cli, read.clone())?;
+        (Some(source), pipeline)
+    };
+
+    let mut config = cli.engine.config(file)?;
+    // The check replays the history of the run
+    config.record_history |= cli.check;
+    config.profile |= cli.profile;
+    let mut skipped = SkippedRows::new(cli)?;
+    let origin = if cli.admin {
+        Origin::Admin
+    } else {
+        Origin::Client
+    };
+    let resumed = cli
+        .checkpoint
+        .as_ref()
+        .filter(|path| cli.resume && path.exists());
+    let restored = resumed.or(cli.restore.as_ref());
+    // Read before the checkpoint is replaced
+    let start = if cli.check {
+        Some(start_engine(restored, config.clone())?)
+    } else {
+        None
+    };
+
+    // Malformed or rejected rows of an atomic input that was rolled back
+    let mut rolled_back = None;
+    let (transaction_engine, rows_read) = match (cli.threads, source.as_mut()) {
+        (Some(threads), None) => {
+            ingest_in_parallel(inputs, threads, config, origin, cli, read, &mut skipped)?
+        }
+        (Some(threads), Some(source)) => execute_all(
+            ShardedEngine::new(threads, config),
+            source.as_mut(),
+            cli,
+            &mut skipped,
+            origin,
+        )?,
+        (None, source) => {
+            let source = source.expect("Only sharded runs read files in parallel");
+            let mut transaction_engine = start_engine(restored, config)?;
+            let resumed_rows = match resumed {
+                Some(_) => transaction_engine.input_offset(),
+                None => 0,
+            };
+            if resumed.is_some() {
+                log::info("Resuming from checkpoint", &[("rows", &resumed_rows)]);
+            }
+            if let Some(path) = &cli.audit_log {
+                transaction_engine.set_audit_sink(Box::new(JsonlAuditSink::create(path)?));
+            }
+            let journal = match &cli.journal {
+                Some(path) => {
+                    let format = cli
+                        .journal_format
+                        .unwrap_or_else(|| JournalFormat::from_path(path));
+                    Some(
+                        Journal::append(&mut transaction_engine, path, format)?
+                            .with_decimals(cli.amounts().max_decimals as usize),
+                    )
+                }
+                None => None,
+            };
+            let mut windows = match &cli.window_dir {
+                Some(dir) => {
+                    let sink = DirWindowSink::create(dir)?
+                        .with_decimals(cli.amounts().max_decimals as usize);
+                    Some(Windows::new(
+                        &mut transaction_engine,
+                        cli.windows(),
+                        Box::new(sink),
+                    ))
+                }
+                None => None,
+            };
+            let mut checkpointed = resumed_rows;
+            let batch = if cli.atomic {
+                Some(transaction_engine.begin_batch()?)
+            } else {
+                None
+            };
+            let mut retry = cli
+                .dead_letters
+                .as_ref()
+                .map(|_| RetryBuffer::new(cli.retry_after.map(|rows| rows as u64)));
+            let rows_read = for_each_transaction(
+                source.as_mut(),
+                cli,
+                &mut skipped,
+                resumed_rows,
+                |transaction, rows| {
+                    match retry.as_mut() {
+                        Some(retry) => {
+                            for err in retry.execute(&mut transaction_engine, transaction, origin) {
+                                report_rejected(&err);
+                            }
+                        }
+                        None => {
+                            if let Err(err) = transaction_engine.execute_from(transaction, origin) {
+                                report_rejected(&err);
+                            }
+                        }
+                    }
+                    if let Some(windows) = windows.as_mut() {
+                        if let Some(report) = windows.tick(&mut transaction_engine)? {
+                            report_window(&report);
+                        }
+                    }
+                    if let Some(path) = &cli.checkpoint {
+                        if rows - checkpointed >= cli.checkpoint_every as u64 {
+                            transaction_engine.checkpoint_at(path, rows)?;
+                            checkpointed = rows;
+                        }
+                    }
+                    Ok(())
+                },
+            )?;
+            if let (Some(retry), Some(path)) = (retry, &cli.dead_letters) {
+                let dead_letters = retry.finish(&mut transaction_engine);
+                write_dead_letters(path, &dead_letters, cli.input_format)?;
+            }
+            if let Some(windows) = windows {
+                if let Some(report) = windows.finish(&mut transaction_engine)? {
+                    report_window(&report);
+                }
+            }
+            if let Some(batch) = batch {
+                let rejects = batch.rejected(&transaction_engine)
+                    + skipped.lines.len() as u64
+                    + skipped.out_of_order;
+                if rejects > cli.max_rejects.unwrap_or(0) {
+                    transaction_engine.rollback(batch)?;
+                    rolled_back = Some(rejects);
+                }
+            }
+            transaction_engine.flush_audit()?;
+            if let Some(journal) = journal {
+                journal.finish()?;
+            }
+            (transaction_engine, rows_read)
+        }
+    };
+    drop(progress);
+
+    if let Some(start) = start {
+        let report = InvariantReport::check(&transaction_engine, start)?;
+        if !report.is_ok() {
+            eprint!("{}", report);
+            return Err(format!("found {} invariant violations", report.violations.len()).into());
+        }
+    }
+    if let Some(path) = &cli.snapshot {
+        transaction_engine.snapshot(path)?;
+    }
+    let malformed_rows = skipped.lines.len() as u64;
+    let filtered_rows = skipped.filtered;
+    skipped.finish()?;
+    if cli.stats || cli.report_file.is_some() {
+        let mut report = RunReport::new(
+            &transaction_engine,
+            rows_read,
+            malformed_rows,
+            started.elapsed(),
+        );
+        report.filtered_rows = filtered_rows;
+        report.dormant = cli.dormant_after.map(|transactions| {
+            (
+                transactions,
+                transaction_engine.dormant_accounts(transactions),
+            )
+        });
+        report.queue_peaks = pipeline.map(|metrics| metrics.peaks());
+        if cli.stats {
+            eprint!("{}", report);
+        }
+        if let Some(path) = &cli.report_file {
+            fs::write(path, report.to_string())?;
+        }
+    }
+    if let Some(profile) = transaction_engine.profile() {
+        eprint!("{}", profile);
+    }
+
+    write_accounts(
+        &transaction_engine,
+        cli.output.as_deref(),
+        cli.format,
+        cli.sort_output.unwrap_or_default(),
+        cli.amounts().max_decimals as usize,
+        cli.trailer,
+    )?;
+    if let Some(path) = &cli.disputes_output {
+        DisputeReport::new(&transaction_engine)?.write_csv(BufWriter::new(File::create(path)?))?;
+    }
+    // The run completed, there is nothing left to resume
+    if let Some(path) = cli.checkpoint.as_ref().filter(|path| path.exists()) {
+        fs::remove_file(path)?;
+    }
+    if let Some(rejects) = rolled_back {
+        return Err(format!(
+            "rolled back the input, {} rows were malformed or rejected and at most {} may be",
+            rejects,
+            cli.max_rejects.unwrap_or(0)
         )
+        .into());
+    }
+    Ok(())
+}
+
+/// Engine a batch run starts from, restored from the snapshot if given
+fn start_engine(
+    snapshot: Option<&PathBuf>,
+    config: EngineConfig,
+) -> Result<TransactionEngine, SnapshotError> {
+    match snapshot {
+        Some(path) => TransactionEngine::restore_with_config(path, config),
+        None => Ok(TransactionEngine::with_config(config)),
+    }
+}
+
+/// Runs the input on a scratch engine, started from `--restore` if given, and prints the
+/// problems found instead of the accounts. Files are read one after the other.
+fn validate(cli: &Cli, file: &ConfigFile) -> Result<(), Box<dyn Error>> {
+    let inputs = discover_inputs(&cli.input, cli.input_format)?;
+    let (mut source, _) = open_inputs(inputs, cli, None)?;
+    let config = cli.engine.config(file)?;
+    let mut scratch = match &cli.restore {
+        Some(path) => TransactionEngine::restore_with_config(path, config)?,
+        None => TransactionEngine::with_config(config),
+    };
+    let origin = if cli.admin {
+        Origin::Admin
+    } else {
+        Origin::Client
+    };
+
+    let report = ValidationReport::collect(&mut scratch, source.as_mut(), origin, &cli.filter)?;
+    match cli.output.as_deref() {
+        Some(path) => fs::write(path, report.to_string())?,
+        None => print!("{}", report),
+    }
+    if report.is_valid() {
+        Ok(())
+    } else {
+        Err(format!("found {} problems in the input", report.problems()).into())
+    }
+}
+
+/// Decimal places of the balances written by commands without input, those of the input amounts
+/// of the configuration file
+fn output_decimals(file: &ConfigFile) -> usize {
+    file.io
+        .max_decimals
+        .map_or(DEFAULT_DECIMALS, |decimals| decimals as usize)
+}
+
+/// Writes every account to `output`, or stdout if not given, with amounts of `decimals` places,
+/// followed by a [`Trailer`] if asked for
+fn write_accounts(
+    transaction_engine: &TransactionEngine,
+    output: Option<&Path>,
+    format: Option<OutputFormat>,
+    order: OutputOrder,
+    decimals: usize,
+    trailer: bool,
+) -> Result<(), Box<dyn Error>> {
+    let sink: Box<dyn io::Write> = match output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    let mut sink = TrailerWriter::new(sink);
+    let mut counted = Trailer::default();
+    let currencies = transaction_engine
+        .accounts
+        .values()
+        .any(|account| account.currencies().iter().any(Option::is_some));
+    let closed = transaction_engine
+        .accounts
+        .keys()
+        .any(|client_id| transaction_engine.is_closed(*client_id));
+    let reserved = transaction_engine
+        .iter_snapshots()
+        .any(|snapshot| snapshot.reserved != 0.0);
+    let format = format.unwrap_or_default();
+    let mut writer: Box<dyn AccountWriter + '_> = match format {
+        OutputFormat::Csv => {
+            let mut writer = CsvAccountWriter::new(&mut sink).with_decimals(decimals);
+            if reserved {
+                writer = writer.with_reserved_column();
+            }
+            if closed {
+                writer = writer.with_closed_column();
+            }
+            if currencies {
+                writer = writer.with_currency_column();
+            }
+            Box::new(writer)
+        }
+        OutputFormat::Json => Box::new(JsonAccountWriter::new(&mut sink).with_decimals(decimals)),
+        OutputFormat::Jsonl => Box::new(JsonlAccountWriter::new(&mut sink).with_decimals(decimals)),
+        OutputFormat::Table => Box::new(TableAccountWriter::new(&mut sink).with_decimals(decimals)),
+    };
+    for snapshot in transaction_engine.snapshots(order) {
+        counted.count(&snapshot, decimals);
+        writer.write_snapshot(&snapshot)?;
+    }
+    writer.finish()?;
+    drop(writer);
+    if trailer {
+        sink.finish(counted, format, decimals)?;
+    }
+
+    Ok(())
+}
+
+fn verify(cli: &VerifyOutputCli) -> Result<(), Box<dyn Error>> {
+    let contents = fs::read(&cli.path).map_err(|err| format!("{}: {}", cli.path.display(), err))?;
+    let trailer = rust_coding_test::verify_output(&contents)
+        .map_err(|err| format!("{}: {}", cli.path.display(), err))?;
+    println!(
+        "{}: {} rows, totals adding up to {}, checksum verified",
+        cli.path.display(),
+        trailer.rows,
+        trailer.total
+    );
+    Ok(())
+}
+
+/// Serves the gRPC interface of the server on its own thread
+#[cfg(feature = "grpc")]
+fn serve_grpc(server: &Server, address: SocketAddr) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(address)?;
+    println!("Serving gRPC on {}", listener.local_addr()?);
+    let server = server.clone();
+    thread::spawn(move || {
+        if let Err(err) = server.serve_grpc(listener) {
+            log::event(Level::Error, "gRPC listener failed", &[("error", &err)]);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(feature = "grpc"))]
+fn serve_grpc(_: &Server, _: SocketAddr) -> Result<(), Box<dyn Error>> {
+    Err("--grpc-listen needs the grpc feature, e.g. cargo build --features grpc".into())
+}
+
+fn serve(cli: &ServeCli, file: &ConfigFile) -> Result<(), Box<dyn Error>> {
+    let config = cli.engine.config(file)?;
+    let restore = cli
+        .restore
+        .as_ref()
+        .or(cli.checkpoint.as_ref().filter(|path| path.exists()));
+    let mut transaction_engine = match (&cli.state, restore) {
+        (Some(directory), _) => {
+            let store = FileStateStore::open(directory, cli.wal_sync_every)?;
+            TransactionEngine::open_store(Box::new(store), config)?
+        }
+        (None, Some(path)) => TransactionEngine::restore_with_config(path, config)?,
+        (None, None) => TransactionEngine::with_config(config),
+    };
+    if let Some(path) = &cli.wal {
+        let replayed = transaction_engine.open_wal(path, cli.wal_sync_every)?;
+        log::info(
+            "Replayed write-ahead log",
+            &[("path", &path.display()), ("transactions", &replayed)],
+        );
+    }
+    if let Some(path) = &cli.audit_log {
+        transaction_engine.set_audit_sink(Box::new(JsonlAuditSink::create(path)?));
+    }
+
+    let mut server = Server::new(transaction_engine);
+    if let Some(path) = &cli.checkpoint {
+        server = server.with_checkpoints(path, cli.checkpoint_every as u64);
+    }
+    if cli.state.is_some() {
+        server = server.with_state_saves(cli.checkpoint_every as u64);
+    }
+    if let Some(token) = &cli.admin_token {
+        server = server.with_admin_token(token);
+    }
+    server = server.with_rate_limits(cli.rate_limits);
+    if let Some(interval) = cli.read_view_every {
+        server = server.with_read_view(interval as u64);
+    }
+    if cli.webhooks.is_enabled() {
+        server = server.with_webhooks(&cli.webhooks);
+    }
+    match cli.changes_output.as_deref() {
+        Some(path) if path == Path::new("-") => {
+            server = server.with_changes(Box::new(JsonlChangeSink::stdout()));
+        }
+        Some(path) => server = server.with_changes(Box::new(JsonlChangeSink::create(path)?)),
+        None => {}
+    }
+    if let Some(address) = cli.grpc_listen {
+        serve_grpc(&server, address)?;
+    }
+    let listener = TcpListener::bind(cli.listen)?;
+    println!("Listening on {}", listener.local_addr()?);
+    server.serve(listener)?;
+    Ok(())
+}
+
+fn apply_fees(cli: &ApplyFeesCli, file: &ConfigFile) -> Result<(), Box<dyn Error>> {
+    let schedule = cli.schedule(file)?;
+    let config = cli.engine.config(file)?;
+    let mut transaction_engine = TransactionEngine::restore_with_config(&cli.restore, config)?;
+    if let Some(path) = &cli.batch.audit_log {
+        transaction_engine.set_audit_sink(Box::new(JsonlAuditSink::create(path)?));
+    }
+    let summary = transaction_engine.apply_fees(&schedule);
+    transaction_engine.flush_audit()?;
+    for err in &summary.errors {
+        report_rejected(err);
+    }
+    eprint!("{}", summary);
+
+    if let Some(path) = &cli.batch.snapshot {
+        transaction_engine.snapshot(path)?;
+    }
+    write_accounts(
+        &transaction_engine,
+        cli.batch.output.as_deref(),
+        cli.batch.format,
+        cli.batch.sort_output.unwrap_or_default(),
+        output_decimals(file),
+        false,
+    )
+}
+
+fn advance_time(cli: &AdvanceTimeCli, file: &ConfigFile) -> Result<(), Box<dyn Error>> {
+    let mut schedule = Schedule::load(&cli.schedule)?;
+    let config = cli.engine.config(file)?;
+    let mut transaction_engine = TransactionEngine::restore_with_config(&cli.restore, config)?;
+    if let Some(path) = &cli.batch.audit_log {
+        transaction_engine.set_audit_sink(Box::new(JsonlAuditSink::create(path)?));
+    }
+    let summary = transaction_engine.advance_time(&mut schedule, cli.ticks, cli.until);
+    transaction_engine.flush_audit()?;
+    for err in &summary.errors {
+        report_rejected(err);
+    }
+    eprint!("{}", summary);
+
+    if let Some(path) = &cli.batch.snapshot {
+        transaction_engine.snapshot(path)?;
+    }
+    write_accounts(
+        &transaction_engine,
+        cli.batch.output.as_deref(),
+        cli.batch.format,
+        cli.batch.sort_output.unwrap_or_default(),
+        output_decimals(file),
+        false,
+    )
+}
+
+fn merge_clients(cli: &MergeClientsCli, file: &ConfigFile) -> Result<(), Box<dyn Error>> {
+    let config = cli.engine.config(file)?;
+    let mut transaction_engine = TransactionEngine::restore_with_config(&cli.restore, config)?;
+    if let Some(path) = &cli.batch.audit_log {
+        transaction_engine.set_audit_sink(Box::new(JsonlAuditSink::create(path)?));
+    }
+    transaction_engine.merge_clients(cli.from, cli.into)?;
+    transaction_engine.flush_audit()?;
+    log::info(
+        "Merged accounts",
+        &[("client", &cli.from), ("into", &cli.into)],
+    );
+
+    if let Some(path) = &cli.batch.snapshot {
+        transaction_engine.snapshot(path)?;
+    }
+    write_accounts(
+        &transaction_engine,
+        cli.batch.output.as_deref(),
+        cli.batch.format,
+        cli.batch.sort_output.unwrap_or_default(),
+        output_decimals(file),
+        false,
+    )
+}
+
+/// Configuration of the commands working on a snapshot or export rather than on a running engine.
+/// Transaction logs are restored in memory so that no store of a running engine is touched.
+fn offline_config(file: &ConfigFile) -> EngineConfig {
+    EngineConfig {
+        storage: Storage::Memory,
+        ..file.engine_config()
+    }
+}
+
+fn query(cli: &QueryCli, file: &ConfigFile) -> Result<(), Box<dyn Error>> {
+    let config = offline_config(file);
+    let mut transaction_engine = TransactionEngine::restore_with_config(&cli.restore, config)?;
+    if let Some(transaction_id) = cli.as_of {
+        transaction_engine = transaction_engine.state_at(transaction_id)?;
+    }
+    let report = ClientReport::new(&transaction_engine, cli.client_id, cli.recent)?
+        .ok_or_else(|| format!("client {} has no account", cli.client_id))?;
+    match cli.format {
+        ReportFormat::Text => print!("{}", report),
+        ReportFormat::Json => println!("{}", report.to_json()),
+    }
+    Ok(())
+}
+
+fn analyze(cli: &AnalyzeCli, file: &ConfigFile) -> Result<(), Box<dyn Error>> {
+    let config = offline_config(file);
+    let transaction_engine = TransactionEngine::restore_with_config(&cli.restore, config)?;
+    let analysis = Analysis::new(&transaction_engine, cli.top, cli.currency)?;
+    match cli.format {
+        ReportFormat::Text => print!("{}", analysis),
+        ReportFormat::Json => println!("{}", analysis.to_json()),
+    }
+    Ok(())
+}
+
+fn export(cli: &ExportCli, file: &ConfigFile) -> Result<(), Box<dyn Error>> {
+    let config = offline_config(file);
+    let transaction_engine = TransactionEngine::restore_with_config(&cli.restore, config)?;
+    let writer: Box<dyn Write> = match &cli.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+    match cli.format {
+        ExportFormat::Jsonl => transaction_engine.export(writer)?,
+    }
+    Ok(())
+}
+
+fn import(cli: &ImportCli, file: &ConfigFile) -> Result<(), Box<dyn Error>> {
+    let config = offline_config(file);
+    let input =
+        File::open(&cli.input).map_err(|err| format!("{}: {}", cli.input.display(), err))?;
+    let transaction_engine = TransactionEngine::import(io::BufReader::new(input), config)
+        .map_err(|err| format!("{}: {}", cli.input.display(), err))?;
+    transaction_engine.snapshot(&cli.snapshot)?;
+    log::info(
+        "Imported engine state",
+        &[
+            ("accounts", &transaction_engine.accounts.len()),
+            ("snapshot", &cli.snapshot.display()),
+        ],
+    );
+    Ok(())
+}
+
+fn statements(cli: &StatementsCli, file: &ConfigFile) -> Result<(), Box<dyn Error>> {
+    let config = offline_config(file);
+    let transaction_engine = TransactionEngine::restore_with_config(&cli.restore, config)?;
+    let mut statements = Statement::all(&transaction_engine, cli.after)?;
+    if let Some(client_id) = cli.client_id {
+        statements.retain(|statement| statement.client_id == client_id);
+        if statements.is_empty() {
+            return Err(format!("client {} has no account", client_id).into());
+        }
+    }
+    fs::create_dir_all(&cli.output_dir)?;
+    let decimals = output_decimals(file);
+    for statement in &statements {
+        let contents = match cli.format {
+            StatementFormat::Csv => statement.to_csv(decimals),
+            StatementFormat::Text => statement.to_text(decimals),
+        };
+        let name = format!("client-{}.{}", statement.client_id, cli.format.extension());
+        fs::write(cli.output_dir.join(name), contents)?;
+    }
+    log::info(
+        "Wrote statements",
+        &[
+            ("statements", &statements.len()),
+            ("directory", &cli.output_dir.display()),
+        ],
+    );
+    Ok(())
+}
+
+fn repl(cli: &ReplCli, file: &ConfigFile) -> Result<(), Box<dyn Error>> {
+    let mut config = cli.engine.config(file)?;
+    // Shown by the show command
+    config.record_history = true;
+    let origin = if cli.admin {
+        Origin::Admin
+    } else {
+        Origin::Client
+    };
+    let mut repl = Repl::new(start_engine(cli.restore.as_ref(), config)?, origin);
+    let stdin = io::stdin();
+    // Piped sessions only get the replies
+    let prompt = stdin.is_terminal();
+    if prompt {
+        println!("Type help for the commands, quit to leave");
+    }
+    let mut lines = stdin.lock().lines();
+    loop {
+        if prompt {
+            print!("> ");
+            io::stdout().flush()?;
+        }
+        let Some(line) = lines.next() else {
+            break;
+        };
+        match repl.eval(&line?) {
+            Some(reply) if reply.is_empty() => {}
+            Some(reply) => println!("{}", reply),
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+fn diff(cli: &DiffCli, file: &ConfigFile) -> Result<(), Box<dyn Error>> {
+    let left = read_accounts(&cli.left, file)?;
+    let right = read_accounts(&cli.right, file)?;
+    let reconciliation = Reconciliation::new(left, right, cli.tolerance);
+    let report = match cli.format {
+        DiffFormat::Text => reconciliation.to_string(),
+        DiffFormat::Csv => reconciliation.to_csv(),
+        DiffFormat::Json => format!("{}\n", reconciliation.to_json()),
+    };
+    match &cli.output {
+        Some(path) => fs::write(path, report)?,
+        None => print!("{}", report),
+    }
+    if reconciliation.is_clean() {
+        Ok(())
+    } else {
+        Err(format!("{} accounts differ", reconciliation.diffs.len()).into())
+    }
+}
+
+/// Accounts of a snapshot, recognized by its first record, or of a csv output
+fn read_accounts(path: &Path, file: &ConfigFile) -> Result<Vec<AccountSnapshot>, Box<dyn Error>> {
+    let contents = fs::read(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    if contents.starts_with(b"snapshot,") {
+        let transaction_engine =
+            TransactionEngine::restore_with_config(path, offline_config(file))?;
+        return Ok(transaction_engine.sorted_snapshots().collect());
+    }
+    let accounts = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(contents.as_slice())
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .map_err(|err| format!("{}: {}", path.display(), err))?;
+    Ok(accounts)
+}
+
+fn gen_data(cli: &GenDataCli) -> Result<(), Box<dyn Error>> {
+    let sink: Box<dyn io::Write> = match &cli.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout().lock()),
+    };
+    let rows = cli.workload.write_csv(BufWriter::new(sink))?;
+    log::info("Generated transactions", &[("rows", &rows)]);
+    Ok(())
+}
+
+/// Malformed rows skipped while reading the input
+struct SkippedRows {
+    lines: Vec<u64>,
+    /// Rows rejected by the ordering of the input, which have no line number
+    out_of_order: u64,
+    /// Rows of clients left out by the client filter, which are not malformed
+    filtered: u64,
+    format: InputFormat,
+    /// Receives the skipped rows so that they can be fixed and processed again
+    rejects: Option<BufWriter<File>>,
+}
+
+impl SkippedRows {
+    /// Line numbers listed at most in the report
+    const REPORTED_LINES: usize = 10;
+
+    fn new(cli: &Cli) -> io::Result<Self> {
+        let rejects = match &cli.rejects_file {
+            Some(path) => {
+                let mut writer = BufWriter::new(File::create(path)?);
+                if cli.input_format == InputFormat::Csv {
+                    writeln!(writer, "{}", CSV_COLUMNS.join(","))?;
+                }
+                Some(writer)
+            }
+            None => None,
+        };
+        Ok(SkippedRows {
+            lines: Vec::new(),
+            out_of_order: 0,
+            filtered: 0,
+            format: cli.input_format,
+            rejects,
+        })
+    }
+
+    fn record(&mut self, err: &InputError) -> io::Result<()> {
+        log::warn("Skipped malformed row", &[("error", err)]);
+        let row = match err {
+            InputError::Malformed { line, row, .. } => {
+                self.lines.push(*line);
+                row.clone()
+            }
+            InputError::OutOfOrder { transaction, .. } => {
+                self.out_of_order += 1;
+                self.format.format_row(transaction)
+            }
+            _ => String::new(),
+        };
+        if let Some(rejects) = self.rejects.as_mut() {
+            if !row.is_empty() {
+                writeln!(rejects, "{}", row)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes the rejects file and reports the skipped rows on stderr
+    fn finish(self) -> io::Result<()> {
+        if let Some(mut rejects) = self.rejects {
+            rejects.flush()?;
+        }
+        if !self.lines.is_empty() {
+            let mut lines: Vec<_> = self
+                .lines
+                .iter()
+                .take(Self::REPORTED_LINES)
+                .map(u64::to_string)
+                .collect();
+            if self.lines.len() > Self::REPORTED_LINES {
+                lines.push("...".to_string());
+            }
+            eprintln!(
+                "Skipped {} malformed rows on lines {}",
+                self.lines.len(),
+                lines.join(", ")
+            );
+        }
+        if self.out_of_order > 0 {
+            eprintln!("Skipped {} rows arriving out of order", self.out_of_order);
+        }
+        if self.filtered > 0 {
+            eprintln!("Skipped {} rows of other clients", self.filtered);
+        }
+        Ok(())
+    }
+}
+
+/// Writes the rows that still failed once retried at the end of the input in its format, and
+/// reports them on stderr
+fn write_dead_letters(
+    path: &Path,
+    dead_letters: &[DeadLetter],
+    format: InputFormat,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    if format == InputFormat::Csv {
+        writeln!(writer, "{}", CSV_COLUMNS.join(","))?;
+    }
+    for dead_letter in dead_letters {
+        log::warn(
+            "Dead-lettered transaction",
+            &[
+                ("tx", &dead_letter.transaction.transaction_id),
+                ("reason", &dead_letter.error),
+            ],
+        );
+        writeln!(writer, "{}", format.format_row(&dead_letter.transaction))?;
     }
+    writer.flush()?;
+    if !dead_letters.is_empty() {
+        eprintln!(
+            "Wrote {} rows still failing once retried to {}",
+            dead_letters.len(),
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Source of the input with the metrics of its queues, if read through a pipeline
+type Inputs = (Box<dyn TransactionSource>, Option<Arc<PipelineMetrics>>);
+
+/// Source reading the input files one after the other, in the ordering of the input and read
+/// ahead or through a pipeline if requested
+fn open_inputs(
+    mut inputs: Vec<PathBuf>,
+    cli: &Cli,
+    read: Option<Arc<AtomicU64>>,
+) -> Result<Inputs, Box<dyn Error>> {
+    let mut source = if inputs.len() == 1 {
+        open_file(inputs.remove(0), cli, read)?
+    } else {
+        let mut source = MultiFileSource::new(inputs, cli.input_format)
+            .with_columns(cli.columns.clone())
+            .with_amounts(cli.amounts());
+        if cli.fast_parse {
+            source = source.fast_parse();
+        }
+        if cli.namespace_sources {
+            source = source.with_namespaces();
+        }
+        Box::new(match read {
+            Some(read) => source.counting(read),
+            None => source,
+        })
+    };
+    source = cli.sampled(source);
+    let window = cli.ordering.unwrap_or_default().window();
+    if let Some(capacities) = cli.pipeline() {
+        // Reordering is the validation stage, which otherwise passes transactions on
+        let validate =
+            move |parsed: Box<dyn TransactionSource + Send>| -> Box<dyn TransactionSource> {
+                match window {
+                    Some(window) => Box::new(OrderedSource::new(parsed, window)),
+                    None => parsed,
+                }
+            };
+        let pipeline = Pipeline::spawn(source, validate, capacities);
+        let metrics = pipeline.metrics();
+        return Ok((Box::new(pipeline), Some(metrics)));
+    }
+    if let Some(window) = window {
+        source = Box::new(OrderedSource::new(source, window));
+    }
+    let source = match cli.read_ahead {
+        Some(capacity) => Box::new(ReadAheadSource::spawn(source, capacity)),
+        None => source,
+    };
+    Ok((source, None))
+}
+
+/// Opens a file in the input format and with the column mapping of `cli`, adding the bytes read
+/// from it to `read` if given
+fn open_file(
+    path: PathBuf,
+    cli: &Cli,
+    read: Option<Arc<AtomicU64>>,
+) -> Result<Box<dyn TransactionSource + Send>, InputError> {
+    open_source_with(
+        path,
+        cli.input_format,
+        &cli.columns,
+        cli.amounts(),
+        cli.fast_parse,
+        read,
+    )
+}
+
+/// Total size of the input files, unknown when reading stdin
+fn input_size(inputs: &[PathBuf]) -> Option<u64> {
+    inputs
+        .iter()
+        .map(|path| match path.to_str() {
+            Some(STDIN) => None,
+            _ => fs::metadata(path).ok().map(|metadata| metadata.len()),
+        })
+        .sum()
+}
+
+/// Reads up to `threads` input files at once into an engine sharded over as many shards, in no
+/// particular order unless `--parallel-read` asks for the rows to be applied in file order.
+/// Malformed rows are recorded in file order once every file is read. Returns the merged engine
+/// and the number of rows read.
+fn ingest_in_parallel(
+    inputs: Vec<PathBuf>,
+    threads: usize,
+    config: EngineConfig,
+    origin: Origin,
+    cli: &Cli,
+    read: Option<Arc<AtomicU64>>,
+    skipped: &mut SkippedRows,
+) -> Result<(TransactionEngine, u64), Box<dyn Error>> {
+    let engine = if cli.parallel_read {
+        ParallelEngine::Ordered(OrderedDispatcher::new(ShardedEngine::new(threads, config)))
+    } else {
+        ParallelEngine::Unordered(ConcurrentEngine::new(threads, config))
+    };
+    let readers = threads.min(inputs.len());
+    let queue = Mutex::new(inputs.into_iter().enumerate());
+    let window = cli.ordering.unwrap_or_default().window();
+    let namespaces = cli
+        .namespace_sources
+        .then(|| Mutex::new(TransactionNamespaces::default()));
+    let read_file = |index, path: &PathBuf| -> Result<ReadFile, InputError> {
+        let mut source = open_file(path.clone(), cli, read.clone())?;
+        if let Some(window) = window {
+            source = Box::new(OrderedSource::new(source, window));
+        }
+        let mut file = ReadFile {
+            index,
+            rows: 0,
+            filtered: 0,
+            malformed: Vec::new(),
+        };
+        let name = path.file_name().unwrap_or(path.as_os_str());
+        let name = name.to_string_lossy();
+        while let Some(mut result) = source.next_transaction() {
+            file.rows += 1;
+            if let (Some(namespaces), Ok(transaction)) = (namespaces.as_ref(), result.as_mut()) {
+                let mut namespaces = namespaces.lock().expect("Namespaces lock poisoned");
+                if let Err(message) = namespaces.assign(index, &name, transaction) {
+                    result = Err(InputError::Malformed {
+                        line: file.rows,
+                        message,
+                        row: String::new(),
+                    });
+                }
+            }
+            match result {
+                Ok(transaction) if !cli.filter.allows(transaction.client_id) => {
+                    file.filtered += 1;
+                }
+                Ok(transaction) => engine.submit(index, transaction, origin),
+                Err(err) if err.is_fatal() => return Err(err),
+                Err(err) if cli.strict => return Err(err),
+                Err(err) => file.malformed.push(err),
+            }
+        }
+        log::info(
+            "Finished input file",
+            &[("file", &path.display()), ("rows", &file.rows)],
+        );
+        Ok(file)
+    };
+
+    let outcomes: Vec<Result<Vec<ReadFile>, InputError>> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..readers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut files = Vec::new();
+                    loop {
+                        let next = queue.lock().expect("Input queue lock poisoned").next();
+                        let Some((index, path)) = next else {
+                            break;
+                        };
+                        let file = read_file(index, &path);
+                        engine.finish_file(index);
+                        files.push(file?);
+                    }
+                    Ok(files)
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("Input reader panicked"))
+            .collect()
+    });
+
+    let mut files = Vec::new();
+    for outcome in outcomes {
+        files.extend(outcome?);
+    }
+    files.sort_by_key(|file| file.index);
+    let mut rows_read = 0;
+    for file in files {
+        rows_read += file.rows;
+        skipped.filtered += file.filtered;
+        for err in &file.malformed {
+            skipped.record(err)?;
+        }
+    }
+    Ok((engine.finish(), rows_read))
+}
+
+/// Engine fed by the readers of [`ingest_in_parallel`]
+enum ParallelEngine {
+    /// Rows are applied as soon as they are read
+    Unordered(ConcurrentEngine),
+    /// Rows are applied in file order, see `--parallel-read`
+    Ordered(OrderedDispatcher),
+}
+
+impl ParallelEngine {
+    fn submit(&self, file: usize, transaction: Transaction, origin: Origin) {
+        match self {
+            ParallelEngine::Unordered(engine) => {
+                if let Err(err) = engine.submit_from(transaction, origin) {
+                    report_rejected(&err);
+                }
+            }
+            ParallelEngine::Ordered(dispatcher) => {
+                dispatcher.submit_from(file, transaction, origin)
+            }
+        }
+    }
+
+    fn finish_file(&self, file: usize) {
+        if let ParallelEngine::Ordered(dispatcher) = self {
+            dispatcher.finish_file(file);
+        }
+    }
+
+    /// Merged engine, once the rejected rows not reported yet are
+    fn finish(self) -> TransactionEngine {
+        match self {
+            ParallelEngine::Unordered(engine) => engine.into_engine(),
+            ParallelEngine::Ordered(dispatcher) => {
+                let (engine, errors) = dispatcher.finish();
+                for err in errors {
+                    report_rejected(&err);
+                }
+                engine
+            }
+        }
+    }
+}
+
+/// Input file read by [`ingest_in_parallel`]
+struct ReadFile {
+    /// Position of the file in file name order
+    index: usize,
+    rows: u64,
+    /// Rows of clients left out by the client filter
+    filtered: u64,
+    malformed: Vec<InputError>,
+}
+
+/// Passes every well-formed transaction to `apply` with the number of rows read so far, skipping
+/// malformed rows unless in strict mode and rows of clients left out by the client filter. The
+/// first `skip` rows, already reflected in a resumed checkpoint, are read but not passed on.
+/// Returns the number of rows read.
+fn for_each_transaction<F>(
+    source: &mut dyn TransactionSource,
+    cli: &Cli,
+    skipped: &mut SkippedRows,
+    skip: u64,
+    mut apply: F,
+) -> Result<u64, Box<dyn Error>>
+where
+    F: FnMut(Transaction, u64) -> Result<(), Box<dyn Error>>,
+{
+    let mut rows = 0;
+    while let Some(result) = source.next_transaction() {
+        rows += 1;
+        match result {
+            Ok(_) | Err(InputError::Malformed { .. }) if rows <= skip => {}
+            Ok(transaction) if !cli.filter.allows(transaction.client_id) => skipped.filtered += 1,
+            Ok(transaction) => apply(transaction, rows)?,
+            Err(err) if err.is_fatal() => return Err(err.into()),
+            Err(err) if cli.strict => return Err(err.into()),
+            Err(err) => skipped.record(&err)?,
+        }
+    }
+    Ok(rows)
+}
+
+/// Executes every row of the source on `processor`, reporting the rejected ones. Returns the
+/// final engine and the number of rows read.
+fn execute_all<P: TransactionProcessor>(
+    mut processor: P,
+    source: &mut dyn TransactionSource,
+    cli: &Cli,
+    skipped: &mut SkippedRows,
+    origin: Origin,
+) -> Result<(TransactionEngine, u64), Box<dyn Error>> {
+    let rows_read = for_each_transaction(source, cli, skipped, 0, |transaction, _| {
+        if let Err(err) = processor.execute_from(transaction, origin) {
+            report_rejected(&err);
+        }
+        Ok(())
+    })?;
+    let (transaction_engine, errors) = processor.finalize();
+    for err in errors {
+        report_rejected(&err);
+    }
+    Ok((transaction_engine, rows_read))
+}
+
+fn report_window(report: &WindowReport) {
+    log::info(
+        "Window closed",
+        &[
+            ("window", &report.index),
+            ("transactions", &report.transactions),
+            ("rejected", &report.rejected),
+            ("accounts_changed", &report.accounts_changed),
+        ],
+    );
+}
+
+fn report_rejected(err: &EngineError) {
+    log::warn(
+        "Rejected transaction",
+        &[("tx", &err.transaction_id()), ("reason", err)],
+    );
 }
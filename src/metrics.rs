@@ -0,0 +1,356 @@
+//! Counters and latency histogram collected by the engine, with a Prometheus text exporter.
+
+use crate::error::EngineError;
+use crate::transaction::TransactionType;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Clock timing transactions. There is no clock on wasm32, where `Instant::now` panics, so
+/// transactions are not timed there.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use std::time::Instant as Clock;
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) struct Clock;
+
+#[cfg(target_arch = "wasm32")]
+impl Clock {
+    pub(crate) fn now() -> Self {
+        Clock
+    }
+
+    pub(crate) fn elapsed(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+/// Upper bounds in seconds of the latency histogram buckets
+const LATENCY_BUCKETS: [f64; 8] = [1e-6, 5e-6, 1e-5, 5e-5, 1e-4, 5e-4, 1e-3, 1e-2];
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EngineMetrics {
+    /// Transactions executed per type, whether applied or rejected
+    processed: BTreeMap<&'static str, u64>,
+    /// Rejected transactions per [`EngineError::code`]
+    rejected: BTreeMap<&'static str, u64>,
+    /// Rejected references to transactions no account owns, per type
+    orphans: BTreeMap<&'static str, u64>,
+    accounts_created: u64,
+    /// Transactions that stopped being disputable under the retention policy
+    evictions: u64,
+    /// Disputes resolved by the engine under the dispute expiry policy
+    expired_disputes: u64,
+    /// Transactions flagged per [`RiskRule`](crate::risk::RiskRule)
+    risk_flags: BTreeMap<&'static str, u64>,
+    /// Transactions refused or delayed per rate limit, see [`crate::throttle`]
+    throttled: BTreeMap<&'static str, u64>,
+    /// Count per bucket of [`LATENCY_BUCKETS`], plus one for slower transactions
+    latency_buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    latency_sum: Duration,
+}
+
+impl EngineMetrics {
+    pub(crate) fn record(
+        &mut self,
+        transaction_type: TransactionType,
+        result: &Result<(), EngineError>,
+        latency: Duration,
+    ) {
+        *self.processed.entry(transaction_type.as_str()).or_default() += 1;
+        if let Err(err) = result {
+            *self.rejected.entry(err.code()).or_default() += 1;
+            if transaction_type.is_reference() && matches!(err, EngineError::UnknownTransaction(_))
+            {
+                *self.orphans.entry(transaction_type.as_str()).or_default() += 1;
+            }
+        }
+        let seconds = latency.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.latency_buckets[bucket] += 1;
+        self.latency_sum += latency;
+    }
+
+    pub(crate) fn record_account_created(&mut self) {
+        self.accounts_created += 1;
+    }
+
+    pub(crate) fn record_eviction(&mut self) {
+        self.evictions += 1;
+    }
+
+    pub(crate) fn record_expired_dispute(&mut self) {
+        self.expired_disputes += 1;
+    }
+
+    pub(crate) fn record_risk_flag(&mut self, rule: &'static str) {
+        *self.risk_flags.entry(rule).or_default() += 1;
+    }
+
+    pub(crate) fn record_throttled(&mut self, limit: &'static str, count: u64) {
+        *self.throttled.entry(limit).or_default() += count;
+    }
+
+    /// Adds the metrics of another engine, e.g. a shard
+    pub(crate) fn merge(&mut self, other: &EngineMetrics) {
+        for (transaction_type, count) in &other.processed {
+            *self.processed.entry(transaction_type).or_default() += count;
+        }
+        for (reason, count) in &other.rejected {
+            *self.rejected.entry(reason).or_default() += count;
+        }
+        for (transaction_type, count) in &other.orphans {
+            *self.orphans.entry(transaction_type).or_default() += count;
+        }
+        self.accounts_created += other.accounts_created;
+        self.evictions += other.evictions;
+        self.expired_disputes += other.expired_disputes;
+        for (rule, count) in &other.risk_flags {
+            *self.risk_flags.entry(rule).or_default() += count;
+        }
+        for (limit, count) in &other.throttled {
+            *self.throttled.entry(limit).or_default() += count;
+        }
+        for (bucket, count) in self.latency_buckets.iter_mut().zip(other.latency_buckets) {
+            *bucket += count;
+        }
+        self.latency_sum += other.latency_sum;
+    }
+
+    pub fn processed(&self, transaction_type: TransactionType) -> u64 {
+        self.processed
+            .get(transaction_type.as_str())
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn total_processed(&self) -> u64 {
+        self.processed.values().sum()
+    }
+
+    /// Rejected transactions per reason, see [`EngineError::code`]
+    pub fn rejected(&self) -> impl Iterator<Item = (&'static str, u64)> + '_ {
+        self.rejected
+            .iter()
+            .map(|(reason, count)| (*reason, *count))
+    }
+
+    pub fn total_rejected(&self) -> u64 {
+        self.rejected.values().sum()
+    }
+
+    /// Disputes, resolves, chargebacks and the other references rejected because no account
+    /// owns the transaction they name, per type
+    pub fn orphans(&self) -> impl Iterator<Item = (&'static str, u64)> + '_ {
+        self.orphans
+            .iter()
+            .map(|(transaction_type, count)| (*transaction_type, *count))
+    }
+
+    pub fn total_orphans(&self) -> u64 {
+        self.orphans.values().sum()
+    }
+
+    pub fn accounts_created(&self) -> u64 {
+        self.accounts_created
+    }
+
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    pub fn expired_disputes(&self) -> u64 {
+        self.expired_disputes
+    }
+
+    /// Transactions flagged per risk rule
+    pub fn risk_flags(&self) -> impl Iterator<Item = (&'static str, u64)> + '_ {
+        self.risk_flags.iter().map(|(rule, count)| (*rule, *count))
+    }
+
+    pub fn total_risk_flags(&self) -> u64 {
+        self.risk_flags.values().sum()
+    }
+
+    /// Transactions refused or delayed per rate limit, `global` or `client`
+    pub fn throttled(&self) -> impl Iterator<Item = (&'static str, u64)> + '_ {
+        self.throttled.iter().map(|(limit, count)| (*limit, *count))
+    }
+
+    pub fn total_throttled(&self) -> u64 {
+        self.throttled.values().sum()
+    }
+
+    pub fn mean_latency(&self) -> Duration {
+        match self.total_processed() {
+            0 => Duration::ZERO,
+            count => self.latency_sum / count as u32,
+        }
+    }
+
+    /// Metrics in the Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        // Writing to a string cannot fail
+        let _ = self.write_prometheus(&mut out);
+        out
+    }
+
+    fn write_prometheus(&self, out: &mut String) -> std::fmt::Result {
+        writeln!(out, "# TYPE engine_transactions_total counter")?;
+        for transaction_type in TransactionType::ALL {
+            writeln!(
+                out,
+                "engine_transactions_total{{type=\"{}\"}} {}",
+                transaction_type,
+                self.processed(transaction_type)
+            )?;
+        }
+        writeln!(out, "# TYPE engine_rejected_transactions_total counter")?;
+        for (reason, count) in self.rejected() {
+            writeln!(
+                out,
+                "engine_rejected_transactions_total{{reason=\"{}\"}} {}",
+                reason, count
+            )?;
+        }
+        writeln!(out, "# TYPE engine_orphan_references_total counter")?;
+        for (transaction_type, count) in self.orphans() {
+            writeln!(
+                out,
+                "engine_orphan_references_total{{type=\"{}\"}} {}",
+                transaction_type, count
+            )?;
+        }
+        writeln!(out, "# TYPE engine_accounts_created_total counter")?;
+        writeln!(
+            out,
+            "engine_accounts_created_total {}",
+            self.accounts_created
+        )?;
+        writeln!(out, "# TYPE engine_evicted_transactions_total counter")?;
+        writeln!(out, "engine_evicted_transactions_total {}", self.evictions)?;
+        writeln!(out, "# TYPE engine_expired_disputes_total counter")?;
+        writeln!(
+            out,
+            "engine_expired_disputes_total {}",
+            self.expired_disputes
+        )?;
+        writeln!(out, "# TYPE engine_risk_flags_total counter")?;
+        for (rule, count) in self.risk_flags() {
+            writeln!(
+                out,
+                "engine_risk_flags_total{{rule=\"{}\"}} {}",
+                rule, count
+            )?;
+        }
+        writeln!(out, "# TYPE engine_throttled_transactions_total counter")?;
+        for (limit, count) in self.throttled() {
+            writeln!(
+                out,
+                "engine_throttled_transactions_total{{limit=\"{}\"}} {}",
+                limit, count
+            )?;
+        }
+
+        writeln!(out, "# TYPE engine_transaction_duration_seconds histogram")?;
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(self.latency_buckets) {
+            cumulative += count;
+            writeln!(
+                out,
+                "engine_transaction_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound, cumulative
+            )?;
+        }
+        writeln!(
+            out,
+            "engine_transaction_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            self.total_processed()
+        )?;
+        writeln!(
+            out,
+            "engine_transaction_duration_seconds_sum {}",
+            self.latency_sum.as_secs_f64()
+        )?;
+        writeln!(
+            out,
+            "engine_transaction_duration_seconds_count {}",
+            self.total_processed()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::engine::TransactionEngine;
+        use crate::transaction::{transaction, TransactionType};
+
+        fn engine() -> TransactionEngine {
+            let mut engine = TransactionEngine::new();
+            for transaction in [
+                transaction(TransactionType::Deposit, 1, 1, Some(1.0)),
+                transaction(TransactionType::Deposit, 2, 2, Some(1.0)),
+                transaction(TransactionType::Withdrawal, 1, 3, Some(5.0)),
+                transaction(TransactionType::Dispute, 1, 9, None),
+                transaction(TransactionType::Deposit, 1, 1, Some(1.0)),
+            ] {
+                let _ = engine.execute(transaction);
+            }
+            engine
+        }
+
+        #[test]
+        fn transactions_and_rejects_are_counted() {
+            let engine = engine();
+            let metrics = engine.metrics();
+
+            assert_eq!(metrics.processed(TransactionType::Deposit), 3);
+            assert_eq!(metrics.processed(TransactionType::Withdrawal), 1);
+            assert_eq!(metrics.total_processed(), 5);
+            assert_eq!(
+                metrics.rejected().collect::<Vec<_>>(),
+                vec![
+                    ("duplicate_transaction", 1),
+                    ("insufficient_funds", 1),
+                    ("unknown_transaction", 1)
+                ]
+            );
+            assert_eq!(metrics.accounts_created(), 2);
+            assert_eq!(metrics.orphans().collect::<Vec<_>>(), vec![("dispute", 1)]);
+        }
+
+        #[test]
+        fn only_references_to_unknown_transactions_are_orphans() {
+            let mut engine = engine();
+            let _ = engine.execute(transaction(TransactionType::Chargeback, 2, 2, None));
+            let _ = engine.execute(transaction(TransactionType::Resolve, 3, 8, None));
+
+            assert_eq!(
+                engine.metrics().orphans().collect::<Vec<_>>(),
+                vec![("dispute", 1), ("resolve", 1)]
+            );
+            assert_eq!(engine.metrics().total_orphans(), 2);
+            assert!(!engine.accounts.contains_key(&3));
+        }
+
+        #[test]
+        fn prometheus_output_contains_every_metric() {
+            let output = engine().metrics().to_prometheus();
+
+            assert!(output.contains("engine_transactions_total{type=\"deposit\"} 3\n"));
+            assert!(output.contains("engine_transactions_total{type=\"chargeback\"} 0\n"));
+            assert!(output
+                .contains("engine_rejected_transactions_total{reason=\"insufficient_funds\"} 1\n"));
+            assert!(output.contains("engine_orphan_references_total{type=\"dispute\"} 1\n"));
+            assert!(output.contains("engine_accounts_created_total 2\n"));
+            assert!(output.contains("engine_evicted_transactions_total 0\n"));
+            assert!(output.contains("engine_transaction_duration_seconds_bucket{le=\"+Inf\"} 5\n"));
+            assert!(output.contains("engine_transaction_duration_seconds_count 5\n"));
+        }
+    }
+}
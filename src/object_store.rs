@@ -0,0 +1,609 @@
+//! Input read straight from object storage, built with the `object-store` feature. Input paths
+//! of the form `s3://<bucket>/<key>` or `http://<host>[:<port>]/<path>` are streamed through
+//! the csv and ndjson readers as they are downloaded, without a copy on local disk:
+//!
+//! ```text
+//! cargo run --features object-store -- s3://settlements/2024-03-01.csv
+//! ```
+//!
+//! S3 objects are fetched from `AWS_ENDPOINT_URL_S3` or `AWS_ENDPOINT_URL` if set, e.g. a MinIO
+//! server or a proxy inside the network, with the bucket as the first component of the path, and
+//! from `http://<bucket>.s3.<region>.amazonaws.com` otherwise. The region is that of
+//! `AWS_REGION` or `AWS_DEFAULT_REGION`, `us-east-1` by default. Requests are signed with AWS
+//! Signature Version 4 when `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` are set, along with
+//! `AWS_SESSION_TOKEN` for temporary credentials, and sent anonymously for public objects
+//! otherwise.
+//!
+//! Only plain http is spoken: `https://` urls, and endpoints, are refused with an error saying so.
+//! Objects behind https are read through a TLS terminating proxy.
+
+use crate::sha256::{hmac, to_hex, Sha256};
+use crate::timestamp::Timestamp;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Timeout of connecting to the server
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest wait for the next bytes of an object
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Bytes of the body of an error answer kept in the error message
+const ERROR_BODY_LIMIT: u64 = 512;
+
+/// Object to stream, as given on the command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ObjectUrl {
+    S3 { bucket: String, key: String },
+    Http(HttpUrl),
+}
+
+impl FromStr for ObjectUrl {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.strip_prefix("s3://") {
+            Some(rest) => match rest.split_once('/') {
+                Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => Ok(ObjectUrl::S3 {
+                    bucket: bucket.to_string(),
+                    key: key.to_string(),
+                }),
+                _ => Err(format!(
+                    "invalid object url '{}', expected s3://<bucket>/<key>",
+                    value
+                )),
+            },
+            None => value.parse().map(ObjectUrl::Http),
+        }
+    }
+}
+
+impl fmt::Display for ObjectUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjectUrl::S3 { bucket, key } => write!(f, "s3://{}/{}", bucket, key),
+            ObjectUrl::Http(url) => url.fmt(f),
+        }
+    }
+}
+
+/// `http://<host>[:<port>][/<path>]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpUrl {
+    pub host: String,
+    pub port: u16,
+    /// Starts with `/`, and may carry a query
+    pub path: String,
+}
+
+impl HttpUrl {
+    /// Value of the `Host` header, without the port when it is the default one
+    fn authority(&self) -> String {
+        match self.port {
+            80 => self.host.clone(),
+            port => format!("{}:{}", self.host, port),
+        }
+    }
+}
+
+impl FromStr for HttpUrl {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid url '{}'", value);
+        let rest = match value.strip_prefix("http://") {
+            Some(rest) => rest,
+            None if value.starts_with("https://") => {
+                return Err(format!(
+                    "url '{}' is https, only http is supported, e.g. through a TLS terminating \
+                     proxy",
+                    value
+                ))
+            }
+            None => return Err(invalid()),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => (authority, 80),
+        };
+        if host.is_empty() || host.contains(char::is_whitespace) || path.contains(' ') {
+            return Err(invalid());
+        }
+        Ok(HttpUrl {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for HttpUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}{}", self.authority(), self.path)
+    }
+}
+
+/// Keys requests to S3 are signed with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Of temporary credentials
+    pub session_token: Option<String>,
+}
+
+/// Where S3 objects are fetched from and how requests are signed, see the [module](self)
+/// documentation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Settings {
+    /// Server answering for every bucket, addressed by path, instead of AWS
+    pub endpoint: Option<HttpUrl>,
+    pub region: String,
+    /// Anonymous requests unless given
+    pub credentials: Option<Credentials>,
+}
+
+impl S3Settings {
+    /// Settings of the `AWS_*` environment variables
+    pub fn from_env() -> Result<Self, String> {
+        let var = |name| {
+            std::env::var(name)
+                .ok()
+                .filter(|value: &String| !value.is_empty())
+        };
+        let endpoint = match var("AWS_ENDPOINT_URL_S3").or_else(|| var("AWS_ENDPOINT_URL")) {
+            Some(endpoint) => Some(endpoint.trim_end_matches('/').parse()?),
+            None => None,
+        };
+        let credentials = match (var("AWS_ACCESS_KEY_ID"), var("AWS_SECRET_ACCESS_KEY")) {
+            (Some(access_key_id), Some(secret_access_key)) => Some(Credentials {
+                access_key_id,
+                secret_access_key,
+                session_token: var("AWS_SESSION_TOKEN"),
+            }),
+            _ => None,
+        };
+        Ok(S3Settings {
+            endpoint,
+            region: var("AWS_REGION")
+                .or_else(|| var("AWS_DEFAULT_REGION"))
+                .unwrap_or_else(|| "us-east-1".to_string()),
+            credentials,
+        })
+    }
+
+    /// Location of the object on the server
+    fn url(&self, bucket: &str, key: &str) -> HttpUrl {
+        let key = uri_encode(key, false);
+        match &self.endpoint {
+            Some(endpoint) => HttpUrl {
+                host: endpoint.host.clone(),
+                port: endpoint.port,
+                path: format!(
+                    "{}/{}/{}",
+                    endpoint.path.trim_end_matches('/'),
+                    uri_encode(bucket, true),
+                    key
+                ),
+            },
+            None => HttpUrl {
+                host: format!("{}.s3.{}.amazonaws.com", bucket, self.region),
+                port: 80,
+                path: format!("/{}", key),
+            },
+        }
+    }
+}
+
+/// Streams the object at the url, an `s3://` one with the settings of the environment
+pub fn open(url: &str) -> io::Result<Box<dyn Read + Send>> {
+    let url: ObjectUrl = url.parse().map_err(invalid_input)?;
+    let s3 = match url {
+        ObjectUrl::S3 { .. } => S3Settings::from_env().map_err(invalid_input)?,
+        ObjectUrl::Http(_) => S3Settings {
+            endpoint: None,
+            region: String::new(),
+            credentials: None,
+        },
+    };
+    open_with(&url, &s3)
+}
+
+/// Streams the object at the url, fetching S3 objects as the settings say
+pub fn open_with(url: &ObjectUrl, s3: &S3Settings) -> io::Result<Box<dyn Read + Send>> {
+    let (location, mut headers) = match url {
+        ObjectUrl::Http(url) => (url.clone(), Vec::new()),
+        ObjectUrl::S3 { bucket, key } => {
+            let location = s3.url(bucket, key);
+            let headers = match &s3.credentials {
+                Some(credentials) => {
+                    let now = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |elapsed| elapsed.as_secs());
+                    s3_headers(
+                        &location,
+                        &s3.region,
+                        credentials,
+                        Timestamp::from_secs(now),
+                    )
+                }
+                None => Vec::new(),
+            };
+            (location, headers)
+        }
+    };
+    headers.retain(|(name, _)| name != "host");
+    get(&location, &headers).map_err(|err| io::Error::new(err.kind(), format!("{}: {}", url, err)))
+}
+
+/// Headers of a signed GET of the object. The payload of a GET is empty, but S3 takes
+/// `UNSIGNED-PAYLOAD` in its place.
+fn s3_headers(
+    location: &HttpUrl,
+    region: &str,
+    credentials: &Credentials,
+    now: Timestamp,
+) -> Vec<(String, String)> {
+    let amz_date = now.to_string().replace(['-', ':'], "");
+    let mut headers = vec![
+        ("host".to_string(), location.authority()),
+        (
+            "x-amz-content-sha256".to_string(),
+            "UNSIGNED-PAYLOAD".to_string(),
+        ),
+        ("x-amz-date".to_string(), amz_date.clone()),
+    ];
+    if let Some(token) = &credentials.session_token {
+        headers.push(("x-amz-security-token".to_string(), token.clone()));
+    }
+    let authorization = authorization(
+        credentials,
+        region,
+        "s3",
+        &amz_date,
+        &location.path,
+        &headers,
+        "UNSIGNED-PAYLOAD",
+    );
+    headers.push(("authorization".to_string(), authorization));
+    headers
+}
+
+/// `Authorization` header of a GET signed with AWS Signature Version 4. Headers are lower case
+/// and sorted by name, the path is already encoded.
+fn authorization(
+    credentials: &Credentials,
+    region: &str,
+    service: &str,
+    amz_date: &str,
+    path: &str,
+    headers: &[(String, String)],
+    payload_hash: &str,
+) -> String {
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    let mut query: Vec<_> = query.split('&').filter(|pair| !pair.is_empty()).collect();
+    query.sort();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+    let mut canonical = format!("GET\n{}\n{}\n", path, query.join("&"));
+    for (name, value) in headers {
+        canonical.push_str(&format!("{}:{}\n", name, value.trim()));
+    }
+    canonical.push_str(&format!("\n{}\n{}", signed_headers, payload_hash));
+
+    let date = &amz_date[..8];
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let mut digest = Sha256::default();
+    digest.update(canonical.as_bytes());
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        to_hex(&digest.finish())
+    );
+    let secret = format!("AWS4{}", credentials.secret_access_key);
+    let key = hmac(secret.as_bytes(), date.as_bytes());
+    let key = hmac(&key, region.as_bytes());
+    let key = hmac(&key, service.as_bytes());
+    let key = hmac(&key, b"aws4_request");
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        credentials.access_key_id,
+        scope,
+        signed_headers,
+        to_hex(&hmac(&key, string_to_sign.as_bytes()))
+    )
+}
+
+/// Percent-encodes everything but the unreserved characters, and `/` unless `slash` is set
+fn uri_encode(value: &str, slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if !slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Sends the GET and returns the body of a `2xx` answer as it arrives
+fn get(url: &HttpUrl, headers: &[(String, String)]) -> io::Result<Box<dyn Read + Send>> {
+    let address = (url.host.as_str(), url.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host has no address"))?;
+    let mut stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECT_TIMEOUT))?;
+    let mut request = format!("GET {} HTTP/1.1\r\nHost: {}\r\n", url.path, url.authority());
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("Connection: close\r\n\r\n");
+    stream.write_all(request.as_bytes())?;
+    stream.flush()?;
+
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let status: u16 = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| invalid_data(format!("malformed status line '{}'", line.trim_end())))?;
+    let mut chunked = false;
+    let mut length = None;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid_data("answer ended in its headers"));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.eq_ignore_ascii_case("chunked");
+            } else if name.eq_ignore_ascii_case("content-length") {
+                length = value.parse::<u64>().ok();
+            }
+        }
+    }
+    let body: Box<dyn Read + Send> = match (chunked, length) {
+        (true, _) => Box::new(ChunkedReader::new(reader)),
+        (false, Some(length)) => Box::new(reader.take(length)),
+        (false, None) => Box::new(reader),
+    };
+    if !(200..300).contains(&status) {
+        let mut message = String::new();
+        let _ = body.take(ERROR_BODY_LIMIT).read_to_string(&mut message);
+        let kind = match status {
+            404 => io::ErrorKind::NotFound,
+            401 | 403 => io::ErrorKind::PermissionDenied,
+            _ => io::ErrorKind::Other,
+        };
+        return Err(io::Error::new(
+            kind,
+            format!("answered {}: {}", status, message.trim()),
+        ));
+    }
+    Ok(body)
+}
+
+/// Body sent with `Transfer-Encoding: chunked`
+struct ChunkedReader<R> {
+    inner: R,
+    /// Bytes left in the current chunk
+    remaining: u64,
+    done: bool,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    fn new(inner: R) -> Self {
+        ChunkedReader {
+            inner,
+            remaining: 0,
+            done: false,
+        }
+    }
+
+    fn line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.inner.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "answer ended within a chunk",
+            ));
+        }
+        Ok(line.trim_end().to_string())
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.remaining == 0 && !self.done {
+            let line = self.line()?;
+            let size = line.split(';').next().unwrap_or_default().trim();
+            self.remaining = u64::from_str_radix(size, 16)
+                .map_err(|_| invalid_data(format!("malformed chunk size '{}'", line)))?;
+            if self.remaining == 0 {
+                // Trailers, up to the empty line ending the body
+                while !self.line()?.is_empty() {}
+                self.done = true;
+            }
+        }
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        let wanted = buf.len().min(self.remaining as usize);
+        let count = self.inner.read(&mut buf[..wanted])?;
+        if count == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "answer ended within a chunk",
+            ));
+        }
+        self.remaining -= count as u64;
+        if self.remaining == 0 && !self.line()?.is_empty() {
+            return Err(invalid_data("chunk longer than its size"));
+        }
+        Ok(count)
+    }
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::object_store::{
+            authorization, open_with, uri_encode, Credentials, HttpUrl, ObjectUrl, S3Settings,
+        };
+        use std::io::{BufRead, BufReader, Read, Write};
+        use std::net::TcpListener;
+        use std::thread;
+
+        #[test]
+        fn urls_are_parsed() {
+            assert_eq!(
+                "s3://settlements/2024/03/01.csv".parse(),
+                Ok(ObjectUrl::S3 {
+                    bucket: "settlements".to_string(),
+                    key: "2024/03/01.csv".to_string(),
+                })
+            );
+            assert_eq!(
+                "http://files.internal:8080/feeds/today.ndjson".parse(),
+                Ok(ObjectUrl::Http(HttpUrl {
+                    host: "files.internal".to_string(),
+                    port: 8080,
+                    path: "/feeds/today.ndjson".to_string(),
+                }))
+            );
+            assert!("s3://settlements".parse::<ObjectUrl>().is_err());
+            assert!("https://files.internal/today.csv"
+                .parse::<ObjectUrl>()
+                .unwrap_err()
+                .contains("only http is supported"));
+            assert_eq!(
+                uri_encode("2024/march 1+2.csv", false),
+                "2024/march%201%2B2.csv"
+            );
+        }
+
+        /// `get-vanilla` of the AWS Signature Version 4 test suite
+        #[test]
+        fn requests_are_signed_as_aws_expects() {
+            let credentials = Credentials {
+                access_key_id: "AKIDEXAMPLE".to_string(),
+                secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+                session_token: None,
+            };
+            let headers = [
+                ("host".to_string(), "example.amazonaws.com".to_string()),
+                ("x-amz-date".to_string(), "20150830T123600Z".to_string()),
+            ];
+            assert_eq!(
+                authorization(
+                    &credentials,
+                    "us-east-1",
+                    "service",
+                    "20150830T123600Z",
+                    "/",
+                    &headers,
+                    "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+                ),
+                "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+                 SignedHeaders=host;x-amz-date, \
+                 Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+            );
+        }
+
+        #[test]
+        fn objects_are_streamed_from_the_endpoint() {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let server = thread::spawn(move || {
+                let mut requests = Vec::new();
+                let answers = [
+                    "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                     11\r\ntype,client,tx,am\r\n15\r\nount\ndeposit,1,1,2.0\n\r\n0\r\n\r\n",
+                    "HTTP/1.1 404 Not Found\r\nContent-Length: 9\r\n\r\nNoSuchKey",
+                ];
+                for (answer, stream) in answers.into_iter().zip(listener.incoming()) {
+                    let mut stream = stream.unwrap();
+                    let mut request = Vec::new();
+                    let mut reader = BufReader::new(&mut stream);
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if line == "\r\n" {
+                            break;
+                        }
+                        request.push(line.trim_end().to_string());
+                    }
+                    stream.write_all(answer.as_bytes()).unwrap();
+                    requests.push(request);
+                }
+                requests
+            });
+
+            let s3 = S3Settings {
+                endpoint: Some(format!("http://127.0.0.1:{}", port).parse().unwrap()),
+                region: "eu-west-1".to_string(),
+                credentials: Some(Credentials {
+                    access_key_id: "AKIDEXAMPLE".to_string(),
+                    secret_access_key: "secret".to_string(),
+                    session_token: Some("token".to_string()),
+                }),
+            };
+            let url = "s3://settlements/2024 03.csv".parse().unwrap();
+            let mut body = String::new();
+            open_with(&url, &s3)
+                .unwrap()
+                .read_to_string(&mut body)
+                .unwrap();
+            let missing = open_with(&"s3://settlements/gone.csv".parse().unwrap(), &s3);
+            let requests = server.join().unwrap();
+
+            assert_eq!(body, "type,client,tx,amount\ndeposit,1,1,2.0\n");
+            let request = &requests[0];
+            assert_eq!(request[0], "GET /settlements/2024%2003.csv HTTP/1.1");
+            assert!(request.contains(&"x-amz-security-token: token".to_string()));
+            assert!(request.iter().any(|header| header
+                .starts_with("authorization: AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/")
+                && header.contains(
+                    "/eu-west-1/s3/aws4_request, \
+                 SignedHeaders=host;x-amz-content-sha256;x-amz-date;x-amz-security-token, "
+                )));
+            let err = missing.err().unwrap();
+            assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+            assert_eq!(
+                err.to_string(),
+                "s3://settlements/gone.csv: answered 404: NoSuchKey"
+            );
+        }
+    }
+}
@@ -0,0 +1,324 @@
+//! Callbacks of embedders on what the engine applies, e.g. to keep a live dashboard up to date or
+//! to trigger side effects, without going through the dispatch of the engine. Observers are
+//! registered with [`TransactionEngine::subscribe`](crate::engine::TransactionEngine::subscribe)
+//! and called synchronously, in the order they subscribed, after each transaction was applied:
+//!
+//! 1. [`EngineObserver::on_applied`] with the transaction,
+//! 2. [`EngineObserver::on_balance_changed`] for each currency of each account whose funds it
+//!    changed, in order of client and currency,
+//! 3. [`EngineObserver::on_dispute`] if it moved a transaction along its dispute cycles,
+//! 4. [`EngineObserver::on_lock_changed`] for each account it locked or unlocked.
+//!
+//! Rejected transactions change nothing and are not observed, see
+//! [`AuditSink`](crate::audit::AuditSink) for those. Disputes resolved as they expire and
+//! withdrawals settled by [`TransactionEngine::advance`](crate::engine::TransactionEngine::advance)
+//! are observed without a transaction.
+//!
+//! ```
+//! use rust_coding_test::{
+//!     Amount, Balance, ClientId, Currency, EngineObserver, Transaction, TransactionEngine,
+//!     TransactionId, TransactionType,
+//! };
+//! use std::sync::{Arc, Mutex};
+//!
+//! struct Dashboard(Arc<Mutex<Vec<f64>>>);
+//!
+//! impl EngineObserver for Dashboard {
+//!     fn on_balance_changed(
+//!         &mut self,
+//!         _client_id: ClientId,
+//!         _currency: Option<Currency>,
+//!         _before: Balance,
+//!         after: Balance,
+//!     ) {
+//!         self.0.lock().unwrap().push(after.available);
+//!     }
+//! }
+//!
+//! let available = Arc::new(Mutex::new(Vec::new()));
+//! let mut engine = TransactionEngine::new();
+//! engine.subscribe(Box::new(Dashboard(available.clone())));
+//! engine
+//!     .execute(Transaction {
+//!         transaction_type: TransactionType::Deposit,
+//!         client_id: 1,
+//!         transaction_id: TransactionId(1),
+//!         amount: Some(Amount::new(2.5).unwrap()),
+//!         to_client_id: None,
+//!         currency: None,
+//!         timestamp: None,
+//!         metadata: Default::default(),
+//!     })
+//!     .unwrap();
+//!
+//! assert_eq!(*available.lock().unwrap(), [2.5]);
+//! ```
+
+use crate::account::{Balance, ClientAccount, ClientId, DisputeState};
+use crate::currency::Currency;
+use crate::transaction::{Transaction, TransactionId};
+
+/// Receives what the engine applied. Every method does nothing by default, so observers only
+/// implement those they are interested in.
+pub trait EngineObserver: Send {
+    /// Transaction changed the state of the accounts
+    fn on_applied(&mut self, _transaction: &Transaction) {}
+
+    /// Funds of the client in the currency went from `before` to `after`. The funds of an
+    /// account created or merged away are zero on that side.
+    fn on_balance_changed(
+        &mut self,
+        _client_id: ClientId,
+        _currency: Option<Currency>,
+        _before: Balance,
+        _after: Balance,
+    ) {
+    }
+
+    /// Transaction of the client was disputed, resolved, charged back or represented
+    fn on_dispute(
+        &mut self,
+        _client_id: ClientId,
+        _transaction_id: TransactionId,
+        _state: DisputeState,
+    ) {
+    }
+
+    /// Account of the client was locked or unlocked
+    fn on_lock_changed(&mut self, _client_id: ClientId, _locked: bool) {}
+}
+
+/// Funds and lock of an account, compared before and after a change to notify the observers
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Observed {
+    client_id: ClientId,
+    balances: Vec<(Option<Currency>, Balance)>,
+    locked: bool,
+}
+
+impl Observed {
+    /// The account of the client as it is now, empty if it has none
+    pub(crate) fn of(client_id: ClientId, account: Option<&dyn ClientAccount>) -> Self {
+        let Some(account) = account else {
+            return Observed {
+                client_id,
+                ..Observed::default()
+            };
+        };
+        Observed {
+            client_id,
+            balances: account
+                .currencies()
+                .into_iter()
+                .map(|currency| (currency, account.balance(currency)))
+                .filter(|(_, balance)| *balance != Balance::default())
+                .collect(),
+            locked: account.is_locked(),
+        }
+    }
+
+    fn balance(&self, currency: Option<Currency>) -> Balance {
+        self.balances
+            .iter()
+            .find(|(balance_currency, _)| *balance_currency == currency)
+            .map_or_else(Balance::default, |(_, balance)| *balance)
+    }
+}
+
+/// Tells the observers how the accounts changed from `before` to `after`, given in the same
+/// order of clients, and about the `dispute` step of the change if any
+pub(crate) fn notify(
+    observers: &mut [Box<dyn EngineObserver>],
+    before: &[Observed],
+    after: &[Observed],
+    dispute: Option<(ClientId, TransactionId, DisputeState)>,
+) {
+    for (before, after) in before.iter().zip(after) {
+        let mut currencies: Vec<_> = before
+            .balances
+            .iter()
+            .chain(&after.balances)
+            .map(|(currency, _)| *currency)
+            .collect();
+        currencies.sort_unstable();
+        currencies.dedup();
+        for currency in currencies {
+            let (from, to) = (before.balance(currency), after.balance(currency));
+            if from != to {
+                for observer in observers.iter_mut() {
+                    observer.on_balance_changed(before.client_id, currency, from, to);
+                }
+            }
+        }
+    }
+    if let Some((client_id, transaction_id, state)) = dispute {
+        for observer in observers.iter_mut() {
+            observer.on_dispute(client_id, transaction_id, state);
+        }
+    }
+    for (before, after) in before.iter().zip(after) {
+        if before.locked != after.locked {
+            for observer in observers.iter_mut() {
+                observer.on_lock_changed(before.client_id, after.locked);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::account::{Balance, ClientId, DisputeState};
+        use crate::currency::Currency;
+        use crate::engine::{EngineConfig, TransactionEngine};
+        use crate::observer::EngineObserver;
+        use crate::policy::DisputeExpiry;
+        use crate::transaction::{
+            transaction, Origin, Transaction, TransactionId, TransactionType,
+        };
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Clone, Default)]
+        struct Recorder(Arc<Mutex<Vec<String>>>);
+
+        impl Recorder {
+            fn take(&self) -> Vec<String> {
+                std::mem::take(&mut self.0.lock().unwrap())
+            }
+
+            fn push(&self, event: String) {
+                self.0.lock().unwrap().push(event);
+            }
+        }
+
+        impl EngineObserver for Recorder {
+            fn on_applied(&mut self, transaction: &Transaction) {
+                self.push(format!(
+                    "applied {} {}",
+                    transaction.transaction_type, transaction.transaction_id
+                ));
+            }
+
+            fn on_balance_changed(
+                &mut self,
+                client_id: ClientId,
+                currency: Option<Currency>,
+                before: Balance,
+                after: Balance,
+            ) {
+                let currency = currency.map_or(String::new(), |currency| format!(" {}", currency));
+                self.push(format!(
+                    "client {}{} {}/{} -> {}/{}",
+                    client_id, currency, before.available, before.held, after.available, after.held
+                ));
+            }
+
+            fn on_dispute(
+                &mut self,
+                client_id: ClientId,
+                transaction_id: TransactionId,
+                state: DisputeState,
+            ) {
+                self.push(format!(
+                    "client {} tx {} {}",
+                    client_id, transaction_id, state
+                ));
+            }
+
+            fn on_lock_changed(&mut self, client_id: ClientId, locked: bool) {
+                self.push(format!("client {} locked {}", client_id, locked));
+            }
+        }
+
+        #[test]
+        fn observers_see_every_change_of_the_accounts() {
+            let recorder = Recorder::default();
+            let mut engine = TransactionEngine::new();
+            engine.subscribe(Box::new(recorder.clone()));
+
+            engine
+                .execute(transaction(TransactionType::Deposit, 2, 1, Some(5.0)))
+                .unwrap();
+            let mut transfer = transaction(TransactionType::Transfer, 2, 2, Some(2.0));
+            transfer.to_client_id = Some(1);
+            engine.execute(transfer).unwrap();
+            assert_eq!(
+                recorder.take(),
+                [
+                    "applied deposit 1",
+                    "client 2 0/0 -> 5/0",
+                    "applied transfer 2",
+                    "client 1 0/0 -> 2/0",
+                    "client 2 5/0 -> 3/0",
+                ]
+            );
+
+            // Rejected transactions change nothing
+            let _ = engine.execute(transaction(TransactionType::Withdrawal, 2, 3, Some(9.0)));
+            assert!(recorder.take().is_empty());
+
+            engine
+                .execute(transaction(TransactionType::Dispute, 2, 1, None))
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Chargeback, 2, 1, None))
+                .unwrap();
+            engine
+                .execute_from(
+                    transaction(TransactionType::Unlock, 2, 4, None),
+                    Origin::Admin,
+                )
+                .unwrap();
+            assert_eq!(
+                recorder.take(),
+                [
+                    "applied dispute 1",
+                    "client 2 3/0 -> -2/5",
+                    "client 2 tx 1 disputed",
+                    "applied chargeback 1",
+                    "client 2 -2/5 -> -2/0",
+                    "client 2 tx 1 chargedback",
+                    "client 2 locked true",
+                    "applied unlock 4",
+                    "client 2 locked false",
+                ]
+            );
+        }
+
+        #[test]
+        fn expired_disputes_are_observed_without_a_transaction() {
+            let recorder = Recorder::default();
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                dispute_expiry: DisputeExpiry {
+                    after_transactions: Some(1),
+                    after_days: None,
+                },
+                ..EngineConfig::default()
+            });
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(3.0)))
+                .unwrap();
+            engine.subscribe(Box::new(recorder.clone()));
+
+            engine
+                .execute(transaction(TransactionType::Dispute, 1, 1, None))
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Deposit, 2, 2, Some(1.0)))
+                .unwrap();
+
+            assert_eq!(
+                recorder.take(),
+                [
+                    "applied dispute 1",
+                    "client 1 3/0 -> 0/3",
+                    "client 1 tx 1 disputed",
+                    "applied deposit 2",
+                    "client 2 0/0 -> 1/0",
+                    "client 1 0/3 -> 3/0",
+                    "client 1 tx 1 resolved",
+                ]
+            );
+        }
+    }
+}
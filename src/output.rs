@@ -0,0 +1,1097 @@
+use crate::account::{ClientAccount, ClientId, DUST};
+use crate::currency::Currency;
+use crate::engine::TransactionEngine;
+use crate::input::parse_flat_object;
+use crate::projection::{AccountProjection, ProjectedValue};
+use crate::sha256::{self, Sha256};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Format used to write the final state of accounts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    /// A json array, for APIs
+    Json,
+    /// One json object per line, for streaming consumers
+    Jsonl,
+    /// Aligned columns with a totals row, for reading in a terminal
+    Table,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            "table" => Ok(OutputFormat::Table),
+            _ => Err(format!("unknown output format '{}'", value)),
+        }
+    }
+}
+
+/// Order in which accounts are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputOrder {
+    /// Whatever order the engine keeps its accounts in, which differs between runs
+    #[default]
+    None,
+    /// By client id then currency, so that runs over the same input write identical output
+    Client,
+}
+
+impl FromStr for OutputOrder {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "none" => Ok(OutputOrder::None),
+            "client" => Ok(OutputOrder::Client),
+            _ => Err(format!("unknown output order '{}'", value)),
+        }
+    }
+}
+
+/// Decimal places of the amounts written, unless a writer is given others
+pub const DEFAULT_DECIMALS: usize = 4;
+
+/// Funds of a client account in one currency at a point in time, owned and independent of the
+/// account implementation. Written as one row of the output for every currency of every account.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AccountSnapshot {
+    pub client: ClientId,
+    #[serde(serialize_with = "serialize_amount")]
+    pub available: f64,
+    #[serde(serialize_with = "serialize_amount")]
+    pub held: f64,
+    #[serde(serialize_with = "serialize_amount")]
+    pub total: f64,
+    pub locked: bool,
+    /// Part of the available funds that cannot be withdrawn, left out of json output without one
+    #[serde(
+        default,
+        skip_serializing_if = "is_zero",
+        serialize_with = "serialize_amount"
+    )]
+    pub reserved: f64,
+    /// Account was closed, left out of json output for open accounts
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub closed: bool,
+    /// Left out for funds without currency
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+}
+
+impl AccountSnapshot {
+    /// Funds of the account in the currency
+    pub fn new(account: &dyn ClientAccount, currency: Option<Currency>) -> Self {
+        let balance = account.balance(currency);
+        AccountSnapshot {
+            client: account.get_client_id(),
+            available: balance.available,
+            held: balance.held,
+            total: balance.total(),
+            locked: account.is_locked(),
+            reserved: 0.0,
+            closed: false,
+            currency,
+        }
+    }
+
+    /// One record per currency of the account
+    pub fn all(account: &dyn ClientAccount) -> impl Iterator<Item = Self> + '_ {
+        account
+            .currencies()
+            .into_iter()
+            .map(move |currency| Self::new(account, currency))
+    }
+
+    /// Json object with amounts written with [`DEFAULT_DECIMALS`] decimal places
+    pub fn to_json(&self) -> String {
+        self.to_json_with_decimals(DEFAULT_DECIMALS)
+    }
+
+    /// Json object with amounts written with the given decimal places
+    pub fn to_json_with_decimals(&self, decimals: usize) -> String {
+        let reserved = if self.reserved != 0.0 {
+            format!(",\"reserved\":{:.*}", decimals, self.reserved)
+        } else {
+            String::new()
+        };
+        let closed = if self.closed { ",\"closed\":true" } else { "" };
+        let currency = self.currency.map_or(String::new(), |currency| {
+            format!(",\"currency\":\"{}\"", currency)
+        });
+        format!(
+            "{{\"client\":{},\"available\":{:.*},\"held\":{:.*},\"total\":{:.*},\"locked\":{}{}{}{}}}",
+            self.client,
+            decimals,
+            self.available,
+            decimals,
+            self.held,
+            decimals,
+            self.total,
+            self.locked,
+            reserved,
+            closed,
+            currency
+        )
+    }
+
+    /// Json object with the given decimal places followed by the projected columns
+    fn to_projected_json(&self, decimals: usize, projected: &[(String, ProjectedValue)]) -> String {
+        let mut object = self.to_json_with_decimals(decimals);
+        if !projected.is_empty() {
+            object.pop();
+            for (name, value) in projected {
+                object.push_str(&format!(
+                    ",{}:{}",
+                    json_string(name),
+                    value.to_json(decimals)
+                ));
+            }
+            object.push('}');
+        }
+        object
+    }
+
+    fn csv_fields(
+        &self,
+        decimals: usize,
+        reserved_column: bool,
+        closed_column: bool,
+        currency_column: bool,
+    ) -> Vec<String> {
+        let mut fields = vec![
+            self.client.to_string(),
+            format!("{:.*}", decimals, self.available),
+            format!("{:.*}", decimals, self.held),
+            format!("{:.*}", decimals, self.total),
+            self.locked.to_string(),
+        ];
+        if reserved_column {
+            fields.push(format!("{:.*}", decimals, self.reserved));
+        }
+        if closed_column {
+            fields.push(self.closed.to_string());
+        }
+        if currency_column {
+            fields.push(
+                self.currency
+                    .map_or(String::new(), |currency| currency.to_string()),
+            );
+        }
+        fields
+    }
+}
+
+fn is_zero(amount: &f64) -> bool {
+    *amount == 0.0
+}
+
+/// Amounts are serialized with [`DEFAULT_DECIMALS`] decimal places
+fn serialize_amount<S: Serializer>(amount: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{:.*}", DEFAULT_DECIMALS, amount))
+}
+
+/// Quotes and escapes a string for json output
+pub(crate) fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Destination for the final state of client accounts
+pub trait AccountWriter {
+    /// Names the columns of the [`AccountProjection`] of the next rows before any is written, so
+    /// that csv and table headers name them even when there are no rows
+    fn set_projected_columns(&mut self, _columns: Vec<String>) {}
+
+    /// Writes the row of one currency of an account followed by the columns of an
+    /// [`AccountProjection`], which must be the same for every row. Csv and table headers name
+    /// the columns set by [`AccountWriter::set_projected_columns`], else those of the first row.
+    fn write_projected(
+        &mut self,
+        snapshot: &AccountSnapshot,
+        projected: &[(String, ProjectedValue)],
+    ) -> io::Result<()>;
+
+    /// Writes the row of one currency of an account
+    fn write_snapshot(&mut self, snapshot: &AccountSnapshot) -> io::Result<()> {
+        self.write_projected(snapshot, &[])
+    }
+
+    /// Writes one row per currency of the account
+    fn write_account(&mut self, account: &dyn ClientAccount) -> io::Result<()> {
+        for snapshot in AccountSnapshot::all(account) {
+            self.write_snapshot(&snapshot)?;
+        }
+        Ok(())
+    }
+
+    /// Writes one row per currency of the account with the columns of the projection
+    fn write_account_projected(
+        &mut self,
+        account: &dyn ClientAccount,
+        projection: &dyn AccountProjection,
+    ) -> io::Result<()> {
+        self.set_projected_columns(projection.columns());
+        for snapshot in AccountSnapshot::all(account) {
+            self.write_projected(&snapshot, &projection.row(account, &snapshot)?)?;
+        }
+        Ok(())
+    }
+
+    /// Flushes any buffered output. Must be called once all accounts are written.
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+/// Accounts of the engine as csv ordered by client, with a currency column if any account holds
+/// a currency
+pub(crate) fn accounts_csv(engine: &TransactionEngine) -> String {
+    let mut output = Vec::new();
+    let currencies = engine
+        .accounts
+        .values()
+        .any(|account| account.currencies().iter().any(Option::is_some));
+    let mut writer = CsvAccountWriter::new(&mut output);
+    if currencies {
+        writer = writer.with_currency_column();
+    }
+    for snapshot in engine.snapshots(OutputOrder::Client) {
+        // Writing to memory does not fail
+        let _ = writer.write_snapshot(&snapshot);
+    }
+    let _ = writer.finish();
+    drop(writer);
+    String::from_utf8(output).expect("accounts are written as utf-8")
+}
+
+const HEADER: [&str; 5] = ["client", "available", "held", "total", "locked"];
+
+/// Writes accounts as csv rows, one per client and currency, preceded by a header
+pub struct CsvAccountWriter<W: io::Write> {
+    writer: csv::Writer<W>,
+    header_written: bool,
+    projected_columns: Option<Vec<String>>,
+    decimals: usize,
+    reserved_column: bool,
+    closed_column: bool,
+    currency_column: bool,
+}
+
+impl<W: io::Write> CsvAccountWriter<W> {
+    pub fn new(writer: W) -> Self {
+        CsvAccountWriter {
+            writer: csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(writer),
+            header_written: false,
+            projected_columns: None,
+            decimals: DEFAULT_DECIMALS,
+            reserved_column: false,
+            closed_column: false,
+            currency_column: false,
+        }
+    }
+
+    /// Writes amounts with the given decimal places instead of [`DEFAULT_DECIMALS`]
+    pub fn with_decimals(mut self, decimals: usize) -> Self {
+        self.decimals = decimals;
+        self
+    }
+
+    /// Adds a `reserved` column after `locked`, for engines with reserves
+    pub fn with_reserved_column(mut self) -> Self {
+        self.reserved_column = true;
+        self
+    }
+
+    /// Adds a `closed` column after `locked` and `reserved`, for engines with closed accounts
+    pub fn with_closed_column(mut self) -> Self {
+        self.closed_column = true;
+        self
+    }
+
+    /// Adds a trailing `currency` column, empty for funds without currency. Without it only
+    /// accounts without currencies can be written unambiguously.
+    pub fn with_currency_column(mut self) -> Self {
+        self.currency_column = true;
+        self
+    }
+
+    fn write_header(&mut self, projected: &[(String, ProjectedValue)]) -> io::Result<()> {
+        if !self.header_written {
+            let mut header = HEADER.to_vec();
+            if self.reserved_column {
+                header.push("reserved");
+            }
+            if self.closed_column {
+                header.push("closed");
+            }
+            if self.currency_column {
+                header.push("currency");
+            }
+            match &self.projected_columns {
+                Some(columns) => header.extend(columns.iter().map(String::as_str)),
+                None => header.extend(projected.iter().map(|(name, _)| name.as_str())),
+            }
+            self.writer.write_record(header)?;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+}
+
+impl CsvAccountWriter<io::Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+impl CsvAccountWriter<File> {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(File::create(path)?))
+    }
+}
+
+impl<W: io::Write> AccountWriter for CsvAccountWriter<W> {
+    fn set_projected_columns(&mut self, columns: Vec<String>) {
+        self.projected_columns = Some(columns);
+    }
+
+    fn write_projected(
+        &mut self,
+        snapshot: &AccountSnapshot,
+        projected: &[(String, ProjectedValue)],
+    ) -> io::Result<()> {
+        self.write_header(projected)?;
+        let mut fields = snapshot.csv_fields(
+            self.decimals,
+            self.reserved_column,
+            self.closed_column,
+            self.currency_column,
+        );
+        fields.extend(
+            projected
+                .iter()
+                .map(|(_, value)| value.to_field(self.decimals)),
+        );
+        self.writer.write_record(fields)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // Header is still expected when there are no accounts to write
+        self.write_header(&[])?;
+        self.writer.flush()
+    }
+}
+
+/// Writes accounts as a json array of objects, one per client and currency
+pub struct JsonAccountWriter<W: io::Write> {
+    writer: W,
+    decimals: usize,
+    accounts_written: usize,
+}
+
+impl<W: io::Write> JsonAccountWriter<W> {
+    pub fn new(writer: W) -> Self {
+        JsonAccountWriter {
+            writer,
+            decimals: DEFAULT_DECIMALS,
+            accounts_written: 0,
+        }
+    }
+
+    /// Writes amounts with the given decimal places instead of [`DEFAULT_DECIMALS`]
+    pub fn with_decimals(mut self, decimals: usize) -> Self {
+        self.decimals = decimals;
+        self
+    }
+}
+
+impl<W: io::Write> AccountWriter for JsonAccountWriter<W> {
+    fn write_projected(
+        &mut self,
+        snapshot: &AccountSnapshot,
+        projected: &[(String, ProjectedValue)],
+    ) -> io::Result<()> {
+        let separator = if self.accounts_written == 0 { "[" } else { "," };
+        writeln!(
+            self.writer,
+            "{}{}",
+            separator,
+            snapshot.to_projected_json(self.decimals, projected)
+        )?;
+        self.accounts_written += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.accounts_written == 0 {
+            write!(self.writer, "[")?;
+        }
+        writeln!(self.writer, "]")?;
+        self.writer.flush()
+    }
+}
+
+/// Writes accounts as json objects, one per line and per client and currency
+pub struct JsonlAccountWriter<W: io::Write> {
+    writer: W,
+    decimals: usize,
+}
+
+impl<W: io::Write> JsonlAccountWriter<W> {
+    pub fn new(writer: W) -> Self {
+        JsonlAccountWriter {
+            writer,
+            decimals: DEFAULT_DECIMALS,
+        }
+    }
+
+    /// Writes amounts with the given decimal places instead of [`DEFAULT_DECIMALS`]
+    pub fn with_decimals(mut self, decimals: usize) -> Self {
+        self.decimals = decimals;
+        self
+    }
+}
+
+impl<W: io::Write> AccountWriter for JsonlAccountWriter<W> {
+    fn write_projected(
+        &mut self,
+        snapshot: &AccountSnapshot,
+        projected: &[(String, ProjectedValue)],
+    ) -> io::Result<()> {
+        writeln!(
+            self.writer,
+            "{}",
+            snapshot.to_projected_json(self.decimals, projected)
+        )
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Writes accounts as a table with right-aligned columns, followed by the totals of every
+/// currency. Rows are kept until [`AccountWriter::finish`], as the width of the columns depends
+/// on all of them.
+pub struct TableAccountWriter<W: io::Write> {
+    writer: W,
+    decimals: usize,
+    projected_columns: Option<Vec<String>>,
+    snapshots: Vec<(AccountSnapshot, Vec<(String, ProjectedValue)>)>,
+}
+
+impl<W: io::Write> TableAccountWriter<W> {
+    pub fn new(writer: W) -> Self {
+        TableAccountWriter {
+            writer,
+            decimals: DEFAULT_DECIMALS,
+            projected_columns: None,
+            snapshots: Vec::new(),
+        }
+    }
+
+    /// Writes amounts with the given decimal places instead of [`DEFAULT_DECIMALS`]
+    pub fn with_decimals(mut self, decimals: usize) -> Self {
+        self.decimals = decimals;
+        self
+    }
+}
+
+impl<W: io::Write> AccountWriter for TableAccountWriter<W> {
+    fn set_projected_columns(&mut self, columns: Vec<String>) {
+        self.projected_columns = Some(columns);
+    }
+
+    fn write_projected(
+        &mut self,
+        snapshot: &AccountSnapshot,
+        projected: &[(String, ProjectedValue)],
+    ) -> io::Result<()> {
+        self.snapshots.push((snapshot.clone(), projected.to_vec()));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let currency_column = self
+            .snapshots
+            .iter()
+            .any(|(snapshot, _)| snapshot.currency.is_some());
+        let reserved_column = self
+            .snapshots
+            .iter()
+            .any(|(snapshot, _)| snapshot.reserved != 0.0);
+        let closed_column = self.snapshots.iter().any(|(snapshot, _)| snapshot.closed);
+        let projected_columns = match self.projected_columns.take() {
+            Some(columns) => columns,
+            None => self.snapshots.first().map_or(Vec::new(), |(_, projected)| {
+                projected.iter().map(|(name, _)| name.clone()).collect()
+            }),
+        };
+        let mut header = HEADER.to_vec();
+        if reserved_column {
+            header.push("reserved");
+        }
+        if closed_column {
+            header.push("closed");
+        }
+        if currency_column {
+            header.push("currency");
+        }
+        header.extend(projected_columns.iter().map(String::as_str));
+        let mut rows = vec![header.iter().map(|name| name.to_string()).collect()];
+        let mut totals: BTreeMap<Option<Currency>, [f64; 4]> = BTreeMap::new();
+        for (snapshot, projected) in self.snapshots.drain(..) {
+            let sums = totals.entry(snapshot.currency).or_default();
+            sums[0] += snapshot.available;
+            sums[1] += snapshot.held;
+            sums[2] += snapshot.total;
+            sums[3] += snapshot.reserved;
+            let mut row = snapshot.csv_fields(
+                self.decimals,
+                reserved_column,
+                closed_column,
+                currency_column,
+            );
+            row.extend(
+                projected
+                    .iter()
+                    .map(|(_, value)| value.to_field(self.decimals)),
+            );
+            rows.push(row);
+        }
+        for (currency, [available, held, total, reserved]) in totals {
+            let mut row = vec![
+                "total".to_string(),
+                format!("{:.*}", self.decimals, available),
+                format!("{:.*}", self.decimals, held),
+                format!("{:.*}", self.decimals, total),
+                String::new(),
+            ];
+            if reserved_column {
+                row.push(format!("{:.*}", self.decimals, reserved));
+            }
+            if closed_column {
+                row.push(String::new());
+            }
+            if currency_column {
+                row.push(currency.map_or(String::new(), |currency| currency.to_string()));
+            }
+            rows.push(row);
+        }
+        let mut widths = vec![0; header.len()];
+        for row in &rows {
+            for (width, field) in widths.iter_mut().zip(row) {
+                *width = (*width).max(field.len());
+            }
+        }
+        for row in rows {
+            let fields: Vec<_> = row
+                .iter()
+                .zip(&widths)
+                .map(|(field, width)| format!("{:>1$}", field, width))
+                .collect();
+            writeln!(self.writer, "{}", fields.join("  ").trim_end())?;
+        }
+        self.writer.flush()
+    }
+}
+
+/// Last line of an output written with `--trailer`: how many rows are above it, the sum of their
+/// totals as written and the SHA-256 digest of every byte before it, so that consumers can tell
+/// that the output is whole and untouched. Csv output ends with a
+/// `#trailer,rows=<N>,total=<SUM>,sha256=<HEX>` line, which csv readers skip as a comment, and json
+/// output with a `{"trailer":true,"rows":<N>,"total":<SUM>,"sha256":"<HEX>"}` line.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trailer {
+    pub rows: u64,
+    /// Sum of the totals over every currency, each rounded as written
+    pub total: f64,
+    pub sha256: [u8; 32],
+}
+
+impl Trailer {
+    /// Counts a row written with amounts of `decimals` places
+    pub fn count(&mut self, snapshot: &AccountSnapshot, decimals: usize) {
+        let scale = 10f64.powi(decimals as i32);
+        self.rows += 1;
+        self.total += (snapshot.total * scale).round() / scale;
+    }
+
+    /// Line written after an output in the format, without line break
+    pub fn to_line(&self, format: OutputFormat, decimals: usize) -> String {
+        let sha256 = sha256::to_hex(&self.sha256);
+        match format {
+            OutputFormat::Json | OutputFormat::Jsonl => format!(
+                "{{\"trailer\":true,\"rows\":{},\"total\":{:.*},\"sha256\":\"{}\"}}",
+                self.rows, decimals, self.total, sha256
+            ),
+            OutputFormat::Csv | OutputFormat::Table => format!(
+                "#trailer,rows={},total={:.*},sha256={}",
+                self.rows, decimals, self.total, sha256
+            ),
+        }
+    }
+
+    /// Trailer of a line in either format
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let fields = if let Some(fields) = line.strip_prefix("#trailer,") {
+            fields
+                .split(',')
+                .map(|field| {
+                    field
+                        .split_once('=')
+                        .map(|(key, value)| (key.to_string(), value.to_string()))
+                        .ok_or_else(|| format!("invalid trailer field '{}'", field))
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        } else if line.starts_with('{') {
+            let fields = parse_flat_object(line)?;
+            if !fields.contains(&("trailer".to_string(), "true".to_string())) {
+                return Err("the last line is not a trailer".to_string());
+            }
+            fields
+        } else {
+            return Err("the last line is not a trailer".to_string());
+        };
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+                .ok_or_else(|| format!("trailer has no {}", name))
+        };
+        let invalid = |name: &str| format!("invalid {} in trailer", name);
+        let hex = field("sha256")?;
+        let mut sha256 = [0; 32];
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err(invalid("sha256"));
+        }
+        for (byte, digits) in sha256.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+            let digits = std::str::from_utf8(digits).map_err(|_| invalid("sha256"))?;
+            *byte = u8::from_str_radix(digits, 16).map_err(|_| invalid("sha256"))?;
+        }
+        Ok(Trailer {
+            rows: field("rows")?.parse().map_err(|_| invalid("rows"))?,
+            total: field("total")?.parse().map_err(|_| invalid("total"))?,
+            sha256,
+        })
+    }
+}
+
+/// Writes through to another writer while digesting everything written, so that a [`Trailer`]
+/// can be appended once the accounts are written
+pub struct TrailerWriter<W: io::Write> {
+    inner: W,
+    digest: Sha256,
+}
+
+impl<W: io::Write> TrailerWriter<W> {
+    pub fn new(inner: W) -> Self {
+        TrailerWriter {
+            inner,
+            digest: Sha256::default(),
+        }
+    }
+
+    /// Appends the trailer, with the digest of what was written so far, on a line of its own
+    pub fn finish(
+        mut self,
+        mut trailer: Trailer,
+        format: OutputFormat,
+        decimals: usize,
+    ) -> io::Result<Trailer> {
+        trailer.sha256 = self.digest.finish();
+        writeln!(self.inner, "{}", trailer.to_line(format, decimals))?;
+        self.inner.flush()?;
+        Ok(trailer)
+    }
+}
+
+impl<W: io::Write> io::Write for TrailerWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.digest.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Checks an output against its trailer: the digest of what precedes it, then the rows and the
+/// sum of their totals. Returns the trailer if they all match.
+pub fn verify_output(contents: &[u8]) -> Result<Trailer, String> {
+    let lines = contents.strip_suffix(b"\n").unwrap_or(contents);
+    let start = lines
+        .iter()
+        .rposition(|byte| *byte == b'\n')
+        .map_or(0, |i| i + 1);
+    let (body, line) = contents.split_at(start);
+    let line = std::str::from_utf8(line).map_err(|_| "the last line is not a trailer")?;
+    let trailer = Trailer::parse(line.trim_end())?;
+    let mut digest = Sha256::default();
+    digest.update(body);
+    if digest.finish() != trailer.sha256 {
+        return Err("the checksum does not match, the output was changed or cut short".to_string());
+    }
+    let body = std::str::from_utf8(body).map_err(|err| err.to_string())?;
+    let (rows, total) = if body.starts_with('[') || body.starts_with('{') {
+        json_totals(body)?
+    } else {
+        csv_totals(body)?
+    };
+    if rows != trailer.rows {
+        return Err(format!(
+            "the output has {} rows but the trailer {}",
+            rows, trailer.rows
+        ));
+    }
+    if (total - trailer.total).abs() >= DUST {
+        return Err(format!(
+            "the totals of the output add up to {} but the trailer to {}",
+            total, trailer.total
+        ));
+    }
+    Ok(trailer)
+}
+
+/// Rows of a json array or json lines output, and the sum of their totals
+fn json_totals(body: &str) -> Result<(u64, f64), String> {
+    let (mut rows, mut total) = (0, 0.0);
+    for line in body.lines() {
+        let object = line.trim_start_matches(['[', ',']).trim_end_matches(']');
+        if object.is_empty() {
+            continue;
+        }
+        let fields = parse_flat_object(object)?;
+        let row_total = fields
+            .iter()
+            .find(|(key, _)| key == "total")
+            .and_then(|(_, value)| value.parse::<f64>().ok())
+            .ok_or_else(|| format!("row without total: {}", object))?;
+        rows += 1;
+        total += row_total;
+    }
+    Ok((rows, total))
+}
+
+/// Rows of a csv output, and the sum of their totals
+fn csv_totals(body: &str) -> Result<(u64, f64), String> {
+    let mut reader = csv::Reader::from_reader(body.as_bytes());
+    let column = reader
+        .headers()
+        .map_err(|err| err.to_string())?
+        .iter()
+        .position(|name| name == "total")
+        .ok_or("the output has no total column")?;
+    let (mut rows, mut total) = (0, 0.0);
+    for record in reader.records() {
+        let record = record.map_err(|err| err.to_string())?;
+        total += record
+            .get(column)
+            .and_then(|value| value.parse::<f64>().ok())
+            .ok_or_else(|| {
+                format!(
+                    "row without total: {}",
+                    record.iter().collect::<Vec<_>>().join(",")
+                )
+            })?;
+        rows += 1;
+    }
+    Ok((rows, total))
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::account::{BasicAccount, ClientAccount};
+        use crate::currency::Currency;
+        use crate::output::{
+            verify_output, AccountSnapshot, AccountWriter, CsvAccountWriter, JsonAccountWriter,
+            JsonlAccountWriter, OutputFormat, TableAccountWriter, Trailer, TrailerWriter,
+        };
+        use crate::transaction::TransactionId;
+
+        fn write_to_string(accounts: &[BasicAccount]) -> String {
+            let mut buffer = Vec::new();
+            {
+                let mut writer = CsvAccountWriter::new(&mut buffer);
+                for account in accounts {
+                    writer.write_account(account).unwrap();
+                }
+                writer.finish().unwrap();
+            }
+            String::from_utf8(buffer).unwrap()
+        }
+
+        #[test]
+        fn amounts_are_written_with_four_decimals() {
+            let mut account = BasicAccount::new(1);
+            account.deposit(TransactionId(0), 1.5, None).unwrap();
+
+            let output = write_to_string(&[account]);
+
+            assert_eq!(
+                output,
+                "client,available,held,total,locked\n1,1.5000,0.0000,1.5000,false\n"
+            );
+        }
+
+        #[test]
+        fn amounts_are_written_with_the_given_decimals() {
+            let mut account = BasicAccount::new(1);
+            account.deposit(TransactionId(0), 1.12345678, None).unwrap();
+            let mut csv = Vec::new();
+            let mut jsonl = Vec::new();
+            {
+                let mut writer = CsvAccountWriter::new(&mut csv).with_decimals(8);
+                writer.write_account(&account).unwrap();
+                writer.finish().unwrap();
+                let mut writer = JsonlAccountWriter::new(&mut jsonl).with_decimals(2);
+                writer.write_account(&account).unwrap();
+                writer.finish().unwrap();
+            }
+
+            assert_eq!(
+                String::from_utf8(csv).unwrap(),
+                "client,available,held,total,locked\n1,1.12345678,0.00000000,1.12345678,false\n"
+            );
+            assert_eq!(
+                String::from_utf8(jsonl).unwrap(),
+                "{\"client\":1,\"available\":1.12,\"held\":0.00,\"total\":1.12,\"locked\":false}\n"
+            );
+        }
+
+        #[test]
+        fn header_is_written_without_accounts() {
+            assert_eq!(write_to_string(&[]), "client,available,held,total,locked\n");
+        }
+
+        #[test]
+        fn written_records_can_be_read_back() {
+            let mut first = BasicAccount::new(1);
+            first.deposit(TransactionId(0), 2.0, None).unwrap();
+            first.dispute(TransactionId(0)).unwrap();
+            let mut second = BasicAccount::new(2);
+            second.deposit(TransactionId(1), 3.25, None).unwrap();
+            let expected = vec![
+                AccountSnapshot::new(&first, None),
+                AccountSnapshot::new(&second, None),
+            ];
+
+            let output = write_to_string(&[first, second]);
+            let records: Vec<AccountSnapshot> = csv::Reader::from_reader(output.as_bytes())
+                .deserialize()
+                .collect::<Result<_, _>>()
+                .unwrap();
+
+            assert_eq!(records, expected);
+        }
+
+        #[test]
+        fn writes_to_file_path() {
+            let path = std::env::temp_dir().join("rust-coding-test-output.csv");
+            let mut account = BasicAccount::new(7);
+            account.deposit(TransactionId(0), 1.0, None).unwrap();
+
+            let mut writer = CsvAccountWriter::create(&path).unwrap();
+            writer.write_account(&account).unwrap();
+            writer.finish().unwrap();
+
+            let contents = std::fs::read_to_string(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(
+                contents,
+                "client,available,held,total,locked\n7,1.0000,0.0000,1.0000,false\n"
+            );
+        }
+
+        #[test]
+        fn json_writer_emits_array_of_accounts() {
+            let mut first = BasicAccount::new(1);
+            first.deposit(TransactionId(0), 1.5, None).unwrap();
+            let second = BasicAccount::new(2);
+
+            let mut buffer = Vec::new();
+            let mut writer = JsonAccountWriter::new(&mut buffer);
+            writer.write_account(&first).unwrap();
+            writer.write_account(&second).unwrap();
+            writer.finish().unwrap();
+
+            assert_eq!(
+                String::from_utf8(buffer).unwrap(),
+                "[{\"client\":1,\"available\":1.5000,\"held\":0.0000,\"total\":1.5000,\"locked\":false}\n\
+                 ,{\"client\":2,\"available\":0.0000,\"held\":0.0000,\"total\":0.0000,\"locked\":false}\n\
+                 ]\n"
+            );
+        }
+
+        #[test]
+        fn json_writer_emits_empty_array_without_accounts() {
+            let mut buffer = Vec::new();
+            JsonAccountWriter::new(&mut buffer).finish().unwrap();
+
+            assert_eq!(String::from_utf8(buffer).unwrap(), "[]\n");
+        }
+
+        #[test]
+        fn accounts_are_written_once_per_currency() {
+            let eur: Currency = "EUR".parse().unwrap();
+            let mut account = BasicAccount::new(1);
+            account.deposit(TransactionId(0), 1.5, None).unwrap();
+            account.deposit(TransactionId(1), 2.0, Some(eur)).unwrap();
+
+            let mut csv = Vec::new();
+            let mut writer = CsvAccountWriter::new(&mut csv).with_currency_column();
+            writer.write_account(&account).unwrap();
+            writer.finish().unwrap();
+            drop(writer);
+            let mut json = Vec::new();
+            let mut writer = JsonAccountWriter::new(&mut json);
+            writer.write_account(&account).unwrap();
+            writer.finish().unwrap();
+
+            assert_eq!(
+                String::from_utf8(csv).unwrap(),
+                "client,available,held,total,locked,currency\n\
+                 1,1.5000,0.0000,1.5000,false,\n\
+                 1,2.0000,0.0000,2.0000,false,EUR\n"
+            );
+            assert!(String::from_utf8(json).unwrap().contains(
+                ",{\"client\":1,\"available\":2.0000,\"held\":0.0000,\"total\":2.0000,\"locked\":false,\"currency\":\"EUR\"}\n"
+            ));
+        }
+
+        #[test]
+        fn jsonl_writer_emits_one_object_per_line() {
+            let mut account = BasicAccount::new(1);
+            account.deposit(TransactionId(0), 1.5, None).unwrap();
+
+            let mut buffer = Vec::new();
+            let mut writer = JsonlAccountWriter::new(&mut buffer);
+            writer.write_account(&account).unwrap();
+            writer.write_account(&BasicAccount::new(2)).unwrap();
+            writer.finish().unwrap();
+
+            assert_eq!(
+                String::from_utf8(buffer).unwrap(),
+                "{\"client\":1,\"available\":1.5000,\"held\":0.0000,\"total\":1.5000,\"locked\":false}\n\
+                 {\"client\":2,\"available\":0.0000,\"held\":0.0000,\"total\":0.0000,\"locked\":false}\n"
+            );
+        }
+
+        #[test]
+        fn table_writer_aligns_columns_and_adds_totals() {
+            let eur: Currency = "EUR".parse().unwrap();
+            let mut first = BasicAccount::new(1);
+            first.deposit(TransactionId(0), 1.5, None).unwrap();
+            first.deposit(TransactionId(1), 2.0, Some(eur)).unwrap();
+            let mut second = BasicAccount::new(12);
+            second.deposit(TransactionId(2), 100.0, None).unwrap();
+            second.dispute(TransactionId(2)).unwrap();
+
+            let mut buffer = Vec::new();
+            let mut writer = TableAccountWriter::new(&mut buffer);
+            writer.write_account(&first).unwrap();
+            writer.write_account(&second).unwrap();
+            writer.finish().unwrap();
+            let mut empty = Vec::new();
+            TableAccountWriter::new(&mut empty).finish().unwrap();
+
+            assert_eq!(
+                String::from_utf8(buffer).unwrap(),
+                "client  available      held     total  locked  currency\n\
+                 \x20    1     1.5000    0.0000    1.5000   false\n\
+                 \x20    1     2.0000    0.0000    2.0000   false       EUR\n\
+                 \x20   12     0.0000  100.0000  100.0000   false\n\
+                 \x20total     1.5000  100.0000  101.5000\n\
+                 \x20total     2.0000    0.0000    2.0000               EUR\n"
+            );
+            assert_eq!(
+                String::from_utf8(empty).unwrap(),
+                "client  available  held  total  locked\n"
+            );
+        }
+
+        #[test]
+        fn trailers_are_verified_against_the_output() {
+            let mut first = BasicAccount::new(1);
+            first.deposit(TransactionId(0), 1.5, None).unwrap();
+            let mut second = BasicAccount::new(2);
+            second.deposit(TransactionId(1), 2.25, None).unwrap();
+            let write = |format: OutputFormat| {
+                let mut buffer = Vec::new();
+                let mut sink = TrailerWriter::new(&mut buffer);
+                let mut trailer = Trailer::default();
+                {
+                    let mut writer: Box<dyn AccountWriter> = match format {
+                        OutputFormat::Json => Box::new(JsonAccountWriter::new(&mut sink)),
+                        OutputFormat::Jsonl => Box::new(JsonlAccountWriter::new(&mut sink)),
+                        _ => Box::new(CsvAccountWriter::new(&mut sink)),
+                    };
+                    for account in [&first, &second] {
+                        let snapshot = AccountSnapshot::new(account, None);
+                        trailer.count(&snapshot, 4);
+                        writer.write_snapshot(&snapshot).unwrap();
+                    }
+                    writer.finish().unwrap();
+                }
+                sink.finish(trailer, format, 4).unwrap();
+                String::from_utf8(buffer).unwrap()
+            };
+
+            let csv = write(OutputFormat::Csv);
+            let trailer = csv.lines().last().unwrap();
+            assert!(trailer.starts_with("#trailer,rows=2,total=3.7500,sha256="));
+            assert_eq!(verify_output(csv.as_bytes()).unwrap().rows, 2);
+            for format in [OutputFormat::Json, OutputFormat::Jsonl] {
+                let json = write(format);
+                assert!(json
+                    .lines()
+                    .last()
+                    .unwrap()
+                    .starts_with("{\"trailer\":true,\"rows\":2,\"total\":3.7500,\"sha256\":\""));
+                assert_eq!(verify_output(json.as_bytes()).unwrap().total, 3.75);
+            }
+
+            let changed = csv.replace("2.2500", "2.2600");
+            assert_eq!(
+                verify_output(changed.as_bytes()),
+                Err("the checksum does not match, the output was changed or cut short".to_string())
+            );
+            let cut = format!("{}\n{}\n", csv.lines().next().unwrap(), trailer);
+            assert!(verify_output(cut.as_bytes()).is_err());
+            assert_eq!(
+                verify_output(b"client,available,held,total,locked\n"),
+                Err("the last line is not a trailer".to_string())
+            );
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! Account whose withdrawals and outgoing transfers may take the available funds below zero, down
+//! to the limit of its [`OverdraftPolicy`]. Selected per client in the `[accounts]` table of the
+//! configuration file, see [`AccountTypes`](crate::account::AccountTypes).
+
+use crate::account::{AccountState, Balance, BasicAccount, ClientAccount, ClientId, DisputeState};
+use crate::currency::Currency;
+use crate::error::UpdateError;
+use crate::policy::{AccountPolicies, OverdraftPolicy};
+use crate::storage::TransactionStore;
+use crate::transaction::TransactionId;
+use std::io;
+
+/// Behaves as a [`BasicAccount`] except for withdrawals, which are accepted as long as the
+/// available funds stay above minus the overdraft limit. Every withdrawal leaving the account
+/// overdrawn is charged the overdraft fee on top, and the fee must fit within the limit too.
+/// Only the withdrawn amount is disputable, fees are not. Authorizations are not covered by the
+/// overdraft.
+#[derive(Debug)]
+pub struct OverdraftAccount {
+    inner: BasicAccount,
+    overdraft: OverdraftPolicy,
+}
+
+impl OverdraftAccount {
+    pub fn new(client_id: ClientId, overdraft: OverdraftPolicy) -> Self {
+        Self::with_policies(client_id, AccountPolicies::default(), overdraft)
+    }
+
+    pub fn with_policies(
+        client_id: ClientId,
+        policies: AccountPolicies,
+        overdraft: OverdraftPolicy,
+    ) -> Self {
+        OverdraftAccount {
+            inner: BasicAccount::with_policies(client_id, policies),
+            overdraft,
+        }
+    }
+
+    /// Account keeping its disputable transactions in `store`, which must be empty
+    pub fn with_store(
+        client_id: ClientId,
+        policies: AccountPolicies,
+        overdraft: OverdraftPolicy,
+        store: Box<dyn TransactionStore>,
+    ) -> Self {
+        OverdraftAccount {
+            inner: BasicAccount::with_store(client_id, policies, store),
+            overdraft,
+        }
+    }
+
+    /// Restores the account, writing its transaction log to `store` if given
+    pub fn from_state(
+        state: AccountState,
+        policies: AccountPolicies,
+        overdraft: OverdraftPolicy,
+        store: Option<Box<dyn TransactionStore>>,
+    ) -> io::Result<Self> {
+        let inner = match store {
+            Some(store) => BasicAccount::from_state_with_store(state, policies, store)?,
+            None => BasicAccount::from_state(state, policies),
+        };
+        Ok(OverdraftAccount { inner, overdraft })
+    }
+}
+
+impl ClientAccount for OverdraftAccount {
+    fn deposit(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), UpdateError> {
+        self.inner.deposit(transaction_id, amount, currency)
+    }
+
+    /// Fails if the withdrawal, plus the fee if it overdraws the account, exceeds the available
+    /// funds and the overdraft limit
+    fn withdraw(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), UpdateError> {
+        let overdraws = self.inner.balance(currency).available < amount;
+        let fee = if overdraws { self.overdraft.fee } else { 0.0 };
+        self.inner.withdraw_down_to(
+            transaction_id,
+            amount,
+            currency,
+            fee - self.overdraft.limit,
+        )?;
+        if fee > 0.0 {
+            self.inner.charge(fee, currency);
+        }
+        Ok(())
+    }
+
+    fn adjust(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), UpdateError> {
+        self.inner.adjust(transaction_id, amount, currency)
+    }
+
+    fn post_adjustment(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), UpdateError> {
+        self.inner.post_adjustment(transaction_id, amount, currency)
+    }
+
+    fn dispute(&mut self, transaction_id: TransactionId) -> Result<(), UpdateError> {
+        self.inner.dispute(transaction_id)
+    }
+
+    fn dispute_partial(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+    ) -> Result<(), UpdateError> {
+        self.inner.dispute_partial(transaction_id, amount)
+    }
+
+    fn resolve(&mut self, transaction_id: TransactionId) -> Result<(), UpdateError> {
+        self.inner.resolve(transaction_id)
+    }
+
+    fn chargeback(&mut self, transaction_id: TransactionId) -> Result<(), UpdateError> {
+        self.inner.chargeback(transaction_id)
+    }
+
+    fn represent(&mut self, transaction_id: TransactionId) -> Result<(), UpdateError> {
+        self.inner.represent(transaction_id)
+    }
+
+    fn authorize(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), UpdateError> {
+        self.inner.authorize(transaction_id, amount, currency)
+    }
+
+    fn capture(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: Option<f64>,
+    ) -> Result<(), UpdateError> {
+        self.inner.capture(transaction_id, amount)
+    }
+
+    fn void(&mut self, transaction_id: TransactionId) -> Result<(), UpdateError> {
+        self.inner.void(transaction_id)
+    }
+
+    fn dispute_state(&self, transaction_id: TransactionId) -> (DisputeState, u32) {
+        self.inner.dispute_state(transaction_id)
+    }
+
+    fn forget(&mut self, transaction_id: TransactionId) -> bool {
+        self.inner.forget(transaction_id)
+    }
+
+    fn get_client_id(&self) -> ClientId {
+        self.inner.get_client_id()
+    }
+
+    fn balance(&self, currency: Option<Currency>) -> Balance {
+        self.inner.balance(currency)
+    }
+
+    fn currencies(&self) -> Vec<Option<Currency>> {
+        self.inner.currencies()
+    }
+
+    fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    fn set_locked(&mut self, locked: bool) {
+        self.inner.set_locked(locked)
+    }
+
+    fn state(&self) -> io::Result<AccountState> {
+        self.inner.state()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::account::ClientAccount;
+        use crate::error::UpdateError;
+        use crate::overdraft::OverdraftAccount;
+        use crate::policy::OverdraftPolicy;
+        use crate::transaction::TransactionId;
+
+        fn overdraft_account() -> OverdraftAccount {
+            let mut account = OverdraftAccount::new(
+                1,
+                OverdraftPolicy {
+                    limit: 10.0,
+                    fee: 1.0,
+                },
+            );
+            account.deposit(TransactionId(1), 5.0, None).unwrap();
+            account
+        }
+
+        #[test]
+        fn withdrawals_can_overdraw_up_to_the_limit_with_a_fee() {
+            let mut account = overdraft_account();
+
+            account.withdraw(TransactionId(2), 5.0, None).unwrap();
+            assert_eq!(account.get_available_funds(), 0.0);
+            account.withdraw(TransactionId(3), 4.0, None).unwrap();
+            assert_eq!(account.get_available_funds(), -5.0);
+            // The fee has to fit within the limit as well
+            assert_eq!(
+                account.withdraw(TransactionId(4), 4.5, None),
+                Err(UpdateError::InsufficientFunds {
+                    transaction_id: TransactionId(4),
+                    requested: 4.5,
+                    available: 4.0,
+                })
+            );
+            account.withdraw(TransactionId(5), 4.0, None).unwrap();
+            assert_eq!(account.get_available_funds(), -10.0);
+        }
+
+        #[test]
+        fn only_the_withdrawn_amount_is_disputable() {
+            let mut account = overdraft_account();
+            account.withdraw(TransactionId(2), 7.0, None).unwrap();
+            assert_eq!(account.get_available_funds(), -3.0);
+
+            account.dispute(TransactionId(2)).unwrap();
+            account.chargeback(TransactionId(2)).unwrap();
+
+            assert_eq!(account.get_available_funds(), 4.0);
+            assert!(account.is_locked());
+        }
+    }
+}
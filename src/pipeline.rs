@@ -0,0 +1,250 @@
+//! Bounded pipeline from the input to the engine: transactions are read and parsed on one
+//! thread, validated on another and applied by the caller, with a bounded queue between the
+//! stages. A stage blocks once the queue after it is full, so memory stays bounded when the
+//! producer outpaces the engine, and the depths of the queues show which stage is the bottleneck.
+
+use crate::error::InputError;
+use crate::input::TransactionSource;
+use crate::transaction::Transaction;
+use std::fmt::Write;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// Transactions waiting in a queue by default
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
+const PARSED: usize = 0;
+const VALIDATED: usize = 1;
+
+/// Transactions each queue holds before the stage feeding it blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PipelineCapacities {
+    /// Between parsing and validation
+    pub parsed: usize,
+    /// Between validation and the engine
+    pub validated: usize,
+}
+
+impl Default for PipelineCapacities {
+    fn default() -> Self {
+        PipelineCapacities {
+            parsed: DEFAULT_QUEUE_CAPACITY,
+            validated: DEFAULT_QUEUE_CAPACITY,
+        }
+    }
+}
+
+/// Transactions waiting in each queue. Counting those being handed over, a depth can exceed the
+/// capacity of its queue by up to two.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueDepths {
+    pub parsed: usize,
+    pub validated: usize,
+}
+
+/// Current and highest depths of the queues, updated by the stages as transactions go through
+#[derive(Debug, Default)]
+pub struct PipelineMetrics {
+    depths: [AtomicUsize; 2],
+    peaks: [AtomicUsize; 2],
+}
+
+impl PipelineMetrics {
+    pub fn depths(&self) -> QueueDepths {
+        Self::load(&self.depths)
+    }
+
+    pub fn peaks(&self) -> QueueDepths {
+        Self::load(&self.peaks)
+    }
+
+    /// Depths in the Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        for (name, depths) in [
+            ("pipeline_queue_depth", self.depths()),
+            ("pipeline_queue_peak_depth", self.peaks()),
+        ] {
+            // Writing to a string cannot fail
+            let _ = writeln!(out, "# TYPE {} gauge", name);
+            let _ = writeln!(out, "{}{{queue=\"parsed\"}} {}", name, depths.parsed);
+            let _ = writeln!(out, "{}{{queue=\"validated\"}} {}", name, depths.validated);
+        }
+        out
+    }
+
+    fn load(counters: &[AtomicUsize; 2]) -> QueueDepths {
+        QueueDepths {
+            parsed: counters[PARSED].load(Ordering::Relaxed),
+            validated: counters[VALIDATED].load(Ordering::Relaxed),
+        }
+    }
+
+    fn push(&self, queue: usize) {
+        let depth = self.depths[queue].fetch_add(1, Ordering::Relaxed) + 1;
+        self.peaks[queue].fetch_max(depth, Ordering::Relaxed);
+    }
+
+    fn pop(&self, queue: usize) {
+        self.depths[queue].fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Source fed by a stage running on its own thread
+struct Queue {
+    receiver: Receiver<Result<Transaction, InputError>>,
+    queue: usize,
+    metrics: Arc<PipelineMetrics>,
+    stage: Option<JoinHandle<()>>,
+}
+
+impl Queue {
+    /// Runs the source built by `stage` on a new thread, queueing up to `capacity` of its
+    /// transactions
+    fn spawn<S, F>(stage: F, capacity: usize, queue: usize, metrics: Arc<PipelineMetrics>) -> Self
+    where
+        S: TransactionSource,
+        F: FnOnce() -> S + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        let counter = metrics.clone();
+        let stage = thread::spawn(move || {
+            let mut source = stage();
+            while let Some(result) = source.next_transaction() {
+                counter.push(queue);
+                // Receiver was dropped, nobody is interested in the rest of the input
+                if sender.send(result).is_err() {
+                    break;
+                }
+            }
+        });
+        Queue {
+            receiver,
+            queue,
+            metrics,
+            stage: Some(stage),
+        }
+    }
+}
+
+impl TransactionSource for Queue {
+    fn next_transaction(&mut self) -> Option<Result<Transaction, InputError>> {
+        match self.receiver.recv() {
+            Ok(result) => {
+                self.metrics.pop(self.queue);
+                Some(result)
+            }
+            Err(_) => {
+                // Stage has finished, surface a panic of its thread as an io error
+                let stage = self.stage.take()?;
+                stage.join().err().map(|_| {
+                    Err(InputError::Io(io::Error::other(
+                        "pipeline stage thread panicked",
+                    )))
+                })
+            }
+        }
+    }
+}
+
+/// Source of the transactions that went through the parse and validation stages
+pub struct Pipeline {
+    validated: Queue,
+    metrics: Arc<PipelineMetrics>,
+}
+
+impl Pipeline {
+    /// Reads `source` on a parse thread and passes what it reads through the source `validate`
+    /// builds over it on a validation thread, e.g. an
+    /// [`OrderedSource`](crate::input::OrderedSource)
+    pub fn spawn<S, V, F>(source: S, validate: F, capacities: PipelineCapacities) -> Self
+    where
+        S: TransactionSource + Send + 'static,
+        V: TransactionSource,
+        F: FnOnce(Box<dyn TransactionSource + Send>) -> V + Send + 'static,
+    {
+        let metrics = Arc::new(PipelineMetrics::default());
+        let parsed = Queue::spawn(
+            move || source,
+            capacities.parsed.max(1),
+            PARSED,
+            metrics.clone(),
+        );
+        let validated = Queue::spawn(
+            move || validate(Box::new(parsed)),
+            capacities.validated.max(1),
+            VALIDATED,
+            metrics.clone(),
+        );
+        Pipeline { validated, metrics }
+    }
+
+    /// Metrics of the queues, readable from other threads while the pipeline runs
+    pub fn metrics(&self) -> Arc<PipelineMetrics> {
+        self.metrics.clone()
+    }
+}
+
+impl TransactionSource for Pipeline {
+    fn next_transaction(&mut self) -> Option<Result<Transaction, InputError>> {
+        self.validated.next_transaction()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::error::InputError;
+        use crate::input::{CsvSource, OrderedSource, TransactionSource};
+        use crate::pipeline::{Pipeline, PipelineCapacities, QueueDepths};
+
+        fn input(rows: u32) -> CsvSource<std::io::Cursor<String>> {
+            let mut csv = "type,client,tx,amount\n".to_string();
+            for tx in (1..=rows).rev() {
+                csv.push_str(&format!("deposit,1,{},1.0\n", tx));
+            }
+            CsvSource::new(std::io::Cursor::new(csv))
+        }
+
+        #[test]
+        fn stages_block_once_their_queue_is_full() {
+            let mut pipeline = Pipeline::spawn(
+                input(100),
+                |parsed| OrderedSource::new(parsed, 200),
+                PipelineCapacities {
+                    parsed: 4,
+                    validated: 2,
+                },
+            );
+            let metrics = pipeline.metrics();
+
+            let ids: Vec<_> = std::iter::from_fn(|| pipeline.next_transaction())
+                .map(|transaction| transaction.unwrap().transaction_id.0)
+                .collect();
+
+            assert_eq!(ids, (1..=100).collect::<Vec<_>>());
+            assert_eq!(metrics.depths(), QueueDepths::default());
+            let peaks = metrics.peaks();
+            assert!(peaks.parsed >= 1 && peaks.parsed <= 6, "{:?}", peaks);
+            assert!(peaks.validated >= 1 && peaks.validated <= 4, "{:?}", peaks);
+            assert!(metrics
+                .to_prometheus()
+                .contains("pipeline_queue_depth{queue=\"parsed\"} 0\n"));
+        }
+
+        #[test]
+        fn input_errors_go_through_the_stages() {
+            let source = CsvSource::new("type,tx\ndeposit,1\n".as_bytes());
+            let mut pipeline =
+                Pipeline::spawn(source, |parsed| parsed, PipelineCapacities::default());
+
+            assert!(matches!(
+                pipeline.next_transaction(),
+                Some(Err(InputError::MissingColumn { .. }))
+            ));
+        }
+    }
+}
@@ -0,0 +1,435 @@
+use crate::error::ConfigError;
+use crate::toml;
+use crate::transaction::TransactionType;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Decides which transactions are still accepted once an account is locked by a chargeback
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LockPolicy {
+    /// Every transaction against a locked account is rejected
+    #[default]
+    RejectAll,
+    /// Incoming funds are still accepted, everything else is rejected
+    AllowDeposits,
+}
+
+impl LockPolicy {
+    pub fn allows(&self, transaction_type: TransactionType) -> bool {
+        // Representments settle the chargeback the lock came from, resolves and chargebacks the
+        // disputes opened before it
+        if matches!(
+            transaction_type,
+            TransactionType::Represent | TransactionType::Resolve | TransactionType::Chargeback
+        ) {
+            return true;
+        }
+        match self {
+            LockPolicy::RejectAll => false,
+            LockPolicy::AllowDeposits => transaction_type == TransactionType::Deposit,
+        }
+    }
+}
+
+/// Decides what happens to deposits and withdrawals reusing an already seen transaction id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+    /// Any reuse of a transaction id is rejected
+    #[default]
+    Reject,
+    /// Re-delivery of an identical row is accepted as a no-op, a different row is rejected
+    Idempotent,
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "reject" => Ok(DuplicatePolicy::Reject),
+            "idempotent" => Ok(DuplicatePolicy::Idempotent),
+            _ => Err(format!("unknown duplicate policy '{}'", value)),
+        }
+    }
+}
+
+/// Decides what happens to input amounts with more decimal places than allowed, e.g. when an
+/// upstream system sends 8 where the run keeps 4
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PrecisionPolicy {
+    /// The row is malformed
+    #[default]
+    Reject,
+    /// The extra decimal places are dropped
+    Truncate,
+    /// The amount is rounded to the nearest allowed one, halves to the even last place
+    RoundHalfEven,
+}
+
+impl FromStr for PrecisionPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "reject" => Ok(PrecisionPolicy::Reject),
+            "truncate" => Ok(PrecisionPolicy::Truncate),
+            "round-half-even" => Ok(PrecisionPolicy::RoundHalfEven),
+            _ => Err(format!("unknown precision policy '{}'", value)),
+        }
+    }
+}
+
+/// Decides what happens when disputing a deposit would leave the available funds negative,
+/// e.g. after deposit -> withdrawal -> dispute of the deposit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NegativeBalancePolicy {
+    /// The full amount is held and available funds go negative
+    #[default]
+    Allow,
+    /// The dispute is rejected and the transaction stays disputable
+    RejectDispute,
+    /// Only the funds that are still available are held
+    HoldPartial,
+    /// The funds that are still available are held and the rest is queued as a pending hold,
+    /// held as funds come in, e.g. from later deposits, until the dispute is resolved or charged
+    /// back. Available funds never go negative, and pending holds are filled in order of dispute.
+    HoldPending,
+}
+
+impl FromStr for NegativeBalancePolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "allow" => Ok(NegativeBalancePolicy::Allow),
+            "reject-dispute" => Ok(NegativeBalancePolicy::RejectDispute),
+            "hold-partial" => Ok(NegativeBalancePolicy::HoldPartial),
+            "hold-pending" => Ok(NegativeBalancePolicy::HoldPending),
+            _ => Err(format!("unknown negative balance policy '{}'", value)),
+        }
+    }
+}
+
+/// Decides which transactions can be disputed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DisputePolicy {
+    /// Deposits and withdrawals can be disputed. Disputing a withdrawal holds a negative amount
+    /// so that a chargeback reverses the withdrawal and credits the client.
+    #[default]
+    ReverseWithdrawalOnChargeback,
+    /// Only deposits can be disputed, disputes of withdrawals are rejected
+    DepositsOnly,
+}
+
+impl DisputePolicy {
+    /// Whether a transaction that changed the available funds by `amount` can be disputed
+    pub fn is_disputable(&self, amount: f64) -> bool {
+        match self {
+            DisputePolicy::ReverseWithdrawalOnChargeback => true,
+            DisputePolicy::DepositsOnly => amount >= 0.0,
+        }
+    }
+}
+
+impl FromStr for DisputePolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "reverse-withdrawals" => Ok(DisputePolicy::ReverseWithdrawalOnChargeback),
+            "deposits-only" => Ok(DisputePolicy::DepositsOnly),
+            _ => Err(format!("unknown dispute policy '{}'", value)),
+        }
+    }
+}
+
+/// How often the same funds of a transaction can be disputed, e.g. to follow the second
+/// presentment cycle of card networks. A resolve makes the funds it released disputable again
+/// until the transaction went through this many disputes. One by default, so that resolved funds
+/// cannot be disputed again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisputeCycles(pub u32);
+
+impl Default for DisputeCycles {
+    fn default() -> Self {
+        DisputeCycles(1)
+    }
+}
+
+impl DisputeCycles {
+    /// Whether funds resolved after the given number of disputes become disputable again
+    pub fn allows_redispute(&self, cycles: u32) -> bool {
+        cycles < self.0
+    }
+}
+
+impl FromStr for DisputeCycles {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.parse() {
+            Ok(0) | Err(_) => Err(format!("invalid number of dispute cycles '{}'", value)),
+            Ok(cycles) => Ok(DisputeCycles(cycles)),
+        }
+    }
+}
+
+/// When disputes that are neither resolved nor charged back are resolved by the engine, releasing
+/// the held funds. Off by default, so that disputes stay open until they are settled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DisputeExpiry {
+    /// Transactions executed by the engine after the dispute, including rejected ones
+    pub after_transactions: Option<u64>,
+    /// Days between the timestamps of the dispute and of a later transaction. Not enforced for
+    /// disputes without a timestamp.
+    pub after_days: Option<u32>,
+}
+
+impl DisputeExpiry {
+    pub fn is_enabled(&self) -> bool {
+        self.after_transactions.is_some() || self.after_days.is_some()
+    }
+}
+
+/// Decides how many deposits, withdrawals and transfers stay disputable. Bounded policies evict
+/// the oldest transactions so that memory stays flat on very large inputs. Evicted transactions
+/// can no longer be disputed, and reusing their id is no longer detected as a duplicate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RetentionPolicy {
+    /// Every transaction stays disputable
+    #[default]
+    Unbounded,
+    /// Only the last N transactions of each client stay disputable
+    PerClient(usize),
+    /// At most N transactions stay disputable across all clients, the oldest are evicted first
+    Global(usize),
+}
+
+impl FromStr for RetentionPolicy {
+    type Err = String;
+
+    /// Parses `unbounded`, `per-client:<N>` or `global:<N>`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("unknown retention policy '{}'", value);
+        if value == "unbounded" {
+            return Ok(RetentionPolicy::Unbounded);
+        }
+        let (mode, limit) = value.split_once(':').ok_or_else(invalid)?;
+        let limit = match limit.parse() {
+            Ok(0) | Err(_) => return Err(invalid()),
+            Ok(limit) => limit,
+        };
+        match mode {
+            "per-client" => Ok(RetentionPolicy::PerClient(limit)),
+            "global" => Ok(RetentionPolicy::Global(limit)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Policies applied by an account to its own operations
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AccountPolicies {
+    pub lock: LockPolicy,
+    pub negative_balance: NegativeBalancePolicy,
+    pub dispute: DisputePolicy,
+    pub max_dispute_cycles: DisputeCycles,
+    /// Lift the lock once every chargeback of the account was represented
+    pub unlock_on_representment: bool,
+}
+
+/// Implementation of a client's account, see
+/// [`AccountTypes`](crate::account::AccountTypes)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AccountKind {
+    /// [`BasicAccount`](crate::account::BasicAccount), never going below zero on withdrawals
+    #[default]
+    Basic,
+    /// [`OverdraftAccount`](crate::overdraft::OverdraftAccount), withdrawing down to the
+    /// [`OverdraftPolicy`] limit
+    Overdraft,
+    /// [`SavingsAccount`](crate::savings::SavingsAccount), settling withdrawals after the
+    /// [`SavingsPolicy`] delay
+    Savings,
+}
+
+impl FromStr for AccountKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "basic" => Ok(AccountKind::Basic),
+            "overdraft" => Ok(AccountKind::Overdraft),
+            "savings" => Ok(AccountKind::Savings),
+            _ => Err(format!("unknown account type '{}'", value)),
+        }
+    }
+}
+
+/// Overdraft granted to overdraft accounts
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct OverdraftPolicy {
+    /// How far below zero withdrawals and transfers can take the available funds
+    pub limit: f64,
+    /// Charged on every withdrawal or transfer that leaves the account overdrawn, within the
+    /// limit. Not disputable.
+    pub fee: f64,
+}
+
+impl OverdraftPolicy {
+    pub(crate) fn from_table(table: &toml::Table) -> Result<Self, ConfigError> {
+        let mut overdraft = OverdraftPolicy::default();
+        for (key, entry) in &table.entries {
+            match key.as_str() {
+                "limit" => overdraft.limit = non_negative(key, entry)?,
+                "fee" => overdraft.fee = non_negative(key, entry)?,
+                _ => return Err(entry.invalid(format!("unknown key '{}' in [overdraft]", key))),
+            }
+        }
+        Ok(overdraft)
+    }
+}
+
+/// Settlement delay of savings accounts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SavingsPolicy {
+    /// Ticks a withdrawal stays held before it settles, settling at once when zero
+    pub withdrawal_delay: u32,
+}
+
+impl SavingsPolicy {
+    pub(crate) fn from_table(table: &toml::Table) -> Result<Self, ConfigError> {
+        let mut savings = SavingsPolicy::default();
+        for (key, entry) in &table.entries {
+            match key.as_str() {
+                "withdrawal_delay" => {
+                    savings.withdrawal_delay = u32::try_from(entry.as_count(key)?)
+                        .map_err(|_| entry.invalid(format!("'{}' is too large", key)))?
+                }
+                _ => return Err(entry.invalid(format!("unknown key '{}' in [savings]", key))),
+            }
+        }
+        Ok(savings)
+    }
+}
+
+/// Limits on the transactions of every client, unlimited unless set. Transactions breaking a
+/// limit are rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LimitsPolicy {
+    /// Largest amount of a single deposit, withdrawal or transfer
+    pub max_transaction_amount: Option<f64>,
+    /// Largest amount a client can withdraw or transfer away in each currency until
+    /// [`TransactionEngine::reset_daily_limits`](crate::engine::TransactionEngine::reset_daily_limits)
+    /// is called, or until the day of a timestamped withdrawal changes. Without timestamps a batch
+    /// run counts as a single day.
+    pub daily_withdrawal_cap: Option<f64>,
+    /// Most transactions accepted from a client in a run
+    pub max_transactions_per_client: Option<u64>,
+}
+
+impl LimitsPolicy {
+    /// Reads the `[limits]` table of a TOML file. Other tables are ignored.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+
+    pub fn from_toml(input: &str) -> Result<Self, ConfigError> {
+        match toml::parse(input)?.get("limits") {
+            Some(table) => Self::from_table(table),
+            None => Ok(LimitsPolicy::default()),
+        }
+    }
+
+    pub(crate) fn from_table(table: &toml::Table) -> Result<Self, ConfigError> {
+        let mut limits = LimitsPolicy::default();
+        for (key, entry) in &table.entries {
+            match key.as_str() {
+                "max_transaction_amount" => {
+                    limits.max_transaction_amount = Some(positive(key, entry)?)
+                }
+                "daily_withdrawal_cap" => limits.daily_withdrawal_cap = Some(positive(key, entry)?),
+                "max_transactions_per_client" => {
+                    limits.max_transactions_per_client = Some(entry.as_count(key)?)
+                }
+                _ => return Err(entry.invalid(format!("unknown limit '{}'", key))),
+            }
+        }
+        Ok(limits)
+    }
+}
+
+fn positive(key: &str, entry: &toml::Entry) -> Result<f64, ConfigError> {
+    match entry.as_number(key)? {
+        value if value > 0.0 => Ok(value),
+        value => Err(entry.invalid(format!("'{}' must be positive, found {}", key, value))),
+    }
+}
+
+fn non_negative(key: &str, entry: &toml::Entry) -> Result<f64, ConfigError> {
+    match entry.as_number(key)? {
+        value if value >= 0.0 => Ok(value),
+        value => Err(entry.invalid(format!("'{}' must not be negative, found {}", key, value))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::error::ConfigError;
+        use crate::policy::{LimitsPolicy, RetentionPolicy};
+
+        #[test]
+        fn limits_are_read_from_toml() {
+            let limits = LimitsPolicy::from_toml(
+                "[limits]\n\
+                 max_transaction_amount = 1_000\n\
+                 daily_withdrawal_cap = 2500.5\n\
+                 max_transactions_per_client = 100\n",
+            )
+            .unwrap();
+
+            assert_eq!(
+                limits,
+                LimitsPolicy {
+                    max_transaction_amount: Some(1000.0),
+                    daily_withdrawal_cap: Some(2500.5),
+                    max_transactions_per_client: Some(100),
+                }
+            );
+            assert_eq!(
+                LimitsPolicy::from_toml("").unwrap(),
+                LimitsPolicy::default()
+            );
+        }
+
+        #[test]
+        fn invalid_limits_are_rejected() {
+            for input in [
+                "[limits]\nmax_deposit = 1\n",
+                "[limits]\nmax_transaction_amount = -1\n",
+                "[limits]\nmax_transactions_per_client = 1.5\n",
+            ] {
+                assert!(
+                    matches!(
+                        LimitsPolicy::from_toml(input),
+                        Err(ConfigError::Invalid { line: 2, .. })
+                    ),
+                    "{}",
+                    input
+                );
+            }
+        }
+
+        #[test]
+        fn retention_policies_are_parsed() {
+            assert_eq!("unbounded".parse(), Ok(RetentionPolicy::Unbounded));
+            assert_eq!("per-client:10".parse(), Ok(RetentionPolicy::PerClient(10)));
+            assert_eq!("global:1000".parse(), Ok(RetentionPolicy::Global(1000)));
+            for invalid in ["global", "global:0", "lru:5", "per-client:x"] {
+                assert!(invalid.parse::<RetentionPolicy>().is_err(), "{}", invalid);
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Interface shared by the engines, so that the single threaded
+//! [`TransactionEngine`], the [`ShardedEngine`](crate::sharded::ShardedEngine), the
+//! [`ConcurrentEngine`](crate::concurrent::ConcurrentEngine), the
+//! [`ActorEngine`](crate::actor::ActorEngine) and test doubles can be swapped for one another by
+//! code that only executes transactions and reads accounts back. The
+//! [`Server`](crate::server::Server) and the [`Consumer`](crate::consumer::Consumer) keep a
+//! [`TransactionEngine`], which they checkpoint and persist.
+//!
+//! ```
+//! use rust_coding_test::{
+//!     Amount, EngineConfig, ShardedEngine, Transaction, TransactionEngine, TransactionId,
+//!     TransactionProcessor, TransactionType,
+//! };
+//!
+//! fn deposit<P: TransactionProcessor>(mut engine: P) -> TransactionEngine {
+//!     engine
+//!         .execute(Transaction {
+//!             transaction_type: TransactionType::Deposit,
+//!             client_id: 1,
+//!             transaction_id: TransactionId(1),
+//!             amount: Some(Amount::new(2.5).unwrap()),
+//!             to_client_id: None,
+//!             currency: None,
+//!             timestamp: None,
+//!             metadata: Default::default(),
+//!         })
+//!         .unwrap();
+//!     assert_eq!(engine.account_snapshot(1).unwrap().available, 2.5);
+//!     engine.finalize().0
+//! }
+//!
+//! deposit(TransactionEngine::new());
+//! deposit(ShardedEngine::new(2, EngineConfig::default()));
+//! ```
+
+use crate::account::ClientId;
+use crate::engine::TransactionEngine;
+use crate::error::EngineError;
+use crate::output::AccountSnapshot;
+use crate::transaction::{Origin, Transaction};
+
+/// Executes transactions against accounts that can be read back at any time. Reads reflect
+/// every transaction executed before them, including those still queued by engines applying
+/// them asynchronously.
+pub trait TransactionProcessor {
+    /// Applies the transaction, see [`TransactionEngine::execute_from`]. Engines queueing
+    /// transactions accept them right away and return the rejections from
+    /// [`TransactionProcessor::finalize`] instead.
+    fn execute_from(&mut self, transaction: Transaction, origin: Origin)
+        -> Result<(), EngineError>;
+
+    /// Applies a transaction submitted by a client
+    fn execute(&mut self, transaction: Transaction) -> Result<(), EngineError> {
+        self.execute_from(transaction, Origin::Client)
+    }
+
+    /// Funds without currency of the client's account, if the client has one
+    fn account_snapshot(&self, client_id: ClientId) -> Option<AccountSnapshot>;
+
+    /// Funds of every account, one snapshot per currency of each, in no particular order
+    fn iter_snapshots(&self) -> Box<dyn Iterator<Item = AccountSnapshot> + '_>;
+
+    /// Waits for the queued transactions and returns every account in a single engine, e.g. to
+    /// write them or snapshot it, with the rejections not returned by
+    /// [`TransactionProcessor::execute_from`] in the order the transactions were executed
+    fn finalize(self) -> (TransactionEngine, Vec<EngineError>)
+    where
+        Self: Sized;
+}
+
+impl TransactionProcessor for TransactionEngine {
+    fn execute_from(
+        &mut self,
+        transaction: Transaction,
+        origin: Origin,
+    ) -> Result<(), EngineError> {
+        TransactionEngine::execute_from(self, transaction, origin)
+    }
+
+    fn account_snapshot(&self, client_id: ClientId) -> Option<AccountSnapshot> {
+        TransactionEngine::account_snapshot(self, client_id)
+    }
+
+    fn iter_snapshots(&self) -> Box<dyn Iterator<Item = AccountSnapshot> + '_> {
+        Box::new(TransactionEngine::iter_snapshots(self))
+    }
+
+    fn finalize(self) -> (TransactionEngine, Vec<EngineError>) {
+        (self, Vec::new())
+    }
+}
@@ -0,0 +1,254 @@
+//! Latencies of the transactions executed by an engine per type and per account, collected when
+//! [`EngineConfig::profile`](crate::engine::EngineConfig::profile) is enabled and printed after a
+//! batch run with `--profile`, to find which transactions and clients are worth optimizing, e.g.
+//! the accounts of a dispute-heavy workload with long transaction logs.
+
+use crate::account::ClientId;
+use crate::transaction::TransactionType;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::time::Duration;
+
+/// Accounts listed by the report of a [`Profile`]
+pub const HOTTEST_ACCOUNTS: usize = 10;
+
+/// Bits of a latency in nanoseconds kept below its highest bit, so that quantiles are within
+/// 1/16 of the actual latency
+const SUB_BUCKET_BITS: u32 = 4;
+
+/// Log-linear histogram of latencies in nanoseconds, bounded in memory whatever the number of
+/// transactions
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Latencies {
+    counts: Vec<u64>,
+    total: Duration,
+    max: Duration,
+}
+
+impl Latencies {
+    fn bucket(nanos: u64) -> usize {
+        let sub_buckets = 1 << SUB_BUCKET_BITS;
+        if nanos < sub_buckets {
+            return nanos as usize;
+        }
+        let magnitude = 63 - nanos.leading_zeros();
+        let shift = magnitude - SUB_BUCKET_BITS;
+        ((shift + 1) as usize) * sub_buckets as usize + ((nanos >> shift) - sub_buckets) as usize
+    }
+
+    /// Lowest latency in nanoseconds falling into the bucket
+    fn lower_bound(bucket: usize) -> u64 {
+        let sub_buckets = 1 << SUB_BUCKET_BITS;
+        if bucket < sub_buckets {
+            return bucket as u64;
+        }
+        let shift = (bucket / sub_buckets - 1) as u32;
+        ((bucket % sub_buckets + sub_buckets) as u64) << shift
+    }
+
+    pub(crate) fn record(&mut self, latency: Duration) {
+        let bucket = Self::bucket(latency.as_nanos().min(u64::MAX as u128) as u64);
+        if self.counts.len() <= bucket {
+            self.counts.resize(bucket + 1, 0);
+        }
+        self.counts[bucket] += 1;
+        self.total += latency;
+        self.max = self.max.max(latency);
+    }
+
+    fn merge(&mut self, other: &Latencies) {
+        if self.counts.len() < other.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        self.total += other.total;
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Time spent on every recorded transaction
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// Latency below which the share `quantile` of the transactions fall, e.g. 0.99 for the p99,
+    /// zero if nothing was recorded
+    pub fn quantile(&self, quantile: f64) -> Duration {
+        let rank = (quantile.clamp(0.0, 1.0) * self.count() as f64)
+            .ceil()
+            .max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_nanos(Self::lower_bound(bucket)).min(self.max);
+            }
+        }
+        Duration::ZERO
+    }
+}
+
+/// Latencies per transaction type and per account
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    types: BTreeMap<&'static str, Latencies>,
+    /// Transactions and time spent per client executing them
+    accounts: HashMap<ClientId, (u64, Duration)>,
+}
+
+impl Profile {
+    pub(crate) fn record(
+        &mut self,
+        transaction_type: TransactionType,
+        client_id: ClientId,
+        latency: Duration,
+    ) {
+        self.types
+            .entry(transaction_type.as_str())
+            .or_default()
+            .record(latency);
+        let (count, total) = self.accounts.entry(client_id).or_default();
+        *count += 1;
+        *total += latency;
+    }
+
+    /// Adds the profile of another engine, e.g. a shard
+    pub(crate) fn merge(&mut self, other: &Profile) {
+        for (transaction_type, latencies) in &other.types {
+            self.types
+                .entry(transaction_type)
+                .or_default()
+                .merge(latencies);
+        }
+        for (client_id, (count, total)) in &other.accounts {
+            let (own_count, own_total) = self.accounts.entry(*client_id).or_default();
+            *own_count += count;
+            *own_total += *total;
+        }
+    }
+
+    /// Latencies of the transactions of the type, empty if none was executed
+    pub fn latencies(&self, transaction_type: TransactionType) -> Latencies {
+        self.types
+            .get(transaction_type.as_str())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Up to `count` clients whose transactions took the most time, with the number of
+    /// transactions and that time, slowest first
+    pub fn hottest_accounts(&self, count: usize) -> Vec<(ClientId, u64, Duration)> {
+        let mut accounts: Vec<_> = self
+            .accounts
+            .iter()
+            .map(|(client_id, (transactions, total))| (*client_id, *transactions, *total))
+            .collect();
+        accounts.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+        accounts.truncate(count);
+        accounts
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let transactions: u64 = self.types.values().map(Latencies::count).sum();
+        writeln!(f, "Profiled {} transactions", transactions)?;
+        for transaction_type in TransactionType::ALL {
+            let latencies = self.latencies(transaction_type);
+            if latencies.count() == 0 {
+                continue;
+            }
+            writeln!(
+                f,
+                "  {}: {}, p50 {:?}, p99 {:?}, max {:?}, total {:?}",
+                transaction_type,
+                latencies.count(),
+                latencies.quantile(0.5),
+                latencies.quantile(0.99),
+                latencies.max(),
+                latencies.total()
+            )?;
+        }
+        let hottest = self.hottest_accounts(HOTTEST_ACCOUNTS);
+        writeln!(f, "Hottest {} accounts", hottest.len())?;
+        for (client_id, transactions, total) in hottest {
+            writeln!(
+                f,
+                "  client {}: {} transactions, total {:?}",
+                client_id, transactions, total
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::profile::{Latencies, Profile};
+        use crate::transaction::TransactionType;
+        use std::time::Duration;
+
+        #[test]
+        fn quantiles_are_within_a_sixteenth() {
+            for nanos in [0, 1, 15, 16, 17, 31, 32, 1000, 123_456, 9_876_543_210] {
+                let bucket = Latencies::bucket(nanos);
+                let lower = Latencies::lower_bound(bucket);
+                assert!(lower <= nanos, "{}", nanos);
+                assert!(nanos - lower <= nanos / 16, "{}", nanos);
+                assert_eq!(Latencies::bucket(lower), bucket, "{}", nanos);
+            }
+
+            let mut latencies = Latencies::default();
+            for micros in 1..=100 {
+                latencies.record(Duration::from_micros(micros));
+            }
+            assert_eq!(latencies.count(), 100);
+            let p50 = latencies.quantile(0.5).as_nanos();
+            assert!((47_000..=50_000).contains(&p50), "{}", p50);
+            let p99 = latencies.quantile(0.99).as_nanos();
+            assert!((93_000..=99_000).contains(&p99), "{}", p99);
+            assert_eq!(latencies.max(), Duration::from_micros(100));
+            assert_eq!(Latencies::default().quantile(0.5), Duration::ZERO);
+        }
+
+        #[test]
+        fn hottest_accounts_took_the_most_time() {
+            let mut profile = Profile::default();
+            let mut shard = Profile::default();
+            profile.record(TransactionType::Deposit, 1, Duration::from_micros(3));
+            profile.record(TransactionType::Dispute, 2, Duration::from_micros(10));
+            shard.record(TransactionType::Deposit, 1, Duration::from_micros(2));
+            shard.record(TransactionType::Deposit, 3, Duration::from_micros(1));
+            profile.merge(&shard);
+
+            assert_eq!(profile.latencies(TransactionType::Deposit).count(), 3);
+            assert_eq!(
+                profile.hottest_accounts(2),
+                [
+                    (2, 1, Duration::from_micros(10)),
+                    (1, 2, Duration::from_micros(5)),
+                ]
+            );
+            assert_eq!(
+                profile.to_string(),
+                "Profiled 4 transactions\n  \
+                 deposit: 3, p50 1.984µs, p99 2.944µs, max 3µs, total 6µs\n  \
+                 dispute: 1, p50 9.728µs, p99 9.728µs, max 10µs, total 10µs\n\
+                 Hottest 3 accounts\n  \
+                 client 2: 1 transactions, total 10µs\n  \
+                 client 1: 2 transactions, total 5µs\n  \
+                 client 3: 1 transactions, total 1µs\n"
+            );
+        }
+    }
+}
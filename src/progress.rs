@@ -0,0 +1,127 @@
+//! Progress indicator of batch runs, drawn on stderr while the input is read. Each redraw returns
+//! the cursor with a carriage return and overwrites the line in place.
+
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Redraws the bytes read so far against the size of the input on a background thread, and
+/// clears the line when dropped
+pub struct Progress {
+    read: Arc<AtomicU64>,
+    stop: Sender<()>,
+    drawer: Option<JoinHandle<()>>,
+}
+
+impl Progress {
+    /// Starts drawing for an input of `total` bytes, unknown when reading stdin. None when stderr
+    /// is not a terminal, so that nothing ends up in log files or pipes.
+    pub fn start(total: Option<u64>) -> Option<Self> {
+        if !io::stderr().is_terminal() {
+            return None;
+        }
+        let read = Arc::new(AtomicU64::new(0));
+        let (stop, stopped) = mpsc::channel();
+        let counter = read.clone();
+        let drawer = thread::spawn(move || {
+            let started = Instant::now();
+            let mut stderr = io::stderr();
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(REDRAW_INTERVAL) {
+                let line = render(counter.load(Ordering::Relaxed), total, started.elapsed());
+                let _ = write!(stderr, "\r{}\x1b[K", line);
+            }
+            let _ = write!(stderr, "\r\x1b[K");
+        });
+        Some(Progress {
+            read,
+            stop,
+            drawer: Some(drawer),
+        })
+    }
+
+    /// Counter the sources reading the input add the bytes they read to
+    pub fn counter(&self) -> Arc<AtomicU64> {
+        self.read.clone()
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        let _ = self.stop.send(());
+        if let Some(drawer) = self.drawer.take() {
+            let _ = drawer.join();
+        }
+    }
+}
+
+/// Line showing `read` bytes out of `total`, with the time left extrapolated from `elapsed`
+fn render(read: u64, total: Option<u64>, elapsed: Duration) -> String {
+    let total = match total {
+        Some(total) if total > 0 => total,
+        _ => return format!("{} read", bytes(read)),
+    };
+    let read = read.min(total);
+    let mut line = format!(
+        "{:5.1}% {} / {}",
+        read as f64 * 100.0 / total as f64,
+        bytes(read),
+        bytes(total)
+    );
+    if read > 0 {
+        let left = elapsed.as_secs_f64() * (total - read) as f64 / read as f64;
+        line.push_str(&format!(", {} left", duration(left as u64)));
+    }
+    line
+}
+
+fn bytes(count: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if count < 1024 {
+        return format!("{} B", count);
+    }
+    let mut value = count as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn duration(secs: u64) -> String {
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::progress::render;
+        use std::time::Duration;
+
+        #[test]
+        fn progress_shows_share_read_and_time_left() {
+            let elapsed = Duration::from_secs(30);
+
+            assert_eq!(
+                render(3 * 1024 * 1024, Some(12 * 1024 * 1024), elapsed),
+                " 25.0% 3.0 MiB / 12.0 MiB, 1:30 left"
+            );
+            assert_eq!(
+                render(1_000_000_000, Some(4_000_000_000), elapsed * 40),
+                " 25.0% 953.7 MiB / 3.7 GiB, 1:00:00 left"
+            );
+            assert_eq!(render(0, Some(2048), elapsed), "  0.0% 0 B / 2.0 KiB");
+            // Stdin has no known size
+            assert_eq!(render(1536, None, elapsed), "1.5 KiB read");
+        }
+    }
+}
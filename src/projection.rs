@@ -0,0 +1,306 @@
+//! Extra output columns computed from every account, for reports needing more than its funds,
+//! such as the number of open disputes. An [`AccountProjection`] names its columns and gives
+//! their values for every row, which every [`AccountWriter`](crate::AccountWriter) writes after
+//! the standard columns, so that reports can be extended without touching the writers:
+//!
+//! ```
+//! use rust_coding_test::{
+//!     AccountProjection, AccountSnapshot, AccountWriter, ClientAccount, CsvAccountWriter,
+//!     OpenDisputes, OutputOrder, ProjectedValue, TransactionEngine,
+//! };
+//! use std::io;
+//!
+//! /// Whether the available funds of the account cover a fee of 10
+//! struct CoversFee;
+//!
+//! impl AccountProjection for CoversFee {
+//!     fn columns(&self) -> Vec<String> {
+//!         vec!["covers_fee".to_string()]
+//!     }
+//!
+//!     fn project(
+//!         &self,
+//!         _: &dyn ClientAccount,
+//!         snapshot: &AccountSnapshot,
+//!     ) -> io::Result<Vec<ProjectedValue>> {
+//!         Ok(vec![ProjectedValue::Flag(snapshot.available >= 10.0)])
+//!     }
+//! }
+//!
+//! let engine = TransactionEngine::new();
+//! let mut output = Vec::new();
+//! let mut writer = CsvAccountWriter::new(&mut output);
+//! let projection: [&dyn AccountProjection; 2] = [&OpenDisputes, &CoversFee];
+//! engine.write_projected(&mut writer, OutputOrder::Client, &projection).unwrap();
+//! writer.finish().unwrap();
+//! ```
+//!
+//! Projections needing more than the account, such as the history of the ledger, can hold a
+//! reference to the engine.
+
+use crate::account::ClientAccount;
+use crate::output::{json_string, AccountSnapshot};
+use std::io;
+
+/// Value of an extra column of one row
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProjectedValue {
+    /// Written with the decimal places of the writer
+    Amount(f64),
+    Count(u64),
+    Flag(bool),
+    Text(String),
+    /// Empty csv field, json `null`
+    Missing,
+}
+
+impl ProjectedValue {
+    /// Field of csv and table output
+    pub(crate) fn to_field(&self, decimals: usize) -> String {
+        match self {
+            ProjectedValue::Amount(amount) => format!("{:.*}", decimals, amount),
+            ProjectedValue::Count(count) => count.to_string(),
+            ProjectedValue::Flag(flag) => flag.to_string(),
+            ProjectedValue::Text(text) => text.clone(),
+            ProjectedValue::Missing => String::new(),
+        }
+    }
+
+    /// Json value, with amounts written as numbers like those of the standard columns
+    pub(crate) fn to_json(&self, decimals: usize) -> String {
+        match self {
+            ProjectedValue::Text(text) => json_string(text),
+            ProjectedValue::Missing => "null".to_string(),
+            value => value.to_field(decimals),
+        }
+    }
+}
+
+/// Extra columns of the output of accounts, written after the standard ones
+pub trait AccountProjection {
+    /// Names of the columns, the same for every row
+    fn columns(&self) -> Vec<String>;
+
+    /// Values of the columns for the row of one currency of the account, in the order of
+    /// [`AccountProjection::columns`]. Fails if the state of the account cannot be read.
+    fn project(
+        &self,
+        account: &dyn ClientAccount,
+        snapshot: &AccountSnapshot,
+    ) -> io::Result<Vec<ProjectedValue>>;
+
+    /// Columns of the row with their values, as the writers take them. Fails if the projection
+    /// gives more or fewer values than it has columns.
+    fn row(
+        &self,
+        account: &dyn ClientAccount,
+        snapshot: &AccountSnapshot,
+    ) -> io::Result<Vec<(String, ProjectedValue)>> {
+        let columns = self.columns();
+        let values = self.project(account, snapshot)?;
+        if values.len() != columns.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "projection gives {} values for its {} columns",
+                    values.len(),
+                    columns.len()
+                ),
+            ));
+        }
+        Ok(columns.into_iter().zip(values).collect())
+    }
+}
+
+/// Columns of every projection, one after the other
+impl<const N: usize> AccountProjection for [&dyn AccountProjection; N] {
+    fn columns(&self) -> Vec<String> {
+        self.iter()
+            .flat_map(|projection| projection.columns())
+            .collect()
+    }
+
+    fn project(
+        &self,
+        account: &dyn ClientAccount,
+        snapshot: &AccountSnapshot,
+    ) -> io::Result<Vec<ProjectedValue>> {
+        let mut values = Vec::new();
+        for projection in self {
+            values.extend(projection.project(account, snapshot)?);
+        }
+        Ok(values)
+    }
+}
+
+/// `open_disputes` column with the number of transactions under dispute in the currency of the
+/// row
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OpenDisputes;
+
+impl AccountProjection for OpenDisputes {
+    fn columns(&self) -> Vec<String> {
+        vec!["open_disputes".to_string()]
+    }
+
+    fn project(
+        &self,
+        account: &dyn ClientAccount,
+        snapshot: &AccountSnapshot,
+    ) -> io::Result<Vec<ProjectedValue>> {
+        let disputes = account
+            .state()?
+            .active_disputes
+            .iter()
+            .filter(|(_, currency, _)| *currency == snapshot.currency)
+            .count();
+        Ok(vec![ProjectedValue::Count(disputes as u64)])
+    }
+}
+
+/// `last_tx` column with the highest id among the disputable transactions of the account in the
+/// currency of the row, empty without one. Ids are those of the input, which feeds allocate in
+/// order.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LastTransaction;
+
+impl AccountProjection for LastTransaction {
+    fn columns(&self) -> Vec<String> {
+        vec!["last_tx".to_string()]
+    }
+
+    fn project(
+        &self,
+        account: &dyn ClientAccount,
+        snapshot: &AccountSnapshot,
+    ) -> io::Result<Vec<ProjectedValue>> {
+        let last = account
+            .state()?
+            .transaction_log
+            .iter()
+            .rev()
+            .find(|(_, currency, _)| *currency == snapshot.currency)
+            .map_or(ProjectedValue::Missing, |(transaction_id, _, _)| {
+                ProjectedValue::Count(u64::from(transaction_id.0))
+            });
+        Ok(vec![last])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::account::{BasicAccount, ClientAccount};
+        use crate::engine::TransactionEngine;
+        use crate::output::{
+            AccountSnapshot, AccountWriter, CsvAccountWriter, JsonlAccountWriter, OutputOrder,
+            TableAccountWriter,
+        };
+        use crate::projection::{AccountProjection, LastTransaction, OpenDisputes, ProjectedValue};
+        use crate::transaction::{transaction, TransactionType};
+        use std::io;
+
+        fn write(engine: &TransactionEngine, mut writer: impl AccountWriter) {
+            let projection: [&dyn AccountProjection; 2] = [&OpenDisputes, &LastTransaction];
+            engine
+                .write_projected(&mut writer, OutputOrder::Client, &projection)
+                .unwrap();
+            writer.finish().unwrap();
+        }
+
+        #[test]
+        fn projected_columns_follow_the_standard_ones() {
+            let mut engine = TransactionEngine::new();
+            for transaction in [
+                transaction(TransactionType::Deposit, 1, 1, Some(2.0)),
+                transaction(TransactionType::Deposit, 1, 3, Some(1.0)),
+                transaction(TransactionType::Dispute, 1, 1, None),
+            ] {
+                engine.execute(transaction).unwrap();
+            }
+            // Account without transactions of its own
+            engine.accounts.insert(2, Box::new(BasicAccount::new(2)));
+
+            let mut csv = Vec::new();
+            write(&engine, CsvAccountWriter::new(&mut csv));
+            let mut jsonl = Vec::new();
+            write(&engine, JsonlAccountWriter::new(&mut jsonl));
+            let mut table = Vec::new();
+            write(&engine, TableAccountWriter::new(&mut table));
+
+            assert_eq!(
+                String::from_utf8(csv).unwrap(),
+                "client,available,held,total,locked,open_disputes,last_tx\n\
+                 1,1.0000,2.0000,3.0000,false,1,3\n\
+                 2,0.0000,0.0000,0.0000,false,0,\n"
+            );
+            assert_eq!(
+                String::from_utf8(jsonl).unwrap(),
+                "{\"client\":1,\"available\":1.0000,\"held\":2.0000,\"total\":3.0000,\
+                 \"locked\":false,\"open_disputes\":1,\"last_tx\":3}\n\
+                 {\"client\":2,\"available\":0.0000,\"held\":0.0000,\"total\":0.0000,\
+                 \"locked\":false,\"open_disputes\":0,\"last_tx\":null}\n"
+            );
+            assert_eq!(
+                String::from_utf8(table).unwrap(),
+                "client  available    held   total  locked  open_disputes  last_tx\n\
+                 \x20    1     1.0000  2.0000  3.0000   false              1        3\n\
+                 \x20    2     0.0000  0.0000  0.0000   false              0\n\
+                 \x20total     1.0000  2.0000  3.0000\n"
+            );
+        }
+
+        /// Projection naming one column but giving two values
+        struct Mismatched;
+
+        impl AccountProjection for Mismatched {
+            fn columns(&self) -> Vec<String> {
+                vec!["flag".to_string()]
+            }
+
+            fn project(
+                &self,
+                _: &dyn ClientAccount,
+                _: &AccountSnapshot,
+            ) -> io::Result<Vec<ProjectedValue>> {
+                Ok(vec![ProjectedValue::Flag(true), ProjectedValue::Missing])
+            }
+        }
+
+        #[test]
+        fn headers_name_projected_columns_without_rows() {
+            let engine = TransactionEngine::new();
+            let mut csv = Vec::new();
+            write(&engine, CsvAccountWriter::new(&mut csv));
+            let mut table = Vec::new();
+            write(&engine, TableAccountWriter::new(&mut table));
+
+            assert_eq!(
+                String::from_utf8(csv).unwrap(),
+                "client,available,held,total,locked,open_disputes,last_tx\n"
+            );
+            assert_eq!(
+                String::from_utf8(table).unwrap(),
+                "client  available  held  total  locked  open_disputes  last_tx\n"
+            );
+        }
+
+        #[test]
+        fn rows_need_a_value_per_column() {
+            let mut engine = TransactionEngine::new();
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(2.0)))
+                .unwrap();
+            let mut writer = CsvAccountWriter::new(Vec::new());
+            let err = engine
+                .write_projected(&mut writer, OutputOrder::Client, &Mismatched)
+                .unwrap_err();
+
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(
+                err.to_string(),
+                "projection gives 2 values for its 1 columns"
+            );
+        }
+    }
+}
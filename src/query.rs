@@ -0,0 +1,222 @@
+//! Everything the engine knows about a single client, printed by the `query` subcommand so that
+//! support staff do not have to search the output of every account.
+
+use crate::account::ClientId;
+use crate::currency::Currency;
+use crate::engine::TransactionEngine;
+use crate::input::InputFormat;
+use crate::ledger::LedgerEntry;
+use crate::output::AccountSnapshot;
+use crate::transaction::TransactionId;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+/// Format of a [`ClientReport`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReportFormat {
+    /// Lines meant to be read by people
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(ReportFormat::Text),
+            "json" => Ok(ReportFormat::Json),
+            _ => Err(format!("unknown report format '{}'", value)),
+        }
+    }
+}
+
+/// Balances, open disputes and latest ledger entries of a client
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientReport {
+    pub client_id: ClientId,
+    pub locked: bool,
+    /// Funds in every currency of the account
+    pub balances: Vec<AccountSnapshot>,
+    /// Transactions under dispute with the held amount
+    pub open_disputes: Vec<(TransactionId, Option<Currency>, f64)>,
+    /// Latest applied transactions, oldest first. Only recorded by engines with
+    /// [`record_history`](crate::engine::EngineConfig::record_history) enabled.
+    pub recent: Vec<LedgerEntry>,
+}
+
+impl ClientReport {
+    /// Report with up to `recent` ledger entries, `None` if the client has no account. Fails if
+    /// the state of the account cannot be read.
+    pub fn new(
+        engine: &TransactionEngine,
+        client_id: ClientId,
+        recent: usize,
+    ) -> io::Result<Option<Self>> {
+        let Some(account) = engine.accounts.get(&client_id) else {
+            return Ok(None);
+        };
+        let history: Vec<_> = engine.history(client_id).collect();
+        let skipped = history.len().saturating_sub(recent);
+        Ok(Some(ClientReport {
+            client_id,
+            locked: account.is_locked(),
+            balances: engine.account_snapshots(account.as_ref()).collect(),
+            open_disputes: account.state()?.active_disputes,
+            recent: history[skipped..]
+                .iter()
+                .map(|&entry| entry.clone())
+                .collect(),
+        }))
+    }
+
+    pub fn to_json(&self) -> String {
+        let balances: Vec<_> = self.balances.iter().map(AccountSnapshot::to_json).collect();
+        let disputes: Vec<_> = self
+            .open_disputes
+            .iter()
+            .map(|(transaction_id, currency, held)| {
+                let currency = currency.map_or(String::new(), |currency| {
+                    format!(",\"currency\":\"{}\"", currency)
+                });
+                format!(
+                    "{{\"tx\":{},\"held\":{:.4}{}}}",
+                    transaction_id, held, currency
+                )
+            })
+            .collect();
+        let recent: Vec<_> = self
+            .recent
+            .iter()
+            .map(|entry| {
+                format!(
+                    "{{\"sequence\":{},\"transaction\":{}}}",
+                    entry.sequence,
+                    InputFormat::Ndjson.format_row(&entry.transaction)
+                )
+            })
+            .collect();
+        format!(
+            "{{\"client\":{},\"locked\":{},\"balances\":[{}],\"open_disputes\":[{}],\"recent\":[{}]}}",
+            self.client_id,
+            self.locked,
+            balances.join(","),
+            disputes.join(","),
+            recent.join(",")
+        )
+    }
+}
+
+impl fmt::Display for ClientReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.locked { "locked" } else { "active" };
+        writeln!(f, "Client {}, {}", self.client_id, state)?;
+        writeln!(f, "Balances")?;
+        for balance in &self.balances {
+            let currency = balance
+                .currency
+                .map_or("-".to_string(), |currency| currency.to_string());
+            writeln!(
+                f,
+                "  {}: available {:.4}, held {:.4}, total {:.4}",
+                currency, balance.available, balance.held, balance.total
+            )?;
+        }
+        writeln!(f, "Open disputes: {}", self.open_disputes.len())?;
+        for (transaction_id, currency, held) in &self.open_disputes {
+            let currency = currency.map_or(String::new(), |currency| format!(" {}", currency));
+            writeln!(f, "  tx {}: {:.4}{} held", transaction_id, held, currency)?;
+        }
+        writeln!(f, "Recent transactions: {}", self.recent.len())?;
+        for entry in &self.recent {
+            let transaction = &entry.transaction;
+            write!(
+                f,
+                "  #{} {} tx {}",
+                entry.sequence, transaction.transaction_type, transaction.transaction_id
+            )?;
+            if let Some(amount) = transaction.amount {
+                write!(f, " {:.4}", amount)?;
+            }
+            if let Some(currency) = transaction.currency {
+                write!(f, " {}", currency)?;
+            }
+            if let Some(to) = transaction.to_client_id {
+                write!(f, " to client {}", to)?;
+            }
+            if let Some(timestamp) = transaction.timestamp {
+                write!(f, " at {}", timestamp)?;
+            }
+            if !transaction.metadata.is_empty() {
+                let metadata: Vec<_> = transaction
+                    .sorted_metadata()
+                    .into_iter()
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect();
+                write!(f, " ({})", metadata.join(", "))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::engine::{EngineConfig, TransactionEngine};
+        use crate::query::ClientReport;
+        use crate::transaction::{transaction, TransactionId, TransactionType};
+
+        #[test]
+        fn report_shows_balances_disputes_and_recent_entries() {
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                record_history: true,
+                ..EngineConfig::default()
+            });
+            let mut withdrawal = transaction(TransactionType::Withdrawal, 1, 3, Some(1.0));
+            withdrawal
+                .metadata
+                .insert("merchant".to_string(), "shop".to_string());
+            for transaction in [
+                transaction(TransactionType::Deposit, 1, 1, Some(5.0)),
+                transaction(TransactionType::Deposit, 1, 2, Some(2.0)),
+                withdrawal,
+                transaction(TransactionType::Dispute, 1, 2, None),
+            ] {
+                engine.execute(transaction).unwrap();
+            }
+
+            let report = ClientReport::new(&engine, 1, 2).unwrap().unwrap();
+
+            assert_eq!(report.open_disputes, [(TransactionId(2), None, 2.0)]);
+            let kinds: Vec<_> = report
+                .recent
+                .iter()
+                .map(|entry| entry.transaction.transaction_type)
+                .collect();
+            assert_eq!(
+                kinds,
+                [TransactionType::Withdrawal, TransactionType::Dispute]
+            );
+            assert_eq!(
+                report.to_string(),
+                "Client 1, active\n\
+                 Balances\n  -: available 4.0000, held 2.0000, total 6.0000\n\
+                 Open disputes: 1\n  tx 2: 2.0000 held\n\
+                 Recent transactions: 2\n  #2 withdrawal tx 3 1.0000 (merchant=shop)\n  #3 dispute tx 2\n"
+            );
+            assert_eq!(
+                report.to_json(),
+                "{\"client\":1,\"locked\":false,\
+                 \"balances\":[{\"client\":1,\"available\":4.0000,\"held\":2.0000,\"total\":6.0000,\"locked\":false}],\
+                 \"open_disputes\":[{\"tx\":2,\"held\":2.0000}],\
+                 \"recent\":[{\"sequence\":2,\"transaction\":{\"type\":\"withdrawal\",\"client\":1,\"tx\":3,\"amount\":1,\"merchant\":\"shop\"}},\
+                 {\"sequence\":3,\"transaction\":{\"type\":\"dispute\",\"client\":1,\"tx\":2}}]}"
+            );
+            assert!(ClientReport::new(&engine, 2, 2).unwrap().is_none());
+        }
+    }
+}
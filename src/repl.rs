@@ -0,0 +1,228 @@
+//! Interactive prompt of the `repl` subcommand, to explore how the engine treats a sequence of
+//! transactions without writing a csv file for it. Every line is a command, its reply printed
+//! before the next prompt:
+//!
+//! - `<type> <client> <tx> [<amount>] [<to>]`, e.g. `deposit 1 5 10.0` or `transfer 1 6 2.5 2`,
+//!   applies a transaction and tells whether it was applied or why it was rejected
+//! - `show <client>` prints the balances, open disputes and latest transactions of a client
+//! - `disputes` lists the disputes open on every account
+//! - `accounts` dumps every account as csv, ordered by client
+//! - `save <path>` writes a snapshot, which batch runs and later sessions can `--restore`
+//! - `help` lists the commands and `quit` or `exit` ends the session, as does the end of input
+
+use crate::account::ClientId;
+use crate::engine::TransactionEngine;
+use crate::output::OutputOrder;
+use crate::query::ClientReport;
+use crate::transaction::{Amount, Origin, Transaction, TransactionId, TransactionType};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// Ledger entries shown by `show`
+const SHOWN_RECENT: usize = 10;
+
+const HELP: &str = "\
+<type> <client> <tx> [<amount>] [<to>]  apply a transaction, e.g. deposit 1 5 10.0
+show <client>                           balances, open disputes and latest transactions
+disputes                                disputes open on every account
+accounts                                every account as csv
+save <path>                             write a snapshot of the engine
+help                                    this message
+quit, exit                              end the session";
+
+/// Engine driven by the commands of an interactive session
+pub struct Repl {
+    engine: TransactionEngine,
+    origin: Origin,
+}
+
+impl Repl {
+    /// Session applying transactions to `engine` as `origin` submitted them, so that admin
+    /// sessions can lock accounts or adjust balances
+    pub fn new(engine: TransactionEngine, origin: Origin) -> Self {
+        Repl { engine, origin }
+    }
+
+    /// Runs one line and returns the reply, empty for a blank line, or `None` once the session
+    /// is over
+    pub fn eval(&mut self, line: &str) -> Option<String> {
+        let words: Vec<_> = line.split_whitespace().collect();
+        let reply = match words[..] {
+            [] => String::new(),
+            ["quit" | "exit"] => return None,
+            ["help"] => HELP.to_string(),
+            ["show", client_id] => match client_id.parse::<ClientId>() {
+                Ok(client_id) => match ClientReport::new(&self.engine, client_id, SHOWN_RECENT) {
+                    Ok(Some(report)) => report.to_string().trim_end().to_string(),
+                    Ok(None) => format!("client {} has no account", client_id),
+                    Err(err) => format!("cannot read the account of client {}: {}", client_id, err),
+                },
+                Err(_) => format!("invalid client '{}'", client_id),
+            },
+            ["disputes"] => self.disputes(),
+            ["accounts"] => {
+                let mut csv = String::from("client,currency,available,held,total,locked\n");
+                for snapshot in self.engine.snapshots(OutputOrder::Client) {
+                    let currency = snapshot.currency.map_or(String::new(), |c| c.to_string());
+                    let _ = writeln!(
+                        csv,
+                        "{},{},{:.4},{:.4},{:.4},{}",
+                        snapshot.client,
+                        currency,
+                        snapshot.available,
+                        snapshot.held,
+                        snapshot.total,
+                        snapshot.locked
+                    );
+                }
+                csv.trim_end().to_string()
+            }
+            ["save", path] => match self.engine.snapshot(path) {
+                Ok(()) => format!("saved {}", path),
+                Err(err) => format!("could not save {}: {}", path, err),
+            },
+            [name, ..] if TransactionType::from_str(name).is_ok() => {
+                match parse_transaction(&words) {
+                    Ok(transaction) => match self.engine.execute_from(transaction, self.origin) {
+                        Ok(()) => "applied".to_string(),
+                        Err(err) => format!("rejected: {}", err),
+                    },
+                    Err(message) => message,
+                }
+            }
+            [name, ..] => format!("unknown command '{}', try help", name),
+        };
+        Some(reply)
+    }
+
+    fn disputes(&self) -> String {
+        let accounts: BTreeMap<_, _> = self.engine.accounts.iter().collect();
+        let mut lines = Vec::new();
+        for (client_id, account) in accounts {
+            let state = match account.state() {
+                Ok(state) => state,
+                Err(err) => {
+                    return format!("cannot read the account of client {}: {}", client_id, err)
+                }
+            };
+            for (transaction_id, currency, held) in state.active_disputes {
+                let currency = currency.map_or(String::new(), |c| format!(" {}", c));
+                lines.push(format!(
+                    "client {} tx {}: {:.4}{} held",
+                    client_id, transaction_id, held, currency
+                ));
+            }
+        }
+        if lines.is_empty() {
+            return "no open disputes".to_string();
+        }
+        lines.join("\n")
+    }
+}
+
+/// Transaction of a line of words, the first one naming its type
+fn parse_transaction(words: &[&str]) -> Result<Transaction, String> {
+    let [name, client_id, transaction_id, rest @ ..] = words else {
+        return Err(format!(
+            "usage: {} <client> <tx> [<amount>] [<to>]",
+            words[0]
+        ));
+    };
+    if rest.len() > 2 {
+        return Err(format!("unexpected '{}'", rest[2]));
+    }
+    let transaction_type = TransactionType::from_str(name)?;
+    let client_id = client_id
+        .parse::<ClientId>()
+        .map_err(|_| format!("invalid client '{}'", client_id))?;
+    let transaction_id = transaction_id
+        .parse::<TransactionId>()
+        .map_err(|_| format!("invalid tx '{}'", transaction_id))?;
+    let amount = rest
+        .first()
+        .map(|amount| {
+            amount
+                .parse::<f64>()
+                .map_err(|_| format!("invalid amount '{}'", amount))
+                .and_then(|amount| Amount::of(transaction_type, amount))
+        })
+        .transpose()?;
+    let to_client_id = rest
+        .get(1)
+        .map(|to| {
+            to.parse::<ClientId>()
+                .map_err(|_| format!("invalid client '{}'", to))
+        })
+        .transpose()?;
+    Ok(Transaction {
+        transaction_type,
+        client_id,
+        transaction_id,
+        amount,
+        to_client_id,
+        currency: None,
+        timestamp: None,
+        metadata: Default::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::engine::{EngineConfig, TransactionEngine};
+        use crate::repl::Repl;
+        use crate::transaction::Origin;
+
+        #[test]
+        fn commands_apply_and_inspect_transactions() {
+            let mut repl = Repl::new(
+                TransactionEngine::with_config(EngineConfig {
+                    record_history: true,
+                    ..EngineConfig::default()
+                }),
+                Origin::Client,
+            );
+            let mut eval = |line: &str| repl.eval(line).unwrap();
+
+            assert_eq!(eval("deposit 1 1 10.0"), "applied");
+            assert_eq!(eval("deposit 2 2 3"), "applied");
+            assert_eq!(
+                eval("withdrawal 1 3 20"),
+                "rejected: client 1: transaction 3: insufficient funds, requested 20.0000 but only \
+                 10.0000 available"
+            );
+            assert_eq!(eval("dispute 1 1"), "applied");
+            assert_eq!(eval("transfer 2 4 1.5 1"), "applied");
+            assert_eq!(
+                eval("lock 1 5"),
+                "rejected: transaction 5 is administrative and was not submitted by an admin"
+            );
+            assert_eq!(eval(""), "");
+            assert_eq!(
+                eval("deposit 1"),
+                "usage: deposit <client> <tx> [<amount>] [<to>]"
+            );
+            assert_eq!(eval("deposit 1 x 1.0"), "invalid tx 'x'");
+            assert_eq!(eval("frobnicate"), "unknown command 'frobnicate', try help");
+
+            assert_eq!(
+                eval("show 1"),
+                "Client 1, active\n\
+                 Balances\n  -: available 1.5000, held 10.0000, total 11.5000\n\
+                 Open disputes: 1\n  tx 1: 10.0000 held\n\
+                 Recent transactions: 3\n  #0 deposit tx 1 10.0000\n  #2 dispute tx 1\n  \
+                 #3 transfer tx 4 1.5000 to client 1"
+            );
+            assert_eq!(eval("show 9"), "client 9 has no account");
+            assert_eq!(eval("disputes"), "client 1 tx 1: 10.0000 held");
+            assert_eq!(
+                eval("accounts"),
+                "client,currency,available,held,total,locked\n\
+                 1,,1.5000,10.0000,11.5000,false\n\
+                 2,,1.5000,0.0000,1.5000,false"
+            );
+            assert!(repl.eval("quit").is_none());
+        }
+    }
+}
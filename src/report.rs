@@ -0,0 +1,581 @@
+//! Summary of a batch run, printed on stderr with `--stats` or written with `--report-file`, the
+//! disputes it left open, written with `--disputes-output`, and the problems found in an input by
+//! `--validate`.
+
+use crate::account::ClientId;
+use crate::books::GlobalLedger;
+use crate::currency::Currency;
+use crate::engine::TransactionEngine;
+use crate::error::{EngineError, InputError};
+use crate::filter::ClientFilter;
+use crate::input::TransactionSource;
+use crate::metrics::EngineMetrics;
+use crate::pipeline::QueueDepths;
+use crate::timestamp::Timestamp;
+use crate::transaction::{Origin, TransactionId, TransactionType};
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+/// Dormant accounts listed by a [`RunReport`], the others are only counted
+const DORMANT_LISTED: usize = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunReport {
+    pub metrics: EngineMetrics,
+    /// Rows of the input, including malformed ones
+    pub rows_read: u64,
+    pub malformed_rows: u64,
+    /// Rows of clients left out by a [`ClientFilter`], not known to the engine
+    pub filtered_rows: u64,
+    pub accounts_locked: u64,
+    pub accounts_closed: u64,
+    /// Threshold in transactions and the open accounts without activity for that long, if
+    /// dormant accounts were looked for
+    pub dormant: Option<(u64, Vec<ClientId>)>,
+    /// Highest depths of the queues of the input, if it was read through a
+    /// [`Pipeline`](crate::pipeline::Pipeline)
+    pub queue_peaks: Option<QueueDepths>,
+    /// Funds held by open disputes over all accounts, per currency
+    pub held_funds: BTreeMap<Option<Currency>, f64>,
+    /// Funds that entered and left the accounts
+    pub books: GlobalLedger,
+    /// Funds of the accounts and those expected by the books, per currency where they differ
+    pub unbalanced: BTreeMap<Option<Currency>, (f64, f64)>,
+    /// Wall-clock time of the run
+    pub elapsed: Duration,
+}
+
+impl RunReport {
+    /// Collects the figures of an engine that processed `rows_read` rows in `elapsed`
+    pub fn new(
+        engine: &TransactionEngine,
+        rows_read: u64,
+        malformed_rows: u64,
+        elapsed: Duration,
+    ) -> Self {
+        let mut held_funds = BTreeMap::new();
+        for account in engine.accounts.values() {
+            for currency in account.currencies() {
+                let held = account.balance(currency).held;
+                if held != 0.0 {
+                    *held_funds.entry(currency).or_default() += held;
+                }
+            }
+        }
+        RunReport {
+            metrics: engine.metrics().clone(),
+            rows_read,
+            malformed_rows,
+            filtered_rows: 0,
+            accounts_locked: engine
+                .accounts
+                .values()
+                .filter(|account| account.is_locked())
+                .count() as u64,
+            accounts_closed: engine
+                .accounts
+                .keys()
+                .filter(|client_id| engine.is_closed(**client_id))
+                .count() as u64,
+            dormant: None,
+            queue_peaks: None,
+            held_funds,
+            books: engine.global_ledger().clone(),
+            unbalanced: engine
+                .global_ledger()
+                .discrepancies(engine)
+                .into_iter()
+                .map(|(currency, held, expected)| (currency, (held, expected)))
+                .collect(),
+            elapsed,
+        }
+    }
+
+    pub fn applied(&self) -> u64 {
+        self.metrics.total_processed() - self.metrics.total_rejected()
+    }
+
+    /// Rows read per second of wall-clock time
+    pub fn throughput(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            seconds if seconds > 0.0 => self.rows_read as f64 / seconds,
+            _ => 0.0,
+        }
+    }
+}
+
+impl fmt::Display for RunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let metrics = &self.metrics;
+        writeln!(
+            f,
+            "Processed {} transactions, mean latency {:?}",
+            metrics.total_processed(),
+            metrics.mean_latency()
+        )?;
+        for transaction_type in TransactionType::ALL {
+            writeln!(
+                f,
+                "  {}: {}",
+                transaction_type,
+                metrics.processed(transaction_type)
+            )?;
+        }
+        writeln!(f, "Applied {} transactions", self.applied())?;
+        writeln!(f, "Rejected {} transactions", metrics.total_rejected())?;
+        for (reason, count) in metrics.rejected() {
+            writeln!(f, "  {}: {}", reason, count)?;
+        }
+        writeln!(
+            f,
+            "Read {} rows, skipped {} malformed rows",
+            self.rows_read, self.malformed_rows
+        )?;
+        if self.filtered_rows > 0 {
+            writeln!(f, "Skipped {} rows of other clients", self.filtered_rows)?;
+        }
+        if let Some(peaks) = self.queue_peaks {
+            writeln!(
+                f,
+                "Queued at most {} parsed and {} validated rows",
+                peaks.parsed, peaks.validated
+            )?;
+        }
+        writeln!(
+            f,
+            "Created {} accounts, {} locked",
+            metrics.accounts_created(),
+            self.accounts_locked
+        )?;
+        if self.accounts_closed > 0 {
+            writeln!(f, "Closed {} accounts", self.accounts_closed)?;
+        }
+        if metrics.total_orphans() > 0 {
+            writeln!(
+                f,
+                "Found {} orphan references to transactions no account owns",
+                metrics.total_orphans()
+            )?;
+            for (transaction_type, count) in metrics.orphans() {
+                writeln!(f, "  {}: {}", transaction_type, count)?;
+            }
+        }
+        if let Some((transactions, dormant)) = &self.dormant {
+            write!(
+                f,
+                "Found {} accounts dormant for {} transactions",
+                dormant.len(),
+                transactions
+            )?;
+            let listed: Vec<_> = dormant
+                .iter()
+                .take(DORMANT_LISTED)
+                .map(ClientId::to_string)
+                .collect();
+            match dormant.len() {
+                0 => writeln!(f)?,
+                len if len > DORMANT_LISTED => writeln!(f, ": {}, ...", listed.join(", "))?,
+                _ => writeln!(f, ": {}", listed.join(", "))?,
+            }
+        }
+        if self.held_funds.is_empty() {
+            writeln!(f, "Held 0.0000 under dispute")?;
+        }
+        for (currency, held) in &self.held_funds {
+            match currency {
+                Some(currency) => writeln!(f, "Held {:.4} {} under dispute", held, currency)?,
+                None => writeln!(f, "Held {:.4} under dispute", held)?,
+            }
+        }
+        for (currency, flows) in self.books.currencies() {
+            match currency {
+                Some(currency) => write!(f, "Books in {}:", currency)?,
+                None => write!(f, "Books:")?,
+            }
+            if flows.opening != 0.0 {
+                write!(f, " opening {:.4},", flows.opening)?;
+            }
+            write!(
+                f,
+                " deposited {:.4}, withdrawn {:.4}, charged back {:.4}, fees {:.4}",
+                flows.deposited, flows.withdrawn, flows.charged_back, flows.fees
+            )?;
+            for (name, amount) in [
+                ("interest", flows.interest),
+                ("adjusted", flows.adjusted),
+                ("reversed", flows.reversed),
+            ] {
+                if amount != 0.0 {
+                    write!(f, ", {} {:.4}", name, amount)?;
+                }
+            }
+            match self.unbalanced.get(&currency) {
+                Some((held, expected)) => writeln!(
+                    f,
+                    ", unbalanced: the accounts hold {:.4} but the books expect {:.4}",
+                    held, expected
+                )?,
+                None => writeln!(f, ", balanced at {:.4}", flows.expected())?,
+            }
+        }
+        if metrics.evictions() > 0 {
+            writeln!(
+                f,
+                "Evicted {} transactions under the retention policy",
+                metrics.evictions()
+            )?;
+        }
+        if metrics.expired_disputes() > 0 {
+            writeln!(
+                f,
+                "Resolved {} expired disputes",
+                metrics.expired_disputes()
+            )?;
+        }
+        if metrics.total_risk_flags() > 0 {
+            writeln!(
+                f,
+                "Flagged {} suspicious transactions",
+                metrics.total_risk_flags()
+            )?;
+            for (rule, count) in metrics.risk_flags() {
+                writeln!(f, "  {}: {}", rule, count)?;
+            }
+        }
+        writeln!(
+            f,
+            "Took {:.1?}, {:.0} rows/s",
+            self.elapsed,
+            self.throughput()
+        )
+    }
+}
+
+/// Dispute of a transaction that was neither resolved nor charged back
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActiveDispute {
+    pub client_id: ClientId,
+    pub transaction_id: TransactionId,
+    pub currency: Option<Currency>,
+    /// Funds held by the dispute
+    pub held: f64,
+    /// Transactions executed by the engine since the dispute, including rejected ones
+    pub age: u64,
+    /// Timestamp of the dispute, if it had one
+    pub opened_at: Option<Timestamp>,
+}
+
+/// Every dispute left open by a run, so that they can be followed up before the held funds are
+/// forgotten about
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DisputeReport {
+    /// By client then transaction
+    pub disputes: Vec<ActiveDispute>,
+}
+
+impl DisputeReport {
+    const HEADER: [&'static str; 6] = ["client", "tx", "currency", "amount", "age", "opened_at"];
+
+    /// Open disputes of every account of the engine, failing if the state of an account cannot
+    /// be read
+    pub fn new(engine: &TransactionEngine) -> io::Result<Self> {
+        let mut disputes = Vec::new();
+        for account in engine.accounts.values() {
+            for (transaction_id, currency, held) in account.state()?.active_disputes {
+                let opened = engine.expiry.opened(transaction_id);
+                disputes.push(ActiveDispute {
+                    client_id: account.get_client_id(),
+                    transaction_id,
+                    currency,
+                    held,
+                    age: opened.map_or(0, |dispute| engine.lsn.saturating_sub(dispute.opened)),
+                    opened_at: opened.and_then(|dispute| dispute.timestamp),
+                });
+            }
+        }
+        disputes.sort_by_key(|dispute| (dispute.client_id, dispute.transaction_id));
+        Ok(DisputeReport { disputes })
+    }
+
+    /// Writes the disputes as csv rows preceded by a header, with empty currencies and
+    /// timestamps for disputes without
+    pub fn write_csv<W: io::Write>(&self, writer: W) -> io::Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(Self::HEADER)?;
+        for dispute in &self.disputes {
+            writer.write_record([
+                dispute.client_id.to_string(),
+                dispute.transaction_id.to_string(),
+                dispute
+                    .currency
+                    .map_or(String::new(), |currency| currency.to_string()),
+                format!("{:.4}", dispute.held),
+                dispute.age.to_string(),
+                dispute
+                    .opened_at
+                    .map_or(String::new(), |timestamp| timestamp.to_string()),
+            ])?;
+        }
+        writer.flush()
+    }
+}
+
+/// Problems an input would run into, found by executing it on a scratch engine whose balances are
+/// then thrown away, so that a file can be vetted before it is applied to the real ledger
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    /// Rows of the input, including malformed ones
+    pub rows_read: u64,
+    pub malformed: Vec<InputError>,
+    /// Rows of clients left out by the [`ClientFilter`], which are not checked
+    pub filtered_rows: u64,
+    /// Transactions the engine would refuse, e.g. disputes of unknown transactions or deposits to
+    /// locked accounts
+    pub rejected: Vec<EngineError>,
+    /// Clients whose accounts the input would lock, in client order
+    pub locked: Vec<ClientId>,
+}
+
+impl ValidationReport {
+    /// Problems of each kind listed at most when displayed
+    const LISTED: usize = 20;
+
+    /// Executes every row of `source` allowed by `filter` on `engine`, which should be a copy of
+    /// the ledger the input is meant for. Only fails if the source cannot be read at all.
+    pub fn collect(
+        engine: &mut TransactionEngine,
+        source: &mut dyn TransactionSource,
+        origin: Origin,
+        filter: &ClientFilter,
+    ) -> Result<Self, InputError> {
+        let locked_before: HashSet<ClientId> = engine
+            .accounts
+            .values()
+            .filter(|account| account.is_locked())
+            .map(|account| account.get_client_id())
+            .collect();
+        let mut report = ValidationReport::default();
+        while let Some(result) = source.next_transaction() {
+            report.rows_read += 1;
+            match result {
+                Ok(transaction) if !filter.allows(transaction.client_id) => {
+                    report.filtered_rows += 1;
+                }
+                Ok(transaction) => {
+                    if let Err(err) = engine.execute_from(transaction, origin) {
+                        report.rejected.push(err);
+                    }
+                }
+                Err(err) if err.is_fatal() => return Err(err),
+                Err(err) => report.malformed.push(err),
+            }
+        }
+        report.locked = engine
+            .accounts
+            .values()
+            .filter(|account| account.is_locked())
+            .map(|account| account.get_client_id())
+            .filter(|client_id| !locked_before.contains(client_id))
+            .collect();
+        report.locked.sort_unstable();
+        Ok(report)
+    }
+
+    /// Malformed rows and rejected transactions. Locked accounts are not problems by themselves.
+    pub fn problems(&self) -> usize {
+        self.malformed.len() + self.rejected.len()
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.problems() == 0
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Validated {} rows, found {} problems",
+            self.rows_read,
+            self.problems()
+        )?;
+        if self.filtered_rows > 0 {
+            writeln!(f, "Skipped {} rows of other clients", self.filtered_rows)?;
+        }
+        writeln!(f, "Malformed {} rows", self.malformed.len())?;
+        list(f, &self.malformed)?;
+        writeln!(f, "Would reject {} transactions", self.rejected.len())?;
+        list(f, &self.rejected)?;
+        let locked: Vec<_> = self.locked.iter().map(ClientId::to_string).collect();
+        match locked.len() {
+            0 => writeln!(f, "Would lock no accounts"),
+            _ => writeln!(
+                f,
+                "Would lock the accounts of clients {}",
+                locked.join(", ")
+            ),
+        }
+    }
+}
+
+fn list<T: fmt::Display>(f: &mut fmt::Formatter<'_>, problems: &[T]) -> fmt::Result {
+    for problem in problems.iter().take(ValidationReport::LISTED) {
+        writeln!(f, "  {}", problem)?;
+    }
+    if problems.len() > ValidationReport::LISTED {
+        writeln!(
+            f,
+            "  and {} more",
+            problems.len() - ValidationReport::LISTED
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::engine::TransactionEngine;
+        use crate::filter::ClientFilter;
+        use crate::input::CsvSource;
+        use crate::report::{DisputeReport, RunReport, ValidationReport};
+        use crate::transaction::{transaction, Origin, TransactionType};
+        use std::time::Duration;
+
+        #[test]
+        fn report_sums_up_the_run() {
+            let mut engine = TransactionEngine::new();
+            for transaction in [
+                transaction(TransactionType::Deposit, 1, 1, Some(2.0)),
+                transaction(TransactionType::Deposit, 2, 2, Some(1.5)),
+                transaction(TransactionType::Dispute, 2, 2, None),
+                transaction(TransactionType::Chargeback, 2, 2, None),
+                transaction(TransactionType::Deposit, 3, 3, Some(1.0)),
+                transaction(TransactionType::Dispute, 3, 3, None),
+                transaction(TransactionType::Withdrawal, 1, 4, Some(5.0)),
+            ] {
+                let _ = engine.execute(transaction);
+            }
+
+            let report = RunReport::new(&engine, 8, 1, Duration::from_secs(2));
+            let text = report.to_string();
+
+            assert_eq!(report.applied(), 6);
+            assert_eq!(report.accounts_locked, 1);
+            assert_eq!(report.held_funds.get(&None), Some(&1.0));
+            assert_eq!(report.throughput(), 4.0);
+            assert!(text.contains("Applied 6 transactions\n"), "{}", text);
+            assert!(text.contains("  insufficient_funds: 1\n"), "{}", text);
+            assert!(text.contains("Read 8 rows, skipped 1 malformed rows\n"));
+            assert!(text.contains("Created 3 accounts, 1 locked\n"));
+            assert!(text.contains("Held 1.0000 under dispute\n"));
+            assert!(!text.contains("orphan"), "{}", text);
+            assert!(text.contains(
+                "Books: deposited 4.5000, withdrawn 0.0000, charged back 1.5000, fees 0.0000, \
+                 balanced at 3.0000\n"
+            ));
+            assert!(text.ends_with("Took 2.0s, 4 rows/s\n"), "{}", text);
+        }
+
+        #[test]
+        fn orphan_references_are_reported_without_accounts() {
+            let mut engine = TransactionEngine::new();
+            for transaction in [
+                transaction(TransactionType::Deposit, 1, 1, Some(2.0)),
+                transaction(TransactionType::Dispute, 2, 7, None),
+                transaction(TransactionType::Dispute, 3, 8, None),
+                transaction(TransactionType::Chargeback, 3, 8, None),
+            ] {
+                let _ = engine.execute(transaction);
+            }
+
+            let text = RunReport::new(&engine, 4, 0, Duration::from_secs(1)).to_string();
+
+            assert!(text.contains("Created 1 accounts, 0 locked\n"), "{}", text);
+            assert!(
+                text.contains(
+                    "Found 3 orphan references to transactions no account owns\n  \
+                     chargeback: 1\n  dispute: 2\n"
+                ),
+                "{}",
+                text
+            );
+        }
+
+        #[test]
+        fn open_disputes_are_listed_with_their_age() {
+            let mut engine = TransactionEngine::new();
+            for transaction in [
+                transaction(TransactionType::Deposit, 2, 1, Some(2.0)),
+                transaction(TransactionType::Deposit, 1, 2, Some(1.5)),
+                transaction(TransactionType::Dispute, 2, 1, None),
+                transaction(TransactionType::Deposit, 1, 3, Some(0.5)),
+                transaction(TransactionType::Dispute, 1, 3, None),
+                transaction(TransactionType::Dispute, 1, 2, None),
+                transaction(TransactionType::Resolve, 1, 2, None),
+                transaction(TransactionType::Withdrawal, 2, 4, Some(1.0)),
+            ] {
+                let _ = engine.execute(transaction);
+            }
+
+            let report = DisputeReport::new(&engine).unwrap();
+            let mut csv = Vec::new();
+            report.write_csv(&mut csv).unwrap();
+
+            assert_eq!(
+                String::from_utf8(csv).unwrap(),
+                "client,tx,currency,amount,age,opened_at\n\
+                 1,3,,0.5000,3,\n\
+                 2,1,,2.0000,5,\n"
+            );
+        }
+
+        #[test]
+        fn validation_lists_problems_and_locked_accounts() {
+            let mut engine = TransactionEngine::new();
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(5.0)))
+                .unwrap();
+            let input = "type,client,tx,amount\n\
+                         withdrawal,1,2,2.0\n\
+                         dispute,1,9,\n\
+                         deposit,2,3,bogus\n\
+                         dispute,1,1,\n\
+                         chargeback,1,1,\n\
+                         deposit,1,4,1.0\n";
+
+            let report = ValidationReport::collect(
+                &mut engine,
+                &mut CsvSource::new(input.as_bytes()),
+                Origin::Client,
+                &ClientFilter::default(),
+            )
+            .unwrap();
+            let text = report.to_string();
+
+            assert_eq!(report.rows_read, 6);
+            assert_eq!(report.problems(), 3);
+            assert!(!report.is_valid());
+            assert_eq!(report.locked, [1]);
+            assert!(
+                text.starts_with("Validated 6 rows, found 3 problems\n"),
+                "{}",
+                text
+            );
+            assert!(text.contains("  malformed row on line 4: "), "{}", text);
+            assert!(
+                text.contains("  transaction 9 was never applied\n"),
+                "{}",
+                text
+            );
+            assert!(
+                text.ends_with("Would lock the accounts of clients 1\n"),
+                "{}",
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! Order in which transactions are evicted under a bounded [`RetentionPolicy`].
+
+use crate::account::ClientId;
+use crate::policy::RetentionPolicy;
+use crate::transaction::TransactionId;
+use std::collections::{HashMap, VecDeque};
+
+/// Transactions retained by the engine, oldest first
+#[derive(Debug, Default)]
+pub(crate) struct Retention {
+    policy: RetentionPolicy,
+    /// Used by [`RetentionPolicy::Global`]
+    global: VecDeque<(ClientId, TransactionId)>,
+    /// Used by [`RetentionPolicy::PerClient`]
+    per_client: HashMap<ClientId, VecDeque<TransactionId>>,
+}
+
+impl Retention {
+    pub(crate) fn new(policy: RetentionPolicy) -> Self {
+        Retention {
+            policy,
+            ..Retention::default()
+        }
+    }
+
+    /// Retains a new transaction of the client and returns the transactions to evict to stay
+    /// within the limit
+    pub(crate) fn retain(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Vec<(ClientId, TransactionId)> {
+        let mut evicted = Vec::new();
+        match self.policy {
+            RetentionPolicy::Unbounded => {}
+            RetentionPolicy::PerClient(limit) => {
+                let queue = self.per_client.entry(client_id).or_default();
+                queue.push_back(transaction_id);
+                while queue.len() > limit {
+                    evicted.extend(queue.pop_front().map(|oldest| (client_id, oldest)));
+                }
+            }
+            RetentionPolicy::Global(limit) => {
+                self.global.push_back((client_id, transaction_id));
+                while self.global.len() > limit {
+                    evicted.extend(self.global.pop_front());
+                }
+            }
+        }
+        evicted
+    }
+
+    /// Retained transactions, oldest first within each client. Restoring them in this order
+    /// with [`Retention::restore`] recreates the same eviction order.
+    pub(crate) fn retained(&self) -> Vec<(ClientId, TransactionId)> {
+        if !self.global.is_empty() {
+            return self.global.iter().copied().collect();
+        }
+        let mut clients: Vec<_> = self.per_client.keys().copied().collect();
+        clients.sort_unstable();
+        clients
+            .into_iter()
+            .flat_map(|client_id| {
+                self.per_client[&client_id]
+                    .iter()
+                    .map(move |transaction_id| (client_id, *transaction_id))
+            })
+            .collect()
+    }
+
+    /// Retains a transaction without evicting any, the next call to [`Retention::retain`]
+    /// catches up if the limit is exceeded
+    pub(crate) fn restore(&mut self, client_id: ClientId, transaction_id: TransactionId) {
+        match self.policy {
+            RetentionPolicy::Unbounded => {}
+            RetentionPolicy::PerClient(_) => self
+                .per_client
+                .entry(client_id)
+                .or_default()
+                .push_back(transaction_id),
+            RetentionPolicy::Global(_) => self.global.push_back((client_id, transaction_id)),
+        }
+    }
+
+    /// Retains the transactions of `from` as those of `into`, after those of `into` when
+    /// retained per client
+    pub(crate) fn remap(&mut self, from: ClientId, into: ClientId) {
+        for (client_id, _) in self.global.iter_mut() {
+            if *client_id == from {
+                *client_id = into;
+            }
+        }
+        if let Some(moved) = self.per_client.remove(&from) {
+            self.per_client.entry(into).or_default().extend(moved);
+        }
+    }
+
+    /// Takes over the transactions of another engine that processed a disjoint set of clients
+    pub(crate) fn absorb(&mut self, other: Retention) {
+        self.global.extend(other.global);
+        self.per_client.extend(other.per_client);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::policy::RetentionPolicy;
+        use crate::retention::Retention;
+        use crate::transaction::TransactionId;
+
+        #[test]
+        fn oldest_transactions_are_evicted_first() {
+            let mut per_client = Retention::new(RetentionPolicy::PerClient(2));
+            let mut global = Retention::new(RetentionPolicy::Global(2));
+            let mut evicted = (Vec::new(), Vec::new());
+            for (client_id, transaction_id) in [(1, 1), (2, 2), (1, 3), (1, 4)] {
+                evicted
+                    .0
+                    .extend(per_client.retain(client_id, TransactionId(transaction_id)));
+                evicted
+                    .1
+                    .extend(global.retain(client_id, TransactionId(transaction_id)));
+            }
+
+            assert_eq!(evicted.0, vec![(1, TransactionId(1))]);
+            assert_eq!(
+                per_client.retained(),
+                vec![
+                    (1, TransactionId(3)),
+                    (1, TransactionId(4)),
+                    (2, TransactionId(2))
+                ]
+            );
+            assert_eq!(
+                evicted.1,
+                vec![(1, TransactionId(1)), (2, TransactionId(2))]
+            );
+            assert_eq!(
+                global.retained(),
+                vec![(1, TransactionId(3)), (1, TransactionId(4))]
+            );
+        }
+    }
+}
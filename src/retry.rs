@@ -0,0 +1,221 @@
+//! Retry buffer for feeds delivering transactions out of order, where a dispute, resolve,
+//! chargeback or capture may arrive before the transaction it references. The engine rejects
+//! those as [`EngineError::UnknownTransaction`]; a [`RetryBuffer`] parks them instead and tries
+//! again once the referenced transaction could have arrived: every given number of rows and in any
+//! case at the end of the batch. Whatever still cannot be applied then is a [`DeadLetter`],
+//! left for manual review.
+//!
+//! Transactions referencing the same transaction are applied in the order they arrived, so a
+//! resolve is never tried before the dispute parked ahead of it.
+
+use crate::engine::TransactionEngine;
+use crate::error::EngineError;
+use crate::transaction::{Origin, Transaction, TransactionId, TransactionType};
+use std::collections::{HashMap, HashSet};
+
+/// Transaction that failed its last attempt, see the [module](self) documentation
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeadLetter {
+    pub transaction: Transaction,
+    pub origin: Origin,
+    pub error: EngineError,
+}
+
+struct Parked {
+    transaction: Transaction,
+    origin: Origin,
+    /// Row at which the transaction is tried again, `None` if only at the end of the batch
+    retry_at: Option<u64>,
+}
+
+/// Runs transactions on an engine, parking those that reference a transaction it has not seen
+/// yet, see the [module](self) documentation
+pub struct RetryBuffer {
+    retry_after: Option<u64>,
+    parked: Vec<Parked>,
+    /// Parked transactions per referenced transaction
+    waiting: HashMap<TransactionId, usize>,
+    rows: u64,
+}
+
+impl RetryBuffer {
+    /// Buffer trying parked transactions again every `retry_after` rows, if given, and at the end
+    /// of the batch
+    pub fn new(retry_after: Option<u64>) -> Self {
+        RetryBuffer {
+            retry_after,
+            parked: Vec::new(),
+            waiting: HashMap::new(),
+            rows: 0,
+        }
+    }
+
+    /// Transactions waiting to be tried again
+    pub fn len(&self) -> usize {
+        self.parked.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parked.is_empty()
+    }
+
+    /// Executes the transaction, or parks it if it references a transaction the engine has not
+    /// seen or that another parked transaction references, then tries again the parked
+    /// transactions that are due. Returns why the transaction, or any retried one, was rejected.
+    pub fn execute(
+        &mut self,
+        engine: &mut TransactionEngine,
+        transaction: Transaction,
+        origin: Origin,
+    ) -> Vec<EngineError> {
+        self.rows += 1;
+        let mut rejected = Vec::new();
+        if self.must_wait(engine, &transaction) {
+            *self.waiting.entry(transaction.transaction_id).or_default() += 1;
+            self.parked.push(Parked {
+                transaction,
+                origin,
+                retry_at: self.retry_after.map(|rows| self.rows + rows),
+            });
+        } else if let Err(err) = engine.execute_from(transaction, origin) {
+            rejected.push(err);
+        }
+        if self
+            .parked
+            .iter()
+            .any(|parked| parked.retry_at.is_some_and(|row| row <= self.rows))
+        {
+            self.retry(engine, &mut rejected);
+        }
+        rejected
+    }
+
+    /// Tries every parked transaction one last time at the end of the batch, returning those
+    /// that still failed
+    pub fn finish(self, engine: &mut TransactionEngine) -> Vec<DeadLetter> {
+        let mut dead_letters = Vec::new();
+        for Parked {
+            transaction,
+            origin,
+            ..
+        } in self.parked
+        {
+            if let Err(error) = engine.execute_from(transaction.clone(), origin) {
+                dead_letters.push(DeadLetter {
+                    transaction,
+                    origin,
+                    error,
+                });
+            }
+        }
+        dead_letters
+    }
+
+    /// Applies the parked transactions that are due and whose reference the engine has seen by
+    /// now. Those due but still waiting are tried again `retry_after` rows later.
+    fn retry(&mut self, engine: &mut TransactionEngine, rejected: &mut Vec<EngineError>) {
+        // References of transactions kept parked, which those behind them wait for
+        let mut blocked = HashSet::new();
+        let mut kept = Vec::with_capacity(self.parked.len());
+        for mut parked in std::mem::take(&mut self.parked) {
+            let transaction_id = parked.transaction.transaction_id;
+            let due = parked.retry_at.is_some_and(|row| row <= self.rows);
+            if !due
+                || blocked.contains(&transaction_id)
+                || !engine.seen_transactions.contains_key(&transaction_id)
+            {
+                if due {
+                    parked.retry_at = self.retry_after.map(|rows| self.rows + rows);
+                }
+                blocked.insert(transaction_id);
+                kept.push(parked);
+                continue;
+            }
+            self.release(transaction_id);
+            if let Err(err) = engine.execute_from(parked.transaction, parked.origin) {
+                rejected.push(err);
+            }
+        }
+        self.parked = kept;
+    }
+
+    fn must_wait(&self, engine: &TransactionEngine, transaction: &Transaction) -> bool {
+        references_transaction(transaction.transaction_type)
+            && (self.waiting.contains_key(&transaction.transaction_id)
+                || !engine
+                    .seen_transactions
+                    .contains_key(&transaction.transaction_id))
+    }
+
+    fn release(&mut self, transaction_id: TransactionId) {
+        if let Some(count) = self.waiting.get_mut(&transaction_id) {
+            *count -= 1;
+            if *count == 0 {
+                self.waiting.remove(&transaction_id);
+            }
+        }
+    }
+}
+
+/// Types whose id references an earlier transaction of the client
+fn references_transaction(transaction_type: TransactionType) -> bool {
+    matches!(
+        transaction_type,
+        TransactionType::Dispute
+            | TransactionType::Resolve
+            | TransactionType::Chargeback
+            | TransactionType::Represent
+            | TransactionType::Reversal
+            | TransactionType::Capture
+            | TransactionType::Void
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::engine::TransactionEngine;
+        use crate::error::EngineError;
+        use crate::retry::RetryBuffer;
+        use crate::transaction::{transaction, Origin, TransactionId, TransactionType};
+
+        #[test]
+        fn transactions_referencing_later_ones_are_retried() {
+            let mut engine = TransactionEngine::new();
+            let mut buffer = RetryBuffer::new(Some(2));
+
+            // The dispute and resolve of tx 1, and the dispute of tx 9, come before the deposits
+            for transaction in [
+                transaction(TransactionType::Dispute, 1, 1, None),
+                transaction(TransactionType::Resolve, 1, 1, None),
+                transaction(TransactionType::Dispute, 2, 9, None),
+                transaction(TransactionType::Deposit, 1, 1, Some(5.0)),
+            ] {
+                assert!(buffer
+                    .execute(&mut engine, transaction, Origin::Client)
+                    .is_empty());
+            }
+            assert_eq!(buffer.len(), 3);
+            // The dispute of tx 1 was due on the third row, before tx 1 arrived, so is applied on
+            // the fifth. The resolve waits for it and is applied on the sixth.
+            for (row, held) in [(5, 5.0), (6, 0.0)] {
+                let deposit = transaction(TransactionType::Deposit, 3, row, Some(1.0));
+                assert!(buffer
+                    .execute(&mut engine, deposit, Origin::Client)
+                    .is_empty());
+                assert_eq!(engine.accounts[&1].get_held_funds(), held);
+            }
+            assert_eq!(buffer.len(), 1);
+            assert_eq!(engine.accounts[&1].get_available_funds(), 5.0);
+            assert_eq!(engine.metrics().rejected().count(), 0);
+
+            let dead_letters = buffer.finish(&mut engine);
+            assert_eq!(dead_letters.len(), 1);
+            assert_eq!(dead_letters[0].transaction.transaction_id, TransactionId(9));
+            assert_eq!(
+                dead_letters[0].error,
+                EngineError::UnknownTransaction(TransactionId(9))
+            );
+        }
+    }
+}
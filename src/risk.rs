@@ -0,0 +1,373 @@
+//! Detection of suspicious activity. Every [`RiskRule`] of the engine looks at each transaction
+//! before it is applied, and the transactions a rule flags are reported as
+//! [`AuditEvent::RiskFlagged`](crate::audit::AuditEvent::RiskFlagged) events and counted in the
+//! [`EngineMetrics`](crate::metrics::EngineMetrics). Flagged transactions are still applied
+//! unless [`RiskPolicy::block`] is set.
+//!
+//! The built-in rules are enabled in the `[risk]` table of a TOML file:
+//!
+//! ```toml
+//! [risk]
+//! rapid_withdrawals = true         # many withdrawals or transfers of a client in a row
+//! rapid_withdrawals_count = 3      # withdrawals flagged from the third one
+//! rapid_withdrawals_within = 10    # within 10 transactions of the engine
+//! disputed_deposits = true         # deposits disputed soon after they were made
+//! disputed_deposits_within = 5     # within 5 transactions of the engine
+//! near_limit = true                # amounts just under a threshold
+//! near_limit_threshold = 10_000
+//! near_limit_margin = 0.05         # from 5% under the threshold
+//! block = false                    # reject flagged transactions instead
+//! ```
+//!
+//! Rules keep what they need to remember about earlier transactions in memory only, so they start
+//! over when an engine is restored from a snapshot.
+
+use crate::account::ClientId;
+use crate::error::ConfigError;
+use crate::toml;
+use crate::transaction::{Transaction, TransactionId, TransactionType};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+/// Check of every transaction executed by the engine, e.g. for patterns typical of fraud or money
+/// laundering. Rules see transactions before they are applied, including those the engine rejects
+/// afterwards, since attempts can be as suspicious as the transactions that succeed.
+pub trait RiskRule: Send + fmt::Debug {
+    /// Name of the rule in risk events and metrics
+    fn name(&self) -> &'static str;
+
+    /// Why the transaction is suspicious, `None` if it is not. `lsn` counts the transactions
+    /// executed by the engine, this one included.
+    fn evaluate(&mut self, lsn: u64, transaction: &Transaction) -> Option<String>;
+}
+
+/// Flags a client making `count` withdrawals or transfers within `within` transactions of the
+/// engine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RapidWithdrawals {
+    pub enabled: bool,
+    pub count: usize,
+    pub within: u64,
+}
+
+impl Default for RapidWithdrawals {
+    fn default() -> Self {
+        RapidWithdrawals {
+            enabled: false,
+            count: 3,
+            within: 10,
+        }
+    }
+}
+
+/// Flags disputes of deposits made at most `within` transactions of the engine earlier
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisputedDeposits {
+    pub enabled: bool,
+    pub within: u64,
+}
+
+impl Default for DisputedDeposits {
+    fn default() -> Self {
+        DisputedDeposits {
+            enabled: false,
+            within: 5,
+        }
+    }
+}
+
+/// Flags deposits, withdrawals and transfers of at least `threshold` less `margin` of it, but
+/// less than `threshold`, as when amounts are split to stay under a reporting threshold or a
+/// [limit](crate::policy::LimitsPolicy)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NearLimit {
+    pub enabled: bool,
+    pub threshold: f64,
+    /// Share of the threshold, between 0 and 1
+    pub margin: f64,
+}
+
+impl Default for NearLimit {
+    fn default() -> Self {
+        NearLimit {
+            enabled: false,
+            threshold: 10_000.0,
+            margin: 0.05,
+        }
+    }
+}
+
+/// Built-in rules applied by the engine, all disabled by default
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RiskPolicy {
+    pub rapid_withdrawals: RapidWithdrawals,
+    pub disputed_deposits: DisputedDeposits,
+    pub near_limit: NearLimit,
+    /// Reject flagged transactions rather than only reporting them
+    pub block: bool,
+}
+
+impl RiskPolicy {
+    pub(crate) fn from_table(table: &toml::Table) -> Result<Self, ConfigError> {
+        let mut policy = RiskPolicy::default();
+        for (key, entry) in &table.entries {
+            match key.as_str() {
+                "rapid_withdrawals" => policy.rapid_withdrawals.enabled = entry.as_bool(key)?,
+                "rapid_withdrawals_count" => match entry.as_count(key)? {
+                    0 => return Err(entry.invalid(format!("'{}' must be positive", key))),
+                    count => policy.rapid_withdrawals.count = count as usize,
+                },
+                "rapid_withdrawals_within" => {
+                    policy.rapid_withdrawals.within = entry.as_count(key)?
+                }
+                "disputed_deposits" => policy.disputed_deposits.enabled = entry.as_bool(key)?,
+                "disputed_deposits_within" => {
+                    policy.disputed_deposits.within = entry.as_count(key)?
+                }
+                "near_limit" => policy.near_limit.enabled = entry.as_bool(key)?,
+                "near_limit_threshold" => match entry.as_number(key)? {
+                    threshold if threshold > 0.0 => policy.near_limit.threshold = threshold,
+                    _ => return Err(entry.invalid(format!("'{}' must be positive", key))),
+                },
+                "near_limit_margin" => match entry.as_number(key)? {
+                    margin if (0.0..=1.0).contains(&margin) => policy.near_limit.margin = margin,
+                    _ => return Err(entry.invalid(format!("'{}' must be between 0 and 1", key))),
+                },
+                "block" => policy.block = entry.as_bool(key)?,
+                _ => return Err(entry.invalid(format!("unknown key '{}' in [risk]", key))),
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Enabled rules, each starting without any memory of earlier transactions
+    pub fn rules(&self) -> Vec<Box<dyn RiskRule>> {
+        let mut rules: Vec<Box<dyn RiskRule>> = Vec::new();
+        if self.rapid_withdrawals.enabled {
+            rules.push(Box::new(RapidWithdrawalsRule {
+                settings: self.rapid_withdrawals,
+                recent: HashMap::new(),
+            }));
+        }
+        if self.disputed_deposits.enabled {
+            rules.push(Box::new(DisputedDepositsRule {
+                within: self.disputed_deposits.within,
+                deposits: HashMap::new(),
+                order: VecDeque::new(),
+            }));
+        }
+        if self.near_limit.enabled {
+            rules.push(Box::new(self.near_limit));
+        }
+        rules
+    }
+}
+
+#[derive(Debug)]
+struct RapidWithdrawalsRule {
+    settings: RapidWithdrawals,
+    /// Log sequence numbers of the latest withdrawals of every client, within the window
+    recent: HashMap<ClientId, VecDeque<u64>>,
+}
+
+impl RiskRule for RapidWithdrawalsRule {
+    fn name(&self) -> &'static str {
+        "rapid_withdrawals"
+    }
+
+    fn evaluate(&mut self, lsn: u64, transaction: &Transaction) -> Option<String> {
+        if !matches!(
+            transaction.transaction_type,
+            TransactionType::Withdrawal | TransactionType::Transfer
+        ) {
+            return None;
+        }
+        let recent = self.recent.entry(transaction.client_id).or_default();
+        while recent
+            .front()
+            .is_some_and(|&earlier| lsn.saturating_sub(earlier) >= self.settings.within)
+        {
+            recent.pop_front();
+        }
+        recent.push_back(lsn);
+        (recent.len() >= self.settings.count).then(|| {
+            format!(
+                "{} withdrawals within {} transactions",
+                recent.len(),
+                self.settings.within
+            )
+        })
+    }
+}
+
+#[derive(Debug)]
+struct DisputedDepositsRule {
+    within: u64,
+    /// Log sequence number of the deposits made within the window
+    deposits: HashMap<TransactionId, u64>,
+    /// Deposits in the order they were made
+    order: VecDeque<(u64, TransactionId)>,
+}
+
+impl RiskRule for DisputedDepositsRule {
+    fn name(&self) -> &'static str {
+        "disputed_deposits"
+    }
+
+    fn evaluate(&mut self, lsn: u64, transaction: &Transaction) -> Option<String> {
+        while let Some(&(made, transaction_id)) = self.order.front() {
+            if lsn.saturating_sub(made) <= self.within {
+                break;
+            }
+            self.order.pop_front();
+            if self.deposits.get(&transaction_id) == Some(&made) {
+                self.deposits.remove(&transaction_id);
+            }
+        }
+        match transaction.transaction_type {
+            TransactionType::Deposit => {
+                self.deposits.insert(transaction.transaction_id, lsn);
+                self.order.push_back((lsn, transaction.transaction_id));
+                None
+            }
+            TransactionType::Dispute => {
+                let made = self.deposits.get(&transaction.transaction_id)?;
+                Some(format!(
+                    "deposit disputed {} transactions after it was made",
+                    lsn - made
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl RiskRule for NearLimit {
+    fn name(&self) -> &'static str {
+        "near_limit"
+    }
+
+    fn evaluate(&mut self, _lsn: u64, transaction: &Transaction) -> Option<String> {
+        if !matches!(
+            transaction.transaction_type,
+            TransactionType::Deposit
+                | TransactionType::Withdrawal
+                | TransactionType::Transfer
+                | TransactionType::Authorize
+        ) {
+            return None;
+        }
+        let amount = transaction.amount?.value();
+        let floor = self.threshold * (1.0 - self.margin);
+        (floor <= amount && amount < self.threshold)
+            .then(|| format!("amount {:.4} just under {:.4}", amount, self.threshold))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::risk::{DisputedDeposits, NearLimit, RapidWithdrawals, RiskPolicy};
+        use crate::toml;
+        use crate::transaction::{transaction, TransactionType};
+
+        #[test]
+        fn rules_flag_bursts_quick_disputes_and_amounts_under_thresholds() {
+            let policy = RiskPolicy {
+                rapid_withdrawals: RapidWithdrawals {
+                    enabled: true,
+                    count: 2,
+                    within: 3,
+                },
+                disputed_deposits: DisputedDeposits {
+                    enabled: true,
+                    within: 2,
+                },
+                near_limit: NearLimit {
+                    enabled: true,
+                    threshold: 100.0,
+                    margin: 0.1,
+                },
+                block: false,
+            };
+            let mut rules = policy.rules();
+            let mut flags = Vec::new();
+            for (lsn, transaction) in [
+                transaction(TransactionType::Deposit, 1, 1, Some(95.0)),
+                transaction(TransactionType::Withdrawal, 1, 2, Some(1.0)),
+                transaction(TransactionType::Withdrawal, 1, 3, Some(1.0)),
+                transaction(TransactionType::Dispute, 1, 1, None),
+                transaction(TransactionType::Deposit, 2, 4, Some(100.0)),
+                transaction(TransactionType::Withdrawal, 1, 5, Some(1.0)),
+                transaction(TransactionType::Dispute, 2, 4, None),
+                transaction(TransactionType::Withdrawal, 2, 6, Some(89.0)),
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                for rule in rules.iter_mut() {
+                    if let Some(reason) = rule.evaluate(lsn as u64 + 1, &transaction) {
+                        flags.push((transaction.transaction_id.0, rule.name(), reason));
+                    }
+                }
+            }
+
+            assert_eq!(
+                flags,
+                [
+                    (
+                        1,
+                        "near_limit",
+                        "amount 95.0000 just under 100.0000".to_string()
+                    ),
+                    (
+                        3,
+                        "rapid_withdrawals",
+                        "2 withdrawals within 3 transactions".to_string()
+                    ),
+                    (
+                        4,
+                        "disputed_deposits",
+                        "deposit disputed 2 transactions after it was made".to_string()
+                    ),
+                ]
+            );
+            assert!(RiskPolicy::default().rules().is_empty());
+        }
+
+        #[test]
+        fn policy_is_read_from_the_risk_table() {
+            let tables = toml::parse(
+                "[risk]\nrapid_withdrawals = true\nrapid_withdrawals_count = 4\n\
+                 near_limit = true\nnear_limit_margin = 0.1\nblock = true\n",
+            )
+            .unwrap();
+
+            let policy = RiskPolicy::from_table(&tables["risk"]).unwrap();
+
+            assert_eq!(
+                policy.rapid_withdrawals,
+                RapidWithdrawals {
+                    enabled: true,
+                    count: 4,
+                    within: 10,
+                }
+            );
+            assert!(!policy.disputed_deposits.enabled);
+            assert_eq!(policy.near_limit.margin, 0.1);
+            assert!(policy.block);
+            for invalid in [
+                "[risk]\nnear_limit_margin = 2\n",
+                "[risk]\nrapid_withdrawals_count = 0\n",
+                "[risk]\nunknown = true\n",
+            ] {
+                let tables = toml::parse(invalid).unwrap();
+                assert!(
+                    RiskPolicy::from_table(&tables["risk"]).is_err(),
+                    "{}",
+                    invalid
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,292 @@
+//! Account whose withdrawals settle after a delay, to model savings accounts and settlement
+//! periods. Selected per client in the `[accounts]` table of the configuration file, see
+//! [`AccountTypes`](crate::account::AccountTypes).
+
+use crate::account::{AccountState, Balance, BasicAccount, ClientAccount, ClientId, DisputeState};
+use crate::currency::Currency;
+use crate::error::UpdateError;
+use crate::policy::{AccountPolicies, SavingsPolicy};
+use crate::storage::TransactionStore;
+use crate::transaction::TransactionId;
+use std::io;
+
+/// Behaves as a [`BasicAccount`] except for withdrawals and outgoing transfers, which move the
+/// funds from available to held until they settle [`SavingsPolicy::withdrawal_delay`] ticks
+/// later, when they leave the account. Ticks are counted by
+/// [`TransactionEngine::advance`](crate::engine::TransactionEngine::advance). Pending
+/// withdrawals are disputable as soon as they are made and keep settling while disputed.
+/// Captured authorizations leave the account at once, their funds having been held already.
+#[derive(Debug)]
+pub struct SavingsAccount {
+    inner: BasicAccount,
+    savings: SavingsPolicy,
+    /// Withdrawals that did not settle yet with the ticks left, in order of withdrawal
+    pending: Vec<(TransactionId, Option<Currency>, f64, u32)>,
+}
+
+impl SavingsAccount {
+    pub fn new(client_id: ClientId, savings: SavingsPolicy) -> Self {
+        Self::with_policies(client_id, AccountPolicies::default(), savings)
+    }
+
+    pub fn with_policies(
+        client_id: ClientId,
+        policies: AccountPolicies,
+        savings: SavingsPolicy,
+    ) -> Self {
+        SavingsAccount {
+            inner: BasicAccount::with_policies(client_id, policies),
+            savings,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Account keeping its disputable transactions in `store`, which must be empty
+    pub fn with_store(
+        client_id: ClientId,
+        policies: AccountPolicies,
+        savings: SavingsPolicy,
+        store: Box<dyn TransactionStore>,
+    ) -> Self {
+        SavingsAccount {
+            inner: BasicAccount::with_store(client_id, policies, store),
+            savings,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Restores the account with its pending withdrawals, writing its transaction log to `store`
+    /// if given
+    pub fn from_state(
+        mut state: AccountState,
+        policies: AccountPolicies,
+        savings: SavingsPolicy,
+        store: Option<Box<dyn TransactionStore>>,
+    ) -> io::Result<Self> {
+        let pending = std::mem::take(&mut state.pending_withdrawals);
+        let inner = match store {
+            Some(store) => BasicAccount::from_state_with_store(state, policies, store)?,
+            None => BasicAccount::from_state(state, policies),
+        };
+        Ok(SavingsAccount {
+            inner,
+            savings,
+            pending,
+        })
+    }
+}
+
+impl ClientAccount for SavingsAccount {
+    fn deposit(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), UpdateError> {
+        self.inner.deposit(transaction_id, amount, currency)
+    }
+
+    /// Fails if there are not enough available funds in the currency. Funds of pending
+    /// withdrawals are held and so never available twice.
+    fn withdraw(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), UpdateError> {
+        self.inner.withdraw(transaction_id, amount, currency)?;
+        if self.savings.withdrawal_delay > 0 {
+            self.inner.change_held(amount, currency);
+            self.pending.push((
+                transaction_id,
+                currency,
+                amount,
+                self.savings.withdrawal_delay,
+            ));
+        }
+        Ok(())
+    }
+
+    fn adjust(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), UpdateError> {
+        self.inner.adjust(transaction_id, amount, currency)
+    }
+
+    fn post_adjustment(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), UpdateError> {
+        self.inner.post_adjustment(transaction_id, amount, currency)
+    }
+
+    fn dispute(&mut self, transaction_id: TransactionId) -> Result<(), UpdateError> {
+        self.inner.dispute(transaction_id)
+    }
+
+    fn dispute_partial(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+    ) -> Result<(), UpdateError> {
+        self.inner.dispute_partial(transaction_id, amount)
+    }
+
+    fn resolve(&mut self, transaction_id: TransactionId) -> Result<(), UpdateError> {
+        self.inner.resolve(transaction_id)
+    }
+
+    fn chargeback(&mut self, transaction_id: TransactionId) -> Result<(), UpdateError> {
+        self.inner.chargeback(transaction_id)
+    }
+
+    fn represent(&mut self, transaction_id: TransactionId) -> Result<(), UpdateError> {
+        self.inner.represent(transaction_id)
+    }
+
+    fn authorize(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), UpdateError> {
+        self.inner.authorize(transaction_id, amount, currency)
+    }
+
+    fn capture(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: Option<f64>,
+    ) -> Result<(), UpdateError> {
+        self.inner.capture(transaction_id, amount)
+    }
+
+    fn void(&mut self, transaction_id: TransactionId) -> Result<(), UpdateError> {
+        self.inner.void(transaction_id)
+    }
+
+    fn dispute_state(&self, transaction_id: TransactionId) -> (DisputeState, u32) {
+        self.inner.dispute_state(transaction_id)
+    }
+
+    fn forget(&mut self, transaction_id: TransactionId) -> bool {
+        self.inner.forget(transaction_id)
+    }
+
+    /// Settles the pending withdrawals whose delay ran out, taking their funds out of held
+    fn advance(&mut self, ticks: u32) {
+        let inner = &mut self.inner;
+        self.pending
+            .retain_mut(|(_, currency, amount, ticks_left)| {
+                *ticks_left = ticks_left.saturating_sub(ticks);
+                if *ticks_left == 0 {
+                    inner.change_held(-*amount, *currency);
+                }
+                *ticks_left > 0
+            });
+    }
+
+    fn get_client_id(&self) -> ClientId {
+        self.inner.get_client_id()
+    }
+
+    fn balance(&self, currency: Option<Currency>) -> Balance {
+        self.inner.balance(currency)
+    }
+
+    fn currencies(&self) -> Vec<Option<Currency>> {
+        self.inner.currencies()
+    }
+
+    fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    fn set_locked(&mut self, locked: bool) {
+        self.inner.set_locked(locked)
+    }
+
+    fn state(&self) -> io::Result<AccountState> {
+        Ok(AccountState {
+            pending_withdrawals: self.pending.clone(),
+            ..self.inner.state()?
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::account::{BasicAccount, ClientAccount};
+        use crate::policy::{AccountPolicies, SavingsPolicy};
+        use crate::savings::SavingsAccount;
+        use crate::transaction::TransactionId;
+
+        fn savings_account() -> SavingsAccount {
+            let mut account = SavingsAccount::new(
+                1,
+                SavingsPolicy {
+                    withdrawal_delay: 2,
+                },
+            );
+            account.deposit(TransactionId(1), 10.0, None).unwrap();
+            account
+        }
+
+        #[test]
+        fn withdrawals_are_held_until_they_settle() {
+            let mut account = savings_account();
+
+            account.withdraw(TransactionId(2), 4.0, None).unwrap();
+            assert_eq!(account.get_available_funds(), 6.0);
+            assert_eq!(account.get_held_funds(), 4.0);
+            // Held funds cannot be withdrawn again
+            assert!(account.withdraw(TransactionId(3), 7.0, None).is_err());
+
+            account.advance(1);
+            account.withdraw(TransactionId(4), 1.0, None).unwrap();
+            assert_eq!(account.get_held_funds(), 5.0);
+            account.advance(1);
+            assert_eq!(account.get_held_funds(), 1.0);
+            assert_eq!(account.get_total_funds(), 6.0);
+            account.advance(5);
+            assert_eq!(account.get_held_funds(), 0.0);
+            assert_eq!(account.get_total_funds(), 5.0);
+        }
+
+        #[test]
+        fn pending_withdrawals_are_restored() {
+            let mut account = savings_account();
+            account.withdraw(TransactionId(2), 4.0, None).unwrap();
+            account.advance(1);
+
+            let state = account.state().unwrap();
+            assert_eq!(
+                state.pending_withdrawals,
+                [(TransactionId(2), None, 4.0, 1)]
+            );
+            let mut restored = SavingsAccount::from_state(
+                state.clone(),
+                AccountPolicies::default(),
+                SavingsPolicy {
+                    withdrawal_delay: 2,
+                },
+                None,
+            )
+            .unwrap();
+            restored.advance(1);
+            assert_eq!(restored.get_held_funds(), 0.0);
+            assert_eq!(restored.get_available_funds(), 6.0);
+
+            // Other accounts have nothing to settle
+            let mut basic = BasicAccount::from_state(state, AccountPolicies::default());
+            basic.advance(1);
+            assert!(basic.state().unwrap().pending_withdrawals.is_empty());
+        }
+    }
+}
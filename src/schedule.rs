@@ -0,0 +1,351 @@
+//! Standing orders: deposits, withdrawals and transfers repeated at a fixed interval and turned
+//! into transactions by
+//! [`TransactionEngine::advance_time`](crate::engine::TransactionEngine::advance_time), so that
+//! recurring payments can be simulated in batch runs.
+//!
+//! Orders are registered with [`Schedule::register`] or read from a csv file:
+//!
+//! ```text
+//! type,client,tx,amount,every,to,currency,start
+//! deposit,1,100000,250.0,30,,,
+//! transfer,2,200000,12.5,7d,3,EUR,2024-03-01
+//! ```
+//!
+//! `every` is a number of ticks, or a duration in days, hours, minutes or seconds such as `7d`,
+//! `12h`, `30m` or `90s` measured against the timestamps the schedule is advanced to. A timed
+//! order is first due at `start`, or at the first timestamp it is advanced to without one.
+
+use crate::account::ClientId;
+use crate::currency::Currency;
+use crate::error::{EngineError, InputError};
+use crate::timestamp::{Timestamp, SECONDS_PER_DAY};
+use crate::transaction::{Amount, Transaction, TransactionId, TransactionType};
+use csv::{ReaderBuilder, Trim};
+use serde::Deserialize;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+/// How often a [`StandingOrder`] is due
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interval {
+    /// Ticks of [`TransactionEngine::advance`](crate::engine::TransactionEngine::advance),
+    /// whatever the caller makes them stand for
+    Ticks(u32),
+    /// Seconds between the timestamps of the transactions
+    Seconds(u64),
+}
+
+impl FromStr for Interval {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid interval '{}'", value);
+        let interval = match value.char_indices().last() {
+            Some((at, unit)) if unit.is_ascii_alphabetic() => {
+                let count: u64 = value[..at].parse().map_err(|_| invalid())?;
+                let unit = match unit {
+                    'd' => SECONDS_PER_DAY,
+                    'h' => 3600,
+                    'm' => 60,
+                    's' => 1,
+                    _ => return Err(invalid()),
+                };
+                Interval::Seconds(count.checked_mul(unit).ok_or_else(invalid)?)
+            }
+            _ => Interval::Ticks(value.parse().map_err(|_| invalid())?),
+        };
+        match interval {
+            Interval::Ticks(0) | Interval::Seconds(0) => Err(invalid()),
+            interval => Ok(interval),
+        }
+    }
+}
+
+/// Deposit, withdrawal or transfer made every `every`
+#[derive(Debug, Clone, PartialEq)]
+pub struct StandingOrder {
+    pub transaction_type: TransactionType,
+    pub client_id: ClientId,
+    /// Id of the first transaction of the order, every later one taking the next id. Leave room
+    /// for the transactions of the order between the ids of the input and of other orders.
+    pub transaction_id: TransactionId,
+    pub amount: Amount,
+    /// Client credited by a transfer
+    pub to_client_id: Option<ClientId>,
+    pub currency: Option<Currency>,
+    pub every: Interval,
+    /// When an order in seconds is first due, ignored by orders in ticks
+    pub start: Option<Timestamp>,
+}
+
+impl StandingOrder {
+    /// The `made`th transaction of the order, counted from 0
+    fn transaction(&self, made: u32, timestamp: Option<Timestamp>) -> Transaction {
+        Transaction {
+            transaction_type: self.transaction_type,
+            client_id: self.client_id,
+            transaction_id: self.transaction_id.wrapping_add(made),
+            amount: Some(self.amount),
+            to_client_id: self.to_client_id,
+            currency: self.currency,
+            timestamp,
+            metadata: Default::default(),
+        }
+    }
+}
+
+/// A row of a schedule file
+#[derive(Deserialize)]
+struct Row {
+    #[serde(rename = "type")]
+    transaction_type: TransactionType,
+    client: ClientId,
+    tx: TransactionId,
+    amount: f64,
+    every: String,
+    #[serde(default)]
+    to: Option<ClientId>,
+    #[serde(default)]
+    currency: Option<Currency>,
+    #[serde(default)]
+    start: Option<Timestamp>,
+}
+
+impl Row {
+    fn order(self) -> Result<StandingOrder, String> {
+        if !self.transaction_type.has_new_id() {
+            return Err(format!(
+                "a standing order cannot make a {}",
+                self.transaction_type
+            ));
+        }
+        let amount = match Amount::new(self.amount) {
+            Ok(amount) if amount > Amount::ZERO => amount,
+            _ => return Err(format!("invalid amount {}", self.amount)),
+        };
+        Ok(StandingOrder {
+            transaction_type: self.transaction_type,
+            client_id: self.client,
+            transaction_id: self.tx,
+            amount,
+            to_client_id: self.to,
+            currency: self.currency,
+            every: self.every.parse()?,
+            start: self.start,
+        })
+    }
+}
+
+/// A registered order with how far it got
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    order: StandingOrder,
+    /// Transactions made so far
+    made: u32,
+}
+
+/// Standing orders due as time passes. The schedule counts its own ticks from the moment it is
+/// created and is not part of the engine state, so a schedule loaded again starts over: orders
+/// carried over to a later run need a later `start` and transaction ids of their own.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Schedule {
+    entries: Vec<Entry>,
+    tick: u64,
+}
+
+impl Schedule {
+    /// Reads the orders of a csv file with a `type, client, tx, amount, every[, to][,
+    /// currency][, start]` header
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, InputError> {
+        Self::from_reader(File::open(path).map_err(InputError::Io)?)
+    }
+
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, InputError> {
+        let mut reader = ReaderBuilder::new()
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(reader);
+        let headers = reader.headers()?.clone();
+        let mut schedule = Schedule::default();
+        for record in reader.records() {
+            let record = record?;
+            let line = record.position().map_or(0, |position| position.line());
+            let order = record
+                .deserialize::<Row>(Some(&headers))
+                .map_err(|err| err.to_string())
+                .and_then(Row::order)
+                .map_err(|message| InputError::Malformed {
+                    line,
+                    message,
+                    row: record.iter().collect::<Vec<_>>().join(","),
+                })?;
+            schedule.register(order);
+        }
+        Ok(schedule)
+    }
+
+    pub fn register(&mut self, order: StandingOrder) {
+        self.entries.push(Entry { order, made: 0 });
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Lets a tick pass, returning the transactions of the orders in ticks that fell due, in
+    /// order of registration
+    pub(crate) fn tick(&mut self) -> Vec<Transaction> {
+        self.tick += 1;
+        let tick = self.tick;
+        let mut due = Vec::new();
+        for entry in &mut self.entries {
+            if let Interval::Ticks(every) = entry.order.every {
+                if tick.is_multiple_of(u64::from(every)) {
+                    due.push(entry.order.transaction(entry.made, None));
+                    entry.made += 1;
+                }
+            }
+        }
+        due
+    }
+
+    /// Returns the transactions of the orders in seconds due at or before `now`, in order of
+    /// their timestamps
+    pub(crate) fn until(&mut self, now: Timestamp) -> Vec<Transaction> {
+        let mut due = Vec::new();
+        for (index, entry) in self.entries.iter_mut().enumerate() {
+            let Interval::Seconds(every) = entry.order.every else {
+                continue;
+            };
+            let start = *entry.order.start.get_or_insert(now);
+            loop {
+                let at = start.as_secs() + u64::from(entry.made) * every;
+                if at > now.as_secs() {
+                    break;
+                }
+                let timestamp = Timestamp::from_secs(at);
+                due.push((
+                    timestamp,
+                    index,
+                    entry.order.transaction(entry.made, Some(timestamp)),
+                ));
+                entry.made += 1;
+            }
+        }
+        due.sort_by_key(|&(timestamp, index, _)| (timestamp, index));
+        due.into_iter()
+            .map(|(_, _, transaction)| transaction)
+            .collect()
+    }
+}
+
+/// What a call of
+/// [`TransactionEngine::advance_time`](crate::engine::TransactionEngine::advance_time) executed
+#[derive(Debug, Default)]
+pub struct ScheduleSummary {
+    /// Transactions of standing orders that were applied
+    pub executed: u64,
+    /// Transactions of standing orders the engine refused, such as withdrawals exceeding the
+    /// available funds
+    pub errors: Vec<EngineError>,
+}
+
+impl fmt::Display for ScheduleSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Executed {} scheduled transactions", self.executed)?;
+        if !self.errors.is_empty() {
+            writeln!(f, "Rejected {} scheduled transactions", self.errors.len())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::error::InputError;
+        use crate::schedule::{Interval, Schedule};
+        use crate::timestamp::Timestamp;
+        use crate::transaction::{TransactionId, TransactionType};
+
+        #[test]
+        fn orders_are_due_every_interval() {
+            let mut schedule = Schedule::from_reader(
+                "type, client, tx, amount, every, to, currency, start\n\
+                 deposit, 1, 100, 5.0, 2\n\
+                 transfer, 2, 200, 1.5, 1d, 3, EUR, 1970-01-02\n\
+                 withdrawal, 4, 300, 2.0, 12h\n"
+                    .as_bytes(),
+            )
+            .unwrap();
+            assert_eq!(schedule.len(), 3);
+
+            assert!(schedule.tick().is_empty());
+            let due = schedule.tick();
+            assert_eq!(due.len(), 1);
+            assert_eq!(
+                (due[0].transaction_type, due[0].transaction_id),
+                (TransactionType::Deposit, TransactionId(100))
+            );
+            assert!(schedule.tick().is_empty());
+            assert_eq!(schedule.tick()[0].transaction_id, TransactionId(101));
+
+            // The withdrawal starts now, the transfer is due on days 1 and 2
+            let due = schedule.until(Timestamp::from_secs(2 * 86_400));
+            let made: Vec<_> = due
+                .iter()
+                .map(|transaction| {
+                    (
+                        transaction.client_id,
+                        transaction.transaction_id,
+                        transaction.timestamp.unwrap().as_secs(),
+                    )
+                })
+                .collect();
+            assert_eq!(
+                made,
+                [
+                    (2, TransactionId(200), 86_400),
+                    (2, TransactionId(201), 172_800),
+                    (4, TransactionId(300), 172_800)
+                ]
+            );
+            assert_eq!(schedule.until(Timestamp::from_secs(215_000)).len(), 0);
+            assert_eq!(
+                schedule.until(Timestamp::from_secs(216_000))[0].transaction_id,
+                TransactionId(301)
+            );
+        }
+
+        #[test]
+        fn invalid_orders_are_refused() {
+            assert_eq!("3".parse(), Ok(Interval::Ticks(3)));
+            assert_eq!("2h".parse(), Ok(Interval::Seconds(7200)));
+            for interval in ["0", "0d", "2w", "h", "-1", ""] {
+                assert!(interval.parse::<Interval>().is_err(), "{}", interval);
+            }
+            for row in [
+                "dispute, 1, 1, 1.0, 2",
+                "deposit, 1, 1, -1.0, 2",
+                "deposit, 1, 1, 1.0, weekly",
+            ] {
+                let input = format!("type,client,tx,amount,every\n{}\n", row);
+                assert!(
+                    matches!(
+                        Schedule::from_reader(input.as_bytes()),
+                        Err(InputError::Malformed { line: 2, .. })
+                    ),
+                    "{}",
+                    row
+                );
+            }
+        }
+    }
+}
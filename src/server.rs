@@ -0,0 +1,1096 @@
+//! Minimal HTTP/1.1 server exposing a shared engine as a long running service.
+//!
+//! Routes:
+//! - `POST /transactions` applies a json object, a json array of objects or ndjson
+//! - `POST /admin/transactions` does the same on behalf of an administrator, accepting lock and
+//!   unlock transactions. Only enabled with an admin token, which requests must carry as
+//!   `Authorization: Bearer <token>`.
+//! - `POST /admin/undo/{count}` reverses the last `count` deposits and withdrawals applied, see
+//!   [`TransactionEngine::undo_last`], with the admin token. Engines without recorded history
+//!   refuse it with `409 Conflict`, as they do undos that cannot be carried out.
+//! - `GET /accounts` returns every account, one object per currency, ordered by client id
+//! - `GET /accounts/{client_id}` returns the funds of an account without currency
+//! - `GET /accounts/{client_id}/{currency}` returns the funds of an account in a currency
+//! - `GET /health` reports that the server is up
+//! - `GET /metrics` returns the engine metrics in the Prometheus text format
+//!
+//! Every connection is served on its own thread and closed after one response. Client
+//! submissions over the [`RateLimits`] of the server are refused as a whole with `429 Too Many
+//! Requests`, see [`crate::throttle`]. A server given a [`ChangeSink`] emits the accounts changed
+//! by every submission before responding, in the order the submissions were applied.
+//!
+//! Account queries wait for the engine, and so for the submission being applied. A server given
+//! [`Server::with_read_view`] answers `GET /accounts` and `GET /accounts/{client_id}` from a
+//! [`ReadView`] instead: a copy of the accounts republished after every submission and every
+//! given number of transactions within one, so that reads during a long batch see accounts
+//! slightly behind but never wait for it.
+//!
+//! A server given [`Server::with_webhooks`] posts the accounts locked and the chargebacks applied
+//! to the [`Webhooks`] of its configuration, see [`crate::webhook`], and appends the outcome of
+//! the deliveries to `GET /metrics`.
+//!
+//! Builds with the grpc feature also serve the gRPC interface of `proto/engine.proto` from the
+//! same server with `Server::serve_grpc`, see `src/grpc.rs`.
+
+use crate::account::ClientId;
+use crate::changes::{ChangeSink, ChangeStream};
+use crate::currency::Currency;
+use crate::engine::TransactionEngine;
+use crate::error::EngineError;
+use crate::input::parse_json_transaction;
+use crate::log::{self, Span};
+use crate::output::{json_string, AccountSnapshot};
+use crate::throttle::{RateLimiter, RateLimits, Throttled};
+use crate::transaction::{Origin, Transaction, TransactionId};
+use crate::webhook::{WebhookNotifier, WebhookStats, Webhooks};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::thread;
+use std::time::Instant;
+
+/// Requests with a larger body are rejected before reading it
+pub(crate) const MAX_BODY_SIZE: usize = 8 * 1024 * 1024;
+const ADMIN_TRANSACTIONS: &str = "/admin/transactions";
+const ADMIN_UNDO: &str = "/admin/undo/";
+
+/// Where the engine state is saved periodically
+enum Checkpoint {
+    /// Snapshot written with [`TransactionEngine::checkpoint`]
+    Snapshot(PathBuf),
+    /// State store of the engine, see [`TransactionEngine::save_state`]
+    Store,
+}
+
+/// Funds of every account as the engine held them at a log sequence number, published by
+/// servers with [`Server::with_read_view`]. Views are never changed once published, a newer one
+/// takes their place.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReadView {
+    /// Log sequence number of the engine when the view was taken
+    pub lsn: u64,
+    accounts: BTreeMap<ClientId, Vec<AccountSnapshot>>,
+}
+
+impl ReadView {
+    pub fn of(engine: &TransactionEngine) -> Self {
+        let mut accounts: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for snapshot in engine.iter_snapshots() {
+            accounts.entry(snapshot.client).or_default().push(snapshot);
+        }
+        ReadView {
+            lsn: engine.lsn,
+            accounts,
+        }
+    }
+
+    /// Funds of the account in the currency, nothing held in currencies it never had
+    pub fn account(
+        &self,
+        client_id: ClientId,
+        currency: Option<Currency>,
+    ) -> Option<AccountSnapshot> {
+        let snapshots = self.accounts.get(&client_id)?;
+        let snapshot = snapshots
+            .iter()
+            .find(|snapshot| snapshot.currency == currency)
+            .cloned()
+            .unwrap_or_else(|| AccountSnapshot {
+                available: 0.0,
+                held: 0.0,
+                total: 0.0,
+                reserved: 0.0,
+                currency,
+                ..snapshots[0].clone()
+            });
+        Some(snapshot)
+    }
+
+    /// Funds of every account, ordered by client id then currency
+    pub fn snapshots(&self) -> impl Iterator<Item = &AccountSnapshot> + '_ {
+        self.accounts.values().flatten()
+    }
+}
+
+/// Latest [`ReadView`] of a server, replaced at the end of every submission and every
+/// `interval` transactions within one
+struct Published {
+    view: RwLock<Arc<ReadView>>,
+    interval: u64,
+}
+
+impl Published {
+    /// Latest view, the lock only held to clone it
+    fn latest(&self) -> Arc<ReadView> {
+        Arc::clone(&self.view.read().expect("Read view lock poisoned"))
+    }
+
+    /// Replaces the latest view with one of the engine
+    fn publish(&self, engine: &TransactionEngine) {
+        // Taken before locking, so that readers only ever wait for the swap
+        let view = Arc::new(ReadView::of(engine));
+        *self.view.write().expect("Read view lock poisoned") = view;
+    }
+
+    /// Publishes a view once the engine executed `interval` transactions since the latest one
+    fn publish_due(&self, engine: &TransactionEngine) {
+        if engine.lsn.saturating_sub(self.latest().lsn) >= self.interval {
+            self.publish(engine);
+        }
+    }
+}
+
+/// Serves requests against an engine shared by all connections
+#[derive(Clone)]
+pub struct Server {
+    engine: Arc<Mutex<TransactionEngine>>,
+    /// Where the state is saved every given number of transactions
+    checkpoint: Option<(Arc<Checkpoint>, u64)>,
+    /// Log sequence number of the engine at the last checkpoint
+    checkpoint_lsn: Arc<AtomicU64>,
+    /// Bearer token of admin requests, the admin route is disabled without it
+    admin_token: Option<Arc<str>>,
+    /// Limits of client submissions, none without it
+    rate_limiter: Option<Arc<Mutex<RateLimiter>>>,
+    /// Accounts changed by the submissions, none without sink
+    changes: Option<Arc<Mutex<ChangeStream>>>,
+    /// Accounts served to queries, which read the engine without it
+    read_view: Option<Arc<Published>>,
+    /// Outcome of the deliveries to the webhooks, none without them
+    webhooks: Option<Arc<WebhookStats>>,
+}
+
+impl Server {
+    pub fn new(engine: TransactionEngine) -> Self {
+        Server {
+            checkpoint_lsn: Arc::new(AtomicU64::new(engine.lsn)),
+            engine: Arc::new(Mutex::new(engine)),
+            checkpoint: None,
+            admin_token: None,
+            rate_limiter: None,
+            changes: None,
+            read_view: None,
+            webhooks: None,
+        }
+    }
+
+    /// Refuses client submissions over `limits`. Admin submissions are never limited.
+    pub fn with_rate_limits(mut self, limits: RateLimits) -> Self {
+        self.rate_limiter = limits
+            .is_enabled()
+            .then(|| Arc::new(Mutex::new(RateLimiter::new(limits))));
+        self
+    }
+
+    /// Enables `POST /admin/transactions` and `POST /admin/undo/{count}` for requests authorized
+    /// with the token
+    pub fn with_admin_token(mut self, token: &str) -> Self {
+        self.admin_token = Some(Arc::from(token));
+        self
+    }
+
+    /// Checkpoints the engine to `path` once `interval` transactions were executed since the
+    /// last checkpoint, see [`TransactionEngine::checkpoint`]
+    pub fn with_checkpoints<P: Into<PathBuf>>(mut self, path: P, interval: u64) -> Self {
+        self.checkpoint = Some((Arc::new(Checkpoint::Snapshot(path.into())), interval.max(1)));
+        self
+    }
+
+    /// Saves the state of an engine opened with [`TransactionEngine::open_store`] once
+    /// `interval` transactions were executed since it was last saved
+    pub fn with_state_saves(mut self, interval: u64) -> Self {
+        self.checkpoint = Some((Arc::new(Checkpoint::Store), interval.max(1)));
+        self
+    }
+
+    /// Emits the accounts changed by every submission to `sink`
+    pub fn with_changes(mut self, sink: Box<dyn ChangeSink>) -> Self {
+        let changes = ChangeStream::new(&mut self.lock(), sink);
+        self.changes = Some(Arc::new(Mutex::new(changes)));
+        self
+    }
+
+    /// Answers account queries from a [`ReadView`] republished at the end of every submission
+    /// and every `interval` transactions within one, rather than from the engine
+    pub fn with_read_view(mut self, interval: u64) -> Self {
+        let view = ReadView::of(&self.lock());
+        self.read_view = Some(Arc::new(Published {
+            view: RwLock::new(Arc::new(view)),
+            interval: interval.max(1),
+        }));
+        self
+    }
+
+    /// Notifies the webhooks of the accounts the engine locks and the chargebacks it applies
+    pub fn with_webhooks(mut self, webhooks: &Webhooks) -> Self {
+        let notifier = WebhookNotifier::start(webhooks);
+        self.webhooks = Some(notifier.stats());
+        self.lock().subscribe(Box::new(notifier));
+        self
+    }
+
+    /// Latest view of the accounts, `None` unless the server has one
+    pub fn read_view(&self) -> Option<Arc<ReadView>> {
+        self.read_view.as_ref().map(|published| published.latest())
+    }
+
+    /// Engine behind the server, e.g. to inspect or snapshot it while serving
+    pub fn engine(&self) -> Arc<Mutex<TransactionEngine>> {
+        Arc::clone(&self.engine)
+    }
+
+    /// Accepts connections until the listener fails
+    pub fn serve(&self, listener: TcpListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let server = self.clone();
+            thread::spawn(move || {
+                // The client may already be gone, there is nobody to report the error to
+                let _ = server.handle_connection(stream);
+            });
+        }
+        Ok(())
+    }
+
+    fn handle_connection(&self, stream: TcpStream) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let response = match read_request(&mut reader)? {
+            Ok(request) => {
+                let _span = Span::enter(
+                    "request",
+                    &[("method", &request.method), ("path", &request.path)],
+                );
+                let response = self.route(&request);
+                log::debug("Handled request", &[("status", &response.status)]);
+                response
+            }
+            Err(response) => response,
+        };
+        response.write_to(stream)
+    }
+
+    fn route(&self, request: &Request) -> Response {
+        let path = request
+            .path
+            .split_once('?')
+            .map_or(request.path.as_str(), |(path, _)| path);
+        if path == ADMIN_TRANSACTIONS {
+            return self.admin(
+                &request.method,
+                request.authorization.as_deref(),
+                &request.body,
+            );
+        }
+        if let Some(count) = path.strip_prefix(ADMIN_UNDO) {
+            return self.undo(&request.method, request.authorization.as_deref(), count);
+        }
+        self.handle(&request.method, path, &request.body)
+    }
+
+    pub(crate) fn handle(&self, method: &str, path: &str, body: &[u8]) -> Response {
+        let path = path.split_once('?').map_or(path, |(path, _)| path);
+        match (method, path) {
+            ("GET", "/health") => Response::new(200, "{\"status\":\"ok\"}".to_string()),
+            ("POST", "/transactions") => self.submit(body, Origin::Client),
+            ("GET", "/accounts") => self.accounts(),
+            ("GET", "/metrics") => {
+                let mut body = self.lock().metrics().to_prometheus();
+                if let Some(webhooks) = &self.webhooks {
+                    body.push_str(&webhooks.to_prometheus());
+                }
+                Response {
+                    status: 200,
+                    content_type: "text/plain; version=0.0.4",
+                    body,
+                    retry_after: None,
+                }
+            }
+            ("GET", path) if path.starts_with("/accounts/") => {
+                let (client_id, currency) = match path["/accounts/".len()..].split_once('/') {
+                    Some((client_id, currency)) => (client_id, Some(currency)),
+                    None => (&path["/accounts/".len()..], None),
+                };
+                match (
+                    client_id.parse::<ClientId>(),
+                    currency.map(str::parse::<Currency>).transpose(),
+                ) {
+                    (Ok(client_id), Ok(currency)) => self.account(client_id, currency),
+                    _ => Response::error(404, "no such account"),
+                }
+            }
+            (_, "/health" | "/transactions" | "/accounts" | "/metrics") => {
+                Response::error(405, "method not allowed")
+            }
+            (_, path) if path.starts_with("/accounts/") => {
+                Response::error(405, "method not allowed")
+            }
+            _ => Response::error(404, "not found"),
+        }
+    }
+
+    /// Submits the body as an administrator once the `Authorization` header is checked
+    pub(crate) fn admin(&self, method: &str, authorization: Option<&str>, body: &[u8]) -> Response {
+        if let Err(response) = self.authorize(method, authorization) {
+            return response;
+        }
+        self.submit(body, Origin::Admin)
+    }
+
+    /// Checks that the request is an admin POST carrying the token
+    fn authorize(&self, method: &str, authorization: Option<&str>) -> Result<(), Response> {
+        let Some(token) = &self.admin_token else {
+            return Err(Response::error(403, "admin API is disabled"));
+        };
+        if method != "POST" {
+            return Err(Response::error(405, "method not allowed"));
+        }
+        let bearer = authorization.and_then(|value| value.strip_prefix("Bearer "));
+        if !bearer.is_some_and(|bearer| constant_time_eq(bearer.as_bytes(), token.as_bytes())) {
+            return Err(Response::error(401, "missing or invalid admin token"));
+        }
+        Ok(())
+    }
+
+    /// Reverses the last `count` deposits and withdrawals once the request is authorized
+    pub(crate) fn undo(&self, method: &str, authorization: Option<&str>, count: &str) -> Response {
+        if let Err(response) = self.authorize(method, authorization) {
+            return response;
+        }
+        let Ok(count) = count.parse::<usize>() else {
+            return Response::error(404, "not found");
+        };
+        let mut engine = self.lock();
+        if !engine.config().record_history {
+            return Response::error(409, "undo needs the history, recorded with record_history");
+        }
+        let undone = engine.undo_last(count);
+        // Whatever was undone before a failure stays undone
+        if let Err(message) = self.persist(&mut engine) {
+            return Response::error(500, &message);
+        }
+        match undone {
+            Ok(undone) => {
+                log::info("Undid transactions", &[("count", &undone.len())]);
+                let ids: Vec<_> = undone.iter().map(ToString::to_string).collect();
+                Response::new(200, format!("{{\"undone\":[{}]}}", ids.join(",")))
+            }
+            Err(err) => {
+                log::warn("Refused undo", &[("reason", &err)]);
+                Response::error(409, &err.to_string())
+            }
+        }
+    }
+
+    /// Applies every transaction of the body and reports the outcome of each. Nothing is applied
+    /// when any of them is malformed.
+    fn submit(&self, body: &[u8], origin: Origin) -> Response {
+        let body = match std::str::from_utf8(body) {
+            Ok(body) => body,
+            Err(_) => return Response::error(400, "body is not valid utf-8"),
+        };
+        let transactions = match split_objects(body).and_then(|objects| {
+            objects
+                .into_iter()
+                .map(parse_json_transaction)
+                .collect::<Result<Vec<_>, _>>()
+        }) {
+            Ok(transactions) => transactions,
+            Err(message) => return Response::error(400, &message),
+        };
+        match self.execute(transactions, origin) {
+            Ok(results) => {
+                let results: Vec<_> = results
+                    .iter()
+                    .map(|(transaction_id, result)| status_json(*transaction_id, result))
+                    .collect();
+                Response::new(200, format!("{{\"results\":[{}]}}", results.join(",")))
+            }
+            Err(Refused::Throttled(throttled)) => {
+                let mut response = Response::error(429, &throttled.to_string());
+                // Whole seconds as the header requires, rounded up so that the retry succeeds
+                response.retry_after = throttled
+                    .retry_after
+                    .map(|retry_after| retry_after.as_secs_f64().ceil() as u64);
+                response
+            }
+            Err(Refused::Failed(message)) => Response::error(500, &message),
+        }
+    }
+
+    /// Applies the transactions in order once they are within the rate limits, returning the
+    /// outcome of each once they are durable
+    pub(crate) fn execute(
+        &self,
+        transactions: Vec<Transaction>,
+        origin: Origin,
+    ) -> Result<Vec<Outcome>, Refused> {
+        if let (Some(limiter), Origin::Client) = (&self.rate_limiter, origin) {
+            let clients: Vec<_> = transactions
+                .iter()
+                .map(|transaction| transaction.client_id)
+                .collect();
+            let acquired = limiter
+                .lock()
+                .expect("Rate limiter lock poisoned")
+                .acquire(&clients, Instant::now());
+            if let Err(throttled) = acquired {
+                log::info("Throttled request", &[("reason", &throttled)]);
+                self.lock()
+                    .metrics
+                    .record_throttled(throttled.limit, clients.len() as u64);
+                return Err(Refused::Throttled(throttled));
+            }
+        }
+
+        let mut engine = self.lock();
+        let results = transactions
+            .into_iter()
+            .map(|transaction| {
+                let transaction_id = transaction.transaction_id;
+                let result = engine.execute_from(transaction, origin);
+                if let Err(err) = &result {
+                    log::warn(
+                        "Rejected transaction",
+                        &[("tx", &transaction_id), ("reason", err)],
+                    );
+                }
+                if let Some(published) = &self.read_view {
+                    published.publish_due(&engine);
+                }
+                (transaction_id, result)
+            })
+            .collect();
+        self.persist(&mut engine).map_err(Refused::Failed)?;
+        Ok(results)
+    }
+
+    /// Makes what was just applied durable and visible and emits the accounts it changed,
+    /// returning what failed otherwise
+    fn persist(&self, engine: &mut TransactionEngine) -> Result<(), String> {
+        if let Err(err) = engine.flush_audit() {
+            return Err(format!("failed to write audit log: {}", err));
+        }
+        // Transactions are only acknowledged once they are durable
+        if let Err(err) = engine.sync_wal() {
+            return Err(format!("failed to sync write-ahead log: {}", err));
+        }
+        if let Some(changes) = &self.changes {
+            let emitted = changes
+                .lock()
+                .expect("Change stream lock poisoned")
+                .emit(engine);
+            if let Err(err) = emitted {
+                return Err(format!("failed to emit changed accounts: {}", err));
+            }
+        }
+        if let Some((checkpoint, interval)) = &self.checkpoint {
+            if engine.lsn - self.checkpoint_lsn.load(Ordering::Relaxed) >= *interval {
+                let saved = match checkpoint.as_ref() {
+                    Checkpoint::Snapshot(path) => engine.checkpoint(path),
+                    Checkpoint::Store => engine.save_state(),
+                };
+                if let Err(err) = saved {
+                    return Err(format!("failed to checkpoint: {}", err));
+                }
+                self.checkpoint_lsn.store(engine.lsn, Ordering::Relaxed);
+            }
+        }
+        // Undone transactions leave the sequence number as it is, so views are always republished
+        if let Some(published) = &self.read_view {
+            published.publish(engine);
+        }
+        Ok(())
+    }
+
+    fn accounts(&self) -> Response {
+        let records: Vec<_> = self
+            .snapshots()
+            .iter()
+            .map(AccountSnapshot::to_json)
+            .collect();
+        Response::new(200, format!("[{}]", records.join(",")))
+    }
+
+    fn account(&self, client_id: ClientId, currency: Option<Currency>) -> Response {
+        match self.snapshot(client_id, currency) {
+            Some(record) => Response::new(200, record.to_json()),
+            None => Response::error(404, "no such account"),
+        }
+    }
+
+    /// Funds of every account as queries are answered, ordered by client id then currency
+    pub(crate) fn snapshots(&self) -> Vec<AccountSnapshot> {
+        match self.read_view() {
+            Some(view) => view.snapshots().cloned().collect(),
+            None => self.lock().sorted_snapshots().collect(),
+        }
+    }
+
+    /// Funds of the account in the currency as queries are answered, `None` without an account
+    pub(crate) fn snapshot(
+        &self,
+        client_id: ClientId,
+        currency: Option<Currency>,
+    ) -> Option<AccountSnapshot> {
+        if let Some(view) = self.read_view() {
+            return view.account(client_id, currency);
+        }
+        let engine = self.lock();
+        let account = engine.accounts.get(&client_id)?;
+        Some(AccountSnapshot {
+            reserved: engine.reserve(client_id, currency),
+            closed: engine.is_closed(client_id),
+            ..AccountSnapshot::new(account.as_ref(), currency)
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, TransactionEngine> {
+        self.engine.lock().expect("Engine lock poisoned")
+    }
+}
+
+pub(crate) struct Request {
+    method: String,
+    path: String,
+    /// Value of the `Authorization` header
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+/// Reads a request, or the error response to send when it is not acceptable
+fn read_request(reader: &mut impl BufRead) -> io::Result<Result<Request, Response>> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Ok(Err(Response::error(400, "malformed request line"))),
+    };
+
+    let mut content_length = 0;
+    let mut authorization = None;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(Err(Response::error(400, "unexpected end of headers")));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = match value.trim().parse() {
+                    Ok(length) => length,
+                    Err(_) => return Ok(Err(Response::error(400, "invalid content-length"))),
+                };
+            } else if name.trim().eq_ignore_ascii_case("authorization") {
+                authorization = Some(value.trim().to_string());
+            }
+        }
+    }
+    if content_length > MAX_BODY_SIZE {
+        return Ok(Err(Response::error(413, "request body too large")));
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    Ok(Ok(Request {
+        method,
+        path,
+        authorization,
+        body,
+    }))
+}
+
+/// Compares without returning early so that the time taken does not reveal the token
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Splits a body holding a json object, a json array of objects or ndjson into its objects
+fn split_objects(body: &str) -> Result<Vec<&str>, String> {
+    let body = body.trim();
+    let Some(array) = body.strip_prefix('[') else {
+        return Ok(body
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect());
+    };
+    let array = array
+        .strip_suffix(']')
+        .ok_or_else(|| "expected ']' at end of array".to_string())?;
+
+    let mut objects = Vec::new();
+    let mut start = 0;
+    let (mut depth, mut in_string, mut escaped) = (0usize, false, false);
+    for (i, c) in array.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '{' | '[' if !in_string => depth += 1,
+            '}' | ']' if !in_string => depth = depth.saturating_sub(1),
+            ',' if !in_string && depth == 0 => {
+                objects.push(array[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    let last = array[start..].trim();
+    if !last.is_empty() || !objects.is_empty() {
+        objects.push(last);
+    }
+    Ok(objects)
+}
+
+/// Status of a submitted transaction, `{"tx":1,"status":"applied"}` or
+/// `{"tx":1,"status":"rejected","reason":"..."}`
+pub(crate) fn status_json(
+    transaction_id: TransactionId,
+    result: &Result<(), EngineError>,
+) -> String {
+    match result {
+        Ok(()) => format!("{{\"tx\":{},\"status\":\"applied\"}}", transaction_id),
+        Err(err) => format!(
+            "{{\"tx\":{},\"status\":\"rejected\",\"reason\":{}}}",
+            transaction_id,
+            json_string(&err.to_string())
+        ),
+    }
+}
+
+/// Transaction of a submission with whether the engine applied it
+pub(crate) type Outcome = (TransactionId, Result<(), EngineError>);
+
+/// Why a submission was not acknowledged as a whole
+#[derive(Debug)]
+pub(crate) enum Refused {
+    /// Over the rate limits of the server, nothing was applied
+    Throttled(Throttled),
+    /// The transactions were applied but could not be made durable or emitted
+    Failed(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+    /// Seconds after which a throttled request may be sent again
+    retry_after: Option<u64>,
+}
+
+impl Response {
+    fn new(status: u16, body: String) -> Self {
+        Response {
+            status,
+            content_type: "application/json",
+            body,
+            retry_after: None,
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Response::new(status, format!("{{\"error\":{}}}", json_string(message)))
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Payload Too Large",
+            429 => "Too Many Requests",
+            _ => "Internal Server Error",
+        }
+    }
+
+    fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        write!(
+            writer,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n",
+            self.status,
+            self.reason(),
+            self.content_type,
+            self.body.len()
+        )?;
+        if let Some(seconds) = self.retry_after {
+            write!(writer, "Retry-After: {}\r\n", seconds)?;
+        }
+        write!(writer, "Connection: close\r\n\r\n{}", self.body)?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::engine::{EngineConfig, TransactionEngine};
+        use crate::server::{read_request, split_objects, Response, Server};
+        use crate::state::{MemoryStateStore, StateStore};
+        use crate::throttle::RateLimits;
+        use crate::transaction::{Amount, Transaction, TransactionId, TransactionType};
+        use crate::webhook::Webhooks;
+        use std::sync::mpsc;
+
+        fn post(server: &Server, body: &str) -> Response {
+            server.handle("POST", "/transactions", body.as_bytes())
+        }
+
+        #[test]
+        fn transactions_are_applied_and_accounts_reported() {
+            let server = Server::new(TransactionEngine::new());
+
+            let response = post(
+                &server,
+                r#"[{"type":"deposit","client":2,"tx":1,"amount":3.0},
+                    {"type":"withdrawal","client":2,"tx":2,"amount":5.0}]"#,
+            );
+            assert_eq!(response.status, 200);
+            assert_eq!(
+                response.body,
+                "{\"results\":[{\"tx\":1,\"status\":\"applied\"},{\"tx\":2,\"status\":\"rejected\",\
+                 \"reason\":\"client 2: transaction 2: insufficient funds, requested 5.0000 but only 3.0000 available\"}]}"
+            );
+            post(
+                &server,
+                r#"{"type":"deposit","client":1,"tx":3,"amount":1.5}"#,
+            );
+
+            assert_eq!(
+                server.handle("GET", "/accounts", &[]).body,
+                "[{\"client\":1,\"available\":1.5000,\"held\":0.0000,\"total\":1.5000,\"locked\":false},\
+                 {\"client\":2,\"available\":3.0000,\"held\":0.0000,\"total\":3.0000,\"locked\":false}]"
+            );
+            assert_eq!(
+                server.handle("GET", "/accounts/1", &[]).body,
+                "{\"client\":1,\"available\":1.5000,\"held\":0.0000,\"total\":1.5000,\"locked\":false}"
+            );
+        }
+
+        #[test]
+        fn account_queries_are_answered_from_the_read_view() {
+            let server = Server::new(TransactionEngine::new()).with_read_view(2);
+            assert_eq!(server.handle("GET", "/accounts", &[]).body, "[]");
+
+            post(
+                &server,
+                r#"[{"type":"deposit","client":1,"tx":1,"amount":1.0},
+                    {"type":"deposit","client":1,"tx":2,"amount":2.0},
+                    {"type":"deposit","client":2,"tx":3,"amount":4.0}]"#,
+            );
+            let view = server.read_view().unwrap();
+            assert_eq!(view.lsn, 3);
+            assert_eq!(view.account(1, None).unwrap().total, 3.0);
+
+            // Queries do not wait for the engine, busy applying a batch
+            let engine = server.engine();
+            let mut engine = engine.lock().unwrap();
+            engine
+                .execute(Transaction {
+                    transaction_type: TransactionType::Deposit,
+                    client_id: 2,
+                    transaction_id: TransactionId(4),
+                    amount: Some(Amount::unchecked(1.0)),
+                    to_client_id: None,
+                    currency: None,
+                    timestamp: None,
+                    metadata: Default::default(),
+                })
+                .unwrap();
+            assert_eq!(
+                server.handle("GET", "/accounts/2", &[]).body,
+                "{\"client\":2,\"available\":4.0000,\"held\":0.0000,\"total\":4.0000,\"locked\":false}"
+            );
+            assert_eq!(
+                server.handle("GET", "/accounts/2/EUR", &[]).body,
+                "{\"client\":2,\"available\":0.0000,\"held\":0.0000,\"total\":0.0000,\"locked\":false,\
+                 \"currency\":\"EUR\"}"
+            );
+            assert_eq!(server.handle("GET", "/accounts/3", &[]).status, 404);
+            drop(engine);
+
+            post(
+                &server,
+                r#"{"type":"withdrawal","client":2,"tx":5,"amount":5.0}"#,
+            );
+            assert_eq!(
+                server.handle("GET", "/accounts", &[]).body,
+                "[{\"client\":1,\"available\":3.0000,\"held\":0.0000,\"total\":3.0000,\"locked\":false},\
+                 {\"client\":2,\"available\":0.0000,\"held\":0.0000,\"total\":0.0000,\"locked\":false}]"
+            );
+        }
+
+        #[test]
+        fn accounts_changed_by_each_submission_are_emitted() {
+            let (sender, receiver) = mpsc::channel();
+            let server = Server::new(TransactionEngine::new()).with_changes(Box::new(sender));
+
+            post(
+                &server,
+                r#"[{"type":"deposit","client":2,"tx":1,"amount":3.0},
+                    {"type":"deposit","client":1,"tx":2,"amount":1.0}]"#,
+            );
+            post(
+                &server,
+                r#"{"type":"withdrawal","client":2,"tx":3,"amount":5.0}"#,
+            );
+            post(
+                &server,
+                r#"{"type":"withdrawal","client":2,"tx":4,"amount":1.0}"#,
+            );
+
+            let batches: Vec<Vec<_>> = receiver
+                .try_iter()
+                .map(|changes| changes.iter().map(|change| change.to_json()).collect())
+                .collect();
+            assert_eq!(
+                batches,
+                [
+                    vec![
+                        "{\"client\":1,\"available\":1.0000,\"held\":0.0000,\"total\":1.0000,\"locked\":false}",
+                        "{\"client\":2,\"available\":3.0000,\"held\":0.0000,\"total\":3.0000,\"locked\":false}",
+                    ],
+                    vec![
+                        "{\"client\":2,\"available\":2.0000,\"held\":0.0000,\"total\":2.0000,\"locked\":false}",
+                    ],
+                ]
+            );
+        }
+
+        #[test]
+        fn malformed_batch_is_not_applied() {
+            let server = Server::new(TransactionEngine::new());
+
+            let response = post(
+                &server,
+                "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":1.0}\n{\"type\":\"gift\"}",
+            );
+
+            assert_eq!(response.status, 400);
+            assert!(server.engine().lock().unwrap().accounts.is_empty());
+        }
+
+        #[test]
+        fn unknown_routes_and_accounts_are_reported() {
+            let server = Server::new(TransactionEngine::new());
+
+            assert_eq!(server.handle("GET", "/health", &[]).status, 200);
+            assert_eq!(server.handle("GET", "/metrics", &[]).status, 200);
+            assert_eq!(server.handle("GET", "/accounts/7", &[]).status, 404);
+            assert_eq!(server.handle("GET", "/accounts/abc", &[]).status, 404);
+            assert_eq!(server.handle("DELETE", "/accounts", &[]).status, 405);
+            assert_eq!(server.handle("GET", "/nothing", &[]).status, 404);
+        }
+
+        #[test]
+        fn webhook_deliveries_are_reported_in_metrics() {
+            let server = Server::new(TransactionEngine::new()).with_webhooks(&Webhooks {
+                urls: vec!["http://127.0.0.1:9/hooks".parse().unwrap()],
+                secret: "s3cret".to_string(),
+                ..Webhooks::default()
+            });
+
+            let metrics = server.handle("GET", "/metrics", &[]).body;
+            assert!(metrics.contains("engine_transactions_total"));
+            assert!(metrics.contains(
+                "webhook_deliveries_total{url=\"http://127.0.0.1:9/hooks\",status=\"delivered\"} 0"
+            ));
+        }
+
+        #[test]
+        fn request_is_read_with_its_body() {
+            let raw = "POST /transactions HTTP/1.1\r\nHost: x\r\nContent-Length: 4\r\n\r\nbody";
+
+            let request = read_request(&mut raw.as_bytes()).unwrap().unwrap();
+
+            assert_eq!(request.method, "POST");
+            assert_eq!(request.path, "/transactions");
+            assert_eq!(request.authorization, None);
+            assert_eq!(request.body, b"body");
+        }
+
+        #[test]
+        fn objects_are_split_from_arrays_and_lines() {
+            assert_eq!(
+                split_objects(r#"[{"a":"x,}"}, {"b":1}]"#).unwrap(),
+                vec![r#"{"a":"x,}"}"#, r#"{"b":1}"#]
+            );
+            assert_eq!(split_objects("[]").unwrap(), Vec::<&str>::new());
+            assert_eq!(
+                split_objects("{\"a\":1}\n\n{\"b\":2}\n").unwrap(),
+                vec!["{\"a\":1}", "{\"b\":2}"]
+            );
+        }
+
+        #[test]
+        fn engine_is_checkpointed_periodically() {
+            let path = std::env::temp_dir().join("server_engine_is_checkpointed.snapshot");
+            let _ = std::fs::remove_file(&path);
+            let server = Server::new(TransactionEngine::new()).with_checkpoints(&path, 2);
+
+            post(
+                &server,
+                r#"{"type":"deposit","client":1,"tx":1,"amount":1.0}"#,
+            );
+            assert!(!path.exists());
+            post(
+                &server,
+                r#"{"type":"deposit","client":1,"tx":2,"amount":1.0}"#,
+            );
+            let restored = TransactionEngine::restore(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(restored.accounts[&1].get_available_funds(), 2.0);
+        }
+
+        #[test]
+        fn admin_transactions_require_the_token() {
+            let lock = r#"{"type":"lock","client":1,"tx":1}"#;
+            let disabled = Server::new(TransactionEngine::new());
+            assert_eq!(
+                disabled
+                    .admin("POST", Some("Bearer s3cret"), lock.as_bytes())
+                    .status,
+                403
+            );
+
+            let server = Server::new(TransactionEngine::new()).with_admin_token("s3cret");
+            assert_eq!(server.admin("POST", None, lock.as_bytes()).status, 401);
+            assert_eq!(
+                server
+                    .admin("POST", Some("Bearer guess"), lock.as_bytes())
+                    .status,
+                401
+            );
+            assert_eq!(server.admin("GET", Some("Bearer s3cret"), &[]).status, 405);
+            assert_eq!(
+                post(&server, lock).body,
+                "{\"results\":[{\"tx\":1,\"status\":\"rejected\",\"reason\":\
+                 \"transaction 1 is administrative and was not submitted by an admin\"}]}"
+            );
+            assert!(server.engine().lock().unwrap().accounts.is_empty());
+
+            let response = server.admin("POST", Some("Bearer s3cret"), lock.as_bytes());
+            assert_eq!(response.status, 200);
+            assert!(server.engine().lock().unwrap().accounts[&1].is_locked());
+        }
+
+        #[test]
+        fn the_last_transactions_are_undone_by_admins() {
+            let server = Server::new(TransactionEngine::with_config(EngineConfig {
+                record_history: true,
+                ..EngineConfig::default()
+            }))
+            .with_admin_token("s3cret");
+            post(
+                &server,
+                "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":5.0}\n\
+                 {\"type\":\"deposit\",\"client\":1,\"tx\":2,\"amount\":2.0}\n\
+                 {\"type\":\"dispute\",\"client\":1,\"tx\":1}\n\
+                 {\"type\":\"deposit\",\"client\":2,\"tx\":3,\"amount\":1.0}",
+            );
+
+            assert_eq!(server.undo("POST", None, "1").status, 401);
+            assert_eq!(server.undo("POST", Some("Bearer s3cret"), "x").status, 404);
+            let undone = server.undo("POST", Some("Bearer s3cret"), "1");
+            assert_eq!(
+                (undone.status, undone.body.as_str()),
+                (200, "{\"undone\":[3]}")
+            );
+            let refused = server.undo("POST", Some("Bearer s3cret"), "2");
+            assert_eq!(refused.status, 409);
+            assert!(refused.body.contains("transaction 1 cannot be reversed"));
+            {
+                let engine = server.engine();
+                let engine = engine.lock().unwrap();
+                assert_eq!(engine.accounts[&1].get_total_funds(), 7.0);
+                assert_eq!(engine.accounts[&2].get_total_funds(), 0.0);
+            }
+
+            let without_history = Server::new(TransactionEngine::new()).with_admin_token("s3cret");
+            assert_eq!(
+                without_history
+                    .undo("POST", Some("Bearer s3cret"), "1")
+                    .status,
+                409
+            );
+        }
+
+        #[test]
+        fn submissions_over_the_rate_limits_are_refused() {
+            let server = Server::new(TransactionEngine::new())
+                .with_admin_token("s3cret")
+                .with_rate_limits(RateLimits {
+                    global: None,
+                    per_client: Some("0.001:2".parse().unwrap()),
+                });
+            let deposit = |client, tx| {
+                format!(
+                    r#"{{"type":"deposit","client":{},"tx":{},"amount":1.0}}"#,
+                    client, tx
+                )
+            };
+
+            assert_eq!(post(&server, &deposit(1, 1)).status, 200);
+            let both = format!("{}\n{}", deposit(1, 2), deposit(2, 3));
+            assert_eq!(post(&server, &both).status, 200);
+            let response = post(&server, &format!("{}\n{}", deposit(1, 4), deposit(2, 5)));
+            assert_eq!(response.status, 429);
+            assert_eq!(response.retry_after, Some(1000));
+            assert!(response
+                .body
+                .starts_with("{\"error\":\"client 1 is over its rate limit, retry after "));
+            // Nothing of the refused request was applied or taken from the bucket of client 2
+            assert_eq!(post(&server, &deposit(2, 6)).status, 200);
+            let unlimited = server.admin("POST", Some("Bearer s3cret"), deposit(1, 7).as_bytes());
+            assert_eq!(unlimited.status, 200);
+
+            let engine = server.engine();
+            let engine = engine.lock().unwrap();
+            assert_eq!(engine.accounts[&1].get_available_funds(), 3.0);
+            assert_eq!(engine.accounts[&2].get_available_funds(), 2.0);
+            assert_eq!(
+                engine.metrics().throttled().collect::<Vec<_>>(),
+                [("client", 2)]
+            );
+            let mut written = Vec::new();
+            response.write_to(&mut written).unwrap();
+            assert!(String::from_utf8(written)
+                .unwrap()
+                .starts_with("HTTP/1.1 429 Too Many Requests\r\n"));
+        }
+
+        #[test]
+        fn engine_state_is_saved_in_its_store_periodically() {
+            let store = MemoryStateStore::new();
+            let engine =
+                TransactionEngine::open_store(Box::new(store.clone()), EngineConfig::default())
+                    .unwrap();
+            let server = Server::new(engine).with_state_saves(2);
+
+            for tx in 1..=3 {
+                post(
+                    &server,
+                    &format!(
+                        r#"{{"type":"deposit","client":1,"tx":{},"amount":1.0}}"#,
+                        tx
+                    ),
+                );
+            }
+            let (saved, recorded) = store.clone().load(EngineConfig::default()).unwrap();
+
+            assert_eq!(saved.unwrap().accounts[&1].get_available_funds(), 2.0);
+            assert_eq!(recorded.len(), 1);
+        }
+    }
+}
@@ -0,0 +1,384 @@
+use crate::account::ClientId;
+use crate::engine::{EngineConfig, TransactionEngine};
+use crate::error::EngineError;
+use crate::output::AccountSnapshot;
+use crate::processor::TransactionProcessor;
+use crate::transaction::{Origin, Transaction, TransactionId, TransactionType};
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::mpsc::{self, Sender, SyncSender};
+use std::thread::{self, JoinHandle};
+
+/// Batches waiting for a shard before `submit` blocks
+const SHARD_QUEUE_CAPACITY: usize = 64;
+/// Transactions are sent to shards in batches to amortise the cost of the channel
+const BATCH_SIZE: usize = 256;
+
+type Batch = Vec<(u64, Transaction, Origin)>;
+
+type ShardOutcome = (TransactionEngine, Vec<(u64, EngineError)>);
+
+enum ShardMessage {
+    Batch(Batch),
+    /// Asks for the funds of the client, or of every account of the shard if `None`, once the
+    /// batches sent before are applied
+    Snapshots {
+        client_id: Option<ClientId>,
+        reply: Sender<Vec<AccountSnapshot>>,
+    },
+}
+
+/// Processes transactions on several worker threads. Accounts are independent, so clients are
+/// partitioned across shards by a hash of the client id and every shard owns its accounts.
+/// Transfers between clients of different shards are rejected since no shard owns both accounts.
+pub struct ShardedEngine {
+    senders: Vec<SyncSender<ShardMessage>>,
+    /// Transactions not yet sent to each shard, flushed by reads as well
+    batches: RefCell<Vec<Batch>>,
+    workers: Vec<JoinHandle<ShardOutcome>>,
+    router: ShardRouter,
+    /// Position of the next transaction in the input, used to report errors in input order
+    sequence: u64,
+    /// Transactions rejected before reaching a shard
+    rejected: Vec<(u64, EngineError)>,
+}
+
+impl ShardedEngine {
+    pub fn new(shards: usize, config: EngineConfig) -> Self {
+        Self::from_engines(
+            (0..shards.max(1))
+                .map(|_| TransactionEngine::with_config(config.clone()))
+                .collect(),
+        )
+    }
+
+    /// Engine with one shard per given engine, which are expected to be new
+    pub(crate) fn from_engines(engines: Vec<TransactionEngine>) -> Self {
+        let shards = engines.len();
+        let (senders, workers) = engines
+            .into_iter()
+            .map(|mut engine| {
+                let (sender, receiver) = mpsc::sync_channel(SHARD_QUEUE_CAPACITY);
+                let worker = thread::spawn(move || {
+                    let mut errors = Vec::new();
+                    for message in receiver {
+                        match message {
+                            ShardMessage::Batch(batch) => {
+                                for (sequence, transaction, origin) in batch {
+                                    if let Err(err) = engine.execute_from(transaction, origin) {
+                                        errors.push((sequence, err));
+                                    }
+                                }
+                            }
+                            ShardMessage::Snapshots { client_id, reply } => {
+                                let snapshots = match client_id {
+                                    Some(client_id) => {
+                                        engine.account_snapshot(client_id).into_iter().collect()
+                                    }
+                                    None => engine.iter_snapshots().collect(),
+                                };
+                                // The reader may have given up waiting
+                                let _ = reply.send(snapshots);
+                            }
+                        }
+                    }
+                    (engine, errors)
+                });
+                (sender, worker)
+            })
+            .unzip();
+
+        ShardedEngine {
+            batches: RefCell::new(vec![Vec::with_capacity(BATCH_SIZE); shards]),
+            senders,
+            workers,
+            router: ShardRouter::new(shards),
+            sequence: 0,
+            rejected: Vec::new(),
+        }
+    }
+
+    pub fn shards(&self) -> usize {
+        self.senders.len()
+    }
+
+    /// Queues the transaction on its shard. Blocks while the shard's queue is full.
+    /// Transactions are only guaranteed to be applied once `finish` is called.
+    pub fn submit(&mut self, transaction: Transaction) {
+        self.submit_from(transaction, Origin::Client)
+    }
+
+    /// Queues a transaction from the given source, see [`TransactionEngine::execute_from`]
+    pub fn submit_from(&mut self, transaction: Transaction, origin: Origin) {
+        let shard = match self.router.route(&transaction) {
+            Ok(shard) => shard,
+            Err(err) => {
+                self.rejected.push((self.sequence, err));
+                self.sequence += 1;
+                return;
+            }
+        };
+
+        let full = {
+            let mut batches = self.batches.borrow_mut();
+            batches[shard].push((self.sequence, transaction, origin));
+            batches[shard].len() >= BATCH_SIZE
+        };
+        self.sequence += 1;
+        if full {
+            self.flush(shard);
+        }
+    }
+
+    fn flush(&self, shard: usize) {
+        let batch = std::mem::replace(
+            &mut self.batches.borrow_mut()[shard],
+            Vec::with_capacity(BATCH_SIZE),
+        );
+        self.send(shard, ShardMessage::Batch(batch));
+    }
+
+    fn send(&self, shard: usize, message: ShardMessage) {
+        self.senders[shard]
+            .send(message)
+            .expect("Shard worker stopped unexpectedly");
+    }
+
+    /// Funds of the client, or of every account of the shard, once the transactions submitted
+    /// to it so far are applied
+    fn snapshots(&self, shard: usize, client_id: Option<ClientId>) -> Vec<AccountSnapshot> {
+        self.flush(shard);
+        let (reply, snapshots) = mpsc::channel();
+        self.send(shard, ShardMessage::Snapshots { client_id, reply });
+        snapshots.recv().expect("Shard worker stopped unexpectedly")
+    }
+
+    /// Waits for all queued transactions and merges the shards into a single engine. Rejected
+    /// transactions are returned in input order.
+    pub fn finish(mut self) -> (TransactionEngine, Vec<EngineError>) {
+        for shard in 0..self.shards() {
+            self.flush(shard);
+        }
+        // Closing the channels lets the workers drain their queues and exit
+        drop(self.senders);
+
+        let mut merged: Option<TransactionEngine> = None;
+        let mut errors = std::mem::take(&mut self.rejected);
+        for worker in self.workers {
+            let (engine, shard_errors) = worker.join().expect("Shard worker panicked");
+            errors.extend(shard_errors);
+            match merged.as_mut() {
+                Some(merged) => merged.absorb(engine),
+                None => merged = Some(engine),
+            }
+        }
+        errors.sort_by_key(|(sequence, _)| *sequence);
+
+        (
+            merged.expect("Sharded engine has at least one shard"),
+            errors.into_iter().map(|(_, err)| err).collect(),
+        )
+    }
+}
+
+/// Every rejection, including those of transfers between shards, is returned by `finalize`
+impl TransactionProcessor for ShardedEngine {
+    fn execute_from(
+        &mut self,
+        transaction: Transaction,
+        origin: Origin,
+    ) -> Result<(), EngineError> {
+        self.submit_from(transaction, origin);
+        Ok(())
+    }
+
+    fn account_snapshot(&self, client_id: ClientId) -> Option<AccountSnapshot> {
+        let shard = self.router.shard_of(client_id);
+        self.snapshots(shard, Some(client_id)).pop()
+    }
+
+    fn iter_snapshots(&self) -> Box<dyn Iterator<Item = AccountSnapshot> + '_> {
+        Box::new((0..self.shards()).flat_map(|shard| self.snapshots(shard, None)))
+    }
+
+    fn finalize(self) -> (TransactionEngine, Vec<EngineError>) {
+        self.finish()
+    }
+}
+
+/// Partitions clients across shards by a hash of the client id
+#[derive(Debug)]
+pub(crate) struct ShardRouter {
+    shards: usize,
+    /// Client of every deposit, withdrawal and authorization seen so far. Rows referencing a
+    /// transaction are routed to the shard of its owner so that validation matches the single
+    /// threaded engine.
+    owners: HashMap<TransactionId, ClientId>,
+}
+
+impl ShardRouter {
+    pub(crate) fn new(shards: usize) -> Self {
+        ShardRouter {
+            shards: shards.max(1),
+            owners: HashMap::new(),
+        }
+    }
+
+    /// Shard that must execute the transaction. Fails for transfers to a client of another
+    /// shard and merges into one, which no shard can apply atomically.
+    pub(crate) fn route(&mut self, transaction: &Transaction) -> Result<usize, EngineError> {
+        let routing_client = match transaction.transaction_type {
+            TransactionType::Lock
+            | TransactionType::Unlock
+            | TransactionType::Merge
+            | TransactionType::Close
+            | TransactionType::Reserve
+            | TransactionType::Fee
+            | TransactionType::Interest => transaction.client_id,
+            TransactionType::Deposit
+            | TransactionType::Withdrawal
+            | TransactionType::Transfer
+            | TransactionType::Authorize
+            | TransactionType::Adjustment => *self
+                .owners
+                .entry(transaction.transaction_id)
+                .or_insert(transaction.client_id),
+            _ => self
+                .owners
+                .get(&transaction.transaction_id)
+                .copied()
+                .unwrap_or(transaction.client_id),
+        };
+        let shard = self.shard_of(routing_client);
+        match transaction.to_client_id {
+            Some(to_client_id)
+                if matches!(
+                    transaction.transaction_type,
+                    TransactionType::Transfer | TransactionType::Merge
+                ) && self.shard_of(to_client_id) != shard =>
+            {
+                Err(EngineError::CrossShardTransfer(transaction.transaction_id))
+            }
+            _ => Ok(shard),
+        }
+    }
+
+    pub(crate) fn shard_of(&self, client_id: ClientId) -> usize {
+        let mut hasher = DefaultHasher::new();
+        client_id.hash(&mut hasher);
+        (hasher.finish() % self.shards as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::account::ClientId;
+        use crate::engine::{EngineConfig, TransactionEngine};
+        use crate::error::EngineError;
+        use crate::sharded::ShardedEngine;
+        use crate::transaction::{Amount, Transaction, TransactionId, TransactionType};
+
+        /// Mix of transactions over a few clients, including disputes from the wrong client and
+        /// reused transaction ids
+        fn input() -> Vec<Transaction> {
+            let types = [
+                TransactionType::Deposit,
+                TransactionType::Deposit,
+                TransactionType::Withdrawal,
+                TransactionType::Dispute,
+                TransactionType::Resolve,
+                TransactionType::Dispute,
+                TransactionType::Chargeback,
+            ];
+            (0..500u32)
+                .map(|i| {
+                    let transaction_type = types[i as usize % types.len()];
+                    let referencing = !matches!(
+                        transaction_type,
+                        TransactionType::Deposit | TransactionType::Withdrawal
+                    );
+                    Transaction {
+                        transaction_type,
+                        client_id: (i % 13) as ClientId,
+                        transaction_id: if referencing || i % 17 == 0 {
+                            TransactionId(i.saturating_sub(3))
+                        } else {
+                            TransactionId(i)
+                        },
+                        amount: (!referencing).then(|| Amount::unchecked(f64::from(i % 7 + 1))),
+                        to_client_id: None,
+                        currency: None,
+                        timestamp: None,
+                        metadata: Default::default(),
+                    }
+                })
+                .collect()
+        }
+
+        #[test]
+        fn sharded_engine_matches_single_threaded_engine() {
+            let mut single = TransactionEngine::new();
+            let single_errors: Vec<_> = input()
+                .into_iter()
+                .filter_map(|transaction| single.execute(transaction).err())
+                .collect();
+
+            let mut sharded = ShardedEngine::new(4, EngineConfig::default());
+            for transaction in input() {
+                sharded.submit(transaction);
+            }
+            let (merged, sharded_errors) = sharded.finish();
+
+            assert_eq!(sharded_errors, single_errors);
+            assert_eq!(merged.accounts.len(), single.accounts.len());
+            for (client_id, account) in &single.accounts {
+                let merged_account = &merged.accounts[client_id];
+                assert_eq!(
+                    merged_account.get_available_funds(),
+                    account.get_available_funds()
+                );
+                assert_eq!(merged_account.get_held_funds(), account.get_held_funds());
+                assert_eq!(merged_account.is_locked(), account.is_locked());
+            }
+        }
+
+        #[test]
+        fn transfers_across_shards_are_rejected() {
+            let deposit = |client_id: u8| Transaction {
+                transaction_type: TransactionType::Deposit,
+                client_id: ClientId::from(client_id),
+                transaction_id: TransactionId(u32::from(client_id)),
+                amount: Some(Amount::unchecked(1.0)),
+                to_client_id: None,
+                currency: None,
+                timestamp: None,
+                metadata: Default::default(),
+            };
+            let transfer = |transaction_id, to_client_id| Transaction {
+                transaction_type: TransactionType::Transfer,
+                transaction_id,
+                to_client_id: Some(to_client_id),
+                ..deposit(0)
+            };
+            let mut sharded = ShardedEngine::new(2, EngineConfig::default());
+            let (same, other): (Vec<ClientId>, Vec<ClientId>) = (1..10).partition(|client_id| {
+                sharded.router.shard_of(*client_id) == sharded.router.shard_of(0)
+            });
+            sharded.submit(deposit(0));
+            sharded.submit(transfer(TransactionId(10), same[0]));
+            sharded.submit(transfer(TransactionId(11), other[0]));
+
+            let (engine, errors) = sharded.finish();
+
+            assert_eq!(
+                errors,
+                vec![EngineError::CrossShardTransfer(TransactionId(11))]
+            );
+            assert_eq!(engine.accounts[&same[0]].get_available_funds(), 1.0);
+            assert!(!engine.accounts.contains_key(&other[0]));
+        }
+    }
+}
@@ -0,0 +1,1174 @@
+//! Versioned on-disk format of the engine state.
+//!
+//! A snapshot is a csv file without header where the first field of every record tags what it
+//! describes. The first record is always `snapshot,<version>`, followed by:
+//!
+//! ```text
+//! account,<client>,<available>,<held>,<locked>
+//! balance,<client>,<currency>,<available>,<held>                    (since version 5)
+//! log,<client>,<tx>,<amount>[,<currency>]
+//! dispute,<client>,<tx>,<amount>[,<currency>]
+//! seen,<tx>,<client>,<type>,<amount>,<applied>[,<to>[,<currency>[,<timestamp>]]]
+//! ledger,<client>,<sequence>,<type>,<tx>,<amount>[,<to>[,<currency>[,<timestamp>]]]
+//!                                                                   (since version 2)
+//! lsn,<lsn>                                                         (since version 3)
+//! usage,<client>,<transactions>                                     (since version 6)
+//! withdrawn,<client>,<amount>[,<currency>]                          (since version 6)
+//! retained,<client>,<tx>                                            (since version 7)
+//! cycles,<client>,<tx>,<state>,<disputes>                           (since version 8)
+//! withdrawn_on,<client>,<day>                                       (since version 9)
+//! pending,<client>,<tx>,<amount>,<ticks>[,<currency>]               (since version 10)
+//! offset,<rows>                                                     (since version 11)
+//! opened,<client>,<tx>,<lsn>[,<timestamp>]                          (since version 12)
+//! ingested,<source>,<offset>,<index>                                (since version 13)
+//! authorization,<client>,<tx>,<amount>[,<currency>]                 (since version 15)
+//! chargedback,<client>,<tx>,<amount>[,<currency>]                   (since version 16)
+//! closed,<client>                                                   (since version 17)
+//! active,<client>,<lsn>                                             (since version 17)
+//! books,<opening>,<deposited>,<withdrawn>,<charged_back>,<fees>,<interest>,<adjusted>,<reversed>
+//!       [,<currency>]                                               (since version 18)
+//! pending_hold,<client>,<tx>,<amount>[,<currency>]                  (since version 19)
+//! reserve,<client>,<amount>[,<currency>]                            (since version 20)
+//! ```
+//!
+//! The trailing `<to>` field is the credited client of a transfer (since version 4). Transfers
+//! are written once in the ledger of the debited client and restored in both ledgers.
+//!
+//! `account` records hold the funds without currency, left empty when the account has none
+//! (since version 5), and `balance` records the funds in each currency. Trailing `<currency>`
+//! fields are only written for transactions made in a currency, and trailing `<timestamp>` fields
+//! (since version 9) for transactions with a timestamp. `ledger` records of transactions with
+//! [metadata](crate::transaction::Transaction::metadata) end with every field and a name and value
+//! pair per entry in order of name (since version 14).
+//!
+//! `usage` and `withdrawn` records hold what clients used up of the configured limits, only
+//! written for the limits that are tracked, and `withdrawn_on` the day of their last timestamped
+//! withdrawal in days since the unix epoch. `retained` records list the transactions kept under
+//! a bounded retention policy in eviction order, oldest first. `cycles` records hold the dispute
+//! state of every transaction that was disputed; older snapshots only know the open disputes,
+//! which are restored as in their first cycle. `pending` records list the withdrawals of savings
+//! accounts that did not settle yet with the ticks left, in order of withdrawal. The `offset`
+//! record holds the rows of a batch input reflected in the state, only written by checkpoints of
+//! batch runs. `opened` records hold when the open disputes were opened, in order of opening;
+//! open disputes without one are tracked from the restored `lsn`, so that they expire like the
+//! disputes opened next. `ingested` records hold the high-water
+//! mark of every input executed at a position, see the [`ingest`](crate::ingest) module.
+//! `authorization` records hold the authorizations that were neither captured nor voided with the
+//! held amount, and `chargedback` records the chargebacks that were not represented with the
+//! amount they took. `closed` records list the closed accounts, and `active` records the `lsn`
+//! of the last transaction applied to every account; accounts without one count as last active
+//! at the restored `lsn`. `books` records hold the [flows](crate::books::Flows) of the funds of
+//! every currency; older snapshots open the books with the restored funds. `pending_hold` records
+//! list the disputed amounts waiting for funds to be held, in order of dispute. `reserve` records
+//! hold the reserves set for accounts, in place of those of their tier.
+//!
+//! Amounts are written with full precision so that restoring is lossless. Readers of a newer
+//! version must keep accepting every older version.
+
+use crate::account::{AccountState, Balance, ClientId};
+use crate::books::Flows;
+use crate::currency::Currency;
+use crate::engine::{EngineConfig, SeenTransaction, TransactionEngine};
+use crate::error::SnapshotError;
+use crate::expiry::OpenDispute;
+use crate::ingest::InputPosition;
+use crate::ledger::LedgerEntry;
+use crate::transaction::{Transaction, TransactionId};
+use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+pub const SNAPSHOT_VERSION: u32 = 20;
+
+/// Fields of a `ledger` record up to its timestamp, followed by its metadata
+const LEDGER_FIELDS: usize = 9;
+
+/// Replaces the snapshot at `path` with a new one written next to it, so that a crash leaves
+/// either the old or the new snapshot
+pub(crate) fn write_atomically(
+    engine: &TransactionEngine,
+    path: &Path,
+) -> Result<(), SnapshotError> {
+    let partial = path.with_extension("partial");
+    let mut file = File::create(&partial)?;
+    write_snapshot(engine, BufWriter::new(&mut file))?;
+    #[cfg(feature = "chaos")]
+    crate::chaos::hit(crate::chaos::FailPoint::MidSnapshot);
+    file.sync_all()?;
+    fs::rename(&partial, path)?;
+    Ok(())
+}
+
+pub(crate) fn write_snapshot<W: Write>(
+    engine: &TransactionEngine,
+    writer: W,
+) -> Result<(), SnapshotError> {
+    let mut writer = WriterBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_writer(writer);
+    writer.write_record(["snapshot", &SNAPSHOT_VERSION.to_string()])?;
+    writer.write_record(["lsn", &engine.lsn.to_string()])?;
+    if engine.input_offset > 0 {
+        writer.write_record(["offset", &engine.input_offset.to_string()])?;
+    }
+    for position in engine.ingested.iter() {
+        writer.write_record([
+            "ingested",
+            &position.source,
+            &position.offset.to_string(),
+            &position.index.to_string(),
+        ])?;
+    }
+
+    // Sorted so that snapshots of the same state are identical
+    let accounts: BTreeMap<_, _> = engine.accounts.iter().collect();
+    for (client_id, account) in accounts {
+        let state = account.state()?;
+        let client = client_id.to_string();
+        let (available, held) = state
+            .balances
+            .iter()
+            .find(|(currency, _)| currency.is_none())
+            .map_or((String::new(), String::new()), |(_, balance)| {
+                (balance.available.to_string(), balance.held.to_string())
+            });
+        writer.write_record([
+            "account",
+            &client,
+            &available,
+            &held,
+            &state.locked.to_string(),
+        ])?;
+        for (currency, balance) in &state.balances {
+            if let Some(currency) = currency {
+                writer.write_record([
+                    "balance",
+                    &client,
+                    currency.as_str(),
+                    &balance.available.to_string(),
+                    &balance.held.to_string(),
+                ])?;
+            }
+        }
+        for (tag, entries) in [
+            ("log", &state.transaction_log),
+            ("dispute", &state.active_disputes),
+            ("authorization", &state.authorizations),
+            ("chargedback", &state.chargebacks),
+            ("pending_hold", &state.pending_holds),
+        ] {
+            for (transaction_id, currency, amount) in entries {
+                writer.write_record(with_optional_fields(
+                    vec![
+                        tag.to_string(),
+                        client.clone(),
+                        transaction_id.to_string(),
+                        amount.to_string(),
+                    ],
+                    [currency.map(|currency| currency.to_string())],
+                ))?;
+            }
+        }
+        for (transaction_id, dispute_state, cycles) in &state.dispute_states {
+            writer.write_record([
+                "cycles",
+                &client,
+                &transaction_id.to_string(),
+                dispute_state.as_str(),
+                &cycles.to_string(),
+            ])?;
+        }
+        for (transaction_id, currency, amount, ticks) in &state.pending_withdrawals {
+            writer.write_record(with_optional_fields(
+                vec![
+                    "pending".to_string(),
+                    client.clone(),
+                    transaction_id.to_string(),
+                    amount.to_string(),
+                    ticks.to_string(),
+                ],
+                [currency.map(|currency| currency.to_string())],
+            ))?;
+        }
+    }
+
+    let seen: BTreeMap<_, _> = engine.seen_transactions.iter().collect();
+    for (transaction_id, seen) in seen {
+        writer.write_record(with_optional_fields(
+            vec![
+                "seen".to_string(),
+                transaction_id.to_string(),
+                seen.client_id.to_string(),
+                seen.transaction_type.to_string(),
+                seen.amount.to_string(),
+                seen.applied.to_string(),
+            ],
+            [
+                seen.to_client_id.map(|client_id| client_id.to_string()),
+                seen.currency.map(|currency| currency.to_string()),
+                seen.timestamp.map(|timestamp| timestamp.to_string()),
+            ],
+        ))?;
+    }
+
+    let usage: BTreeMap<_, _> = engine.usage.iter().collect();
+    for (client_id, usage) in usage {
+        let client = client_id.to_string();
+        if usage.transactions > 0 {
+            writer.write_record(["usage", &client, &usage.transactions.to_string()])?;
+        }
+        for (currency, amount) in &usage.withdrawn {
+            writer.write_record(with_optional_fields(
+                vec!["withdrawn".to_string(), client.clone(), amount.to_string()],
+                [currency.map(|currency| currency.to_string())],
+            ))?;
+        }
+        if let Some(day) = usage.day {
+            writer.write_record(["withdrawn_on", &client, &day.to_string()])?;
+        }
+    }
+
+    let closed: BTreeSet<_> = engine.closed.iter().collect();
+    for client_id in closed {
+        writer.write_record(["closed", &client_id.to_string()])?;
+    }
+    let reserves: BTreeMap<_, _> = engine.reserves.iter().collect();
+    for ((client_id, currency), reserve) in reserves {
+        writer.write_record(with_optional_fields(
+            vec![
+                "reserve".to_string(),
+                client_id.to_string(),
+                reserve.to_string(),
+            ],
+            [currency.map(|currency| currency.to_string())],
+        ))?;
+    }
+    let last_active: BTreeMap<_, _> = engine.last_active.iter().collect();
+    for (client_id, lsn) in last_active {
+        writer.write_record(["active", &client_id.to_string(), &lsn.to_string()])?;
+    }
+
+    for (client_id, transaction_id) in engine.retention.retained() {
+        writer.write_record([
+            "retained",
+            &client_id.to_string(),
+            &transaction_id.to_string(),
+        ])?;
+    }
+
+    for (transaction_id, dispute) in engine.expiry.tracked() {
+        writer.write_record(with_optional_fields(
+            vec![
+                "opened".to_string(),
+                dispute.client_id.to_string(),
+                transaction_id.to_string(),
+                dispute.opened.to_string(),
+            ],
+            [dispute.timestamp.map(|timestamp| timestamp.to_string())],
+        ))?;
+    }
+
+    for (currency, flows) in engine.books.currencies() {
+        writer.write_record(with_optional_fields(
+            vec![
+                "books".to_string(),
+                flows.opening.to_string(),
+                flows.deposited.to_string(),
+                flows.withdrawn.to_string(),
+                flows.charged_back.to_string(),
+                flows.fees.to_string(),
+                flows.interest.to_string(),
+                flows.adjusted.to_string(),
+                flows.reversed.to_string(),
+            ],
+            [currency.map(|currency| currency.to_string())],
+        ))?;
+    }
+
+    let mut clients: Vec<_> = engine.ledger.clients().collect();
+    clients.sort_unstable();
+    for client_id in clients {
+        for entry in engine.ledger.history(client_id) {
+            let transaction = &entry.transaction;
+            // Credited leg of a transfer, restored from the debited one
+            if transaction.client_id != client_id {
+                continue;
+            }
+            let mut record = with_optional_fields(
+                vec![
+                    "ledger".to_string(),
+                    client_id.to_string(),
+                    entry.sequence.to_string(),
+                    transaction.transaction_type.to_string(),
+                    transaction.transaction_id.to_string(),
+                    transaction
+                        .amount
+                        .map_or(String::new(), |amount| amount.to_string()),
+                ],
+                [
+                    transaction
+                        .to_client_id
+                        .map(|client_id| client_id.to_string()),
+                    transaction.currency.map(|currency| currency.to_string()),
+                    transaction.timestamp.map(|timestamp| timestamp.to_string()),
+                ],
+            );
+            if !transaction.metadata.is_empty() {
+                record.resize(LEDGER_FIELDS, String::new());
+                for (name, value) in transaction.sorted_metadata() {
+                    record.extend([name.to_string(), value.to_string()]);
+                }
+            }
+            writer.write_record(record)?;
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Appends the optional fields, leaving out the trailing ones that are not set
+fn with_optional_fields<const N: usize>(
+    mut record: Vec<String>,
+    optional: [Option<String>; N],
+) -> Vec<String> {
+    let set = optional
+        .iter()
+        .rposition(Option::is_some)
+        .map_or(0, |last| last + 1);
+    record.extend(
+        optional
+            .into_iter()
+            .take(set)
+            .map(Option::unwrap_or_default),
+    );
+    record
+}
+
+pub(crate) fn read_snapshot<R: Read>(
+    reader: R,
+    config: EngineConfig,
+) -> Result<TransactionEngine, SnapshotError> {
+    let mut reader = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(reader);
+    let mut records = reader.records();
+
+    let header = records.next().ok_or(SnapshotError::Malformed {
+        line: 1,
+        message: "empty snapshot".to_string(),
+    })??;
+    if header.get(0) != Some("snapshot") {
+        return Err(SnapshotError::Malformed {
+            line: 1,
+            message: "missing snapshot header".to_string(),
+        });
+    }
+    let version: u32 = field(&header, 1)?;
+
+    match version {
+        // Later versions only added record types, so all are read the same way
+        1..=20 => read_v1(records, config),
+        _ => Err(SnapshotError::UnsupportedVersion(version)),
+    }
+}
+
+fn read_v1<I>(records: I, config: EngineConfig) -> Result<TransactionEngine, SnapshotError>
+where
+    I: Iterator<Item = csv::Result<StringRecord>>,
+{
+    let mut states: BTreeMap<ClientId, AccountState> = BTreeMap::new();
+    let mut engine = TransactionEngine::with_config(config);
+
+    for record in records {
+        let record = record?;
+        match record.get(0) {
+            Some("account") => {
+                let client_id = field(&record, 1)?;
+                let available: Option<f64> = optional_field(&record, 2)?;
+                let held: Option<f64> = optional_field(&record, 3)?;
+                let balances = match (available, held) {
+                    (Some(available), Some(held)) => vec![(None, Balance { available, held })],
+                    _ => Vec::new(),
+                };
+                states.insert(
+                    client_id,
+                    AccountState {
+                        client_id,
+                        balances,
+                        locked: field(&record, 4)?,
+                        transaction_log: Vec::new(),
+                        active_disputes: Vec::new(),
+                        dispute_states: Vec::new(),
+                        pending_withdrawals: Vec::new(),
+                        authorizations: Vec::new(),
+                        chargebacks: Vec::new(),
+                        pending_holds: Vec::new(),
+                    },
+                );
+            }
+            Some("balance") => {
+                let client_id: ClientId = field(&record, 1)?;
+                let balance = (
+                    Some(field(&record, 2)?),
+                    Balance {
+                        available: field(&record, 3)?,
+                        held: field(&record, 4)?,
+                    },
+                );
+                states
+                    .get_mut(&client_id)
+                    .ok_or_else(|| malformed(&record, "entry for unknown account".to_string()))?
+                    .balances
+                    .push(balance);
+            }
+            Some(tag @ ("log" | "dispute" | "authorization" | "chargedback" | "pending_hold")) => {
+                let client_id: ClientId = field(&record, 1)?;
+                let entry: (TransactionId, Option<Currency>, f64) = (
+                    field(&record, 2)?,
+                    optional_field(&record, 4)?,
+                    field(&record, 3)?,
+                );
+                let state = states
+                    .get_mut(&client_id)
+                    .ok_or_else(|| malformed(&record, "entry for unknown account".to_string()))?;
+                match tag {
+                    "log" => state.transaction_log.push(entry),
+                    "dispute" => state.active_disputes.push(entry),
+                    "authorization" => state.authorizations.push(entry),
+                    "chargedback" => state.chargebacks.push(entry),
+                    _ => state.pending_holds.push(entry),
+                }
+            }
+            Some("cycles") => {
+                let client_id: ClientId = field(&record, 1)?;
+                let entry = (field(&record, 2)?, field(&record, 3)?, field(&record, 4)?);
+                states
+                    .get_mut(&client_id)
+                    .ok_or_else(|| malformed(&record, "entry for unknown account".to_string()))?
+                    .dispute_states
+                    .push(entry);
+            }
+            Some("pending") => {
+                let client_id: ClientId = field(&record, 1)?;
+                let entry = (
+                    field(&record, 2)?,
+                    optional_field(&record, 5)?,
+                    field(&record, 3)?,
+                    field(&record, 4)?,
+                );
+                states
+                    .get_mut(&client_id)
+                    .ok_or_else(|| malformed(&record, "entry for unknown account".to_string()))?
+                    .pending_withdrawals
+                    .push(entry);
+            }
+            Some("seen") => {
+                engine.seen_transactions.insert(
+                    field(&record, 1)?,
+                    SeenTransaction {
+                        client_id: field(&record, 2)?,
+                        transaction_type: field(&record, 3)?,
+                        amount: field(&record, 4)?,
+                        to_client_id: optional_field(&record, 6)?,
+                        currency: optional_field(&record, 7)?,
+                        timestamp: optional_field(&record, 8)?,
+                        applied: field(&record, 5)?,
+                    },
+                );
+            }
+            Some("lsn") => engine.lsn = field(&record, 1)?,
+            Some("offset") => engine.input_offset = field(&record, 1)?,
+            Some("ingested") => engine.ingested.advance(&InputPosition {
+                source: field(&record, 1)?,
+                offset: field(&record, 2)?,
+                index: field(&record, 3)?,
+            }),
+            Some("usage") => {
+                let client_id: ClientId = field(&record, 1)?;
+                engine.usage.entry(client_id).or_default().transactions = field(&record, 2)?;
+            }
+            Some("retained") => engine
+                .retention
+                .restore(field(&record, 1)?, field(&record, 2)?),
+            Some("withdrawn") => {
+                let client_id: ClientId = field(&record, 1)?;
+                engine
+                    .usage
+                    .entry(client_id)
+                    .or_default()
+                    .withdrawn
+                    .insert(optional_field(&record, 3)?, field(&record, 2)?);
+            }
+            Some("withdrawn_on") => {
+                let client_id: ClientId = field(&record, 1)?;
+                engine.usage.entry(client_id).or_default().day = Some(field(&record, 2)?);
+            }
+            Some("closed") => {
+                engine.closed.insert(field(&record, 1)?);
+            }
+            Some("reserve") => {
+                engine.reserves.insert(
+                    (field(&record, 1)?, optional_field(&record, 3)?),
+                    field(&record, 2)?,
+                );
+            }
+            Some("books") => {
+                *engine.books.entry(optional_field(&record, 9)?) = Flows {
+                    opening: field(&record, 1)?,
+                    deposited: field(&record, 2)?,
+                    withdrawn: field(&record, 3)?,
+                    charged_back: field(&record, 4)?,
+                    fees: field(&record, 5)?,
+                    interest: field(&record, 6)?,
+                    adjusted: field(&record, 7)?,
+                    reversed: field(&record, 8)?,
+                }
+            }
+            Some("active") => {
+                engine
+                    .last_active
+                    .insert(field(&record, 1)?, field(&record, 2)?);
+            }
+            Some("opened") => engine.expiry.open(
+                field(&record, 2)?,
+                OpenDispute {
+                    client_id: field(&record, 1)?,
+                    opened: field(&record, 3)?,
+                    timestamp: optional_field(&record, 4)?,
+                },
+            ),
+            Some("ledger") => {
+                let metadata = record.iter().skip(LEDGER_FIELDS).collect::<Vec<_>>();
+                if metadata.len() % 2 != 0 {
+                    return Err(malformed(&record, "metadata without a value".to_string()));
+                }
+                engine.ledger.restore_entry(LedgerEntry {
+                    sequence: field(&record, 2)?,
+                    transaction: Transaction {
+                        transaction_type: field(&record, 3)?,
+                        client_id: field(&record, 1)?,
+                        transaction_id: field(&record, 4)?,
+                        amount: optional_field(&record, 5)?,
+                        to_client_id: optional_field(&record, 6)?,
+                        currency: optional_field(&record, 7)?,
+                        timestamp: optional_field(&record, 8)?,
+                        metadata: metadata
+                            .chunks(2)
+                            .map(|pair| (pair[0].to_string(), pair[1].to_string()))
+                            .collect(),
+                    },
+                });
+            }
+            other => {
+                return Err(malformed(
+                    &record,
+                    format!("unknown record type {:?}", other.unwrap_or_default()),
+                ))
+            }
+        }
+    }
+
+    restore_accounts(&mut engine, states)?;
+    Ok(engine)
+}
+
+/// Creates the accounts of the restored states, once every other part of the engine state is
+/// restored, and opens the books of the currencies they do not know yet with their funds
+pub(crate) fn restore_accounts(
+    engine: &mut TransactionEngine,
+    states: BTreeMap<ClientId, AccountState>,
+) -> Result<(), SnapshotError> {
+    for (client_id, state) in states {
+        for (transaction_id, _, _) in &state.active_disputes {
+            if !engine.expiry.is_tracked(*transaction_id) {
+                let opened = engine.lsn;
+                engine.expiry.open(
+                    *transaction_id,
+                    OpenDispute {
+                        client_id,
+                        opened,
+                        timestamp: None,
+                    },
+                );
+            }
+        }
+        let lsn = engine.lsn;
+        engine.last_active.entry(client_id).or_insert(lsn);
+        let store = engine.config().storage.open(client_id);
+        let policies = engine.config().account_policies_for(client_id);
+        let account = engine
+            .config()
+            .account_factory()
+            .restore(state, policies, store)?;
+        engine.accounts.insert(client_id, account);
+    }
+    let accounts: Vec<_> = engine.accounts.values().map(AsRef::as_ref).collect();
+    engine.books.open(accounts);
+    Ok(())
+}
+
+fn field<T: FromStr>(record: &StringRecord, index: usize) -> Result<T, SnapshotError> {
+    let value = record
+        .get(index)
+        .ok_or_else(|| malformed(record, format!("missing field {}", index)))?;
+    value
+        .parse()
+        .map_err(|_| malformed(record, format!("invalid value '{}'", value)))
+}
+
+/// Missing and empty fields are `None`
+fn optional_field<T: FromStr>(
+    record: &StringRecord,
+    index: usize,
+) -> Result<Option<T>, SnapshotError> {
+    match record.get(index) {
+        None | Some("") => Ok(None),
+        Some(_) => field(record, index).map(Some),
+    }
+}
+
+fn malformed(record: &StringRecord, message: String) -> SnapshotError {
+    SnapshotError::Malformed {
+        line: record.position().map_or(0, |position| position.line()),
+        message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::account::{AccountTypes, DisputeState};
+        use crate::engine::{EngineConfig, TransactionEngine};
+        use crate::error::{EngineError, Limit, SnapshotError};
+        use crate::ingest::InputPosition;
+        use crate::policy::{
+            AccountKind, DisputeCycles, DisputeExpiry, LimitsPolicy, NegativeBalancePolicy,
+            RetentionPolicy, SavingsPolicy,
+        };
+        use crate::snapshot::{read_snapshot, write_snapshot};
+        use crate::transaction::{transaction, Transaction, TransactionId, TransactionType};
+        use std::sync::Arc;
+
+        fn snapshot_bytes(engine: &TransactionEngine) -> Vec<u8> {
+            let mut buffer = Vec::new();
+            write_snapshot(engine, &mut buffer).unwrap();
+            buffer
+        }
+
+        #[test]
+        fn restored_engine_continues_where_snapshot_left_off() {
+            let mut engine = TransactionEngine::new();
+            for transaction in [
+                transaction(TransactionType::Deposit, 1, 1, Some(0.1)),
+                transaction(TransactionType::Deposit, 1, 2, Some(0.2)),
+                transaction(TransactionType::Dispute, 1, 1, None),
+                transaction(TransactionType::Deposit, 2, 3, Some(5.0)),
+                transaction(TransactionType::Dispute, 2, 3, None),
+                transaction(TransactionType::Chargeback, 2, 3, None),
+            ] {
+                engine.execute(transaction).unwrap();
+            }
+
+            let bytes = snapshot_bytes(&engine);
+            let mut restored = read_snapshot(bytes.as_slice(), EngineConfig::default()).unwrap();
+
+            assert_eq!(snapshot_bytes(&restored), bytes);
+            assert_eq!(
+                restored.accounts[&1].state().unwrap(),
+                engine.accounts[&1].state().unwrap()
+            );
+            assert!(restored.accounts[&2].is_locked());
+            // Open dispute, disputable log and seen transaction ids survive the restore
+            restored
+                .execute(transaction(TransactionType::Resolve, 1, 1, None))
+                .unwrap();
+            restored
+                .execute(transaction(TransactionType::Dispute, 1, 2, None))
+                .unwrap();
+            assert_eq!(
+                restored.execute(transaction(TransactionType::Deposit, 1, 3, Some(1.0))),
+                Err(EngineError::DuplicateTransaction(TransactionId(3)))
+            );
+        }
+
+        #[test]
+        fn unsupported_version_is_rejected() {
+            let result = read_snapshot("snapshot,99\n".as_bytes(), EngineConfig::default());
+
+            assert!(matches!(result, Err(SnapshotError::UnsupportedVersion(99))));
+        }
+
+        #[test]
+        fn malformed_record_reports_line() {
+            let input = "snapshot,1\naccount,1,1.0,0.0,false\nlog,1,abc,1.0\n";
+
+            let result = read_snapshot(input.as_bytes(), EngineConfig::default());
+
+            assert!(matches!(
+                result,
+                Err(SnapshotError::Malformed { line: 3, .. })
+            ));
+        }
+
+        #[test]
+        fn history_survives_restore() {
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                record_history: true,
+                ..EngineConfig::default()
+            });
+            let mut deposit = transaction(TransactionType::Deposit, 1, 1, Some(2.0));
+            deposit.metadata.extend([
+                ("merchant".to_string(), "ACME, Inc.".to_string()),
+                ("memo".to_string(), String::new()),
+            ]);
+            engine.execute(deposit).unwrap();
+            engine
+                .execute(transaction(TransactionType::Dispute, 1, 1, None))
+                .unwrap();
+
+            let restored =
+                read_snapshot(snapshot_bytes(&engine).as_slice(), engine.config().clone()).unwrap();
+
+            assert_eq!(
+                restored.history(1).collect::<Vec<_>>(),
+                engine.history(1).collect::<Vec<_>>()
+            );
+        }
+
+        #[test]
+        fn version_1_snapshot_is_still_readable() {
+            let input =
+                "snapshot,1\naccount,1,1.5,0,false\nlog,1,1,1.5\nseen,1,1,deposit,1.5,true\n";
+
+            let engine = read_snapshot(input.as_bytes(), EngineConfig::default()).unwrap();
+
+            assert_eq!(engine.accounts[&1].get_available_funds(), 1.5);
+            assert_eq!(engine.transaction_owner(TransactionId(1)), Some(1));
+        }
+
+        #[test]
+        fn transfers_survive_restore() {
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                record_history: true,
+                ..EngineConfig::default()
+            });
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(2.0)))
+                .unwrap();
+            engine
+                .execute(Transaction {
+                    to_client_id: Some(2),
+                    ..transaction(TransactionType::Transfer, 1, 2, Some(0.5))
+                })
+                .unwrap();
+
+            let bytes = snapshot_bytes(&engine);
+            let mut restored = read_snapshot(bytes.as_slice(), engine.config().clone()).unwrap();
+
+            assert_eq!(snapshot_bytes(&restored), bytes);
+            assert_eq!(restored.history(2).count(), 1);
+            // The credited client can still dispute its leg
+            restored
+                .execute(transaction(TransactionType::Dispute, 2, 2, None))
+                .unwrap();
+            assert_eq!(restored.accounts[&2].get_held_funds(), 0.5);
+        }
+
+        #[test]
+        fn currencies_survive_restore() {
+            let eur = "EUR".parse().unwrap();
+            let mut engine = TransactionEngine::new();
+            for transaction in [
+                transaction(TransactionType::Deposit, 1, 1, Some(2.0)),
+                transaction(TransactionType::Deposit, 1, 2, Some(3.0)),
+                transaction(TransactionType::Deposit, 2, 3, Some(1.0)),
+                transaction(TransactionType::Dispute, 1, 2, None),
+            ]
+            .into_iter()
+            .enumerate()
+            .map(|(i, transaction)| Transaction {
+                currency: (i != 0).then_some(eur),
+                ..transaction
+            }) {
+                engine.execute(transaction).unwrap();
+            }
+
+            let bytes = snapshot_bytes(&engine);
+            let restored = read_snapshot(bytes.as_slice(), EngineConfig::default()).unwrap();
+
+            assert_eq!(snapshot_bytes(&restored), bytes);
+            assert_eq!(
+                restored.accounts[&1].state().unwrap(),
+                engine.accounts[&1].state().unwrap()
+            );
+            assert_eq!(restored.accounts[&2].currencies(), vec![Some(eur)]);
+            assert_eq!(
+                restored.seen_transactions[&TransactionId(2)].currency,
+                Some(eur)
+            );
+        }
+
+        #[test]
+        fn limit_usage_survives_restore() {
+            let config = EngineConfig {
+                limits: LimitsPolicy {
+                    daily_withdrawal_cap: Some(3.0),
+                    max_transactions_per_client: Some(3),
+                    ..LimitsPolicy::default()
+                },
+                ..EngineConfig::default()
+            };
+            let mut engine = TransactionEngine::with_config(config.clone());
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(5.0)))
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Withdrawal, 1, 2, Some(2.0)))
+                .unwrap();
+
+            let bytes = snapshot_bytes(&engine);
+            let mut restored = read_snapshot(bytes.as_slice(), config).unwrap();
+
+            assert_eq!(snapshot_bytes(&restored), bytes);
+            assert_eq!(
+                restored.execute(transaction(TransactionType::Withdrawal, 1, 3, Some(1.5))),
+                Err(EngineError::LimitExceeded {
+                    client_id: 1,
+                    transaction_id: TransactionId(3),
+                    limit: Limit::DailyWithdrawals(3.0)
+                })
+            );
+            restored
+                .execute(transaction(TransactionType::Withdrawal, 1, 4, Some(1.0)))
+                .unwrap();
+            assert_eq!(
+                restored
+                    .execute(transaction(TransactionType::Deposit, 1, 5, Some(1.0)))
+                    .unwrap_err()
+                    .code(),
+                "transaction_count_limit"
+            );
+        }
+
+        #[test]
+        fn timestamps_survive_restore() {
+            let config = EngineConfig {
+                dispute_window: Some(30),
+                record_history: true,
+                limits: LimitsPolicy {
+                    daily_withdrawal_cap: Some(3.0),
+                    ..LimitsPolicy::default()
+                },
+                ..EngineConfig::default()
+            };
+            let at = |transaction: Transaction, timestamp: &str| Transaction {
+                timestamp: timestamp.parse().ok(),
+                metadata: Default::default(),
+                ..transaction
+            };
+            let mut engine = TransactionEngine::with_config(config.clone());
+            for transaction in [
+                at(
+                    transaction(TransactionType::Deposit, 1, 1, Some(5.0)),
+                    "2024-03-01T09:00:00Z",
+                ),
+                at(
+                    transaction(TransactionType::Withdrawal, 1, 2, Some(2.0)),
+                    "2024-03-01T10:00:00Z",
+                ),
+            ] {
+                engine.execute(transaction).unwrap();
+            }
+
+            let bytes = snapshot_bytes(&engine);
+            let mut restored = read_snapshot(bytes.as_slice(), config).unwrap();
+
+            assert_eq!(snapshot_bytes(&restored), bytes);
+            assert_eq!(
+                restored.history(1).next().unwrap().transaction.timestamp,
+                "2024-03-01T09:00:00Z".parse().ok()
+            );
+            // The cap starts over on the next day, the deposit is past the window by then
+            restored
+                .execute(at(
+                    transaction(TransactionType::Withdrawal, 1, 3, Some(2.5)),
+                    "2024-03-02T10:00:00Z",
+                ))
+                .unwrap();
+            assert_eq!(
+                restored
+                    .execute(at(
+                        transaction(TransactionType::Dispute, 1, 1, None),
+                        "2024-04-01T10:00:00Z"
+                    ))
+                    .unwrap_err()
+                    .code(),
+                "dispute_window_expired"
+            );
+        }
+
+        #[test]
+        fn retention_order_survives_restore() {
+            let config = EngineConfig {
+                retention_policy: RetentionPolicy::Global(2),
+                ..EngineConfig::default()
+            };
+            let mut engine = TransactionEngine::with_config(config.clone());
+            for (client_id, transaction_id) in [(1, 1), (2, 2)] {
+                engine
+                    .execute(transaction(
+                        TransactionType::Deposit,
+                        client_id,
+                        transaction_id,
+                        Some(1.0),
+                    ))
+                    .unwrap();
+            }
+
+            let bytes = snapshot_bytes(&engine);
+            let mut restored = read_snapshot(bytes.as_slice(), config).unwrap();
+            restored
+                .execute(transaction(TransactionType::Deposit, 1, 3, Some(1.0)))
+                .unwrap();
+
+            assert!(String::from_utf8_lossy(&bytes).contains("retained,1,1\nretained,2,2\n"));
+            assert_eq!(
+                restored.execute(transaction(TransactionType::Dispute, 1, 1, None)),
+                Err(EngineError::UnknownTransaction(TransactionId(1)))
+            );
+            restored
+                .execute(transaction(TransactionType::Dispute, 2, 2, None))
+                .unwrap();
+        }
+
+        #[test]
+        fn dispute_cycles_survive_restore() {
+            let config = EngineConfig {
+                max_dispute_cycles: DisputeCycles(2),
+                ..EngineConfig::default()
+            };
+            let mut engine = TransactionEngine::with_config(config.clone());
+            for transaction in [
+                transaction(TransactionType::Deposit, 1, 1, Some(1.0)),
+                transaction(TransactionType::Dispute, 1, 1, None),
+                transaction(TransactionType::Resolve, 1, 1, None),
+            ] {
+                engine.execute(transaction).unwrap();
+            }
+
+            let bytes = snapshot_bytes(&engine);
+            let mut restored = read_snapshot(bytes.as_slice(), config).unwrap();
+
+            assert!(String::from_utf8_lossy(&bytes).contains("cycles,1,1,resolved,1\n"));
+            for transaction_type in [TransactionType::Dispute, TransactionType::Resolve] {
+                restored
+                    .execute(transaction(transaction_type, 1, 1, None))
+                    .unwrap();
+            }
+            assert_eq!(
+                restored.accounts[&1].dispute_state(TransactionId(1)),
+                (DisputeState::Resolved, 2)
+            );
+            assert!(restored
+                .execute(transaction(TransactionType::Dispute, 1, 1, None))
+                .is_err());
+        }
+
+        #[test]
+        fn pending_withdrawals_survive_restore() {
+            let accounts = AccountTypes {
+                default: AccountKind::Savings,
+                savings: SavingsPolicy {
+                    withdrawal_delay: 2,
+                },
+                ..AccountTypes::default()
+            };
+            let config = EngineConfig {
+                account_factory: Some(Arc::new(accounts)),
+                ..EngineConfig::default()
+            };
+            let mut engine = TransactionEngine::with_config(config.clone());
+            for transaction in [
+                transaction(TransactionType::Deposit, 1, 1, Some(5.0)),
+                transaction(TransactionType::Withdrawal, 1, 2, Some(2.0)),
+            ] {
+                engine.execute(transaction).unwrap();
+            }
+            engine.advance(1);
+
+            let bytes = snapshot_bytes(&engine);
+            let mut restored = read_snapshot(bytes.as_slice(), config).unwrap();
+
+            assert!(String::from_utf8_lossy(&bytes).contains("pending,1,2,2,1\n"));
+            assert_eq!(restored.accounts[&1].get_held_funds(), 2.0);
+            restored.advance(1);
+            assert_eq!(restored.accounts[&1].get_held_funds(), 0.0);
+            assert_eq!(restored.accounts[&1].get_total_funds(), 3.0);
+        }
+
+        #[test]
+        fn pending_holds_survive_restore() {
+            let config = EngineConfig {
+                negative_balance_policy: NegativeBalancePolicy::HoldPending,
+                ..EngineConfig::default()
+            };
+            let mut engine = TransactionEngine::with_config(config.clone());
+            for transaction in [
+                transaction(TransactionType::Deposit, 1, 1, Some(5.0)),
+                transaction(TransactionType::Withdrawal, 1, 2, Some(3.0)),
+                transaction(TransactionType::Dispute, 1, 1, None),
+            ] {
+                engine.execute(transaction).unwrap();
+            }
+
+            let bytes = snapshot_bytes(&engine);
+            let mut restored = read_snapshot(bytes.as_slice(), config).unwrap();
+
+            assert!(String::from_utf8_lossy(&bytes).contains("pending_hold,1,1,3\n"));
+            restored
+                .execute(transaction(TransactionType::Deposit, 1, 3, Some(4.0)))
+                .unwrap();
+            assert_eq!(restored.accounts[&1].get_held_funds(), 5.0);
+            assert_eq!(restored.accounts[&1].get_available_funds(), 1.0);
+        }
+
+        #[test]
+        fn open_authorizations_survive_restore() {
+            let mut engine = TransactionEngine::new();
+            for transaction in [
+                transaction(TransactionType::Deposit, 1, 1, Some(5.0)),
+                transaction(TransactionType::Authorize, 1, 2, Some(3.0)),
+            ] {
+                engine.execute(transaction).unwrap();
+            }
+
+            let bytes = snapshot_bytes(&engine);
+            let mut restored = read_snapshot(bytes.as_slice(), EngineConfig::default()).unwrap();
+
+            assert!(String::from_utf8_lossy(&bytes).contains("authorization,1,2,3\n"));
+            assert_eq!(restored.accounts[&1].get_held_funds(), 3.0);
+            restored
+                .execute(transaction(TransactionType::Capture, 1, 2, Some(1.0)))
+                .unwrap();
+            assert_eq!(restored.accounts[&1].get_held_funds(), 0.0);
+            assert_eq!(restored.accounts[&1].get_total_funds(), 4.0);
+        }
+
+        #[test]
+        fn closed_and_dormant_accounts_survive_restore() {
+            let mut engine = TransactionEngine::new();
+            for transaction in [
+                transaction(TransactionType::Deposit, 1, 1, Some(1.0)),
+                transaction(TransactionType::Deposit, 2, 2, Some(1.0)),
+                transaction(TransactionType::Deposit, 3, 3, Some(1.0)),
+            ] {
+                engine.execute(transaction).unwrap();
+            }
+            engine.close_account(3).unwrap();
+
+            let text = String::from_utf8(snapshot_bytes(&engine)).unwrap();
+            let restored = read_snapshot(text.as_bytes(), EngineConfig::default()).unwrap();
+
+            assert!(text.contains("\nclosed,3\nactive,1,1\nactive,2,2\nactive,3,4\n"));
+            assert!(restored.is_closed(3));
+            assert_eq!(restored.dormant_accounts(2), [1, 2]);
+
+            // Accounts of older snapshots count as active at the restored lsn
+            let older: String = text
+                .lines()
+                .filter(|line| !line.starts_with("active,"))
+                .map(|line| format!("{}\n", line))
+                .collect();
+            let restored = read_snapshot(older.as_bytes(), EngineConfig::default()).unwrap();
+            assert!(restored.dormant_accounts(1).is_empty());
+        }
+
+        #[test]
+        fn input_offset_survives_restore() {
+            let mut engine = TransactionEngine::new();
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(5.0)))
+                .unwrap();
+            assert!(!String::from_utf8_lossy(&snapshot_bytes(&engine)).contains("offset"));
+
+            engine.input_offset = 3;
+            let bytes = snapshot_bytes(&engine);
+            let restored = read_snapshot(bytes.as_slice(), EngineConfig::default()).unwrap();
+
+            assert!(String::from_utf8_lossy(&bytes).contains("\noffset,3\n"));
+            assert_eq!(restored.input_offset(), 3);
+        }
+
+        #[test]
+        fn high_water_marks_survive_restore() {
+            let mut engine = TransactionEngine::new();
+            let deposit = transaction(TransactionType::Deposit, 1, 1, Some(5.0));
+            let position = InputPosition::new("payments", 4, 0);
+            engine.execute_at(deposit.clone(), position.clone());
+
+            let bytes = snapshot_bytes(&engine);
+            let mut restored = read_snapshot(bytes.as_slice(), EngineConfig::default()).unwrap();
+
+            assert!(String::from_utf8_lossy(&bytes).contains("\ningested,payments,4,0\n"));
+            assert_eq!(restored.high_water_mark("payments"), Some((4, 0)));
+            assert_eq!(restored.execute_at(deposit, position), None);
+        }
+
+        #[test]
+        fn open_disputes_keep_expiring_after_restore() {
+            let config = || EngineConfig {
+                dispute_expiry: DisputeExpiry {
+                    after_transactions: Some(2),
+                    after_days: None,
+                },
+                ..EngineConfig::default()
+            };
+            let mut engine = TransactionEngine::new();
+            for transaction_id in 1..=2 {
+                engine
+                    .execute(transaction(
+                        TransactionType::Deposit,
+                        1,
+                        transaction_id,
+                        Some(1.0),
+                    ))
+                    .unwrap();
+            }
+            engine
+                .execute(transaction(TransactionType::Dispute, 1, 1, None))
+                .unwrap();
+
+            // Disputes opened without the policy are tracked too. Those of snapshots written
+            // before they were are tracked from the restored lsn.
+            let text = String::from_utf8(snapshot_bytes(&engine)).unwrap();
+            assert!(text.contains("\nopened,1,1,3\n"), "{}", text);
+            let older: String = text
+                .lines()
+                .filter(|line| !line.starts_with("opened,"))
+                .map(|line| format!("{}\n", line))
+                .collect();
+            let mut restored = read_snapshot(older.as_bytes(), config()).unwrap();
+            restored
+                .execute(transaction(TransactionType::Dispute, 1, 2, None))
+                .unwrap();
+            let bytes = snapshot_bytes(&restored);
+            assert!(String::from_utf8_lossy(&bytes).contains("\nopened,1,1,3\nopened,1,2,4\n"));
+
+            let mut restored = read_snapshot(bytes.as_slice(), config()).unwrap();
+            restored
+                .execute(transaction(TransactionType::Deposit, 1, 3, Some(1.0)))
+                .unwrap();
+            assert_eq!(restored.accounts[&1].get_held_funds(), 1.0);
+            restored
+                .execute(transaction(TransactionType::Deposit, 1, 4, Some(1.0)))
+                .unwrap();
+            assert_eq!(restored.accounts[&1].get_held_funds(), 0.0);
+        }
+    }
+}
@@ -0,0 +1,234 @@
+//! Where the state of an engine lives between restarts of a service.
+//!
+//! A [`StateStore`] holds the state saved last together with every transaction recorded since.
+//! The engine records each transaction before applying it, so that once the record is synced a
+//! transaction is either part of the state after a restart or was never acknowledged: opening
+//! an engine over a store loads the saved state and applies the recorded transactions again.
+//! Saving replaces the state and drops the records it includes.
+//!
+//! [`MemoryStateStore`] keeps everything in memory, e.g. for tests or embedders that persist
+//! elsewhere. [`FileStateStore`] keeps a snapshot and a write-ahead log in a directory. Embedders
+//! keeping the state elsewhere, e.g. in an embedded database, implement the same trait. The schema
+//! of a PostgreSQL backend over sqlx or tokio-postgres is bundled as [`POSTGRES_MIGRATIONS`] so
+//! that deployments can prepare the database ahead of it.
+
+use crate::engine::{EngineConfig, TransactionEngine};
+use crate::error::SnapshotError;
+use crate::ingest::InputPosition;
+use crate::snapshot;
+use crate::transaction::Transaction;
+use crate::wal::{WalEntry, WriteAheadLog};
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Schema migrations of the PostgreSQL backend in the order they apply, by name
+pub const POSTGRES_MIGRATIONS: &[(&str, &str)] = &[(
+    "0001_create_state",
+    include_str!("../migrations/postgres/0001_create_state.sql"),
+)];
+
+/// Durable state of an engine, see the [module documentation](self)
+pub trait StateStore: Send {
+    /// State saved last, restored with `config`, and the transactions recorded since in order.
+    /// `None` if nothing was saved yet.
+    fn load(
+        &mut self,
+        config: EngineConfig,
+    ) -> Result<(Option<TransactionEngine>, Vec<WalEntry>), SnapshotError>;
+
+    /// Records a transaction before the engine applies it as its `lsn`th, with the position it
+    /// was read from if executed with
+    /// [`TransactionEngine::execute_at`]. Loading returns the position with the transaction.
+    fn record(
+        &mut self,
+        lsn: u64,
+        transaction: &Transaction,
+        position: Option<&InputPosition>,
+    ) -> io::Result<()>;
+
+    /// Makes the recorded transactions durable
+    fn sync(&mut self) -> io::Result<()>;
+
+    /// Replaces the saved state with that of `engine` and drops the records it includes
+    fn save(&mut self, engine: &TransactionEngine) -> Result<(), SnapshotError>;
+}
+
+#[derive(Debug, Default)]
+struct Saved {
+    snapshot: Option<Vec<u8>>,
+    records: Vec<WalEntry>,
+}
+
+/// Store whose clones share the same state in memory, so that an engine can be dropped and
+/// opened again from a clone
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStateStore {
+    saved: Arc<Mutex<Saved>>,
+}
+
+impl MemoryStateStore {
+    pub fn new() -> Self {
+        MemoryStateStore::default()
+    }
+
+    fn saved(&self) -> MutexGuard<'_, Saved> {
+        self.saved.lock().expect("State store lock poisoned")
+    }
+}
+
+impl StateStore for MemoryStateStore {
+    fn load(
+        &mut self,
+        config: EngineConfig,
+    ) -> Result<(Option<TransactionEngine>, Vec<WalEntry>), SnapshotError> {
+        let saved = self.saved();
+        let engine = match &saved.snapshot {
+            Some(snapshot) => Some(snapshot::read_snapshot(snapshot.as_slice(), config)?),
+            None => None,
+        };
+        Ok((engine, saved.records.clone()))
+    }
+
+    fn record(
+        &mut self,
+        lsn: u64,
+        transaction: &Transaction,
+        position: Option<&InputPosition>,
+    ) -> io::Result<()> {
+        self.saved().records.push(WalEntry {
+            lsn,
+            transaction: transaction.clone(),
+            position: position.cloned(),
+        });
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn save(&mut self, engine: &TransactionEngine) -> Result<(), SnapshotError> {
+        let mut snapshot = Vec::new();
+        snapshot::write_snapshot(engine, &mut snapshot)?;
+        let mut saved = self.saved();
+        saved.snapshot = Some(snapshot);
+        saved.records.clear();
+        Ok(())
+    }
+}
+
+/// Store keeping the saved state as `state.snapshot` and the records as `state.wal` in a
+/// directory. The snapshot is replaced atomically, see [`TransactionEngine::checkpoint`].
+pub struct FileStateStore {
+    snapshot: PathBuf,
+    wal: WriteAheadLog,
+    /// Records read when the log was opened, until they are loaded
+    recorded: Vec<WalEntry>,
+}
+
+impl FileStateStore {
+    /// Opens the store in `directory`, creating it if needed. Records are synced to disk every
+    /// `sync_every` transactions and on [`StateStore::sync`].
+    pub fn open<P: AsRef<Path>>(directory: P, sync_every: usize) -> io::Result<Self> {
+        let directory = directory.as_ref();
+        fs::create_dir_all(directory)?;
+        let (wal, recorded) = WriteAheadLog::open(directory.join("state.wal"), sync_every)?;
+        Ok(FileStateStore {
+            snapshot: directory.join("state.snapshot"),
+            wal,
+            recorded,
+        })
+    }
+}
+
+impl StateStore for FileStateStore {
+    fn load(
+        &mut self,
+        config: EngineConfig,
+    ) -> Result<(Option<TransactionEngine>, Vec<WalEntry>), SnapshotError> {
+        let engine = if self.snapshot.exists() {
+            Some(TransactionEngine::restore_with_config(
+                &self.snapshot,
+                config,
+            )?)
+        } else {
+            None
+        };
+        Ok((engine, mem::take(&mut self.recorded)))
+    }
+
+    fn record(
+        &mut self,
+        lsn: u64,
+        transaction: &Transaction,
+        position: Option<&InputPosition>,
+    ) -> io::Result<()> {
+        self.wal.append(lsn, transaction, position)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.wal.sync()
+    }
+
+    fn save(&mut self, engine: &TransactionEngine) -> Result<(), SnapshotError> {
+        snapshot::write_atomically(engine, &self.snapshot)?;
+        self.wal.truncate()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::engine::{EngineConfig, TransactionEngine};
+        use crate::state::{FileStateStore, MemoryStateStore, StateStore};
+        use crate::transaction::{transaction, TransactionType};
+
+        /// Runs transactions on an engine over the store, saving it halfway, and opens the store
+        /// again as a restarted process would
+        fn restart(open: impl Fn() -> Box<dyn StateStore>) -> TransactionEngine {
+            let config = EngineConfig::default();
+            let mut engine = TransactionEngine::open_store(open(), config.clone()).unwrap();
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 1, Some(5.0)))
+                .unwrap();
+            engine.save_state().unwrap();
+            engine
+                .execute(transaction(TransactionType::Deposit, 1, 2, Some(3.0)))
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Dispute, 1, 1, None))
+                .unwrap();
+            assert!(engine
+                .execute(transaction(TransactionType::Deposit, 1, 2, Some(3.0)))
+                .is_err());
+            engine.sync_wal().unwrap();
+            drop(engine);
+
+            TransactionEngine::open_store(open(), config).unwrap()
+        }
+
+        #[test]
+        fn state_survives_restarts() {
+            let memory = MemoryStateStore::new();
+            let directory = std::env::temp_dir().join("rust-coding-test-state");
+            let _ = std::fs::remove_dir_all(&directory);
+
+            let engines = [
+                restart(|| Box::new(memory.clone())),
+                restart(|| Box::new(FileStateStore::open(&directory, 1).unwrap())),
+            ];
+            std::fs::remove_dir_all(&directory).unwrap();
+
+            for engine in engines {
+                assert_eq!(engine.lsn, 4);
+                let account = &engine.accounts[&1];
+                assert_eq!(account.get_available_funds(), 3.0);
+                assert_eq!(account.get_held_funds(), 5.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,420 @@
+//! Account statements of clients, written by the `statements` subcommand from the ledger kept in
+//! a snapshot: the balance of each client at the start of the period, every transaction applied
+//! to the account since with the balance it left, the disputes raised and how they ended, and
+//! the balance at the end.
+
+use crate::account::ClientId;
+use crate::currency::Currency;
+use crate::engine::TransactionEngine;
+use crate::error::EngineError;
+use crate::ledger::LedgerEntry;
+use crate::log;
+use crate::output::AccountSnapshot;
+use crate::transaction::{Amount, Transaction, TransactionId, TransactionType};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// Format of the statement files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StatementFormat {
+    /// One row per balance or transaction
+    #[default]
+    Csv,
+    /// Lines meant to be read by people
+    Text,
+}
+
+impl StatementFormat {
+    /// Extension of the statement files
+    pub fn extension(&self) -> &'static str {
+        match self {
+            StatementFormat::Csv => "csv",
+            StatementFormat::Text => "txt",
+        }
+    }
+}
+
+impl FromStr for StatementFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "csv" => Ok(StatementFormat::Csv),
+            "text" => Ok(StatementFormat::Text),
+            _ => Err(format!("unknown statement format '{}'", value)),
+        }
+    }
+}
+
+/// Transaction applied to the account of a client with the funds it left in its currency
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatementLine {
+    /// Order in which the engine applied the transaction
+    pub sequence: u64,
+    pub transaction: Transaction,
+    pub balance: AccountSnapshot,
+}
+
+/// Dispute raised during the period of a statement
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatementDispute {
+    pub transaction_id: TransactionId,
+    pub currency: Option<Currency>,
+    /// Amount of the disputed transaction, unknown if it was applied before the ledger started
+    pub amount: Option<f64>,
+    /// open, resolved, charged back or represented by the end of the period
+    pub outcome: &'static str,
+}
+
+/// Account statement of a client over a period of its ledger
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub client_id: ClientId,
+    /// Funds in every currency at the start of the period, none for an account opened since
+    pub opening: Vec<AccountSnapshot>,
+    pub lines: Vec<StatementLine>,
+    pub disputes: Vec<StatementDispute>,
+    /// Funds in every currency at the end of the period
+    pub closing: Vec<AccountSnapshot>,
+}
+
+impl Statement {
+    fn empty(client_id: ClientId) -> Self {
+        Statement {
+            client_id,
+            opening: Vec::new(),
+            lines: Vec::new(),
+            disputes: Vec::new(),
+            closing: Vec::new(),
+        }
+    }
+
+    /// Statements of every client, in order of client id, over the transactions applied after
+    /// `after`, the first one recorded with that id, or over the whole ledger. The ledger is
+    /// replayed on a new engine as [`TransactionEngine::state_at`] does, so it must be complete
+    /// for the balances to be those of the engine.
+    pub fn all(
+        engine: &TransactionEngine,
+        after: Option<TransactionId>,
+    ) -> Result<Vec<Statement>, EngineError> {
+        let entries = engine.ledger.in_order();
+        let start = match after {
+            Some(transaction_id) => {
+                entries
+                    .iter()
+                    .position(|entry| entry.transaction.transaction_id == transaction_id)
+                    .ok_or(EngineError::UnknownTransaction(transaction_id))?
+                    + 1
+            }
+            None => 0,
+        };
+        let mut replica = TransactionEngine::with_config(engine.config().clone()).into_replica();
+        replay(&mut replica, &entries[..start]);
+
+        let mut statements: BTreeMap<ClientId, Statement> = replica
+            .accounts
+            .iter()
+            .map(|(client_id, account)| {
+                let statement = Statement {
+                    opening: replica.account_snapshots(account.as_ref()).collect(),
+                    ..Statement::empty(*client_id)
+                };
+                (*client_id, statement)
+            })
+            .collect();
+        for entry in &entries[start..] {
+            replay(&mut replica, &[entry]);
+            let transaction = &entry.transaction;
+            for client_id in [Some(transaction.client_id), transaction.to_client_id]
+                .into_iter()
+                .flatten()
+            {
+                let Some(account) = replica.accounts.get(&client_id) else {
+                    continue;
+                };
+                let statement = statements
+                    .entry(client_id)
+                    .or_insert_with(|| Statement::empty(client_id));
+                statement.lines.push(StatementLine {
+                    sequence: entry.sequence,
+                    transaction: transaction.clone(),
+                    balance: AccountSnapshot {
+                        reserved: replica.reserve(client_id, transaction.currency),
+                        closed: replica.is_closed(client_id),
+                        ..AccountSnapshot::new(account.as_ref(), transaction.currency)
+                    },
+                });
+            }
+        }
+
+        statements.retain(|client_id, _| replica.accounts.contains_key(client_id));
+        for (client_id, statement) in &mut statements {
+            let account = &replica.accounts[client_id];
+            statement.closing = replica.account_snapshots(account.as_ref()).collect();
+            statement.disputes = disputes(engine, *client_id, &statement.lines);
+        }
+        Ok(statements.into_values().collect())
+    }
+
+    /// Rows of a csv file: the opening balances, the transactions and the closing balances, with
+    /// amounts written with the given decimal places. Dispute rows tell how the dispute ended.
+    pub fn to_csv(&self, decimals: usize) -> String {
+        let mut csv = String::from(
+            "line,sequence,type,tx,amount,currency,to,timestamp,available,held,total,dispute\n",
+        );
+        let balance = |csv: &mut String, line: &str, balance: &AccountSnapshot| {
+            let currency = balance.currency.map_or(String::new(), |c| c.to_string());
+            let _ = writeln!(
+                csv,
+                "{},,,,,{},,,{:.*},{:.*},{:.*},",
+                line,
+                currency,
+                decimals,
+                balance.available,
+                decimals,
+                balance.held,
+                decimals,
+                balance.total
+            );
+        };
+        for opening in &self.opening {
+            balance(&mut csv, "opening", opening);
+        }
+        for (position, line) in self.lines.iter().enumerate() {
+            let transaction = &line.transaction;
+            let outcome = match transaction.transaction_type {
+                TransactionType::Dispute => outcome(&self.lines[position + 1..], transaction),
+                _ => "",
+            };
+            let _ = writeln!(
+                csv,
+                "transaction,{},{},{},{},{},{},{},{:.*},{:.*},{:.*},{}",
+                line.sequence,
+                transaction.transaction_type,
+                transaction.transaction_id,
+                transaction
+                    .amount
+                    .map_or(String::new(), |amount| format!("{:.*}", decimals, amount)),
+                transaction
+                    .currency
+                    .map_or(String::new(), |c| c.to_string()),
+                transaction
+                    .to_client_id
+                    .map_or(String::new(), |to| to.to_string()),
+                transaction
+                    .timestamp
+                    .map_or(String::new(), |t| t.to_string()),
+                decimals,
+                line.balance.available,
+                decimals,
+                line.balance.held,
+                decimals,
+                line.balance.total,
+                outcome
+            );
+        }
+        for closing in &self.closing {
+            balance(&mut csv, "closing", closing);
+        }
+        csv
+    }
+
+    /// Same as [`Statement::to_csv`] as lines meant to be read by people
+    pub fn to_text(&self, decimals: usize) -> String {
+        let mut text = String::new();
+        let balances = |text: &mut String, title: &str, balances: &[AccountSnapshot]| {
+            let _ = writeln!(text, "{}", title);
+            if balances.is_empty() {
+                let _ = writeln!(text, "  none");
+            }
+            for balance in balances {
+                let currency = balance.currency.map_or("-".to_string(), |c| c.to_string());
+                let _ = writeln!(
+                    text,
+                    "  {}: available {:.*}, held {:.*}, total {:.*}",
+                    currency,
+                    decimals,
+                    balance.available,
+                    decimals,
+                    balance.held,
+                    decimals,
+                    balance.total
+                );
+            }
+        };
+        let _ = writeln!(text, "Statement of client {}", self.client_id);
+        balances(&mut text, "Opening balance", &self.opening);
+        let _ = writeln!(text, "Transactions: {}", self.lines.len());
+        for line in &self.lines {
+            let transaction = &line.transaction;
+            let _ = write!(
+                text,
+                "  #{} {} tx {}",
+                line.sequence, transaction.transaction_type, transaction.transaction_id
+            );
+            if let Some(amount) = transaction.amount {
+                let _ = write!(text, " {:.*}", decimals, amount);
+            }
+            if let Some(currency) = transaction.currency {
+                let _ = write!(text, " {}", currency);
+            }
+            if let Some(to) = transaction.to_client_id {
+                let _ = write!(text, " to client {}", to);
+            }
+            if let Some(timestamp) = transaction.timestamp {
+                let _ = write!(text, " at {}", timestamp);
+            }
+            let _ = writeln!(
+                text,
+                ": available {:.*}, held {:.*}, total {:.*}",
+                decimals,
+                line.balance.available,
+                decimals,
+                line.balance.held,
+                decimals,
+                line.balance.total
+            );
+        }
+        let _ = writeln!(text, "Disputes: {}", self.disputes.len());
+        for dispute in &self.disputes {
+            let _ = write!(text, "  tx {}", dispute.transaction_id);
+            if let Some(amount) = dispute.amount {
+                let _ = write!(text, " {:.*}", decimals, amount);
+            }
+            if let Some(currency) = dispute.currency {
+                let _ = write!(text, " {}", currency);
+            }
+            let _ = writeln!(text, ": {}", dispute.outcome);
+        }
+        balances(&mut text, "Closing balance", &self.closing);
+        text
+    }
+}
+
+/// Applies the entries to the replica, logging those that could not be
+fn replay(replica: &mut TransactionEngine, entries: &[&LedgerEntry]) {
+    for (entry, err) in replica.replay_history(entries) {
+        log::warn(
+            "Could not replay ledger entry",
+            &[("sequence", &entry.sequence), ("error", &err)],
+        );
+    }
+}
+
+/// How the dispute ended among the lines following it
+fn outcome(lines: &[StatementLine], dispute: &Transaction) -> &'static str {
+    let mut outcome = "open";
+    for line in lines {
+        let transaction = &line.transaction;
+        if transaction.transaction_id != dispute.transaction_id
+            || transaction.currency != dispute.currency
+        {
+            continue;
+        }
+        match transaction.transaction_type {
+            TransactionType::Resolve => return "resolved",
+            TransactionType::Chargeback => outcome = "charged back",
+            TransactionType::Represent => return "represented",
+            // Disputed again after it was resolved
+            TransactionType::Dispute => break,
+            _ => {}
+        }
+    }
+    outcome
+}
+
+/// Disputes raised by the lines of a client, with the amount of the disputed transaction
+fn disputes(
+    engine: &TransactionEngine,
+    client_id: ClientId,
+    lines: &[StatementLine],
+) -> Vec<StatementDispute> {
+    lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| line.transaction.transaction_type == TransactionType::Dispute)
+        .map(|(position, line)| {
+            let transaction = &line.transaction;
+            let amount = engine
+                .history(client_id)
+                .transaction_ids(transaction.transaction_id..=transaction.transaction_id)
+                .find(|entry| {
+                    matches!(
+                        entry.transaction.transaction_type,
+                        TransactionType::Deposit | TransactionType::Withdrawal
+                    )
+                })
+                .and_then(|entry| entry.transaction.amount.map(Amount::value));
+            StatementDispute {
+                transaction_id: transaction.transaction_id,
+                currency: transaction.currency,
+                amount,
+                outcome: outcome(&lines[position + 1..], transaction),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::engine::{EngineConfig, TransactionEngine};
+        use crate::statement::Statement;
+        use crate::transaction::{transaction, TransactionId, TransactionType};
+
+        #[test]
+        fn statements_itemize_the_transactions_after_the_opening_balance() {
+            let mut engine = TransactionEngine::with_config(EngineConfig {
+                record_history: true,
+                ..EngineConfig::default()
+            });
+            for transaction in [
+                transaction(TransactionType::Deposit, 1, 1, Some(5.0)),
+                transaction(TransactionType::Deposit, 2, 2, Some(3.0)),
+                transaction(TransactionType::Deposit, 1, 3, Some(2.0)),
+                transaction(TransactionType::Withdrawal, 1, 4, Some(1.5)),
+                transaction(TransactionType::Dispute, 1, 3, None),
+                transaction(TransactionType::Dispute, 2, 2, None),
+                transaction(TransactionType::Resolve, 2, 2, None),
+            ] {
+                engine.execute(transaction).unwrap();
+            }
+
+            let statements = Statement::all(&engine, Some(TransactionId(2))).unwrap();
+            let clients: Vec<_> = statements.iter().map(|s| s.client_id).collect();
+            assert_eq!(clients, [1, 2]);
+            assert_eq!(
+                statements[0].to_csv(2),
+                "line,sequence,type,tx,amount,currency,to,timestamp,available,held,total,dispute\n\
+                 opening,,,,,,,,5.00,0.00,5.00,\n\
+                 transaction,2,deposit,3,2.00,,,,7.00,0.00,7.00,\n\
+                 transaction,3,withdrawal,4,1.50,,,,5.50,0.00,5.50,\n\
+                 transaction,4,dispute,3,,,,,3.50,2.00,5.50,open\n\
+                 closing,,,,,,,,3.50,2.00,5.50,\n"
+            );
+            assert_eq!(
+                statements[1].to_text(4),
+                "Statement of client 2\n\
+                 Opening balance\n  \
+                 -: available 3.0000, held 0.0000, total 3.0000\n\
+                 Transactions: 2\n  \
+                 #5 dispute tx 2: available 0.0000, held 3.0000, total 3.0000\n  \
+                 #6 resolve tx 2: available 3.0000, held 0.0000, total 3.0000\n\
+                 Disputes: 1\n  \
+                 tx 2 3.0000: resolved\n\
+                 Closing balance\n  \
+                 -: available 3.0000, held 0.0000, total 3.0000\n"
+            );
+            // The whole ledger starts from no accounts
+            let statements = Statement::all(&engine, None).unwrap();
+            assert!(statements[0].opening.is_empty());
+            assert_eq!(statements[0].lines.len(), 4);
+            assert_eq!(
+                statements[0].closing,
+                [statements[0].lines[3].balance.clone()]
+            );
+            assert!(Statement::all(&engine, Some(TransactionId(9))).is_err());
+        }
+    }
+}
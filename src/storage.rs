@@ -0,0 +1,347 @@
+//! Where accounts keep their disputable transactions.
+//!
+//! By default the log of every account is a `HashMap` in memory. [`DiskStore`] keeps the logs
+//! of all accounts in a single file instead, so that RAM stays flat however many transactions
+//! remain disputable. Transaction ids are unique, so the file holds one fixed size slot per
+//! transaction id at offset `id * SLOT_SIZE`. A slot has room for two entries, as both legs of a
+//! transfer are logged under its id:
+//!
+//! ```text
+//! byte  0       1 if the entry holds a transaction
+//! bytes 1..4    currency code, zeroes without currency
+//! bytes 4..     client id, little endian, as wide as [`ClientId`]
+//! bytes 8..16   amount, little endian f64, at bytes 16..24 with 64 bit client ids
+//! ```
+//!
+//! Slots of ids that were never logged are holes of a sparse file on most file systems. The
+//! file only backs the running engine and is truncated when opened, snapshots remain the way
+//! to persist state.
+
+use crate::account::ClientId;
+use crate::currency::Currency;
+use crate::transaction::TransactionId;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Currency and change to the available funds of a logged transaction
+pub type LogEntry = (Option<Currency>, f64);
+
+/// Disputable transactions of one account
+pub trait TransactionStore: Send + fmt::Debug {
+    fn insert(&mut self, transaction_id: TransactionId, entry: LogEntry) -> io::Result<()>;
+
+    fn get(&self, transaction_id: TransactionId) -> io::Result<Option<LogEntry>>;
+
+    fn remove(&mut self, transaction_id: TransactionId) -> io::Result<Option<LogEntry>>;
+
+    /// Every entry, sorted by transaction id. Only used to snapshot the account, so it may be
+    /// slow.
+    fn entries(&self) -> io::Result<Vec<(TransactionId, Option<Currency>, f64)>>;
+}
+
+impl TransactionStore for HashMap<TransactionId, LogEntry> {
+    fn insert(&mut self, transaction_id: TransactionId, entry: LogEntry) -> io::Result<()> {
+        HashMap::insert(self, transaction_id, entry);
+        Ok(())
+    }
+
+    fn get(&self, transaction_id: TransactionId) -> io::Result<Option<LogEntry>> {
+        Ok(HashMap::get(self, &transaction_id).copied())
+    }
+
+    fn remove(&mut self, transaction_id: TransactionId) -> io::Result<Option<LogEntry>> {
+        Ok(HashMap::remove(self, &transaction_id))
+    }
+
+    fn entries(&self) -> io::Result<Vec<(TransactionId, Option<Currency>, f64)>> {
+        let mut entries: Vec<_> = self
+            .iter()
+            .map(|(id, (currency, amount))| (*id, *currency, *amount))
+            .collect();
+        entries.sort_by_key(|(id, _, _)| *id);
+        Ok(entries)
+    }
+}
+
+/// Decides where the engine keeps the transaction logs of accounts
+#[derive(Debug, Clone, Default)]
+pub enum Storage {
+    #[default]
+    Memory,
+    Disk(DiskStore),
+}
+
+impl Storage {
+    /// Log for a new account of the client, `None` to keep it in memory
+    pub(crate) fn open(&self, client_id: ClientId) -> Option<Box<dyn TransactionStore>> {
+        match self {
+            Storage::Memory => None,
+            Storage::Disk(store) => Some(Box::new(DiskLog {
+                store: store.clone(),
+                client_id,
+            })),
+        }
+    }
+}
+
+const CLIENT_ID_SIZE: usize = std::mem::size_of::<ClientId>();
+const AMOUNT_OFFSET: usize = (4 + CLIENT_ID_SIZE).next_multiple_of(8);
+const ENTRY_SIZE: usize = AMOUNT_OFFSET + 8;
+const ENTRIES_PER_SLOT: usize = 2;
+const SLOT_SIZE: u64 = (ENTRY_SIZE * ENTRIES_PER_SLOT) as u64;
+
+type Slot = [u8; SLOT_SIZE as usize];
+
+type Entries = Vec<(TransactionId, Option<Currency>, f64)>;
+
+/// File holding the transaction logs of every account. Clones share the same file, which may be
+/// used by several threads.
+#[derive(Clone)]
+pub struct DiskStore {
+    inner: Arc<Mutex<DiskFile>>,
+}
+
+struct DiskFile {
+    file: File,
+    path: PathBuf,
+    /// Entries of every client read by the last full scan, dropped on the next write. Snapshots
+    /// ask every account for its entries in turn, so this reads the file once per snapshot.
+    scanned: Option<HashMap<ClientId, Entries>>,
+}
+
+impl fmt::Debug for DiskStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DiskStore")
+            .field("path", &self.lock().path)
+            .finish()
+    }
+}
+
+impl DiskStore {
+    /// Creates the file, truncating it if it exists
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(DiskStore {
+            inner: Arc::new(Mutex::new(DiskFile {
+                file,
+                path: path.to_path_buf(),
+                scanned: None,
+            })),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, DiskFile> {
+        // The file is left consistent even if a holder panicked
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn read(
+        &self,
+        transaction_id: TransactionId,
+        client_id: ClientId,
+    ) -> io::Result<Option<LogEntry>> {
+        let slot = read_slot(&mut self.lock().file, transaction_id)?;
+        Ok(slot
+            .chunks_exact(ENTRY_SIZE)
+            .filter_map(decode)
+            .find(|(owner, _)| *owner == client_id)
+            .map(|(_, entry)| entry))
+    }
+
+    /// Stores the entry of the client under the transaction id, or clears it if `None`
+    fn write(
+        &self,
+        transaction_id: TransactionId,
+        client_id: ClientId,
+        entry: Option<LogEntry>,
+    ) -> io::Result<()> {
+        let mut disk = self.lock();
+        let mut slot = read_slot(&mut disk.file, transaction_id)?;
+        let position = slot
+            .chunks_exact(ENTRY_SIZE)
+            .position(|chunk| matches!(decode(chunk), Some((owner, _)) if owner == client_id))
+            .or_else(|| {
+                slot.chunks_exact(ENTRY_SIZE)
+                    .position(|chunk| decode(chunk).is_none())
+            });
+        let position = match (position, entry) {
+            (Some(position), _) => position,
+            (None, None) => return Ok(()),
+            (None, Some(_)) => {
+                return Err(io::Error::other(format!(
+                    "transaction {} is already logged for {} clients",
+                    transaction_id, ENTRIES_PER_SLOT
+                )))
+            }
+        };
+        let bytes = match entry {
+            Some(entry) => encode(client_id, entry),
+            None => [0; ENTRY_SIZE],
+        };
+        slot[position * ENTRY_SIZE..(position + 1) * ENTRY_SIZE].copy_from_slice(&bytes);
+
+        disk.scanned = None;
+        disk.file
+            .seek(SeekFrom::Start(u64::from(transaction_id.0) * SLOT_SIZE))?;
+        disk.file.write_all(&slot)
+    }
+
+    fn entries(&self, client_id: ClientId) -> io::Result<Entries> {
+        let mut disk = self.lock();
+        if disk.scanned.is_none() {
+            disk.file.seek(SeekFrom::Start(0))?;
+            let scanned = scan(BufReader::new(&disk.file))?;
+            disk.scanned = Some(scanned);
+        }
+        Ok(disk
+            .scanned
+            .as_ref()
+            .and_then(|scanned| scanned.get(&client_id))
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+fn read_slot(file: &mut File, transaction_id: TransactionId) -> io::Result<Slot> {
+    file.seek(SeekFrom::Start(u64::from(transaction_id.0) * SLOT_SIZE))?;
+    let mut slot = [0; SLOT_SIZE as usize];
+    // Slots past the end of the file were never written
+    match file.read_exact(&mut slot) {
+        Ok(()) => Ok(slot),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok([0; SLOT_SIZE as usize]),
+        Err(err) => Err(err),
+    }
+}
+
+/// Reads every slot in a single pass, grouping the transactions by client
+fn scan<R: Read>(mut reader: R) -> io::Result<HashMap<ClientId, Entries>> {
+    let mut scanned: HashMap<ClientId, Entries> = HashMap::new();
+    let mut slot = [0; SLOT_SIZE as usize];
+    let mut transaction_id = TransactionId(0);
+    loop {
+        match reader.read_exact(&mut slot) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(scanned),
+            Err(err) => return Err(err),
+        }
+        for (client_id, (currency, amount)) in slot.chunks_exact(ENTRY_SIZE).filter_map(decode) {
+            scanned
+                .entry(client_id)
+                .or_default()
+                .push((transaction_id, currency, amount));
+        }
+        transaction_id = transaction_id.wrapping_add(1);
+    }
+}
+
+fn encode(client_id: ClientId, (currency, amount): LogEntry) -> [u8; ENTRY_SIZE] {
+    let mut bytes = [0; ENTRY_SIZE];
+    bytes[0] = 1;
+    if let Some(currency) = currency {
+        bytes[1..4].copy_from_slice(currency.as_str().as_bytes());
+    }
+    bytes[4..4 + CLIENT_ID_SIZE].copy_from_slice(&client_id.to_le_bytes());
+    bytes[AMOUNT_OFFSET..].copy_from_slice(&amount.to_le_bytes());
+    bytes
+}
+
+fn decode(bytes: &[u8]) -> Option<(ClientId, LogEntry)> {
+    if bytes[0] != 1 {
+        return None;
+    }
+    let mut client_id = [0; CLIENT_ID_SIZE];
+    client_id.copy_from_slice(&bytes[4..4 + CLIENT_ID_SIZE]);
+    let client_id = ClientId::from_le_bytes(client_id);
+    let currency = match &bytes[1..4] {
+        [0, 0, 0] => None,
+        code => std::str::from_utf8(code).ok()?.parse().ok(),
+    };
+    let mut amount = [0; 8];
+    amount.copy_from_slice(&bytes[AMOUNT_OFFSET..]);
+    Some((client_id, (currency, f64::from_le_bytes(amount))))
+}
+
+/// Log of one account in a [`DiskStore`]
+#[derive(Debug)]
+struct DiskLog {
+    store: DiskStore,
+    client_id: ClientId,
+}
+
+impl TransactionStore for DiskLog {
+    fn insert(&mut self, transaction_id: TransactionId, entry: LogEntry) -> io::Result<()> {
+        self.store
+            .write(transaction_id, self.client_id, Some(entry))
+    }
+
+    fn get(&self, transaction_id: TransactionId) -> io::Result<Option<LogEntry>> {
+        self.store.read(transaction_id, self.client_id)
+    }
+
+    fn remove(&mut self, transaction_id: TransactionId) -> io::Result<Option<LogEntry>> {
+        let entry = self.get(transaction_id)?;
+        if entry.is_some() {
+            self.store.write(transaction_id, self.client_id, None)?;
+        }
+        Ok(entry)
+    }
+
+    fn entries(&self) -> io::Result<Vec<(TransactionId, Option<Currency>, f64)>> {
+        self.store.entries(self.client_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::storage::{DiskStore, Storage};
+        use crate::transaction::TransactionId;
+
+        #[test]
+        fn disk_store_keeps_entries_per_client() {
+            let path = std::env::temp_dir().join("rust-coding-test-storage.log");
+            let storage = Storage::Disk(DiskStore::create(&path).unwrap());
+            let mut first = storage.open(1).unwrap();
+            let mut second = storage.open(2).unwrap();
+            let eur = Some("EUR".parse().unwrap());
+
+            first.insert(TransactionId(7), (None, 1.5)).unwrap();
+            first.insert(TransactionId(3), (eur, -2.0)).unwrap();
+            second.insert(TransactionId(5), (None, 4.0)).unwrap();
+            // Both legs of a transfer share the id
+            second.insert(TransactionId(3), (eur, 2.0)).unwrap();
+
+            assert_eq!(first.get(TransactionId(7)).unwrap(), Some((None, 1.5)));
+            assert_eq!(first.get(TransactionId(5)).unwrap(), None);
+            assert_eq!(first.get(TransactionId(1_000)).unwrap(), None);
+            assert_eq!(
+                first.entries().unwrap(),
+                vec![(TransactionId(3), eur, -2.0), (TransactionId(7), None, 1.5)]
+            );
+            assert_eq!(second.remove(TransactionId(5)).unwrap(), Some((None, 4.0)));
+            assert_eq!(second.get(TransactionId(5)).unwrap(), None);
+            assert_eq!(
+                second.entries().unwrap(),
+                vec![(TransactionId(3), eur, 2.0)]
+            );
+            assert!(storage
+                .open(3)
+                .unwrap()
+                .insert(TransactionId(3), (None, 1.0))
+                .is_err());
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
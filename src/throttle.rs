@@ -0,0 +1,309 @@
+//! Token bucket rate limits on the transactions submitted to a long running service, protecting
+//! the engine from upstream systems retrying too eagerly.
+//!
+//! Limits are read from the `[rate_limits]` table of the configuration file:
+//!
+//! ```toml
+//! [rate_limits]
+//! global = 1_000             # transactions per second over all clients
+//! global_burst = 5_000       # transactions accepted at once, the rate by default
+//! per_client = 10            # transactions per second of each client
+//! per_client_burst = 20
+//! ```
+//!
+//! The [`Server`](crate::server::Server) answers requests over a limit with `429 Too Many
+//! Requests` and a `Retry-After` header, while the [`Consumer`](crate::consumer::Consumer) waits
+//! until the transactions of a message are allowed, as a stream cannot be asked to send them
+//! again later. Throttled transactions are counted per limit in the
+//! [`EngineMetrics`](crate::metrics::EngineMetrics).
+
+use crate::account::ClientId;
+use crate::error::ConfigError;
+use crate::toml;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Sustained rate and burst of a token bucket
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// Transactions allowed per second on average
+    pub per_second: f64,
+    /// Transactions allowed at once after a quiet period, at least 1
+    pub burst: f64,
+}
+
+impl RateLimit {
+    /// Limit whose burst is a second worth of transactions
+    pub fn per_second(per_second: f64) -> Self {
+        RateLimit {
+            per_second,
+            burst: per_second.max(1.0),
+        }
+    }
+}
+
+/// Parses `<PER_SECOND>` or `<PER_SECOND>:<BURST>`
+impl FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid rate limit '{}'", value);
+        let positive = |number: &str| match number.parse::<f64>() {
+            Ok(number) if number.is_finite() && number > 0.0 => Ok(number),
+            _ => Err(invalid()),
+        };
+        match value.split_once(':') {
+            Some((per_second, burst)) => Ok(RateLimit {
+                per_second: positive(per_second)?,
+                burst: positive(burst)?.max(1.0),
+            }),
+            None => Ok(RateLimit::per_second(positive(value)?)),
+        }
+    }
+}
+
+/// Limits of a service, none unless set
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RateLimits {
+    /// Shared by every client
+    pub global: Option<RateLimit>,
+    /// Applied to each client separately
+    pub per_client: Option<RateLimit>,
+}
+
+impl RateLimits {
+    pub fn is_enabled(&self) -> bool {
+        self.global.is_some() || self.per_client.is_some()
+    }
+
+    pub(crate) fn from_table(table: &toml::Table) -> Result<Self, ConfigError> {
+        let mut limits = RateLimits::default();
+        let mut bursts = BTreeMap::new();
+        for (key, entry) in &table.entries {
+            let number = match entry.as_number(key)? {
+                number if number > 0.0 => number,
+                _ => return Err(entry.invalid(format!("'{}' must be positive", key))),
+            };
+            match key.as_str() {
+                "global" => limits.global = Some(RateLimit::per_second(number)),
+                "per_client" => limits.per_client = Some(RateLimit::per_second(number)),
+                "global_burst" | "per_client_burst" => {
+                    bursts.insert(key.as_str(), (number, entry));
+                }
+                _ => return Err(entry.invalid(format!("unknown key '{}' in [rate_limits]", key))),
+            }
+        }
+        for (key, (burst, entry)) in bursts {
+            let limit = match key {
+                "global_burst" => &mut limits.global,
+                _ => &mut limits.per_client,
+            };
+            match limit {
+                Some(limit) => limit.burst = burst.max(1.0),
+                None => {
+                    let rate = key.trim_end_matches("_burst");
+                    return Err(entry.invalid(format!("'{}' requires '{}'", key, rate)));
+                }
+            }
+        }
+        Ok(limits)
+    }
+}
+
+/// Why transactions were refused
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Throttled {
+    /// `global` or `client`
+    pub limit: &'static str,
+    /// Client over its limit, for the per client limit
+    pub client_id: Option<ClientId>,
+    /// How long until the transactions would be allowed, `None` if they never are as they exceed
+    /// the burst of the limit and have to be split
+    pub retry_after: Option<Duration>,
+}
+
+impl fmt::Display for Throttled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.client_id {
+            Some(client_id) => write!(f, "client {} is over its rate limit", client_id)?,
+            None => write!(f, "global rate limit exceeded")?,
+        }
+        match self.retry_after {
+            Some(retry_after) => write!(f, ", retry after {:.3}s", retry_after.as_secs_f64()),
+            None => write!(f, ", submit fewer transactions at once"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(limit: RateLimit, now: Instant) -> Self {
+        Bucket {
+            tokens: limit.burst,
+            updated: now,
+        }
+    }
+
+    /// Adds the tokens earned since the last refill
+    fn refill(&mut self, limit: RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(limit.burst);
+        self.updated = now;
+    }
+
+    /// How long until `count` tokens are available, `None` if available now and `Some(None)` if
+    /// never
+    fn wait(&self, limit: RateLimit, count: f64) -> Option<Option<Duration>> {
+        if count <= self.tokens {
+            None
+        } else if count > limit.burst {
+            Some(None)
+        } else {
+            Some(Some(Duration::from_secs_f64(
+                (count - self.tokens) / limit.per_second,
+            )))
+        }
+    }
+}
+
+/// Buckets of [`RateLimits`], one per client for the per client limit
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    limits: RateLimits,
+    global: Option<Bucket>,
+    clients: HashMap<ClientId, Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        RateLimiter {
+            limits,
+            global: None,
+            clients: HashMap::new(),
+        }
+    }
+
+    pub fn limits(&self) -> RateLimits {
+        self.limits
+    }
+
+    /// Takes a token per transaction, given by the client submitting it, if every limit allows
+    /// all of them at `now`. Takes nothing otherwise.
+    pub fn acquire(&mut self, clients: &[ClientId], now: Instant) -> Result<(), Throttled> {
+        if let Some(limit) = self.limits.global {
+            let bucket = self.global.get_or_insert_with(|| Bucket::full(limit, now));
+            bucket.refill(limit, now);
+            if let Some(retry_after) = bucket.wait(limit, clients.len() as f64) {
+                return Err(Throttled {
+                    limit: "global",
+                    client_id: None,
+                    retry_after,
+                });
+            }
+        }
+        if let Some(limit) = self.limits.per_client {
+            let mut counts = BTreeMap::new();
+            for client_id in clients {
+                *counts.entry(*client_id).or_insert(0.0) += 1.0;
+            }
+            for (&client_id, &count) in &counts {
+                let bucket = self
+                    .clients
+                    .entry(client_id)
+                    .or_insert_with(|| Bucket::full(limit, now));
+                bucket.refill(limit, now);
+                if let Some(retry_after) = bucket.wait(limit, count) {
+                    return Err(Throttled {
+                        limit: "client",
+                        client_id: Some(client_id),
+                        retry_after,
+                    });
+                }
+            }
+            for (client_id, count) in counts {
+                if let Some(bucket) = self.clients.get_mut(&client_id) {
+                    bucket.tokens -= count;
+                }
+            }
+        }
+        if let Some(bucket) = &mut self.global {
+            bucket.tokens -= clients.len() as f64;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::error::ConfigError;
+        use crate::throttle::{RateLimit, RateLimiter, RateLimits};
+        use crate::toml;
+        use std::time::{Duration, Instant};
+
+        #[test]
+        fn buckets_refill_at_their_rate() {
+            let mut limiter = RateLimiter::new(RateLimits {
+                global: Some("10:4".parse().unwrap()),
+                per_client: Some(RateLimit::per_second(2.0)),
+            });
+            let start = Instant::now();
+
+            limiter.acquire(&[1, 1, 2], start).unwrap();
+            let throttled = limiter.acquire(&[1], start).unwrap_err();
+            assert_eq!((throttled.limit, throttled.client_id), ("client", Some(1)));
+            assert_eq!(throttled.retry_after, Some(Duration::from_millis(500)));
+            // Nothing was taken by the refused request
+            let throttled = limiter.acquire(&[2, 3], start).unwrap_err();
+            assert_eq!(throttled.limit, "global");
+            assert_eq!(throttled.retry_after, Some(Duration::from_millis(100)));
+            limiter.acquire(&[3], start).unwrap();
+
+            let later = start + Duration::from_millis(500);
+            limiter.acquire(&[1, 2], later).unwrap();
+            let burst = limiter.acquire(&[4; 5], later + Duration::from_secs(1));
+            assert_eq!(burst.unwrap_err().retry_after, None);
+        }
+
+        #[test]
+        fn limits_are_read_from_toml() {
+            let tables =
+                toml::parse("[rate_limits]\nglobal = 100\nper_client = 5\nper_client_burst = 10\n")
+                    .unwrap();
+            let limits = RateLimits::from_table(&tables["rate_limits"]).unwrap();
+
+            assert_eq!(limits.global, Some(RateLimit::per_second(100.0)));
+            assert_eq!(
+                limits.per_client,
+                Some(RateLimit {
+                    per_second: 5.0,
+                    burst: 10.0
+                })
+            );
+            for invalid in [
+                "[rate_limits]\nglobal = 0\n",
+                "[rate_limits]\nglobal_burst = 10\n",
+                "[rate_limits]\nper_second = 1\n",
+            ] {
+                let tables = toml::parse(invalid).unwrap();
+                assert!(
+                    matches!(
+                        RateLimits::from_table(&tables["rate_limits"]),
+                        Err(ConfigError::Invalid { line: 2, .. })
+                    ),
+                    "{}",
+                    invalid
+                );
+            }
+            assert!("0.5:0".parse::<RateLimit>().is_err());
+            assert!("fast".parse::<RateLimit>().is_err());
+        }
+    }
+}
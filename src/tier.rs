@@ -0,0 +1,374 @@
+//! Tiers of the clients, e.g. business clients allowed larger transactions and an overdraft, or
+//! internal accounts of the operator exempt from the dispute rules of retail ones. Every client is
+//! in one tier, read from the `[tiers]` table of the configuration file or from a client master
+//! csv file, and the table of its tier overrides the engine wide policies for its account:
+//!
+//! ```toml
+//! [tiers]
+//! default = "retail"                   # clients not listed: retail, business or internal
+//! client_master = "clients.csv"        # 'client,tier' rows, over the clients listed here
+//! 7 = "business"
+//!
+//! [business]                           # or [retail], [internal]
+//! account = "overdraft"                # type of the accounts of the tier
+//! overdraft_limit = 5_000              # instead of those of the [overdraft] table
+//! overdraft_fee = 0
+//! max_transaction_amount = 50_000      # instead of those of the [limits] table
+//! daily_withdrawal_cap = 100_000
+//! max_transactions_per_client = 10_000
+//! negative_balance = "reject-dispute"  # instead of those of the [engine] table
+//! dispute_policy = "deposits-only"
+//! max_dispute_cycles = 3
+//! reserve = 100                        # of the available funds without currency
+//! ```
+//!
+//! The engine resolves the policies of an account when it creates or restores it, see
+//! [`EngineConfig::account_policies_for`](crate::engine::EngineConfig::account_policies_for) and
+//! [`EngineConfig::limits_for`](crate::engine::EngineConfig::limits_for), and the reserve of the
+//! funds as they are withdrawn, see
+//! [`TransactionEngine::reserve`](crate::engine::TransactionEngine::reserve). Tiers are
+//! configuration, so snapshots do not keep them and restored accounts take the tier configured at
+//! the time.
+
+use crate::account::ClientId;
+use crate::error::ConfigError;
+use crate::policy::{
+    AccountKind, AccountPolicies, DisputeCycles, DisputePolicy, LimitsPolicy,
+    NegativeBalancePolicy, OverdraftPolicy,
+};
+use crate::toml::{self, Entry};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Category of a client, see the [module](self) documentation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Tier {
+    #[default]
+    Retail,
+    Business,
+    /// Accounts of the operator itself, e.g. for fees or suspense funds
+    Internal,
+}
+
+impl Tier {
+    pub const ALL: [Tier; 3] = [Tier::Retail, Tier::Business, Tier::Internal];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Tier::Retail => "retail",
+            Tier::Business => "business",
+            Tier::Internal => "internal",
+        }
+    }
+}
+
+impl fmt::Display for Tier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Tier {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Tier::ALL
+            .into_iter()
+            .find(|tier| tier.as_str() == value)
+            .ok_or_else(|| format!("unknown tier '{}'", value))
+    }
+}
+
+/// Policies of the accounts of a tier, the engine wide ones where `None`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TierPolicy {
+    pub account: Option<AccountKind>,
+    pub overdraft_limit: Option<f64>,
+    pub overdraft_fee: Option<f64>,
+    /// Limits replacing those of the engine that are set here
+    pub limits: LimitsPolicy,
+    pub negative_balance: Option<NegativeBalancePolicy>,
+    pub dispute: Option<DisputePolicy>,
+    pub max_dispute_cycles: Option<DisputeCycles>,
+    /// Available funds without currency that withdrawals, authorizations and transfers cannot
+    /// take, unless a reserve was set for the account
+    pub reserve: Option<f64>,
+}
+
+impl TierPolicy {
+    /// Limits of the engine with those of the tier in their place
+    pub fn limits(&self, limits: LimitsPolicy) -> LimitsPolicy {
+        LimitsPolicy {
+            max_transaction_amount: self
+                .limits
+                .max_transaction_amount
+                .or(limits.max_transaction_amount),
+            daily_withdrawal_cap: self
+                .limits
+                .daily_withdrawal_cap
+                .or(limits.daily_withdrawal_cap),
+            max_transactions_per_client: self
+                .limits
+                .max_transactions_per_client
+                .or(limits.max_transactions_per_client),
+        }
+    }
+
+    /// Account policies of the engine with those of the tier in their place
+    pub fn account_policies(&self, policies: AccountPolicies) -> AccountPolicies {
+        AccountPolicies {
+            negative_balance: self.negative_balance.unwrap_or(policies.negative_balance),
+            dispute: self.dispute.unwrap_or(policies.dispute),
+            max_dispute_cycles: self
+                .max_dispute_cycles
+                .unwrap_or(policies.max_dispute_cycles),
+            ..policies
+        }
+    }
+
+    /// Overdraft of the engine with the limit and fee of the tier in their place
+    pub fn overdraft(&self, overdraft: OverdraftPolicy) -> OverdraftPolicy {
+        OverdraftPolicy {
+            limit: self.overdraft_limit.unwrap_or(overdraft.limit),
+            fee: self.overdraft_fee.unwrap_or(overdraft.fee),
+        }
+    }
+
+    pub(crate) fn from_table(name: &str, table: &toml::Table) -> Result<Self, ConfigError> {
+        let mut policy = TierPolicy::default();
+        let mut limits = toml::Table::default();
+        for (key, entry) in &table.entries {
+            match key.as_str() {
+                "account" => policy.account = Some(entry.parse(key)?),
+                "overdraft_limit" => policy.overdraft_limit = Some(non_negative(key, entry)?),
+                "overdraft_fee" => policy.overdraft_fee = Some(non_negative(key, entry)?),
+                "reserve" => policy.reserve = Some(non_negative(key, entry)?),
+                "max_transaction_amount"
+                | "daily_withdrawal_cap"
+                | "max_transactions_per_client" => {
+                    limits.entries.insert(key.clone(), entry.clone());
+                }
+                "negative_balance" => policy.negative_balance = Some(entry.parse(key)?),
+                "dispute_policy" => policy.dispute = Some(entry.parse(key)?),
+                "max_dispute_cycles" => match u32::try_from(entry.as_count(key)?) {
+                    Ok(cycles) if cycles > 0 => {
+                        policy.max_dispute_cycles = Some(DisputeCycles(cycles))
+                    }
+                    _ => return Err(entry.invalid(format!("'{}' must be at least 1", key))),
+                },
+                _ => return Err(entry.invalid(format!("unknown key '{}' in [{}]", key, name))),
+            }
+        }
+        policy.limits = LimitsPolicy::from_table(&limits)?;
+        Ok(policy)
+    }
+}
+
+/// Tier of every client and the policies of each tier, see the [module](self) documentation
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Tiers {
+    /// Tier of the clients not listed in `clients`
+    pub default: Tier,
+    pub clients: BTreeMap<ClientId, Tier>,
+    /// Client master file named by the `[tiers]` table, read by [`Tiers::load_client_master`]
+    pub client_master: Option<PathBuf>,
+    pub policies: BTreeMap<Tier, TierPolicy>,
+}
+
+impl Tiers {
+    pub fn tier(&self, client_id: ClientId) -> Tier {
+        self.clients
+            .get(&client_id)
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    /// Policies of the tier of the client, `None` if its tier overrides nothing
+    pub fn policy(&self, client_id: ClientId) -> Option<&TierPolicy> {
+        self.policies.get(&self.tier(client_id))
+    }
+
+    /// Whether the tier of any client changes the type or the overdraft of its account
+    pub fn shapes_accounts(&self) -> bool {
+        self.policies.values().any(|policy| {
+            policy.account.is_some()
+                || policy.overdraft_limit.is_some()
+                || policy.overdraft_fee.is_some()
+        })
+    }
+
+    /// Reads the tiers of a client master csv file with `client` and `tier` columns. Clients it
+    /// lists take its tier over that of the `[tiers]` table.
+    pub fn load_client_master<P: AsRef<Path>>(&mut self, path: P) -> Result<(), ConfigError> {
+        self.read_client_master(std::fs::File::open(path)?)
+    }
+
+    pub fn read_client_master<R: Read>(&mut self, reader: R) -> Result<(), ConfigError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let line = |position: Option<&csv::Position>| position.map_or(0, csv::Position::line);
+        let headers = reader.headers().map_err(|err| ConfigError::Invalid {
+            line: 1,
+            message: err.to_string(),
+        })?;
+        let column = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header == name)
+                .ok_or_else(|| ConfigError::Invalid {
+                    line: 1,
+                    message: format!("client master has no '{}' column", name),
+                })
+        };
+        let (client_column, tier_column) = (column("client")?, column("tier")?);
+        for record in reader.records() {
+            let record = record.map_err(|err| ConfigError::Invalid {
+                line: line(err.position()),
+                message: err.to_string(),
+            })?;
+            let invalid = |message: String| ConfigError::Invalid {
+                line: line(record.position()),
+                message,
+            };
+            let client = record.get(client_column).unwrap_or_default();
+            let client_id = client
+                .parse::<ClientId>()
+                .map_err(|_| invalid(format!("invalid client '{}'", client)))?;
+            let tier = record
+                .get(tier_column)
+                .unwrap_or_default()
+                .parse()
+                .map_err(invalid)?;
+            self.clients.insert(client_id, tier);
+        }
+        Ok(())
+    }
+
+    /// Reads the tiers of the `[tiers]` table, keeping the policies of the tiers
+    pub(crate) fn read_table(&mut self, table: &toml::Table) -> Result<(), ConfigError> {
+        for (key, entry) in &table.entries {
+            if key == "client_master" {
+                self.client_master = Some(PathBuf::from(entry.as_str(key)?));
+                continue;
+            }
+            let tier = entry.parse(key)?;
+            match key.parse::<ClientId>() {
+                _ if key == "default" => self.default = tier,
+                Ok(client_id) => {
+                    self.clients.insert(client_id, tier);
+                }
+                Err(_) => {
+                    return Err(entry.invalid(format!(
+                        "expected 'default', 'client_master' or a client id in [tiers], found \
+                         '{}'",
+                        key
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops the limits of every tier, for replicas of an engine
+    pub(crate) fn clear_limits(&mut self) {
+        for policy in self.policies.values_mut() {
+            policy.limits = LimitsPolicy::default();
+        }
+    }
+}
+
+fn non_negative(key: &str, entry: &Entry) -> Result<f64, ConfigError> {
+    match entry.as_number(key)? {
+        value if value >= 0.0 => Ok(value),
+        value => Err(entry.invalid(format!("'{}' must not be negative, found {}", key, value))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::config::ConfigFile;
+        use crate::engine::TransactionEngine;
+        use crate::error::{ConfigError, EngineError};
+        use crate::policy::NegativeBalancePolicy;
+        use crate::tier::Tier;
+        use crate::transaction::{transaction, TransactionType};
+
+        #[test]
+        fn accounts_follow_the_policies_of_their_tier() {
+            let mut config = ConfigFile::from_toml(
+                "[limits]\n\
+                 max_transaction_amount = 100\n\
+                 [tiers]\n\
+                 2 = \"internal\"\n\
+                 [business]\n\
+                 account = \"overdraft\"\n\
+                 overdraft_limit = 50\n\
+                 max_transaction_amount = 1_000\n\
+                 [internal]\n\
+                 negative_balance = \"reject-dispute\"\n",
+            )
+            .unwrap();
+            config
+                .tiers
+                .read_client_master("client,tier\n3, business\n".as_bytes())
+                .unwrap();
+            assert_eq!(config.tiers.tier(1), Tier::Retail);
+            assert_eq!(config.tiers.tier(3), Tier::Business);
+            let engine_config = config.engine_config();
+            assert_eq!(
+                engine_config.account_policies_for(2).negative_balance,
+                NegativeBalancePolicy::RejectDispute
+            );
+            let mut engine = TransactionEngine::with_config(engine_config);
+
+            // Retail clients keep the engine wide limit
+            assert!(matches!(
+                engine.execute(transaction(TransactionType::Deposit, 1, 1, Some(500.0))),
+                Err(EngineError::LimitExceeded { client_id: 1, .. })
+            ));
+            engine
+                .execute(transaction(TransactionType::Deposit, 3, 2, Some(500.0)))
+                .unwrap();
+            // Business accounts are overdraft accounts with the limit of the tier
+            engine
+                .execute(transaction(TransactionType::Withdrawal, 3, 3, Some(540.0)))
+                .unwrap();
+            assert_eq!(engine.accounts[&3].get_available_funds(), -40.0);
+            assert!(engine
+                .execute(transaction(TransactionType::Withdrawal, 3, 4, Some(20.0)))
+                .is_err());
+
+            engine
+                .execute(transaction(TransactionType::Deposit, 2, 5, Some(10.0)))
+                .unwrap();
+            engine
+                .execute(transaction(TransactionType::Withdrawal, 2, 6, Some(10.0)))
+                .unwrap();
+            assert!(engine
+                .execute(transaction(TransactionType::Dispute, 2, 5, None))
+                .is_err());
+        }
+
+        #[test]
+        fn invalid_client_masters_are_rejected() {
+            let mut config = ConfigFile::default();
+            for (input, line) in [
+                ("client\n1\n", 1),
+                ("client,tier\n1,retail\nx,retail\n", 3),
+                ("client,tier\n1,gold\n", 2),
+            ] {
+                match config.tiers.read_client_master(input.as_bytes()) {
+                    Err(ConfigError::Invalid { line: found, .. }) => {
+                        assert_eq!(found, line, "{}", input)
+                    }
+                    other => panic!("{}: {:?}", input, other),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! Points in time carried by transactions, kept as seconds since the unix epoch in UTC.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+pub const SECONDS_PER_DAY: u64 = 86_400;
+
+/// When a transaction took effect. Parsed from seconds since the unix epoch, a date such as
+/// `2024-03-01` or a date and time such as `2024-03-01T12:30:00Z`, with an optional `+HH:MM`
+/// offset instead of `Z`. Written back as a date and time in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(u64);
+
+impl Timestamp {
+    pub fn from_secs(secs: u64) -> Self {
+        Timestamp(secs)
+    }
+
+    pub fn as_secs(&self) -> u64 {
+        self.0
+    }
+
+    /// Day the timestamp falls on in UTC, counted from the unix epoch
+    pub fn day(&self) -> u64 {
+        self.0 / SECONDS_PER_DAY
+    }
+
+    /// Whether at most `days` days passed between `earlier` and this timestamp. Timestamps
+    /// before `earlier` are always within.
+    pub fn is_within_days(&self, earlier: Timestamp, days: u32) -> bool {
+        self.0.saturating_sub(earlier.0) <= u64::from(days) * SECONDS_PER_DAY
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day) = civil_from_days(self.day() as i64);
+        let seconds = self.0 % SECONDS_PER_DAY;
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            year,
+            month,
+            day,
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid timestamp '{}'", value);
+        if !value.is_empty() && value.bytes().all(|byte| byte.is_ascii_digit()) {
+            return value.parse().map(Timestamp).map_err(|_| invalid());
+        }
+
+        let (date, time) = match value.split_once(['T', ' ']) {
+            Some((date, time)) => (date, Some(time)),
+            None => (value, None),
+        };
+        let mut parts = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+        let (year, month, day) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(Some(year)), Some(Some(month)), Some(Some(day))) => (year, month, day),
+            _ => return Err(invalid()),
+        };
+        let days = days_from_civil(year, month, day);
+        // Catches months and days out of range, e.g. February 30th
+        if days < 0 || civil_from_days(days) != (year, month, day) {
+            return Err(invalid());
+        }
+
+        let seconds = match time {
+            Some(time) => seconds_of_day(time).ok_or_else(invalid)?,
+            None => 0,
+        };
+        (days * SECONDS_PER_DAY as i64)
+            .checked_add(seconds)
+            .and_then(|secs| u64::try_from(secs).ok())
+            .map(Timestamp)
+            .ok_or_else(invalid)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Parses `HH:MM[:SS[.fraction]]` followed by `Z` or an offset, returning the seconds since
+/// midnight in UTC. Negative or past the day when the offset moves the time to another day.
+fn seconds_of_day(time: &str) -> Option<i64> {
+    let (time, offset) = if let Some(time) = time.strip_suffix('Z') {
+        (time, 0)
+    } else if let Some(position) = time.rfind(['+', '-']) {
+        let (time, offset) = time.split_at(position);
+        let sign = if offset.starts_with('-') { -1 } else { 1 };
+        let (hours, minutes) = offset[1..].split_once(':')?;
+        let offset = hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60;
+        (time, sign * offset)
+    } else {
+        (time, 0)
+    };
+    // Fractions of a second are dropped
+    let time = time.split_once('.').map_or(time, |(time, _)| time);
+    let mut parts = time.split(':').map(|part| part.parse::<i64>().ok());
+    let hours = parts.next()??;
+    let minutes = parts.next()??;
+    let seconds = parts.next().unwrap_or(Some(0))?;
+    if parts.next().is_some() || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+    Some(hours * 3600 + minutes * 60 + seconds - offset)
+}
+
+/// Days since the unix epoch of a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Inverse of [`days_from_civil`]
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::timestamp::Timestamp;
+
+        #[test]
+        fn dates_and_times_are_parsed_as_utc() {
+            let parsed = |value: &str| value.parse::<Timestamp>().map(|t| t.as_secs());
+
+            assert_eq!(parsed("1709296200"), Ok(1_709_296_200));
+            assert_eq!(parsed("2024-03-01"), Ok(1_709_251_200));
+            assert_eq!(parsed("2024-03-01T12:30:00Z"), Ok(1_709_296_200));
+            assert_eq!(parsed("2024-03-01 12:30:00.250"), Ok(1_709_296_200));
+            assert_eq!(parsed("2024-03-01T14:30+02:00"), Ok(1_709_296_200));
+            for invalid in [
+                "",
+                "2024-02-30",
+                "2024-13-01",
+                "1969-12-31",
+                "2024-03-01T25:00Z",
+            ] {
+                assert!(invalid.parse::<Timestamp>().is_err(), "{}", invalid);
+            }
+        }
+
+        #[test]
+        fn timestamps_are_written_back_as_utc_date_and_time() {
+            let timestamp = Timestamp::from_secs(1_709_296_200);
+
+            assert_eq!(timestamp.to_string(), "2024-03-01T12:30:00Z");
+            assert_eq!(timestamp.to_string().parse(), Ok(timestamp));
+            assert_eq!(Timestamp::from_secs(0).to_string(), "1970-01-01T00:00:00Z");
+            assert_eq!(timestamp.day(), 19_783);
+        }
+
+        #[test]
+        fn windows_are_counted_in_whole_days() {
+            let deposit: Timestamp = "2024-01-01T10:00:00Z".parse().unwrap();
+
+            assert!("2024-03-31T10:00:00Z"
+                .parse::<Timestamp>()
+                .unwrap()
+                .is_within_days(deposit, 90));
+            assert!(!"2024-03-31T10:00:01Z"
+                .parse::<Timestamp>()
+                .unwrap()
+                .is_within_days(deposit, 90));
+            assert!(deposit.is_within_days("2024-02-01".parse().unwrap(), 0));
+        }
+    }
+}
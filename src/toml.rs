@@ -0,0 +1,287 @@
+//! Parser for the subset of TOML used by configuration files: `[table]` headers and
+//! `key = value` pairs whose values are strings, integers, floats or booleans. Comments start
+//! with `#`. Arrays, inline tables and dates are not supported.
+
+use crate::error::ConfigError;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Integer(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Boolean(_) => "boolean",
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::String(value) => write!(f, "{:?}", value),
+            Value::Integer(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{}", value),
+            Value::Boolean(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Value as found in the file, with its line for error messages
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub line: u64,
+    pub value: Value,
+}
+
+impl Entry {
+    /// Integers are accepted wherever a float is expected
+    pub fn as_number(&self, key: &str) -> Result<f64, ConfigError> {
+        match self.value {
+            Value::Float(value) => Ok(value),
+            Value::Integer(value) => Ok(value as f64),
+            _ => Err(self.mismatch(key, "number")),
+        }
+    }
+
+    pub fn as_str(&self, key: &str) -> Result<&str, ConfigError> {
+        match &self.value {
+            Value::String(value) => Ok(value),
+            _ => Err(self.mismatch(key, "string")),
+        }
+    }
+
+    pub fn as_bool(&self, key: &str) -> Result<bool, ConfigError> {
+        match self.value {
+            Value::Boolean(value) => Ok(value),
+            _ => Err(self.mismatch(key, "boolean")),
+        }
+    }
+
+    /// Parses a string value, e.g. into one of the policies
+    pub fn parse<T: FromStr<Err = String>>(&self, key: &str) -> Result<T, ConfigError> {
+        self.as_str(key)?
+            .parse()
+            .map_err(|err| self.invalid(format!("{} for '{}'", err, key)))
+    }
+
+    pub fn as_count(&self, key: &str) -> Result<u64, ConfigError> {
+        match self.value {
+            Value::Integer(value) if value >= 0 => Ok(value as u64),
+            _ => Err(self.mismatch(key, "non-negative integer")),
+        }
+    }
+
+    pub fn invalid(&self, message: String) -> ConfigError {
+        ConfigError::Invalid {
+            line: self.line,
+            message,
+        }
+    }
+
+    fn mismatch(&self, key: &str, expected: &str) -> ConfigError {
+        self.invalid(format!(
+            "expected a {} for '{}', found {} {}",
+            expected,
+            key,
+            self.value.type_name(),
+            self.value
+        ))
+    }
+}
+
+/// Keys of a table with the line of its header, 0 for keys before the first header
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Table {
+    pub line: u64,
+    pub entries: BTreeMap<String, Entry>,
+}
+
+/// Tables by name. Keys before the first header are in the table `""`.
+pub type Document = BTreeMap<String, Table>;
+
+pub fn parse(input: &str) -> Result<Document, ConfigError> {
+    let mut document = Document::new();
+    let mut table = String::new();
+    for (index, line) in input.lines().enumerate() {
+        let line_number = index as u64 + 1;
+        let invalid = |message: String| ConfigError::Invalid {
+            line: line_number,
+            message,
+        };
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[') {
+            let name = name
+                .strip_suffix(']')
+                .ok_or_else(|| invalid("unterminated table header".to_string()))?
+                .trim();
+            if !is_bare_key(name) {
+                return Err(invalid(format!("invalid table name '{}'", name)));
+            }
+            if document.contains_key(name) {
+                return Err(invalid(format!("table '{}' is defined twice", name)));
+            }
+            table = name.to_string();
+            document.insert(
+                table.clone(),
+                Table {
+                    line: line_number,
+                    entries: BTreeMap::new(),
+                },
+            );
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| invalid("expected 'key = value'".to_string()))?;
+        let key = key.trim();
+        if !is_bare_key(key) {
+            return Err(invalid(format!("invalid key '{}'", key)));
+        }
+        let value = parse_value(value.trim()).map_err(invalid)?;
+        let keys = &mut document.entry(table.clone()).or_default().entries;
+        if keys.contains_key(key) {
+            return Err(invalid(format!("key '{}' is defined twice", key)));
+        }
+        keys.insert(
+            key.to_string(),
+            Entry {
+                line: line_number,
+                value,
+            },
+        );
+    }
+    Ok(document)
+}
+
+/// Drops a trailing comment, leaving `#` inside strings alone
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn is_bare_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn parse_value(value: &str) -> Result<Value, String> {
+    if let Some(string) = value.strip_prefix('"') {
+        return parse_string(string).map(Value::String);
+    }
+    match value {
+        "true" => return Ok(Value::Boolean(true)),
+        "false" => return Ok(Value::Boolean(false)),
+        _ => {}
+    }
+    let number = value.replace('_', "");
+    if let Ok(integer) = number.parse() {
+        return Ok(Value::Integer(integer));
+    }
+    match number.parse() {
+        Ok(float) if value.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '+') => {
+            Ok(Value::Float(float))
+        }
+        _ => Err(format!("invalid value '{}'", value)),
+    }
+}
+
+/// Parses the rest of a basic string after its opening quote
+fn parse_string(input: &str) -> Result<String, String> {
+    let mut value = String::new();
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                return match chars.as_str().trim() {
+                    "" => Ok(value),
+                    rest => Err(format!("unexpected '{}' after string", rest)),
+                }
+            }
+            '\\' => match chars.next() {
+                Some('"') => value.push('"'),
+                Some('\\') => value.push('\\'),
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                other => return Err(format!("invalid escape '\\{}'", other.unwrap_or(' '))),
+            },
+            c => value.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::error::ConfigError;
+        use crate::toml::{parse, Value};
+
+        #[test]
+        fn tables_and_values_are_parsed() {
+            let document = parse(
+                "top = \"a # not a comment\"\n\
+                 # comment\n\
+                 [limits]\n\
+                 max = 1_000 # trailing comment\n\
+                 cap = 2.5\n\
+                 enabled = true\n",
+            )
+            .unwrap();
+
+            let limits = &document["limits"];
+            assert_eq!(
+                document[""].entries["top"].value,
+                Value::String("a # not a comment".to_string())
+            );
+            assert_eq!(limits.line, 3);
+            assert_eq!(limits.entries["max"].value, Value::Integer(1000));
+            assert_eq!(limits.entries["cap"].value, Value::Float(2.5));
+            assert_eq!(limits.entries["enabled"].value, Value::Boolean(true));
+            assert_eq!(limits.entries["cap"].line, 5);
+        }
+
+        #[test]
+        fn invalid_lines_are_reported() {
+            assert!(matches!(
+                parse("[limits]\nmax = 1\nmax = 2\n"),
+                Err(ConfigError::Invalid { line: 3, message }) if message == "key 'max' is defined twice"
+            ));
+            assert!(matches!(
+                parse("[limits\n"),
+                Err(ConfigError::Invalid { line: 1, .. })
+            ));
+            assert!(matches!(
+                parse("a = [1, 2]\n"),
+                Err(ConfigError::Invalid { line: 1, .. })
+            ));
+        }
+    }
+}
@@ -1,7 +1,14 @@
 use crate::account::ClientId;
-use serde::Deserialize;
+use crate::currency::Currency;
+use crate::timestamp::Timestamp;
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+use std::str::FromStr;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
@@ -9,17 +16,502 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    /// Moves funds from the client to the `to` client
+    Transfer,
+    /// Freezes the account of the client, only accepted from an [`Origin::Admin`] source
+    Lock,
+    /// Lifts the lock of the account, e.g. after a chargeback investigation. Only accepted from
+    /// an [`Origin::Admin`] source.
+    Unlock,
+    /// Charges the amount to the available funds, e.g. a maintenance fee. Only accepted from an
+    /// [`Origin::Admin`] source.
+    Fee,
+    /// Credits the amount to the available funds as interest. Only accepted from an
+    /// [`Origin::Admin`] source.
+    Interest,
+    /// Undoes the referenced deposit or withdrawal of the client outside of the dispute flow,
+    /// taking the deposited funds back or crediting the withdrawn ones. Only accepted from an
+    /// [`Origin::Admin`] source.
+    Reversal,
+    /// Moves the account of the client into that of the `to` client, e.g. when the same customer
+    /// was registered twice. Only accepted from an [`Origin::Admin`] source.
+    Merge,
+    /// Puts the amount of a card-style payment on hold, moving it from the available to the held
+    /// funds until it is captured or voided
+    Authorize,
+    /// Turns the referenced authorization into a withdrawal of the amount, at most the authorized
+    /// one and all of it if omitted, releasing the rest of the hold
+    Capture,
+    /// Releases the hold of the referenced authorization
+    Void,
+    /// Credits back the amount of the referenced chargeback when the merchant wins its
+    /// representment. Accepted on accounts locked by the chargeback.
+    Represent,
+    /// Manual ledger correction by finance adding the amount, negative for a debit, to the
+    /// available funds even if that leaves them negative. Disputable like a deposit or a
+    /// withdrawal of the same sign. Only accepted from an [`Origin::Admin`] source by engines
+    /// allowing adjustments.
+    Adjustment,
+    /// Closes the account of the client, which takes no further transactions. Refused while it
+    /// holds funds. Only accepted from an [`Origin::Admin`] source.
+    Close,
+    /// Sets the reserve of the funds of the client in the currency to the amount: the part of
+    /// the available funds that withdrawals, authorizations and transfers cannot take, while
+    /// still counting towards the total. Only accepted from an [`Origin::Admin`] source.
+    Reserve,
 }
 
-pub type TransactionId = u32;
+impl TransactionType {
+    pub const ALL: [TransactionType; 19] = [
+        TransactionType::Deposit,
+        TransactionType::Withdrawal,
+        TransactionType::Dispute,
+        TransactionType::Resolve,
+        TransactionType::Chargeback,
+        TransactionType::Transfer,
+        TransactionType::Lock,
+        TransactionType::Unlock,
+        TransactionType::Fee,
+        TransactionType::Interest,
+        TransactionType::Reversal,
+        TransactionType::Merge,
+        TransactionType::Authorize,
+        TransactionType::Capture,
+        TransactionType::Void,
+        TransactionType::Represent,
+        TransactionType::Adjustment,
+        TransactionType::Close,
+        TransactionType::Reserve,
+    ];
 
-#[derive(Debug, Deserialize)]
+    /// Name of the type as used in input files
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Transfer => "transfer",
+            TransactionType::Lock => "lock",
+            TransactionType::Unlock => "unlock",
+            TransactionType::Fee => "fee",
+            TransactionType::Interest => "interest",
+            TransactionType::Reversal => "reversal",
+            TransactionType::Merge => "merge",
+            TransactionType::Authorize => "authorize",
+            TransactionType::Capture => "capture",
+            TransactionType::Void => "void",
+            TransactionType::Represent => "represent",
+            TransactionType::Adjustment => "adjustment",
+            TransactionType::Close => "close",
+            TransactionType::Reserve => "reserve",
+        }
+    }
+
+    /// Administrative types that clients cannot issue themselves
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            TransactionType::Lock
+                | TransactionType::Unlock
+                | TransactionType::Fee
+                | TransactionType::Interest
+                | TransactionType::Reversal
+                | TransactionType::Merge
+                | TransactionType::Adjustment
+                | TransactionType::Close
+                | TransactionType::Reserve
+        )
+    }
+
+    /// Types whose id is a new transaction rather than a reference to an earlier one
+    pub fn has_new_id(&self) -> bool {
+        matches!(
+            self,
+            TransactionType::Deposit
+                | TransactionType::Withdrawal
+                | TransactionType::Transfer
+                | TransactionType::Authorize
+                | TransactionType::Adjustment
+        )
+    }
+
+    /// Types applied to the account owning the transaction they reference, which they never
+    /// create
+    pub fn is_reference(&self) -> bool {
+        matches!(
+            self,
+            TransactionType::Dispute
+                | TransactionType::Resolve
+                | TransactionType::Chargeback
+                | TransactionType::Capture
+                | TransactionType::Void
+                | TransactionType::Represent
+        )
+    }
+}
+
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for TransactionType {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "deposit" => Ok(TransactionType::Deposit),
+            "withdrawal" => Ok(TransactionType::Withdrawal),
+            "dispute" => Ok(TransactionType::Dispute),
+            "resolve" => Ok(TransactionType::Resolve),
+            "chargeback" => Ok(TransactionType::Chargeback),
+            "transfer" => Ok(TransactionType::Transfer),
+            "lock" => Ok(TransactionType::Lock),
+            "unlock" => Ok(TransactionType::Unlock),
+            "fee" => Ok(TransactionType::Fee),
+            "interest" => Ok(TransactionType::Interest),
+            "reversal" => Ok(TransactionType::Reversal),
+            "merge" => Ok(TransactionType::Merge),
+            "authorize" => Ok(TransactionType::Authorize),
+            "capture" => Ok(TransactionType::Capture),
+            "void" => Ok(TransactionType::Void),
+            "represent" => Ok(TransactionType::Represent),
+            "adjustment" => Ok(TransactionType::Adjustment),
+            "close" => Ok(TransactionType::Close),
+            "reserve" => Ok(TransactionType::Reserve),
+            _ => Err(format!("unknown transaction type '{}'", value)),
+        }
+    }
+}
+
+/// Identifier of a transaction, which disputes, resolves, chargebacks and the like refer back
+/// to. Every `u32` is a valid id; the type keeps ids apart from client ids, counts and amounts.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+// Laid out as a u32 across the C interface
+#[repr(transparent)]
+pub struct TransactionId(pub u32);
+
+impl TransactionId {
+    pub const MAX: TransactionId = TransactionId(u32::MAX);
+
+    /// Id `steps` after this one, none past [`TransactionId::MAX`]
+    pub fn checked_add(self, steps: u32) -> Option<Self> {
+        self.0.checked_add(steps).map(TransactionId)
+    }
+
+    /// Id `steps` after this one, wrapping around past [`TransactionId::MAX`]
+    pub fn wrapping_add(self, steps: u32) -> Self {
+        TransactionId(self.0.wrapping_add(steps))
+    }
+}
+
+impl From<u32> for TransactionId {
+    fn from(id: u32) -> Self {
+        TransactionId(id)
+    }
+}
+
+impl From<TransactionId> for u32 {
+    fn from(id: TransactionId) -> Self {
+        id.0
+    }
+}
+
+impl fmt::Display for TransactionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for TransactionId {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        value
+            .parse()
+            .map(TransactionId)
+            .map_err(|_| format!("invalid transaction id '{}'", value))
+    }
+}
+
+/// Amount of money moved by a transaction: a finite number with at most
+/// [`Amount::MAX_DECIMALS`] decimal places, never negative unless built with [`Amount::signed`].
+/// Input amounts are held to the tighter [`AmountRules`](crate::input::AmountRules) first.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default, Serialize)]
+#[serde(transparent)]
+pub struct Amount(f64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0.0);
+    /// Decimal places an amount may have
+    pub const MAX_DECIMALS: u32 = 8;
+
+    /// Fails for values that are negative, not finite or with more than
+    /// [`Amount::MAX_DECIMALS`] decimal places
+    pub fn new(value: f64) -> Result<Self, String> {
+        if value < 0.0 {
+            return Err(format!("amount {} is negative", value));
+        }
+        Self::signed(value)
+    }
+
+    /// Same as [`Amount::new`], allowing negative values such as the debits of adjustments
+    pub fn signed(value: f64) -> Result<Self, String> {
+        if !value.is_finite() {
+            return Err(format!("amount {} is not a finite number", value));
+        }
+        if !fits_decimals(value, Self::MAX_DECIMALS) {
+            return Err(format!(
+                "amount {} has more than {} decimal places",
+                value,
+                Self::MAX_DECIMALS
+            ));
+        }
+        // Negative zero would be written out as -0
+        Ok(Amount(value + 0.0))
+    }
+
+    /// Amount of a transaction of the type, failing as [`Amount::new`]. Only adjustments take
+    /// negative amounts, for debits.
+    pub fn of(transaction_type: TransactionType, value: f64) -> Result<Self, String> {
+        match transaction_type {
+            TransactionType::Adjustment => Self::signed(value),
+            _ => Self::new(value),
+        }
+    }
+
+    /// Amount the engine computed from other amounts, such as a fee, or kept in its history,
+    /// which only rounding errors could fail the checks of
+    pub(crate) fn unchecked(value: f64) -> Self {
+        Amount(value)
+    }
+
+    pub fn value(self) -> f64 {
+        self.0
+    }
+
+    pub fn abs(self) -> Amount {
+        Amount(self.0.abs())
+    }
+}
+
+/// `value` in units of its last allowed decimal place, with the error of the binary
+/// representation of decimal amounts that is tolerated around it
+pub(crate) fn scale_to_decimals(value: f64, decimals: u32) -> (f64, f64) {
+    let scaled = value * 10f64.powf(f64::from(decimals));
+    (scaled, scaled.abs().max(1.0) * 1e-12)
+}
+
+/// Whether `value` has at most `decimals` decimal places, the rule of both [`Amount`] and
+/// [`AmountRules`](crate::input::AmountRules)
+pub(crate) fn fits_decimals(value: f64, decimals: u32) -> bool {
+    let (scaled, tolerance) = scale_to_decimals(value, decimals);
+    (scaled - scaled.round()).abs() <= tolerance
+}
+
+impl TryFrom<f64> for Amount {
+    type Error = String;
+
+    fn try_from(value: f64) -> Result<Self, Self::Error> {
+        Amount::new(value)
+    }
+}
+
+impl From<Amount> for f64 {
+    fn from(amount: Amount) -> Self {
+        amount.0
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for Amount {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let amount = value
+            .parse()
+            .map_err(|_| format!("invalid amount '{}'", value))?;
+        Amount::new(amount)
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Amount::new(f64::deserialize(deserializer)?).map_err(de::Error::custom)
+    }
+}
+
+/// Sum of two amounts, which keeps their decimal places
+impl Add for Amount {
+    type Output = Amount;
+
+    fn add(self, other: Amount) -> Amount {
+        Amount(self.0 + other.0)
+    }
+}
+
+impl AddAssign for Amount {
+    fn add_assign(&mut self, other: Amount) {
+        self.0 += other.0;
+    }
+}
+
+impl Sum for Amount {
+    fn sum<I: Iterator<Item = Amount>>(amounts: I) -> Self {
+        amounts.fold(Amount::ZERO, Add::add)
+    }
+}
+
+impl Neg for Amount {
+    type Output = Amount;
+
+    fn neg(self) -> Amount {
+        Amount(-self.0 + 0.0)
+    }
+}
+
+/// Balances stay `f64`, and take amounts as they are
+impl Add<Amount> for f64 {
+    type Output = f64;
+
+    fn add(self, amount: Amount) -> f64 {
+        self + amount.0
+    }
+}
+
+impl Sub<Amount> for f64 {
+    type Output = f64;
+
+    fn sub(self, amount: Amount) -> f64 {
+        self - amount.0
+    }
+}
+
+impl AddAssign<Amount> for f64 {
+    fn add_assign(&mut self, amount: Amount) {
+        *self += amount.0;
+    }
+}
+
+impl SubAssign<Amount> for f64 {
+    fn sub_assign(&mut self, amount: Amount) {
+        *self -= amount.0;
+    }
+}
+
+/// Who submitted a transaction. Administrative types are rejected unless they come from an
+/// admin source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Origin {
+    #[default]
+    Client,
+    Admin,
+}
+
+/// Transaction as applied by the engine. Rows of the input are read into one by the
+/// [sources](crate::input) of the [`input`](crate::input) module, which check the amounts.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Transaction {
-    #[serde(rename = "type")]
     pub transaction_type: TransactionType,
-    #[serde(rename = "client")]
     pub client_id: ClientId,
-    #[serde(rename = "tx")]
     pub transaction_id: TransactionId,
-    pub amount: Option<f64>,
+    pub amount: Option<Amount>,
+    /// Client credited by a transfer, the debited client being `client_id`
+    pub to_client_id: Option<ClientId>,
+    pub currency: Option<Currency>,
+    /// When the transaction took effect, if the input says so
+    pub timestamp: Option<Timestamp>,
+    /// Extra columns of the input such as a merchant or memo, captured with
+    /// [`ColumnMapping::capture`](crate::input::ColumnMapping::capture). The engine ignores
+    /// them, but keeps them in the ledger and passes them on to audit events.
+    pub metadata: HashMap<String, String>,
+}
+
+impl Transaction {
+    /// Metadata in order of name, the order in which it is written out
+    pub fn sorted_metadata(&self) -> Vec<(&str, &str)> {
+        let mut metadata: Vec<_> = self
+            .metadata
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        metadata.sort_unstable();
+        metadata
+    }
+}
+
+/// Transaction of the tests, in the default currency and without destination or timestamp
+#[cfg(test)]
+pub(crate) fn transaction(
+    transaction_type: TransactionType,
+    client_id: ClientId,
+    transaction_id: u32,
+    amount: Option<f64>,
+) -> Transaction {
+    Transaction {
+        transaction_type,
+        client_id,
+        transaction_id: TransactionId(transaction_id),
+        amount: amount.map(Amount::unchecked),
+        to_client_id: None,
+        currency: None,
+        timestamp: None,
+        metadata: Default::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::transaction::{Amount, TransactionType};
+
+        #[test]
+        fn amounts_are_checked_when_built() {
+            assert_eq!(Amount::new(1.5).map(Amount::value), Ok(1.5));
+            assert_eq!(Amount::new(0.12345678).map(Amount::value), Ok(0.12345678));
+            assert!(Amount::new(0.123456789).is_err());
+            assert!(Amount::new(-1.0).is_err());
+            assert!(Amount::new(f64::NAN).is_err());
+            assert!(Amount::new(f64::INFINITY).is_err());
+            assert_eq!(Amount::signed(-1.5).map(Amount::value), Ok(-1.5));
+            assert!(Amount::of(TransactionType::Deposit, -1.5).is_err());
+            assert!(Amount::of(TransactionType::Adjustment, -1.5).is_ok());
+            assert_eq!("2.5".parse(), Amount::new(2.5));
+            assert!("2.5.1".parse::<Amount>().is_err());
+        }
+
+        #[test]
+        fn amounts_add_up_and_display_as_numbers() {
+            let amount = Amount::new(1.25).unwrap();
+            assert_eq!((amount + amount).to_string(), "2.5");
+            assert_eq!(
+                [amount; 4].into_iter().sum::<Amount>(),
+                Amount::new(5.0).unwrap()
+            );
+            assert_eq!(10.0 - amount, 8.75);
+            assert_eq!((-Amount::ZERO).to_string(), "0");
+            let mut balance = 1.0;
+            balance += amount;
+            assert_eq!(balance, 2.25);
+        }
+
+        #[test]
+        fn amounts_are_deserialized_as_numbers() {
+            let mut reader = csv::Reader::from_reader("amount\n1.5\n-1\n".as_bytes());
+            let amounts: Vec<_> = reader.deserialize::<(Amount,)>().collect();
+            assert_eq!(amounts[0].as_ref().unwrap().0, Amount::new(1.5).unwrap());
+            assert!(amounts[1].is_err());
+        }
+    }
 }
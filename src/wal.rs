@@ -0,0 +1,398 @@
+//! Write-ahead log of the transactions given to an engine.
+//!
+//! Every transaction is appended before it is applied, one csv line each:
+//!
+//! ```text
+//! <lsn>,<type>,<client>,<tx>,<amount>[,<to>[,<currency>[,<timestamp>]]]
+//! ```
+//!
+//! Transactions read from an input delivered at least once also carry their
+//! [`InputPosition`] in three more fields, `<source>,<offset>,<index>`, after all of the
+//! optional ones.
+//!
+//! The log sequence number (lsn) counts the transactions executed by the engine and is stored in
+//! snapshots, so recovery replays only the entries the snapshot does not include. Rejected
+//! transactions are logged too; replaying them rejects them again since the engine is
+//! deterministic. A line without its trailing newline is the tail of an interrupted append and
+//! is dropped when the log is opened.
+
+use crate::ingest::InputPosition;
+use crate::input::deserialize_row;
+use crate::transaction::Transaction;
+use csv::StringRecord;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Transaction read back from the log
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalEntry {
+    pub lsn: u64,
+    pub transaction: Transaction,
+    /// Where the transaction was read from, if executed at a position
+    pub position: Option<InputPosition>,
+}
+
+pub struct WriteAheadLog {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    /// Appends since the last fsync
+    unsynced: usize,
+    /// Number of appends after which the log is synced to disk
+    sync_every: usize,
+}
+
+impl WriteAheadLog {
+    /// Opens the log at `path`, creating it if needed, and returns the entries already in it.
+    /// Appends are synced to disk every `sync_every` entries and on [`WriteAheadLog::sync`].
+    pub fn open<P: AsRef<Path>>(path: P, sync_every: usize) -> io::Result<(Self, Vec<WalEntry>)> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        let complete = contents
+            .iter()
+            .rposition(|&byte| byte == b'\n')
+            .map_or(0, |position| position + 1);
+        if complete < contents.len() {
+            file.set_len(complete as u64)?;
+            file.seek(SeekFrom::End(0))?;
+        }
+        let entries = read_entries(&contents[..complete])?;
+
+        let wal = WriteAheadLog {
+            path,
+            writer: BufWriter::new(file),
+            unsynced: 0,
+            sync_every: sync_every.max(1),
+        };
+        Ok((wal, entries))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends the transaction, with the position it was read from if any
+    pub(crate) fn append(
+        &mut self,
+        lsn: u64,
+        transaction: &Transaction,
+        position: Option<&InputPosition>,
+    ) -> io::Result<()> {
+        write!(
+            self.writer,
+            "{},{},{},{},{}",
+            lsn,
+            transaction.transaction_type,
+            transaction.client_id,
+            transaction.transaction_id,
+            transaction
+                .amount
+                .map_or(String::new(), |amount| amount.to_string())
+        )?;
+        let optional = [
+            transaction
+                .to_client_id
+                .map(|client_id| client_id.to_string()),
+            transaction.currency.map(|currency| currency.to_string()),
+            transaction.timestamp.map(|timestamp| timestamp.to_string()),
+        ];
+        // Trailing fields that are not set are left out, unless a position follows them
+        let set = match position {
+            Some(_) => optional.len(),
+            None => optional
+                .iter()
+                .rposition(Option::is_some)
+                .map_or(0, |last| last + 1),
+        };
+        for field in optional.into_iter().take(set) {
+            write!(self.writer, ",{}", field.unwrap_or_default())?;
+        }
+        if let Some(position) = position {
+            let source = &position.source;
+            if source.contains([',', '"', '\n', '\r']) {
+                write!(self.writer, ",\"{}\"", source.replace('"', "\"\""))?;
+            } else {
+                write!(self.writer, ",{}", source)?;
+            }
+            write!(self.writer, ",{},{}", position.offset, position.index)?;
+        }
+        writeln!(self.writer)?;
+        self.unsynced += 1;
+        if self.unsynced >= self.sync_every {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Writes buffered entries and waits until they are on disk
+    pub fn sync(&mut self) -> io::Result<()> {
+        if self.unsynced > 0 {
+            self.writer.flush()?;
+            self.writer.get_ref().sync_data()?;
+            self.unsynced = 0;
+        }
+        Ok(())
+    }
+
+    /// Drops every entry, once they are all included in a snapshot
+    pub(crate) fn truncate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().set_len(0)?;
+        self.writer.get_ref().sync_data()?;
+        self.unsynced = 0;
+        Ok(())
+    }
+}
+
+/// Fields of a transaction after the lsn, with every optional one
+const TRANSACTION_FIELDS: usize = 7;
+
+fn read_entries(contents: &[u8]) -> io::Result<Vec<WalEntry>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(contents);
+    let invalid = |line: u64, message: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("corrupt write-ahead log on line {}: {}", line, message),
+        )
+    };
+
+    let mut entries = Vec::new();
+    let mut record = StringRecord::new();
+    let mut line = 0;
+    while reader
+        .read_record(&mut record)
+        .map_err(|err| invalid(line + 1, err.to_string()))?
+    {
+        line += 1;
+        let lsn = record
+            .get(0)
+            .unwrap_or_default()
+            .parse()
+            .map_err(|_| invalid(line, "invalid lsn".to_string()))?;
+        let row: StringRecord = record.iter().skip(1).take(TRANSACTION_FIELDS).collect();
+        let transaction = deserialize_row(&row, None).map_err(|err| invalid(line, err))?;
+        let position = match record.len() {
+            length if length <= TRANSACTION_FIELDS + 1 => None,
+            length if length == TRANSACTION_FIELDS + 4 => {
+                let invalid_position = || invalid(line, "invalid input position".to_string());
+                Some(InputPosition {
+                    source: record[TRANSACTION_FIELDS + 1].to_string(),
+                    offset: record[TRANSACTION_FIELDS + 2]
+                        .parse()
+                        .map_err(|_| invalid_position())?,
+                    index: record[TRANSACTION_FIELDS + 3]
+                        .parse()
+                        .map_err(|_| invalid_position())?,
+                })
+            }
+            _ => return Err(invalid(line, "invalid input position".to_string())),
+        };
+        entries.push(WalEntry {
+            lsn,
+            transaction,
+            position,
+        });
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::engine::TransactionEngine;
+        use crate::ingest::InputPosition;
+        use crate::transaction::{Amount, Transaction, TransactionId, TransactionType};
+        use crate::wal::{WalEntry, WriteAheadLog};
+        use std::fs;
+        use std::path::PathBuf;
+
+        fn temp_path(name: &str) -> PathBuf {
+            let path = std::env::temp_dir().join(name);
+            let _ = fs::remove_file(&path);
+            path
+        }
+
+        fn deposit(transaction_id: u32, amount: f64) -> Transaction {
+            Transaction {
+                transaction_type: TransactionType::Deposit,
+                client_id: 1,
+                transaction_id: TransactionId(transaction_id),
+                amount: Some(Amount::unchecked(amount)),
+                to_client_id: None,
+                currency: None,
+                timestamp: None,
+                metadata: Default::default(),
+            }
+        }
+
+        #[test]
+        fn optional_fields_are_read_back() {
+            let path = temp_path("wal_optional_fields_are_read_back.wal");
+            let timestamped = Transaction {
+                timestamp: Some("2024-03-01T12:30:00Z".parse().unwrap()),
+                metadata: Default::default(),
+                ..deposit(1, 1.5)
+            };
+            let transfer = Transaction {
+                transaction_type: TransactionType::Transfer,
+                to_client_id: Some(2),
+                currency: Some("EUR".parse().unwrap()),
+                ..timestamped.clone()
+            };
+            let (mut wal, _) = WriteAheadLog::open(&path, 1).unwrap();
+            wal.append(1, &timestamped, None).unwrap();
+            wal.append(2, &transfer, None).unwrap();
+            drop(wal);
+
+            let (_, entries) = WriteAheadLog::open(&path, 1).unwrap();
+            let contents = fs::read_to_string(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+
+            assert!(contents.starts_with("1,deposit,1,1,1.5,,,2024-03-01T12:30:00Z\n"));
+            assert_eq!(
+                entries
+                    .into_iter()
+                    .map(|entry| entry.transaction)
+                    .collect::<Vec<_>>(),
+                vec![timestamped, transfer]
+            );
+        }
+
+        #[test]
+        fn appended_entries_are_read_back() {
+            let path = temp_path("wal_appended_entries_are_read_back.wal");
+            let (mut wal, entries) = WriteAheadLog::open(&path, 2).unwrap();
+            assert!(entries.is_empty());
+            wal.append(1, &deposit(1, 1.5), None).unwrap();
+            wal.append(2, &deposit(2, 0.1), None).unwrap();
+            drop(wal);
+
+            let (_, entries) = WriteAheadLog::open(&path, 2).unwrap();
+            fs::remove_file(&path).unwrap();
+
+            assert_eq!(
+                entries,
+                vec![
+                    WalEntry {
+                        lsn: 1,
+                        transaction: deposit(1, 1.5),
+                        position: None,
+                    },
+                    WalEntry {
+                        lsn: 2,
+                        transaction: deposit(2, 0.1),
+                        position: None,
+                    },
+                ]
+            );
+        }
+
+        #[test]
+        fn input_positions_are_read_back() {
+            let path = temp_path("wal_input_positions_are_read_back.wal");
+            let position = InputPosition::new("payments,eu", 7, 1);
+            let (mut wal, _) = WriteAheadLog::open(&path, 1).unwrap();
+            wal.append(1, &deposit(1, 1.5), Some(&position)).unwrap();
+            wal.append(2, &deposit(2, 0.5), None).unwrap();
+            drop(wal);
+
+            let (_, entries) = WriteAheadLog::open(&path, 1).unwrap();
+            let contents = fs::read_to_string(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+
+            assert!(contents.starts_with("1,deposit,1,1,1.5,,,,\"payments,eu\",7,1\n"));
+            assert_eq!(entries[0].position, Some(position));
+            assert_eq!(entries[0].transaction, deposit(1, 1.5));
+            assert_eq!(entries[1].position, None);
+        }
+
+        #[test]
+        fn interrupted_append_is_dropped() {
+            let path = temp_path("wal_interrupted_append_is_dropped.wal");
+            fs::write(&path, "1,deposit,1,1,1.5\n2,deposit,1,2,2.").unwrap();
+
+            let (mut wal, entries) = WriteAheadLog::open(&path, 1).unwrap();
+            wal.append(2, &deposit(2, 3.0), None).unwrap();
+            let contents = fs::read_to_string(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+
+            assert_eq!(entries.len(), 1);
+            assert_eq!(contents, "1,deposit,1,1,1.5\n2,deposit,1,2,3\n");
+        }
+
+        #[test]
+        fn corrupt_entry_is_an_error() {
+            let path = temp_path("wal_corrupt_entry_is_an_error.wal");
+            fs::write(&path, "1,deposit,1,1,1.5\nx,deposit,1,2,2\n").unwrap();
+
+            let err = WriteAheadLog::open(&path, 1).err().unwrap();
+            fs::remove_file(&path).unwrap();
+
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+            assert!(err.to_string().contains("line 2"));
+        }
+
+        #[test]
+        fn engine_recovers_from_snapshot_and_log() {
+            let snapshot = temp_path("wal_engine_recovers.snapshot");
+            let log = temp_path("wal_engine_recovers.wal");
+            let dispute = Transaction {
+                transaction_type: TransactionType::Dispute,
+                client_id: 1,
+                transaction_id: TransactionId(1),
+                amount: None,
+                to_client_id: None,
+                currency: None,
+                timestamp: None,
+                metadata: Default::default(),
+            };
+            let mut engine = TransactionEngine::new();
+            engine.open_wal(&log, 1).unwrap();
+            engine.execute(deposit(1, 2.0)).unwrap();
+            engine.checkpoint(&snapshot).unwrap();
+            engine.execute(deposit(2, 1.0)).unwrap();
+            engine.execute(dispute).unwrap();
+            assert!(engine.execute(deposit(2, 1.0)).is_err());
+            drop(engine);
+
+            let mut recovered = TransactionEngine::restore(&snapshot).unwrap();
+            let replayed = recovered.open_wal(&log, 1).unwrap();
+            fs::remove_file(&snapshot).unwrap();
+            fs::remove_file(&log).unwrap();
+
+            assert_eq!(replayed, 3);
+            assert_eq!(recovered.accounts[&1].get_available_funds(), 1.0);
+            assert_eq!(recovered.accounts[&1].get_held_funds(), 2.0);
+        }
+
+        #[test]
+        fn entries_in_snapshot_are_not_replayed() {
+            let snapshot = temp_path("wal_entries_in_snapshot.snapshot");
+            let log = temp_path("wal_entries_in_snapshot.wal");
+            let mut engine = TransactionEngine::new();
+            engine.open_wal(&log, 1).unwrap();
+            engine.execute(deposit(1, 2.0)).unwrap();
+            // Crash after the snapshot is written but before the log is truncated
+            engine.snapshot(&snapshot).unwrap();
+            drop(engine);
+
+            let mut recovered = TransactionEngine::restore(&snapshot).unwrap();
+            let replayed = recovered.open_wal(&log, 1).unwrap();
+            fs::remove_file(&snapshot).unwrap();
+            fs::remove_file(&log).unwrap();
+
+            assert_eq!(replayed, 0);
+            assert_eq!(recovered.accounts[&1].get_available_funds(), 2.0);
+        }
+    }
+}
@@ -0,0 +1,541 @@
+//! Webhooks notifying risk systems in near real time when the engine behind a
+//! [`Server`](crate::server::Server) locks an account or applies a chargeback.
+//!
+//! Endpoints are read from the `[webhooks]` table of the configuration file, or given on the
+//! command line:
+//!
+//! ```toml
+//! [webhooks]
+//! urls = "http://risk.internal:8080/hooks"   # comma separated, http only
+//! secret = "shared-secret"                   # key of the signatures, required
+//! max_attempts = 5                           # deliveries tried per event, 5 by default
+//! backoff_ms = 1_000                         # before the first retry, doubled after each
+//! ```
+//!
+//! Every event is posted to every endpoint as a json object:
+//!
+//! ```json
+//! {"id":7,"event":"chargeback","client":1,"tx":3,"occurred_at":1760400000}
+//! {"id":8,"event":"account_locked","client":1,"occurred_at":1760400000}
+//! ```
+//!
+//! `id` orders the events of the server and identifies retries of the same event, `occurred_at`
+//! is in seconds since the Unix epoch. The `X-Webhook-Signature` header carries
+//! `sha256=<hex>`, the HMAC-SHA256 of the body keyed with the secret, which receivers recompute
+//! to authenticate the payload.
+//!
+//! Events are queued and delivered by a thread per endpoint, so the engine never waits for an
+//! endpoint. Answers other than `2xx`, refused connections and timeouts are retried until
+//! `max_attempts` is reached; events arriving while the queue of an endpoint is full are dropped.
+//! Deliveries, retries, failures and drops are counted per endpoint in [`WebhookStats`], which the
+//! server appends to `GET /metrics`.
+
+use crate::account::{ClientId, DisputeState};
+use crate::error::ConfigError;
+use crate::log;
+use crate::observer::EngineObserver;
+use crate::sha256::{hmac, to_hex};
+use crate::toml;
+use crate::transaction::TransactionId;
+use std::fmt::{self, Write as _};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Deliveries tried per event unless configured
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+
+/// Wait before the first retry unless configured
+pub const DEFAULT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest wait between two attempts, however many failed
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Events waiting for an endpoint before new ones are dropped
+const QUEUE_CAPACITY: usize = 1024;
+
+/// Timeout of connecting to an endpoint, and of every read and write after
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Endpoint events are posted to, `http://<host>[:<port>][/<path>]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebhookUrl {
+    pub host: String,
+    pub port: u16,
+    /// Starts with `/`
+    pub path: String,
+}
+
+impl FromStr for WebhookUrl {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid webhook url '{}'", value);
+        let rest = match value.strip_prefix("http://") {
+            Some(rest) => rest,
+            None if value.starts_with("https://") => {
+                return Err(format!(
+                    "webhook url '{}' is https, only http is supported, e.g. through a TLS \
+                     terminating proxy",
+                    value
+                ))
+            }
+            None => return Err(invalid()),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => (authority, 80),
+        };
+        if host.is_empty() || host.contains(char::is_whitespace) || path.contains(' ') {
+            return Err(invalid());
+        }
+        Ok(WebhookUrl {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for WebhookUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}:{}{}", self.host, self.port, self.path)
+    }
+}
+
+/// Endpoints and delivery settings, none unless set
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Webhooks {
+    pub urls: Vec<WebhookUrl>,
+    /// Key of the signatures, required with any url
+    pub secret: String,
+    /// [`DEFAULT_MAX_ATTEMPTS`] unless given
+    pub max_attempts: Option<u32>,
+    /// [`DEFAULT_BACKOFF`] unless given
+    pub backoff: Option<Duration>,
+}
+
+impl Webhooks {
+    pub fn is_enabled(&self) -> bool {
+        !self.urls.is_empty()
+    }
+
+    pub(crate) fn from_table(table: &toml::Table) -> Result<Self, ConfigError> {
+        let mut webhooks = Webhooks::default();
+        for (key, entry) in &table.entries {
+            match key.as_str() {
+                "urls" => {
+                    webhooks.urls = entry
+                        .as_str(key)?
+                        .split(',')
+                        .map(|url| url.trim().parse())
+                        .collect::<Result<_, _>>()
+                        .map_err(|err| entry.invalid(err))?
+                }
+                "secret" => webhooks.secret = entry.as_str(key)?.to_string(),
+                "max_attempts" | "backoff_ms" => {
+                    let number = entry.as_number(key)?;
+                    if number < 1.0 || number.fract() != 0.0 || number > f64::from(u32::MAX) {
+                        return Err(entry.invalid(format!("'{}' must be a positive integer", key)));
+                    }
+                    if key == "max_attempts" {
+                        webhooks.max_attempts = Some(number as u32);
+                    } else {
+                        webhooks.backoff = Some(Duration::from_millis(number as u64));
+                    }
+                }
+                _ => return Err(entry.invalid(format!("unknown key '{}' in [webhooks]", key))),
+            }
+        }
+        Ok(webhooks)
+    }
+}
+
+/// What risk systems are notified of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookEvent {
+    AccountLocked {
+        client_id: ClientId,
+    },
+    Chargeback {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    },
+}
+
+impl WebhookEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WebhookEvent::AccountLocked { .. } => "account_locked",
+            WebhookEvent::Chargeback { .. } => "chargeback",
+        }
+    }
+
+    /// Payload of the event, see the [module](self) documentation
+    pub fn to_json(&self, id: u64, occurred_at: u64) -> String {
+        match self {
+            WebhookEvent::AccountLocked { client_id } => format!(
+                "{{\"id\":{},\"event\":\"{}\",\"client\":{},\"occurred_at\":{}}}",
+                id,
+                self.name(),
+                client_id,
+                occurred_at
+            ),
+            WebhookEvent::Chargeback {
+                client_id,
+                transaction_id,
+            } => format!(
+                "{{\"id\":{},\"event\":\"{}\",\"client\":{},\"tx\":{},\"occurred_at\":{}}}",
+                id,
+                self.name(),
+                client_id,
+                transaction_id,
+                occurred_at
+            ),
+        }
+    }
+}
+
+/// Outcome of the deliveries to one endpoint
+#[derive(Debug, Default)]
+pub struct EndpointStats {
+    pub delivered: AtomicU64,
+    /// Attempts that failed and were tried again
+    pub retried: AtomicU64,
+    /// Events given up on after their last attempt
+    pub failed: AtomicU64,
+    /// Events not queued as the endpoint was too far behind
+    pub dropped: AtomicU64,
+}
+
+/// Outcome of the deliveries to every endpoint, updated as they happen
+#[derive(Debug, Default)]
+pub struct WebhookStats {
+    pub endpoints: Vec<(WebhookUrl, EndpointStats)>,
+}
+
+impl WebhookStats {
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        // Writing to a string cannot fail
+        let _ = self.write_prometheus(&mut out);
+        out
+    }
+
+    fn write_prometheus(&self, out: &mut String) -> fmt::Result {
+        writeln!(out, "# TYPE webhook_deliveries_total counter")?;
+        for (url, stats) in &self.endpoints {
+            for (status, count) in [("delivered", &stats.delivered), ("failed", &stats.failed)] {
+                writeln!(
+                    out,
+                    "webhook_deliveries_total{{url=\"{}\",status=\"{}\"}} {}",
+                    url,
+                    status,
+                    count.load(Ordering::Relaxed)
+                )?;
+            }
+        }
+        writeln!(out, "# TYPE webhook_retries_total counter")?;
+        for (url, stats) in &self.endpoints {
+            writeln!(
+                out,
+                "webhook_retries_total{{url=\"{}\"}} {}",
+                url,
+                stats.retried.load(Ordering::Relaxed)
+            )?;
+        }
+        writeln!(out, "# TYPE webhook_dropped_events_total counter")?;
+        for (url, stats) in &self.endpoints {
+            writeln!(
+                out,
+                "webhook_dropped_events_total{{url=\"{}\"}} {}",
+                url,
+                stats.dropped.load(Ordering::Relaxed)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Observer queueing the locks and chargebacks of the engine for the delivery threads of the
+/// endpoints, see the [module](self) documentation
+pub struct WebhookNotifier {
+    queues: Vec<SyncSender<Arc<str>>>,
+    stats: Arc<WebhookStats>,
+    next_id: u64,
+}
+
+impl WebhookNotifier {
+    /// Starts a delivery thread per endpoint, which ends once the notifier is dropped and the
+    /// events queued were delivered or given up on
+    pub fn start(webhooks: &Webhooks) -> Self {
+        let stats = Arc::new(WebhookStats {
+            endpoints: webhooks
+                .urls
+                .iter()
+                .map(|url| (url.clone(), EndpointStats::default()))
+                .collect(),
+        });
+        let queues = (0..webhooks.urls.len())
+            .map(|endpoint| {
+                let (sender, receiver) = mpsc::sync_channel(QUEUE_CAPACITY);
+                let delivery = Delivery {
+                    secret: webhooks.secret.clone(),
+                    max_attempts: webhooks.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS).max(1),
+                    backoff: webhooks.backoff.unwrap_or(DEFAULT_BACKOFF),
+                    stats: Arc::clone(&stats),
+                    endpoint,
+                };
+                thread::spawn(move || delivery.run(receiver));
+                sender
+            })
+            .collect();
+        WebhookNotifier {
+            queues,
+            stats,
+            next_id: 1,
+        }
+    }
+
+    pub fn stats(&self) -> Arc<WebhookStats> {
+        Arc::clone(&self.stats)
+    }
+
+    fn notify(&mut self, event: WebhookEvent) {
+        let occurred_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        let payload: Arc<str> = Arc::from(event.to_json(self.next_id, occurred_at));
+        self.next_id += 1;
+        for (queue, (_, stats)) in self.queues.iter().zip(&self.stats.endpoints) {
+            match queue.try_send(Arc::clone(&payload)) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => {
+                    stats.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
+
+impl EngineObserver for WebhookNotifier {
+    fn on_dispute(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        state: DisputeState,
+    ) {
+        if state == DisputeState::ChargedBack {
+            self.notify(WebhookEvent::Chargeback {
+                client_id,
+                transaction_id,
+            });
+        }
+    }
+
+    fn on_lock_changed(&mut self, client_id: ClientId, locked: bool) {
+        if locked {
+            self.notify(WebhookEvent::AccountLocked { client_id });
+        }
+    }
+}
+
+/// Delivers the events queued for one endpoint
+struct Delivery {
+    secret: String,
+    max_attempts: u32,
+    backoff: Duration,
+    stats: Arc<WebhookStats>,
+    endpoint: usize,
+}
+
+impl Delivery {
+    fn run(self, receiver: Receiver<Arc<str>>) {
+        let (url, stats) = &self.stats.endpoints[self.endpoint];
+        for payload in receiver {
+            let signature = to_hex(&hmac(self.secret.as_bytes(), payload.as_bytes()));
+            let mut backoff = self.backoff;
+            for attempt in 1..=self.max_attempts {
+                let error = match post(url, &payload, &signature) {
+                    Ok(status) if (200..300).contains(&status) => {
+                        stats.delivered.fetch_add(1, Ordering::Relaxed);
+                        break;
+                    }
+                    Ok(status) => format!("answered {}", status),
+                    Err(err) => err.to_string(),
+                };
+                if attempt == self.max_attempts {
+                    stats.failed.fetch_add(1, Ordering::Relaxed);
+                    log::warn(
+                        "Webhook delivery failed",
+                        &[("url", url), ("attempts", &attempt), ("error", &error)],
+                    );
+                    break;
+                }
+                log::debug(
+                    "Retrying webhook delivery",
+                    &[("url", url), ("attempt", &attempt), ("error", &error)],
+                );
+                stats.retried.fetch_add(1, Ordering::Relaxed);
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+/// Posts the payload, returning the status of the answer
+fn post(url: &WebhookUrl, payload: &str, signature: &str) -> io::Result<u16> {
+    let address = (url.host.as_str(), url.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host has no address"))?;
+    let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\n\
+         Host: {}:{}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         X-Webhook-Signature: sha256={}\r\n\
+         Connection: close\r\n\r\n{}",
+        url.path,
+        url.host,
+        url.port,
+        payload.len(),
+        signature,
+        payload
+    )?;
+    stream.flush()?;
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line)?;
+    status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed answer"))
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::engine::TransactionEngine;
+        use crate::sha256::{hmac, to_hex};
+        use crate::transaction::{transaction, TransactionType};
+        use crate::webhook::{WebhookNotifier, WebhookUrl, Webhooks};
+        use std::io::{BufRead, BufReader, Write};
+        use std::net::TcpListener;
+        use std::sync::atomic::Ordering;
+        use std::thread;
+        use std::time::{Duration, Instant};
+
+        /// Signature header and body of a request
+        fn read_request(reader: &mut impl BufRead) -> (String, String) {
+            let mut signature = String::new();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(": ") {
+                    match name {
+                        "Content-Length" => length = value.parse().unwrap(),
+                        "X-Webhook-Signature" => signature = value.to_string(),
+                        _ => {}
+                    }
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            (signature, String::from_utf8(body).unwrap())
+        }
+
+        #[test]
+        fn urls_are_parsed() {
+            assert_eq!(
+                "http://risk.internal:8080/hooks/engine".parse(),
+                Ok(WebhookUrl {
+                    host: "risk.internal".to_string(),
+                    port: 8080,
+                    path: "/hooks/engine".to_string(),
+                })
+            );
+            let url: WebhookUrl = "http://localhost".parse().unwrap();
+            assert_eq!(url.to_string(), "http://localhost:80/");
+            assert!("https://localhost/hooks".parse::<WebhookUrl>().is_err());
+            assert!("http://:80/hooks".parse::<WebhookUrl>().is_err());
+            assert!("localhost:80".parse::<WebhookUrl>().is_err());
+        }
+
+        #[test]
+        fn chargebacks_and_locks_are_posted_signed_and_retried() {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/hooks", listener.local_addr().unwrap());
+            // The first attempt fails, the retry and the next event are delivered
+            let endpoint = thread::spawn(move || {
+                let mut requests = Vec::new();
+                for status in ["500 Internal Server Error", "200 OK", "204 No Content"] {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    requests.push(read_request(&mut BufReader::new(&stream)));
+                    write!(stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+                }
+                requests
+            });
+            let notifier = WebhookNotifier::start(&Webhooks {
+                urls: vec![url.parse().unwrap()],
+                secret: "s3cret".to_string(),
+                max_attempts: Some(3),
+                backoff: Some(Duration::from_millis(1)),
+            });
+            let stats = notifier.stats();
+            let mut engine = TransactionEngine::new();
+            engine.subscribe(Box::new(notifier));
+            for transaction in [
+                transaction(TransactionType::Deposit, 1, 1, Some(5.0)),
+                transaction(TransactionType::Dispute, 1, 1, None),
+                transaction(TransactionType::Chargeback, 1, 1, None),
+            ] {
+                engine.execute(transaction).unwrap();
+            }
+
+            let requests = endpoint.join().unwrap();
+            let (_, endpoint) = &stats.endpoints[0];
+            let started = Instant::now();
+            while endpoint.delivered.load(Ordering::Relaxed) < 2 {
+                assert!(started.elapsed() < Duration::from_secs(10));
+                thread::sleep(Duration::from_millis(1));
+            }
+            assert_eq!(endpoint.retried.load(Ordering::Relaxed), 1);
+            assert_eq!(endpoint.failed.load(Ordering::Relaxed), 0);
+            assert_eq!(requests[0], requests[1]);
+            for (signature, body) in &requests {
+                let expected = to_hex(&hmac(b"s3cret", body.as_bytes()));
+                assert_eq!(*signature, format!("sha256={}", expected));
+            }
+            assert!(requests[1]
+                .1
+                .starts_with("{\"id\":1,\"event\":\"chargeback\",\"client\":1,\"tx\":1,"));
+            assert!(requests[2]
+                .1
+                .starts_with("{\"id\":2,\"event\":\"account_locked\",\"client\":1,"));
+            assert!(stats.to_prometheus().contains(&format!(
+                "webhook_deliveries_total{{url=\"{}\",status=\"delivered\"}} 2",
+                url
+            )));
+        }
+    }
+}
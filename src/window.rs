@@ -0,0 +1,378 @@
+//! Micro-batching of a stream of transactions. [`Windows`] cut what an engine executes into
+//! windows of [`WindowPolicy::transactions`] transactions or [`WindowPolicy::interval`] of
+//! wall-clock time, whichever comes first. When a window closes, its [`WindowReport`] and the
+//! snapshots of the accounts it changed go to a [`WindowSink`], so that a long running input such
+//! as stdin yields results as it goes rather than once at the end.
+//!
+//! Windows are only closed by [`Windows::tick`], called after every transaction, and by
+//! [`Windows::finish`]: a stream that goes quiet closes its window with the next transaction.
+//!
+//! ```
+//! use rust_coding_test::{
+//!     Amount, Transaction, TransactionEngine, TransactionId, TransactionType, WindowPolicy,
+//!     Windows,
+//! };
+//! use std::sync::mpsc;
+//!
+//! let (sender, receiver) = mpsc::channel();
+//! let mut engine = TransactionEngine::new();
+//! let policy = WindowPolicy {
+//!     transactions: Some(2),
+//!     ..WindowPolicy::default()
+//! };
+//! let mut windows = Windows::new(&mut engine, policy, Box::new(sender));
+//! for transaction_id in 1..=3 {
+//!     engine
+//!         .execute(Transaction {
+//!             transaction_type: TransactionType::Deposit,
+//!             client_id: 1,
+//!             transaction_id: TransactionId(transaction_id),
+//!             amount: Some(Amount::new(1.0).unwrap()),
+//!             to_client_id: None,
+//!             currency: None,
+//!             timestamp: None,
+//!             metadata: Default::default(),
+//!         })
+//!         .unwrap();
+//!     windows.tick(&mut engine).unwrap();
+//! }
+//! windows.finish(&mut engine).unwrap();
+//!
+//! let sizes: Vec<_> = receiver.iter().map(|(report, _)| report.transactions).collect();
+//! assert_eq!(sizes, [2, 1]);
+//! ```
+
+use crate::changes::Changed;
+use crate::engine::TransactionEngine;
+use crate::output::{AccountSnapshot, DEFAULT_DECIMALS};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+/// When windows close. Without a bound the whole input is a single window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WindowPolicy {
+    /// Transactions of a window, applied or rejected
+    pub transactions: Option<u64>,
+    /// Wall-clock time a window stays open at most
+    pub interval: Option<Duration>,
+    /// Resets the metrics of the engine with every window, so that they only count the
+    /// transactions of the current one
+    pub reset_metrics: bool,
+}
+
+/// What happened in a closed window
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowReport {
+    /// Position of the window in the stream, from 1
+    pub index: u64,
+    /// Transactions executed in the window, applied or rejected
+    pub transactions: u64,
+    pub rejected: u64,
+    /// Accounts whose funds, lock or closure changed in the window
+    pub accounts_changed: usize,
+    /// Wall-clock time the window was open
+    pub elapsed: Duration,
+}
+
+impl WindowReport {
+    pub const HEADER: [&'static str; 5] = [
+        "window",
+        "transactions",
+        "rejected",
+        "accounts_changed",
+        "seconds",
+    ];
+
+    fn record(&self) -> [String; 5] {
+        [
+            self.index.to_string(),
+            self.transactions.to_string(),
+            self.rejected.to_string(),
+            self.accounts_changed.to_string(),
+            format!("{:.3}", self.elapsed.as_secs_f64()),
+        ]
+    }
+}
+
+impl fmt::Display for WindowReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "window {}: {} transactions, {} rejected, {} accounts changed in {:.3}s",
+            self.index,
+            self.transactions,
+            self.rejected,
+            self.accounts_changed,
+            self.elapsed.as_secs_f64()
+        )
+    }
+}
+
+/// Destination of the closed windows
+pub trait WindowSink: Send {
+    /// Receives the report of the window and one snapshot per currency of every account it
+    /// changed, in order of client id then currency
+    fn window(&mut self, report: &WindowReport, changes: Vec<AccountSnapshot>) -> io::Result<()>;
+}
+
+/// Writes the accounts changed by every window as json lines to `window-<index>.jsonl` in a
+/// directory, and the reports as csv rows to `windows.csv` in the same directory
+pub struct DirWindowSink {
+    dir: PathBuf,
+    reports: csv::Writer<File>,
+    decimals: usize,
+}
+
+impl DirWindowSink {
+    /// Creates the directory if needed, replacing the reports of an earlier run
+    pub fn create<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let mut reports = csv::Writer::from_writer(File::create(dir.join("windows.csv"))?);
+        reports.write_record(WindowReport::HEADER)?;
+        reports.flush()?;
+        Ok(DirWindowSink {
+            dir,
+            reports,
+            decimals: DEFAULT_DECIMALS,
+        })
+    }
+
+    /// Writes amounts with the given decimal places, [`DEFAULT_DECIMALS`] by default
+    pub fn with_decimals(mut self, decimals: usize) -> Self {
+        self.decimals = decimals;
+        self
+    }
+
+    /// File holding the accounts changed by the window
+    pub fn path(&self, index: u64) -> PathBuf {
+        self.dir.join(format!("window-{:06}.jsonl", index))
+    }
+}
+
+impl WindowSink for DirWindowSink {
+    fn window(&mut self, report: &WindowReport, changes: Vec<AccountSnapshot>) -> io::Result<()> {
+        let mut accounts = BufWriter::new(File::create(self.path(report.index))?);
+        for change in &changes {
+            writeln!(accounts, "{}", change.to_json_with_decimals(self.decimals))?;
+        }
+        accounts.flush()?;
+        // Written last, so that a window listed in the reports has its accounts in place
+        self.reports.write_record(report.record())?;
+        self.reports.flush()
+    }
+}
+
+/// Sends every window as a whole, failing once the receiver is gone
+impl WindowSink for Sender<(WindowReport, Vec<AccountSnapshot>)> {
+    fn window(&mut self, report: &WindowReport, changes: Vec<AccountSnapshot>) -> io::Result<()> {
+        self.send((report.clone(), changes))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "window receiver is gone"))
+    }
+}
+
+/// Windows of the transactions executed by an engine, see the [module](self) documentation
+pub struct Windows {
+    policy: WindowPolicy,
+    sink: Box<dyn WindowSink>,
+    changed: Changed,
+    /// Windows closed so far
+    closed: u64,
+    opened: Instant,
+    /// Metrics of the engine when the window opened
+    processed: u64,
+    rejected: u64,
+}
+
+impl Windows {
+    /// Opens the first window, on the changes of the engine from now on. The engine must be
+    /// given to every later call.
+    pub fn new(
+        engine: &mut TransactionEngine,
+        policy: WindowPolicy,
+        sink: Box<dyn WindowSink>,
+    ) -> Self {
+        let metrics = engine.metrics();
+        Windows {
+            policy,
+            sink,
+            processed: metrics.total_processed(),
+            rejected: metrics.total_rejected(),
+            changed: Changed::subscribe(engine),
+            closed: 0,
+            opened: Instant::now(),
+        }
+    }
+
+    /// Closes the window if it is due, returning its report
+    pub fn tick(&mut self, engine: &mut TransactionEngine) -> io::Result<Option<WindowReport>> {
+        let full = self
+            .policy
+            .transactions
+            .is_some_and(|transactions| self.transactions(engine) >= transactions);
+        let expired = self
+            .policy
+            .interval
+            .is_some_and(|interval| self.opened.elapsed() >= interval);
+        if full || expired {
+            self.close(engine).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Closes the last window, unless it is empty
+    pub fn finish(mut self, engine: &mut TransactionEngine) -> io::Result<Option<WindowReport>> {
+        if self.transactions(engine) == 0 {
+            return Ok(None);
+        }
+        self.close(engine).map(Some)
+    }
+
+    /// Windows closed so far
+    pub fn closed(&self) -> u64 {
+        self.closed
+    }
+
+    fn transactions(&self, engine: &TransactionEngine) -> u64 {
+        engine.metrics().total_processed() - self.processed
+    }
+
+    fn close(&mut self, engine: &mut TransactionEngine) -> io::Result<WindowReport> {
+        let (accounts_changed, changes) = self.changed.take_snapshots(engine);
+        self.closed += 1;
+        let report = WindowReport {
+            index: self.closed,
+            transactions: self.transactions(engine),
+            rejected: engine.metrics().total_rejected() - self.rejected,
+            accounts_changed,
+            elapsed: self.opened.elapsed(),
+        };
+        if self.policy.reset_metrics {
+            engine.reset_metrics();
+        }
+        let metrics = engine.metrics();
+        self.processed = metrics.total_processed();
+        self.rejected = metrics.total_rejected();
+        self.opened = Instant::now();
+        self.sink.window(&report, changes)?;
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod unit {
+        use crate::engine::TransactionEngine;
+        use crate::transaction::{transaction, TransactionType};
+        use crate::window::{DirWindowSink, WindowPolicy, WindowReport, Windows};
+        use std::fs;
+        use std::sync::mpsc;
+        use std::time::Duration;
+
+        #[test]
+        fn windows_report_their_transactions_and_changed_accounts() {
+            let (sender, receiver) = mpsc::channel();
+            let mut engine = TransactionEngine::new();
+            let policy = WindowPolicy {
+                transactions: Some(3),
+                reset_metrics: true,
+                ..WindowPolicy::default()
+            };
+            let mut windows = Windows::new(&mut engine, policy, Box::new(sender));
+            for transaction in [
+                transaction(TransactionType::Deposit, 1, 1, Some(2.0)),
+                transaction(TransactionType::Deposit, 2, 2, Some(1.0)),
+                // Rejected, yet counted
+                transaction(TransactionType::Withdrawal, 2, 3, Some(5.0)),
+                transaction(TransactionType::Dispute, 1, 1, None),
+            ] {
+                let _ = engine.execute(transaction);
+                windows.tick(&mut engine).unwrap();
+            }
+            assert_eq!(windows.closed(), 1);
+            let last = windows.finish(&mut engine).unwrap().unwrap();
+            assert_eq!(last.index, 2);
+            assert_eq!(engine.metrics().total_processed(), 0);
+
+            let windows: Vec<_> = receiver
+                .iter()
+                .map(|(report, changes)| {
+                    let accounts: Vec<_> = changes
+                        .iter()
+                        .map(|change| (change.client, change.held))
+                        .collect();
+                    (
+                        report.transactions,
+                        report.rejected,
+                        report.accounts_changed,
+                        accounts,
+                    )
+                })
+                .collect();
+            assert_eq!(
+                windows,
+                [
+                    (3, 1, 2, vec![(1, 0.0), (2, 0.0)]),
+                    (1, 0, 1, vec![(1, 2.0)]),
+                ]
+            );
+        }
+
+        #[test]
+        fn windows_close_once_their_interval_is_over() {
+            let (sender, receiver) = mpsc::channel();
+            let mut engine = TransactionEngine::new();
+            let policy = WindowPolicy {
+                interval: Some(Duration::ZERO),
+                ..WindowPolicy::default()
+            };
+            let mut windows = Windows::new(&mut engine, policy, Box::new(sender));
+            for transaction_id in 1..=2 {
+                engine
+                    .execute(transaction(
+                        TransactionType::Deposit,
+                        1,
+                        transaction_id,
+                        Some(1.0),
+                    ))
+                    .unwrap();
+                assert!(windows.tick(&mut engine).unwrap().is_some());
+            }
+            // Nothing happened since
+            assert_eq!(windows.finish(&mut engine).unwrap(), None);
+            assert_eq!(receiver.iter().count(), 2);
+            assert_eq!(engine.metrics().total_processed(), 2);
+        }
+
+        #[test]
+        fn directory_sink_writes_a_file_per_window_and_the_reports() {
+            let dir = std::env::temp_dir().join(format!("windows-{}", std::process::id()));
+            let mut engine = TransactionEngine::new();
+            let policy = WindowPolicy {
+                transactions: Some(1),
+                ..WindowPolicy::default()
+            };
+            let sink = DirWindowSink::create(&dir).unwrap();
+            let first = sink.path(1);
+            let mut windows = Windows::new(&mut engine, policy, Box::new(sink));
+            engine
+                .execute(transaction(TransactionType::Deposit, 7, 1, Some(1.5)))
+                .unwrap();
+            windows.tick(&mut engine).unwrap();
+
+            let accounts = fs::read_to_string(&first).unwrap();
+            assert_eq!(accounts.lines().count(), 1);
+            assert!(accounts.contains("\"client\":7"));
+            let reports = fs::read_to_string(dir.join("windows.csv")).unwrap();
+            let rows: Vec<_> = reports.lines().collect();
+            assert_eq!(rows[0], WindowReport::HEADER.join(","));
+            assert!(rows[1].starts_with("1,1,0,1,"));
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Runs killed by the `chaos` feature at every failure point, then recovered, must end in the
+//! state of an uninterrupted run. Run with `cargo test --features chaos`.
+#![cfg(feature = "chaos")]
+
+use rust_coding_test::chaos::{self, FailPoint, CHAOS_VAR, EXIT_CODE};
+use rust_coding_test::{
+    Amount, InputFormat, InputPosition, Transaction, TransactionEngine, TransactionId,
+    TransactionType,
+};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Blocks of deposits, a withdrawal, a dispute and its resolution or chargeback, which locks
+/// the account, over a few clients
+fn transactions() -> Vec<Transaction> {
+    (0..60u32)
+        .map(|row| {
+            let block = row / 6;
+            let (transaction_type, transaction_id, amount) = match row % 6 {
+                0 | 1 | 5 => (
+                    TransactionType::Deposit,
+                    row + 1,
+                    Some(f64::from(row) + 0.5),
+                ),
+                2 => (TransactionType::Withdrawal, row + 1, Some(1.5)),
+                3 => (TransactionType::Dispute, block * 6 + 1, None),
+                _ if block % 3 == 0 => (TransactionType::Chargeback, block * 6 + 1, None),
+                _ => (TransactionType::Resolve, block * 6 + 1, None),
+            };
+            Transaction {
+                transaction_type,
+                client_id: (block % 4 + 1) as _,
+                transaction_id: TransactionId(transaction_id),
+                amount: amount.map(|amount| Amount::new(amount).unwrap()),
+                to_client_id: None,
+                currency: None,
+                timestamp: None,
+                metadata: Default::default(),
+            }
+        })
+        .collect()
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rust-coding-test-chaos-{}", name))
+}
+
+fn write_input(path: &Path) {
+    let mut csv = "type,client,tx,amount\n".to_string();
+    for transaction in transactions() {
+        csv.push_str(&InputFormat::Csv.format_row(&transaction));
+        csv.push('\n');
+    }
+    std::fs::write(path, csv).unwrap();
+}
+
+fn run(args: &[&str], points: Option<&str>) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_rust-coding-test"));
+    command.args(args).env_remove(CHAOS_VAR);
+    if let Some(points) = points {
+        command.env(CHAOS_VAR, points);
+    }
+    command.output().expect("Failed to run binary")
+}
+
+#[test]
+fn resumed_runs_match_an_uninterrupted_run_wherever_they_were_killed() {
+    let input = temp_path("input.csv");
+    write_input(&input);
+    let input = input.to_str().unwrap();
+    let uninterrupted = run(&["--sort-output", "client", input], None);
+    assert!(uninterrupted.status.success());
+
+    for points in ["after-parse:37", "before-apply:25", "mid-snapshot:3"] {
+        let checkpoint = temp_path(&format!("{}.checkpoint", points.replace(':', "-")));
+        let partial = checkpoint.with_extension("partial");
+        let args = [
+            "--checkpoint",
+            checkpoint.to_str().unwrap(),
+            "--checkpoint-every",
+            "10",
+            "--sort-output",
+            "client",
+        ];
+        let killed = run(&[&args[..], &[input]].concat(), Some(points));
+        assert_eq!(killed.status.code(), Some(EXIT_CODE), "{}", points);
+        assert!(killed.stdout.is_empty(), "{}", points);
+        assert!(checkpoint.exists(), "{}", points);
+        assert_eq!(partial.exists(), points.starts_with("mid-snapshot"));
+
+        let resumed = run(&[&args[..], &["--resume", input]].concat(), None);
+        let _ = std::fs::remove_file(&partial);
+
+        assert!(resumed.status.success(), "{}", points);
+        assert_eq!(
+            String::from_utf8_lossy(&resumed.stdout),
+            String::from_utf8_lossy(&uninterrupted.stdout),
+            "{}",
+            points
+        );
+        assert!(!checkpoint.exists(), "{}", points);
+    }
+    std::fs::remove_file(input).unwrap();
+}
+
+/// Executes the transactions at their row of a feed delivered at least once, checkpointing every
+/// 10 transactions, on an engine logging them to `wal` and restored from `checkpoint` if present
+fn ingest(wal: &Path, checkpoint: &Path) -> TransactionEngine {
+    let mut engine = if checkpoint.exists() {
+        TransactionEngine::restore(checkpoint).unwrap()
+    } else {
+        TransactionEngine::new()
+    };
+    engine.open_wal(wal, 1).unwrap();
+    for (row, transaction) in transactions().into_iter().enumerate() {
+        let position = InputPosition::new("feed", row as u64, 0);
+        if engine.execute_at(transaction, position).is_some() && (row + 1) % 10 == 0 {
+            engine.checkpoint(checkpoint).unwrap();
+        }
+    }
+    engine
+}
+
+#[test]
+fn write_ahead_log_recovery_matches_an_uninterrupted_run() {
+    let mut uninterrupted = TransactionEngine::new();
+    for transaction in transactions() {
+        let _ = uninterrupted.execute(transaction);
+    }
+
+    for (point, at) in [(FailPoint::BeforeApply, 25), (FailPoint::MidSnapshot, 2)] {
+        let wal = temp_path(&format!("{}.wal", point));
+        let checkpoint = temp_path(&format!("{}.snapshot", point));
+        let _ = std::fs::remove_file(&wal);
+        let _ = std::fs::remove_file(&checkpoint);
+
+        chaos::arm(point, at);
+        let killed = panic::catch_unwind(AssertUnwindSafe(|| ingest(&wal, &checkpoint)));
+        chaos::disarm();
+        assert!(killed.is_err(), "{}", point);
+        assert!(checkpoint.exists(), "{}", point);
+
+        // The feed is delivered again from the start, skipping what the log already holds
+        let recovered = ingest(&wal, &checkpoint);
+        assert_eq!(
+            recovered.sorted_snapshots().collect::<Vec<_>>(),
+            uninterrupted.sorted_snapshots().collect::<Vec<_>>(),
+            "{}",
+            point
+        );
+        std::fs::remove_file(&wal).unwrap();
+        std::fs::remove_file(&checkpoint).unwrap();
+        let _ = std::fs::remove_file(checkpoint.with_extension("partial"));
+    }
+}
@@ -0,0 +1,1907 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::{Child, Command, Output, Stdio};
+
+fn asset(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("assets")
+        .join(name)
+}
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rust-coding-test"))
+        .args(args)
+        .output()
+        .expect("Failed to run binary")
+}
+
+fn sorted_lines(output: &[u8]) -> Vec<String> {
+    let mut lines: Vec<String> = String::from_utf8_lossy(output)
+        .lines()
+        .map(str::to_string)
+        .collect();
+    lines.sort();
+    lines
+}
+
+#[test]
+fn processes_positional_input_file() {
+    let output = run(&[asset("test_with_disputes.csv").to_str().unwrap()]);
+
+    assert!(output.status.success());
+    assert_eq!(
+        sorted_lines(&output.stdout),
+        vec![
+            "1,11.5000,0.0000,11.5000,false",
+            "2,0.0000,2.0000,2.0000,false",
+            "client,available,held,total,locked",
+        ]
+    );
+}
+
+#[test]
+fn balances_are_written_per_currency() {
+    let path = std::env::temp_dir().join("rust-coding-test-cli-currencies.csv");
+    std::fs::write(
+        &path,
+        "type, client, tx, amount, currency\n\
+         deposit, 1, 1, 2.0, EUR\n\
+         deposit, 1, 2, 1.0, usd\n\
+         withdrawal, 1, 3, 1.5, USD\n\
+         dispute, 1, 1, , USD\n\
+         deposit, 2, 4, 1.0,\n",
+    )
+    .unwrap();
+
+    let output = run(&[path.to_str().unwrap()]);
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        sorted_lines(&output.stdout),
+        vec![
+            "1,1.0000,0.0000,1.0000,false,USD",
+            "1,2.0000,0.0000,2.0000,false,EUR",
+            "2,1.0000,0.0000,1.0000,false,",
+            "client,available,held,total,locked,currency",
+        ]
+    );
+}
+
+#[test]
+fn writes_json_to_output_file() {
+    let path = std::env::temp_dir().join("rust-coding-test-cli-output.json");
+    let output = run(&[
+        "--input",
+        asset("test_basic.csv").to_str().unwrap(),
+        "--output",
+        path.to_str().unwrap(),
+        "--format",
+        "json",
+    ]);
+
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    let contents = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(contents.starts_with('['));
+    assert!(contents.trim_end().ends_with(']'));
+    assert!(contents.contains(
+        "{\"client\":1,\"available\":1.5000,\"held\":0.0000,\"total\":1.5000,\"locked\":false}"
+    ));
+}
+
+#[test]
+fn malformed_rows_are_skipped_unless_strict() {
+    let path = std::env::temp_dir().join("rust-coding-test-cli-malformed.csv");
+    std::fs::write(
+        &path,
+        "type, client, tx, amount\ndeposit, 1, 1, 1.0\nunknown, 1, 2, 1.0\n",
+    )
+    .unwrap();
+
+    let lenient = run(&["--verbose", path.to_str().unwrap()]);
+    let strict = run(&["--strict", path.to_str().unwrap()]);
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(lenient.status.success());
+    assert!(String::from_utf8_lossy(&lenient.stdout).contains("1,1.0000,0.0000,1.0000,false"));
+    assert!(String::from_utf8_lossy(&lenient.stderr).contains("Skipped malformed row"));
+    assert!(!strict.status.success());
+    assert!(strict.stdout.is_empty());
+}
+
+#[test]
+fn skipped_rows_are_reported_and_written_to_rejects_file() {
+    let path = std::env::temp_dir().join("rust-coding-test-cli-rejects-input.csv");
+    let rejects = std::env::temp_dir().join("rust-coding-test-cli-rejects.csv");
+    std::fs::write(
+        &path,
+        "type, client, tx, amount\ndeposit, 1, 1, 1.0\nunknown, 1, 2, 1.0\ndeposit, 1, x, 1.0\n",
+    )
+    .unwrap();
+
+    let output = run(&[
+        "--rejects-file",
+        rejects.to_str().unwrap(),
+        path.to_str().unwrap(),
+    ]);
+    let written = std::fs::read_to_string(&rejects).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&rejects).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Skipped 2 malformed rows on lines 3, 4\n"
+    );
+    assert_eq!(
+        written,
+        "type,client,tx,amount,to,currency,timestamp\nunknown,1,2,1.0\ndeposit,1,x,1.0\n"
+    );
+}
+
+#[test]
+fn disputes_arriving_before_their_deposit_are_retried() {
+    let path = std::env::temp_dir().join("rust-coding-test-cli-retry-input.csv");
+    let dead_letters = std::env::temp_dir().join("rust-coding-test-cli-dead-letters.csv");
+    std::fs::write(
+        &path,
+        "type, client, tx, amount\n\
+         dispute, 1, 2\n\
+         deposit, 1, 1, 1.0\n\
+         deposit, 1, 2, 2.0\n\
+         chargeback, 1, 7\n",
+    )
+    .unwrap();
+
+    let output = run(&[
+        "--dead-letters",
+        dead_letters.to_str().unwrap(),
+        "--retry-after",
+        "1",
+        path.to_str().unwrap(),
+    ]);
+    let written = std::fs::read_to_string(&dead_letters).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&dead_letters).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "client,available,held,total,locked\n1,1.0000,2.0000,3.0000,false\n"
+    );
+    assert_eq!(
+        written,
+        "type,client,tx,amount,to,currency,timestamp\nchargeback,1,7\n"
+    );
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("Wrote 1 rows still failing once retried")
+    );
+}
+
+#[test]
+fn shuffled_input_is_reordered_within_the_window() {
+    let path = std::env::temp_dir().join("rust-coding-test-cli-ordering-input.csv");
+    let rejects = std::env::temp_dir().join("rust-coding-test-cli-ordering-rejects.csv");
+    std::fs::write(
+        &path,
+        "type, client, tx, amount\n\
+         deposit, 1, 1, 1.0\n\
+         withdrawal, 1, 3, 1.5\n\
+         deposit, 1, 2, 2.0\n\
+         deposit, 1, 5, 1.0\n\
+         deposit, 1, 6, 1.0\n\
+         deposit, 1, 4, 5.0\n",
+    )
+    .unwrap();
+
+    let output = run(&[
+        "--ordering",
+        "reorder:1",
+        "--rejects-file",
+        rejects.to_str().unwrap(),
+        path.to_str().unwrap(),
+    ]);
+    let written = std::fs::read_to_string(&rejects).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&rejects).unwrap();
+
+    assert!(output.status.success());
+    // The withdrawal is moved behind the deposit it needs, the last deposit is too late
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "client,available,held,total,locked\n1,3.5000,0.0000,3.5000,false\n"
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Skipped 1 rows arriving out of order\n"
+    );
+    assert_eq!(
+        written,
+        "type,client,tx,amount,to,currency,timestamp\ndeposit,1,4,5\n"
+    );
+}
+
+#[test]
+fn input_is_read_from_stdin() {
+    let path = asset("test_with_disputes.csv");
+    let direct = run(&[path.to_str().unwrap()]);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rust-coding-test"))
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to run binary");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(&std::fs::read(&path).unwrap())
+        .unwrap();
+    let piped = child.wait_with_output().unwrap();
+
+    assert!(piped.status.success());
+    assert_eq!(sorted_lines(&piped.stdout), sorted_lines(&direct.stdout));
+}
+
+#[test]
+fn repl_sessions_apply_typed_transactions() {
+    let snapshot = std::env::temp_dir().join("rust-coding-test-cli-repl.snapshot");
+    let session = |args: &[&str], input: String| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_rust-coding-test"))
+            .arg("repl")
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to run binary");
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        child.wait_with_output().unwrap()
+    };
+
+    let first = session(
+        &["--admin"],
+        format!(
+            "deposit 1 1 5.0\n\nwithdrawal 1 2 7\nlock 1 3\nsave {}\nquit\ndeposit 1 4 1.0\n",
+            snapshot.display()
+        ),
+    );
+    let second = session(
+        &["--restore", snapshot.to_str().unwrap()],
+        "accounts\nunlock 1 5\n".to_string(),
+    );
+    std::fs::remove_file(&snapshot).unwrap();
+
+    assert!(first.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&first.stdout),
+        format!(
+            "applied\n\
+             rejected: client 1: transaction 2: insufficient funds, requested 7.0000 but only \
+             5.0000 available\n\
+             applied\n\
+             saved {}\n",
+            snapshot.display()
+        )
+    );
+    assert!(second.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&second.stdout),
+        "client,currency,available,held,total,locked\n\
+         1,,5.0000,0.0000,5.0000,true\n\
+         rejected: transaction 5 is administrative and was not submitted by an admin\n"
+    );
+}
+
+#[test]
+fn gzip_input_gives_same_result() {
+    let direct = run(&[asset("test_with_disputes.csv").to_str().unwrap()]);
+    let gzip = run(&[asset("test_with_disputes.csv.gz").to_str().unwrap()]);
+
+    assert!(gzip.status.success());
+    assert_eq!(sorted_lines(&gzip.stdout), sorted_lines(&direct.stdout));
+    assert_eq!(gzip.stderr, direct.stderr);
+}
+
+#[test]
+fn zstd_input_is_reported() {
+    let path = std::env::temp_dir().join("rust-coding-test-cli-input.csv.zst");
+    std::fs::write(&path, b"\x28\xb5\x2f\xfd\x00\x00").unwrap();
+
+    let output = run(&[path.to_str().unwrap()]);
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("input is zstd compressed"));
+}
+
+#[test]
+fn ndjson_input_gives_same_result_as_csv() {
+    let csv = run(&[asset("test_basic.csv").to_str().unwrap()]);
+    let ndjson = run(&[asset("test_basic.ndjson").to_str().unwrap()]);
+
+    assert!(ndjson.status.success());
+    assert_eq!(sorted_lines(&ndjson.stdout), sorted_lines(&csv.stdout));
+}
+
+#[test]
+fn read_ahead_gives_same_result() {
+    let path = asset("test_with_disputes.csv");
+    let direct = run(&[path.to_str().unwrap()]);
+    let read_ahead = run(&["--read-ahead", "2", path.to_str().unwrap()]);
+
+    assert!(read_ahead.status.success());
+    assert_eq!(
+        sorted_lines(&read_ahead.stdout),
+        sorted_lines(&direct.stdout)
+    );
+}
+
+#[test]
+fn pipeline_gives_same_result_and_reports_queue_depths() {
+    let path = asset("test_with_disputes.csv");
+    let direct = run(&[path.to_str().unwrap()]);
+    let pipelined = run(&[
+        "--parse-queue",
+        "2",
+        "--validate-queue",
+        "1",
+        "--ordering",
+        "reorder:4",
+        "--stats",
+        path.to_str().unwrap(),
+    ]);
+
+    assert!(pipelined.status.success());
+    assert_eq!(
+        sorted_lines(&pipelined.stdout),
+        sorted_lines(&direct.stdout)
+    );
+    let stats = String::from_utf8_lossy(&pipelined.stderr);
+    assert!(stats.contains("Queued at most "), "{}", stats);
+}
+
+#[test]
+fn sharded_processing_gives_same_result() {
+    let path = asset("test_with_disputes.csv");
+    let direct = run(&[path.to_str().unwrap()]);
+    let sharded = run(&["--threads", "3", path.to_str().unwrap()]);
+
+    assert!(sharded.status.success());
+    assert_eq!(sorted_lines(&sharded.stdout), sorted_lines(&direct.stdout));
+}
+
+#[test]
+fn lock_and_unlock_rows_need_admin_input() {
+    let path = std::env::temp_dir().join("rust-coding-test-cli-admin.csv");
+    std::fs::write(
+        &path,
+        "type,client,tx,amount\ndeposit,1,1,2.0\nlock,1,2,\ndeposit,1,3,1.0\n",
+    )
+    .unwrap();
+    let client = run(&["-v", path.to_str().unwrap()]);
+    let admin = run(&["--admin", path.to_str().unwrap()]);
+    let sharded = run(&["--admin", "--threads", "2", path.to_str().unwrap()]);
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(client.status.success());
+    assert!(String::from_utf8_lossy(&client.stderr).contains("not submitted by an admin"));
+    assert_eq!(
+        String::from_utf8_lossy(&client.stdout).lines().nth(1),
+        Some("1,3.0000,0.0000,3.0000,false")
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&admin.stdout).lines().nth(1),
+        Some("1,2.0000,0.0000,2.0000,true")
+    );
+    assert_eq!(sorted_lines(&sharded.stdout), sorted_lines(&admin.stdout));
+}
+
+#[test]
+fn closed_and_dormant_accounts_are_reported() {
+    let path = std::env::temp_dir().join("rust-coding-test-cli-close.csv");
+    std::fs::write(
+        &path,
+        "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,2,2,1.0\nclose,1,3,\n\
+         deposit,1,4,1.0\ndeposit,3,5,1.0\n",
+    )
+    .unwrap();
+    let output = run(&[
+        "--admin",
+        "--stats",
+        "--dormant-after",
+        "2",
+        "--sort-output",
+        "client",
+        path.to_str().unwrap(),
+    ]);
+    std::fs::remove_file(&path).unwrap();
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "client,available,held,total,locked,closed\n\
+         1,2.0000,0.0000,2.0000,false,true\n\
+         2,1.0000,0.0000,1.0000,false,false\n\
+         3,1.0000,0.0000,1.0000,false,false\n"
+    );
+    assert!(stderr.contains("  account_closed: 1\n"), "{}", stderr);
+    assert!(stderr.contains("Closed 1 accounts\n"), "{}", stderr);
+    assert!(
+        stderr.contains("Found 1 accounts dormant for 2 transactions: 2\n"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn overly_precise_amounts_follow_the_precision_policy() {
+    let path = std::env::temp_dir().join("rust-coding-test-cli-precision.csv");
+    std::fs::write(
+        &path,
+        "type,client,tx,amount\ndeposit,1,1,1.125\ndeposit,2,2,0.015\n",
+    )
+    .unwrap();
+    let run_with = |precision: &str| {
+        run(&[
+            "--max-decimals",
+            "2",
+            "--precision",
+            precision,
+            "--sort-output",
+            "client",
+            path.to_str().unwrap(),
+        ])
+    };
+    let rounded = run_with("round-half-even");
+    let truncated = run_with("truncate");
+    let rejected = run_with("reject");
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(rounded.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&rounded.stdout),
+        "client,available,held,total,locked\n1,1.12,0.00,1.12,false\n2,0.02,0.00,0.02,false\n"
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&truncated.stdout),
+        "client,available,held,total,locked\n1,1.12,0.00,1.12,false\n2,0.01,0.00,0.01,false\n"
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&rejected.stdout),
+        "client,available,held,total,locked\n"
+    );
+}
+
+#[test]
+fn amounts_are_read_in_the_given_format() {
+    let path = std::env::temp_dir().join("rust-coding-test-cli-amount-format.csv");
+    std::fs::write(
+        &path,
+        "type,client,tx,amount\ndeposit,1,1,\"1.234,5\"\nwithdrawal,1,2,\"0,25\"\n\
+         deposit,2,3,1.5\n",
+    )
+    .unwrap();
+    let output = run(&[
+        "--amount-format",
+        "de",
+        "--sort-output",
+        "client",
+        path.to_str().unwrap(),
+    ]);
+    let strict = run(&["--amount-format", "de", "--strict", path.to_str().unwrap()]);
+    let invalid = run(&["--amount-format", "ru", path.to_str().unwrap()]);
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "client,available,held,total,locked\n1,1234.2500,0.0000,1234.2500,false\n"
+    );
+    assert!(String::from_utf8_lossy(&strict.stderr)
+        .contains("line 4: amount '1.5' has misplaced grouping separators '.'"));
+    assert!(!invalid.status.success());
+}
+
+#[test]
+fn fast_parse_gives_same_result() {
+    for name in ["test_basic.csv", "test_with_disputes.csv"] {
+        let path = asset(name);
+        let direct = run(&["--sort-output", "client", path.to_str().unwrap()]);
+        let fast = run(&[
+            "--fast-parse",
+            "--sort-output",
+            "client",
+            path.to_str().unwrap(),
+        ]);
+
+        assert!(fast.status.success());
+        assert_eq!(fast.stdout, direct.stdout, "{}", name);
+        assert_eq!(fast.stderr, direct.stderr, "{}", name);
+    }
+}
+
+#[test]
+fn disk_storage_gives_same_result() {
+    let path = asset("test_with_disputes.csv");
+    let storage = std::env::temp_dir().join("rust-coding-test-cli-storage.log");
+    let direct = run(&[path.to_str().unwrap()]);
+    let on_disk = run(&[
+        "--storage",
+        "disk",
+        "--storage-path",
+        storage.to_str().unwrap(),
+        path.to_str().unwrap(),
+    ]);
+
+    assert!(on_disk.status.success());
+    assert_eq!(sorted_lines(&on_disk.stdout), sorted_lines(&direct.stdout));
+    std::fs::remove_file(&storage).unwrap();
+}
+
+#[test]
+fn incremental_files_continue_from_snapshot() {
+    let snapshot = std::env::temp_dir().join("rust-coding-test-cli.snapshot");
+    let second_day = std::env::temp_dir().join("rust-coding-test-cli-second-day.csv");
+    std::fs::write(
+        &second_day,
+        "type, client, tx, amount\ndispute, 1, 3,\nchargeback, 1, 3,\n",
+    )
+    .unwrap();
+
+    let first = run(&[
+        "--snapshot",
+        snapshot.to_str().unwrap(),
+        asset("test_basic.csv").to_str().unwrap(),
+    ]);
+    let second = run(&[
+        "--restore",
+        snapshot.to_str().unwrap(),
+        second_day.to_str().unwrap(),
+    ]);
+    std::fs::remove_file(&snapshot).unwrap();
+    std::fs::remove_file(&second_day).unwrap();
+
+    assert!(first.status.success());
+    assert!(second.status.success());
+    assert_eq!(
+        sorted_lines(&second.stdout),
+        vec![
+            "1,-0.5000,0.0000,-0.5000,true",
+            "2,2.0000,0.0000,2.0000,false",
+            "client,available,held,total,locked",
+        ]
+    );
+}
+
+#[test]
+fn checked_runs_keep_their_output() {
+    let path = asset("test_with_disputes.csv");
+    let snapshot = std::env::temp_dir().join("rust-coding-test-cli-check.snapshot");
+    let direct = run(&[
+        "--snapshot",
+        snapshot.to_str().unwrap(),
+        path.to_str().unwrap(),
+    ]);
+    let checked = run(&["--check", path.to_str().unwrap()]);
+    let sharded = run(&["--check", "--threads", "3", path.to_str().unwrap()]);
+    // Only the transactions of the run are replayed on the restored accounts
+    let restored = run(&[
+        "--check",
+        "--restore",
+        snapshot.to_str().unwrap(),
+        asset("test_basic.csv").to_str().unwrap(),
+    ]);
+    std::fs::remove_file(&snapshot).unwrap();
+
+    for output in [&checked, &sharded, &restored] {
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    assert_eq!(sorted_lines(&checked.stdout), sorted_lines(&direct.stdout));
+    assert_eq!(sorted_lines(&sharded.stdout), sorted_lines(&direct.stdout));
+}
+
+#[test]
+fn fees_are_applied_to_the_accounts_of_a_snapshot() {
+    let snapshot = std::env::temp_dir().join("rust-coding-test-cli-fees.snapshot");
+    let fees = std::env::temp_dir().join("rust-coding-test-cli-fees.toml");
+    std::fs::write(
+        &fees,
+        "[fees]\nmaintenance_fee = 0.5\nwaive_fee_above = 2\n",
+    )
+    .unwrap();
+
+    let first = run(&[
+        "--snapshot",
+        snapshot.to_str().unwrap(),
+        asset("test_basic.csv").to_str().unwrap(),
+    ]);
+    let applied = run(&[
+        "apply-fees",
+        "--restore",
+        snapshot.to_str().unwrap(),
+        "--fees",
+        fees.to_str().unwrap(),
+    ]);
+    std::fs::remove_file(&snapshot).unwrap();
+    std::fs::remove_file(&fees).unwrap();
+
+    assert!(first.status.success());
+    assert!(applied.status.success());
+    assert_eq!(
+        sorted_lines(&applied.stdout),
+        vec![
+            "1,1.0000,0.0000,1.0000,false",
+            "2,2.0000,0.0000,2.0000,false",
+            "client,available,held,total,locked",
+        ]
+    );
+    assert!(
+        String::from_utf8_lossy(&applied.stderr).contains("Charged 0.5000 in fees to 1 accounts\n")
+    );
+}
+
+#[test]
+fn standing_orders_are_executed_on_the_accounts_of_a_snapshot() {
+    let snapshot = std::env::temp_dir().join("rust-coding-test-cli-schedule.snapshot");
+    let orders = std::env::temp_dir().join("rust-coding-test-cli-schedule.csv");
+    std::fs::write(
+        &orders,
+        "type,client,tx,amount,every,to,currency,start\n\
+         deposit,1,100,1.0,2\n\
+         transfer,2,200,0.5,1d,1,,2024-03-01\n",
+    )
+    .unwrap();
+
+    let first = run(&[
+        "--snapshot",
+        snapshot.to_str().unwrap(),
+        asset("test_basic.csv").to_str().unwrap(),
+    ]);
+    let advanced = run(&[
+        "advance-time",
+        "--restore",
+        snapshot.to_str().unwrap(),
+        "--schedule",
+        orders.to_str().unwrap(),
+        "--ticks",
+        "4",
+        "--until",
+        "2024-03-02T12:00:00Z",
+    ]);
+    std::fs::remove_file(&snapshot).unwrap();
+    std::fs::remove_file(&orders).unwrap();
+
+    assert!(first.status.success());
+    assert!(advanced.status.success());
+    assert_eq!(
+        sorted_lines(&advanced.stdout),
+        vec![
+            "1,4.5000,0.0000,4.5000,false",
+            "2,1.0000,0.0000,1.0000,false",
+            "client,available,held,total,locked",
+        ]
+    );
+    assert!(
+        String::from_utf8_lossy(&advanced.stderr).contains("Executed 4 scheduled transactions\n")
+    );
+}
+
+#[test]
+fn clients_of_a_snapshot_are_merged() {
+    let snapshot = std::env::temp_dir().join("rust-coding-test-cli-merge.snapshot");
+    let first = run(&[
+        "--snapshot",
+        snapshot.to_str().unwrap(),
+        asset("test_basic.csv").to_str().unwrap(),
+    ]);
+    let merged = run(&[
+        "merge-clients",
+        "--restore",
+        snapshot.to_str().unwrap(),
+        "--from",
+        "2",
+        "--into",
+        "1",
+    ]);
+    let unknown = run(&[
+        "merge-clients",
+        "--restore",
+        snapshot.to_str().unwrap(),
+        "--from",
+        "3",
+        "--into",
+        "1",
+    ]);
+    std::fs::remove_file(&snapshot).unwrap();
+
+    assert!(first.status.success());
+    assert!(merged.status.success());
+    assert_eq!(
+        sorted_lines(&merged.stdout),
+        vec![
+            "1,3.5000,0.0000,3.5000,false",
+            "client,available,held,total,locked",
+        ]
+    );
+    assert!(!unknown.status.success());
+    assert!(String::from_utf8_lossy(&unknown.stderr).contains("client 3 which has no account"));
+}
+
+#[test]
+fn accounts_of_an_output_and_a_snapshot_are_compared() {
+    let snapshot = std::env::temp_dir().join("rust-coding-test-cli-diff.snapshot");
+    let output = std::env::temp_dir().join("rust-coding-test-cli-diff.csv");
+    let other = std::env::temp_dir().join("rust-coding-test-cli-diff-other.csv");
+    let first = run(&[
+        "--snapshot",
+        snapshot.to_str().unwrap(),
+        "--output",
+        output.to_str().unwrap(),
+        asset("test_basic.csv").to_str().unwrap(),
+    ]);
+    let second = run(&[
+        "--output",
+        other.to_str().unwrap(),
+        asset("test_with_disputes.csv").to_str().unwrap(),
+    ]);
+    let same = run(&["diff", snapshot.to_str().unwrap(), output.to_str().unwrap()]);
+    let different = run(&[
+        "diff",
+        "-f",
+        "csv",
+        output.to_str().unwrap(),
+        other.to_str().unwrap(),
+    ]);
+    let malformed = run(&[
+        "diff",
+        output.to_str().unwrap(),
+        asset("test_basic.csv").to_str().unwrap(),
+    ]);
+    for path in [&snapshot, &output, &other] {
+        std::fs::remove_file(path).unwrap();
+    }
+
+    assert!(first.status.success() && second.status.success());
+    assert!(same.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&same.stdout),
+        "Compared 2 accounts, 0 differ\n"
+    );
+    assert!(!different.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&different.stdout),
+        "client,currency,field,left,right\n\
+         1,,available,1.5000,11.5000\n1,,total,1.5000,11.5000\n\
+         2,,available,2.0000,0.0000\n2,,held,0.0000,2.0000\n"
+    );
+    assert!(String::from_utf8_lossy(&different.stderr).contains("2 accounts differ"));
+    assert!(!malformed.status.success());
+    assert!(String::from_utf8_lossy(&malformed.stderr).contains("test_basic.csv"));
+}
+
+#[test]
+fn outputs_with_a_trailer_are_verified() {
+    let csv = std::env::temp_dir().join("rust-coding-test-cli-trailer.csv");
+    let json = std::env::temp_dir().join("rust-coding-test-cli-trailer.json");
+    let input = asset("test_basic.csv");
+    let written = [(&csv, "csv"), (&json, "json")].map(|(path, format)| {
+        run(&[
+            "--trailer",
+            "-f",
+            format,
+            "-o",
+            path.to_str().unwrap(),
+            input.to_str().unwrap(),
+        ])
+    });
+    let verified = [&csv, &json].map(|path| run(&["verify-output", path.to_str().unwrap()]));
+    let output = std::fs::read_to_string(&csv).unwrap();
+    std::fs::write(&csv, output.replacen("1.5000", "1.6000", 1)).unwrap();
+    let changed = run(&["verify-output", csv.to_str().unwrap()]);
+    for path in [&csv, &json] {
+        std::fs::remove_file(path).unwrap();
+    }
+
+    assert!(written.iter().all(|output| output.status.success()));
+    assert!(output
+        .lines()
+        .last()
+        .unwrap()
+        .starts_with("#trailer,rows=2,total=3.5000,sha256="));
+    for verified in &verified {
+        assert!(verified.status.success());
+        assert!(String::from_utf8_lossy(&verified.stdout)
+            .contains("2 rows, totals adding up to 3.5, checksum verified"));
+    }
+    assert!(!changed.status.success());
+    assert!(String::from_utf8_lossy(&changed.stderr).contains("the checksum does not match"));
+}
+
+#[test]
+fn captured_columns_are_written_to_the_audit_log() {
+    let input = std::env::temp_dir().join("rust-coding-test-cli-metadata.csv");
+    let path = std::env::temp_dir().join("rust-coding-test-cli-metadata.jsonl");
+    std::fs::write(
+        &input,
+        "type,client,tx,amount,merchant,note\n\
+         deposit,1,1,2.0,ACME,ignored\n\
+         withdrawal,1,2,1.0,,\n",
+    )
+    .unwrap();
+    let output = run(&[
+        "--metadata",
+        "merchant",
+        "--audit-log",
+        path.to_str().unwrap(),
+        input.to_str().unwrap(),
+    ]);
+
+    let audit = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        audit.lines().collect::<Vec<_>>(),
+        vec![
+            "{\"event\":\"applied\",\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":2,\
+             \"metadata\":{\"merchant\":\"ACME\"}}",
+            "{\"event\":\"applied\",\"type\":\"withdrawal\",\"client\":1,\"tx\":2,\"amount\":1}",
+        ]
+    );
+}
+
+#[test]
+fn journal_appends_balanced_entries_and_opens_accounts_once() {
+    let input = std::env::temp_dir().join("rust-coding-test-cli-journal.csv");
+    let journal = std::env::temp_dir().join("rust-coding-test-cli-journal.beancount");
+    let _ = std::fs::remove_file(&journal);
+    std::fs::write(
+        &input,
+        "type, client, tx, amount\ndeposit, 1, 1, 3.0\nwithdrawal, 1, 2, 1.0\n",
+    )
+    .unwrap();
+    let args = [
+        "--journal",
+        journal.to_str().unwrap(),
+        input.to_str().unwrap(),
+    ];
+    let first = run(&args);
+    let second = run(&args);
+
+    let text = std::fs::read_to_string(&journal).unwrap();
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_file(&journal).unwrap();
+    assert!(first.status.success() && second.status.success());
+    assert_eq!(text.matches(" * \"deposit tx 1 of client 1\"\n").count(), 2);
+    assert_eq!(
+        text.matches(" * \"withdrawal tx 2 of client 1\"\n").count(),
+        2
+    );
+    assert_eq!(
+        text.matches(" open Liabilities:Clients:1:Available\n")
+            .count(),
+        1
+    );
+    assert_eq!(text.matches(" open Assets:Settlement\n").count(), 1);
+    assert!(text.contains(
+        "  Liabilities:Clients:1:Available                   1.0000 FUNDS\n\
+         \x20 Assets:Settlement                                -1.0000 FUNDS\n"
+    ));
+}
+
+#[test]
+fn windows_write_the_accounts_they_changed() {
+    let input = std::env::temp_dir().join("rust-coding-test-cli-windows.csv");
+    let dir = std::env::temp_dir().join("rust-coding-test-cli-windows");
+    std::fs::write(
+        &input,
+        "type, client, tx, amount\n\
+         deposit, 1, 1, 3.0\n\
+         deposit, 2, 2, 1.0\n\
+         withdrawal, 2, 3, 5.0\n\
+         withdrawal, 1, 4, 1.0\n",
+    )
+    .unwrap();
+    let output = run(&[
+        "--window-dir",
+        dir.to_str().unwrap(),
+        "--window-size",
+        "3",
+        input.to_str().unwrap(),
+    ]);
+
+    let reports = std::fs::read_to_string(dir.join("windows.csv")).unwrap();
+    let first = std::fs::read_to_string(dir.join("window-000001.jsonl")).unwrap();
+    let second = std::fs::read_to_string(dir.join("window-000002.jsonl")).unwrap();
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success());
+    let rows: Vec<_> = reports
+        .lines()
+        .map(|line| line.rsplit_once(',').unwrap().0)
+        .collect();
+    assert_eq!(
+        rows,
+        [
+            "window,transactions,rejected,accounts_changed",
+            "1,3,1,2",
+            "2,1,0,1"
+        ]
+    );
+    assert_eq!(first.lines().count(), 2);
+    assert_eq!(
+        second,
+        "{\"client\":1,\"available\":2.0000,\"held\":0.0000,\"total\":2.0000,\"locked\":false}\n"
+    );
+}
+
+#[test]
+fn audit_log_records_every_transaction() {
+    let path = std::env::temp_dir().join("rust-coding-test-cli-audit.jsonl");
+    let output = run(&[
+        "--audit-log",
+        path.to_str().unwrap(),
+        asset("test_with_disputes.csv").to_str().unwrap(),
+    ]);
+
+    let audit = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+    let lines: Vec<_> = audit.lines().collect();
+    // 9 rows plus one dispute_opened/resolved event for each applied dispute and resolve
+    assert_eq!(lines.len(), 12);
+    assert_eq!(
+        lines[4],
+        "{\"event\":\"rejected\",\"type\":\"withdrawal\",\"client\":2,\"tx\":5,\"amount\":3,\
+         \"reason\":\"client 2: transaction 5: insufficient funds, requested 3.0000 but only 2.0000 available\"}"
+    );
+}
+
+#[test]
+fn limits_are_loaded_from_toml_file() {
+    let limits = std::env::temp_dir().join("rust-coding-test-cli-limits.toml");
+    let audit = std::env::temp_dir().join("rust-coding-test-cli-limits-audit.jsonl");
+    std::fs::write(
+        &limits,
+        "[limits]\nmax_transaction_amount = 5\ndaily_withdrawal_cap = 1\n",
+    )
+    .unwrap();
+
+    let output = run(&[
+        "--limits",
+        limits.to_str().unwrap(),
+        "--audit-log",
+        audit.to_str().unwrap(),
+        asset("test_basic.csv").to_str().unwrap(),
+    ]);
+    let events = std::fs::read_to_string(&audit).unwrap();
+    std::fs::remove_file(&limits).unwrap();
+    std::fs::remove_file(&audit).unwrap();
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("1,3.0000,0.0000,3.0000,false"));
+    assert!(
+        events.contains(
+            "\"reason\":\"client 1: transaction 4 exceeds the daily withdrawal cap of 1.0000\""
+        ),
+        "{}",
+        events
+    );
+}
+
+#[test]
+fn settings_are_read_from_engine_toml_in_working_directory() {
+    let dir = std::env::temp_dir().join("rust-coding-test-cli-config");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("engine.toml"),
+        "[engine]\nnegative_balance = \"reject-dispute\"\n[io]\nformat = \"json\"\n",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_rust-coding-test"))
+        .arg(asset("test_with_disputes.csv"))
+        .current_dir(&dir)
+        .output()
+        .expect("Failed to run binary");
+    let csv = run(&[
+        "--config",
+        dir.join("engine.toml").to_str().unwrap(),
+        "--format",
+        "csv",
+        asset("test_with_disputes.csv").to_str().unwrap(),
+    ]);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with('['));
+    assert!(csv.status.success());
+    assert!(String::from_utf8_lossy(&csv.stdout).starts_with("client,"));
+}
+
+#[test]
+fn invalid_config_file_is_reported() {
+    let path = std::env::temp_dir().join("rust-coding-test-cli-invalid.toml");
+    std::fs::write(&path, "[io]\nformat = \"xml\"\n").unwrap();
+
+    let output = run(&[
+        "--config",
+        path.to_str().unwrap(),
+        asset("test_basic.csv").to_str().unwrap(),
+    ]);
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Error: invalid configuration on line 2: unknown output format 'xml' for 'format'\n"
+    );
+}
+
+#[test]
+fn stats_summary_is_printed() {
+    let output = run(&["--stats", asset("test_with_disputes.csv").to_str().unwrap()]);
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success());
+    assert!(stderr.starts_with("Processed 9 transactions"), "{}", stderr);
+    assert!(stderr.contains("  deposit: 4\n"), "{}", stderr);
+    assert!(stderr.contains("Created 2 accounts"), "{}", stderr);
+}
+
+#[test]
+fn open_disputes_are_written_to_file() {
+    let path = std::env::temp_dir().join("rust-coding-test-cli-disputes.csv");
+    let output = run(&[
+        "--disputes-output",
+        path.to_str().unwrap(),
+        asset("test_with_disputes.csv").to_str().unwrap(),
+    ]);
+    let disputes = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        disputes,
+        "client,tx,currency,amount,age,opened_at\n2,2,,2.0000,3,\n"
+    );
+}
+
+#[test]
+fn profile_is_printed() {
+    let output = run(&[
+        "--profile",
+        "--threads",
+        "2",
+        asset("test_with_disputes.csv").to_str().unwrap(),
+    ]);
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success());
+    assert!(
+        stderr.starts_with("Profiled 9 transactions\n"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("  deposit: 4, p50 "), "{}", stderr);
+    assert!(stderr.contains("Hottest 2 accounts\n"), "{}", stderr);
+}
+
+#[test]
+fn report_is_written_to_file() {
+    let path = std::env::temp_dir().join("rust-coding-test-cli-report.txt");
+    let output = run(&[
+        "--report-file",
+        path.to_str().unwrap(),
+        asset("test_with_disputes.csv").to_str().unwrap(),
+    ]);
+    let report = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+    assert!(report.contains("Applied 8 transactions\n"), "{}", report);
+    assert!(report.contains("  insufficient_funds: 1\n"), "{}", report);
+    assert!(report.contains("Read 9 rows, skipped 0 malformed rows\n"));
+    assert!(report.contains("Created 2 accounts, 0 locked\n"));
+    assert!(report.contains("Held 2.0000 under dispute\n"));
+}
+
+#[test]
+fn generated_data_can_be_processed() {
+    let path = std::env::temp_dir().join("rust-coding-test-cli-generated.csv");
+    let generated = run(&[
+        "gen-data",
+        "--rows",
+        "200",
+        "--clients",
+        "5",
+        "--dispute-rate",
+        "0.2",
+        "--output",
+        path.to_str().unwrap(),
+    ]);
+    let output = run(&["--strict", path.to_str().unwrap()]);
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(generated.status.success());
+    assert!(output.status.success());
+    let accounts = sorted_lines(&output.stdout);
+    assert_eq!(accounts.len(), 6);
+    assert_eq!(accounts[5], "client,available,held,total,locked");
+}
+
+#[test]
+fn sorted_output_is_identical_across_runs() {
+    let path = std::env::temp_dir().join("rust-coding-test-cli-sorted.csv");
+    let generated = run(&[
+        "gen-data",
+        "--rows",
+        "2000",
+        "--clients",
+        "300",
+        "--output",
+        path.to_str().unwrap(),
+    ]);
+    let input = path.to_str().unwrap();
+    let unsorted = run(&[input]);
+    let first = run(&["--sort-output", "client", input]);
+    let second = run(&["--sort-output", "client", input]);
+    let sharded = run(&["--sort-output", "client", "--threads", "4", input]);
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(generated.status.success());
+    assert!(first.status.success());
+    assert_eq!(first.stdout, second.stdout);
+    assert_eq!(first.stdout, sharded.stdout);
+    assert_eq!(sorted_lines(&first.stdout), sorted_lines(&unsorted.stdout));
+    let clients: Vec<u16> = String::from_utf8_lossy(&first.stdout)
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').next().unwrap().parse().unwrap())
+        .collect();
+    assert!(clients.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[test]
+fn directories_and_patterns_of_files_are_merged() {
+    let directory = std::env::temp_dir().join("rust-coding-test-cli-directory");
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir(&directory).unwrap();
+    let header = "type, client, tx, amount\n";
+    for (name, rows) in [
+        (
+            "1.csv",
+            "deposit, 1, 1, 5.0\ndeposit, 2, 2, 3.0\ndispute, 2, 2,\n",
+        ),
+        ("2.csv", "deposit, 3, 3, 2.0\nwithdrawal, 3, 4, 1.0\n"),
+        ("3.csv", "deposit, 4, 5, 2.0\n"),
+    ] {
+        std::fs::write(directory.join(name), format!("{}{}", header, rows)).unwrap();
+    }
+    std::fs::write(directory.join("notes.txt"), "not an input").unwrap();
+
+    let input = directory.to_str().unwrap();
+    let pattern = directory.join("*.csv");
+    let listed = run(&["--sort-output", "client", input]);
+    let matched = run(&["--sort-output", "client", pattern.to_str().unwrap()]);
+    // Files read in parallel must not depend on each other
+    let parallel = run(&["--unordered", "--threads", "2", input]);
+    let unsharded = run(&["--unordered", input]);
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    assert!(listed.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&listed.stdout),
+        "client,available,held,total,locked\n\
+         1,5.0000,0.0000,5.0000,false\n\
+         2,0.0000,3.0000,3.0000,false\n\
+         3,1.0000,0.0000,1.0000,false\n\
+         4,2.0000,0.0000,2.0000,false\n"
+    );
+    assert_eq!(listed.stdout, matched.stdout);
+    assert!(parallel.status.success());
+    assert_eq!(sorted_lines(&parallel.stdout), sorted_lines(&listed.stdout));
+    assert_eq!(unsharded.status.code(), Some(2));
+}
+
+#[test]
+fn files_read_in_parallel_keep_the_order_of_every_client() {
+    let directory = std::env::temp_dir().join("rust-coding-test-cli-parallel-read");
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir(&directory).unwrap();
+    let header = "type, client, tx, amount\n";
+    let mut rows = String::new();
+    for file in 0..4u32 {
+        rows.clear();
+        for client in 1..=6u32 {
+            let tx = file * 100 + client;
+            if file % 2 == 0 {
+                rows.push_str(&format!("deposit, {}, {}, 2.0\n", client, tx));
+            } else {
+                rows.push_str(&format!("withdrawal, {}, {}, 1.5\n", client, tx));
+                rows.push_str(&format!("dispute, {}, {},\n", client, tx - 100));
+            }
+        }
+        let name = format!("{}.csv", file);
+        std::fs::write(directory.join(name), format!("{}{}", header, rows)).unwrap();
+    }
+
+    let input = directory.to_str().unwrap();
+    let sequential = run(&["--sort-output", "client", "--threads", "3", input]);
+    let parallel = run(&[
+        "--sort-output",
+        "client",
+        "--threads",
+        "3",
+        "--parallel-read",
+        input,
+    ]);
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    assert!(sequential.status.success());
+    assert!(parallel.status.success());
+    assert!(String::from_utf8_lossy(&sequential.stdout).contains("1,-1.5000,4.0000,2.5000,false\n"));
+    assert_eq!(parallel.stdout, sequential.stdout);
+    assert_eq!(parallel.stderr, sequential.stderr);
+}
+
+#[cfg(not(feature = "object-store"))]
+#[test]
+fn object_urls_need_the_object_store_feature() {
+    let output = run(&["s3://settlements/2024-03-01.csv"]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("reading s3://settlements/2024-03-01.csv needs the object-store feature"));
+}
+
+#[test]
+fn files_reusing_transaction_ids_are_namespaced() {
+    let directory = std::env::temp_dir().join("rust-coding-test-cli-namespaces");
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir(&directory).unwrap();
+    let header = "type, client, tx, amount\n";
+    for (name, rows) in [
+        ("acquirer-a.csv", "deposit, 1, 1, 5.0\ndeposit, 2, 2, 3.0\n"),
+        ("acquirer-b.csv", "deposit, 1, 1, 2.0\ndispute, 1, 1,\n"),
+    ] {
+        std::fs::write(directory.join(name), format!("{}{}", header, rows)).unwrap();
+    }
+
+    let input = directory.to_str().unwrap();
+    let merged = run(&["--sort-output", "client", input]);
+    let namespaced = run(&["--sort-output", "client", "--namespace-sources", input]);
+    let parallel = run(&[
+        "--namespace-sources",
+        "--unordered",
+        "--threads",
+        "2",
+        input,
+    ]);
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    // The second deposit is a duplicate and the dispute holds the deposit of the other file
+    assert_eq!(
+        String::from_utf8_lossy(&merged.stdout),
+        "client,available,held,total,locked\n\
+         1,0.0000,5.0000,5.0000,false\n\
+         2,3.0000,0.0000,3.0000,false\n"
+    );
+    assert!(namespaced.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&namespaced.stdout),
+        "client,available,held,total,locked\n\
+         1,5.0000,2.0000,7.0000,false\n\
+         2,3.0000,0.0000,3.0000,false\n"
+    );
+    assert!(parallel.status.success());
+    assert_eq!(
+        sorted_lines(&parallel.stdout),
+        sorted_lines(&namespaced.stdout)
+    );
+}
+
+#[test]
+fn atomic_inputs_are_rolled_back_on_rejected_rows() {
+    let directory = std::env::temp_dir();
+    let opening = directory.join("rust-coding-test-cli-atomic-opening.csv");
+    let settlement = directory.join("rust-coding-test-cli-atomic-settlement.csv");
+    let start = directory.join("rust-coding-test-cli-atomic-start.snapshot");
+    let end = directory.join("rust-coding-test-cli-atomic-end.snapshot");
+    std::fs::write(&opening, "type, client, tx, amount\ndeposit, 1, 1, 10.0\n").unwrap();
+    std::fs::write(
+        &settlement,
+        "type, client, tx, amount\nwithdrawal, 1, 2, 4.0\nwithdrawal, 1, 3, 50.0\n",
+    )
+    .unwrap();
+
+    let opened = run(&[
+        "--snapshot",
+        start.to_str().unwrap(),
+        opening.to_str().unwrap(),
+    ]);
+    let rolled_back = run(&[
+        "--atomic",
+        "--restore",
+        start.to_str().unwrap(),
+        "--snapshot",
+        end.to_str().unwrap(),
+        settlement.to_str().unwrap(),
+    ]);
+    let unchanged = std::fs::read(&end).unwrap() == std::fs::read(&start).unwrap();
+    let tolerated = run(&[
+        "--atomic",
+        "--max-rejects",
+        "1",
+        "--restore",
+        start.to_str().unwrap(),
+        settlement.to_str().unwrap(),
+    ]);
+    for path in [&opening, &settlement, &start, &end] {
+        std::fs::remove_file(path).unwrap();
+    }
+
+    assert!(opened.status.success());
+    assert!(!rolled_back.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&rolled_back.stdout),
+        "client,available,held,total,locked\n1,10.0000,0.0000,10.0000,false\n"
+    );
+    assert!(String::from_utf8_lossy(&rolled_back.stderr)
+        .contains("rolled back the input, 1 rows were malformed or rejected and at most 0 may be"));
+    assert!(unchanged);
+    assert!(tolerated.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&tolerated.stdout),
+        "client,available,held,total,locked\n1,6.0000,0.0000,6.0000,false\n"
+    );
+}
+
+#[test]
+fn progress_is_not_drawn_outside_a_terminal() {
+    let input = asset("test_with_disputes.csv");
+    let plain = run(&[input.to_str().unwrap()]);
+    let output = run(&["--progress", input.to_str().unwrap()]);
+
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+    assert_eq!(sorted_lines(&output.stdout), sorted_lines(&plain.stdout));
+}
+
+#[test]
+fn validation_reports_problems_without_writing_state() {
+    let valid = std::env::temp_dir().join("rust-coding-test-cli-validate.csv");
+    let snapshot = std::env::temp_dir().join("rust-coding-test-cli-validate.snapshot");
+    let _ = std::fs::remove_file(&snapshot);
+    std::fs::write(&valid, "type, client, tx, amount\ndeposit, 1, 1, 2.0\n").unwrap();
+
+    let passed = run(&["--validate", valid.to_str().unwrap()]);
+    let failed = run(&[
+        "--validate",
+        "--snapshot",
+        snapshot.to_str().unwrap(),
+        asset("test_basic.csv").to_str().unwrap(),
+    ]);
+    std::fs::remove_file(&valid).unwrap();
+
+    assert!(passed.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&passed.stdout),
+        "Validated 1 rows, found 0 problems\n\
+         Malformed 0 rows\n\
+         Would reject 0 transactions\n\
+         Would lock no accounts\n"
+    );
+    assert_eq!(failed.status.code(), Some(1));
+    let report = String::from_utf8_lossy(&failed.stdout);
+    assert!(report.contains("Would reject 1 transactions\n  client 2: transaction 5: "));
+    assert!(!snapshot.exists());
+}
+
+#[test]
+fn interrupted_run_resumes_from_checkpoint() {
+    let path = std::env::temp_dir().join("rust-coding-test-cli-resume.csv");
+    let checkpoint = std::env::temp_dir().join("rust-coding-test-cli-resume.snapshot");
+    let _ = std::fs::remove_file(&checkpoint);
+    let rows = "type, client, tx, amount\n\
+                deposit, 1, 1, 5.0\n\
+                deposit, 2, 2, 3.0\n\
+                withdrawal, 1, 3, 1.0\n\
+                deposit, 3, 4, 2.0\n";
+    let input = path.to_str().unwrap();
+    let checkpoint_arg = checkpoint.to_str().unwrap();
+    let args = ["--checkpoint", checkpoint_arg, "--checkpoint-every", "2"];
+
+    // Stopped by the malformed row after the checkpoint of the first four rows
+    std::fs::write(&path, format!("{}bogus\ndeposit, 1, 5, 1.0\n", rows)).unwrap();
+    let interrupted = run(&[&args[..], &["--strict", input]].concat());
+    let left_behind = checkpoint.exists();
+    std::fs::write(
+        &path,
+        format!("{}deposit, 2, 6, 1.0\ndeposit, 1, 5, 1.0\n", rows),
+    )
+    .unwrap();
+    let resumed = run(&[&args[..], &["--resume", "--stats", input]].concat());
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(interrupted.status.code(), Some(1));
+    assert!(left_behind);
+    assert!(resumed.status.success());
+    assert_eq!(
+        sorted_lines(&resumed.stdout),
+        vec![
+            "1,5.0000,0.0000,5.0000,false",
+            "2,4.0000,0.0000,4.0000,false",
+            "3,2.0000,0.0000,2.0000,false",
+            "client,available,held,total,locked",
+        ]
+    );
+    let stats = String::from_utf8_lossy(&resumed.stderr);
+    assert!(stats.contains("Applied 2 transactions\n"), "{}", stats);
+    assert!(stats.contains("Read 6 rows"), "{}", stats);
+    assert!(!checkpoint.exists());
+}
+
+#[test]
+fn only_selected_clients_are_applied() {
+    let input = asset("test_with_disputes.csv");
+    let included = run(&["--clients", "1,3-5", "--stats", input.to_str().unwrap()]);
+    let excluded = run(&["--exclude-clients", "1", input.to_str().unwrap()]);
+
+    assert!(included.status.success());
+    assert_eq!(
+        sorted_lines(&included.stdout),
+        vec![
+            "1,11.5000,0.0000,11.5000,false",
+            "client,available,held,total,locked",
+        ]
+    );
+    let stats = String::from_utf8_lossy(&included.stderr);
+    assert!(stats.contains("Read 9 rows"), "{}", stats);
+    assert!(
+        stats.contains("Skipped 3 rows of other clients"),
+        "{}",
+        stats
+    );
+    assert_eq!(
+        sorted_lines(&excluded.stdout),
+        vec![
+            "2,0.0000,2.0000,2.0000,false",
+            "client,available,held,total,locked",
+        ]
+    );
+    let invalid = run(&["--clients", "5-1", input.to_str().unwrap()]);
+    assert_eq!(invalid.status.code(), Some(2));
+}
+
+#[test]
+fn limited_and_sampled_runs_apply_part_of_the_input() {
+    let input = asset("test_with_disputes.csv");
+    let limited = run(&["--limit", "3", "--stats", input.to_str().unwrap()]);
+    let sampled = || run(&["--sample", "0.5", "--seed", "3", input.to_str().unwrap()]);
+    let (first, second) = (sampled(), sampled());
+
+    assert!(limited.status.success());
+    assert_eq!(
+        sorted_lines(&limited.stdout),
+        vec![
+            "1,3.0000,0.0000,3.0000,false",
+            "2,2.0000,0.0000,2.0000,false",
+            "client,available,held,total,locked",
+        ]
+    );
+    let stats = String::from_utf8_lossy(&limited.stderr);
+    assert!(stats.contains("Read 3 rows"), "{}", stats);
+    assert!(first.status.success());
+    assert_eq!(first.stdout, second.stdout);
+}
+
+#[test]
+fn query_prints_the_state_of_one_client() {
+    let snapshot = std::env::temp_dir().join("rust-coding-test-cli-query.snapshot");
+    let config = std::env::temp_dir().join("rust-coding-test-cli-query.toml");
+    std::fs::write(&config, "[engine]\nrecord_history = true\n").unwrap();
+    let input = asset("test_with_disputes.csv");
+
+    let first = run(&[
+        "--config",
+        config.to_str().unwrap(),
+        "--snapshot",
+        snapshot.to_str().unwrap(),
+        input.to_str().unwrap(),
+    ]);
+    let query = |extra: &[&str]| {
+        let mut args = vec!["query", "--restore", snapshot.to_str().unwrap()];
+        args.extend_from_slice(extra);
+        run(&args)
+    };
+    let text = query(&["--client", "2", "--recent", "2"]);
+    let json = query(&["--client", "2", "-f", "json", "--recent", "0"]);
+    let unknown = query(&["--client", "9"]);
+    let before_dispute = query(&["--client", "2", "--as-of-tx", "4"]);
+    let never_applied = query(&["--client", "2", "--as-of-tx", "99"]);
+    std::fs::remove_file(&snapshot).unwrap();
+    std::fs::remove_file(&config).unwrap();
+
+    assert!(first.status.success());
+    assert!(text.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&text.stdout),
+        "Client 2, active\n\
+         Balances\n  -: available 0.0000, held 2.0000, total 2.0000\n\
+         Open disputes: 1\n  tx 2: 2.0000 held\n\
+         Recent transactions: 2\n  #1 deposit tx 2 2.0000\n  #4 dispute tx 2\n"
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&json.stdout),
+        "{\"client\":2,\"locked\":false,\
+         \"balances\":[{\"client\":2,\"available\":0.0000,\"held\":2.0000,\"total\":2.0000,\"locked\":false}],\
+         \"open_disputes\":[{\"tx\":2,\"held\":2.0000}],\"recent\":[]}\n"
+    );
+    assert_eq!(unknown.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&unknown.stderr).contains("client 9 has no account"));
+    assert_eq!(
+        String::from_utf8_lossy(&before_dispute.stdout),
+        "Client 2, active\n\
+         Balances\n  -: available 2.0000, held 0.0000, total 2.0000\n\
+         Open disputes: 0\n\
+         Recent transactions: 1\n  #1 deposit tx 2 2.0000\n"
+    );
+    assert_eq!(never_applied.status.code(), Some(1));
+}
+
+#[test]
+fn analyze_prints_statistics_of_a_snapshot() {
+    let snapshot = std::env::temp_dir().join("rust-coding-test-cli-analyze.snapshot");
+    let input = asset("test_with_disputes.csv");
+
+    let first = run(&[
+        "--snapshot",
+        snapshot.to_str().unwrap(),
+        input.to_str().unwrap(),
+    ]);
+    let text = run(&[
+        "analyze",
+        "--restore",
+        snapshot.to_str().unwrap(),
+        "--top",
+        "1",
+    ]);
+    let json = run(&[
+        "analyze",
+        "--restore",
+        snapshot.to_str().unwrap(),
+        "-f",
+        "json",
+    ]);
+    std::fs::remove_file(&snapshot).unwrap();
+
+    assert!(first.status.success());
+    assert!(text.status.success());
+    let text = String::from_utf8_lossy(&text.stdout);
+    assert!(
+        text.starts_with(
+            "Accounts: 2, 0 locked (0.00%)\n\
+             Balances: 2, mean total 6.7500, median total 6.7500\n\
+             Top balances\n      client  currency         available              held             total\n\
+             \x20          1         -           11.5000            0.0000           11.5000\n\
+             Most disputed accounts\n"
+        ),
+        "{}",
+        text
+    );
+    let json = String::from_utf8_lossy(&json.stdout);
+    assert!(
+        json.contains("\"most_disputed\":[{\"client\":2,\"disputes\":1,\"held\":2.0000}]"),
+        "{}",
+        json
+    );
+    assert!(json.contains("{\"from\":10,\"to\":100,\"balances\":1}"));
+}
+
+#[test]
+fn statements_are_written_per_client() {
+    let snapshot = std::env::temp_dir().join("rust-coding-test-cli-statements.snapshot");
+    let config = std::env::temp_dir().join("rust-coding-test-cli-statements.toml");
+    let directory = std::env::temp_dir().join("rust-coding-test-cli-statements");
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::write(&config, "[engine]\nrecord_history = true\n").unwrap();
+    let input = asset("test_with_disputes.csv");
+
+    let first = run(&[
+        "--config",
+        config.to_str().unwrap(),
+        "--snapshot",
+        snapshot.to_str().unwrap(),
+        input.to_str().unwrap(),
+    ]);
+    let statements = |extra: &[&str]| {
+        let mut args = vec![
+            "statements",
+            "--restore",
+            snapshot.to_str().unwrap(),
+            "--output-dir",
+            directory.to_str().unwrap(),
+            "--after-tx",
+            "3",
+        ];
+        args.extend_from_slice(extra);
+        run(&args)
+    };
+    let csv = statements(&[]);
+    let mut files: Vec<_> = std::fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    let client_2 = std::fs::read_to_string(directory.join("client-2.csv")).unwrap();
+    let text = statements(&["--client", "1", "-f", "text"]);
+    let client_1 = std::fs::read_to_string(directory.join("client-1.txt")).unwrap();
+    let unknown = statements(&["--client", "9"]);
+    std::fs::remove_dir_all(&directory).unwrap();
+    std::fs::remove_file(&snapshot).unwrap();
+    std::fs::remove_file(&config).unwrap();
+
+    assert!(first.status.success());
+    assert!(csv.status.success());
+    assert_eq!(files, ["client-1.csv", "client-2.csv"]);
+    assert_eq!(
+        client_2,
+        "line,sequence,type,tx,amount,currency,to,timestamp,available,held,total,dispute\n\
+         opening,,,,,,,,2.0000,0.0000,2.0000,\n\
+         transaction,4,dispute,2,,,,,0.0000,2.0000,2.0000,open\n\
+         closing,,,,,,,,0.0000,2.0000,2.0000,\n"
+    );
+    assert!(text.status.success());
+    assert_eq!(
+        client_1,
+        "Statement of client 1\n\
+         Opening balance\n  -: available 3.0000, held 0.0000, total 3.0000\n\
+         Transactions: 4\n  \
+         #3 withdrawal tx 4 1.5000: available 1.5000, held 0.0000, total 1.5000\n  \
+         #5 deposit tx 6 10.0000: available 11.5000, held 0.0000, total 11.5000\n  \
+         #6 dispute tx 6: available 1.5000, held 10.0000, total 11.5000\n  \
+         #7 resolve tx 6: available 11.5000, held 0.0000, total 11.5000\n\
+         Disputes: 1\n  tx 6 10.0000: resolved\n\
+         Closing balance\n  -: available 11.5000, held 0.0000, total 11.5000\n"
+    );
+    assert_eq!(unknown.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&unknown.stderr).contains("client 9 has no account"));
+}
+
+#[test]
+fn input_columns_are_mapped_from_custom_names() {
+    let path = std::env::temp_dir().join("rust-coding-test-cli-columns.csv");
+    std::fs::write(
+        &path,
+        "kind,client_id,transaction_id,amount\ndeposit,1,1,2.5\nwithdrawal,1,2,1.0\n",
+    )
+    .unwrap();
+    let input = path.to_str().unwrap();
+
+    let mapped = run(&[
+        "--map",
+        "type=kind",
+        "--map",
+        "client=client_id",
+        "--map",
+        "tx=transaction_id",
+        input,
+    ]);
+    let unmapped = run(&["--map", "type=kind", input]);
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(mapped.status.success());
+    assert_eq!(
+        sorted_lines(&mapped.stdout),
+        vec![
+            "1,1.5000,0.0000,1.5000,false",
+            "client,available,held,total,locked"
+        ]
+    );
+    assert_eq!(unmapped.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&unmapped.stderr).contains("input has no 'client' column"));
+}
+
+#[test]
+fn accounts_are_written_as_json_lines_or_a_table() {
+    let input = asset("test_with_disputes.csv");
+    let run_format = |format: &str| {
+        run(&[
+            "--output-format",
+            format,
+            "--sort-output",
+            "client",
+            input.to_str().unwrap(),
+        ])
+    };
+
+    let jsonl = run_format("jsonl");
+    let table = run_format("table");
+
+    assert!(jsonl.status.success() && table.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&jsonl.stdout),
+        "{\"client\":1,\"available\":11.5000,\"held\":0.0000,\"total\":11.5000,\"locked\":false}\n\
+         {\"client\":2,\"available\":0.0000,\"held\":2.0000,\"total\":2.0000,\"locked\":false}\n"
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&table.stdout),
+        "client  available    held    total  locked\n\
+         \x20    1    11.5000  0.0000  11.5000   false\n\
+         \x20    2     0.0000  2.0000   2.0000   false\n\
+         \x20total    11.5000  2.0000  13.5000\n"
+    );
+}
+
+#[test]
+fn missing_input_is_a_usage_error() {
+    let output = run(&[]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Usage:"));
+}
+
+/// Sends a request to the server and returns the response body
+fn http(address: &str, method: &str, path: &str, body: &str) -> String {
+    let mut stream = TcpStream::connect(address).expect("Failed to connect to server");
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        address,
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    body.to_string()
+}
+
+/// Starts the server on a free port and returns it with its address
+fn start_server(args: &[&str]) -> (Child, String) {
+    let mut server = Command::new(env!("CARGO_BIN_EXE_rust-coding-test"))
+        .args(["serve", "--listen", "127.0.0.1:0"])
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start server");
+    let mut line = String::new();
+    BufReader::new(server.stdout.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let address = line
+        .trim()
+        .strip_prefix("Listening on ")
+        .unwrap()
+        .to_string();
+    (server, address)
+}
+
+#[test]
+fn serve_accepts_transactions_over_http() {
+    let (mut server, address) = start_server(&[]);
+
+    let health = http(&address, "GET", "/health", "");
+    let submitted = http(
+        &address,
+        "POST",
+        "/transactions",
+        "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":2.5}\n\
+         {\"type\":\"dispute\",\"client\":1,\"tx\":1}",
+    );
+    let account = http(&address, "GET", "/accounts/1", "");
+    server.kill().unwrap();
+    server.wait().unwrap();
+
+    assert_eq!(health, "{\"status\":\"ok\"}");
+    assert_eq!(
+        submitted,
+        "{\"results\":[{\"tx\":1,\"status\":\"applied\"},{\"tx\":1,\"status\":\"applied\"}]}"
+    );
+    assert_eq!(
+        account,
+        "{\"client\":1,\"available\":0.0000,\"held\":2.5000,\"total\":2.5000,\"locked\":false}"
+    );
+}
+
+#[test]
+fn serve_recovers_transactions_from_write_ahead_log() {
+    let wal = std::env::temp_dir().join("cli_serve_recovers.wal");
+    let _ = std::fs::remove_file(&wal);
+    let wal_arg = wal.to_str().unwrap();
+
+    let (mut server, address) = start_server(&["--wal", wal_arg]);
+    http(
+        &address,
+        "POST",
+        "/transactions",
+        "{\"type\":\"deposit\",\"client\":3,\"tx\":1,\"amount\":4.0}",
+    );
+    // Killing the process loses all in-memory state
+    server.kill().unwrap();
+    server.wait().unwrap();
+
+    let (mut server, address) = start_server(&["--wal", wal_arg]);
+    let account = http(&address, "GET", "/accounts/3", "");
+    server.kill().unwrap();
+    server.wait().unwrap();
+    std::fs::remove_file(&wal).unwrap();
+
+    assert_eq!(
+        account,
+        "{\"client\":3,\"available\":4.0000,\"held\":0.0000,\"total\":4.0000,\"locked\":false}"
+    );
+}
+
+#[test]
+fn exported_state_is_imported_into_the_same_snapshot() {
+    let snapshot = std::env::temp_dir().join("rust-coding-test-cli-export.snapshot");
+    let export = std::env::temp_dir().join("rust-coding-test-cli-export.jsonl");
+    let imported = std::env::temp_dir().join("rust-coding-test-cli-import.snapshot");
+    let input = asset("test_with_disputes.csv");
+
+    let first = run(&[
+        "--snapshot",
+        snapshot.to_str().unwrap(),
+        input.to_str().unwrap(),
+    ]);
+    let exported = run(&[
+        "export",
+        "--restore",
+        snapshot.to_str().unwrap(),
+        "--format",
+        "jsonl",
+        "-o",
+        export.to_str().unwrap(),
+    ]);
+    let lines = std::fs::read_to_string(&export).unwrap();
+    let import = run(&[
+        "import",
+        export.to_str().unwrap(),
+        "--snapshot",
+        imported.to_str().unwrap(),
+    ]);
+    let original = std::fs::read(&snapshot).unwrap();
+    let restored = std::fs::read(&imported).unwrap();
+    std::fs::write(&export, "snapshot,19\n").unwrap();
+    let not_an_export = run(&[
+        "import",
+        export.to_str().unwrap(),
+        "--snapshot",
+        imported.to_str().unwrap(),
+    ]);
+    for path in [&snapshot, &export, &imported] {
+        std::fs::remove_file(path).unwrap();
+    }
+
+    assert!(first.status.success());
+    assert!(exported.status.success());
+    assert!(lines.starts_with(
+        "{\"kind\":\"header\",\"format\":\"rust-coding-test/engine-state\",\"version\":1,"
+    ));
+    assert!(lines.contains("{\"kind\":\"account\",\"client\":2,\"locked\":false"));
+    assert!(import.status.success());
+    assert_eq!(restored, original);
+    assert!(!not_an_export.status.success());
+    assert!(String::from_utf8_lossy(&not_an_export.stderr).contains("malformed export on line 1"));
+}
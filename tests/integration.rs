@@ -0,0 +1,113 @@
+//! Golden-file tests running every case under `tests/golden` through the binary.
+//!
+//! A case is a directory holding:
+//! - `input.csv`, the transactions to process;
+//! - optionally `args`, extra command line arguments one per line;
+//! - optionally `engine.toml`, picked up since the binary runs in the case directory;
+//! - `expected.csv`, the accounts the binary must write.
+//!
+//! Account rows are written in no particular order, so they are sorted below the header before
+//! comparing. To add a case, create its directory with an input and run
+//! `UPDATE_GOLDEN=1 cargo test --test integration` to write the expected output, then review it.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const INPUT: &str = "input.csv";
+const ARGS: &str = "args";
+const EXPECTED: &str = "expected.csv";
+
+fn cases() -> Vec<PathBuf> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden");
+    let mut cases: Vec<PathBuf> = fs::read_dir(&root)
+        .expect("Failed to read golden cases")
+        .map(|entry| entry.expect("Failed to read golden case").path())
+        .filter(|path| path.join(INPUT).exists())
+        .collect();
+    cases.sort();
+    cases
+}
+
+fn args(case: &Path) -> Vec<String> {
+    match fs::read_to_string(case.join(ARGS)) {
+        Ok(args) => args
+            .lines()
+            .map(str::trim)
+            .filter(|arg| !arg.is_empty())
+            .map(str::to_string)
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Keeps the header first and sorts the account rows
+fn normalize(output: &str) -> String {
+    let mut lines = output.lines();
+    let header = lines.next().unwrap_or_default();
+    let mut rows: Vec<&str> = lines.collect();
+    rows.sort_unstable();
+    let mut normalized = header.to_string();
+    for row in rows {
+        normalized.push('\n');
+        normalized.push_str(row);
+    }
+    normalized.push('\n');
+    normalized
+}
+
+fn run_case(case: &Path) -> Result<String, String> {
+    let output = Command::new(env!("CARGO_BIN_EXE_rust-coding-test"))
+        .current_dir(case)
+        .args(args(case))
+        .arg(INPUT)
+        .output()
+        .map_err(|err| format!("failed to run binary: {}", err))?;
+    if !output.status.success() {
+        return Err(format!(
+            "exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(normalize(&String::from_utf8_lossy(&output.stdout)))
+}
+
+#[test]
+fn golden_cases_match_expected_output() {
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let cases = cases();
+    assert!(!cases.is_empty(), "no golden cases found");
+
+    let mut failures = Vec::new();
+    for case in &cases {
+        let name = case.file_name().unwrap_or_default().to_string_lossy();
+        let actual = match run_case(case) {
+            Ok(actual) => actual,
+            Err(err) => {
+                failures.push(format!("{}: {}", name, err));
+                continue;
+            }
+        };
+        if update {
+            fs::write(case.join(EXPECTED), &actual).expect("Failed to write expected output");
+            continue;
+        }
+        match fs::read_to_string(case.join(EXPECTED)) {
+            Ok(expected) if normalize(&expected) == actual => {}
+            Ok(expected) => failures.push(format!(
+                "{}: output differs\n--- expected\n{}--- actual\n{}",
+                name,
+                normalize(&expected),
+                actual
+            )),
+            Err(_) => failures.push(format!(
+                "{}: {} is missing, run with UPDATE_GOLDEN=1 to create it",
+                name, EXPECTED
+            )),
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+}
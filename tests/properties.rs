@@ -0,0 +1,568 @@
+//! Randomised tests of invariants that must hold for any sequence of transactions. Cases are
+//! generated from fixed seeds so that failures can be reproduced, the failing seed is part of
+//! the assertion message.
+
+use rust_coding_test::{
+    AccountSnapshot, AccountState, ActorEngine, Amount, ClientId, ConcurrentEngine, CsvSource,
+    Currency, DisputeCycles, DisputeExpiry, DisputePolicy, EngineConfig, EngineObserver,
+    InvariantReport, NegativeBalancePolicy, OrderedDispatcher, ShardedEngine, Transaction,
+    TransactionEngine, TransactionId, TransactionProcessor, TransactionSource, TransactionType,
+};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const CASES: u64 = 200;
+const TRANSACTIONS_PER_CASE: usize = 200;
+const CLIENTS: u16 = 4;
+const TOLERANCE: f64 = 1e-6;
+
+/// xorshift64*, good enough to explore transaction sequences without a dependency
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // The state must never be zero
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+
+    fn chance(&mut self, percent: u64) -> bool {
+        self.below(100) < percent
+    }
+}
+
+fn arbitrary_config(rng: &mut Rng) -> EngineConfig {
+    EngineConfig {
+        negative_balance_policy: match rng.below(4) {
+            0 => NegativeBalancePolicy::Allow,
+            1 => NegativeBalancePolicy::RejectDispute,
+            2 => NegativeBalancePolicy::HoldPartial,
+            _ => NegativeBalancePolicy::HoldPending,
+        },
+        dispute_policy: if rng.chance(50) {
+            DisputePolicy::ReverseWithdrawalOnChargeback
+        } else {
+            DisputePolicy::DepositsOnly
+        },
+        max_dispute_cycles: DisputeCycles(1 + rng.below(3) as u32),
+        ..EngineConfig::default()
+    }
+}
+
+/// Mostly well-formed transactions, with a share of reused ids, disputes from the wrong client
+/// and invalid amounts
+fn arbitrary_transactions(rng: &mut Rng) -> Vec<Transaction> {
+    let eur: Currency = "EUR".parse().unwrap();
+    let mut transactions: Vec<Transaction> = Vec::with_capacity(TRANSACTIONS_PER_CASE);
+    let mut next_id = 1;
+    for _ in 0..TRANSACTIONS_PER_CASE {
+        let client_id = 1 + rng.below(CLIENTS as u64) as ClientId;
+        let transaction_type = match rng.below(10) {
+            0..=3 => TransactionType::Deposit,
+            4 | 5 => TransactionType::Withdrawal,
+            6 => TransactionType::Transfer,
+            7 => TransactionType::Dispute,
+            8 => TransactionType::Resolve,
+            _ if rng.chance(30) => TransactionType::Represent,
+            _ => TransactionType::Chargeback,
+        };
+        let references = matches!(
+            transaction_type,
+            TransactionType::Dispute
+                | TransactionType::Resolve
+                | TransactionType::Chargeback
+                | TransactionType::Represent
+        );
+
+        let refers_back = references || rng.chance(5);
+        let mut transaction = if refers_back && !transactions.is_empty() {
+            // Refer to an earlier transaction, usually from the client that made it
+            let earlier = &transactions[rng.below(transactions.len() as u64) as usize];
+            Transaction {
+                transaction_type,
+                client_id: if rng.chance(80) {
+                    earlier.client_id
+                } else {
+                    client_id
+                },
+                transaction_id: earlier.transaction_id,
+                amount: None,
+                to_client_id: None,
+                currency: None,
+                timestamp: None,
+                metadata: Default::default(),
+            }
+        } else {
+            next_id += 1;
+            Transaction {
+                transaction_type,
+                client_id,
+                transaction_id: TransactionId(next_id),
+                amount: None,
+                to_client_id: None,
+                currency: None,
+                timestamp: None,
+                metadata: Default::default(),
+            }
+        };
+        if !references {
+            transaction.amount = match rng.below(20) {
+                0 => None,
+                1 => Some(Amount::signed(-1.0).unwrap()),
+                _ => Some(Amount::new(rng.below(1_000_000) as f64 / 10_000.0).unwrap()),
+            };
+            transaction.currency = rng.chance(20).then_some(eur);
+        }
+        if transaction_type == TransactionType::Dispute && rng.chance(30) {
+            transaction.amount = Some(Amount::new(rng.below(1_000_000) as f64 / 10_000.0).unwrap());
+        }
+        if transaction_type == TransactionType::Transfer {
+            transaction.to_client_id = Some(1 + rng.below(CLIENTS as u64) as ClientId);
+        }
+        transactions.push(transaction);
+    }
+    transactions
+}
+
+fn total_funds(engine: &TransactionEngine) -> BTreeMap<Option<Currency>, f64> {
+    let mut totals = BTreeMap::new();
+    for account in engine.accounts.values() {
+        for currency in account.currencies() {
+            *totals.entry(currency).or_default() += account.balance(currency).total();
+        }
+    }
+    totals
+}
+
+/// Currency and change an accepted transaction makes to the funds of all clients together
+fn expected_change(
+    engine: &TransactionEngine,
+    transaction: &Transaction,
+) -> (Option<Currency>, f64) {
+    let amount = transaction.amount.unwrap_or_default().value();
+    match transaction.transaction_type {
+        TransactionType::Deposit => (transaction.currency, amount),
+        TransactionType::Withdrawal | TransactionType::Fee => (transaction.currency, -amount),
+        TransactionType::Interest | TransactionType::Adjustment => (transaction.currency, amount),
+        // Chargebacks apply to the currency of the disputed transaction
+        TransactionType::Chargeback => engine
+            .accounts
+            .get(&transaction.client_id)
+            .and_then(|account| {
+                account
+                    .state()
+                    .unwrap()
+                    .active_disputes
+                    .into_iter()
+                    .find(|(id, _, _)| *id == transaction.transaction_id)
+            })
+            .map_or((None, 0.0), |(_, currency, held)| (currency, -held)),
+        // Representments credit back what the chargeback took
+        TransactionType::Represent => engine
+            .accounts
+            .get(&transaction.client_id)
+            .and_then(|account| {
+                account
+                    .state()
+                    .unwrap()
+                    .chargebacks
+                    .into_iter()
+                    .find(|(id, _, _)| *id == transaction.transaction_id)
+            })
+            .map_or((None, 0.0), |(_, currency, taken)| (currency, taken)),
+        TransactionType::Transfer
+        | TransactionType::Dispute
+        | TransactionType::Resolve
+        | TransactionType::Lock
+        | TransactionType::Unlock
+        | TransactionType::Close
+        | TransactionType::Reserve => (transaction.currency, 0.0),
+        TransactionType::Reversal
+        | TransactionType::Merge
+        | TransactionType::Authorize
+        | TransactionType::Capture
+        | TransactionType::Void => {
+            unreachable!("reversals, merges and authorizations are not generated")
+        }
+    }
+}
+
+fn assert_held_matches_disputes(seed: u64, engine: &TransactionEngine) {
+    for account in engine.accounts.values() {
+        let state = account.state().unwrap();
+        for currency in account.currencies() {
+            let disputed: f64 = state
+                .active_disputes
+                .iter()
+                .filter(|(_, disputed_currency, _)| *disputed_currency == currency)
+                .map(|(_, _, held)| held)
+                .sum();
+            let balance = account.balance(currency);
+            assert!(
+                (balance.held - disputed).abs() < TOLERANCE,
+                "seed {}: client {} holds {} but disputes amount to {}",
+                seed,
+                account.get_client_id(),
+                balance.held,
+                disputed
+            );
+            assert!(
+                (balance.total() - (balance.available + balance.held)).abs() < TOLERANCE,
+                "seed {}: total of client {} is not available + held",
+                seed,
+                account.get_client_id()
+            );
+        }
+    }
+}
+
+#[test]
+fn funds_are_conserved_and_held_funds_match_disputes() {
+    for seed in 0..CASES {
+        let mut rng = Rng::new(seed);
+        let mut engine = TransactionEngine::with_config(arbitrary_config(&mut rng));
+        for transaction in arbitrary_transactions(&mut rng) {
+            let before = total_funds(&engine);
+            let (changed_currency, change) = expected_change(&engine, &transaction);
+            // Rejected transactions must not change anything
+            let accepted = engine.execute(transaction.clone()).is_ok();
+
+            for (currency, total) in total_funds(&engine) {
+                let previous = before.get(&currency).copied().unwrap_or_default();
+                let expected = if accepted && currency == changed_currency {
+                    previous + change
+                } else {
+                    previous
+                };
+                assert!(
+                    (total - expected).abs() < TOLERANCE,
+                    "seed {}: {:?} changed the funds in {:?} from {} to {}",
+                    seed,
+                    transaction,
+                    currency,
+                    previous,
+                    total
+                );
+            }
+            assert_held_matches_disputes(seed, &engine);
+        }
+        let books = engine.global_ledger();
+        assert!(
+            books.discrepancies(&engine).is_empty(),
+            "seed {}: the books do not balance: {:?}",
+            seed,
+            books
+        );
+    }
+}
+
+#[test]
+fn locked_accounts_only_change_by_settling_disputes() {
+    let mut cases_with_locks = 0;
+    for seed in 0..CASES {
+        let mut rng = Rng::new(seed);
+        // The default lock policy rejects every transaction on locked accounts but representments,
+        // resolves and chargebacks
+        let mut engine = TransactionEngine::with_config(arbitrary_config(&mut rng));
+        let mut locked: BTreeMap<ClientId, AccountState> = BTreeMap::new();
+        for transaction in arbitrary_transactions(&mut rng) {
+            let settled = engine.execute(transaction.clone()).is_ok()
+                && matches!(
+                    transaction.transaction_type,
+                    TransactionType::Represent
+                        | TransactionType::Resolve
+                        | TransactionType::Chargeback
+                );
+            if settled {
+                locked.remove(&transaction.client_id);
+            }
+            for (client_id, state) in &locked {
+                assert_eq!(
+                    &engine.accounts[client_id].state().unwrap(),
+                    state,
+                    "seed {}: locked account changed by {:?}",
+                    seed,
+                    transaction
+                );
+            }
+            for (client_id, account) in &engine.accounts {
+                if account.is_locked() {
+                    locked
+                        .entry(*client_id)
+                        .or_insert_with(|| account.state().unwrap());
+                }
+            }
+        }
+        cases_with_locks += usize::from(!locked.is_empty());
+    }
+    // Otherwise the generator would not be exercising chargebacks
+    assert!(cases_with_locks > 0);
+}
+
+#[test]
+fn replays_are_deterministic() {
+    for seed in 0..CASES {
+        let mut rng = Rng::new(seed);
+        let config = arbitrary_config(&mut rng);
+        let transactions = arbitrary_transactions(&mut rng);
+
+        let run = |config: &EngineConfig| {
+            let mut engine = TransactionEngine::with_config(config.clone());
+            let results: Vec<_> = transactions
+                .iter()
+                .map(|transaction| {
+                    engine
+                        .execute(transaction.clone())
+                        .map_err(|err| err.to_string())
+                })
+                .collect();
+            let mut states: Vec<_> = engine
+                .accounts
+                .values()
+                .map(|account| account.state().unwrap())
+                .collect();
+            states.sort_by_key(|state| state.client_id);
+            (results, states)
+        };
+        assert_eq!(run(&config), run(&config), "seed {}", seed);
+    }
+}
+
+#[test]
+fn every_run_keeps_the_checked_invariants() {
+    for seed in 0..CASES {
+        let mut rng = Rng::new(seed);
+        let config = EngineConfig {
+            record_history: true,
+            dispute_expiry: DisputeExpiry {
+                after_transactions: rng.chance(30).then_some(1 + rng.below(20)),
+                after_days: None,
+            },
+            ..arbitrary_config(&mut rng)
+        };
+        let mut engine = TransactionEngine::with_config(config.clone());
+        for transaction in arbitrary_transactions(&mut rng) {
+            let _ = engine.execute(transaction);
+        }
+
+        let report =
+            InvariantReport::check(&engine, TransactionEngine::with_config(config)).unwrap();
+        assert!(report.is_ok(), "seed {}: {}", seed, report);
+    }
+}
+
+/// Accounts read back from the processor before it is finalized and from the final engine, both
+/// sorted by client and currency, with the number of rejected transactions
+fn processed<P: TransactionProcessor>(
+    mut processor: P,
+    transactions: &[Transaction],
+) -> (Vec<AccountSnapshot>, Vec<AccountSnapshot>, usize) {
+    let mut rejected = transactions
+        .iter()
+        .filter(|transaction| processor.execute((*transaction).clone()).is_err())
+        .count();
+    let mut read: Vec<_> = processor.iter_snapshots().collect();
+    read.sort_by_key(|snapshot| (snapshot.client, snapshot.currency));
+    for snapshot in &read {
+        if snapshot.currency.is_none() {
+            assert_eq!(
+                processor.account_snapshot(snapshot.client).as_ref(),
+                Some(snapshot)
+            );
+        }
+    }
+    let (engine, errors) = processor.finalize();
+    rejected += errors.len();
+    (read, engine.sorted_snapshots().collect(), rejected)
+}
+
+/// Configuration and transactions to apply both to a single engine and to shards of it.
+/// Transfers between clients of different shards are rejected by design, so there are none.
+fn sharded_case(rng: &mut Rng) -> (EngineConfig, Vec<Transaction>) {
+    let config = arbitrary_config(rng);
+    let transactions = arbitrary_transactions(rng)
+        .into_iter()
+        .filter(|transaction| transaction.transaction_type != TransactionType::Transfer)
+        .collect();
+    (config, transactions)
+}
+
+#[test]
+fn every_engine_applies_transactions_alike() {
+    for seed in 0..CASES / 10 {
+        let mut rng = Rng::new(seed);
+        let (config, transactions) = sharded_case(&mut rng);
+
+        let expected = processed(
+            TransactionEngine::with_config(config.clone()),
+            &transactions,
+        );
+        assert_eq!(expected.0, expected.1, "seed {}", seed);
+        for (name, outcome) in [
+            (
+                "sharded",
+                processed(ShardedEngine::new(3, config.clone()), &transactions),
+            ),
+            (
+                "concurrent",
+                processed(ConcurrentEngine::new(3, config.clone()), &transactions),
+            ),
+            (
+                "actor",
+                processed(ActorEngine::new(3, config.clone()), &transactions),
+            ),
+        ] {
+            assert_eq!(outcome, expected, "seed {}: {} engine", seed, name);
+        }
+    }
+}
+
+/// Transactions applied to every client, in the order they were applied
+#[derive(Clone, Default)]
+struct AppliedOrder(Arc<Mutex<BTreeMap<ClientId, Vec<TransactionId>>>>);
+
+impl EngineObserver for AppliedOrder {
+    fn on_applied(&mut self, transaction: &Transaction) {
+        self.0
+            .lock()
+            .unwrap()
+            .entry(transaction.client_id)
+            .or_default()
+            .push(transaction.transaction_id);
+    }
+}
+
+#[test]
+fn files_read_in_parallel_are_applied_in_input_order() {
+    for seed in 0..CASES / 4 {
+        let mut rng = Rng::new(seed);
+        let (config, transactions) = sharded_case(&mut rng);
+        let files = 1 + rng.below(5) as usize;
+        let mut bounds: Vec<_> = (1..files)
+            .map(|_| rng.below(transactions.len() as u64 + 1) as usize)
+            .collect();
+        bounds.sort();
+        bounds.insert(0, 0);
+        bounds.push(transactions.len());
+        let shards = 1 + rng.below(4) as usize;
+        let capacity = 1 + rng.below(8) as usize;
+        let seeds: Vec<_> = (0..files).map(|_| rng.next()).collect();
+
+        let single_order = AppliedOrder::default();
+        let mut single = TransactionEngine::with_config(config.clone());
+        single.subscribe(Box::new(single_order.clone()));
+        let single_errors: Vec<_> = transactions
+            .iter()
+            .filter_map(|transaction| single.execute(transaction.clone()).err())
+            .collect();
+
+        let sharded_order = AppliedOrder::default();
+        let engine = TransactionEngine::builder()
+            .config(config)
+            .observer(Box::new(sharded_order.clone()))
+            .shards(shards)
+            .build_sharded();
+        let dispatcher = OrderedDispatcher::with_capacity(engine, capacity);
+        thread::scope(|scope| {
+            for (file, rows) in bounds.windows(2).enumerate() {
+                let (dispatcher, transactions) = (&dispatcher, &transactions);
+                let mut rng = Rng::new(seeds[file]);
+                scope.spawn(move || {
+                    for transaction in &transactions[rows[0]..rows[1]] {
+                        // Readers progress at different paces
+                        if rng.chance(20) {
+                            thread::yield_now();
+                        }
+                        dispatcher.submit(file, transaction.clone());
+                    }
+                    dispatcher.finish_file(file);
+                });
+            }
+        });
+        let (merged, sharded_errors) = dispatcher.finish();
+
+        let context = format!("seed {}: {} files on {} shards", seed, files, shards);
+        assert_eq!(sharded_errors, single_errors, "{}", context);
+        assert_eq!(
+            merged.sorted_snapshots().collect::<Vec<_>>(),
+            single.sorted_snapshots().collect::<Vec<_>>(),
+            "{}",
+            context
+        );
+        assert_eq!(
+            *sharded_order.0.lock().unwrap(),
+            *single_order.0.lock().unwrap(),
+            "{}",
+            context
+        );
+    }
+}
+
+/// Stand-in for a fuzz target: csv input built from random fragments of valid and invalid rows
+/// must be read, or rejected row by row, without panicking
+#[test]
+fn arbitrary_csv_input_never_panics() {
+    const FRAGMENTS: [&str; 16] = [
+        "deposit",
+        "withdrawal",
+        "dispute",
+        "transfer",
+        "chargeback",
+        "1",
+        "65536",
+        "-1",
+        "2.5",
+        "1e308",
+        "NaN",
+        "\"",
+        "",
+        " ",
+        "EUR",
+        "\u{fffd}",
+    ];
+    const HEADERS: [&str; 3] = [
+        "type,client,tx,amount\n",
+        "type, client, tx, amount, to, currency\n",
+        "tx,type\n",
+    ];
+    for seed in 0..CASES {
+        let mut rng = Rng::new(seed);
+        let mut input = HEADERS[rng.below(HEADERS.len() as u64) as usize].to_string();
+        for _ in 0..50 {
+            let fields = rng.below(7);
+            let row: Vec<&str> = (0..fields)
+                .map(|_| FRAGMENTS[rng.below(FRAGMENTS.len() as u64) as usize])
+                .collect();
+            input.push_str(&row.join(","));
+            input.push('\n');
+        }
+        let mut bytes = input.into_bytes();
+        // Flip some bytes to reach invalid utf-8 and broken quoting
+        for _ in 0..rng.below(4) {
+            let index = rng.below(bytes.len() as u64) as usize;
+            bytes[index] = rng.next() as u8;
+        }
+
+        let mut source = CsvSource::new(bytes.as_slice());
+        let mut engine = TransactionEngine::new();
+        let mut rows = 0;
+        while let Some(result) = source.next_transaction() {
+            if let Ok(transaction) = result {
+                let _ = engine.execute(transaction);
+            }
+            rows += 1;
+            assert!(rows <= 100, "seed {}: reader does not make progress", seed);
+        }
+    }
+}